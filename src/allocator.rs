@@ -0,0 +1,127 @@
+//! Page allocator shared by every access method in a store.
+//!
+//! The allocator's state lives on its own meta page: the next never-used page offset and the head of a
+//! free list. Freed pages are chained through their first eight bytes.
+use crate::bytes::{read_u32, read_u64, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PinnedPage};
+use crate::storage::Storage;
+
+const MAGIC: u32 = 0x5041_4c43;
+const NEXT_PAGE: usize = 8;
+const FREE_HEAD: usize = 16;
+const NO_PAGE: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageAllocator {
+    meta: PageId
+}
+impl PageAllocator {
+    /// Set up a new allocator on `meta`. Pages after `meta` are handed out in order.
+    pub fn create<S: Storage>(store: &PageStore<S>, meta: PageId) -> Result<PageAllocator, PageError> {
+        let page = store.allocate_page(&meta)?;
+        let mut buf = page.try_write()?;
+        write_u32(&mut *buf, 0, MAGIC);
+        write_u64(&mut *buf, NEXT_PAGE, meta.offset() as u64 + 1);
+        write_u64(&mut *buf, FREE_HEAD, NO_PAGE);
+        Ok(PageAllocator { meta })
+    }
+
+    /// Reattach to an allocator previously created on `meta`.
+    pub fn open<S: Storage>(store: &PageStore<S>, meta: PageId) -> Result<PageAllocator, PageError> {
+        let page = store.pin_page(&meta)?;
+        if read_u32(&*page.try_read()?, 0) != MAGIC {
+            return Err(PageError::WrongPageType)
+        }
+        Ok(PageAllocator { meta })
+    }
+
+    pub fn meta_page(&self) -> PageId {
+        self.meta
+    }
+
+    /// Hand out a zeroed page, preferring previously freed ones.
+    pub fn allocate<'store, S: Storage>(&self, store: &'store PageStore<S>) -> Result<PinnedPage<'store, S>, PageError> {
+        // Waits for the meta page's latch, so threads sharing the store can allocate at the same time.
+        let mut meta_buf = store.write_latch(&self.meta)?;
+        let free_head = read_u64(&*meta_buf, FREE_HEAD);
+        if free_head != NO_PAGE {
+            let page = store.pin_page(&PageId::new(free_head as usize))?;
+            {
+                let mut buf = page.try_write()?;
+                write_u64(&mut *meta_buf, FREE_HEAD, read_u64(&*buf, 0));
+                buf.fill(0);
+            }
+            return Ok(page)
+        }
+
+        let next = read_u64(&*meta_buf, NEXT_PAGE);
+        let page = store.allocate_page(&PageId::new(next as usize))?;
+        write_u64(&mut *meta_buf, NEXT_PAGE, next + 1);
+        Ok(page)
+    }
+
+    /// Return `page` to the free list. The caller must not hold a pin on it.
+    pub fn free<S: Storage>(&self, store: &PageStore<S>, page: PageId) -> Result<(), PageError> {
+        let mut meta_buf = store.write_latch(&self.meta)?;
+        let freed = store.pin_page(&page)?;
+        let mut buf = freed.try_write()?;
+        buf.fill(0);
+        write_u64(&mut *buf, 0, read_u64(&*meta_buf, FREE_HEAD));
+        write_u64(&mut *meta_buf, FREE_HEAD, page.offset() as u64);
+        Ok(())
+    }
+
+    /// Every page on the free list, most recently freed first.
+    pub fn free_pages<S: Storage>(&self, store: &PageStore<S>) -> Result<Vec<PageId>, PageError> {
+        let mut pages = Vec::new();
+        let mut next = read_u64(&*store.read_latch(&self.meta)?, FREE_HEAD);
+        while next != NO_PAGE {
+            let page = PageId::new(next as usize);
+            // A cycle can only come from a corrupt list; stop rather than loop forever.
+            if pages.contains(&page) {
+                break
+            }
+            pages.push(page);
+            next = read_u64(&*store.read_latch(&page)?, 0);
+        }
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page_store::{PageError, PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::PageAllocator;
+
+    #[test]
+    fn test_allocate_and_reuse() -> Result<(), PageError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+
+        let a = allocator.allocate(&store)?.id();
+        let b = allocator.allocate(&store)?.id();
+        assert_eq!((a, b), (PageId::new(1), PageId::new(2)));
+
+        {
+            let page = store.pin_page(&a)?;
+            (*page.try_write()?)[100] = 1;
+        }
+        allocator.free(&store, a)?;
+        let reused = allocator.allocate(&store)?;
+        assert_eq!(reused.id(), a);
+        assert!(reused.try_read()?.iter().all(|b| *b == 0));
+        assert_eq!(allocator.allocate(&store)?.id(), PageId::new(3));
+
+        allocator.free(&store, b)?;
+        allocator.free(&store, PageId::new(3))?;
+        assert_eq!(allocator.free_pages(&store)?, vec![PageId::new(3), b]);
+        assert_eq!(allocator.allocate(&store)?.id(), PageId::new(3));
+        assert_eq!(allocator.allocate(&store)?.id(), b);
+
+        let reopened = PageAllocator::open(&store, PageId::new(0))?;
+        assert_eq!(reopened.allocate(&store)?.id(), PageId::new(4));
+        Ok(())
+    }
+}
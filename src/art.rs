@@ -0,0 +1,656 @@
+//! An ordered in-memory map from byte strings, built as an adaptive radix tree.
+//!
+//! Inner nodes branch on one key byte and come in four sizes, holding up to 4, 16, 48 or 256 children;
+//! a node moves to the next size up when it fills and back down when deletes leave it sparse. Each inner
+//! node also stores the key bytes shared by everything below it, so chains of single-child nodes never
+//! form, and a key that ends at an inner node is kept in that node's end slot. Lookups cost one node per
+//! distinguishing byte rather than a comparison per level, which suits hot secondary lookups on long,
+//! shared-prefix keys.
+//!
+//! As with the skip list, nodes live in an arena and link by index, and the tree is `Sync` behind a
+//! read-write lock. It can stand in for the skip list as the LSM memtable, and `into_sorted` hands its
+//! entries over in key order, ready for `RunBuilder` or `BTree::bulk_load` when it is flushed to pages.
+use std::ops::Bound;
+use std::sync::{RwLock, RwLockReadGuard};
+
+const NIL: usize = usize::MAX;
+
+pub struct Art<V> {
+    inner: RwLock<Inner<V>>,
+}
+struct Inner<V> {
+    nodes: Vec<Node<V>>,
+    /// Arena slots freed by deletes, reused before the arena grows.
+    free: Vec<usize>,
+    root: usize,
+    len: usize,
+}
+enum Node<V> {
+    Leaf { key: Vec<u8>, value: V },
+    Branch {
+        /// Bytes shared by every key below this node, after the byte that led here.
+        prefix: Vec<u8>,
+        /// The leaf whose key ends at this node.
+        end: usize,
+        children: Children,
+    },
+    Free,
+}
+
+/// Where a node hangs from.
+#[derive(Clone, Copy)]
+enum Link {
+    Root,
+    End(usize),
+    Child(usize, u8),
+}
+
+/// Children of an inner node by key byte, in the smallest layout that holds them.
+enum Children {
+    N4(Sorted<4>),
+    N16(Box<Sorted<16>>),
+    /// `slots[byte]` is one more than the index in `next` of that byte's child, or zero.
+    N48 { slots: Box<[u8; 256]>, next: Box<[usize; 48]>, len: usize },
+    N256 { next: Box<[usize; 256]>, len: usize },
+}
+
+/// Up to `N` children with their key bytes kept in ascending order.
+struct Sorted<const N: usize> {
+    keys: [u8; N],
+    next: [usize; N],
+    len: usize,
+}
+impl<const N: usize> Sorted<N> {
+    fn new() -> Sorted<N> {
+        Sorted { keys: [0; N], next: [NIL; N], len: 0 }
+    }
+
+    fn position(&self, byte: u8) -> Result<usize, usize> {
+        self.keys[..self.len].binary_search(&byte)
+    }
+
+    fn insert(&mut self, at: usize, byte: u8, child: usize) {
+        self.keys.copy_within(at..self.len, at + 1);
+        self.next.copy_within(at..self.len, at + 1);
+        self.keys[at] = byte;
+        self.next[at] = child;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, at: usize) {
+        self.keys.copy_within(at + 1..self.len, at);
+        self.next.copy_within(at + 1..self.len, at);
+        self.len -= 1;
+    }
+}
+
+impl Children {
+    fn new() -> Children {
+        Children::N4(Sorted::new())
+    }
+
+    /// The smallest layout holding `entries`, with room for `capacity` children in all.
+    fn build(capacity: usize, entries: &[(u8, usize)]) -> Children {
+        let mut children = match capacity {
+            0..=4 => Children::N4(Sorted::new()),
+            5..=16 => Children::N16(Box::new(Sorted::new())),
+            17..=48 => Children::N48 { slots: Box::new([0; 256]), next: Box::new([NIL; 48]), len: 0 },
+            _ => Children::N256 { next: Box::new([NIL; 256]), len: 0 },
+        };
+        for (byte, child) in entries {
+            children.put(*byte, *child);
+        }
+        children
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Children::N4(sorted) => sorted.len,
+            Children::N16(sorted) => sorted.len,
+            Children::N48 { len, .. } | Children::N256 { len, .. } => *len,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self {
+            Children::N4(sorted) => sorted.len == 4,
+            Children::N16(sorted) => sorted.len == 16,
+            Children::N48 { len, .. } => *len == 48,
+            Children::N256 { .. } => false,
+        }
+    }
+
+    fn get(&self, byte: u8) -> Option<usize> {
+        match self {
+            Children::N4(sorted) => sorted.position(byte).ok().map(|at| sorted.next[at]),
+            Children::N16(sorted) => sorted.position(byte).ok().map(|at| sorted.next[at]),
+            Children::N48 { slots, next, .. } => slots[byte as usize].checked_sub(1).map(|slot| next[slot as usize]),
+            Children::N256 { next, .. } => Some(next[byte as usize]).filter(|child| *child != NIL),
+        }
+    }
+
+    /// Link `child` under `byte`, replacing any child already there and growing the layout if it is full.
+    fn put(&mut self, byte: u8, child: usize) {
+        if self.get(byte).is_none() && self.is_full() {
+            *self = Children::build(self.len() + 1, &self.entries());
+        }
+        match self {
+            Children::N4(sorted) => match sorted.position(byte) {
+                Ok(at) => sorted.next[at] = child,
+                Err(at) => sorted.insert(at, byte, child),
+            },
+            Children::N16(sorted) => match sorted.position(byte) {
+                Ok(at) => sorted.next[at] = child,
+                Err(at) => sorted.insert(at, byte, child),
+            },
+            Children::N48 { slots, next, len } => match slots[byte as usize] {
+                0 => {
+                    let slot = next.iter().position(|c| *c == NIL).expect("layout has room");
+                    next[slot] = child;
+                    slots[byte as usize] = slot as u8 + 1;
+                    *len += 1;
+                }
+                slot => next[slot as usize - 1] = child,
+            },
+            Children::N256 { next, len } => {
+                if next[byte as usize] == NIL {
+                    *len += 1;
+                }
+                next[byte as usize] = child;
+            }
+        }
+    }
+
+    /// Unlink the child under `byte`, moving to a smaller layout once few children are left.
+    fn remove(&mut self, byte: u8) {
+        let shrink_below = match self {
+            Children::N4(sorted) => {
+                let at = sorted.position(byte).expect("child is present");
+                sorted.remove(at);
+                0
+            }
+            Children::N16(sorted) => {
+                let at = sorted.position(byte).expect("child is present");
+                sorted.remove(at);
+                4
+            }
+            Children::N48 { slots, next, len } => {
+                let slot = std::mem::take(&mut slots[byte as usize]);
+                next[slot as usize - 1] = NIL;
+                *len -= 1;
+                13
+            }
+            Children::N256 { next, len } => {
+                next[byte as usize] = NIL;
+                *len -= 1;
+                37
+            }
+        };
+        if self.len() < shrink_below {
+            *self = Children::build(self.len(), &self.entries());
+        }
+    }
+
+    /// The first child whose byte is at least `from`, which may be 256 to ask for none.
+    fn next_from(&self, from: usize) -> Option<(u8, usize)> {
+        match self {
+            Children::N4(sorted) => Children::sorted_from(&sorted.keys[..sorted.len], &sorted.next, from),
+            Children::N16(sorted) => Children::sorted_from(&sorted.keys[..sorted.len], &sorted.next, from),
+            Children::N48 { slots, next, .. } => {
+                (from..256).find(|b| slots[*b] != 0).map(|b| (b as u8, next[slots[b] as usize - 1]))
+            }
+            Children::N256 { next, .. } => (from..256).find(|b| next[*b] != NIL).map(|b| (b as u8, next[b])),
+        }
+    }
+
+    fn sorted_from(keys: &[u8], next: &[usize], from: usize) -> Option<(u8, usize)> {
+        let at = keys.partition_point(|key| (*key as usize) < from);
+        keys.get(at).map(|key| (*key, next[at]))
+    }
+
+    fn entries(&self) -> Vec<(u8, usize)> {
+        let mut entries = Vec::with_capacity(self.len());
+        let mut from = 0;
+        while let Some((byte, child)) = self.next_from(from) {
+            entries.push((byte, child));
+            from = byte as usize + 1;
+        }
+        entries
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+impl<V> Inner<V> {
+    fn alloc(&mut self, node: Node<V>) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, index: usize) -> Node<V> {
+        self.free.push(index);
+        std::mem::replace(&mut self.nodes[index], Node::Free)
+    }
+
+    fn branch(&mut self, index: usize) -> (&mut Vec<u8>, &mut usize, &mut Children) {
+        match &mut self.nodes[index] {
+            Node::Branch { prefix, end, children } => (prefix, end, children),
+            _ => unreachable!("links only lead out of branches"),
+        }
+    }
+
+    fn linked(&mut self, link: Link) -> usize {
+        match link {
+            Link::Root => self.root,
+            Link::End(parent) => *self.branch(parent).1,
+            Link::Child(parent, byte) => self.branch(parent).2.get(byte).unwrap_or(NIL),
+        }
+    }
+
+    fn relink(&mut self, link: Link, to: usize) {
+        match link {
+            Link::Root => self.root = to,
+            Link::End(parent) => *self.branch(parent).1 = to,
+            Link::Child(parent, byte) if to == NIL => self.branch(parent).2.remove(byte),
+            Link::Child(parent, byte) => self.branch(parent).2.put(byte, to),
+        }
+    }
+
+    /// Hang `child` from `branch` under `byte`, or in its end slot if the child's key ends there.
+    fn attach(&mut self, branch: usize, byte: Option<u8>, child: usize) {
+        let link = byte.map_or(Link::End(branch), |byte| Link::Child(branch, byte));
+        self.relink(link, child);
+    }
+
+    /// The leaf holding `key`.
+    fn find(&self, key: &[u8]) -> Option<usize> {
+        let mut node = self.root;
+        let mut depth = 0;
+        while node != NIL {
+            match &self.nodes[node] {
+                Node::Leaf { key: found, .. } => return (found == key).then_some(node),
+                Node::Branch { prefix, end, children } => {
+                    if !key[depth..].starts_with(prefix) {
+                        return None
+                    }
+                    depth += prefix.len();
+                    node = match key.get(depth) {
+                        None => *end,
+                        Some(byte) => {
+                            depth += 1;
+                            children.get(*byte).unwrap_or(NIL)
+                        }
+                    };
+                }
+                Node::Free => unreachable!("free slots are unlinked"),
+            }
+        }
+        None
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: V) -> Option<V> {
+        let mut link = Link::Root;
+        let mut depth = 0;
+        loop {
+            let node = self.linked(link);
+            if node == NIL {
+                let leaf = self.alloc(Node::Leaf { key, value });
+                self.relink(link, leaf);
+                self.len += 1;
+                return None
+            }
+            // Where a new branch goes, if the key parts ways with this node: its prefix, and this
+            // node's byte under it.
+            let split = match &mut self.nodes[node] {
+                Node::Leaf { key: existing, value: old } => {
+                    if *existing == key {
+                        return Some(std::mem::replace(old, value))
+                    }
+                    let common = common_prefix(&existing[depth..], &key[depth..]);
+                    (key[depth..depth + common].to_vec(), existing.get(depth + common).copied())
+                }
+                Node::Branch { prefix, .. } => {
+                    let common = common_prefix(prefix, &key[depth..]);
+                    if common == prefix.len() {
+                        depth += common;
+                        link = match key.get(depth) {
+                            None => Link::End(node),
+                            Some(byte) => {
+                                depth += 1;
+                                Link::Child(node, *byte)
+                            }
+                        };
+                        continue
+                    }
+                    let head = prefix[..common].to_vec();
+                    let byte = prefix[common];
+                    prefix.drain(..=common);
+                    (head, Some(byte))
+                }
+                Node::Free => unreachable!("free slots are unlinked"),
+            };
+            let (prefix, byte) = split;
+            let next = key.get(depth + prefix.len()).copied();
+            let branch = self.alloc(Node::Branch { prefix, end: NIL, children: Children::new() });
+            self.attach(branch, byte, node);
+            let leaf = self.alloc(Node::Leaf { key, value });
+            self.attach(branch, next, leaf);
+            self.relink(link, branch);
+            self.len += 1;
+            return None
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<V> {
+        // Links to every branch on the way down, so the leaf's parent can be folded into its own parent.
+        let mut path = Vec::new();
+        let mut link = Link::Root;
+        let mut depth = 0;
+        loop {
+            let node = self.linked(link);
+            if node == NIL {
+                return None
+            }
+            match &self.nodes[node] {
+                Node::Leaf { key: found, .. } if found == key => break,
+                Node::Leaf { .. } => return None,
+                Node::Branch { prefix, .. } => {
+                    if !key[depth..].starts_with(prefix) {
+                        return None
+                    }
+                    depth += prefix.len();
+                    path.push(link);
+                    link = match key.get(depth) {
+                        None => Link::End(node),
+                        Some(byte) => {
+                            depth += 1;
+                            Link::Child(node, *byte)
+                        }
+                    };
+                }
+                Node::Free => unreachable!("free slots are unlinked"),
+            }
+        }
+
+        let leaf = self.linked(link);
+        self.relink(link, NIL);
+        let Node::Leaf { value, .. } = self.release(leaf) else { unreachable!("the loop stopped at a leaf") };
+        self.len -= 1;
+        if let Link::End(parent) | Link::Child(parent, _) = link {
+            self.collapse(parent, *path.last().expect("parent was passed on the way down"));
+        }
+        Some(value)
+    }
+
+    /// Replace `branch` with its only remaining child, if it has just one.
+    fn collapse(&mut self, branch: usize, link: Link) {
+        let (_, end, children) = self.branch(branch);
+        let (byte, child) = match (*end, children.len()) {
+            (NIL, 1) => children.next_from(0).map(|(byte, child)| (Some(byte), child)).expect("one child"),
+            (end, 0) => (None, end),
+            _ => return,
+        };
+        let Node::Branch { mut prefix, .. } = self.release(branch) else { unreachable!("collapsing a branch") };
+        if let Node::Branch { prefix: below, .. } = &mut self.nodes[child] {
+            prefix.extend(byte);
+            prefix.extend_from_slice(below);
+            *below = prefix;
+        }
+        self.relink(link, child);
+    }
+
+    /// Iterator frames positioned at the first key admitted by `start`.
+    fn seek(&self, start: Bound<&[u8]>) -> Vec<(usize, usize)> {
+        let mut stack = Vec::new();
+        if self.root == NIL {
+            return stack
+        }
+        let (start, inclusive) = match start {
+            Bound::Unbounded => {
+                stack.push((self.root, 0));
+                return stack
+            }
+            Bound::Included(start) => (start, true),
+            Bound::Excluded(start) => (start, false),
+        };
+        let mut node = self.root;
+        let mut depth = 0;
+        loop {
+            match &self.nodes[node] {
+                Node::Leaf { key, .. } => {
+                    if key.as_slice() > start || inclusive && key == start {
+                        stack.push((node, 0));
+                    }
+                    return stack
+                }
+                Node::Branch { prefix, children, .. } => {
+                    let rest = &start[depth..];
+                    let shared = rest.len().min(prefix.len());
+                    match prefix[..shared].cmp(&rest[..shared]) {
+                        std::cmp::Ordering::Less => return stack,
+                        std::cmp::Ordering::Greater => {
+                            stack.push((node, 0));
+                            return stack
+                        }
+                        // Everything below is `start` followed by more bytes.
+                        std::cmp::Ordering::Equal if shared < prefix.len() => {
+                            stack.push((node, 0));
+                            return stack
+                        }
+                        std::cmp::Ordering::Equal => {}
+                    }
+                    depth += prefix.len();
+                    let Some(byte) = start.get(depth) else {
+                        // The end slot holds `start` itself; the children all come after it.
+                        stack.push((node, if inclusive { 0 } else { 1 }));
+                        return stack
+                    };
+                    stack.push((node, *byte as usize + 2));
+                    match children.get(*byte) {
+                        Some(child) => {
+                            node = child;
+                            depth += 1;
+                        }
+                        None => return stack,
+                    }
+                }
+                Node::Free => unreachable!("free slots are unlinked"),
+            }
+        }
+    }
+
+    /// Pop the next leaf in key order off `stack`. A frame is a node and how far it has been visited: 0
+    /// before its end slot, then `b + 1` once every child below byte `b` has been visited.
+    fn advance(&self, stack: &mut Vec<(usize, usize)>) -> Option<usize> {
+        while let Some(&(node, visited)) = stack.last() {
+            match &self.nodes[node] {
+                Node::Leaf { .. } => {
+                    stack.pop();
+                    return Some(node)
+                }
+                Node::Branch { end, children, .. } => {
+                    let frame = stack.len() - 1;
+                    if visited == 0 {
+                        stack[frame].1 = 1;
+                        if *end != NIL {
+                            stack.push((*end, 0));
+                        }
+                        continue
+                    }
+                    match children.next_from(visited - 1) {
+                        Some((byte, child)) => {
+                            stack[frame].1 = byte as usize + 2;
+                            stack.push((child, 0));
+                        }
+                        None => {
+                            stack.pop();
+                        }
+                    }
+                }
+                Node::Free => unreachable!("free slots are unlinked"),
+            }
+        }
+        None
+    }
+}
+
+impl<V> Art<V> {
+    pub fn new() -> Art<V> {
+        Art { inner: RwLock::new(Inner { nodes: Vec::new(), free: Vec::new(), root: NIL, len: 0 }) }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Inner<V>> {
+        self.inner.read().expect("radix tree lock poisoned")
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert or replace the value for `key`, returning the value it replaced.
+    pub fn insert(&self, key: Vec<u8>, value: V) -> Option<V> {
+        self.inner.write().expect("radix tree lock poisoned").insert(key, value)
+    }
+
+    /// Remove `key`, returning its value if it was present.
+    pub fn remove(&self, key: &[u8]) -> Option<V> {
+        self.inner.write().expect("radix tree lock poisoned").remove(key)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<V>
+    where V: Clone {
+        let inner = self.read();
+        let leaf = inner.find(key)?;
+        match &inner.nodes[leaf] {
+            Node::Leaf { value, .. } => Some(value.clone()),
+            _ => unreachable!("find returns leaves"),
+        }
+    }
+
+    /// Entries in key order. The iterator holds the read lock, so writes wait until it is dropped.
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.range_from(Bound::Unbounded)
+    }
+
+    /// Entries from `start` onward, in key order.
+    pub fn range_from(&self, start: Bound<&[u8]>) -> Iter<'_, V> {
+        let inner = self.read();
+        let stack = inner.seek(start);
+        Iter { inner, stack }
+    }
+
+    /// Freeze the tree and take its entries in key order, for flushing to sorted pages.
+    pub fn into_sorted(self) -> Vec<(Vec<u8>, V)> {
+        let inner = self.inner.into_inner().expect("radix tree lock poisoned");
+        let mut order = Vec::with_capacity(inner.len);
+        let mut stack = inner.seek(Bound::Unbounded);
+        while let Some(leaf) = inner.advance(&mut stack) {
+            order.push(leaf);
+        }
+        let mut slots: Vec<Option<Node<V>>> = inner.nodes.into_iter().map(Some).collect();
+        order.into_iter()
+            .map(|i| match slots[i].take() {
+                Some(Node::Leaf { key, value }) => (key, value),
+                _ => unreachable!("each leaf is linked once"),
+            })
+            .collect()
+    }
+}
+impl<V> Default for Art<V> {
+    fn default() -> Self {
+        Art::new()
+    }
+}
+
+pub struct Iter<'a, V> {
+    inner: RwLockReadGuard<'a, Inner<V>>,
+    stack: Vec<(usize, usize)>,
+}
+impl<V: Clone> Iterator for Iter<'_, V> {
+    type Item = (Vec<u8>, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf = self.inner.advance(&mut self.stack)?;
+        match &self.inner.nodes[leaf] {
+            Node::Leaf { key, value } => Some((key.clone(), value.clone())),
+            _ => unreachable!("advance returns leaves"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    use crate::allocator::PageAllocator;
+    use crate::btree::{BTree, BTreeError};
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::Art;
+
+    #[test]
+    fn test_matches_btreemap() {
+        let tree = Art::new();
+        let mut model = BTreeMap::new();
+        let mut x: u32 = 7;
+        for round in 0..20_000 {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            // Short keys over a small alphabet share prefixes and end inside each other; the wide first
+            // byte fills nodes up to the largest layout.
+            let len = (x >> 4) % 5;
+            let mut key = vec![(x >> 8) as u8];
+            key.extend((0..len).map(|i| b'a' + ((x >> (12 + 3 * i)) % 3) as u8));
+            if round % 3 == 2 {
+                assert_eq!(tree.remove(&key), model.remove(&key));
+            } else {
+                assert_eq!(tree.insert(key.clone(), round), model.insert(key, round));
+            }
+        }
+        assert_eq!(tree.len(), model.len());
+        assert!(tree.iter().eq(model.iter().map(|(k, v)| (k.clone(), *v))));
+        for probe in [&b""[..], b"\x10", b"\x10a", b"\x80ab", b"\x80abc", b"\xff", b"\xffccccc"] {
+            assert_eq!(tree.get(probe), model.get(probe).copied());
+            let from = tree.range_from(Bound::Included(probe));
+            assert!(from.eq(model.range(probe.to_vec()..).map(|(k, v)| (k.clone(), *v))), "{probe:?}");
+            let after = tree.range_from(Bound::Excluded(probe));
+            let expected = model.range::<[u8], _>((Bound::Excluded(probe), Bound::Unbounded));
+            assert!(after.eq(expected.map(|(k, v)| (k.clone(), *v))), "{probe:?}");
+        }
+
+        for key in model.keys().cloned().collect::<Vec<_>>().into_iter().step_by(2) {
+            assert_eq!(tree.remove(&key), model.remove(&key));
+        }
+        assert_eq!(tree.into_sorted(), model.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_flushes_into_btree() -> Result<(), BTreeError> {
+        let tree = Art::new();
+        for i in (0..3000u32).rev() {
+            tree.insert(format!("user:{:05}:email", i * 7 % 3000).into_bytes(), i.to_le_bytes().to_vec());
+        }
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let sorted = tree.into_sorted();
+        let btree = BTree::bulk_load(&store, allocator, sorted.iter().map(|(k, v)| (k, v)), 1.0)?;
+        assert!(btree.iter().map(|entry| entry.map(|(k, _)| k)).eq(sorted.iter().map(|(k, _)| Ok(k.clone()))));
+        assert_eq!(btree.get(b"user:00014:email")?, Some(2u32.to_le_bytes().to_vec()));
+        Ok(())
+    }
+}
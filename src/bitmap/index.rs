@@ -0,0 +1,209 @@
+//! Bitmap index: for each distinct column value, the bitmap of row numbers holding that value.
+//!
+//! Values map to their bitmaps through a B+tree. Small bitmaps are stored inline as the tree's value;
+//! larger ones go to the blob store and the tree keeps the blob id. Queries fetch one bitmap per value and
+//! combine them with the `Bitmap` set operations, so a multi-predicate filter is a few bitmap merges.
+use crate::allocator::PageAllocator;
+use crate::blob::{BlobError, BlobId, BlobStore};
+use crate::btree::{BTree, BTreeError};
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::storage::Storage;
+
+use super::Bitmap;
+
+const INLINE: u8 = 0;
+const BLOB: u8 = 1;
+/// Serialized bitmaps longer than this are moved to the blob store.
+const MAX_INLINE_LEN: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum BitmapIndexError {
+    Page(PageError),
+    ValueTooLarge,
+    Corrupt,
+}
+impl From<PageError> for BitmapIndexError {
+    fn from(e: PageError) -> Self {
+        BitmapIndexError::Page(e)
+    }
+}
+impl From<BTreeError> for BitmapIndexError {
+    fn from(e: BTreeError) -> Self {
+        match e {
+            BTreeError::Page(e) => BitmapIndexError::Page(e),
+            BTreeError::EntryTooLarge => BitmapIndexError::ValueTooLarge,
+            BTreeError::Unsorted => unreachable!("bitmap indexes are never bulk loaded"),
+        }
+    }
+}
+impl From<BlobError> for BitmapIndexError {
+    fn from(e: BlobError) -> Self {
+        match e {
+            BlobError::Page(e) => BitmapIndexError::Page(e),
+            BlobError::SeekOutOfRange => BitmapIndexError::Corrupt,
+        }
+    }
+}
+
+pub struct BitmapIndex<'store, S: Storage> {
+    tree: BTree<'store, S>,
+    blobs: BlobStore<'store, S>,
+}
+impl<'store, S: Storage> BitmapIndex<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<BitmapIndex<'store, S>, BitmapIndexError> {
+        Ok(BitmapIndex { tree: BTree::create(store, allocator)?, blobs: BlobStore::new(store, allocator) })
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, meta: PageId) -> Result<BitmapIndex<'store, S>, BitmapIndexError> {
+        Ok(BitmapIndex { tree: BTree::open(store, allocator, meta)?, blobs: BlobStore::new(store, allocator) })
+    }
+
+    /// The page to pass to `open` to find this index again.
+    pub fn meta_page(&self) -> PageId {
+        self.tree.meta_page()
+    }
+
+    /// Rows whose column holds `value`.
+    pub fn get(&self, value: &[u8]) -> Result<Bitmap, BitmapIndexError> {
+        match self.tree.get(value)? {
+            Some(stored) => self.load(&stored),
+            None => Ok(Bitmap::new()),
+        }
+    }
+
+    /// Rows whose column holds any of `values`.
+    pub fn any_of<'v>(&self, values: impl IntoIterator<Item = &'v [u8]>) -> Result<Bitmap, BitmapIndexError> {
+        values.into_iter().try_fold(Bitmap::new(), |acc, value| Ok(acc.or(&self.get(value)?)))
+    }
+
+    /// Distinct values in the index, in key order.
+    pub fn values(&self) -> Result<Vec<Vec<u8>>, BitmapIndexError> {
+        Ok(self.tree.iter().map(|entry| entry.map(|(k, _)| k)).collect::<Result<_, _>>()?)
+    }
+
+    /// Record that `row` holds `value`, returning whether it was not already recorded.
+    pub fn insert(&self, value: &[u8], row: u32) -> Result<bool, BitmapIndexError> {
+        let mut bitmap = self.get(value)?;
+        let added = bitmap.insert(row);
+        if added {
+            self.put(value, &bitmap)?;
+        }
+        Ok(added)
+    }
+
+    /// Record that every row in `rows` holds `value`, rewriting its bitmap once.
+    pub fn insert_all(&self, value: &[u8], rows: &Bitmap) -> Result<(), BitmapIndexError> {
+        let bitmap = self.get(value)?.or(rows);
+        self.put(value, &bitmap)
+    }
+
+    /// Forget that `row` holds `value`, returning whether it was recorded.
+    pub fn remove(&self, value: &[u8], row: u32) -> Result<bool, BitmapIndexError> {
+        let mut bitmap = self.get(value)?;
+        let removed = bitmap.remove(row);
+        if removed {
+            self.put(value, &bitmap)?;
+        }
+        Ok(removed)
+    }
+
+    fn load(&self, stored: &[u8]) -> Result<Bitmap, BitmapIndexError> {
+        let bytes = match stored.first() {
+            Some(&INLINE) => stored[1..].to_vec(),
+            Some(&BLOB) => {
+                let id = BlobId::from_bytes(stored[1..].try_into().map_err(|_| BitmapIndexError::Corrupt)?);
+                let mut reader = self.blobs.open(id)?;
+                let mut bytes = vec![0u8; reader.len() as usize];
+                let mut at = 0;
+                while at < bytes.len() {
+                    at += reader.read(&mut bytes[at..])?;
+                }
+                bytes
+            }
+            _ => return Err(BitmapIndexError::Corrupt),
+        };
+        Bitmap::from_bytes(&bytes).ok_or(BitmapIndexError::Corrupt)
+    }
+
+    /// Replace the bitmap for `value`, dropping the entry altogether once no rows hold it.
+    fn put(&self, value: &[u8], bitmap: &Bitmap) -> Result<(), BitmapIndexError> {
+        let bytes = bitmap.to_bytes();
+        let stored = if bitmap.is_empty() {
+            None
+        } else if bytes.len() <= MAX_INLINE_LEN {
+            Some([&[INLINE], &bytes[..]].concat())
+        } else {
+            let mut writer = self.blobs.create()?;
+            writer.write_all(&bytes)?;
+            Some([&[BLOB], &writer.finish()?.to_bytes()[..]].concat())
+        };
+        let old = match stored {
+            Some(stored) => self.tree.insert(value, &stored)?,
+            None => self.tree.delete(value)?,
+        };
+        if let Some(old) = old.filter(|old| old.first() == Some(&BLOB)) {
+            let id = BlobId::from_bytes(old[1..].try_into().map_err(|_| BitmapIndexError::Corrupt)?);
+            self.blobs.delete(id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::bitmap::Bitmap;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{BitmapIndex, BitmapIndexError};
+
+    #[test]
+    fn test_multi_predicate_filter() -> Result<(), BitmapIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let colour = BitmapIndex::create(&store, allocator)?;
+        let size = BitmapIndex::create(&store, allocator)?;
+
+        let colours: [&[u8]; 3] = [b"red", b"green", b"blue"];
+        let sizes: [&[u8]; 2] = [b"small", b"large"];
+        for (i, value) in colours.iter().enumerate() {
+            colour.insert_all(value, &(0..30_000).filter(|r| r % 3 == i as u32).collect())?;
+        }
+        for row in 0..3_000u32 {
+            size.insert(sizes[row as usize % 7 / 5], row)?;
+        }
+        assert!(!colour.insert(b"red", 0)?);
+
+        let red_or_blue = colour.any_of([&b"red"[..], b"blue"])?;
+        let large = size.get(b"large")?;
+        let hits = red_or_blue.and(&large);
+        let expected: Bitmap = (0..3_000).filter(|r| r % 3 != 1 && r % 7 >= 5).collect();
+        assert_eq!(hits, expected);
+        assert_eq!(large.not(3_000), size.get(b"small")?);
+
+        let colour = BitmapIndex::open(&store, allocator, colour.meta_page())?;
+        assert_eq!(colour.values()?, vec![b"blue".to_vec(), b"green".to_vec(), b"red".to_vec()]);
+        assert_eq!(colour.get(b"purple")?, Bitmap::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_and_bulk_insert() -> Result<(), BitmapIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = BitmapIndex::create(&store, allocator)?;
+
+        index.insert_all(b"x", &(0..1_000).collect())?;
+        index.insert_all(b"x", &(500..2_000).collect())?;
+        assert_eq!(index.get(b"x")?.len(), 2_000);
+        for row in 0..1_999 {
+            assert!(index.remove(b"x", row)?);
+        }
+        assert!(!index.remove(b"x", 0)?);
+        assert!(index.get(b"x")?.iter().eq([1_999]));
+        index.remove(b"x", 1_999)?;
+        assert!(index.values()?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+//! Compressed bitmaps of row numbers and the bitmap index built on them.
+//!
+//! `Bitmap` follows the roaring layout: the 32-bit space is split into chunks of 2^16 by the high 16 bits,
+//! and each non-empty chunk is a container holding the low 16 bits, either as a sorted array while the
+//! chunk is sparse or as a 2^16-bit bitset once it holds more than `ARRAY_MAX` values. Set operations
+//! work container by container.
+pub mod index;
+
+pub use index::{BitmapIndex, BitmapIndexError};
+
+const ARRAY_MAX: usize = 4096;
+const WORDS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Bitmap {
+    /// Containers by chunk, ordered by chunk.
+    containers: Vec<(u16, Container)>,
+}
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    And,
+    Or,
+    AndNot,
+}
+impl Op {
+    fn apply(self, a: u64, b: u64) -> u64 {
+        match self {
+            Op::And => a & b,
+            Op::Or => a | b,
+            Op::AndNot => a & !b,
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Container {
+    Array(Vec<u16>),
+    Bits(Box<[u64; WORDS]>),
+}
+impl Container {
+    fn len(&self) -> usize {
+        match self {
+            Container::Array(values) => values.len(),
+            Container::Bits(words) => words.iter().map(|w| w.count_ones() as usize).sum(),
+        }
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&low).is_ok(),
+            Container::Bits(words) => words[low as usize / 64] >> (low % 64) & 1 == 1,
+        }
+    }
+
+    fn words(&self) -> Box<[u64; WORDS]> {
+        match self {
+            Container::Bits(words) => words.clone(),
+            Container::Array(values) => {
+                let mut words = Box::new([0u64; WORDS]);
+                for v in values {
+                    words[*v as usize / 64] |= 1 << (v % 64);
+                }
+                words
+            }
+        }
+    }
+
+    /// The cheaper representation for a bitset's contents, or `None` if it is empty.
+    fn from_words(words: Box<[u64; WORDS]>) -> Option<Container> {
+        let len: usize = words.iter().map(|w| w.count_ones() as usize).sum();
+        match len {
+            0 => None,
+            len if len <= ARRAY_MAX => Some(Container::Array(Container::Bits(words).iter().collect())),
+            _ => Some(Container::Bits(words)),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(values) => Box::new(values.iter().copied()),
+            Container::Bits(words) => Box::new((0..WORDS).flat_map(move |i| {
+                let word = words[i];
+                (0..64).filter(move |b| word >> b & 1 == 1).map(move |b| (i * 64 + b) as u16)
+            })),
+        }
+    }
+
+    fn insert(&mut self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => match values.binary_search(&low) {
+                Ok(_) => false,
+                Err(at) => {
+                    values.insert(at, low);
+                    if values.len() > ARRAY_MAX {
+                        *self = Container::Bits(self.words());
+                    }
+                    true
+                }
+            },
+            Container::Bits(words) => {
+                let (word, bit) = (low as usize / 64, low % 64);
+                let added = words[word] >> bit & 1 == 0;
+                words[word] |= 1 << bit;
+                added
+            }
+        }
+    }
+
+    fn remove(&mut self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => match values.binary_search(&low) {
+                Ok(at) => {
+                    values.remove(at);
+                    true
+                }
+                Err(_) => false,
+            },
+            Container::Bits(words) => {
+                let (word, bit) = (low as usize / 64, low % 64);
+                let removed = words[word] >> bit & 1 == 1;
+                words[word] &= !(1 << bit);
+                if removed && self.len() <= ARRAY_MAX {
+                    *self = Container::Array(self.iter().collect());
+                }
+                removed
+            }
+        }
+    }
+
+    fn combine(&self, other: &Container, op: Op) -> Option<Container> {
+        if let (Container::Array(a), Container::Array(b), Op::And) = (self, other, op) {
+            // Intersecting two arrays is a merge; no need to expand them to bitsets.
+            let mut out = Vec::new();
+            let (mut i, mut j) = (0, 0);
+            while i < a.len() && j < b.len() {
+                match a[i].cmp(&b[j]) {
+                    std::cmp::Ordering::Less => i += 1,
+                    std::cmp::Ordering::Greater => j += 1,
+                    std::cmp::Ordering::Equal => {
+                        out.push(a[i]);
+                        i += 1;
+                        j += 1;
+                    }
+                }
+            }
+            return (!out.is_empty()).then_some(Container::Array(out))
+        }
+        let (mut left, right) = (self.words(), other.words());
+        for (l, r) in left.iter_mut().zip(right.iter()) {
+            *l = op.apply(*l, *r);
+        }
+        Container::from_words(left)
+    }
+}
+
+impl Bitmap {
+    pub fn new() -> Bitmap {
+        Bitmap::default()
+    }
+
+    /// Every value in `0..len`.
+    pub fn full(len: u32) -> Bitmap {
+        let mut bitmap = Bitmap::new();
+        let mut start = 0u64;
+        while start < len as u64 {
+            let end = (start + (1 << 16)).min(len as u64);
+            let mut words = Box::new([0u64; WORDS]);
+            for v in 0..(end - start) as usize {
+                words[v / 64] |= 1 << (v % 64);
+            }
+            if let Some(container) = Container::from_words(words) {
+                bitmap.containers.push(((start >> 16) as u16, container));
+            }
+            start = end;
+        }
+        bitmap
+    }
+
+    fn find(&self, chunk: u16) -> Result<usize, usize> {
+        self.containers.binary_search_by_key(&chunk, |(c, _)| *c)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.containers.iter().map(|(_, c)| c.len() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.containers.is_empty()
+    }
+
+    pub fn contains(&self, value: u32) -> bool {
+        self.find((value >> 16) as u16).is_ok_and(|i| self.containers[i].1.contains(value as u16))
+    }
+
+    /// Add `value`, returning whether it was absent.
+    pub fn insert(&mut self, value: u32) -> bool {
+        let chunk = (value >> 16) as u16;
+        match self.find(chunk) {
+            Ok(i) => self.containers[i].1.insert(value as u16),
+            Err(i) => {
+                self.containers.insert(i, (chunk, Container::Array(vec![value as u16])));
+                true
+            }
+        }
+    }
+
+    /// Remove `value`, returning whether it was present.
+    pub fn remove(&mut self, value: u32) -> bool {
+        let Ok(i) = self.find((value >> 16) as u16) else { return false };
+        let removed = self.containers[i].1.remove(value as u16);
+        if self.containers[i].1.len() == 0 {
+            self.containers.remove(i);
+        }
+        removed
+    }
+
+    /// Values in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.containers.iter().flat_map(|(chunk, c)| c.iter().map(move |low| (*chunk as u32) << 16 | low as u32))
+    }
+
+    pub fn and(&self, other: &Bitmap) -> Bitmap {
+        self.merge(other, Op::And)
+    }
+
+    pub fn or(&self, other: &Bitmap) -> Bitmap {
+        self.merge(other, Op::Or)
+    }
+
+    /// Values in `self` that are not in `other`.
+    pub fn and_not(&self, other: &Bitmap) -> Bitmap {
+        self.merge(other, Op::AndNot)
+    }
+
+    /// Values in `0..universe` that are not in `self`.
+    pub fn not(&self, universe: u32) -> Bitmap {
+        Bitmap::full(universe).and_not(self)
+    }
+
+    /// Combine container by container. A chunk present on only one side survives unchanged when `op`
+    /// keeps values from that side.
+    fn merge(&self, other: &Bitmap, op: Op) -> Bitmap {
+        let keep_left = op != Op::And;
+        let keep_right = op == Op::Or;
+        let mut containers = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        let (a, b) = (&self.containers, &other.containers);
+        while i < a.len() || j < b.len() {
+            let order = match (a.get(i), b.get(j)) {
+                (Some((ka, _)), Some((kb, _))) => ka.cmp(kb),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                _ => std::cmp::Ordering::Greater,
+            };
+            match order {
+                std::cmp::Ordering::Less => {
+                    if keep_left {
+                        containers.push(a[i].clone());
+                    }
+                    i += 1;
+                }
+                std::cmp::Ordering::Greater => {
+                    if keep_right {
+                        containers.push(b[j].clone());
+                    }
+                    j += 1;
+                }
+                std::cmp::Ordering::Equal => {
+                    if let Some(c) = a[i].1.combine(&b[j].1, op) {
+                        containers.push((a[i].0, c));
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        Bitmap { containers }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = (self.containers.len() as u32).to_le_bytes().to_vec();
+        for (chunk, container) in &self.containers {
+            out.extend_from_slice(&chunk.to_le_bytes());
+            match container {
+                Container::Array(values) => {
+                    out.push(0);
+                    out.extend_from_slice(&(values.len() as u16).to_le_bytes());
+                    values.iter().for_each(|v| out.extend_from_slice(&v.to_le_bytes()));
+                }
+                Container::Bits(words) => {
+                    out.push(1);
+                    words.iter().for_each(|w| out.extend_from_slice(&w.to_le_bytes()));
+                }
+            }
+        }
+        out
+    }
+
+    /// Decode the output of `to_bytes`. Returns `None` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Bitmap> {
+        let take = |at: &mut usize, n: usize| -> Option<&[u8]> {
+            let slice = bytes.get(*at..*at + n)?;
+            *at += n;
+            Some(slice)
+        };
+        let mut at = 0;
+        let count = u32::from_le_bytes(take(&mut at, 4)?.try_into().ok()?);
+        let mut containers = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let chunk = u16::from_le_bytes(take(&mut at, 2)?.try_into().ok()?);
+            let container = match take(&mut at, 1)?[0] {
+                0 => {
+                    let len = u16::from_le_bytes(take(&mut at, 2)?.try_into().ok()?) as usize;
+                    Container::Array(take(&mut at, len * 2)?.chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect())
+                }
+                1 => {
+                    let mut words = Box::new([0u64; WORDS]);
+                    for (word, raw) in words.iter_mut().zip(take(&mut at, WORDS * 8)?.chunks(8)) {
+                        *word = u64::from_le_bytes(raw.try_into().ok()?);
+                    }
+                    Container::Bits(words)
+                }
+                _ => return None,
+            };
+            containers.push((chunk, container));
+        }
+        (at == bytes.len()).then_some(Bitmap { containers })
+    }
+}
+impl FromIterator<u32> for Bitmap {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        let mut bitmap = Bitmap::new();
+        iter.into_iter().for_each(|v| {
+            bitmap.insert(v);
+        });
+        bitmap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::Bitmap;
+
+    #[test]
+    fn test_set_operations_match_btreeset() {
+        // Sparse values in one chunk, a dense run spanning two chunks, and a far-away value.
+        let a: BTreeSet<u32> = (0..70_000).step_by(7).chain(200_000..210_000).chain([u32::MAX]).collect();
+        let b: BTreeSet<u32> = (0..70_000).step_by(3).chain(205_000..260_000).collect();
+        let (ba, bb): (Bitmap, Bitmap) = (a.iter().copied().collect(), b.iter().copied().collect());
+
+        assert_eq!(ba.len(), a.len() as u64);
+        assert!(ba.iter().eq(a.iter().copied()));
+        assert!(ba.and(&bb).iter().eq(a.intersection(&b).copied()));
+        assert!(ba.or(&bb).iter().eq(a.union(&b).copied()));
+        assert!(ba.and_not(&bb).iter().eq(a.difference(&b).copied()));
+        assert!(bb.not(300_000).iter().eq((0..300_000).filter(|v| !b.contains(v))));
+        assert!(ba.contains(21) && !ba.contains(22) && ba.contains(u32::MAX));
+        assert_eq!(Bitmap::from_bytes(&ba.to_bytes()), Some(ba.clone()));
+        assert_eq!(Bitmap::from_bytes(&ba.to_bytes()[1..]), None);
+    }
+
+    #[test]
+    fn test_containers_switch_representation() {
+        let mut bitmap: Bitmap = (0..5000).collect();
+        assert!(bitmap.to_bytes().len() > 8000);
+        for v in 0..2000 {
+            assert!(bitmap.remove(v));
+        }
+        assert!(!bitmap.remove(0));
+        assert!(bitmap.to_bytes().len() < 7000);
+        assert!(bitmap.iter().eq(2000..5000));
+        for v in 2000..5000 {
+            bitmap.remove(v);
+        }
+        assert!(bitmap.is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+//! Blob store: values far larger than a page, written and read as streams.
+//!
+//! A blob's data is cut into full pages that carry no header of their own. A chain of index pages,
+//! starting at the blob's head page, lists the data pages in order and records the blob's length. The
+//! head page id is the `BlobId`, which records embed to refer to a blob. Readers and writers pin one
+//! data page at a time, so a multi-megabyte value never occupies more than a frame or two of the pool
+//! and never has to be held in memory whole.
+use std::io;
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+
+const MAGIC: u32 = 0x424c_4f42;
+const NEXT: usize = 8;
+const COUNT: usize = 16;
+const LEN: usize = 24;
+const PAGES: usize = 32;
+const PAGES_PER_INDEX: usize = (PAGE_SIZE - PAGES) / 8;
+const NO_PAGE: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlobId(PageId);
+impl BlobId {
+    /// Encoding for storing the id inside a record.
+    pub fn to_bytes(self) -> [u8; 8] {
+        (self.0.offset() as u64).to_le_bytes()
+    }
+
+    pub fn from_bytes(bytes: [u8; 8]) -> BlobId {
+        BlobId(PageId::new(u64::from_le_bytes(bytes) as usize))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BlobError {
+    Page(PageError),
+    SeekOutOfRange,
+}
+impl From<PageError> for BlobError {
+    fn from(e: PageError) -> Self {
+        BlobError::Page(e)
+    }
+}
+
+pub struct BlobStore<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+}
+impl<S: Storage> Clone for BlobStore<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<S: Storage> Copy for BlobStore<'_, S> {}
+impl<'store, S: Storage> BlobStore<'store, S> {
+    pub fn new(store: &'store PageStore<S>, allocator: PageAllocator) -> BlobStore<'store, S> {
+        BlobStore { store, allocator }
+    }
+
+    /// Start a new blob. Its id is returned by `BlobWriter::finish`.
+    pub fn create(&self) -> Result<BlobWriter<'store, S>, BlobError> {
+        let head = new_index_page(self.store, &self.allocator)?;
+        Ok(BlobWriter { blobs: *self, head, tail: head, buffer: Vec::with_capacity(PAGE_SIZE), len: 0 })
+    }
+
+    pub fn open(&self, id: BlobId) -> Result<BlobReader<'store, S>, BlobError> {
+        let mut index_pages = Vec::new();
+        let mut len = 0;
+        let mut next = id.0.offset() as u64;
+        while next != NO_PAGE {
+            let page = self.store.pin_page(&PageId::new(next as usize))?;
+            let buf = page.try_read()?;
+            if read_u32(&*buf, 0) != MAGIC {
+                return Err(BlobError::Page(PageError::WrongPageType))
+            }
+            if index_pages.is_empty() {
+                len = read_u64(&*buf, LEN);
+            }
+            index_pages.push(page.id());
+            next = read_u64(&*buf, NEXT);
+        }
+        Ok(BlobReader { store: self.store, index_pages, len, position: 0 })
+    }
+
+    /// Free every page of the blob.
+    pub fn delete(&self, id: BlobId) -> Result<(), BlobError> {
+        let reader = self.open(id)?;
+        for index in &reader.index_pages {
+            let data_pages: Vec<PageId> = {
+                let page = self.store.pin_page(index)?;
+                let buf = page.try_read()?;
+                (0..read_u16(&*buf, COUNT) as usize).map(|i| PageId::new(read_u64(&*buf, PAGES + i * 8) as usize)).collect()
+            };
+            for data in data_pages {
+                self.allocator.free(self.store, data)?;
+            }
+        }
+        for index in reader.index_pages {
+            self.allocator.free(self.store, index)?;
+        }
+        Ok(())
+    }
+}
+
+fn new_index_page<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator) -> Result<PageId, PageError> {
+    let page = allocator.allocate(store)?;
+    let mut buf = page.try_write()?;
+    write_u32(&mut *buf, 0, MAGIC);
+    write_u64(&mut *buf, NEXT, NO_PAGE);
+    Ok(page.id())
+}
+
+/// Appends data to a new blob. The blob only becomes readable through its id once `finish` is called;
+/// dropping an unfinished writer leaks the pages written so far.
+pub struct BlobWriter<'store, S: Storage> {
+    blobs: BlobStore<'store, S>,
+    head: PageId,
+    tail: PageId,
+    buffer: Vec<u8>,
+    len: u64,
+}
+impl<S: Storage> BlobWriter<'_, S> {
+    pub fn write_all(&mut self, mut data: &[u8]) -> Result<(), BlobError> {
+        while !data.is_empty() {
+            let take = data.len().min(PAGE_SIZE - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            self.len += take as u64;
+            if self.buffer.len() == PAGE_SIZE {
+                self.write_page()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out any partial last page and return the new blob's id.
+    pub fn finish(mut self) -> Result<BlobId, BlobError> {
+        if !self.buffer.is_empty() {
+            self.write_page()?;
+        }
+        let head = self.blobs.store.pin_page(&self.head)?;
+        write_u64(&mut *head.try_write()?, LEN, self.len);
+        Ok(BlobId(self.head))
+    }
+
+    fn write_page(&mut self) -> Result<(), BlobError> {
+        let BlobStore { store, allocator } = self.blobs;
+        let data = allocator.allocate(store)?;
+        data.try_write()?[..self.buffer.len()].copy_from_slice(&self.buffer);
+        self.buffer.clear();
+
+        let tail = store.pin_page(&self.tail)?;
+        let mut buf = tail.try_write()?;
+        let count = read_u16(&*buf, COUNT) as usize;
+        if count < PAGES_PER_INDEX {
+            write_u64(&mut *buf, PAGES + count * 8, data.id().offset() as u64);
+            write_u16(&mut *buf, COUNT, count as u16 + 1);
+            return Ok(())
+        }
+        let next = new_index_page(store, &allocator)?;
+        write_u64(&mut *buf, NEXT, next.offset() as u64);
+        drop(buf);
+        let next_page = store.pin_page(&next)?;
+        let mut next_buf = next_page.try_write()?;
+        write_u64(&mut *next_buf, PAGES, data.id().offset() as u64);
+        write_u16(&mut *next_buf, COUNT, 1);
+        self.tail = next;
+        Ok(())
+    }
+}
+impl<S: Storage> io::Write for BlobWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf).map_err(|e| io::Error::other(format!("{e:?}")))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads a blob sequentially from a movable position.
+pub struct BlobReader<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    index_pages: Vec<PageId>,
+    len: u64,
+    position: u64,
+}
+impl<S: Storage> BlobReader<'_, S> {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn seek(&mut self, position: u64) -> Result<(), BlobError> {
+        if position > self.len {
+            return Err(BlobError::SeekOutOfRange)
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    /// Copy bytes from the current position into `out`, returning how many were read. Reads stop at a
+    /// page boundary, so a short read does not mean the end of the blob; zero does.
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize, BlobError> {
+        if self.position == self.len || out.is_empty() {
+            return Ok(0)
+        }
+        let page_number = (self.position / PAGE_SIZE as u64) as usize;
+        let within = (self.position % PAGE_SIZE as u64) as usize;
+        let data = {
+            let index = self.store.pin_page(&self.index_pages[page_number / PAGES_PER_INDEX])?;
+            let at = PAGES + (page_number % PAGES_PER_INDEX) * 8;
+            let page = read_u64(&*index.try_read()?, at);
+            PageId::new(page as usize)
+        };
+        let remaining = (self.len - self.position) as usize;
+        let n = out.len().min(PAGE_SIZE - within).min(remaining);
+        let page = self.store.pin_page(&data)?;
+        out[..n].copy_from_slice(&page.try_read()?[within..within + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+impl<S: Storage> io::Read for BlobReader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        BlobReader::read(self, buf).map_err(|e| io::Error::other(format!("{e:?}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{BlobError, BlobId, BlobReader, BlobStore};
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_stream_multi_megabyte_blob() -> Result<(), BlobError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let blobs = BlobStore::new(&store, allocator);
+
+        let data = content(3 * 1024 * 1024 + 17);
+        let mut writer = blobs.create()?;
+        for piece in data.chunks(1000) {
+            writer.write_all(piece)?;
+        }
+        let id = BlobId::from_bytes(writer.finish()?.to_bytes());
+
+        let mut reader = blobs.open(id)?;
+        assert_eq!(reader.len(), data.len() as u64);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert!(read == data);
+
+        reader.seek(5000)?;
+        let mut buf = [0u8; 100];
+        assert_eq!(BlobReader::read(&mut reader, &mut buf)?, 100);
+        assert_eq!(&buf[..], &data[5000..5100]);
+        assert_eq!(reader.seek(data.len() as u64 + 1), Err(BlobError::SeekOutOfRange));
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_and_delete() -> Result<(), BlobError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let blobs = BlobStore::new(&store, allocator);
+
+        let data = content(20_000);
+        let mut writer = blobs.create()?;
+        io::copy(&mut &data[..], &mut writer).unwrap();
+        let id = writer.finish()?;
+        let empty = blobs.create()?.finish()?;
+        assert!(blobs.open(empty)?.is_empty());
+
+        let mut copied = Vec::new();
+        io::copy(&mut blobs.open(id)?, &mut copied).unwrap();
+        assert_eq!(copied, data);
+
+        blobs.delete(id)?;
+        assert!(blobs.open(id).is_err());
+        Ok(())
+    }
+
+}
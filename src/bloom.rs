@@ -0,0 +1,85 @@
+//! Bloom filters: compact key sets that answer "definitely absent" or "possibly present".
+//!
+//! Access methods keep a filter next to their data so lookups for absent keys can skip reading pages.
+//! Probe positions come from `hash_key` by double hashing, so a filter's bytes stay meaningful across
+//! restarts and can be persisted as they are.
+use crate::hash::hash_key;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+impl BloomFilter {
+    /// A filter sized to hold `expected` keys with roughly the given false positive rate.
+    pub fn new(expected: usize, false_positive_rate: f64) -> BloomFilter {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(expected.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(8.0) as usize;
+        let hashes = ((bits as f64 / expected.max(1) as f64) * ln2).round().clamp(1.0, 16.0) as u32;
+        BloomFilter { bits: vec![0; bits.div_ceil(8)], hashes }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        insert(&mut self.bits, self.hashes, key)
+    }
+
+    /// Add a key given its `hash_key`, for callers that hash keys before the filter can be sized.
+    pub(crate) fn insert_hash(&mut self, hash: u64) {
+        for bit in probes(self.bits.len() * 8, self.hashes, hash) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// False means `key` was never inserted; true means it probably was.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        may_contain(&self.bits, self.hashes, key)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.hashes.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    /// Decode the output of `to_bytes`. Returns `None` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<BloomFilter> {
+        let hashes = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?);
+        (bytes.len() > 4 && hashes > 0).then(|| BloomFilter { bits: bytes[4..].to_vec(), hashes })
+    }
+}
+
+fn probes(bit_count: usize, hashes: u32, h: u64) -> impl Iterator<Item = usize> {
+    let step = h.rotate_left(32) | 1;
+    (0..hashes as u64).map(move |i| (h.wrapping_add(i.wrapping_mul(step)) % bit_count as u64) as usize)
+}
+
+/// Add `key` to a filter held in `bits`, for filters embedded in a page rather than in a `BloomFilter`.
+pub(crate) fn insert(bits: &mut [u8], hashes: u32, key: &[u8]) {
+    for bit in probes(bits.len() * 8, hashes, hash_key(key)) {
+        bits[bit / 8] |= 1 << (bit % 8);
+    }
+}
+
+pub(crate) fn may_contain(bits: &[u8], hashes: u32, key: &[u8]) -> bool {
+    probes(bits.len() * 8, hashes, hash_key(key)).all(|bit| bits[bit / 8] >> (bit % 8) & 1 == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BloomFilter;
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        for i in 0..10_000u32 {
+            filter.insert(&i.to_le_bytes());
+        }
+        assert!((0..10_000u32).all(|i| filter.may_contain(&i.to_le_bytes())));
+        let false_positives = (10_000..110_000u32).filter(|i| filter.may_contain(&i.to_le_bytes())).count();
+        assert!(false_positives < 2_000, "{false_positives}");
+
+        let decoded = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(decoded, filter);
+        assert_eq!(BloomFilter::from_bytes(&[0; 3]), None);
+    }
+}
@@ -0,0 +1,1513 @@
+//! Disk-based B+tree over the page store.
+//!
+//! Keys and values are byte strings and keys are ordered bytewise. Every node is a slotted page whose
+//! records are cells kept in key order, with the node header in the slotted page's reserved area. Leaves
+//! hold (key, value) cells and are chained in both directions. Inner nodes keep their leftmost child in the
+//! header and hold (separator, child) cells, where a child covers every key greater than or equal to its
+//! separator and less than the next one. A meta page records the root so the root can move when it splits.
+//!
+//! Keys are prefix compressed: a node's header stores the longest prefix shared by all of its keys, and
+//! each cell stores only the rest. Leaf splits promote the shortest separator that divides the two
+//! halves rather than the right half's whole first key, so inner nodes stay small for long keys. Nodes
+//! written before prefix compression (format version 0) are still read; they are rewritten in the
+//! current format the first time a cell is added to them.
+//!
+//! A tree can be shared between threads. Descents couple latches, taking each child's latch before
+//! releasing its parent's. Lookups and deletes read latch down to the leaf, which writers then latch for
+//! writing. An insert that might split its leaf starts over with write latches from the meta page down,
+//! keeping only those above the lowest node that cannot split. Range scans hold no latch between items
+//! and find keys moved right by a concurrent split through the leaf chain.
+//!
+//! A delete that leaves a node less than a quarter full rebalances it with a sibling, merging the two if
+//! they fit in one node and moving cells between them otherwise, and a merge that leaves the root with a
+//! single child makes that child the root. Like a splitting insert, such a delete write latches from the
+//! meta page down, keeping only the latches above the lowest node that cannot underflow. Range scans keep
+//! their current leaf pinned, and cells never move left out of a leaf that someone else has pinned, so a
+//! scan's leaf is neither freed nor emptied behind its cursor.
+use std::collections::HashSet;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{Data, PageError, PageId, PageStore, PinnedPage, ReadLatch, WriteLatch, PAGE_SIZE};
+use crate::slotted_page::{SlotId, SlottedPage, SlottedPageError, HEADER_LEN, SLOT_LEN};
+use crate::storage::Storage;
+use crate::varint;
+
+const META_MAGIC: u32 = 0x4254_4d41;
+const ROOT: usize = 8;
+
+const NODE_MAGIC: u32 = 0x4254_4e44;
+const KIND: usize = 4;
+const VERSION: usize = 5;
+const PREFIX_LEN: usize = 6;
+const NEXT: usize = 8;
+const PREV: usize = 16;
+const LEFTMOST: usize = 24;
+/// Fixed part of the node header. The key prefix follows it in the reserved area.
+const NODE_HEADER_LEN: usize = 32;
+/// Version 0 nodes have no prefix and a u16 key length in each cell. Version 1 cells store the key's
+/// suffix after the node prefix with a varint length.
+const FORMAT_VERSION: u8 = 1;
+const LEAF: u8 = 1;
+const INNER: u8 = 2;
+const NO_PAGE: u64 = u64::MAX;
+
+const NODE_CAPACITY: usize = PAGE_SIZE - HEADER_LEN - NODE_HEADER_LEN;
+/// Cells are capped at a quarter of a node so that splitting an overfull node always yields two that fit.
+const MAX_CELL_LEN: usize = NODE_CAPACITY / 4 - SLOT_LEN;
+/// Nodes other than the root whose cells take fewer bytes than this are rebalanced by delete.
+const MIN_FILL: usize = NODE_CAPACITY / 4;
+
+/// Whether an entry of `key` and `value` is small enough for a tree to hold, rather than failing with
+/// `EntryTooLarge`.
+pub fn entry_fits(key: &[u8], value: &[u8]) -> bool {
+    varint::encoded_len(key.len() as u64) + key.len() + value.len().max(8) <= MAX_CELL_LEN
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BTreeError {
+    Page(PageError),
+    EntryTooLarge,
+    /// Bulk load input was not in strictly ascending key order.
+    Unsorted,
+}
+
+/// A broken invariant found by `BTree::verify`. Cell indexes count from 0 within their node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A page reached from the tree is not a B+tree node.
+    NotANode { page: PageId },
+    /// A cell's encoding cannot be parsed.
+    MalformedCell { page: PageId, index: usize },
+    /// An inner node has no leftmost child.
+    NoLeftmostChild { page: PageId },
+    /// A page is the child of more than one inner node, or of one node more than once.
+    ReachedTwice { page: PageId },
+    /// A key is not greater than the one before it in the same node.
+    OutOfOrder { page: PageId, index: usize },
+    /// A key is outside the range the separators of the node's ancestors allow it.
+    OutsideParentRange { page: PageId, index: usize },
+    /// Leaves lie at different depths; `depth` is this leaf's and `expected` the first leaf's.
+    UnevenDepth { page: PageId, depth: usize, expected: usize },
+    /// A leaf's next or previous link does not name the leaf beside it in key order.
+    BadNextLink { page: PageId, found: Option<PageId>, expected: Option<PageId> },
+    BadPrevLink { page: PageId, found: Option<PageId>, expected: Option<PageId> },
+    /// A page in the tree is also on the allocator's free list.
+    FreePageInTree { page: PageId },
+}
+impl From<PageError> for BTreeError {
+    fn from(e: PageError) -> Self {
+        BTreeError::Page(e)
+    }
+}
+
+pub struct BTree<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    meta: PageId,
+}
+impl<'store, S: Storage> BTree<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<BTree<'store, S>, BTreeError> {
+        let meta = allocator.allocate(store)?;
+        let root = allocator.allocate(store)?;
+        Node::init(root.try_write()?, LEAF);
+        let mut buf = meta.try_write()?;
+        write_u32(&mut *buf, 0, META_MAGIC);
+        write_u64(&mut *buf, ROOT, root.id().offset() as u64);
+        Ok(BTree { store, allocator, meta: meta.id() })
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, meta: PageId) -> Result<BTree<'store, S>, BTreeError> {
+        if read_u32(&*store.read_latch(&meta)?, 0) != META_MAGIC {
+            return Err(BTreeError::Page(PageError::WrongPageType))
+        }
+        Ok(BTree { store, allocator, meta })
+    }
+
+    /// Build a tree from `entries`, which must be in strictly ascending key order, bottom-up: leaves are
+    /// written left to right, each packed to `fill_factor` of a page, and then each inner level is built
+    /// over the one below it. Every node is written once, so this is far cheaper than inserting the entries
+    /// one at a time. A fill factor below 1 leaves room for later inserts; it is clamped to at least 0.5.
+    pub fn bulk_load<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        entries: impl IntoIterator<Item = (K, V)>,
+        fill_factor: f64,
+    ) -> Result<BTree<'store, S>, BTreeError> {
+        let tree = BTree::create(store, allocator)?;
+        let budget = (fill_factor.clamp(0.5, 1.0) * NODE_CAPACITY as f64) as usize;
+
+        // The first leaf is the empty root `create` made; each further leaf comes with its separator.
+        let mut level = vec![(Vec::new(), tree.root()?)];
+        let mut cells: Vec<Vec<u8>> = Vec::new();
+        let mut used = 0;
+        for (key, value) in entries {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            if !entry_fits(key, value) {
+                return Err(BTreeError::EntryTooLarge)
+            }
+            let cell = encode_cell(key, value);
+            if let Some(last) = cells.last() {
+                if cell_key(last) >= key {
+                    return Err(BTreeError::Unsorted)
+                }
+                if packed_len(&cells, used, &cell) > budget {
+                    let separator = shortest_separator(cell_key(last), key).to_vec();
+                    let prev = level.last().unwrap().1;
+                    let next = tree.write_leaf(prev, &cells)?;
+                    level.push((separator, next));
+                    cells.clear();
+                    used = 0;
+                }
+            }
+            used += cell.len() + SLOT_LEN;
+            cells.push(cell);
+        }
+        // The last batch belongs to the newest leaf, or to the root if it is the only one.
+        let last = level.last().unwrap().1;
+        let page = store.pin_page(&last)?;
+        Node::new(page.try_write()?).fill(&cells);
+        drop(page);
+
+        while level.len() > 1 {
+            level = tree.build_inner_level(level, budget)?;
+        }
+        tree.set_root(level[0].1)?;
+        Ok(tree)
+    }
+
+    /// Fill the leaf `id` with `cells` and allocate the leaf after it, linked both ways, returning the new
+    /// leaf.
+    fn write_leaf(&self, id: PageId, cells: &[Vec<u8>]) -> Result<PageId, BTreeError> {
+        let next_page = self.allocator.allocate(self.store)?;
+        let mut next = Node::init(next_page.try_write()?, LEAF);
+        next.set_link(PREV, Some(id));
+        drop(next);
+        let page = self.store.pin_page(&id)?;
+        let mut node = Node::new(page.try_write()?);
+        node.fill(cells);
+        node.set_link(NEXT, Some(next_page.id()));
+        Ok(next_page.id())
+    }
+
+    /// Group `children`, each with the separator that precedes it, into inner nodes packed to `budget`
+    /// bytes, returning the new nodes with the separators that precede them.
+    fn build_inner_level(&self, children: Vec<(Vec<u8>, PageId)>, budget: usize) -> Result<Vec<(Vec<u8>, PageId)>, BTreeError> {
+        let mut groups: Vec<Vec<(Vec<u8>, PageId)>> = Vec::new();
+        let mut cells: Vec<Vec<u8>> = Vec::new();
+        let mut used = 0;
+        for (separator, child) in children {
+            let cell = encode_cell(&separator, &(child.offset() as u64).to_le_bytes());
+            match groups.last_mut() {
+                Some(group) if packed_len(&cells, used, &cell) <= budget => {
+                    used += cell.len() + SLOT_LEN;
+                    cells.push(cell);
+                    group.push((separator, child));
+                }
+                // The child starts a new node as its leftmost child, and its separator moves up a level.
+                _ => {
+                    groups.push(vec![(separator, child)]);
+                    cells.clear();
+                    used = 0;
+                }
+            }
+        }
+        // A node needs at least one separator, so a last node left with only its leftmost child takes the
+        // previous node's last child.
+        if groups.len() > 1 && groups.last().unwrap().len() == 1 {
+            let at = groups.len() - 2;
+            let moved = groups[at].pop().unwrap();
+            groups.last_mut().unwrap().insert(0, moved);
+        }
+
+        let mut parents = Vec::with_capacity(groups.len());
+        for mut group in groups {
+            let (separator, leftmost) = group.remove(0);
+            let page = self.allocator.allocate(self.store)?;
+            let mut node = Node::init(page.try_write()?, INNER);
+            node.set_link(LEFTMOST, Some(leftmost));
+            let cells: Vec<_> = group.iter().map(|(key, child)| encode_cell(key, &(child.offset() as u64).to_le_bytes())).collect();
+            node.fill(&cells);
+            drop(node);
+            parents.push((separator, page.id()));
+        }
+        Ok(parents)
+    }
+
+    /// The page to pass to `open` to find this tree again.
+    pub fn meta_page(&self) -> PageId {
+        self.meta
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
+        let node = self.descend_read(key)?;
+        Ok(node.search(key).ok().map(|i| node.tail(i).to_vec()))
+    }
+
+    /// Insert or replace the value for `key`, returning the value it replaced.
+    pub fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
+        if !entry_fits(key, value) {
+            return Err(BTreeError::EntryTooLarge)
+        }
+        let cell = encode_cell(key, value);
+        // Most inserts fit in their leaf, which only needs the leaf's write latch. The rest start over,
+        // write latching from the top for a split.
+        let mut leaf = self.descend_write(key)?;
+        if leaf.can_take(cell.len()) {
+            return Ok(leaf.put(key, &cell))
+        }
+        drop(leaf);
+        self.insert_splitting(key, cell)
+    }
+
+    /// Remove `key`, returning its value if it was present.
+    pub fn delete(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
+        // Most deletes leave their leaf well filled, which only needs the leaf's write latch. The rest start
+        // over, write latching from the top to rebalance.
+        let mut leaf = self.descend_write(key)?;
+        let Ok(i) = leaf.search(key) else { return Ok(None) };
+        if leaf.used() >= MIN_FILL + leaf.cell(i).len() + SLOT_LEN {
+            let old = leaf.tail(i).to_vec();
+            leaf.remove_cell(i);
+            return Ok(Some(old))
+        }
+        drop(leaf);
+        self.delete_rebalancing(key)
+    }
+
+    /// Iterate over `range` in ascending key order.
+    ///
+    /// The iterator latches one leaf at a time, only while it reads from it, and resumes from the last key
+    /// it returned, so it stays valid while the tree is modified, by this thread or others: every key
+    /// present for the whole scan is returned exactly once, and keys inserted or deleted during the scan
+    /// are returned if they sort after the cursor when it reaches them.
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> BTreeRange<'_, 'store, S> {
+        BTreeRange::new(self, range, false)
+    }
+
+    /// Iterate over `range` in descending key order, with the same guarantees as `range`.
+    pub fn rev_range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> BTreeRange<'_, 'store, S> {
+        BTreeRange::new(self, range, true)
+    }
+
+    /// Iterate over the whole tree in ascending key order.
+    pub fn iter(&self) -> BTreeRange<'_, 'store, S> {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// Return every page of the tree, the meta page included, to the allocator. Nothing else may be using
+    /// the tree.
+    pub fn free(self) -> Result<(), BTreeError> {
+        let mut pending = vec![self.root()?];
+        while let Some(id) = pending.pop() {
+            {
+                let node = Node::new(self.store.read_latch(&id)?);
+                node.check()?;
+                if !node.is_leaf() {
+                    pending.extend((0..=node.len()).map(|i| node.child_at(i)));
+                }
+            }
+            self.allocator.free(self.store, id)?;
+        }
+        self.allocator.free(self.store, self.meta)?;
+        Ok(())
+    }
+
+    fn root(&self) -> Result<PageId, BTreeError> {
+        let meta = self.store.read_latch(&self.meta)?;
+        Ok(PageId::new(read_u64(&*meta, ROOT) as usize))
+    }
+
+    fn set_root(&self, root: PageId) -> Result<(), BTreeError> {
+        write_u64(&mut *self.store.write_latch(&self.meta)?, ROOT, root.offset() as u64);
+        Ok(())
+    }
+
+    /// Pin the leaf that covers `key`. The pin keeps the leaf from being merged away once its latch is gone.
+    fn leaf_for(&self, key: &[u8]) -> Result<PinnedPage<'store, S>, BTreeError> {
+        let leaf = self.descend_read(key)?.page.into_inner();
+        Ok(self.store.pin_page(&leaf.id())?)
+    }
+
+    /// Walk from the root to the leaf that covers `key`, read latching each node before releasing its
+    /// parent, and return the leaf still latched.
+    fn descend_read(&self, key: &[u8]) -> Result<Node<ReadLatch<'store, S>>, BTreeError> {
+        let meta = self.store.read_latch(&self.meta)?;
+        let mut node = Node::new(self.store.read_latch(&PageId::new(read_u64(&*meta, ROOT) as usize))?);
+        drop(meta);
+        loop {
+            node.check()?;
+            if node.is_leaf() {
+                return Ok(node)
+            }
+            let child = self.store.read_latch(&node.child_at(node.child_index_for(key)))?;
+            node = Node::new(child);
+        }
+    }
+
+    /// Like `descend_read`, but return the leaf write latched. The leaf's parent stays read latched until
+    /// then, so the leaf cannot split or change parents before the write latch is taken.
+    fn descend_write(&self, key: &[u8]) -> Result<Node<WriteLatch<'store, S>>, BTreeError> {
+        let mut meta = Some(self.store.read_latch(&self.meta)?);
+        let mut parent = None;
+        let mut id = PageId::new(read_u64(&**meta.as_ref().unwrap(), ROOT) as usize);
+        loop {
+            let node = Node::new(self.store.read_latch(&id)?);
+            node.check()?;
+            if node.is_leaf() {
+                let latch = match node.page.into_inner().try_upgrade() {
+                    Ok(latch) => latch,
+                    Err(read) => {
+                        drop(read);
+                        self.store.write_latch(&id)?
+                    }
+                };
+                drop((parent, meta));
+                return Ok(Node::new(latch))
+            }
+            id = node.child_at(node.child_index_for(key));
+            parent = Some(node);
+            meta = None;
+        }
+    }
+
+    /// Pin the leaf holding the largest keys.
+    fn rightmost_leaf(&self) -> Result<PinnedPage<'store, S>, BTreeError> {
+        let meta = self.store.read_latch(&self.meta)?;
+        let mut node = Node::new(self.store.read_latch(&PageId::new(read_u64(&*meta, ROOT) as usize))?);
+        drop(meta);
+        loop {
+            node.check()?;
+            if node.is_leaf() {
+                return Ok(self.store.pin_page(&node.page.into_inner().id())?)
+            }
+            let child = self.store.read_latch(&node.child_at(node.len()))?;
+            node = Node::new(child);
+        }
+    }
+
+    /// Insert `cell` for `key` when its leaf may have to split. Nodes are write latched from the meta page
+    /// down; as soon as a node is sure to absorb a separator from below without splitting, the latches
+    /// above it are released, since no split can reach them.
+    fn insert_splitting(&self, key: &[u8], cell: Vec<u8>) -> Result<Option<Vec<u8>>, BTreeError> {
+        let mut meta = Some(self.store.write_latch(&self.meta)?);
+        let root = PageId::new(read_u64(&**meta.as_ref().unwrap(), ROOT) as usize);
+        let mut path: Vec<Node<WriteLatch<'store, S>>> = Vec::new();
+        let mut id = root;
+        loop {
+            let node = Node::new(self.store.write_latch(&id)?);
+            node.check()?;
+            if node.can_take(if node.is_leaf() { cell.len() } else { MAX_CELL_LEN }) {
+                path.clear();
+                meta = None;
+            }
+            let child = (!node.is_leaf()).then(|| node.child_at(node.child_index_for(key)));
+            path.push(node);
+            match child {
+                Some(child) => id = child,
+                None => break,
+            }
+        }
+
+        let mut node = path.pop().unwrap();
+        let (pos, old) = match node.search(key) {
+            Ok(i) => {
+                let old = node.tail(i).to_vec();
+                node.remove_cell(i);
+                (i, Some(old))
+            }
+            Err(i) => (i, None),
+        };
+        if node.try_insert_cell(pos, &cell) {
+            return Ok(old)
+        }
+        let mut cells = node.cells();
+        cells.insert(pos, cell);
+        let (mut separator, mut right) = self.split_leaf(&mut node, id, cells)?;
+        drop(node);
+
+        // Add the separator to the latched parents, splitting upward as needed.
+        while let Some(mut node) = path.pop() {
+            let pos = node.search(&separator).map_or_else(|i| i, |i| i + 1);
+            let cell = encode_cell(&separator, &(right.offset() as u64).to_le_bytes());
+            if node.try_insert_cell(pos, &cell) {
+                return Ok(old)
+            }
+            let mut cells = node.cells();
+            cells.insert(pos, cell);
+            (separator, right) = self.split_inner(&mut node, cells)?;
+        }
+
+        // The root split, so the meta page is still latched.
+        let mut meta = meta.expect("the meta page stays latched while the root may split");
+        let new_root = self.allocator.allocate(self.store)?;
+        {
+            let mut node = Node::init(new_root.try_write()?, INNER);
+            node.set_link(LEFTMOST, Some(root));
+            node.fill(&[encode_cell(&separator, &(right.offset() as u64).to_le_bytes())]);
+        }
+        write_u64(&mut *meta, ROOT, new_root.id().offset() as u64);
+        Ok(old)
+    }
+
+    /// Delete `key` when its leaf may underflow. Nodes are write latched from the meta page down, and as soon
+    /// as a node is sure to stay well filled after losing a cell, the latches above it are released, since
+    /// no merge can reach them. The root is sure to if it keeps at least one separator.
+    fn delete_rebalancing(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
+        let mut meta = Some(self.store.write_latch(&self.meta)?);
+        let mut id = PageId::new(read_u64(&**meta.as_ref().unwrap(), ROOT) as usize);
+        // Each latched inner node with the index of the child the descent took.
+        let mut path: Vec<(Node<WriteLatch<'store, S>>, usize)> = Vec::new();
+        let mut node = loop {
+            let node = Node::new(self.store.write_latch(&id)?);
+            node.check()?;
+            let safe = match meta {
+                Some(_) if path.is_empty() => node.is_leaf() || node.len() >= 2,
+                _ => node.used() >= MIN_FILL + MAX_CELL_LEN + SLOT_LEN,
+            };
+            if safe {
+                path.clear();
+                meta = None;
+            }
+            if node.is_leaf() {
+                break node
+            }
+            let index = node.child_index_for(key);
+            id = node.child_at(index);
+            path.push((node, index));
+        };
+
+        let Ok(i) = node.search(key) else { return Ok(None) };
+        let old = node.tail(i).to_vec();
+        node.remove_cell(i);
+        let mut freed = Vec::new();
+        while node.underfull() {
+            let Some((mut parent, index)) = path.pop() else { break };
+            self.rebalance(&mut parent, index, node, &mut freed)?;
+            node = parent;
+        }
+        // Only a root whose last separator went in a merge can be left with no separators.
+        if let Some(meta) = &mut meta {
+            if path.is_empty() && !node.is_leaf() && node.len() == 0 {
+                let child = node.link(LEFTMOST).expect("inner nodes have a leftmost child");
+                write_u64(&mut **meta, ROOT, child.offset() as u64);
+                freed.push(id_of(&node));
+            }
+        }
+        drop((node, path, meta));
+        for page in freed {
+            self.allocator.free(self.store, page)?;
+        }
+        Ok(Some(old))
+    }
+
+    /// Rebalance `child`, the underfull child `index` of `parent`, with a sibling beside it: merge the two
+    /// if they fit in one node, and otherwise move cells between them until both are about equally full.
+    /// Pages merged away are added to `freed`, to be freed once their latches are released. Nothing
+    /// changes if the parent has no room for a longer separator, or if cells would have to move left out
+    /// of a leaf someone else has pinned: a forward scan parked on it would miss them.
+    fn rebalance(
+        &self,
+        parent: &mut Node<WriteLatch<'store, S>>,
+        index: usize,
+        child: Node<WriteLatch<'store, S>>,
+        freed: &mut Vec<PageId>,
+    ) -> Result<(), BTreeError> {
+        // The pair is latched left to right, the order scans and splits latch leaves in.
+        let sep = index.saturating_sub(1);
+        let (mut left, mut right) = if index == 0 {
+            (child, Node::new(self.store.write_latch(&parent.child_at(1))?))
+        } else {
+            drop(child);
+            let left = Node::new(self.store.write_latch(&parent.child_at(index - 1))?);
+            (left, Node::new(self.store.write_latch(&parent.child_at(index))?))
+        };
+        let (left_id, right_id) = (id_of(&left), id_of(&right));
+        let leaf = left.is_leaf();
+        let mut cells = left.cells();
+        if !leaf {
+            let leftmost = right.link(LEFTMOST).expect("inner nodes have a leftmost child");
+            cells.push(encode_cell(&parent.key(sep), &(leftmost.offset() as u64).to_le_bytes()));
+        }
+        cells.extend(right.cells());
+        let right_movable = !leaf || self.store.pin_count(&right_id) == 1;
+
+        if fits(&cells) {
+            if !right_movable {
+                return Ok(())
+            }
+            left.fill(&cells);
+            if leaf {
+                let next = right.link(NEXT);
+                left.set_link(NEXT, next);
+                if let Some(next) = next {
+                    Node::new(self.store.write_latch(&next)?).set_link(PREV, Some(left_id));
+                }
+            }
+            parent.remove_cell(sep);
+            freed.push(right_id);
+            return Ok(())
+        }
+        if index == 0 && !right_movable {
+            return Ok(())
+        }
+        let mid = split_point(&cells);
+        let (separator, right_cells) = if leaf {
+            (shortest_separator(cell_key(&cells[mid - 1]), cell_key(&cells[mid])).to_vec(), &cells[mid..])
+        } else {
+            (cell_key(&cells[mid]).to_vec(), &cells[mid + 1..])
+        };
+        let old = encode_cell(&parent.key(sep), parent.tail(sep));
+        parent.remove_cell(sep);
+        if !parent.try_insert_cell(sep, &encode_cell(&separator, &(right_id.offset() as u64).to_le_bytes())) {
+            assert!(parent.try_insert_cell(sep, &old), "the old separator fits where it was");
+            return Ok(())
+        }
+        left.fill(&cells[..mid]);
+        right.fill(right_cells);
+        if !leaf {
+            right.set_link(LEFTMOST, Some(cell_child(&cells[mid])));
+        }
+        Ok(())
+    }
+
+    /// Walk the whole tree checking its invariants, returning every violation found; a healthy tree has
+    /// none. Checks that keys ascend within each node and lie within the range their ancestors'
+    /// separators give them, that every leaf is at the same depth and the leaf chain links the leaves in
+    /// key order, that no page is reached twice, and that no page of the tree is on the free list.
+    /// Meant for tests and offline checks: it latches one node at a time, so concurrent writers can make
+    /// it report violations that are not there.
+    pub fn verify(&self) -> Result<Vec<Violation>, BTreeError> {
+        let mut check = Verify { violations: Vec::new(), seen: HashSet::new(), leaves: Vec::new(), leaf_depth: None };
+        self.verify_node(&mut check, self.root()?, None, None, 0)?;
+
+        for (i, (page, prev, next)) in check.leaves.iter().enumerate() {
+            let expected_prev = i.checked_sub(1).map(|j| check.leaves[j].0);
+            let expected_next = check.leaves.get(i + 1).map(|l| l.0);
+            if *prev != expected_prev {
+                check.violations.push(Violation::BadPrevLink { page: *page, found: *prev, expected: expected_prev });
+            }
+            if *next != expected_next {
+                check.violations.push(Violation::BadNextLink { page: *page, found: *next, expected: expected_next });
+            }
+        }
+        for page in self.allocator.free_pages(self.store)? {
+            if check.seen.contains(&page) {
+                check.violations.push(Violation::FreePageInTree { page });
+            }
+        }
+        Ok(check.violations)
+    }
+
+    /// Check the subtree at `id`, whose keys must be at least `low` and less than `high`.
+    fn verify_node(&self, check: &mut Verify, id: PageId, low: Option<&[u8]>, high: Option<&[u8]>, depth: usize) -> Result<(), BTreeError> {
+        if !check.seen.insert(id) {
+            check.violations.push(Violation::ReachedTwice { page: id });
+            return Ok(())
+        }
+        let node = Node::new(self.store.read_latch(&id)?);
+        if !node.page.is_well_formed() || node.page.reserved().len() < NODE_HEADER_LEN {
+            check.violations.push(Violation::NotANode { page: id });
+            return Ok(())
+        }
+        let prefix_len = read_u16(node.page.reserved(), PREFIX_LEN) as usize;
+        if node.check().is_err() || (node.version() != 0 && node.page.reserved().len() < NODE_HEADER_LEN + prefix_len) {
+            check.violations.push(Violation::NotANode { page: id });
+            return Ok(())
+        }
+        if let Some(index) = node.malformed_cell() {
+            check.violations.push(Violation::MalformedCell { page: id, index });
+            return Ok(())
+        }
+        let keys: Vec<_> = (0..node.len()).map(|i| node.key(i)).collect();
+        for (index, key) in keys.iter().enumerate() {
+            if index > 0 && keys[index - 1] >= *key {
+                check.violations.push(Violation::OutOfOrder { page: id, index });
+            }
+            if low.is_some_and(|low| key.as_slice() < low) || high.is_some_and(|high| key.as_slice() >= high) {
+                check.violations.push(Violation::OutsideParentRange { page: id, index });
+            }
+        }
+        if node.is_leaf() {
+            match check.leaf_depth {
+                Some(expected) if expected != depth => {
+                    check.violations.push(Violation::UnevenDepth { page: id, depth, expected });
+                }
+                _ => check.leaf_depth = Some(depth),
+            }
+            check.leaves.push((id, node.link(PREV), node.link(NEXT)));
+            return Ok(())
+        }
+        if node.link(LEFTMOST).is_none() {
+            check.violations.push(Violation::NoLeftmostChild { page: id });
+            return Ok(())
+        }
+        let children: Vec<_> = (0..=node.len()).map(|i| node.child_at(i)).collect();
+        drop(node);
+        for (i, child) in children.into_iter().enumerate() {
+            let low = if i == 0 { low } else { Some(keys[i - 1].as_slice()) };
+            let high = keys.get(i).map(|k| k.as_slice()).or(high);
+            self.verify_node(check, child, low, high, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn split_leaf<T: DerefMut<Target = Data>>(&self, node: &mut Node<T>, id: PageId, cells: Vec<Vec<u8>>) -> Result<(Vec<u8>, PageId), BTreeError> {
+        let mid = split_point(&cells);
+        let right_page = self.allocator.allocate(self.store)?;
+        let right_id = right_page.id();
+        let old_next = node.link(NEXT);
+        {
+            let mut right = Node::init(right_page.try_write()?, LEAF);
+            right.fill(&cells[mid..]);
+            right.set_link(NEXT, old_next);
+            right.set_link(PREV, Some(id));
+        }
+        node.fill(&cells[..mid]);
+        node.set_link(NEXT, Some(right_id));
+        if let Some(next) = old_next {
+            // A leaf's right neighbour is only ever latched while the leaf is, never the other way round,
+            // so this cannot deadlock.
+            Node::new(self.store.write_latch(&next)?).set_link(PREV, Some(right_id));
+        }
+        Ok((shortest_separator(cell_key(&cells[mid - 1]), cell_key(&cells[mid])).to_vec(), right_id))
+    }
+
+    fn split_inner<T: DerefMut<Target = Data>>(&self, node: &mut Node<T>, cells: Vec<Vec<u8>>) -> Result<(Vec<u8>, PageId), BTreeError> {
+        let mid = split_point(&cells);
+        let separator = cell_key(&cells[mid]).to_vec();
+        let right_page = self.allocator.allocate(self.store)?;
+        {
+            let mut right = Node::init(right_page.try_write()?, INNER);
+            right.set_link(LEFTMOST, Some(cell_child(&cells[mid])));
+            right.fill(&cells[mid + 1..]);
+        }
+        node.fill(&cells[..mid]);
+        Ok((separator, right_page.id()))
+    }
+}
+
+/// State of a `BTree::verify` walk.
+struct Verify {
+    violations: Vec<Violation>,
+    seen: HashSet<PageId>,
+    /// Each leaf in key order with its previous and next links.
+    leaves: Vec<(PageId, Option<PageId>, Option<PageId>)>,
+    leaf_depth: Option<usize>,
+}
+
+fn id_of<S: Storage>(node: &Node<WriteLatch<'_, S>>) -> PageId {
+    node.page.get_ref().id()
+}
+
+pub struct BTreeRange<'tree, 'store, S: Storage> {
+    tree: &'tree BTree<'store, S>,
+    /// The leaf the cursor is in, pinned so that it cannot be merged away between steps.
+    leaf: Option<PinnedPage<'store, S>>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    reverse: bool,
+    last: Option<Vec<u8>>,
+    done: bool,
+}
+enum Step<'store, S: Storage> {
+    Yield(Vec<u8>, Vec<u8>),
+    Move(Option<PinnedPage<'store, S>>),
+    Finished,
+}
+impl<'tree, 'store, S: Storage> BTreeRange<'tree, 'store, S> {
+    fn new<K: AsRef<[u8]>, R: RangeBounds<K>>(tree: &'tree BTree<'store, S>, range: R, reverse: bool) -> Self {
+        let own = |b: Bound<&K>| match b {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        BTreeRange {
+            tree,
+            leaf: None,
+            start: own(range.start_bound()),
+            end: own(range.end_bound()),
+            reverse,
+            last: None,
+            done: false,
+        }
+    }
+
+    fn first_leaf(&self) -> Result<PinnedPage<'store, S>, BTreeError> {
+        let bound = if self.reverse { &self.end } else { &self.start };
+        match bound {
+            Bound::Included(k) | Bound::Excluded(k) => self.tree.leaf_for(k),
+            Bound::Unbounded if self.reverse => self.tree.rightmost_leaf(),
+            Bound::Unbounded => self.tree.leaf_for(&[]),
+        }
+    }
+
+    /// Pin the leaf `page`, if there is one, for the cursor to move to.
+    fn pin(&self, page: Option<PageId>) -> Result<Option<PinnedPage<'store, S>>, BTreeError> {
+        Ok(page.map(|page| self.tree.store.pin_page(&page)).transpose()?)
+    }
+
+    fn step(&mut self) -> Result<Step<'store, S>, BTreeError> {
+        let id = match &self.leaf {
+            Some(leaf) => leaf.id(),
+            None => self.leaf.insert(self.first_leaf()?).id(),
+        };
+        // No latch is held between steps, so the leaf may have split since the cursor last looked: keys
+        // after the cursor then sit in new leaves to its right. Forward scans reach them through the next
+        // link as usual; reverse scans check for them before stepping left.
+        let node = Node::new(self.tree.store.read_latch(&id)?);
+        node.check()?;
+
+        if !self.reverse {
+            let lower = match &self.last {
+                Some(k) => Bound::Excluded(k.as_slice()),
+                None => self.start.as_ref().map(|k| k.as_slice()),
+            };
+            let index = match lower {
+                Bound::Unbounded => 0,
+                Bound::Included(k) => node.search(k).unwrap_or_else(|i| i),
+                Bound::Excluded(k) => node.search(k).map_or_else(|i| i, |i| i + 1),
+            };
+            if index == node.len() {
+                return Ok(Step::Move(self.pin(node.link(NEXT))?))
+            }
+            let key = node.key(index);
+            let in_range = match &self.end {
+                Bound::Unbounded => true,
+                Bound::Included(end) => key <= *end,
+                Bound::Excluded(end) => key < *end,
+            };
+            if !in_range {
+                return Ok(Step::Finished)
+            }
+            let value = node.tail(index).to_vec();
+            Ok(Step::Yield(key, value))
+        } else {
+            let upper = match &self.last {
+                Some(k) => Bound::Excluded(k.as_slice()),
+                None => self.end.as_ref().map(|k| k.as_slice()),
+            };
+            if let Some(next) = node.link(NEXT) {
+                // Leaves are latched left to right, as splits do.
+                let right = Node::new(self.tree.store.read_latch(&next)?);
+                let below_upper = |key: Vec<u8>| match upper {
+                    Bound::Unbounded => true,
+                    Bound::Included(k) => key.as_slice() <= k,
+                    Bound::Excluded(k) => key.as_slice() < k,
+                };
+                if right.len() > 0 && below_upper(right.key(0)) {
+                    return Ok(Step::Move(self.pin(Some(next))?))
+                }
+            }
+            let below = match upper {
+                Bound::Unbounded => node.len(),
+                Bound::Included(k) => node.search(k).map_or_else(|i| i, |i| i + 1),
+                Bound::Excluded(k) => node.search(k).unwrap_or_else(|i| i),
+            };
+            if below == 0 {
+                return Ok(Step::Move(self.pin(node.link(PREV))?))
+            }
+            let key = node.key(below - 1);
+            let in_range = match &self.start {
+                Bound::Unbounded => true,
+                Bound::Included(start) => key >= *start,
+                Bound::Excluded(start) => key > *start,
+            };
+            if !in_range {
+                return Ok(Step::Finished)
+            }
+            let value = node.tail(below - 1).to_vec();
+            Ok(Step::Yield(key, value))
+        }
+    }
+}
+impl<S: Storage> Iterator for BTreeRange<'_, '_, S> {
+    type Item = Result<(Vec<u8>, Vec<u8>), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.step() {
+                Ok(Step::Yield(key, value)) => {
+                    self.last = Some(key.clone());
+                    return Some(Ok((key, value)))
+                }
+                Ok(Step::Move(Some(next))) => self.leaf = Some(next),
+                Ok(Step::Move(None)) | Ok(Step::Finished) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e))
+                }
+            }
+        }
+        self.leaf = None;
+        None
+    }
+}
+
+/// `[key_len varint][key][tail]`, where the tail is a leaf's value or an inner node's child. Nodes store
+/// cells in this form with the node's prefix cut off the key; everywhere else cells hold whole keys.
+fn encode_cell(key: &[u8], tail: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(varint::MAX_LEN + key.len() + tail.len());
+    varint::write_prefixed(&mut cell, key);
+    cell.extend_from_slice(tail);
+    cell
+}
+
+fn cell_key(cell: &[u8]) -> &[u8] {
+    varint::read_prefixed(cell).expect("cells are written by encode_cell").0
+}
+
+fn cell_tail(cell: &[u8]) -> &[u8] {
+    let (_, len) = varint::read_prefixed(cell).expect("cells are written by encode_cell");
+    &cell[len..]
+}
+
+fn cell_child(cell: &[u8]) -> PageId {
+    PageId::new(read_u64(cell_tail(cell), 0) as usize)
+}
+
+/// The shortest prefix of `right` that sorts after `left`, which must sort before `right`.
+fn shortest_separator<'a>(left: &[u8], right: &'a [u8]) -> &'a [u8] {
+    let shared = left.iter().zip(right).take_while(|(l, r)| l == r).count();
+    &right[..shared + 1]
+}
+
+/// The prefix shared by the keys of `cells`, which must be in key order.
+fn common_prefix(cells: &[Vec<u8>]) -> &[u8] {
+    let (Some(first), Some(last)) = (cells.first(), cells.last()) else { return &[] };
+    let (first, last) = (cell_key(first), cell_key(last));
+    &first[..first.iter().zip(last).take_while(|(a, b)| a == b).count()]
+}
+
+/// An upper bound on the node space taken by `cells`, whose cells and slots take `used` bytes before
+/// compression, once `cell` is added after them. The varint lengths of compressed keys are not shortened,
+/// so the bound may be a few bytes over.
+fn packed_len(cells: &[Vec<u8>], used: usize, cell: &[u8]) -> usize {
+    let first = cells.first().map_or(cell_key(cell), |c| cell_key(c));
+    let prefix = first.iter().zip(cell_key(cell)).take_while(|(a, b)| a == b).count();
+    prefix + used + cell.len() + SLOT_LEN - (cells.len() + 1) * prefix
+}
+
+/// Whether a node can hold `cells` once they are prefix compressed.
+fn fits(cells: &[Vec<u8>]) -> bool {
+    let prefix = common_prefix(cells).len();
+    let compressed: usize = cells.iter()
+        .map(|c| c.len() - prefix - varint::encoded_len(cell_key(c).len() as u64) + varint::encoded_len((cell_key(c).len() - prefix) as u64))
+        .map(|len| len + SLOT_LEN)
+        .sum();
+    HEADER_LEN + NODE_HEADER_LEN + prefix + compressed <= PAGE_SIZE
+}
+
+/// Index of the first cell of the right half when splitting `cells` roughly in half by size. Prefix
+/// compression can pack a node into far less than its cells' whole size, and a half whose keys share a
+/// shorter prefix, as when a run of keys meets the one being inserted after it, may then not fit; the split
+/// moves to the nearest point where both halves do.
+fn split_point(cells: &[Vec<u8>]) -> usize {
+    let total: usize = cells.iter().map(|c| c.len() + SLOT_LEN).sum();
+    let mut acc = 0;
+    let mut mid = cells.len() - 1;
+    for (i, cell) in cells.iter().enumerate() {
+        acc += cell.len() + SLOT_LEN;
+        if acc * 2 >= total {
+            mid = (i + 1).clamp(1, cells.len() - 1);
+            break
+        }
+    }
+    if fits(&cells[..mid]) && fits(&cells[mid..]) {
+        return mid
+    }
+    (1..cells.len()).filter(|&i| fits(&cells[..i]) && fits(&cells[i..])).min_by_key(|i| i.abs_diff(mid)).unwrap_or(mid)
+}
+
+/// Typed view of a B+tree node over a slotted page.
+struct Node<T> {
+    page: SlottedPage<T>
+}
+impl<T: Deref<Target = Data>> Node<T> {
+    fn new(data: T) -> Node<T> {
+        Node { page: SlottedPage::new(data) }
+    }
+
+    fn check(&self) -> Result<(), BTreeError> {
+        if self.page.reserved().len() < NODE_HEADER_LEN || read_u32(self.page.reserved(), 0) != NODE_MAGIC {
+            return Err(BTreeError::Page(PageError::WrongPageType))
+        }
+        Ok(())
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.page.reserved()[KIND] == LEAF
+    }
+
+    fn len(&self) -> usize {
+        self.page.slot_count() as usize
+    }
+
+    fn cell(&self, i: usize) -> &[u8] {
+        self.page.get(i as SlotId).expect("B+tree nodes have no empty slots")
+    }
+
+    fn version(&self) -> u8 {
+        self.page.reserved()[VERSION]
+    }
+
+    fn prefix(&self) -> &[u8] {
+        if self.version() == 0 {
+            return &[]
+        }
+        let header = self.page.reserved();
+        &header[NODE_HEADER_LEN..NODE_HEADER_LEN + read_u16(header, PREFIX_LEN) as usize]
+    }
+
+    /// The stored key suffix and the tail of cell `i`.
+    fn parts(&self, i: usize) -> (&[u8], &[u8]) {
+        let cell = self.cell(i);
+        if self.version() == 0 {
+            let len = read_u16(cell, 0) as usize;
+            return (&cell[2..2 + len], &cell[2 + len..])
+        }
+        let (suffix, len) = varint::read_prefixed(cell).expect("cells are written by encode_cell");
+        (suffix, &cell[len..])
+    }
+
+    /// The index of the first cell that `parts` or `child_at` cannot parse, if any.
+    fn malformed_cell(&self) -> Option<usize> {
+        (0..self.len()).find(|&i| {
+            let Some(cell) = self.page.get(i as SlotId) else { return true };
+            let tail = if self.version() == 0 {
+                let len = cell.get(..2).map(|len| read_u16(len, 0) as usize);
+                len.and_then(|len| cell.get(2 + len..))
+            } else {
+                varint::read_prefixed(cell).map(|(_, len)| &cell[len..])
+            };
+            tail.is_none_or(|tail| !self.is_leaf() && tail.len() < 8)
+        })
+    }
+
+    fn key(&self, i: usize) -> Vec<u8> {
+        [self.prefix(), self.parts(i).0].concat()
+    }
+
+    fn tail(&self, i: usize) -> &[u8] {
+        self.parts(i).1
+    }
+
+    fn link(&self, at: usize) -> Option<PageId> {
+        match read_u64(self.page.reserved(), at) {
+            NO_PAGE => None,
+            page => Some(PageId::new(page as usize)),
+        }
+    }
+
+    /// Every cell with its whole key.
+    fn cells(&self) -> Vec<Vec<u8>> {
+        (0..self.len()).map(|i| encode_cell(&self.key(i), self.tail(i))).collect()
+    }
+
+    fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let prefix = self.prefix();
+        let Some(rest) = key.strip_prefix(prefix) else {
+            return Err(if key < prefix { 0 } else { self.len() })
+        };
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.parts(mid).0.cmp(rest) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(lo)
+    }
+
+    /// Which child of an inner node covers `key`: 0 is the leftmost child, `i + 1` the child of cell `i`.
+    fn child_index_for(&self, key: &[u8]) -> usize {
+        match self.search(key) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+
+    /// Bytes taken by the node's cells, their slots and its key prefix.
+    fn used(&self) -> usize {
+        NODE_CAPACITY.saturating_sub(self.page.free_space() + SLOT_LEN)
+    }
+
+    fn underfull(&self) -> bool {
+        self.used() < MIN_FILL
+    }
+
+    /// Whether a cell of up to `len` bytes is sure to fit. A key outside the node's prefix shortens the
+    /// prefix, which can lengthen every stored key by the prefix and its length varint by a byte.
+    fn can_take(&self, len: usize) -> bool {
+        self.page.free_space() >= len + (self.prefix().len() + 1) * (self.len() + 1)
+    }
+
+    fn child_at(&self, index: usize) -> PageId {
+        match index {
+            0 => self.link(LEFTMOST).expect("inner nodes have a leftmost child"),
+            i => PageId::new(read_u64(self.tail(i - 1), 0) as usize),
+        }
+    }
+}
+impl<T: DerefMut<Target = Data>> Node<T> {
+    fn init(data: T, kind: u8) -> Node<T> {
+        let mut node = Node { page: SlottedPage::init(data, NODE_HEADER_LEN) };
+        let header = node.page.reserved_mut();
+        write_u32(header, 0, NODE_MAGIC);
+        header[KIND] = kind;
+        header[VERSION] = FORMAT_VERSION;
+        for at in [NEXT, PREV, LEFTMOST] {
+            write_u64(header, at, NO_PAGE);
+        }
+        node
+    }
+
+    fn set_link(&mut self, at: usize, page: Option<PageId>) {
+        let value = page.map(|p| p.offset() as u64).unwrap_or(NO_PAGE);
+        write_u64(self.page.reserved_mut(), at, value);
+    }
+
+    /// Insert `cell`, which holds a whole key, at `pos`, returning false if the node has no room for it.
+    fn try_insert_cell(&mut self, pos: usize, cell: &[u8]) -> bool {
+        let key = cell_key(cell);
+        if self.version() == FORMAT_VERSION && key.starts_with(self.prefix()) {
+            let compressed = encode_cell(&key[self.prefix().len()..], cell_tail(cell));
+            match self.page.insert_at(pos as SlotId, &compressed) {
+                Ok(()) => return true,
+                Err(SlottedPageError::PageFull) => {}
+                Err(e) => panic!("B+tree cell insert failed: {e:?}"),
+            }
+        }
+        // The key does not share the node's prefix, the node predates prefixes, or it is full and a longer
+        // prefix might make room: rebuild the node.
+        let mut cells = self.cells();
+        cells.insert(pos, cell.to_vec());
+        if !fits(&cells) {
+            return false
+        }
+        self.fill(&cells);
+        true
+    }
+
+    /// Insert or replace the value for `key` with `cell`, which must fit: see `can_take`.
+    fn put(&mut self, key: &[u8], cell: &[u8]) -> Option<Vec<u8>> {
+        let (pos, old) = match self.search(key) {
+            Ok(i) => {
+                let old = self.tail(i).to_vec();
+                self.remove_cell(i);
+                (i, Some(old))
+            }
+            Err(i) => (i, None),
+        };
+        assert!(self.try_insert_cell(pos, cell), "the node had room for the cell");
+        old
+    }
+
+    fn remove_cell(&mut self, pos: usize) {
+        self.page.remove_at(pos as SlotId).expect("B+tree cell position is in range");
+    }
+
+    /// Replace every cell with `cells`, which hold whole keys and must be in key order, keeping the header
+    /// links. The node's prefix becomes the longest one the keys share.
+    fn fill(&mut self, cells: &[Vec<u8>]) {
+        let prefix = common_prefix(cells).to_vec();
+        self.page.reset(NODE_HEADER_LEN + prefix.len());
+        let header = self.page.reserved_mut();
+        header[VERSION] = FORMAT_VERSION;
+        write_u16(header, PREFIX_LEN, prefix.len() as u16);
+        header[NODE_HEADER_LEN..].copy_from_slice(&prefix);
+        for (i, cell) in cells.iter().enumerate() {
+            let compressed = encode_cell(&cell_key(cell)[prefix.len()..], cell_tail(cell));
+            self.page.insert_at(i as SlotId, &compressed).expect("cells fit in a node");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{BTree, BTreeError, Node, Violation, KIND, LEAF, NEXT, NODE_HEADER_LEN, NODE_MAGIC, NO_PAGE, PREV};
+    use crate::bytes::{write_u32, write_u64};
+    use crate::slotted_page::SlottedPage;
+
+    /// Keys 0..n in a scrambled but deterministic order.
+    fn scrambled(n: u64) -> impl Iterator<Item = u64> {
+        (0..n).map(move |i| (i * 7919) % n)
+    }
+
+    fn key(i: u64) -> Vec<u8> {
+        format!("key-{i:08}").into_bytes()
+    }
+
+    fn value(i: u64) -> Vec<u8> {
+        format!("value-{i}-").repeat(3).into_bytes()
+    }
+
+    #[test]
+    fn test_insert_and_get_many() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+
+        for i in scrambled(5000) {
+            assert_eq!(tree.insert(&key(i), &value(i))?, None);
+        }
+        for i in 0..5000 {
+            assert_eq!(tree.get(&key(i))?, Some(value(i)));
+        }
+        assert_eq!(tree.get(b"key-")?, None);
+        assert_eq!(tree.get(b"zzz")?, None);
+
+        let tree = BTree::open(&store, allocator, tree.meta_page())?;
+        assert_eq!(tree.get(&key(4321))?, Some(value(4321)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_and_delete() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+
+        for i in scrambled(1000) {
+            tree.insert(&key(i), &value(i))?;
+        }
+        assert_eq!(tree.insert(&key(10), b"new")?, Some(value(10)));
+        assert_eq!(tree.get(&key(10))?, Some(b"new".to_vec()));
+
+        for i in (0..1000).step_by(2) {
+            assert!(tree.delete(&key(i))?.is_some());
+        }
+        assert_eq!(tree.delete(&key(0))?, None);
+        for i in 0..1000 {
+            assert_eq!(tree.get(&key(i))?.is_some(), i % 2 == 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_rebalances_and_collapses_root() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        for i in scrambled(5000) {
+            tree.insert(&key(i), &value(i))?;
+        }
+        let pages = allocator.allocate(&store)?.id();
+        allocator.free(&store, pages)?;
+
+        // Scans in both directions stay on course while the leaves under them merge.
+        let (mut forward, mut reverse) = (tree.iter(), tree.rev_range::<Vec<u8>, _>(..));
+        let (mut seen, mut seen_reverse) = (Vec::new(), Vec::new());
+        let mut doomed = scrambled(5000).filter(|i| i % 250 != 0);
+        loop {
+            let (a, b) = (forward.next().transpose()?, reverse.next().transpose()?);
+            if a.is_none() && b.is_none() {
+                break
+            }
+            seen.extend(a.map(|(k, _)| k));
+            seen_reverse.extend(b.map(|(k, _)| k));
+            for i in doomed.by_ref().take(40) {
+                assert_eq!(tree.delete(&key(i))?, Some(value(i)));
+            }
+        }
+        let survivors: Vec<_> = (0..5000).step_by(250).map(key).collect();
+        assert!(survivors.iter().all(|k| seen.contains(k) && seen_reverse.contains(k)));
+        assert!(seen.windows(2).all(|w| w[0] < w[1]) && seen_reverse.windows(2).all(|w| w[0] > w[1]));
+
+        assert_eq!(tree.verify()?, vec![]);
+        assert_eq!(tree.iter().map(|e| e.map(|(k, _)| k)).collect::<Result<Vec<_>, _>>()?, survivors);
+
+        // With no scans holding leaves back, every underfull leaf merges. Twenty entries fit in one leaf,
+        // which is the root again, and the other pages were freed.
+        for i in scrambled(5000).filter(|i| i % 250 != 0) {
+            tree.insert(&key(i), &value(i))?;
+        }
+        for i in scrambled(5000).filter(|i| i % 250 != 0) {
+            tree.delete(&key(i))?;
+        }
+        assert_eq!(tree.verify()?, vec![]);
+        assert!(Node::new(store.read_latch(&tree.root()?)?).is_leaf());
+        assert!(allocator.free_pages(&store)?.len() > pages.offset() * 3 / 4);
+
+        for i in (0..5000).step_by(250) {
+            tree.delete(&key(i))?;
+        }
+        assert_eq!((tree.iter().count(), tree.verify()?), (0, vec![]));
+        tree.insert(&key(1), &value(1))?;
+        assert_eq!(tree.get(&key(1))?, Some(value(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_bounds_and_direction() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        for i in scrambled(3000) {
+            tree.insert(&key(i), &value(i))?;
+        }
+
+        let keys = |r: super::BTreeRange<'_, '_, TestStorage>| -> Result<Vec<Vec<u8>>, BTreeError> {
+            r.map(|e| e.map(|(k, _)| k)).collect()
+        };
+        assert_eq!(keys(tree.range(key(100)..key(1100)))?, (100..1100).map(key).collect::<Vec<_>>());
+        assert_eq!(keys(tree.range(key(2990)..))?, (2990..3000).map(key).collect::<Vec<_>>());
+        assert_eq!(keys(tree.rev_range(key(5)..=key(900)))?, (5..=900).rev().map(key).collect::<Vec<_>>());
+        assert_eq!(keys(tree.rev_range::<Vec<u8>, _>(..))?, (0..3000).rev().map(key).collect::<Vec<_>>());
+        assert_eq!(keys(tree.iter())?.len(), 3000);
+        assert!(keys(tree.range(b"a".to_vec()..b"b".to_vec()))?.is_empty());
+
+        let (k, v) = tree.range((std::ops::Bound::Excluded(key(41)), std::ops::Bound::Unbounded)).next().unwrap()?;
+        assert_eq!((k, v), (key(42), value(42)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_survives_splits_during_scan() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        for i in 0..500 {
+            tree.insert(&key(i * 10), &value(i))?;
+        }
+
+        let mut seen = Vec::new();
+        for entry in tree.range::<Vec<u8>, _>(..) {
+            let (k, _) = entry?;
+            seen.push(k);
+            if seen.len() % 50 == 0 {
+                // Force splits both behind and ahead of the cursor.
+                for j in 0..200 {
+                    tree.insert(&key(j * 10 + 1 + seen.len() as u64 % 9), &value(j))?;
+                }
+            }
+        }
+        let originals: Vec<_> = (0..500).map(|i| key(i * 10)).collect();
+        for k in &originals {
+            assert_eq!(seen.iter().filter(|s| *s == k).count(), 1);
+        }
+        assert!(seen.windows(2).all(|w| w[0] < w[1]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_scans() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        for i in 0..1000 {
+            tree.insert(&key(i * 4), &value(i))?;
+        }
+
+        std::thread::scope(|s| -> Result<(), BTreeError> {
+            let writers: Vec<_> = (1..4u64).map(|t| {
+                let tree = &tree;
+                s.spawn(move || -> Result<(), BTreeError> {
+                    for i in scrambled(1000) {
+                        tree.insert(&key(i * 4 + t), &value(i))?;
+                        if i % 3 == 0 {
+                            assert!(tree.delete(&key(i * 4 + t))?.is_some());
+                        }
+                    }
+                    // Deleting the rest empties leaves and merges them while the scans run.
+                    for i in scrambled(1000).filter(|i| i % 3 != 0) {
+                        assert!(tree.delete(&key(i * 4 + t))?.is_some());
+                    }
+                    Ok(())
+                })
+            }).collect();
+            let scanners: Vec<_> = [false, true].into_iter().map(|reverse| {
+                let tree = &tree;
+                s.spawn(move || -> Result<(), BTreeError> {
+                    for _ in 0..5 {
+                        let range = if reverse { tree.rev_range::<Vec<u8>, _>(..) } else { tree.iter() };
+                        let keys: Vec<_> = range.map(|e| e.map(|(k, _)| k)).collect::<Result<_, _>>()?;
+                        assert!(keys.windows(2).all(|w| if reverse { w[0] > w[1] } else { w[0] < w[1] }));
+                        // Keys that were there all along are each seen exactly once.
+                        let mut originals: Vec<_> = keys.into_iter()
+                            .filter(|k| std::str::from_utf8(&k[4..]).unwrap().parse::<u64>().unwrap() % 4 == 0)
+                            .collect();
+                        if reverse {
+                            originals.reverse();
+                        }
+                        assert_eq!(originals, (0..1000).map(|i| key(i * 4)).collect::<Vec<_>>());
+                        for i in scrambled(1000).step_by(37) {
+                            assert_eq!(tree.get(&key(i * 4))?, Some(value(i)));
+                        }
+                    }
+                    Ok(())
+                })
+            }).collect();
+            writers.into_iter().chain(scanners).try_for_each(|t| t.join().unwrap())
+        })?;
+
+        let keys: Vec<_> = tree.iter().map(|e| e.map(|(k, _)| k)).collect::<Result<_, _>>()?;
+        assert_eq!(keys, (0..1000).map(|i| key(i * 4)).collect::<Vec<_>>());
+        assert_eq!(tree.verify()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_load() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::bulk_load(&store, allocator, (0..20_000).map(|i| (key(i), value(i))), 1.0)?;
+        for i in scrambled(20_000) {
+            assert_eq!(tree.get(&key(i))?, Some(value(i)));
+        }
+        let keys: Vec<_> = tree.rev_range::<Vec<u8>, _>(..).map(|e| e.map(|(k, _)| k)).collect::<Result<_, _>>()?;
+        assert_eq!(keys, (0..20_000).rev().map(key).collect::<Vec<_>>());
+        assert_eq!(tree.range(key(19_990)..).count(), 10);
+        let packed = allocator.allocate(&store)?.id().offset();
+        for i in 0..2000 {
+            tree.insert(&format!("key-{:08}x", i * 10).into_bytes(), b"new")?;
+        }
+        assert_eq!(tree.iter().count(), 22_000);
+
+        // The same entries inserted one at a time leave nodes about half full and need more pages.
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        for i in scrambled(20_000) {
+            tree.insert(&key(i), &value(i))?;
+        }
+        assert!(allocator.allocate(&store)?.id().offset() > packed);
+
+        let empty = BTree::bulk_load(&store, allocator, Vec::<(Vec<u8>, Vec<u8>)>::new(), 0.7)?;
+        assert_eq!(empty.iter().count(), 0);
+        let unsorted = BTree::bulk_load(&store, allocator, [(b"b", b"1"), (b"a", b"2")], 0.7);
+        assert_eq!(unsorted.err(), Some(BTreeError::Unsorted));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_reports_violations() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        for i in scrambled(3000) {
+            tree.insert(&key(i), &value(i))?;
+        }
+        assert_eq!(tree.verify()?, vec![]);
+
+        let swapped = tree.leaf_for(&key(500))?.id();
+        {
+            let mut page = SlottedPage::new(store.write_latch(&swapped)?);
+            let (first, second) = (page.get(0).unwrap().to_vec(), page.get(1).unwrap().to_vec());
+            page.update(0, &second).unwrap();
+            page.update(1, &first).unwrap();
+        }
+        let broken = tree.leaf_for(&key(2000))?.id();
+        let next = {
+            let mut node = Node::new(store.write_latch(&broken)?);
+            let next = node.link(NEXT);
+            node.set_link(NEXT, None);
+            next
+        };
+        assert_eq!(tree.verify()?, vec![
+            Violation::OutOfOrder { page: swapped, index: 1 },
+            Violation::BadNextLink { page: broken, found: None, expected: next },
+        ]);
+
+        let freed = tree.leaf_for(&key(1000))?.id();
+        allocator.free(&store, freed)?;
+        let violations = tree.verify()?;
+        assert!(violations.contains(&Violation::FreePageInTree { page: freed }), "{violations:?}");
+        assert!(violations.contains(&Violation::NotANode { page: freed }), "{violations:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_size_limit() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+
+        assert_eq!(tree.insert(b"k", &[0u8; 2000]), Err(BTreeError::EntryTooLarge));
+        for i in 0..200u64 {
+            tree.insert(&[i.to_be_bytes().to_vec(), vec![1u8; 900]].concat(), &[2u8; 90])?;
+        }
+        assert_eq!(tree.get(&[7u64.to_be_bytes().to_vec(), vec![1u8; 900]].concat())?, Some(vec![2u8; 90]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_compression_and_short_separators() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        let long_key = |i: u64| format!("tenant/0042/customers/region-europe/account-{i:08}").into_bytes();
+        for i in scrambled(3000) {
+            tree.insert(&long_key(i), b"v")?;
+        }
+        for i in 0..3000 {
+            assert_eq!(tree.get(&long_key(i))?, Some(b"v".to_vec()));
+        }
+        assert_eq!(tree.range(long_key(10)..long_key(20)).count(), 10);
+
+        let root = store.pin_page(&tree.root()?)?;
+        let node = Node::new(root.try_read()?);
+        assert!(!node.is_leaf());
+        assert!(node.prefix().starts_with(b"tenant/0042/customers/region-europe/account-"));
+        assert!((0..node.len()).all(|i| node.parts(i).0.len() <= 8));
+        let leaf = tree.leaf_for(&long_key(1500))?;
+        assert!(Node::new(leaf.try_read()?).prefix().len() >= 48);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_after_a_compressed_run() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        // Ascending keys pack a leaf by their long shared prefix; the first key without it must not land in
+        // a half whose uncompressed cells outgrow the page.
+        let key = |run: &str, i: u64| format!("{run}/{}/{i:08}", "x".repeat(40)).into_bytes();
+        for run in ["a", "b", "c"] {
+            for i in 0..2000 {
+                tree.insert(&key(run, i), b"v")?;
+            }
+        }
+        assert_eq!(tree.range(key("b", 0)..key("c", 0)).count(), 2000);
+        assert!(tree.verify()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_and_upgrades_version_0_nodes() -> Result<(), BTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = BTree::create(&store, allocator)?;
+        {
+            // Lay out the root leaf as it was written before prefix compression.
+            let root = store.pin_page(&tree.root()?)?;
+            let mut page = SlottedPage::init(root.try_write()?, NODE_HEADER_LEN);
+            let header = page.reserved_mut();
+            write_u32(header, 0, NODE_MAGIC);
+            header[KIND] = LEAF;
+            write_u64(header, NEXT, NO_PAGE);
+            write_u64(header, PREV, NO_PAGE);
+            for i in 0..20 {
+                let cell = [&(key(i).len() as u16).to_le_bytes()[..], &key(i), &value(i)].concat();
+                page.insert(&cell).unwrap();
+            }
+        }
+        assert_eq!(tree.get(&key(7))?, Some(value(7)));
+        assert_eq!(tree.delete(&key(8))?, Some(value(8)));
+        assert_eq!(tree.insert(&key(8), b"new")?, None);
+        for i in 20..2000 {
+            tree.insert(&key(i), &value(i))?;
+        }
+        assert_eq!(tree.get(&key(8))?, Some(b"new".to_vec()));
+        assert_eq!(tree.get(&key(19))?, Some(value(19)));
+        assert_eq!(tree.iter().count(), 2000);
+        Ok(())
+    }
+}
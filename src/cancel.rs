@@ -0,0 +1,48 @@
+//! Cancelling work in progress from outside it.
+//!
+//! A `CancelHandle` is a flag shared by every clone of it. Whatever runs the work checks the flag at the
+//! points it can stop cleanly and fails with its own `Cancelled` error once it is set; whoever holds a
+//! clone, on any thread, sets it with `cancel`. Nothing is interrupted between checks, so cancelling stops
+//! work at the next check rather than at once.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+impl CancelHandle {
+    pub fn new() -> CancelHandle {
+        CancelHandle::default()
+    }
+
+    /// Ask the work this handle belongs to to stop at its next check.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clear the flag, so that work checking it runs on.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancelHandle;
+
+    #[test]
+    fn test_clones_share_the_flag() {
+        let handle = CancelHandle::new();
+        let other = handle.clone();
+        assert!(!other.is_cancelled());
+        std::thread::spawn(move || other.cancel()).join().unwrap();
+        assert!(handle.is_cancelled());
+        handle.reset();
+        assert!(!handle.is_cancelled());
+    }
+}
@@ -0,0 +1,1433 @@
+//! System catalog: the definitions of a database's tables, their columns and their indexes, stored in
+//! the database itself.
+//!
+//! The catalog is a B+tree keyed by table name whose values are encoded `TableDef`s, with definitions
+//! too long for a cell written to an overflow chain the way clustered tables store long rows. Opening a
+//! catalog reads every definition into memory, so lookups never touch a page; every DDL change writes
+//! the tree first and the in-memory copy only once that has succeeded, so the two never disagree.
+//!
+//! Each DDL change ends by flushing the page store. Over a `ShadowStorage` that flush is a commit, and
+//! since one change rewrites at most two catalog entries, a crash leaves either the definitions from
+//! before it or those from after, never a mix. Over raw storage nothing is atomic, as everywhere else.
+//!
+//! Definitions name a table's storage by the page to pass to its `open`, and say which kind of table
+//! it is so the caller knows which `open` that is. Creating and freeing the storage itself is up to the
+//! caller; the catalog only records it. Each definition also keeps its table's `SchemaHistory`, so a
+//! heap table that has been altered can still read the rows written before. An index that backs a
+//! `PRIMARY KEY` or `UNIQUE` constraint says so, and a table has at most one primary key. An index keyed
+//! on paths into JSON columns keeps those paths, encoded, beside its columns, and every index keeps the
+//! collation each of its columns is keyed by, which need not be its column's. A column's default is kept
+//! as the SQL of its expression, for the caller to parse and evaluate.
+//!
+//! A foreign key names the table it references and the unique index there that its values must be found
+//! in. Its own columns are those of an index on its table with the same name, which the caller builds to
+//! find the rows referencing a key. The catalog keeps foreign keys pointing at something: it refuses to
+//! drop a table or index one depends on, and renaming a table renames it in the foreign keys too.
+//!
+//! A trigger belongs to its table and names the writes it runs for, and when; its statements are kept as
+//! SQL, for the caller to parse and run. Triggers go with their table when it is dropped, and their names
+//! are unique within it.
+//!
+//! Views share the tables' names and tree. A view keeps its query as SQL, with the tables and views that
+//! query reads, and the catalog refuses to drop or rename any of those while the view exists.
+//!
+//! Sequences share the names and tree too. A sequence's definition records the first of its values not yet
+//! handed out or reserved, so that a caller reserving a range of values records its end before handing
+//! any of them out, and a crash loses at most the rest of a range, never giving a value twice. A sequence
+//! made for a table's column is owned by the table: it goes with the table when that is dropped, follows
+//! it through a rename, and cannot be dropped on its own.
+//!
+//! Keyspaces share the names and tree too. A keyspace's definition names the meta page of the B+tree that
+//! holds its keys, or the manifest of its LSM tree along with the tree's options, which the caller creates
+//! and frees as it does a table's storage.
+//!
+//! A definition may also carry the statistics from the last time its table was analyzed, for the planner
+//! to estimate costs from. They are a snapshot: nothing keeps them up to date as rows change. It may
+//! carry a TTL too, naming the column after whose time its rows expire and how long after; deleting them
+//! is up to the caller.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::allocator::PageAllocator;
+use crate::btree::{BTree, BTreeError};
+use crate::bytes::{read_u64, write_u64};
+use crate::collation::Collation;
+use crate::json::JsonPath;
+use crate::kv::KeyspaceOptions;
+use crate::lsm::{LsmOptions, MemtableIndex};
+use crate::overflow;
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::storage::Storage;
+use crate::datetime::Interval;
+use crate::table::{IndexKind, PartitionBy, TableStats, Ttl};
+use crate::tuple::{Column, ColumnType, Schema, SchemaHistory, Value};
+use crate::varint;
+
+const INLINE: u8 = 0;
+const OVERFLOW: u8 = 1;
+/// The first byte of a stored definition, saying what it defines.
+const TABLE: u8 = 0;
+const VIEW: u8 = 1;
+const SEQUENCE: u8 = 2;
+const KEYSPACE: u8 = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum CatalogError {
+    Page(PageError),
+    /// A stored definition cannot be decoded.
+    Corrupt,
+    /// A name is too long to be a key of the catalog tree.
+    NameTooLong,
+    DuplicateTable(String),
+    NoSuchTable(String),
+    DuplicateColumn(String),
+    NoSuchColumn(usize),
+    DuplicateIndex(String),
+    NoSuchIndex(String),
+    DuplicateTrigger(String),
+    NoSuchTrigger(String),
+    /// A second primary key for a table that has one.
+    DuplicatePrimaryKey,
+    /// The foreign key or view of this name depends on the table, view or index a change would drop or
+    /// rename.
+    Referenced(String),
+    /// The foreign key of this name has no index of its own, or does not match the unique index it
+    /// references in its number or types of columns, or sets to null columns that cannot hold one.
+    ForeignKeyMismatch(String),
+    /// The current version of a definition's history is not the schema its columns make up.
+    SchemaMismatch,
+    /// A TTL's lifetime does not suit its column: an integer column needs an integer, a timestamp column
+    /// an interval.
+    TtlMismatch,
+    /// A stored definition uses a collation, by this tag, that this build lacks, such as `unicode` without
+    /// the `unicode-collation` feature.
+    UnknownCollation(u8),
+}
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatalogError::Page(e) => write!(f, "page error: {e:?}"),
+            CatalogError::Corrupt => write!(f, "corrupt catalog"),
+            CatalogError::NameTooLong => write!(f, "name too long"),
+            CatalogError::DuplicateTable(name) => write!(f, "a table or view called {name} already exists"),
+            CatalogError::NoSuchTable(name) => write!(f, "no table or view called {name}"),
+            CatalogError::DuplicateColumn(name) => write!(f, "column {name} is given more than once"),
+            CatalogError::NoSuchColumn(c) => write!(f, "no column {}", c + 1),
+            CatalogError::DuplicateIndex(name) => write!(f, "an index called {name} already exists"),
+            CatalogError::NoSuchIndex(name) => write!(f, "no index called {name}"),
+            CatalogError::DuplicateTrigger(name) => write!(f, "a trigger called {name} already exists"),
+            CatalogError::NoSuchTrigger(name) => write!(f, "no trigger called {name}"),
+            CatalogError::DuplicatePrimaryKey => write!(f, "the table already has a primary key"),
+            CatalogError::Referenced(name) => write!(f, "{name} depends on it"),
+            CatalogError::ForeignKeyMismatch(name) => {
+                write!(f, "foreign key {name} does not match the key it references")
+            }
+            CatalogError::SchemaMismatch => write!(f, "corrupt catalog: a schema does not match its columns"),
+            CatalogError::TtlMismatch => write!(f, "the TTL's lifetime does not suit its column's type"),
+            CatalogError::UnknownCollation(tag) => write!(f, "collation {tag} is not in this build"),
+        }
+    }
+}
+impl From<PageError> for CatalogError {
+    fn from(e: PageError) -> Self {
+        CatalogError::Page(e)
+    }
+}
+impl From<BTreeError> for CatalogError {
+    fn from(e: BTreeError) -> Self {
+        match e {
+            BTreeError::Page(e) => CatalogError::Page(e),
+            BTreeError::EntryTooLarge => CatalogError::NameTooLong,
+            BTreeError::Unsorted => unreachable!("the catalog is never bulk loaded"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    pub column: Column,
+    /// The SQL of the expression a row given no value for the column takes, if not null.
+    pub default: Option<String>,
+    /// The collation the column's text compares and sorts by, and indexes on it are keyed by unless they
+    /// say otherwise.
+    pub collation: Collation,
+}
+impl ColumnDef {
+    pub fn new(name: &str, column: Column) -> ColumnDef {
+        ColumnDef { name: name.to_string(), column, default: None, collation: Collation::Binary }
+    }
+
+    pub fn with_default(self, default: &str) -> ColumnDef {
+        ColumnDef { default: Some(default.to_string()), ..self }
+    }
+
+    pub fn with_collation(self, collation: Collation) -> ColumnDef {
+        ColumnDef { collation, ..self }
+    }
+}
+
+/// How a table's rows are stored, and so which table type opens it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableKind {
+    /// A `Table`.
+    Heap,
+    /// A `ClusteredTable` keyed by these columns.
+    Clustered { primary_key: Vec<usize> },
+    /// A `TimeSeriesTable` ordered by this column.
+    TimeSeries { timestamp: usize },
+    /// A `ColumnarTable`.
+    Columnar,
+    /// A `PartitionedTable` partitioned as `by` says. The partitions themselves, and the bounds of range
+    /// partitions, are kept on the table's root page, since partitions come and go without DDL.
+    Partitioned { by: PartitionBy },
+}
+
+/// A constraint a unique index enforces, named by the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    Unique,
+    /// A unique key whose columns are not nullable. A table has at most one.
+    PrimaryKey,
+}
+
+/// What deleting a row does to the rows whose foreign keys reference it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDelete {
+    /// The delete fails.
+    Restrict,
+    /// They are deleted too.
+    Cascade,
+    /// Their foreign key columns are set to null.
+    SetNull,
+}
+
+/// A foreign key on a table. Its values, in the columns of the table's index called `name`, must be the
+/// key of some row of `table` under that table's unique index `references`, unless one of them is null.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub name: String,
+    pub table: String,
+    pub references: String,
+    pub on_delete: OnDelete,
+}
+
+/// Whether a trigger runs before or after the write of each row it runs for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerTiming {
+    Before,
+    After,
+}
+
+/// The writes a trigger runs for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A row-level trigger on a table: statements, kept as the SQL of a script, run for each row an `event`
+/// writes to the table, at `timing` to the write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerDef {
+    pub name: String,
+    pub timing: TriggerTiming,
+    pub event: TriggerEvent,
+    pub body: String,
+}
+
+/// A view: a `SELECT` kept as SQL, read by running it wherever the view is named.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewDef {
+    pub name: String,
+    /// The names of the view's columns, one for each column the query outputs.
+    pub columns: Vec<String>,
+    pub query: String,
+    /// The tables and views the query reads.
+    pub reads: Vec<String>,
+}
+/// A sequence of `Int`s, `increment` apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceDef {
+    pub name: String,
+    pub increment: i64,
+    /// How many values a caller reserves at a time.
+    pub cache: u64,
+    /// The first value not yet reserved.
+    pub next: i64,
+    /// The table whose column takes its values, if it was made for one.
+    pub owner: Option<String>,
+}
+impl SequenceDef {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        varint::write_prefixed(&mut buf, self.name.as_bytes());
+        varint::write_i64(&mut buf, self.increment);
+        varint::write_u64(&mut buf, self.cache);
+        varint::write_i64(&mut buf, self.next);
+        write_strings(&mut buf, self.owner.as_slice());
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<SequenceDef, CatalogError> {
+        let mut reader = Reader { buf };
+        let (name, increment, cache, next) = (reader.string()?, reader.i64()?, reader.u64()?, reader.i64()?);
+        let mut owner = reader.strings()?;
+        if !reader.buf.is_empty() || owner.len() > 1 {
+            return Err(CatalogError::Corrupt)
+        }
+        Ok(SequenceDef { name, increment, cache, next, owner: owner.pop() })
+    }
+}
+
+/// A keyspace: an ordered map of byte strings kept in a B+tree or an LSM tree of its own, outside SQL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyspaceDef {
+    pub name: String,
+    /// The page to pass to `Keyspace::open_with`.
+    pub meta: PageId,
+    /// What the keyspace was created with.
+    pub options: KeyspaceOptions,
+}
+impl KeyspaceDef {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        varint::write_prefixed(&mut buf, self.name.as_bytes());
+        varint::write_u64(&mut buf, self.meta.offset() as u64);
+        // A B+tree's definition ends there, as every definition did before keyspaces could be LSM trees.
+        if let Some(lsm) = &self.options.lsm {
+            let sizes = [lsm.memtable_bytes, lsm.l0_run_limit, lsm.level_base_pages, lsm.level_fanout];
+            sizes.iter().for_each(|&n| varint::write_u64(&mut buf, n as u64));
+            varint::write_u64(&mut buf, lsm.bloom_false_positive_rate.to_bits());
+            buf.push(match lsm.memtable_index {
+                MemtableIndex::SkipList => 0,
+                MemtableIndex::Art => 1,
+            });
+        }
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<KeyspaceDef, CatalogError> {
+        let mut reader = Reader { buf };
+        let (name, meta) = (reader.string()?, PageId::new(reader.u64()? as usize));
+        let mut options = KeyspaceOptions::default();
+        if !reader.buf.is_empty() {
+            let mut size = || Ok::<_, CatalogError>(reader.u64()? as usize);
+            let (memtable_bytes, l0_run_limit, level_base_pages, level_fanout) = (size()?, size()?, size()?, size()?);
+            let bloom_false_positive_rate = f64::from_bits(reader.u64()?);
+            let memtable_index = match reader.byte()? {
+                0 => MemtableIndex::SkipList,
+                1 => MemtableIndex::Art,
+                _ => return Err(CatalogError::Corrupt),
+            };
+            options.lsm = Some(LsmOptions {
+                memtable_bytes,
+                l0_run_limit,
+                level_base_pages,
+                level_fanout,
+                bloom_false_positive_rate,
+                memtable_index,
+            });
+        }
+        if !reader.buf.is_empty() {
+            return Err(CatalogError::Corrupt)
+        }
+        Ok(KeyspaceDef { name, meta, options })
+    }
+}
+
+impl ViewDef {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        varint::write_prefixed(&mut buf, self.name.as_bytes());
+        write_strings(&mut buf, &self.columns);
+        varint::write_prefixed(&mut buf, self.query.as_bytes());
+        write_strings(&mut buf, &self.reads);
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<ViewDef, CatalogError> {
+        let mut reader = Reader { buf };
+        let (name, columns, query, reads) = (reader.string()?, reader.strings()?, reader.string()?, reader.strings()?);
+        if !reader.buf.is_empty() {
+            return Err(CatalogError::Corrupt)
+        }
+        Ok(ViewDef { name, columns, query, reads })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexDef {
+    pub name: String,
+    pub columns: Vec<usize>,
+    /// For each of `columns`, the path into its JSON documents the index keys on, if any, to pass to
+    /// `Table::open_keyed_index`.
+    pub paths: Vec<Option<JsonPath>>,
+    /// The collation each of `columns` is keyed by.
+    pub collations: Vec<Collation>,
+    pub include: Vec<usize>,
+    /// The page to pass to `Table::open_index`, or to `open_unique_index` for an index with a constraint.
+    pub meta: PageId,
+    pub constraint: Option<Constraint>,
+    pub kind: IndexKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDef {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    pub kind: TableKind,
+    /// The page to pass to the table's `open`.
+    pub root: PageId,
+    pub indexes: Vec<IndexDef>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub triggers: Vec<TriggerDef>,
+    /// Every version of the schema, the last one being that of `columns`.
+    pub history: SchemaHistory,
+    /// Statistics about the table's rows, if it has been analyzed since its columns last changed.
+    pub stats: Option<TableStats>,
+    /// How long the table's rows live, if they expire.
+    pub ttl: Option<Ttl>,
+}
+impl TableDef {
+    /// A definition with no indexes whose schema has never changed.
+    pub fn new(name: &str, columns: Vec<ColumnDef>, kind: TableKind, root: PageId) -> TableDef {
+        let history = SchemaHistory::new(Schema::new(columns.iter().map(|c| c.column).collect()));
+        let (indexes, foreign_keys, triggers) = (Vec::new(), Vec::new(), Vec::new());
+        let name = name.to_string();
+        TableDef { name, columns, kind, root, indexes, foreign_keys, triggers, history, stats: None, ttl: None }
+    }
+
+    pub fn schema(&self) -> Schema {
+        Schema::new(self.columns.iter().map(|c| c.column).collect())
+    }
+
+    /// Position of the column called `name`.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    /// The collation of each of `columns`, binary for one there is no such column.
+    pub fn collations(&self, columns: &[usize]) -> Vec<Collation> {
+        columns.iter().map(|&c| self.columns.get(c).map_or(Collation::Binary, |c| c.collation)).collect()
+    }
+
+    pub fn index(&self, name: &str) -> Option<&IndexDef> {
+        self.indexes.iter().find(|i| i.name == name)
+    }
+
+    /// The triggers that run at `timing` to a write of `event`, in the order they run: that of their names.
+    pub fn triggers(&self, timing: TriggerTiming, event: TriggerEvent) -> Vec<&TriggerDef> {
+        let mut triggers: Vec<_> = self.triggers.iter().filter(|t| t.timing == timing && t.event == event).collect();
+        triggers.sort_by(|a, b| a.name.cmp(&b.name));
+        triggers
+    }
+
+    fn check(&self) -> Result<(), CatalogError> {
+        if *self.history.schema() != self.schema() {
+            return Err(CatalogError::SchemaMismatch)
+        }
+        if self.stats.as_ref().is_some_and(|s| s.columns.len() != self.columns.len()) {
+            return Err(CatalogError::SchemaMismatch)
+        }
+        for (i, column) in self.columns.iter().enumerate() {
+            if self.columns[..i].iter().any(|c| c.name == column.name) {
+                return Err(CatalogError::DuplicateColumn(column.name.clone()))
+            }
+        }
+        let in_range = |c: &usize| if *c < self.columns.len() { Ok(()) } else { Err(CatalogError::NoSuchColumn(*c)) };
+        match &self.kind {
+            TableKind::Clustered { primary_key } => primary_key.iter().try_for_each(in_range)?,
+            TableKind::TimeSeries { timestamp } => in_range(timestamp)?,
+            TableKind::Partitioned { by } => in_range(&by.column())?,
+            TableKind::Heap | TableKind::Columnar => {}
+        }
+        if let Some(ttl) = &self.ttl {
+            in_range(&ttl.column)?;
+            if !ttl.suits(self.columns[ttl.column].column.column_type) {
+                return Err(CatalogError::TtlMismatch)
+            }
+        }
+        for (i, index) in self.indexes.iter().enumerate() {
+            if self.indexes[..i].iter().any(|x| x.name == index.name) {
+                return Err(CatalogError::DuplicateIndex(index.name.clone()))
+            }
+            index.columns.iter().chain(&index.include).try_for_each(in_range)?;
+        }
+        if self.indexes.iter().filter(|i| i.constraint == Some(Constraint::PrimaryKey)).count() > 1 {
+            return Err(CatalogError::DuplicatePrimaryKey)
+        }
+        for (i, trigger) in self.triggers.iter().enumerate() {
+            if self.triggers[..i].iter().any(|t| t.name == trigger.name) {
+                return Err(CatalogError::DuplicateTrigger(trigger.name.clone()))
+            }
+        }
+        for key in &self.foreign_keys {
+            let mismatch = || CatalogError::ForeignKeyMismatch(key.name.clone());
+            let index = self.index(&key.name).filter(|i| i.constraint.is_none()).ok_or_else(mismatch)?;
+            if key.on_delete == OnDelete::SetNull && index.columns.iter().any(|&c| !self.columns[c].column.nullable) {
+                return Err(mismatch())
+            }
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        varint::write_prefixed(&mut buf, self.name.as_bytes());
+        varint::write_u64(&mut buf, self.columns.len() as u64);
+        for column in &self.columns {
+            varint::write_prefixed(&mut buf, column.name.as_bytes());
+            column.column.column_type.write(&mut buf);
+            let collated = column.collation != Collation::Binary;
+            buf.push(column.column.nullable as u8 | (column.default.is_some() as u8) << 1 | (collated as u8) << 2);
+            if let Some(default) = &column.default {
+                varint::write_prefixed(&mut buf, default.as_bytes());
+            }
+            if collated {
+                buf.push(column.collation.tag());
+            }
+        }
+        // Definitions written before TTLs have the kind's high bit clear.
+        let expires = if self.ttl.is_some() { 0x80 } else { 0 };
+        match &self.kind {
+            TableKind::Heap => buf.push(expires),
+            TableKind::Clustered { primary_key } => {
+                buf.push(1 | expires);
+                write_columns(&mut buf, primary_key);
+            }
+            TableKind::TimeSeries { timestamp } => {
+                buf.push(2 | expires);
+                varint::write_u64(&mut buf, *timestamp as u64);
+            }
+            TableKind::Columnar => buf.push(3 | expires),
+            TableKind::Partitioned { by } => {
+                buf.push(4 | expires);
+                match by {
+                    PartitionBy::Range(column) => {
+                        buf.push(0);
+                        varint::write_u64(&mut buf, *column as u64);
+                    }
+                    PartitionBy::Hash { column, partitions } => {
+                        buf.push(1);
+                        varint::write_u64(&mut buf, *column as u64);
+                        varint::write_u64(&mut buf, *partitions as u64);
+                    }
+                }
+            }
+        }
+        if let Some(ttl) = &self.ttl {
+            varint::write_u64(&mut buf, ttl.column as u64);
+            match ttl.lifetime {
+                Value::Int(lifetime) => {
+                    buf.push(0);
+                    varint::write_i64(&mut buf, lifetime);
+                }
+                Value::Interval(lifetime) => {
+                    buf.push(1);
+                    varint::write_i64(&mut buf, lifetime.months as i64);
+                    varint::write_i64(&mut buf, lifetime.micros);
+                }
+                _ => unreachable!("checked by check"),
+            }
+        }
+        varint::write_u64(&mut buf, self.root.offset() as u64);
+        varint::write_u64(&mut buf, self.indexes.len() as u64);
+        for index in &self.indexes {
+            varint::write_prefixed(&mut buf, index.name.as_bytes());
+            write_columns(&mut buf, &index.columns);
+            write_columns(&mut buf, &index.include);
+            varint::write_u64(&mut buf, index.meta.offset() as u64);
+            let constraint = match index.constraint {
+                None => 0,
+                Some(Constraint::Unique) => 1,
+                Some(Constraint::PrimaryKey) => 2,
+            };
+            // Definitions written before hash indexes have the high bits clear, which reads as a B+tree.
+            buf.push(constraint | if index.kind == IndexKind::Hash { 4 } else { 0 });
+            let paths: Vec<_> = index.paths.iter().enumerate().filter_map(|(i, p)| Some((i, p.as_ref()?))).collect();
+            varint::write_u64(&mut buf, paths.len() as u64);
+            for (i, path) in paths {
+                varint::write_u64(&mut buf, i as u64);
+                path.write(&mut buf);
+            }
+            buf.extend(index.collations.iter().map(|c| c.tag()));
+        }
+        varint::write_u64(&mut buf, self.foreign_keys.len() as u64);
+        for key in &self.foreign_keys {
+            varint::write_prefixed(&mut buf, key.name.as_bytes());
+            varint::write_prefixed(&mut buf, key.table.as_bytes());
+            varint::write_prefixed(&mut buf, key.references.as_bytes());
+            buf.push(match key.on_delete {
+                OnDelete::Restrict => 0,
+                OnDelete::Cascade => 1,
+                OnDelete::SetNull => 2,
+            });
+        }
+        varint::write_u64(&mut buf, self.triggers.len() as u64);
+        for trigger in &self.triggers {
+            varint::write_prefixed(&mut buf, trigger.name.as_bytes());
+            let timing = match trigger.timing {
+                TriggerTiming::Before => 0,
+                TriggerTiming::After => 1,
+            };
+            let event = match trigger.event {
+                TriggerEvent::Insert => 0,
+                TriggerEvent::Update => 1,
+                TriggerEvent::Delete => 2,
+            };
+            buf.push(timing << 2 | event);
+            varint::write_prefixed(&mut buf, trigger.body.as_bytes());
+        }
+        self.history.write(&mut buf);
+        if let Some(stats) = &self.stats {
+            varint::write_prefixed(&mut buf, &stats.to_bytes());
+        }
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<TableDef, CatalogError> {
+        let mut reader = Reader { buf };
+        let name = reader.string()?;
+        let mut columns = Vec::new();
+        for _ in 0..reader.u64()? {
+            let name = reader.string()?;
+            let column_type = reader.column_type()?;
+            let flags = reader.byte()?;
+            if flags > 7 {
+                return Err(CatalogError::Corrupt)
+            }
+            let default = if flags & 2 != 0 { Some(reader.string()?) } else { None };
+            let collation = if flags & 4 != 0 { reader.collation()? } else { Collation::Binary };
+            let column = Column { column_type, nullable: flags & 1 != 0 };
+            columns.push(ColumnDef { name, column, default, collation });
+        }
+        let kind_byte = reader.byte()?;
+        let kind = match kind_byte & !0x80 {
+            0 => TableKind::Heap,
+            1 => TableKind::Clustered { primary_key: reader.columns()? },
+            2 => TableKind::TimeSeries { timestamp: reader.u64()? as usize },
+            3 => TableKind::Columnar,
+            4 => TableKind::Partitioned {
+                by: match reader.byte()? {
+                    0 => PartitionBy::Range(reader.u64()? as usize),
+                    1 => PartitionBy::Hash { column: reader.u64()? as usize, partitions: reader.u64()? as usize },
+                    _ => return Err(CatalogError::Corrupt),
+                },
+            },
+            _ => return Err(CatalogError::Corrupt),
+        };
+        let mut ttl = None;
+        if kind_byte & 0x80 != 0 {
+            let column = reader.u64()? as usize;
+            let lifetime = match reader.byte()? {
+                0 => Value::Int(reader.i64()?),
+                1 => {
+                    let months = i32::try_from(reader.i64()?).map_err(|_| CatalogError::Corrupt)?;
+                    Value::Interval(Interval { months, micros: reader.i64()? })
+                }
+                _ => return Err(CatalogError::Corrupt),
+            };
+            ttl = Some(Ttl { column, lifetime });
+        }
+        let root = PageId::new(reader.u64()? as usize);
+        let mut indexes = Vec::new();
+        for _ in 0..reader.u64()? {
+            let name = reader.string()?;
+            let columns = reader.columns()?;
+            let include = reader.columns()?;
+            let meta = PageId::new(reader.u64()? as usize);
+            let flags = reader.byte()?;
+            let kind = if flags & 4 != 0 { IndexKind::Hash } else { IndexKind::BTree };
+            let constraint = match flags & !4 {
+                0 => None,
+                1 => Some(Constraint::Unique),
+                2 => Some(Constraint::PrimaryKey),
+                _ => return Err(CatalogError::Corrupt),
+            };
+            let mut paths = vec![None; columns.len()];
+            for _ in 0..reader.u64()? {
+                let part = paths.get_mut(reader.u64()? as usize).ok_or(CatalogError::Corrupt)?;
+                *part = Some(reader.path()?);
+            }
+            let collations = columns.iter().map(|_| reader.collation()).collect::<Result<_, _>>()?;
+            indexes.push(IndexDef { name, columns, paths, collations, include, meta, constraint, kind });
+        }
+        let mut foreign_keys = Vec::new();
+        for _ in 0..reader.u64()? {
+            let (name, table, references) = (reader.string()?, reader.string()?, reader.string()?);
+            let on_delete = match reader.byte()? {
+                0 => OnDelete::Restrict,
+                1 => OnDelete::Cascade,
+                2 => OnDelete::SetNull,
+                _ => return Err(CatalogError::Corrupt),
+            };
+            foreign_keys.push(ForeignKey { name, table, references, on_delete });
+        }
+        let mut triggers = Vec::new();
+        for _ in 0..reader.u64()? {
+            let name = reader.string()?;
+            let flags = reader.byte()?;
+            let timing = match flags >> 2 {
+                0 => TriggerTiming::Before,
+                1 => TriggerTiming::After,
+                _ => return Err(CatalogError::Corrupt),
+            };
+            let event = match flags & 3 {
+                0 => TriggerEvent::Insert,
+                1 => TriggerEvent::Update,
+                2 => TriggerEvent::Delete,
+                _ => return Err(CatalogError::Corrupt),
+            };
+            triggers.push(TriggerDef { name, timing, event, body: reader.string()? });
+        }
+        let (history, len) = SchemaHistory::read(reader.buf).ok_or(CatalogError::Corrupt)?;
+        let mut stats = None;
+        if len != reader.buf.len() {
+            let (bytes, stats_len) = varint::read_prefixed(&reader.buf[len..]).ok_or(CatalogError::Corrupt)?;
+            if len + stats_len != reader.buf.len() {
+                return Err(CatalogError::Corrupt)
+            }
+            stats = Some(TableStats::from_bytes(bytes, history.schema()).ok_or(CatalogError::Corrupt)?);
+        }
+        Ok(TableDef { name, columns, kind, root, indexes, foreign_keys, triggers, history, stats, ttl })
+    }
+}
+
+fn write_columns(buf: &mut Vec<u8>, columns: &[usize]) {
+    varint::write_u64(buf, columns.len() as u64);
+    for &c in columns {
+        varint::write_u64(buf, c as u64);
+    }
+}
+
+fn write_strings(buf: &mut Vec<u8>, strings: &[String]) {
+    varint::write_u64(buf, strings.len() as u64);
+    for s in strings {
+        varint::write_prefixed(buf, s.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, CatalogError> {
+        let (&byte, rest) = self.buf.split_first().ok_or(CatalogError::Corrupt)?;
+        self.buf = rest;
+        Ok(byte)
+    }
+
+    fn u64(&mut self) -> Result<u64, CatalogError> {
+        let (v, len) = varint::read_u64(self.buf).ok_or(CatalogError::Corrupt)?;
+        self.buf = &self.buf[len..];
+        Ok(v)
+    }
+
+    fn i64(&mut self) -> Result<i64, CatalogError> {
+        let (v, len) = varint::read_i64(self.buf).ok_or(CatalogError::Corrupt)?;
+        self.buf = &self.buf[len..];
+        Ok(v)
+    }
+
+    fn column_type(&mut self) -> Result<ColumnType, CatalogError> {
+        let (column_type, len) = ColumnType::read(self.buf).ok_or(CatalogError::Corrupt)?;
+        self.buf = &self.buf[len..];
+        Ok(column_type)
+    }
+
+    fn collation(&mut self) -> Result<Collation, CatalogError> {
+        let tag = self.byte()?;
+        Collation::from_tag(tag).ok_or(CatalogError::UnknownCollation(tag))
+    }
+
+    fn path(&mut self) -> Result<JsonPath, CatalogError> {
+        let (path, len) = JsonPath::read(self.buf).ok_or(CatalogError::Corrupt)?;
+        self.buf = &self.buf[len..];
+        Ok(path)
+    }
+
+    fn string(&mut self) -> Result<String, CatalogError> {
+        let (bytes, len) = varint::read_prefixed(self.buf).ok_or(CatalogError::Corrupt)?;
+        let string = String::from_utf8(bytes.to_vec()).map_err(|_| CatalogError::Corrupt)?;
+        self.buf = &self.buf[len..];
+        Ok(string)
+    }
+
+    fn columns(&mut self) -> Result<Vec<usize>, CatalogError> {
+        (0..self.u64()?).map(|_| self.u64().map(|c| c as usize)).collect()
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, CatalogError> {
+        (0..self.u64()?).map(|_| self.string()).collect()
+    }
+}
+
+pub struct Catalog<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    tree: BTree<'store, S>,
+    tables: BTreeMap<String, TableDef>,
+    views: BTreeMap<String, ViewDef>,
+    sequences: BTreeMap<String, SequenceDef>,
+    keyspaces: BTreeMap<String, KeyspaceDef>,
+}
+impl<'store, S: Storage> Catalog<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<Catalog<'store, S>, CatalogError> {
+        let tree = BTree::create(store, allocator)?;
+        let (tables, views, sequences) = (BTreeMap::new(), BTreeMap::new(), BTreeMap::new());
+        Ok(Catalog { store, allocator, tree, tables, views, sequences, keyspaces: BTreeMap::new() })
+    }
+
+    /// Open the catalog whose tree starts at `root`, reading every definition in it.
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, root: PageId) -> Result<Catalog<'store, S>, CatalogError> {
+        let tree = BTree::open(store, allocator, root)?;
+        let (tables, views, sequences) = (BTreeMap::new(), BTreeMap::new(), BTreeMap::new());
+        let mut catalog = Catalog { store, allocator, tree, tables, views, sequences, keyspaces: BTreeMap::new() };
+        for entry in catalog.tree.iter() {
+            let (_, stored) = entry?;
+            match catalog.load(&stored)?.split_first() {
+                Some((&TABLE, record)) => {
+                    let def = TableDef::decode(record)?;
+                    catalog.tables.insert(def.name.clone(), def);
+                }
+                Some((&VIEW, record)) => {
+                    let def = ViewDef::decode(record)?;
+                    catalog.views.insert(def.name.clone(), def);
+                }
+                Some((&SEQUENCE, record)) => {
+                    let def = SequenceDef::decode(record)?;
+                    catalog.sequences.insert(def.name.clone(), def);
+                }
+                Some((&KEYSPACE, record)) => {
+                    let def = KeyspaceDef::decode(record)?;
+                    catalog.keyspaces.insert(def.name.clone(), def);
+                }
+                _ => return Err(CatalogError::Corrupt),
+            }
+        }
+        Ok(catalog)
+    }
+
+    /// The page to pass to `open` to find this catalog again.
+    pub fn root(&self) -> PageId {
+        self.tree.meta_page()
+    }
+
+    /// Every table, in name order.
+    pub fn tables(&self) -> impl Iterator<Item = &TableDef> {
+        self.tables.values()
+    }
+
+    pub fn table(&self, name: &str) -> Option<&TableDef> {
+        self.tables.get(name)
+    }
+
+    /// Every view, in name order.
+    pub fn views(&self) -> impl Iterator<Item = &ViewDef> {
+        self.views.values()
+    }
+
+    pub fn view(&self, name: &str) -> Option<&ViewDef> {
+        self.views.get(name)
+    }
+
+    /// Every sequence, in name order.
+    pub fn sequences(&self) -> impl Iterator<Item = &SequenceDef> {
+        self.sequences.values()
+    }
+
+    pub fn sequence(&self, name: &str) -> Option<&SequenceDef> {
+        self.sequences.get(name)
+    }
+
+    /// Record a new sequence. The table owning it, if any, must exist.
+    pub fn create_sequence(&mut self, def: SequenceDef) -> Result<(), CatalogError> {
+        self.check_name(&def.name)?;
+        if let Some(owner) = def.owner.as_ref().filter(|owner| !self.tables.contains_key(*owner)) {
+            return Err(CatalogError::NoSuchTable(owner.clone()))
+        }
+        self.put(&def.name, SEQUENCE, def.encode())?;
+        self.store.flush()?;
+        self.sequences.insert(def.name.clone(), def);
+        Ok(())
+    }
+
+    /// Forget the sequence called `name`, which no table may own.
+    pub fn drop_sequence(&mut self, name: &str) -> Result<SequenceDef, CatalogError> {
+        let Some(def) = self.sequences.get(name) else { return Err(CatalogError::NoSuchTable(name.to_string())) };
+        if let Some(owner) = &def.owner {
+            return Err(CatalogError::Referenced(owner.clone()))
+        }
+        self.remove(name)?;
+        self.store.flush()?;
+        Ok(self.sequences.remove(name).unwrap())
+    }
+
+    /// Record that every value of the sequence called `name` before `next` is reserved, committing that
+    /// with the flush it ends with.
+    pub fn advance_sequence(&mut self, name: &str, next: i64) -> Result<(), CatalogError> {
+        let mut def = self.sequences.get(name).cloned().ok_or_else(|| CatalogError::NoSuchTable(name.to_string()))?;
+        def.next = next;
+        self.remove(name)?;
+        self.put(name, SEQUENCE, def.encode())?;
+        self.store.flush()?;
+        self.sequences.insert(def.name.clone(), def);
+        Ok(())
+    }
+
+    /// Every keyspace, in name order.
+    pub fn keyspaces(&self) -> impl Iterator<Item = &KeyspaceDef> {
+        self.keyspaces.values()
+    }
+
+    pub fn keyspace(&self, name: &str) -> Option<&KeyspaceDef> {
+        self.keyspaces.get(name)
+    }
+
+    pub fn create_keyspace(&mut self, def: KeyspaceDef) -> Result<(), CatalogError> {
+        self.check_name(&def.name)?;
+        self.put(&def.name, KEYSPACE, def.encode())?;
+        self.store.flush()?;
+        self.keyspaces.insert(def.name.clone(), def);
+        Ok(())
+    }
+
+    pub fn drop_keyspace(&mut self, name: &str) -> Result<KeyspaceDef, CatalogError> {
+        if !self.keyspaces.contains_key(name) {
+            return Err(CatalogError::NoSuchTable(name.to_string()))
+        }
+        self.remove(name)?;
+        self.store.flush()?;
+        Ok(self.keyspaces.remove(name).unwrap())
+    }
+
+    /// The sequences the table called `table` owns.
+    pub fn owned<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a SequenceDef> + 'a {
+        self.sequences.values().filter(move |s| s.owner.as_deref() == Some(table))
+    }
+
+    /// A view reading the table or view called `name`, if there is one.
+    pub fn reader(&self, name: &str) -> Option<&ViewDef> {
+        self.views.values().find(|view| view.reads.iter().any(|read| read == name))
+    }
+
+    /// Record a new view. Every table and view it reads must exist.
+    pub fn create_view(&mut self, def: ViewDef) -> Result<(), CatalogError> {
+        self.check_name(&def.name)?;
+        if let Some(read) = def.reads.iter().find(|r| !self.tables.contains_key(*r) && !self.views.contains_key(*r)) {
+            return Err(CatalogError::NoSuchTable(read.clone()))
+        }
+        self.put(&def.name, VIEW, def.encode())?;
+        self.store.flush()?;
+        self.views.insert(def.name.clone(), def);
+        Ok(())
+    }
+
+    /// Forget the view called `name`, which no other view may read.
+    pub fn drop_view(&mut self, name: &str) -> Result<ViewDef, CatalogError> {
+        if !self.views.contains_key(name) {
+            return Err(CatalogError::NoSuchTable(name.to_string()))
+        }
+        if let Some(view) = self.reader(name) {
+            return Err(CatalogError::Referenced(view.name.clone()))
+        }
+        self.remove(name)?;
+        self.store.flush()?;
+        Ok(self.views.remove(name).unwrap())
+    }
+
+    /// The foreign keys referencing the table called `table`, each with the table it is on.
+    pub fn referencing<'a>(&'a self, table: &'a str) -> impl Iterator<Item = (&'a TableDef, &'a ForeignKey)> + 'a {
+        self.tables.values().flat_map(move |def| {
+            def.foreign_keys.iter().filter(move |k| k.table == table).map(move |k| (def, k))
+        })
+    }
+
+    /// A foreign key that would be left without what it depends on were the table called `table` dropped,
+    /// or with `index`, that index of it: one referencing it from another table, or the one it belongs to.
+    pub fn dependent<'a>(&'a self, table: &'a str, index: Option<&str>) -> Option<&'a ForeignKey> {
+        let Some(index) = index else {
+            return self.referencing(table).find(|(def, _)| def.name != table).map(|(_, key)| key)
+        };
+        let own = self.tables.get(table)?.foreign_keys.iter().find(|key| key.name == index);
+        own.or_else(|| self.referencing(table).map(|(_, key)| key).find(|key| key.references == index))
+    }
+
+    /// Record a new table. Its storage must already exist at `def.root`.
+    pub fn create_table(&mut self, def: TableDef) -> Result<(), CatalogError> {
+        self.check_name(&def.name)?;
+        def.check()?;
+        self.check_references(&def, &def)?;
+        self.put_table(&def)?;
+        self.store.flush()?;
+        self.tables.insert(def.name.clone(), def);
+        Ok(())
+    }
+
+    /// Forget the table called `name` and the sequences it owns, returning its definition so the caller can
+    /// free its storage.
+    pub fn drop_table(&mut self, name: &str) -> Result<TableDef, CatalogError> {
+        if !self.tables.contains_key(name) {
+            return Err(CatalogError::NoSuchTable(name.to_string()))
+        }
+        if let Some(key) = self.dependent(name, None) {
+            return Err(CatalogError::Referenced(key.name.clone()))
+        }
+        if let Some(view) = self.reader(name) {
+            return Err(CatalogError::Referenced(view.name.clone()))
+        }
+        let owned: Vec<String> = self.owned(name).map(|s| s.name.clone()).collect();
+        self.remove(name)?;
+        for sequence in &owned {
+            self.remove(sequence)?;
+        }
+        self.store.flush()?;
+        for sequence in &owned {
+            self.sequences.remove(sequence);
+        }
+        Ok(self.tables.remove(name).unwrap())
+    }
+
+    /// Rename the table called `name`, which no view may read: a view's query names it as SQL.
+    pub fn rename_table(&mut self, name: &str, new_name: &str) -> Result<(), CatalogError> {
+        self.check_name(new_name)?;
+        let mut def = self.tables.get(name).cloned().ok_or_else(|| CatalogError::NoSuchTable(name.to_string()))?;
+        if let Some(view) = self.reader(name) {
+            return Err(CatalogError::Referenced(view.name.clone()))
+        }
+        let mut referencing: Vec<TableDef> =
+            self.referencing(name).filter(|(d, _)| d.name != name).map(|(d, _)| d.clone()).collect();
+        referencing.dedup_by(|a, b| a.name == b.name);
+        let mut owned: Vec<SequenceDef> = self.owned(name).cloned().collect();
+        owned.iter_mut().for_each(|s| s.owner = Some(new_name.to_string()));
+        def.name = new_name.to_string();
+        for def in referencing.iter_mut().chain(std::iter::once(&mut def)) {
+            def.foreign_keys.iter_mut().filter(|k| k.table == name).for_each(|k| k.table = new_name.to_string());
+        }
+        self.put_table(&def)?;
+        self.remove(name)?;
+        for def in &referencing {
+            self.remove(&def.name)?;
+            self.put_table(def)?;
+        }
+        for sequence in &owned {
+            self.remove(&sequence.name)?;
+            self.put(&sequence.name, SEQUENCE, sequence.encode())?;
+        }
+        self.store.flush()?;
+        self.tables.remove(name);
+        self.tables.insert(def.name.clone(), def);
+        for def in referencing {
+            self.tables.insert(def.name.clone(), def);
+        }
+        for sequence in owned {
+            self.sequences.insert(sequence.name.clone(), sequence);
+        }
+        Ok(())
+    }
+
+    /// Replace the definition of the table called `def.name`, which must exist, with `def`.
+    pub fn alter_table(&mut self, def: TableDef) -> Result<(), CatalogError> {
+        let Some(old) = self.tables.get(&def.name) else {
+            return Err(CatalogError::NoSuchTable(def.name))
+        };
+        def.check()?;
+        self.check_references(old, &def)?;
+        self.replace(def)
+    }
+
+    /// Record a new index on the table called `table`. The index itself must already exist at `index.meta`.
+    pub fn add_index(&mut self, table: &str, index: IndexDef) -> Result<(), CatalogError> {
+        let mut def = self.tables.get(table).cloned().ok_or_else(|| CatalogError::NoSuchTable(table.to_string()))?;
+        def.indexes.push(index);
+        def.check()?;
+        self.replace(def)
+    }
+
+    /// Forget the index called `name` on the table called `table`, returning its definition so the caller
+    /// can free its tree.
+    pub fn drop_index(&mut self, table: &str, name: &str) -> Result<IndexDef, CatalogError> {
+        let mut def = self.tables.get(table).cloned().ok_or_else(|| CatalogError::NoSuchTable(table.to_string()))?;
+        let Some(position) = def.indexes.iter().position(|i| i.name == name) else {
+            return Err(CatalogError::NoSuchIndex(name.to_string()))
+        };
+        if let Some(key) = self.dependent(table, Some(name)) {
+            return Err(CatalogError::Referenced(key.name.clone()))
+        }
+        let index = def.indexes.remove(position);
+        self.replace(def)?;
+        Ok(index)
+    }
+
+    /// Record a new trigger on the table called `table`.
+    pub fn add_trigger(&mut self, table: &str, trigger: TriggerDef) -> Result<(), CatalogError> {
+        let mut def = self.tables.get(table).cloned().ok_or_else(|| CatalogError::NoSuchTable(table.to_string()))?;
+        def.triggers.push(trigger);
+        def.check()?;
+        self.replace(def)
+    }
+
+    /// Forget the trigger called `name` on the table called `table`.
+    pub fn drop_trigger(&mut self, table: &str, name: &str) -> Result<TriggerDef, CatalogError> {
+        let mut def = self.tables.get(table).cloned().ok_or_else(|| CatalogError::NoSuchTable(table.to_string()))?;
+        let Some(position) = def.triggers.iter().position(|t| t.name == name) else {
+            return Err(CatalogError::NoSuchTrigger(name.to_string()))
+        };
+        let trigger = def.triggers.remove(position);
+        self.replace(def)?;
+        Ok(trigger)
+    }
+
+    /// Check that the foreign keys on `new`, the definition to replace `old`, reference unique indexes that
+    /// they match, and that those referencing `old` would still find theirs.
+    fn check_references(&self, old: &TableDef, new: &TableDef) -> Result<(), CatalogError> {
+        for key in &new.foreign_keys {
+            let table = if key.table == new.name { Some(new) } else { self.table(&key.table) };
+            let table = table.ok_or_else(|| CatalogError::NoSuchTable(key.table.clone()))?;
+            let references = table.index(&key.references);
+            let references = references.ok_or_else(|| CatalogError::NoSuchIndex(key.references.clone()))?;
+            let index = new.index(&key.name).ok_or_else(|| CatalogError::ForeignKeyMismatch(key.name.clone()))?;
+            let columns = &index.columns;
+            let matches = references.constraint.is_some()
+                && columns.len() == references.columns.len()
+                && columns.iter().zip(&references.columns).all(|(&c, &r)| {
+                    new.columns[c].column.column_type.same_kind(table.columns[r].column.column_type)
+                });
+            if !matches {
+                return Err(CatalogError::ForeignKeyMismatch(key.name.clone()))
+            }
+        }
+        for (def, key) in self.referencing(&old.name) {
+            let def = if def.name == old.name { new } else { def };
+            if !def.foreign_keys.contains(key) {
+                continue
+            }
+            match new.index(&key.references) {
+                Some(index) if index.constraint.is_some() => {}
+                _ => return Err(CatalogError::Referenced(key.name.clone())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Overwrite the stored definition of an existing table with `def`.
+    fn replace(&mut self, def: TableDef) -> Result<(), CatalogError> {
+        self.remove(&def.name)?;
+        self.put_table(&def)?;
+        self.store.flush()?;
+        self.tables.insert(def.name.clone(), def);
+        Ok(())
+    }
+
+    /// Refuse `name` for a new table, view or sequence if one already has it.
+    fn check_name(&self, name: &str) -> Result<(), CatalogError> {
+        let taken = self.tables.contains_key(name) || self.views.contains_key(name)
+            || self.sequences.contains_key(name) || self.keyspaces.contains_key(name);
+        match taken {
+            true => Err(CatalogError::DuplicateTable(name.to_string())),
+            false => Ok(()),
+        }
+    }
+
+    fn put_table(&self, def: &TableDef) -> Result<(), CatalogError> {
+        self.put(&def.name, TABLE, def.encode())
+    }
+
+    /// Store `body`, a definition of the kind `kind` says, under `name`: inline if it fits in a cell and in
+    /// an overflow chain otherwise.
+    fn put(&self, name: &str, kind: u8, body: Vec<u8>) -> Result<(), CatalogError> {
+        let mut record = vec![kind];
+        record.extend_from_slice(&body);
+        let mut inline = vec![INLINE];
+        inline.extend_from_slice(&record);
+        match self.tree.insert(name.as_bytes(), &inline) {
+            Err(BTreeError::EntryTooLarge) => {}
+            result => return result.map(|_| ()).map_err(CatalogError::from),
+        }
+        let head = overflow::write(self.store, &self.allocator, &record)?;
+        let mut stub = vec![OVERFLOW; 9];
+        write_u64(&mut stub, 1, head.offset() as u64);
+        if let Err(e) = self.tree.insert(name.as_bytes(), &stub) {
+            overflow::free(self.store, &self.allocator, head)?;
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    /// Delete the entry for `name` and release its overflow chain, if it has one.
+    fn remove(&self, name: &str) -> Result<(), CatalogError> {
+        if let Some(stored) = self.tree.delete(name.as_bytes())? {
+            if stored.first() == Some(&OVERFLOW) {
+                overflow::free(self.store, &self.allocator, PageId::new(read_u64(&stored, 1) as usize))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn load(&self, stored: &[u8]) -> Result<Vec<u8>, CatalogError> {
+        match stored.first() {
+            Some(&INLINE) => Ok(stored[1..].to_vec()),
+            Some(&OVERFLOW) if stored.len() == 9 => Ok(overflow::read(self.store, PageId::new(read_u64(stored, 1) as usize))?),
+            _ => Err(CatalogError::Corrupt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::collation::Collation;
+    use crate::json::{JsonPath, Step};
+    use crate::kv::KeyspaceOptions;
+    use crate::lsm::{LsmOptions, MemtableIndex};
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+    use crate::datetime::Interval;
+    use crate::table::{IndexKind, PartitionBy, Ttl};
+    use crate::tuple::{Column, ColumnType, Value};
+
+    use super::{
+        Catalog, CatalogError, ColumnDef, Constraint, ForeignKey, IndexDef, KeyspaceDef, OnDelete, SequenceDef,
+        TableDef, TableKind, TriggerDef, TriggerEvent, TriggerTiming, ViewDef,
+    };
+
+    fn index(name: &str, columns: Vec<usize>, include: Vec<usize>, meta: usize) -> IndexDef {
+        let (paths, collations) = (vec![None; columns.len()], vec![Collation::Binary; columns.len()]);
+        let meta = PageId::new(meta);
+        let (name, kind) = (name.to_string(), IndexKind::BTree);
+        IndexDef { name, columns, paths, collations, include, meta, constraint: None, kind }
+    }
+
+    fn orders(root: PageId) -> TableDef {
+        let columns = vec![
+            ColumnDef::new("id", Column::new(ColumnType::Int)),
+            ColumnDef::new("placed_at", Column::new(ColumnType::Int)),
+            ColumnDef::new("note", Column::nullable(ColumnType::Text)).with_default("'none'")
+                .with_collation(Collation::CaseInsensitive),
+        ];
+        let mut def = TableDef::new("orders", columns, TableKind::Clustered { primary_key: vec![0] }, root);
+        def.indexes.push(index("by_time", vec![1], vec![2], 9));
+        def
+    }
+
+    #[test]
+    fn test_definitions_survive_reopen() -> Result<(), CatalogError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut catalog = Catalog::create(&store, allocator)?;
+        catalog.create_table(orders(PageId::new(7)))?;
+
+        // Enough columns that the definition no longer fits in a cell.
+        let columns = (0..300).map(|i| ColumnDef::new(&format!("c{i}"), Column::nullable(ColumnType::Float))).collect();
+        let mut wide = TableDef::new("wide", columns, TableKind::Heap, PageId::new(8));
+        let path = JsonPath { steps: vec![Step::Key("a".to_string()), Step::Index(-1)], text: true };
+        let (paths, collations) = (vec![None, Some(path)], vec![Collation::Binary, Collation::CaseInsensitive]);
+        wide.indexes.push(IndexDef { paths, collations, ..index("by_path", vec![0, 299], vec![], 10) });
+        catalog.create_table(wide.clone())?;
+        wide.history.add_column(Column::new(ColumnType::Int), Value::Int(5)).unwrap();
+        wide.columns.push(ColumnDef::new("added", Column::new(ColumnType::Int)).with_default("5"));
+        catalog.alter_table(wide.clone())?;
+        catalog.rename_table("orders", "purchases")?;
+        let (constraint, kind) = (Some(Constraint::Unique), IndexKind::Hash);
+        let by_note = IndexDef { constraint, kind, ..index("by_note", vec![2], vec![], 11) };
+        catalog.add_index("purchases", by_note.clone())?;
+        assert_eq!(catalog.drop_index("purchases", "by_time")?.meta, PageId::new(9));
+        let trigger = |name: &str, timing| TriggerDef {
+            name: name.to_string(),
+            timing,
+            event: TriggerEvent::Delete,
+            body: "DELETE FROM notes WHERE note = old.note".to_string(),
+        };
+        catalog.add_trigger("purchases", trigger("purge", TriggerTiming::After))?;
+        catalog.add_trigger("purchases", trigger("audit", TriggerTiming::After))?;
+        let duplicate = catalog.add_trigger("purchases", trigger("purge", TriggerTiming::Before));
+        assert_eq!(duplicate, Err(CatalogError::DuplicateTrigger("purge".to_string())));
+        assert_eq!(catalog.drop_trigger("purchases", "audit")?.name, "audit");
+        assert_eq!(catalog.drop_trigger("purchases", "audit"), Err(CatalogError::NoSuchTrigger("audit".to_string())));
+
+        // A foreign key follows the table it references through a rename, and keeps it from being dropped.
+        let columns = vec![ColumnDef::new("note", Column::nullable(ColumnType::Text))];
+        let mut notes = TableDef::new("notes", columns, TableKind::Heap, PageId::new(12));
+        notes.indexes.push(index("notes_fkey", vec![0], vec![], 13));
+        let key = ForeignKey {
+            name: "notes_fkey".to_string(),
+            table: "orders".to_string(),
+            references: "by_note".to_string(),
+            on_delete: OnDelete::SetNull,
+        };
+        notes.foreign_keys.push(key);
+        assert_eq!(catalog.create_table(notes.clone()), Err(CatalogError::NoSuchTable("orders".to_string())));
+        notes.foreign_keys[0].table = "purchases".to_string();
+        catalog.create_table(notes.clone())?;
+        assert_eq!(catalog.drop_index("purchases", "by_note"), Err(CatalogError::Referenced("notes_fkey".to_string())));
+        catalog.rename_table("purchases", "orders")?;
+        assert_eq!(catalog.referencing("orders").map(|(def, _)| def.name.as_str()).collect::<Vec<_>>(), vec!["notes"]);
+        catalog.rename_table("orders", "purchases")?;
+        assert_eq!(catalog.drop_table("purchases"), Err(CatalogError::Referenced("notes_fkey".to_string())));
+
+        // A view keeps what it reads from being dropped or renamed, which includes other views.
+        let view = |name: &str, reads: &str| ViewDef {
+            name: name.to_string(),
+            columns: vec!["id".to_string()],
+            query: format!("SELECT id FROM {reads}"),
+            reads: vec![reads.to_string()],
+        };
+        catalog.create_view(view("recent", "purchases"))?;
+        let taken = catalog.create_table(TableDef::new("recent", vec![], TableKind::Heap, PageId::new(14)));
+        assert_eq!(taken, Err(CatalogError::DuplicateTable("recent".to_string())));
+        assert_eq!(catalog.rename_table("purchases", "orders"), Err(CatalogError::Referenced("recent".to_string())));
+        let missing = Err(CatalogError::NoSuchTable("missing".to_string()));
+        assert_eq!(catalog.create_view(view("broken", "missing")), missing);
+        catalog.create_view(view("nested", "recent"))?;
+        assert_eq!(catalog.drop_view("recent"), Err(CatalogError::Referenced("nested".to_string())));
+        catalog.drop_view("nested")?;
+
+        let catalog = Catalog::open(&store, allocator, catalog.root())?;
+        assert_eq!(catalog.views().cloned().collect::<Vec<_>>(), vec![view("recent", "purchases")]);
+        assert_eq!(catalog.table("notes"), Some(&notes));
+        let names: Vec<_> = catalog.tables().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["notes", "purchases", "wide"]);
+        assert_eq!(catalog.table("wide"), Some(&wide));
+        let purchases = catalog.table("purchases").unwrap();
+        assert_eq!(purchases.column("placed_at"), Some(1));
+        assert_eq!(purchases.schema().column(2), Column::nullable(ColumnType::Text));
+        assert_eq!(purchases.indexes, vec![by_note]);
+        assert_eq!(purchases.triggers, vec![trigger("purge", TriggerTiming::After)]);
+        assert_eq!(catalog.table("orders"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequences_follow_their_table() -> Result<(), CatalogError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut catalog = Catalog::create(&store, allocator)?;
+        catalog.create_table(orders(PageId::new(7)))?;
+        let sequence = |name: &str, owner: Option<&str>| SequenceDef {
+            name: name.to_string(),
+            increment: -3,
+            cache: 20,
+            next: 100,
+            owner: owner.map(str::to_string),
+        };
+        catalog.create_sequence(sequence("ids", None))?;
+        catalog.create_sequence(sequence("orders_id_seq", Some("orders")))?;
+        let taken = Err(CatalogError::DuplicateTable("orders".to_string()));
+        assert_eq!(catalog.create_sequence(sequence("orders", None)), taken);
+        let missing = Err(CatalogError::NoSuchTable("missing".to_string()));
+        assert_eq!(catalog.create_sequence(sequence("other", Some("missing"))), missing);
+        catalog.advance_sequence("ids", 40)?;
+        catalog.rename_table("orders", "purchases")?;
+        let owned = Err(CatalogError::Referenced("purchases".to_string()));
+        assert_eq!(catalog.drop_sequence("orders_id_seq"), owned);
+
+        let mut catalog = Catalog::open(&store, allocator, catalog.root())?;
+        let ids = SequenceDef { next: 40, ..sequence("ids", None) };
+        let expected = vec![ids.clone(), sequence("orders_id_seq", Some("purchases"))];
+        assert_eq!(catalog.sequences().cloned().collect::<Vec<_>>(), expected);
+        catalog.drop_table("purchases")?;
+        assert_eq!(catalog.sequences().cloned().collect::<Vec<_>>(), vec![ids.clone()]);
+        assert_eq!(catalog.drop_sequence("ids")?, ids);
+        assert_eq!(Catalog::open(&store, allocator, catalog.root())?.sequences().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttls_and_partitioning_survive_reopen() -> Result<(), CatalogError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut catalog = Catalog::create(&store, allocator)?;
+        let ttl = |column, lifetime| Some(Ttl { column, lifetime });
+        catalog.create_table(TableDef { ttl: ttl(1, Value::Int(3600)), ..orders(PageId::new(7)) })?;
+        let columns = vec![ColumnDef::new("seen_at", Column::nullable(ColumnType::TimestampTz))];
+        let mut visits = TableDef::new("visits", columns, TableKind::Heap, PageId::new(8));
+        visits.ttl = ttl(0, Value::Interval(Interval { months: 1, micros: -5 }));
+        catalog.create_table(visits.clone())?;
+
+        let mut bad = TableDef { name: "bad".to_string(), ..visits.clone() };
+        bad.ttl = ttl(0, Value::Int(3600));
+        assert_eq!(catalog.create_table(bad.clone()), Err(CatalogError::TtlMismatch));
+        bad.ttl = ttl(1, Value::Interval(Interval::default()));
+        assert_eq!(catalog.create_table(bad), Err(CatalogError::NoSuchColumn(1)));
+        let mut untyped = orders(PageId::new(7));
+        untyped.ttl = ttl(2, Value::Int(3600));
+        assert_eq!(catalog.alter_table(untyped), Err(CatalogError::TtlMismatch));
+
+        let columns = vec![
+            ColumnDef::new("day", Column::new(ColumnType::Int)),
+            ColumnDef::new("note", Column::nullable(ColumnType::Text)),
+        ];
+        let partitioned = |name, by, root| {
+            TableDef::new(name, columns.clone(), TableKind::Partitioned { by }, PageId::new(root))
+        };
+        let mut days = partitioned("days", PartitionBy::Range(0), 9);
+        days.ttl = ttl(0, Value::Int(86_400));
+        catalog.create_table(days.clone())?;
+        let notes = partitioned("notes", PartitionBy::Hash { column: 1, partitions: 8 }, 10);
+        catalog.create_table(notes.clone())?;
+        let bad = partitioned("bad", PartitionBy::Range(2), 11);
+        assert_eq!(catalog.create_table(bad), Err(CatalogError::NoSuchColumn(2)));
+
+        let catalog = Catalog::open(&store, allocator, catalog.root())?;
+        assert_eq!(catalog.table("orders").and_then(|d| d.ttl.clone()), ttl(1, Value::Int(3600)));
+        assert_eq!(catalog.table("visits"), Some(&visits));
+        assert_eq!((catalog.table("days"), catalog.table("notes")), (Some(&days), Some(&notes)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyspaces_share_names_with_tables() -> Result<(), CatalogError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut catalog = Catalog::create(&store, allocator)?;
+        catalog.create_table(orders(PageId::new(7)))?;
+        let keyspace = |name: &str, meta| KeyspaceDef {
+            name: name.to_string(),
+            meta: PageId::new(meta),
+            options: KeyspaceOptions::default(),
+        };
+        catalog.create_keyspace(keyspace("sessions", 9))?;
+        let art = LsmOptions { memtable_index: MemtableIndex::Art, ..LsmOptions::default() };
+        let lsm = KeyspaceOptions { lsm: Some(art) };
+        catalog.create_keyspace(KeyspaceDef { options: lsm, ..keyspace("events", 12) })?;
+        let taken = Err(CatalogError::DuplicateTable("orders".to_string()));
+        assert_eq!(catalog.create_keyspace(keyspace("orders", 10)), taken);
+        let sessions = TableDef { name: "sessions".to_string(), ..orders(PageId::new(11)) };
+        assert_eq!(catalog.create_table(sessions), Err(CatalogError::DuplicateTable("sessions".to_string())));
+
+        let mut catalog = Catalog::open(&store, allocator, catalog.root())?;
+        let events = KeyspaceDef { options: lsm, ..keyspace("events", 12) };
+        assert_eq!(catalog.keyspaces().cloned().collect::<Vec<_>>(), vec![events, keyspace("sessions", 9)]);
+        assert_eq!(catalog.drop_keyspace("sessions")?, keyspace("sessions", 9));
+        assert_eq!(catalog.drop_keyspace("sessions"), Err(CatalogError::NoSuchTable("sessions".to_string())));
+        assert_eq!(Catalog::open(&store, allocator, catalog.root())?.keyspace("sessions"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_ddl_changes_nothing() -> Result<(), CatalogError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut catalog = Catalog::create(&store, allocator)?;
+        catalog.create_table(orders(PageId::new(7)))?;
+
+        assert_eq!(catalog.create_table(orders(PageId::new(8))), Err(CatalogError::DuplicateTable("orders".to_string())));
+        let mut bad = orders(PageId::new(8));
+        bad.name = "bad".to_string();
+        bad.kind = TableKind::TimeSeries { timestamp: 3 };
+        assert_eq!(catalog.create_table(bad), Err(CatalogError::NoSuchColumn(3)));
+        let duplicate = catalog.add_index("orders", index("by_time", vec![0], vec![], 12));
+        assert_eq!(duplicate, Err(CatalogError::DuplicateIndex("by_time".to_string())));
+        let primary_key = |name, columns| IndexDef { constraint: Some(Constraint::PrimaryKey), ..index(name, columns, vec![], 12) };
+        catalog.add_index("orders", primary_key("orders_pkey", vec![0]))?;
+        assert_eq!(catalog.add_index("orders", primary_key("other_pkey", vec![1])), Err(CatalogError::DuplicatePrimaryKey));
+        catalog.drop_index("orders", "orders_pkey")?;
+        assert_eq!(catalog.drop_table("bad"), Err(CatalogError::NoSuchTable("bad".to_string())));
+
+        let catalog = Catalog::open(&store, allocator, catalog.root())?;
+        assert_eq!(catalog.tables().cloned().collect::<Vec<_>>(), vec![orders(PageId::new(7))]);
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Collations: the orders text is compared and sorted in.
+//!
+//! `Binary` compares text by its UTF-8 bytes, which is the order of its code points. `CaseInsensitive`
+//! compares the lowercase forms of texts, so that two texts differing only in case are equal. `Unicode`,
+//! with the `unicode-collation` feature, is the Unicode Collation Algorithm's three levels cut down to
+//! Latin letters: texts compare by their letters first, with accents and case ignored, then by accents, then
+//! by case with lowercase first, and last by code points, so that only identical texts are equal. A letter
+//! with an accent is its base letter at the first level, and a ligature such as `æ` or `ß` the letters it
+//! joins; other letters follow `z` in code point order, with digits before letters and spaces,
+//! punctuation and symbols before digits. Control characters are ignored until the last level.
+//!
+//! Each collation gives text a sort key: bytes whose order is the collation's order and which are equal
+//! exactly when the texts are. Indexes key collated text on its sort key; comparisons and sorts compare
+//! sort keys, which keeps them in the same order as the indexes.
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Collation {
+    Binary,
+    CaseInsensitive,
+    #[cfg(feature = "unicode-collation")]
+    Unicode,
+}
+impl Collation {
+    /// The collation SQL calls `name`: `binary`, `nocase`, or with the `unicode-collation` feature,
+    /// `unicode`, in any case.
+    pub fn named(name: &str) -> Option<Collation> {
+        match name.to_ascii_lowercase().as_str() {
+            "binary" => Some(Collation::Binary),
+            "nocase" => Some(Collation::CaseInsensitive),
+            #[cfg(feature = "unicode-collation")]
+            "unicode" => Some(Collation::Unicode),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Collation::Binary => "binary",
+            Collation::CaseInsensitive => "nocase",
+            #[cfg(feature = "unicode-collation")]
+            Collation::Unicode => "unicode",
+        }
+    }
+
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            _ => self.sort_key(a).cmp(&self.sort_key(b)),
+        }
+    }
+
+    /// Bytes that order as `text` does under the collation.
+    pub fn sort_key(self, text: &str) -> Vec<u8> {
+        match self {
+            Collation::Binary => text.as_bytes().to_vec(),
+            Collation::CaseInsensitive => text.to_lowercase().into_bytes(),
+            #[cfg(feature = "unicode-collation")]
+            Collation::Unicode => unicode::sort_key(text),
+        }
+    }
+
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Collation::Binary => 0,
+            Collation::CaseInsensitive => 1,
+            #[cfg(feature = "unicode-collation")]
+            Collation::Unicode => 2,
+        }
+    }
+
+    /// The collation `tag` gives, if this build has it.
+    pub(crate) fn from_tag(tag: u8) -> Option<Collation> {
+        match tag {
+            0 => Some(Collation::Binary),
+            1 => Some(Collation::CaseInsensitive),
+            #[cfg(feature = "unicode-collation")]
+            2 => Some(Collation::Unicode),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "unicode-collation")]
+mod unicode {
+    /// Latin letters with accents, and ligatures, as their base letters and their accent: a position in
+    /// `ACCENTS` counting from 1, or 0 for a ligature. Sorted by letter.
+    const DECOMPOSED: [(char, &str, u8); 182] = [
+    ('À', "a", 2), ('Á', "a", 1), ('Â', "a", 4), ('Ã', "a", 9), ('Ä', "a", 7), ('Å', "a", 6), ('Æ', "ae", 0),
+    ('Ç', "c", 11), ('È', "e", 2), ('É', "e", 1), ('Ê', "e", 4), ('Ë', "e", 7), ('Ì', "i", 2), ('Í', "i", 1),
+    ('Î', "i", 4), ('Ï', "i", 7), ('Ð', "d", 14), ('Ñ', "n", 9), ('Ò', "o", 2), ('Ó', "o", 1), ('Ô', "o", 4),
+    ('Õ', "o", 9), ('Ö', "o", 7), ('Ø', "o", 14), ('Ù', "u", 2), ('Ú', "u", 1), ('Û', "u", 4), ('Ü', "u", 7),
+    ('Ý', "y", 1), ('ß', "ss", 0), ('à', "a", 2), ('á', "a", 1), ('â', "a", 4), ('ã', "a", 9), ('ä', "a", 7),
+    ('å', "a", 6), ('æ', "ae", 0), ('ç', "c", 11), ('è', "e", 2), ('é', "e", 1), ('ê', "e", 4), ('ë', "e", 7),
+    ('ì', "i", 2), ('í', "i", 1), ('î', "i", 4), ('ï', "i", 7), ('ð', "d", 14), ('ñ', "n", 9), ('ò', "o", 2),
+    ('ó', "o", 1), ('ô', "o", 4), ('õ', "o", 9), ('ö', "o", 7), ('ø', "o", 14), ('ù', "u", 2), ('ú', "u", 1),
+    ('û', "u", 4), ('ü', "u", 7), ('ý', "y", 1), ('ÿ', "y", 7), ('Ā', "a", 13), ('ā', "a", 13), ('Ă', "a", 3),
+    ('ă', "a", 3), ('Ą', "a", 12), ('ą', "a", 12), ('Ć', "c", 1), ('ć', "c", 1), ('Ĉ', "c", 4), ('ĉ', "c", 4),
+    ('Ċ', "c", 10), ('ċ', "c", 10), ('Č', "c", 5), ('č', "c", 5), ('Ď', "d", 5), ('ď', "d", 5), ('Đ', "d", 14),
+    ('đ', "d", 14), ('Ē', "e", 13), ('ē', "e", 13), ('Ĕ', "e", 3), ('ĕ', "e", 3), ('Ė', "e", 10), ('ė', "e", 10),
+    ('Ę', "e", 12), ('ę', "e", 12), ('Ě', "e", 5), ('ě', "e", 5), ('Ĝ', "g", 4), ('ĝ', "g", 4), ('Ğ', "g", 3),
+    ('ğ', "g", 3), ('Ġ', "g", 10), ('ġ', "g", 10), ('Ģ', "g", 11), ('ģ', "g", 11), ('Ĥ', "h", 4), ('ĥ', "h", 4),
+    ('Ħ', "h", 14), ('ħ', "h", 14), ('Ĩ', "i", 9), ('ĩ', "i", 9), ('Ī', "i", 13), ('ī', "i", 13), ('Ĭ', "i", 3),
+    ('ĭ', "i", 3), ('Į', "i", 12), ('į', "i", 12), ('İ', "i", 10), ('ı', "i", 15), ('Ĵ', "j", 4), ('ĵ', "j", 4),
+    ('Ķ', "k", 11), ('ķ', "k", 11), ('Ĺ', "l", 1), ('ĺ', "l", 1), ('Ļ', "l", 11), ('ļ', "l", 11), ('Ľ', "l", 5),
+    ('ľ', "l", 5), ('Ŀ', "l", 9), ('ŀ', "l", 9), ('Ł', "l", 14), ('ł', "l", 14), ('Ń', "n", 1), ('ń', "n", 1),
+    ('Ņ', "n", 11), ('ņ', "n", 11), ('Ň', "n", 5), ('ň', "n", 5), ('Ō', "o", 13), ('ō', "o", 13), ('Ŏ', "o", 3),
+    ('ŏ', "o", 3), ('Ő', "o", 8), ('ő', "o", 8), ('Œ', "oe", 0), ('œ', "oe", 0), ('Ŕ', "r", 1), ('ŕ', "r", 1),
+    ('Ŗ', "r", 11), ('ŗ', "r", 11), ('Ř', "r", 5), ('ř', "r", 5), ('Ś', "s", 1), ('ś', "s", 1), ('Ŝ', "s", 4),
+    ('ŝ', "s", 4), ('Ş', "s", 11), ('ş', "s", 11), ('Š', "s", 5), ('š', "s", 5), ('Ţ', "t", 11), ('ţ', "t", 11),
+    ('Ť', "t", 5), ('ť', "t", 5), ('Ŧ', "t", 14), ('ŧ', "t", 14), ('Ũ', "u", 9), ('ũ', "u", 9), ('Ū', "u", 13),
+    ('ū', "u", 13), ('Ŭ', "u", 3), ('ŭ', "u", 3), ('Ů', "u", 6), ('ů', "u", 6), ('Ű', "u", 8), ('ű', "u", 8),
+    ('Ų', "u", 12), ('ų', "u", 12), ('Ŵ', "w", 4), ('ŵ', "w", 4), ('Ŷ', "y", 4), ('ŷ', "y", 4), ('Ÿ', "y", 7),
+    ('Ź', "z", 1), ('ź', "z", 1), ('Ż', "z", 10), ('ż', "z", 10), ('Ž', "z", 5), ('ž', "z", 5), ('ſ', "s", 0),
+    ];
+
+    /// The order of accents at the second level, after no accent at all: acute, grave, breve, circumflex,
+    /// caron, ring, diaeresis, double acute, tilde, dot, cedilla, ogonek, macron, stroke and dotless.
+    const ACCENTS: u8 = 15;
+
+    /// The first-level weights of spaces, punctuation and symbols, of digits, and of letters, as the top
+    /// bits of a 24-bit weight.
+    const SYMBOL: u32 = 1 << 21;
+    const DIGIT: u32 = 2 << 21;
+    const LETTER: u32 = 3 << 21;
+
+    pub(super) fn sort_key(text: &str) -> Vec<u8> {
+        let (mut primary, mut secondary, mut tertiary) = (Vec::new(), Vec::new(), Vec::new());
+        for c in text.chars().filter(|c| !c.is_control()) {
+            let upper = c.is_uppercase() as u8;
+            let (base, accent) = match DECOMPOSED.binary_search_by_key(&c, |(c, _, _)| *c) {
+                Ok(i) => (DECOMPOSED[i].1.to_string(), DECOMPOSED[i].2),
+                Err(_) => (c.to_lowercase().collect(), 0),
+            };
+            debug_assert!(accent <= ACCENTS);
+            for letter in base.chars() {
+                primary.extend_from_slice(&weight(letter).to_be_bytes()[1..]);
+                secondary.push(accent + 1);
+                tertiary.push(upper + 1);
+            }
+        }
+        // Every weight is above zero, so a zero ends each level before anything the next text has there.
+        let mut key = primary;
+        key.extend_from_slice(&[0, 0, 0]);
+        key.extend(secondary);
+        key.push(0);
+        key.extend(tertiary);
+        key.push(0);
+        key.extend_from_slice(text.as_bytes());
+        key
+    }
+
+    fn weight(c: char) -> u32 {
+        match c {
+            'a'..='z' => LETTER + (c as u32 - 'a' as u32),
+            c if c.is_alphabetic() => LETTER + 26 + c as u32,
+            '0'..='9' => DIGIT + (c as u32 - '0' as u32),
+            c if c.is_numeric() => DIGIT + 10 + c as u32,
+            c => SYMBOL + c as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::Collation;
+
+    fn sorted(collation: Collation, texts: &[&str]) -> Vec<String> {
+        let mut texts: Vec<String> = texts.iter().map(|s| s.to_string()).collect();
+        texts.sort_by(|a, b| collation.compare(a, b));
+        texts
+    }
+
+    #[test]
+    fn test_binary_and_case_insensitive() {
+        let texts = ["b", "B", "a", "Ab", "ab", "é"];
+        assert_eq!(sorted(Collation::Binary, &texts), ["Ab", "B", "a", "ab", "b", "é"]);
+        assert_eq!(sorted(Collation::CaseInsensitive, &texts), ["a", "Ab", "ab", "b", "B", "é"]);
+        assert_eq!(Collation::CaseInsensitive.compare("ÉCOLE", "école"), Ordering::Equal);
+        assert_eq!(Collation::CaseInsensitive.sort_key("ABC"), Collation::CaseInsensitive.sort_key("abc"));
+        for collation in [Collation::Binary, Collation::CaseInsensitive] {
+            assert_eq!(Collation::named(&collation.name().to_uppercase()), Some(collation));
+            assert_eq!(Collation::from_tag(collation.tag()), Some(collation));
+        }
+        assert_eq!(Collation::named("klingon"), None);
+    }
+
+    #[cfg(feature = "unicode-collation")]
+    #[test]
+    fn test_unicode() {
+        let texts = ["cote", "Côte", "côte", "coté", "côté", "Cote", "cotes", "co-op", "coop", "co2", "Zoë", "æon"];
+        assert_eq!(sorted(Collation::Unicode, &texts), [
+            "æon", "co-op", "co2", "coop", "cote", "Cote", "coté", "côte", "Côte", "côté", "cotes", "Zoë",
+        ]);
+        assert_eq!(sorted(Collation::Unicode, &["straße", "strasse", "strasbourg"]), [
+            "strasbourg", "strasse", "straße",
+        ]);
+        assert_ne!(Collation::Unicode.compare("a\u{1}", "a"), Ordering::Equal);
+        assert_eq!(Collation::named("Unicode"), Some(Collation::Unicode));
+    }
+}
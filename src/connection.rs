@@ -0,0 +1,419 @@
+//! Connections: one handle owning everything a database needs, from the storage up.
+//!
+//! A `Database` borrows the `PageStore` it lives in, which owns the storage, so using one means building
+//! each of those by hand and keeping the store alive for as long as the database. A `Connection` owns the
+//! whole stack: the file it was opened on, or memory, wrapped in a `ShadowStorage`, the page store over
+//! that, and the database over the store, made when the storage has never been committed to and opened
+//! otherwise.
+//!
+//! Durability is the shadow paging's: nothing written reaches the committed generation until the store
+//! is flushed. `execute` flushes after each statement that succeeds, so each commits on its own; writes
+//! made some other way, such as to a keyspace or to a table `database` opens, commit with the next
+//! `commit`, all of them together. A statement that fails is rolled back: the generation it wrote to is
+//! discarded, along with anything else written since the last commit, and the database reads its catalog
+//! again. `rollback` does the same on demand. Dropping a connection commits nothing; `close` commits
+//! first.
+//!
+//! A connection opened on a file keeps the pages its queries spill in a `TempFile` of its own, in the
+//! directory `ConnectionOptions::temp_dir` names, so a query past its memory limit spills to disk rather
+//! than to more memory. The file goes with the connection. One in memory spills to memory.
+//!
+//! After committing a statement, `execute` deletes a batch of the rows that have expired under their tables'
+//! TTLs, as `Database::expire` does, and commits that too, at most once every
+//! `ConnectionOptions::expiry_interval`; `expire` deletes a batch on demand. A batch that fails is rolled
+//! back on its own, leaving the statement committed and the rows for a later batch.
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::time::{Duration, Instant};
+
+use crate::csv::CsvOptions;
+use crate::database::{Database, DatabaseError, QueryResult, Statement};
+use crate::exec;
+use crate::kv::{Keyspace, KeyspaceOptions};
+use crate::page_store::{PageError, PageStore};
+use crate::shadow::ShadowStorage;
+use crate::storage::{FileStorage, MemoryStorage, Storage};
+use crate::temp::TempFile;
+use crate::tuple::Value;
+
+/// What a connection's database is kept in: a file or memory, under shadow paging.
+pub type ConnectionStorage = ShadowStorage<Box<dyn Storage>>;
+
+/// The most expired rows `execute` deletes after a statement.
+const EXPIRE_BATCH: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    /// Make the file if there is none, rather than failing to open it.
+    pub create: bool,
+    /// The directory to make the file of temporary pages in, the system's temporary directory if none.
+    pub temp_dir: Option<PathBuf>,
+    /// The most rounds a recursive common table expression may run.
+    pub recursion_limit: usize,
+    /// The most bytes the operators of one query may hold at once.
+    pub memory_limit: usize,
+    /// The most workers one query may run parts of its plan on at once.
+    pub max_parallel_workers: usize,
+    /// The least time between two batches of expired rows deleted after statements.
+    pub expiry_interval: Duration,
+}
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            create: true,
+            temp_dir: None,
+            recursion_limit: exec::DEFAULT_RECURSION_LIMIT,
+            memory_limit: exec::DEFAULT_QUERY_MEMORY_BYTES,
+            max_parallel_workers: exec::DEFAULT_MAX_PARALLEL_WORKERS,
+            expiry_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+pub struct Connection {
+    /// Borrows the store, so is dropped before it.
+    db: Option<Database<'static, ConnectionStorage>>,
+    store: NonNull<PageStore<ConnectionStorage>>,
+    expiry_interval: Duration,
+    /// When `execute` last deleted a batch of expired rows, or the connection was opened.
+    last_expiry: Instant,
+}
+impl Connection {
+    /// Open the database in the file at `path`, or make one there if it holds none yet. Its queries spill to
+    /// a temporary file in `options.temp_dir`, deleted when the connection is dropped.
+    pub fn open(path: impl AsRef<Path>, options: &ConnectionOptions) -> Result<Connection, DatabaseError> {
+        let file = FileStorage::open(path.as_ref(), options.create).map_err(PageError::Storage)?;
+        let dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+        let temp = TempFile::create(&dir).map_err(PageError::Storage)?;
+        Connection::with_storage(Box::new(file), Box::new(temp), options)
+    }
+
+    /// A database in memory, lost when the connection is closed, whose queries spill to memory too.
+    pub fn open_in_memory() -> Result<Connection, DatabaseError> {
+        let storage = Box::new(MemoryStorage::new());
+        Connection::with_storage(storage, Box::new(MemoryStorage::new()), &ConnectionOptions::default())
+    }
+
+    fn with_storage(
+        storage: Box<dyn Storage>,
+        temp: Box<dyn Storage>,
+        options: &ConnectionOptions,
+    ) -> Result<Connection, DatabaseError> {
+        let storage = ShadowStorage::open(storage).map_err(PageError::Storage)?;
+        let committed = storage.generation() > 0;
+        let mut connection = Connection {
+            db: None,
+            store: NonNull::from(Box::leak(Box::new(PageStore::new(storage)))),
+            expiry_interval: options.expiry_interval,
+            last_expiry: Instant::now(),
+        };
+        // SAFETY: the store is only freed when the connection is dropped, after the database borrowing it,
+        // and the database is only handed out under a borrow of the connection.
+        let store = unsafe { connection.store.as_ref() };
+        let mut db = if committed { Database::open(store)? } else { Database::create(store)? };
+        db.set_recursion_limit(options.recursion_limit);
+        db.set_memory_limit(options.memory_limit);
+        db.set_max_parallel_workers(options.max_parallel_workers);
+        db.set_temp_storage(temp);
+        connection.db = Some(db);
+        Ok(connection)
+    }
+
+    /// The database, to read through or to run a prepared statement over.
+    pub fn database(&self) -> &Database<'_, ConnectionStorage> {
+        self.db.as_ref().unwrap()
+    }
+
+    /// Run the statement in `sql`, as `Database::execute` does, and commit what it wrote.
+    pub fn execute(&mut self, sql: &str) -> Result<u64, DatabaseError> {
+        self.execute_with(sql, &[])
+    }
+
+    /// Run the statement in `sql` with `params` as the values of its bind parameters, as
+    /// `Database::execute_with` does, and commit what it wrote. Then, if `expiry_interval` has passed
+    /// since the last time, delete a batch of expired rows and commit that.
+    pub fn execute_with(&mut self, sql: &str, params: &[Value]) -> Result<u64, DatabaseError> {
+        let db = self.db.as_mut().unwrap();
+        match db.execute_with(sql, params) {
+            Ok(count) => {
+                db.store().flush()?;
+                if self.last_expiry.elapsed() >= self.expiry_interval {
+                    self.last_expiry = Instant::now();
+                    // The statement has committed; a batch that fails is rolled back, for `expire` to report.
+                    let _ = self.expire(EXPIRE_BATCH);
+                }
+                Ok(count)
+            }
+            Err(e) => {
+                self.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Collect the statistics the planner estimates from over the table called `name`, and commit them.
+    pub fn analyze(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let db = self.db.as_mut().unwrap();
+        db.analyze(name)?;
+        Ok(db.store().flush()?)
+    }
+
+    /// Delete at most `limit` expired rows, as `Database::expire` does, and commit that, returning how many
+    /// were deleted. A batch that fails is rolled back.
+    pub fn expire(&mut self, limit: usize) -> Result<u64, DatabaseError> {
+        let db = self.db.as_mut().unwrap();
+        match db.expire(limit) {
+            Ok(count) => {
+                db.store().flush()?;
+                Ok(count)
+            }
+            Err(e) => {
+                self.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run the `SELECT` or `EXPLAIN` in `sql`, as `Database::query` does.
+    pub fn query(&self, sql: &str, params: &[Value]) -> Result<QueryResult, DatabaseError> {
+        self.database().query(sql, params)
+    }
+
+    /// Parse and plan the `SELECT` in `sql`, for a statement to run over `database`.
+    pub fn prepare(&self, sql: &str) -> Result<Statement, DatabaseError> {
+        self.database().prepare(sql)
+    }
+
+    /// Copy the CSV `input` into the table called `table`, as `Database::copy_from_csv` does, committing
+    /// each batch of rows as it is written. A copy that fails rolls back what it wrote after its last batch.
+    pub fn copy_from_csv(&mut self, table: &str, input: impl Read, options: &CsvOptions) -> Result<u64, DatabaseError> {
+        let copied = self.db.as_mut().unwrap().copy_from_csv(table, input, options);
+        if copied.is_err() {
+            self.rollback()?;
+        }
+        copied
+    }
+
+    /// Write the rows of the table called `table` to `out` as CSV, as `Database::copy_to_csv` does.
+    pub fn copy_to_csv(&self, table: &str, out: impl Write, options: &CsvOptions) -> Result<u64, DatabaseError> {
+        self.database().copy_to_csv(table, out, options)
+    }
+
+    /// Create an empty keyspace called `name`, committing it.
+    pub fn create_keyspace(&mut self, name: &str) -> Result<(), DatabaseError> {
+        self.create_keyspace_with(name, &KeyspaceOptions::default())
+    }
+
+    /// Create an empty keyspace called `name`, kept as `options` asks, committing it.
+    pub fn create_keyspace_with(&mut self, name: &str, options: &KeyspaceOptions) -> Result<(), DatabaseError> {
+        self.db.as_mut().unwrap().create_keyspace_with(name, options).map(drop)
+    }
+
+    /// The keyspace called `name`, whose writes commit with the next `commit`.
+    pub fn keyspace(&self, name: &str) -> Result<Keyspace<'_, ConnectionStorage>, DatabaseError> {
+        self.database().open_keyspace(name)
+    }
+
+    /// Drop the keyspace called `name`, committing that.
+    pub fn drop_keyspace(&mut self, name: &str) -> Result<(), DatabaseError> {
+        self.db.as_mut().unwrap().drop_keyspace(name)
+    }
+
+    /// Commit whatever has been written since the last commit.
+    pub fn commit(&self) -> Result<(), DatabaseError> {
+        Ok(self.database().store().flush()?)
+    }
+
+    /// Discard whatever has been written since the last commit.
+    pub fn rollback(&mut self) -> Result<(), DatabaseError> {
+        let db = self.db.as_mut().unwrap();
+        db.store().discard(|storage| storage.rollback())?;
+        db.reload()
+    }
+
+    /// Commit, and close the connection.
+    pub fn close(self) -> Result<(), DatabaseError> {
+        self.commit()
+    }
+}
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.db = None;
+        // SAFETY: leaked by `with_storage`, and nothing borrows it now that the database is gone.
+        unsafe { drop(Box::from_raw(self.store.as_ptr())) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::database::DatabaseError;
+    use crate::kv::KeyspaceOptions;
+    use crate::lsm::LsmOptions;
+    use crate::page_store::PageError;
+    use crate::storage::StorageError;
+    use crate::tuple::Value;
+
+    use super::{Connection, ConnectionOptions};
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("purpledb-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_connections_reopen_what_they_committed() -> Result<(), DatabaseError> {
+        let path = path("reopen");
+        let options = ConnectionOptions::default();
+        let mut connection = Connection::open(&path, &options)?;
+        connection.execute("CREATE TABLE t (a INT PRIMARY KEY, b TEXT)")?;
+        assert_eq!(connection.execute("INSERT INTO t VALUES (1, 'x'), (2, 'y')")?, 2);
+        connection.close()?;
+
+        let mut connection = Connection::open(&path, &ConnectionOptions { create: false, ..options.clone() })?;
+        connection.execute("UPDATE t SET b = 'z' WHERE a = 2")?;
+        let mut statement = connection.prepare("SELECT b FROM t WHERE a = ?")?;
+        let rows = statement.query(connection.database(), &[Value::Int(2)])?.into_result()?.rows;
+        assert_eq!(rows, vec![vec![Value::Text("z".to_string())]]);
+        // Dropped rather than closed, the connection has still committed each statement.
+        drop(connection);
+
+        let connection = Connection::open(&path, &options)?;
+        let rows = connection.query("SELECT a, b FROM t ORDER BY a", &[])?.rows;
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(rows, vec![vec![Value::Int(1), text("x")], vec![Value::Int(2), text("z")]]);
+        connection.close()?;
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = Connection::open(&path, &ConnectionOptions { create: false, ..options }).err();
+        let not_found = DatabaseError::Page(PageError::Storage(StorageError::Io(std::io::ErrorKind::NotFound)));
+        assert_eq!(missing, Some(not_found));
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyspaces_beside_tables() -> Result<(), DatabaseError> {
+        let path = path("keyspaces");
+        let mut connection = Connection::open(&path, &ConnectionOptions::default())?;
+        connection.execute("CREATE TABLE t (a INT)")?;
+        connection.create_keyspace("kv")?;
+        connection.create_keyspace_with("events", &KeyspaceOptions { lsm: Some(LsmOptions::default()) })?;
+        let mut kv = connection.keyspace("kv")?;
+        kv.put(b"b", b"2")?;
+        kv.put(b"a", b"1")?;
+        connection.keyspace("events")?.put(b"e", b"1")?;
+        connection.commit()?;
+        // Written after the last commit, and so lost with the connection.
+        kv.put(b"c", b"3")?;
+        drop(connection);
+
+        let mut connection = Connection::open(&path, &ConnectionOptions::default())?;
+        let kv = connection.keyspace("kv")?;
+        let entries = kv.iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries, vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())]);
+        assert_eq!(kv.get(b"c")?, None);
+        assert_eq!(connection.keyspace("events")?.get(b"e")?, Some(b"1".to_vec()));
+        assert_eq!(connection.query("SELECT count(*) FROM t", &[])?.rows, vec![vec![Value::Int(0)]]);
+        connection.drop_keyspace("kv")?;
+        connection.drop_keyspace("events")?;
+        assert!(connection.keyspace("kv").is_err());
+        connection.close()?;
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_statements_roll_back() -> Result<(), DatabaseError> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.execute("CREATE TABLE t (a TEXT PRIMARY KEY, b INT)")?;
+        connection.execute("INSERT INTO t VALUES ('x', 1)")?;
+        connection.execute("CREATE TABLE log (n INT)")?;
+        connection.execute(
+            "CREATE TRIGGER again AFTER INSERT ON log BEGIN INSERT INTO log VALUES (new.n + 1); END",
+        )?;
+        let depth = connection.execute("INSERT INTO log VALUES (0)");
+        assert_eq!(depth, Err(DatabaseError::TriggerDepth("again".to_string())));
+        assert_eq!(connection.query("SELECT count(*) FROM log", &[])?.rows, vec![vec![Value::Int(0)]]);
+        let long = format!("INSERT INTO t VALUES ('y', 2), ('{}', 3)", "z".repeat(3000));
+        assert!(connection.execute(&long).is_err());
+        assert!(connection.execute("INSERT INTO t VALUES ('w', 4), ('x', 5)").is_err());
+
+        // Uncommitted writes go with a failed statement; the database goes on from the last commit.
+        connection.create_keyspace("kv")?;
+        connection.keyspace("kv")?.put(b"k", b"v")?;
+        assert!(connection.execute("SELECT nope FROM t").is_err());
+        assert_eq!(connection.keyspace("kv")?.get(b"k")?, None);
+        connection.execute("INSERT INTO t VALUES ('v', 6)")?;
+        let rows = connection.query("SELECT a, b FROM t ORDER BY a", &[])?.rows;
+        let row = |a: &str, b| vec![Value::Text(a.to_string()), Value::Int(b)];
+        assert_eq!(rows, vec![row("v", 6), row("x", 1)]);
+
+        connection.analyze("t")?;
+        assert_eq!(connection.database().catalog().table("t").unwrap().stats.as_ref().map(|s| s.row_count), Some(2));
+        connection.close()
+    }
+
+    #[test]
+    fn test_temp_files_go_with_the_connection() -> Result<(), DatabaseError> {
+        let dir = path("temp-dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        let temp_files = || std::fs::read_dir(&dir).unwrap().count();
+        let options = ConnectionOptions { temp_dir: Some(dir.clone()), ..ConnectionOptions::default() };
+        let path = path("temp");
+        let connection = Connection::open(&path, &options)?;
+        let other = Connection::open(path.with_extension("other"), &options)?;
+        assert_eq!(temp_files(), 2);
+        connection.close()?;
+        drop(other);
+        assert_eq!(temp_files(), 0);
+
+        let missing = ConnectionOptions { temp_dir: Some(dir.join("missing")), ..options };
+        assert!(Connection::open(&path, &missing).is_err());
+        std::fs::remove_dir(&dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("other")).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_statements_expire_rows() -> Result<(), DatabaseError> {
+        let path = path("expire");
+        let options = ConnectionOptions { expiry_interval: Duration::ZERO, ..ConnectionOptions::default() };
+        let mut connection = Connection::open(&path, &options)?;
+        connection.execute("CREATE TABLE users (id INT PRIMARY KEY, joined INT) TTL joined + 60")?;
+        connection.execute("CREATE TABLE visits (user_id INT REFERENCES users)")?;
+        // Each statement is followed by a batch of expired rows, here the first row it inserted.
+        connection.execute("INSERT INTO users VALUES (1, 0), (2, 4000000000)")?;
+        let users = |c: &Connection| c.query("SELECT id, joined FROM users", &[]).map(|r| r.rows);
+        assert_eq!(users(&connection)?, vec![vec![Value::Int(2), Value::Int(4_000_000_000)]]);
+
+        // A batch that fails is rolled back, leaving the statement before it committed.
+        connection.execute("INSERT INTO visits VALUES (2)")?;
+        connection.execute("UPDATE users SET joined = 0")?;
+        assert_eq!(users(&connection)?, vec![vec![Value::Int(2), Value::Int(0)]]);
+        let key = vec![Value::Int(2)];
+        let violation = DatabaseError::ForeignKeyViolation { constraint: "visits_user_id_fkey".to_string(), key };
+        assert_eq!(connection.expire(10), Err(violation));
+        connection.execute("DELETE FROM visits")?;
+        drop(connection);
+
+        let connection = Connection::open(&path, &options)?;
+        assert_eq!(users(&connection)?, Vec::<Vec<Value>>::new());
+        connection.close()?;
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_connections() -> Result<(), DatabaseError> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.execute("CREATE TABLE t (a INT)")?;
+        connection.execute("INSERT INTO t VALUES (1), (2), (3)")?;
+        assert_eq!(connection.query("SELECT sum(a) FROM t", &[])?.rows, vec![vec![Value::Int(6)]]);
+        connection.close()
+    }
+}
@@ -0,0 +1,211 @@
+//! CSV: records of fields as RFC 4180 lays them out, for copying tables in and out of a database.
+//!
+//! A field is quoted if it starts with a double quote, and then runs to the next quote not doubled, taking
+//! in delimiters and line breaks; a line break ends a record anywhere else. Either `\n` or `\r\n` breaks
+//! lines, and a line with nothing on it is no record at all, unless records have a single field, when it is
+//! a record of one empty field: that is how a null is written in a table of one column. A field is written
+//! quoted when it could not be read back otherwise: when it holds the delimiter, a quote or a line break, or
+//! is the text nulls are written as.
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::database::DatabaseError;
+
+/// Rows a copy into a table writes, and commits, at a time, unless its options say otherwise.
+pub const DEFAULT_BATCH_ROWS: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// The byte between the fields of a record.
+    pub delimiter: u8,
+    /// Whether the first record is a header naming the columns. Read, it says which column each field is
+    /// for, and columns it does not name take their defaults; written, it names every column.
+    pub header: bool,
+    /// What an unquoted field holding a null reads as, and what a null is written as.
+    pub null: String,
+    /// The most rows a copy into a table writes, and commits, at a time.
+    pub batch_rows: usize,
+}
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions { delimiter: b',', header: true, null: String::new(), batch_rows: DEFAULT_BATCH_ROWS }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CsvError {
+    Io(io::ErrorKind),
+    /// The input ends inside a quoted field.
+    UnterminatedQuote,
+    /// A field is not valid UTF-8.
+    InvalidUtf8,
+    /// A record has a field for each of `expected` columns, but `found` fields.
+    FieldCount { expected: usize, found: usize },
+    /// The header names a column the table does not have, or names one twice.
+    NoSuchColumn(String),
+    /// A field cannot be read as a value for the column of this name.
+    InvalidValue { column: String, value: String },
+    /// The batch of rows from the error's line to this one failed with the error and none of it was
+    /// written: one of the rows broke a constraint, say, or a trigger refused it.
+    Rejected { last_line: u64, error: Box<DatabaseError> },
+}
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::Io(kind) => write!(f, "{}", io::Error::from(*kind)),
+            CsvError::UnterminatedQuote => write!(f, "unterminated quoted field"),
+            CsvError::InvalidUtf8 => write!(f, "field is not valid UTF-8"),
+            CsvError::FieldCount { expected, found } => write!(f, "{found} fields where {expected} were expected"),
+            CsvError::NoSuchColumn(name) => write!(f, "no column called {name}, or it is named twice"),
+            CsvError::InvalidValue { column, value } => write!(f, "{value:?} is not a value for column {column}"),
+            CsvError::Rejected { last_line, error } => write!(f, "rows up to line {last_line} not written: {error}"),
+        }
+    }
+}
+
+/// A field as read: its text, and whether it was quoted, which a null never is.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Field {
+    pub text: String,
+    pub quoted: bool,
+}
+
+/// The records of CSV input, read one at a time.
+pub(crate) struct Records<R> {
+    input: R,
+    delimiter: u8,
+    /// The lines read so far.
+    line: u64,
+    /// Whether a line with nothing on it is a record of one empty field rather than no record.
+    blank_records: bool,
+}
+impl<R: BufRead> Records<R> {
+    pub(crate) fn new(input: R, delimiter: u8) -> Records<R> {
+        Records { input, delimiter, line: 0, blank_records: false }
+    }
+
+    /// Read the records from here on as having `fields` fields each, so that with one a line with nothing
+    /// on it is a record of one empty field.
+    pub(crate) fn expect_fields(&mut self, fields: usize) {
+        self.blank_records = fields == 1;
+    }
+
+    /// The next record, and the line it starts on, counted from 1; or `None` at the end of the input.
+    pub(crate) fn next_record(&mut self) -> Result<Option<(u64, Vec<Field>)>, DatabaseError> {
+        let mut buf = Vec::new();
+        loop {
+            if self.read_line(&mut buf)? == 0 {
+                return Ok(None)
+            }
+            if self.blank_records || !matches!(buf.as_slice(), b"\n" | b"\r\n") {
+                break
+            }
+            buf.clear();
+        }
+        let start = self.line;
+        let fail = |error| DatabaseError::Csv { line: start, error };
+        let finish = |field: Vec<u8>, quoted| match String::from_utf8(field) {
+            Ok(text) => Ok(Field { text, quoted }),
+            Err(_) => Err(fail(CsvError::InvalidUtf8)),
+        };
+        let (mut fields, mut field, mut quoted, mut in_quotes, mut at) = (Vec::new(), Vec::new(), false, false, 0);
+        loop {
+            let Some(&byte) = buf.get(at) else {
+                if !in_quotes {
+                    break
+                }
+                if self.read_line(&mut buf)? == 0 {
+                    return Err(fail(CsvError::UnterminatedQuote))
+                }
+                continue
+            };
+            at += 1;
+            match (in_quotes, byte) {
+                (true, b'"') if buf.get(at) == Some(&b'"') => {
+                    field.push(b'"');
+                    at += 1;
+                }
+                (true, b'"') => in_quotes = false,
+                (true, byte) => field.push(byte),
+                (false, b'"') if field.is_empty() && !quoted => (in_quotes, quoted) = (true, true),
+                (false, byte) if byte == self.delimiter => {
+                    fields.push(finish(std::mem::take(&mut field), quoted)?);
+                    quoted = false;
+                }
+                (false, b'\n') => break,
+                (false, b'\r') if matches!(buf.get(at), Some(&b'\n') | None) => {}
+                (false, byte) => field.push(byte),
+            }
+        }
+        fields.push(finish(field, quoted)?);
+        Ok(Some((start, fields)))
+    }
+
+    /// Append the next line to `buf`, returning its length, 0 at the end of the input.
+    fn read_line(&mut self, buf: &mut Vec<u8>) -> Result<usize, DatabaseError> {
+        let read = self.input.read_until(b'\n', buf);
+        let read = read.map_err(|e| DatabaseError::Csv { line: self.line + 1, error: CsvError::Io(e.kind()) })?;
+        self.line += (read > 0) as u64;
+        Ok(read)
+    }
+}
+
+/// Write a record of `fields`, `None` for each null.
+pub(crate) fn write_record<'a>(
+    out: &mut impl Write,
+    fields: impl IntoIterator<Item = Option<&'a str>>,
+    options: &CsvOptions,
+) -> io::Result<()> {
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.write_all(&[options.delimiter])?;
+        }
+        match field {
+            None => out.write_all(options.null.as_bytes())?,
+            Some(text) if text == options.null || text.bytes().any(|b| matches!(b, b'"' | b'\r' | b'\n'))
+                || text.as_bytes().contains(&options.delimiter) =>
+            {
+                write!(out, "\"{}\"", text.replace('"', "\"\""))?
+            }
+            Some(text) => out.write_all(text.as_bytes())?,
+        }
+    }
+    out.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::DatabaseError;
+
+    use super::{write_record, CsvError, CsvOptions, Field, Records};
+
+    #[test]
+    fn test_records() -> Result<(), DatabaseError> {
+        let input = "a,\"b, \"\"c\"\"\",\n\r\n\"two\nlines\",x\r\n;,\"\",";
+        let mut records = Records::new(input.as_bytes(), b',');
+        let field = |text: &str, quoted| Field { text: text.to_string(), quoted };
+        let first = vec![field("a", false), field("b, \"c\"", true), field("", false)];
+        assert_eq!(records.next_record()?, Some((1, first)));
+        assert_eq!(records.next_record()?, Some((3, vec![field("two\nlines", true), field("x", false)])));
+        assert_eq!(records.next_record()?, Some((5, vec![field(";", false), field("", true), field("", false)])));
+        assert_eq!(records.next_record()?, None);
+
+        let mut records = Records::new("a\n\nb\r\n\r\n".as_bytes(), b',');
+        records.expect_fields(1);
+        assert_eq!(records.next_record()?, Some((1, vec![field("a", false)])));
+        assert_eq!(records.next_record()?, Some((2, vec![field("", false)])));
+        assert_eq!(records.next_record()?, Some((3, vec![field("b", false)])));
+        assert_eq!(records.next_record()?, Some((4, vec![field("", false)])));
+        assert_eq!(records.next_record()?, None);
+
+        let mut records = Records::new("a;b\n\"open;\nstill open".as_bytes(), b';');
+        assert_eq!(records.next_record()?, Some((1, vec![field("a", false), field("b", false)])));
+        assert_eq!(records.next_record(), Err(DatabaseError::Csv { line: 2, error: CsvError::UnterminatedQuote }));
+
+        let mut out = Vec::new();
+        let options = CsvOptions { null: "\\N".to_string(), ..CsvOptions::default() };
+        write_record(&mut out, [Some("a"), None, Some("\\N"), Some("x,\"y\""), Some("")], &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a,\\N,\"\\N\",\"x,\"\"y\"\"\",\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,3585 @@
+//! Databases: a page store holding an allocator, a catalog and the tables the catalog names.
+//!
+//! The first two pages of the store are fixed. Page 0 is the allocator's meta page and page 1 the
+//! database header, which names the catalog's tree, so `open` finds everything else from the store
+//! alone. Tables are made, dropped and renamed through the database, which records each change in the
+//! catalog and allocates or frees the table's pages to match. A `connection::Connection` owns a database
+//! together with the store and the storage under it.
+//!
+//! DDL is atomic over a `ShadowStorage`: the catalog flushes the store as the last step of every change,
+//! and that flush commits the table's new or freed pages and the catalog entry together. A change that
+//! fails part way, or a crash before the flush, leaves the store as it was after the previous commit.
+//!
+//! `alter_table` changes a table's columns without touching its rows: the new schema becomes the next
+//! version of the table's history in the catalog, and rows written before it are read through the
+//! version they were written under.
+//!
+//! `PRIMARY KEY` and `UNIQUE` constraints are unique indexes under the constraint's name, so a table opened
+//! through the database refuses a row whose key another row already holds. Keys with a null in them never
+//! conflict, as in SQL, and a primary key's columns are made not nullable. `execute` runs DDL statements,
+//! naming constraints declared without a name after the table. A `CREATE INDEX` key is a column or, in
+//! parentheses, a path into a JSON column such as `(doc->>'name')`, which a query comparing that same
+//! path with a constant can look up. A column's key is ordered by the collation the column is declared with,
+//! or by the one `COLLATE` names after it, and only answers comparisons under that collation.
+//!
+//! Foreign keys are kept by the database's own `insert`, `update` and `delete`, which look keys up in the
+//! referenced table's unique index and find referencing rows through an index the foreign key keeps on
+//! its columns; writing to a table opened with `open_table` goes around them. The rows a delete cascades
+//! to are written by the same call as the delete, so over a `ShadowStorage` they commit together.
+//!
+//! A column's default is an expression over no columns, kept in the catalog as SQL and evaluated for
+//! each row an `INSERT` through `execute` gives no value for the column; with no default, that is null.
+//! Adding a column with a default evaluates it once for the rows already there, which read it through
+//! their older schema version without being rewritten, so `now()` is the time the column was added for
+//! all of them. Changing a column's default leaves the rows already written alone.
+//!
+//! An `INSERT` through `execute` takes its rows from `VALUES` lists or from a `SELECT`, whose rows are all
+//! read first. They are written through `insert_many` in batches: its records fill one page at a time and
+//! each index takes a batch's keys in sorted order. No row is flushed on its own; over a `ShadowStorage` a
+//! statement's rows commit together with the next flush.
+//!
+//! `UPDATE` and `DELETE` find the rows their `WHERE` holds for by a plan the planner makes as for a
+//! `SELECT` of the table, whose scan gives each row's rid along with it, and read all of them before
+//! writing any. They write through `update` and `delete_many`, keeping indexes and foreign keys, and
+//! `execute` returns how many rows each statement wrote.
+//!
+//! A table declared `WITHOUT ROWID` is a `ClusteredTable`, its rows kept in a tree keyed by its primary key,
+//! which is the only constraint it may have. Queries read it through the tree, under the leading key columns
+//! they fix with `=` and in key order; `UPDATE` and `DELETE` find its rows by their keys, and a row whose key
+//! changes is deleted and inserted again. It has no indexes, triggers or TTL, and `open_table`, which opens
+//! heap tables, refuses it; `open_clustered_table` opens it.
+//!
+//! A table declared `PARTITION BY RANGE (column)` or `PARTITION BY HASH (column) PARTITIONS n` is a
+//! `PartitionedTable`, its rows split over partitions by that column; a range partitioned table takes its
+//! partitions from `ALTER TABLE ... ADD PARTITION FROM lower`. Queries scan only the partitions that may
+//! hold the rows their `=`, or for range partitions their `<`, `<=`, `>` and `>=`, on the column allow, and
+//! a row whose column changes moves partition. It has no constraints, indexes, triggers or TTL, and is
+//! opened with `open_partitioned_table`.
+//!
+//! `INSERT ... ON CONFLICT` inserts its rows one at a time, each speculatively: the insert checks the
+//! table's unique indexes before it writes anything, and a violation in an index the `ON CONFLICT` handles
+//! skips the row, or updates the row holding the key for `DO UPDATE`. The database writes through `&mut
+//! self`, so no other write can come between a conflict and its resolution.
+//!
+//! `query` runs a `SELECT`: it parses and plans the statement, opens the tables the plan reads and
+//! drains the plan's operators into a `QueryResult`. The planner costs plans by the statistics `analyze`
+//! leaves in the catalog; altering a table's columns discards them. Operators that outgrow their memory
+//! budget, such as a hash join over a large input, spill to temporary space the database keeps apart from
+//! the store, so spilled rows never reach its pages. It is in memory unless `set_temp_storage` gives it
+//! other storage, such as a `TempFile`, which a `Connection` opened on a file does.
+//!
+//! `query` also runs `EXPLAIN`, whose rows are the lines of the plan it would run, each node with the
+//! rows and cost the planner estimated for it. `EXPLAIN ANALYZE` runs the plan, throwing its rows away, and
+//! adds the rows each node output, the times it was opened, and the time spent pulling its rows.
+//!
+//! A view is created from its `SELECT`, planned once to check it and find its columns, and kept as the SQL
+//! of that `SELECT` along with the tables and views it reads; those cannot be dropped or renamed while the
+//! view is there. A query naming the view plans its `SELECT` anew.
+//!
+//! A sequence hands out `Int`s from ranges of its cache reserved in the catalog: the end of each range is
+//! recorded, and flushed, before the first value of it is given out, so a crash or a reopen skips what was
+//! left of the range rather than giving a value twice. Over a `ShadowStorage` that flush commits whatever
+//! else was written before it too. `nextval('name')` takes the next value in the values of an `INSERT` and
+//! in defaults, and an identity column, declared `AUTOINCREMENT` or `GENERATED BY DEFAULT AS IDENTITY`, is
+//! an `INT` column defaulting to the next value of a sequence `t_a_seq` made for it and owned by its table.
+//!
+//! A trigger runs before or after each row an insert, update or delete writes to its table, whether
+//! through `execute` or the database's own write methods. One created with `CREATE TRIGGER` is kept in the
+//! catalog with the SQL of its body, whose statements read the rows through `new` and `old`; one registered
+//! with `register_trigger` runs a Rust callback, and lasts only as long as the `Database`. A table's
+//! triggers run in the order of their names, and their writes are part of the write that ran them: over a
+//! `ShadowStorage` they commit together with it, and a trigger that fails fails it. Rows a delete cascades
+//! to run their own table's triggers.
+//!
+//! A table declared with `TTL column + lifetime` after its columns has its rows expire once `lifetime`, an
+//! interval for a timestamp column or a number of seconds for an integer one, has passed since the time in
+//! `column`. Nothing deletes them until `expire` runs, which deletes a bounded batch of them through
+//! `delete_many`, found through an index led by the column where the table has one. A `Connection` runs a
+//! batch every so often as it commits statements.
+//!
+//! A query's recursive common table expressions may run as many rounds as the database's recursion limit,
+//! `exec::DEFAULT_RECURSION_LIMIT` unless `set_recursion_limit` changes it, and fail after that. Its
+//! sorts, hash joins and aggregates share a memory budget of `exec::DEFAULT_QUERY_MEMORY_BYTES`, or what
+//! `set_memory_limit` sets: they spill once it is used up, and fail the query where they cannot. Parts of
+//! its plan may run on as many as `set_max_parallel_workers` workers at once, where the planner finds that
+//! worth it; by default `exec::DEFAULT_MAX_PARALLEL_WORKERS`, so none do.
+//!
+//! A keyspace is an ordered map of byte strings in a B+tree of its own, or in an LSM tree if it is created
+//! with `create_keyspace_with` asking for one, for storing keys and values without SQL. It is recorded in
+//! the catalog under a name no table, view or sequence has, and lives in the store beside the tables; SQL
+//! does not see it.
+//!
+//! `prepare` parses and plans a `SELECT` once, for a `Statement` to run as often as needed with different
+//! parameters. The statement keeps the catalog entries of the tables and views its plan reads, and plans
+//! again whenever one of them has changed since: altered, dropped or renamed, given an index, or analyzed.
+//! A run can be stopped part way through the statement's `cancel_handle`, from this thread or another: its
+//! operators and scans check the handle as they go and fail the run with `DatabaseError::Cancelled`.
+//!
+//! A statement's `query` is a cursor over its rows, pulling each out of the plan's operators as it is
+//! iterated rather than collecting them first, so a result larger than memory can be read a row at a time;
+//! the cursor borrows the database, and whatever it left unread is let go of when it is dropped.
+//!
+//! `copy_from_csv` reads rows into a table from CSV and `copy_to_csv` writes a table's rows out as CSV.
+//! Fields are read as their columns' types the way a cast from text reads them, and a header, unless the
+//! options say there is none, names the column each field is for. The rows go to `insert_many` a batch at
+//! a time, and the store is flushed after each batch, so over a `ShadowStorage` every batch commits on its
+//! own and a copy that fails leaves the batches before the failure written. Errors name the line of the CSV
+//! they were found at.
+
+use std::borrow::Cow;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::ptr::NonNull;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::allocator::PageAllocator;
+use crate::btree::BTree;
+use crate::cancel::CancelHandle;
+use crate::bytes::{read_u32, read_u64, write_u32, write_u64};
+use crate::collation::Collation;
+use crate::csv::{write_record, CsvError, CsvOptions, Field, Records};
+use crate::catalog::{
+    Catalog, CatalogError, ColumnDef, Constraint, ForeignKey, IndexDef, KeyspaceDef, OnDelete, SequenceDef, TableDef,
+    TableKind, TriggerDef, TriggerEvent, TriggerTiming, ViewDef,
+};
+use crate::exec::{self, Context, ExecError, NodeStats, Operator, Plan, Profile};
+use crate::memory::MemoryBudget;
+use crate::heap::{HeapError, Rid};
+use crate::json::JsonPath;
+use crate::kv::{Keyspace, KeyspaceOptions, KvError};
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::planner::{self, PlanError, Planner, Query};
+use crate::sql::{
+    self, AlterColumn, ConflictAction, ConstraintKind, Expr, InsertSource, OnConflict, ParseError, ReferentialAction,
+    Select, TableConstraint,
+};
+use crate::storage::{MemoryStorage, Storage};
+use crate::table::{
+    ClusteredTable, IndexKind, PartitionBy, PartitionRid, PartitionedTable, Table, TableError, TableStats, Ttl,
+};
+use crate::temp::TempSpace;
+use crate::tuple::{Column, ColumnType, Schema, TupleError, Value};
+
+const ALLOCATOR_PAGE: usize = 0;
+const HEADER_PAGE: usize = 1;
+const MAGIC: u32 = 0x5044_4244;
+const CATALOG_ROOT: usize = 8;
+/// Rows `analyze` samples for histograms and distinct counts.
+const ANALYZE_SAMPLE: usize = 10_000;
+/// The most rows an `INSERT` through `execute` writes with one call to `insert_many`.
+const INSERT_BATCH: usize = 4096;
+/// Values a sequence reserves at a time, unless `CREATE SEQUENCE` gives a `CACHE`.
+const SEQUENCE_CACHE: u64 = 32;
+/// How deeply the writes of triggers may run triggers of their own.
+const TRIGGER_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum DatabaseError {
+    Page(PageError),
+    Table(TableError),
+    Catalog(CatalogError),
+    /// The table has no column of this name.
+    NoSuchColumn(String),
+    Parse(ParseError),
+    Plan(PlanError),
+    Exec(ExecError),
+    Kv(KvError),
+    /// `query` was given a statement that is not a `SELECT` or `EXPLAIN`, or `prepare` one that is not a
+    /// `SELECT`.
+    NotAQuery,
+    /// A bind parameter, numbered from 0, given a value of another type than its uses need.
+    ParameterType { parameter: usize, expected: ColumnType },
+    /// A row whose foreign key, named here, has this key, which no row of the table it references holds;
+    /// or a row whose key, under that foreign key, other rows still reference.
+    ForeignKeyViolation { constraint: String, key: Vec<Value> },
+    /// The table a foreign key references has no primary key or unique constraint on the columns it names.
+    NoUniqueKey(String),
+    /// An `ON CONFLICT` names columns no unique index of the table is on, or its `DO UPDATE` names none.
+    NoConflictIndex(Vec<String>),
+    /// A sequence given an increment of zero or a cache of no values.
+    InvalidSequence(String),
+    /// A sequence whose next range of values would pass the largest or smallest `Int`.
+    SequenceExhausted(String),
+    /// A write ran the trigger of this name with triggers already running `TRIGGER_DEPTH` deep.
+    TriggerDepth(String),
+    /// A trigger's callback refused a write, for this reason.
+    Rejected(String),
+    /// A statement `execute` cannot run yet.
+    Unsupported(&'static str),
+    /// The table of this name is not a heap table, so cannot be opened as a `Table`.
+    NotAHeapTable(String),
+    /// The table of this name is not a clustered table, so cannot be opened as a `ClusteredTable`.
+    NotAClusteredTable(String),
+    /// The table of this name is not partitioned, so cannot be opened as a `PartitionedTable`.
+    NotAPartitionedTable(String),
+    /// A prepared statement was cancelled through its `CancelHandle` while it ran.
+    Cancelled,
+    /// A copy to or from CSV failed at this line of the CSV, counted from 1.
+    Csv { line: u64, error: CsvError },
+    /// Writing a Parquet file failed with this error from its output.
+    #[cfg(feature = "parquet")]
+    Parquet(io::ErrorKind),
+}
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Page(e) => write!(f, "page error: {e:?}"),
+            DatabaseError::Table(e) => write!(f, "{e}"),
+            DatabaseError::Catalog(e) => write!(f, "{e}"),
+            DatabaseError::NoSuchColumn(name) => write!(f, "no column called {name}"),
+            DatabaseError::Parse(e) => write!(f, "{e}"),
+            DatabaseError::Plan(e) => write!(f, "{e}"),
+            DatabaseError::Exec(e) => write!(f, "{e}"),
+            DatabaseError::Kv(e) => write!(f, "{e}"),
+            DatabaseError::NotAQuery => write!(f, "not a query"),
+            DatabaseError::ParameterType { parameter, expected } => {
+                write!(f, "parameter {} must be of type {}", parameter + 1, sql::ast::type_name(*expected))
+            }
+            DatabaseError::ForeignKeyViolation { constraint, .. } => write!(f, "key violates foreign key {constraint}"),
+            DatabaseError::NoUniqueKey(table) => write!(f, "no unique key of {table} matches the columns referenced"),
+            DatabaseError::NoConflictIndex(_) => write!(f, "no unique index matches the ON CONFLICT columns"),
+            DatabaseError::InvalidSequence(name) => write!(f, "sequence {name} must step by and cache at least one"),
+            DatabaseError::SequenceExhausted(name) => write!(f, "sequence {name} has run out of values"),
+            DatabaseError::TriggerDepth(name) => write!(f, "trigger {name} nested too deep"),
+            DatabaseError::Rejected(reason) => write!(f, "write refused: {reason}"),
+            DatabaseError::Unsupported(what) => write!(f, "{what} is not supported yet"),
+            DatabaseError::NotAHeapTable(name) => write!(f, "{name} is not a heap table"),
+            DatabaseError::NotAClusteredTable(name) => write!(f, "{name} is not a clustered table"),
+            DatabaseError::NotAPartitionedTable(name) => write!(f, "{name} is not a partitioned table"),
+            DatabaseError::Cancelled => write!(f, "cancelled"),
+            DatabaseError::Csv { line, error } => write!(f, "at line {line} of the CSV: {error}"),
+            #[cfg(feature = "parquet")]
+            DatabaseError::Parquet(kind) => write!(f, "cannot write the Parquet file: {}", io::Error::from(*kind)),
+        }
+    }
+}
+impl From<PageError> for DatabaseError {
+    fn from(e: PageError) -> Self {
+        DatabaseError::Page(e)
+    }
+}
+impl From<TableError> for DatabaseError {
+    fn from(e: TableError) -> Self {
+        match e {
+            TableError::Page(e) => DatabaseError::Page(e),
+            TableError::Heap(HeapError::Cancelled) => DatabaseError::Cancelled,
+            e => DatabaseError::Table(e),
+        }
+    }
+}
+impl From<CatalogError> for DatabaseError {
+    fn from(e: CatalogError) -> Self {
+        match e {
+            CatalogError::Page(e) => DatabaseError::Page(e),
+            e => DatabaseError::Catalog(e),
+        }
+    }
+}
+impl From<KvError> for DatabaseError {
+    fn from(e: KvError) -> Self {
+        match e {
+            KvError::Page(e) => DatabaseError::Page(e),
+            e => DatabaseError::Kv(e),
+        }
+    }
+}
+
+impl From<ParseError> for DatabaseError {
+    fn from(e: ParseError) -> Self {
+        DatabaseError::Parse(e)
+    }
+}
+impl From<PlanError> for DatabaseError {
+    fn from(e: PlanError) -> Self {
+        DatabaseError::Plan(e)
+    }
+}
+impl From<ExecError> for DatabaseError {
+    fn from(e: ExecError) -> Self {
+        match e {
+            ExecError::Page(e) => DatabaseError::Page(e),
+            ExecError::Table(e) => DatabaseError::from(e),
+            ExecError::Cancelled => DatabaseError::Cancelled,
+            e => DatabaseError::Exec(e),
+        }
+    }
+}
+
+/// The rows a query returned, and the names of their columns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// A `SELECT` parsed and planned by `Database::prepare`.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    select: Select,
+    query: Query,
+    /// The catalog entries of the tables and views the plan reads, as they were when it was planned.
+    tables: Vec<TableDef>,
+    views: Vec<ViewDef>,
+    cancel: CancelHandle,
+}
+impl Statement {
+    /// The names of the columns the statement outputs.
+    pub fn columns(&self) -> &[String] {
+        &self.query.columns
+    }
+
+    /// The type of each column the statement outputs, or `None` for a column that may hold any value, such
+    /// as one that is always null.
+    pub fn types(&self) -> &[Option<ColumnType>] {
+        &self.query.types
+    }
+
+    /// The plan the statement runs, as of its last run.
+    pub fn plan(&self) -> &Plan {
+        &self.query.plan
+    }
+
+    /// A handle that stops the statement's run in progress, on whatever thread it is cancelled from, failing
+    /// it with `DatabaseError::Cancelled`. Cancelled with no run in progress, it stops the next run instead;
+    /// the run it stops clears it again, so the statement can be run once more.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// The type each bind parameter must have, numbered from 0, or `None` for a parameter that may hold
+    /// any value.
+    pub fn params(&self) -> &[Option<ColumnType>] {
+        &self.query.params
+    }
+
+    /// Run the statement over `db`, with `params` as the values of its bind parameters, for rows pulled from
+    /// its plan one at a time as the cursor is iterated. A null may stand for any parameter, and an integer
+    /// or a float for either.
+    pub fn query<'db, 'store, S: Storage>(
+        &mut self,
+        db: &'db Database<'store, S>,
+        params: &[Value],
+    ) -> Result<Rows<'db, 'store, S>, DatabaseError> {
+        self.run(db, params, false)
+    }
+
+    /// Run the statement as `query` does, for what `f` makes of each row.
+    pub fn query_map<'db, 'store, S: Storage, T, F: FnMut(Vec<Value>) -> T>(
+        &mut self,
+        db: &'db Database<'store, S>,
+        params: &[Value],
+        f: F,
+    ) -> Result<MappedRows<'db, 'store, S, F>, DatabaseError> {
+        Ok(MappedRows { rows: self.query(db, params)?, f })
+    }
+
+    /// The lines of `EXPLAIN` for the statement, planned as it would be run over `db`; with `analyze`,
+    /// after running it with `params`.
+    pub fn explain<S: Storage>(
+        &mut self,
+        db: &Database<S>,
+        params: &[Value],
+        analyze: bool,
+    ) -> Result<Vec<String>, DatabaseError> {
+        if !analyze {
+            self.replan(db)?;
+            return Ok(self.query.explain(None))
+        }
+        let mut rows = self.run(db, params, true)?;
+        rows.by_ref().try_for_each(|row| row.map(drop))?;
+        Ok(self.query.explain(Some(&rows.stats())))
+    }
+
+    /// Plan the statement again if a table or view it reads has changed since it was planned.
+    fn replan<S: Storage>(&mut self, db: &Database<S>) -> Result<(), DatabaseError> {
+        let stale = self.tables.iter().any(|def| db.catalog.table(&def.name) != Some(def))
+            || self.views.iter().any(|def| db.catalog.view(&def.name) != Some(def));
+        if stale {
+            (self.query, self.tables, self.views) = db.plan(&self.select)?;
+        }
+        Ok(())
+    }
+
+    /// Start running the statement, and if `profiled` count what each node of its plan does.
+    fn run<'db, 'store, S: Storage>(
+        &mut self,
+        db: &'db Database<'store, S>,
+        params: &[Value],
+        profiled: bool,
+    ) -> Result<Rows<'db, 'store, S>, DatabaseError> {
+        self.replan(db)?;
+        for (parameter, (expected, value)) in self.query.params.iter().zip(params).enumerate() {
+            let numeric = |t| matches!(t, ColumnType::Int | ColumnType::Float | ColumnType::Decimal { .. });
+            let fits = |a: ColumnType, b| a.same_kind(b) || numeric(a) && numeric(b);
+            match (*expected, value.column_type()) {
+                (Some(expected), Some(actual)) if !fits(expected, actual) => {
+                    return Err(DatabaseError::ParameterType { parameter, expected })
+                }
+                _ => {}
+            }
+        }
+        let mut plan = self.query.plan.clone();
+        plan.bind(params)?;
+        let mut context = Box::new(Context::new(&db.temp));
+        db.open_tables(&plan, &mut context)?;
+        let plan = Box::new(plan);
+        let profile = Box::new(Profile::new(&plan));
+        let mut rows = Rows {
+            columns: self.query.columns.clone(),
+            operator: None,
+            context: NonNull::from(Box::leak(context)),
+            profile: NonNull::from(Box::leak(profile)),
+            plan: NonNull::from(Box::leak(plan)),
+            cancel: self.cancel.clone(),
+        };
+        // SAFETY: the context, the profile and the plan are only freed when `rows` is dropped, after the
+        // operator borrowing them, and nothing else reaches them until then.
+        let (context, profile, plan) = unsafe { (rows.context.as_mut(), rows.profile.as_ref(), rows.plan.as_ref()) };
+        context.profile = profiled.then_some(profile);
+        context.recursion_limit = db.recursion_limit;
+        context.memory = MemoryBudget::new(db.memory_limit);
+        context.cancel = Some(self.cancel.clone());
+        rows.operator = Some(plan.open(context).map_err(|e| rows.failed(e))?);
+        Ok(rows)
+    }
+}
+
+/// A statement's run, pulling each row out of the operators of its plan as the cursor is iterated. The run's
+/// tables stay open, and what its operators hold in memory or have spilled stays held, until it has given
+/// its last row or failed, or the cursor is dropped before then.
+pub struct Rows<'db, 'store, S: Storage> {
+    columns: Vec<String>,
+    /// The operators of the plan, borrowing the plan and the context; `None` once they are done.
+    operator: Option<Box<dyn Operator + 'db>>,
+    context: NonNull<Context<'db, 'store, S, TempStorage>>,
+    profile: NonNull<Profile>,
+    plan: NonNull<Plan>,
+    cancel: CancelHandle,
+}
+impl<S: Storage> Rows<'_, '_, S> {
+    /// The names of the columns of each row.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Pull every row that is left, for the rows and the names of their columns.
+    pub fn into_result(mut self) -> Result<QueryResult, DatabaseError> {
+        let rows = self.by_ref().collect::<Result<_, _>>()?;
+        Ok(QueryResult { columns: std::mem::take(&mut self.columns), rows })
+    }
+
+    /// What each node of the plan has done so far, if the run is profiled.
+    fn stats(&self) -> Vec<NodeStats> {
+        // SAFETY: as in `Statement::run`.
+        unsafe { self.profile.as_ref() }.stats()
+    }
+
+    /// End the run on `e`, clearing the statement's cancellation if it is what stopped the run.
+    fn failed(&mut self, e: ExecError) -> DatabaseError {
+        self.operator = None;
+        if let ExecError::Cancelled = e {
+            self.cancel.reset();
+        }
+        DatabaseError::from(e)
+    }
+}
+impl<S: Storage> Iterator for Rows<'_, '_, S> {
+    type Item = Result<Vec<Value>, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.operator.as_mut()?.next() {
+            Ok(Some(row)) => Some(Ok(row)),
+            Ok(None) => {
+                self.operator = None;
+                None
+            }
+            Err(e) => Some(Err(self.failed(e))),
+        }
+    }
+}
+impl<S: Storage> Drop for Rows<'_, '_, S> {
+    fn drop(&mut self) {
+        self.operator = None;
+        // SAFETY: each was leaked by `Statement::run` and nothing borrows it now that the operator is gone;
+        // the context, which borrows the profile, goes first.
+        unsafe {
+            drop(Box::from_raw(self.context.as_ptr()));
+            drop(Box::from_raw(self.profile.as_ptr()));
+            drop(Box::from_raw(self.plan.as_ptr()));
+        }
+    }
+}
+
+/// The rows of a statement run by `Statement::query_map`, each as its function made it.
+pub struct MappedRows<'db, 'store, S: Storage, F> {
+    rows: Rows<'db, 'store, S>,
+    f: F,
+}
+impl<S: Storage, T, F: FnMut(Vec<Value>) -> T> Iterator for MappedRows<'_, '_, S, F> {
+    type Item = Result<T, DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.rows.next()?.map(&mut self.f))
+    }
+}
+
+/// A change to a table's columns, made by `Database::alter_table`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlterTable {
+    /// Add a column after the others, reading as `default` in the rows already there.
+    AddColumn { column: ColumnDef, default: Value },
+    DropColumn(String),
+    SetNullable { column: String, nullable: bool },
+    /// Give the column the default with this SQL, or none, for rows inserted from now on.
+    SetDefault { column: String, default: Option<String> },
+}
+
+/// The rows a trigger runs for: the row as it was, for an update or a delete, and the row as it is to be
+/// written, for an insert or an update. A `BEFORE` trigger's callback may change `new`, and the row written
+/// is the one it leaves there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerRow {
+    pub old: Option<Vec<Value>>,
+    pub new: Option<Vec<Value>>,
+}
+
+/// A registered trigger's callback, given the database to write through and the rows it runs for.
+type TriggerFn<S> = dyn for<'store> Fn(&mut Database<'store, S>, &mut TriggerRow) -> Result<(), DatabaseError>;
+
+/// A trigger registered with `Database::register_trigger`, which lasts as long as the database does.
+struct Callback<S: Storage> {
+    table: String,
+    name: String,
+    timing: TriggerTiming,
+    event: TriggerEvent,
+    callback: Rc<TriggerFn<S>>,
+}
+
+/// What a trigger runs: the SQL of the body it was created with, or its callback.
+enum TriggerBody<S: Storage> {
+    Sql(String),
+    Callback(Rc<TriggerFn<S>>),
+}
+
+/// What a database keeps its temporary pages in: memory unless `set_temp_storage` gives it a `TempFile`.
+pub type TempStorage = Box<dyn Storage>;
+
+pub struct Database<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    catalog: Catalog<'store, S>,
+    temp: TempSpace<TempStorage>,
+    /// The most rounds a recursive common table expression may run.
+    recursion_limit: usize,
+    /// The most bytes the operators of one query may hold at once.
+    memory_limit: usize,
+    /// The most workers one query may run parts of its plan on at once.
+    max_parallel_workers: usize,
+    /// The next value of each sequence's reserved range, and how many values are left in it.
+    sequences: HashMap<String, (i64, u64)>,
+    /// The triggers registered with `register_trigger`.
+    callbacks: Vec<Callback<S>>,
+    /// How many triggers are running, each inside the write of the one before.
+    trigger_depth: usize,
+}
+impl<'store, S: Storage> Database<'store, S> {
+    /// Set up an empty database in `store`, which must hold nothing yet.
+    pub fn create(store: &'store PageStore<S>) -> Result<Database<'store, S>, DatabaseError> {
+        let allocator = PageAllocator::create(store, PageId::new(ALLOCATOR_PAGE))?;
+        let header = allocator.allocate(store)?;
+        debug_assert_eq!(header.id(), PageId::new(HEADER_PAGE));
+        let catalog = Catalog::create(store, allocator)?;
+        {
+            let mut buf = header.try_write()?;
+            write_u32(&mut *buf, 0, MAGIC);
+            write_u64(&mut *buf, CATALOG_ROOT, catalog.root().offset() as u64);
+        }
+        drop(header);
+        store.flush()?;
+        Ok(Database::with_catalog(store, allocator, catalog))
+    }
+
+    /// Open the database made earlier by `create` in `store`.
+    pub fn open(store: &'store PageStore<S>) -> Result<Database<'store, S>, DatabaseError> {
+        let allocator = PageAllocator::open(store, PageId::new(ALLOCATOR_PAGE))?;
+        let root = {
+            let header = store.pin_page(&PageId::new(HEADER_PAGE))?;
+            let buf = header.try_read()?;
+            if read_u32(&*buf, 0) != MAGIC {
+                return Err(DatabaseError::Page(PageError::WrongPageType))
+            }
+            PageId::new(read_u64(&*buf, CATALOG_ROOT) as usize)
+        };
+        let catalog = Catalog::open(store, allocator, root)?;
+        Ok(Database::with_catalog(store, allocator, catalog))
+    }
+
+    /// Read the allocator and the catalog back from the store, as `open` does, once what was written to it
+    /// since its last flush has been discarded. The ranges sequences had reserved are given up with them.
+    pub fn reload(&mut self) -> Result<(), DatabaseError> {
+        let Database { allocator, catalog, .. } = Database::open(self.store)?;
+        (self.allocator, self.catalog) = (allocator, catalog);
+        self.sequences.clear();
+        self.trigger_depth = 0;
+        Ok(())
+    }
+
+    fn with_catalog(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        catalog: Catalog<'store, S>,
+    ) -> Database<'store, S> {
+        Database {
+            store,
+            allocator,
+            catalog,
+            temp: TempSpace::new(Box::new(MemoryStorage::new())),
+            recursion_limit: exec::DEFAULT_RECURSION_LIMIT,
+            memory_limit: exec::DEFAULT_QUERY_MEMORY_BYTES,
+            max_parallel_workers: exec::DEFAULT_MAX_PARALLEL_WORKERS,
+            sequences: HashMap::new(),
+            callbacks: Vec::new(),
+            trigger_depth: 0,
+        }
+    }
+
+    pub fn store(&self) -> &'store PageStore<S> {
+        self.store
+    }
+
+    pub fn allocator(&self) -> PageAllocator {
+        self.allocator
+    }
+
+    pub fn catalog(&self) -> &Catalog<'store, S> {
+        &self.catalog
+    }
+
+    /// Fail queries whose recursive common table expressions run more than `rounds` rounds.
+    pub fn set_recursion_limit(&mut self, rounds: usize) {
+        self.recursion_limit = rounds;
+    }
+
+    /// Limit the operators of each query to holding `bytes` between them, past which they spill to temporary
+    /// space and, where they cannot, fail the query with `ExecError::MemoryLimit`.
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = bytes;
+    }
+
+    /// Keep the pages operators spill from now on in `storage`, which should hold nothing else, rather than
+    /// where they were kept before.
+    pub fn set_temp_storage(&mut self, storage: TempStorage) {
+        self.temp = TempSpace::new(storage);
+    }
+
+    /// The temporary space operators spill to.
+    pub fn temp_space(&self) -> &TempSpace<TempStorage> {
+        &self.temp
+    }
+
+    /// Let queries planned from now on run parts of their plans on up to `workers` workers at once. Statements
+    /// already prepared keep the plans they have until they plan again.
+    pub fn set_max_parallel_workers(&mut self, workers: usize) {
+        self.max_parallel_workers = workers;
+    }
+
+    /// Create an empty heap table called `name` with `columns`.
+    pub fn create_table(&mut self, name: &str, columns: Vec<ColumnDef>) -> Result<Table<'store, S>, DatabaseError> {
+        if self.catalog.table(name).is_some() {
+            return Err(DatabaseError::Catalog(CatalogError::DuplicateTable(name.to_string())))
+        }
+        let schema = Schema::new(columns.iter().map(|c| c.column).collect());
+        let table = Table::create(self.store, self.allocator, schema)?;
+        self.catalog.create_table(TableDef::new(name, columns, TableKind::Heap, table.root()))?;
+        Ok(table)
+    }
+
+    /// Create an empty clustered table called `name` with `columns`, keyed by the `primary_key` columns, which
+    /// must not be nullable.
+    pub fn create_clustered_table(
+        &mut self,
+        name: &str,
+        columns: Vec<ColumnDef>,
+        primary_key: Vec<usize>,
+    ) -> Result<ClusteredTable<'store, S>, DatabaseError> {
+        if self.catalog.table(name).is_some() {
+            return Err(DatabaseError::Catalog(CatalogError::DuplicateTable(name.to_string())))
+        }
+        let schema = Schema::new(columns.iter().map(|c| c.column).collect());
+        let table = ClusteredTable::create(self.store, self.allocator, schema, primary_key.clone())?;
+        self.catalog.create_table(TableDef::new(name, columns, TableKind::Clustered { primary_key }, table.root()))?;
+        Ok(table)
+    }
+
+    /// Create an empty table called `name` with `columns`, partitioned as `by` says. A range partitioned table
+    /// has no partitions until `add_partition` adds them.
+    pub fn create_partitioned_table(
+        &mut self,
+        name: &str,
+        columns: Vec<ColumnDef>,
+        by: PartitionBy,
+    ) -> Result<PartitionedTable<'store, S>, DatabaseError> {
+        if self.catalog.table(name).is_some() {
+            return Err(DatabaseError::Catalog(CatalogError::DuplicateTable(name.to_string())))
+        }
+        let schema = Schema::new(columns.iter().map(|c| c.column).collect());
+        let table = PartitionedTable::create(self.store, self.allocator, schema, by)?;
+        let def = TableDef::new(name, columns, TableKind::Partitioned { by }, table.root());
+        if let Err(e) = self.catalog.create_table(def) {
+            table.free()?;
+            return Err(e.into())
+        }
+        Ok(table)
+    }
+
+    /// Open the heap table called `name` with all of its indexes attached.
+    pub fn open_table(&self, name: &str) -> Result<Table<'store, S>, DatabaseError> {
+        let def = self.heap_def(name)?;
+        let mut table = Table::open_with_history(self.store, self.allocator, def.history.clone(), def.root)?;
+        for index in &def.indexes {
+            let (columns, paths, include) = (index.columns.clone(), index.paths.clone(), index.include.clone());
+            let (collations, unique, kind) = (index.collations.clone(), index.constraint.is_some(), index.kind);
+            table.open_keyed_index(&index.name, columns, paths, collations, include, index.meta, unique, kind)?;
+        }
+        Ok(table)
+    }
+
+    /// Open the clustered table called `name`.
+    pub fn open_clustered_table(&self, name: &str) -> Result<ClusteredTable<'store, S>, DatabaseError> {
+        let def = self.table_def(name)?;
+        let TableKind::Clustered { primary_key } = &def.kind else {
+            return Err(DatabaseError::NotAClusteredTable(name.to_string()))
+        };
+        Ok(ClusteredTable::open(self.store, self.allocator, def.schema(), primary_key.clone(), def.root)?)
+    }
+
+    /// Open the partitioned table called `name`.
+    pub fn open_partitioned_table(&self, name: &str) -> Result<PartitionedTable<'store, S>, DatabaseError> {
+        let def = self.table_def(name)?;
+        let TableKind::Partitioned { .. } = def.kind else {
+            return Err(DatabaseError::NotAPartitionedTable(name.to_string()))
+        };
+        Ok(PartitionedTable::open(self.store, self.allocator, def.schema(), def.root)?)
+    }
+
+    /// Add a partition to the range partitioned table called `table` for the rows from `lower` up to the next
+    /// partition's bound, returning the new partition's id. `lower` must be above every existing bound.
+    pub fn add_partition(&mut self, table: &str, lower: Value) -> Result<u32, DatabaseError> {
+        Ok(self.open_partitioned_table(table)?.add_partition(lower)?)
+    }
+
+    /// Open the tables `plan` reads into `context`, each as the type of table its kind is.
+    fn open_tables<T: Storage>(
+        &self,
+        plan: &Plan,
+        context: &mut Context<'_, 'store, S, T>,
+    ) -> Result<(), DatabaseError> {
+        for name in plan.tables() {
+            match self.table_def(name)?.kind {
+                TableKind::Clustered { .. } => {
+                    context.clustered.insert(name.to_string(), self.open_clustered_table(name)?);
+                }
+                TableKind::Partitioned { .. } => {
+                    context.partitioned.insert(name.to_string(), self.open_partitioned_table(name)?);
+                }
+                _ => {
+                    context.tables.insert(name.to_string(), self.open_table(name)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `f` with every row of the table called `table`: in heap order, in primary key order for a
+    /// clustered table, or partition by partition for a partitioned one.
+    pub(crate) fn each_row(
+        &self,
+        table: &str,
+        mut f: impl FnMut(Vec<Value>) -> Result<(), DatabaseError>,
+    ) -> Result<(), DatabaseError> {
+        match self.table_def(table)?.kind {
+            TableKind::Clustered { .. } => self.open_clustered_table(table)?.scan().try_for_each(|row| f(row?)),
+            TableKind::Partitioned { .. } => {
+                self.open_partitioned_table(table)?.scan().try_for_each(|row| f(row?.1))
+            }
+            _ => self.open_table(table)?.scan().try_for_each(|row| f(row?.1)),
+        }
+    }
+
+    /// Build an index called `index` over the rows of the table called `table` and record it. Write to the
+    /// table through tables opened after this, which attach the new index.
+    pub fn create_index(
+        &mut self,
+        table: &str,
+        index: &str,
+        columns: Vec<usize>,
+        include: Vec<usize>,
+    ) -> Result<(), DatabaseError> {
+        let paths = vec![None; columns.len()];
+        let collations = self.table_def(table)?.collations(&columns);
+        self.add_index(table, index, columns, paths, collations, include, None, IndexKind::BTree)
+    }
+
+    /// Add a constraint called `name` on `columns` of the table called `table`, backed by a unique index of
+    /// that name. A primary key also makes its columns not nullable. Fails, changing nothing, if the rows
+    /// already there break the constraint.
+    pub fn add_constraint(
+        &mut self,
+        table: &str,
+        name: &str,
+        constraint: Constraint,
+        columns: Vec<usize>,
+    ) -> Result<(), DatabaseError> {
+        let paths = vec![None; columns.len()];
+        let collations = self.table_def(table)?.collations(&columns);
+        self.add_index(table, name, columns, paths, collations, Vec::new(), Some(constraint), IndexKind::BTree)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_index(
+        &mut self,
+        table: &str,
+        index: &str,
+        columns: Vec<usize>,
+        paths: Vec<Option<JsonPath>>,
+        collations: Vec<Collation>,
+        include: Vec<usize>,
+        constraint: Option<Constraint>,
+        kind: IndexKind,
+    ) -> Result<(), DatabaseError> {
+        let mut def = self.table_def(table)?.clone();
+        let mut opened = self.open_table(table)?;
+        let primary_key = constraint == Some(Constraint::PrimaryKey);
+        if primary_key {
+            if def.indexes.iter().any(|i| i.constraint == Some(Constraint::PrimaryKey)) {
+                return Err(DatabaseError::Catalog(CatalogError::DuplicatePrimaryKey))
+            }
+            for &column in &columns {
+                if def.columns.get(column).is_some_and(|c| c.column.nullable) {
+                    opened.set_nullable(column, false)?;
+                    def.columns[column].column.nullable = false;
+                }
+            }
+        }
+        let unique = constraint.is_some();
+        let (keys, included) = (columns.clone(), include.clone());
+        let built = opened.create_keyed_index(index, keys, paths.clone(), collations.clone(), included, unique, kind)?;
+        let meta = built.meta_page();
+        def.history = opened.history().clone();
+        let name = index.to_string();
+        def.indexes.push(IndexDef { name, columns, paths, collations, include, meta, constraint, kind });
+        Ok(self.catalog.alter_table(def)?)
+    }
+
+    /// Add a foreign key called `name` on `columns` of the table called `table`, referencing the columns
+    /// `references` of the table called `referenced`, which must be those of one of its unique constraints.
+    /// The foreign key's index over `columns` is built under its name. Fails, changing nothing, if a row
+    /// already there has a key no row of `referenced` holds.
+    pub fn add_foreign_key(
+        &mut self,
+        table: &str,
+        name: &str,
+        columns: Vec<usize>,
+        referenced: &str,
+        references: Vec<usize>,
+        on_delete: OnDelete,
+    ) -> Result<(), DatabaseError> {
+        let mut def = self.table_def(table)?.clone();
+        let parent = self.table_def(referenced)?;
+        let unique = parent.indexes.iter().find(|i| i.constraint.is_some() && i.columns == references);
+        let unique = unique.ok_or_else(|| DatabaseError::NoUniqueKey(referenced.to_string()))?.name.clone();
+        let key = ForeignKey { name: name.to_string(), table: referenced.to_string(), references: unique, on_delete };
+        let mismatch = || DatabaseError::Catalog(CatalogError::ForeignKeyMismatch(name.to_string()));
+        let column = |def: &TableDef, c: usize| {
+            def.columns.get(c).map(|c| c.column).ok_or(DatabaseError::NoSuchColumn(c.to_string()))
+        };
+        for (&c, &r) in columns.iter().zip(&references) {
+            let (column, reference) = (column(&def, c)?, column(parent, r)?);
+            let set_null = on_delete == OnDelete::SetNull;
+            if !column.column_type.same_kind(reference.column_type) || (set_null && !column.nullable) {
+                return Err(mismatch())
+            }
+        }
+        if columns.len() != references.len() {
+            return Err(mismatch())
+        }
+
+        let mut opened = self.open_table(table)?;
+        let parent = if referenced == table { None } else { Some(self.open_table(referenced)?) };
+        for row in opened.scan() {
+            let (_, row) = row?;
+            let values: Vec<Value> = columns.iter().map(|&c| row[c].clone()).collect();
+            if values.iter().any(Value::is_null) {
+                continue
+            }
+            if parent.as_ref().unwrap_or(&opened).lookup_first(&key.references, &values, 1)?.is_empty() {
+                return Err(DatabaseError::ForeignKeyViolation { constraint: name.to_string(), key: values })
+            }
+        }
+        let (paths, collations) = (vec![None; columns.len()], def.collations(&columns));
+        let keys = columns.clone();
+        let kind = IndexKind::BTree;
+        let built = opened.create_keyed_index(name, keys, paths.clone(), collations.clone(), Vec::new(), false, kind)?;
+        let (name, include, meta) = (name.to_string(), Vec::new(), built.meta_page());
+        def.indexes.push(IndexDef { name, columns, paths, collations, include, meta, constraint: None, kind });
+        def.foreign_keys.push(key);
+        Ok(self.catalog.alter_table(def)?)
+    }
+
+    /// Drop the constraint called `name` from the table called `table`: a foreign key along with its index,
+    /// or the index backing a primary key or unique constraint.
+    pub fn drop_constraint(&mut self, table: &str, name: &str) -> Result<(), DatabaseError> {
+        let mut def = self.table_def(table)?.clone();
+        let Some(position) = def.foreign_keys.iter().position(|k| k.name == name) else {
+            return self.drop_index(table, name)
+        };
+        def.foreign_keys.remove(position);
+        let index = def.indexes.iter().position(|i| i.name == name).expect("a foreign key has an index");
+        let index = def.indexes.remove(index);
+        BTree::open(self.store, self.allocator, index.meta).and_then(BTree::free).map_err(TableError::from)?;
+        Ok(self.catalog.alter_table(def)?)
+    }
+
+    /// Drop the table called `name`, every index on it and the sequences it owns, handing all of their pages
+    /// back to the allocator.
+    pub fn drop_table(&mut self, name: &str) -> Result<(), DatabaseError> {
+        if let Some(key) = self.catalog.dependent(name, None) {
+            return Err(DatabaseError::Catalog(CatalogError::Referenced(key.name.clone())))
+        }
+        if let Some(view) = self.catalog.reader(name) {
+            return Err(DatabaseError::Catalog(CatalogError::Referenced(view.name.clone())))
+        }
+        let owned: Vec<String> = self.catalog.owned(name).map(|s| s.name.clone()).collect();
+        match self.table_def(name)?.kind {
+            TableKind::Clustered { .. } => self.open_clustered_table(name)?.free()?,
+            TableKind::Partitioned { .. } => self.open_partitioned_table(name)?.free()?,
+            _ => self.open_table(name)?.free()?,
+        }
+        self.catalog.drop_table(name)?;
+        owned.iter().for_each(|s| _ = self.sequences.remove(s));
+        self.callbacks.retain(|c| c.table != name);
+        Ok(())
+    }
+
+    /// Create the sequence called `name`, whose values start at `start` and go up by `increment`, reserving
+    /// `cache` of them at a time.
+    pub fn create_sequence(&mut self, name: &str, start: i64, increment: i64, cache: u64) -> Result<(), DatabaseError> {
+        if increment == 0 || cache == 0 {
+            return Err(DatabaseError::InvalidSequence(name.to_string()))
+        }
+        let def = SequenceDef { name: name.to_string(), increment, cache, next: start, owner: None };
+        Ok(self.catalog.create_sequence(def)?)
+    }
+
+    /// Drop the sequence called `name`, which no table may own.
+    pub fn drop_sequence(&mut self, name: &str) -> Result<(), DatabaseError> {
+        self.catalog.drop_sequence(name)?;
+        self.sequences.remove(name);
+        Ok(())
+    }
+
+    /// The next value of the sequence called `name`, from the range it reserved last, or from a new range
+    /// that the catalog records first.
+    pub fn next_value(&mut self, name: &str) -> Result<i64, DatabaseError> {
+        let def = self.sequence_def(name)?;
+        let (first, increment, cache) = (def.next, def.increment, def.cache);
+        if let Some((next, left)) = self.sequences.get_mut(name).filter(|(_, left)| *left > 0) {
+            let value = *next;
+            (*next, *left) = (value + increment, *left - 1);
+            return Ok(value)
+        }
+        let range = i64::try_from(cache).ok().and_then(|cache| increment.checked_mul(cache));
+        let end = range.and_then(|range| first.checked_add(range));
+        let end = end.ok_or_else(|| DatabaseError::SequenceExhausted(name.to_string()))?;
+        self.catalog.advance_sequence(name, end)?;
+        self.sequences.insert(name.to_string(), (first + increment, cache - 1));
+        Ok(first)
+    }
+
+    /// Make the sequence for the identity column called `column` of the table called `table`, owned by it.
+    fn add_identity(&mut self, table: &str, column: &str) -> Result<(), DatabaseError> {
+        let name = identity_sequence(table, column);
+        let def = SequenceDef { name, increment: 1, cache: SEQUENCE_CACHE, next: 1, owner: Some(table.to_string()) };
+        Ok(self.catalog.create_sequence(def)?)
+    }
+
+    /// Create an empty keyspace called `name`, sharing the names of tables, views and sequences.
+    pub fn create_keyspace(&mut self, name: &str) -> Result<Keyspace<'store, S>, DatabaseError> {
+        self.create_keyspace_with(name, &KeyspaceOptions::default())
+    }
+
+    /// Create an empty keyspace called `name` as `create_keyspace` does, kept as `options` asks.
+    pub fn create_keyspace_with(
+        &mut self,
+        name: &str,
+        options: &KeyspaceOptions,
+    ) -> Result<Keyspace<'store, S>, DatabaseError> {
+        let keyspace = Keyspace::create_with(self.store, self.allocator, options)?;
+        let def = KeyspaceDef { name: name.to_string(), meta: keyspace.meta_page(), options: *options };
+        self.catalog.create_keyspace(def)?;
+        Ok(keyspace)
+    }
+
+    pub fn open_keyspace(&self, name: &str) -> Result<Keyspace<'store, S>, DatabaseError> {
+        let Some(def) = self.catalog.keyspace(name) else {
+            return Err(DatabaseError::Catalog(CatalogError::NoSuchTable(name.to_string())))
+        };
+        Ok(Keyspace::open_with(self.store, self.allocator, def.meta, &def.options)?)
+    }
+
+    /// Drop the keyspace called `name`, freeing its pages.
+    pub fn drop_keyspace(&mut self, name: &str) -> Result<(), DatabaseError> {
+        self.open_keyspace(name)?.free()?;
+        self.catalog.drop_keyspace(name)?;
+        Ok(())
+    }
+
+    /// Drop the index or constraint called `index` on the table called `table`, freeing its pages.
+    pub fn drop_index(&mut self, table: &str, index: &str) -> Result<(), DatabaseError> {
+        let def = self.table_def(table)?;
+        let Some(index_def) = def.indexes.iter().find(|i| i.name == index) else {
+            return Err(DatabaseError::Catalog(CatalogError::NoSuchIndex(index.to_string())))
+        };
+        if let Some(key) = self.catalog.dependent(table, Some(index)) {
+            return Err(DatabaseError::Catalog(CatalogError::Referenced(key.name.clone())))
+        }
+        BTree::open(self.store, self.allocator, index_def.meta).and_then(BTree::free).map_err(TableError::from)?;
+        self.catalog.drop_index(table, index)?;
+        Ok(())
+    }
+
+    /// Change the columns of the table called `name`. Tables opened before the change must not be used
+    /// after it.
+    pub fn alter_table(&mut self, name: &str, change: AlterTable) -> Result<(), DatabaseError> {
+        let mut def = self.table_def(name)?.clone();
+        let mut table = self.open_table(name)?;
+        let position = |column: &str| def.column(column).ok_or_else(|| DatabaseError::NoSuchColumn(column.to_string()));
+        match change {
+            AlterTable::AddColumn { column, default } => {
+                table.add_column(column.column, default)?;
+                def.columns.push(column);
+            }
+            AlterTable::DropColumn(column) => {
+                let column = position(&column)?;
+                if def.ttl.as_ref().is_some_and(|ttl| ttl.column == column) {
+                    return Err(DatabaseError::Unsupported("dropping the column of a table's TTL"))
+                }
+                table.drop_column(column)?;
+                def.columns.remove(column);
+                if let Some(ttl) = def.ttl.as_mut().filter(|ttl| ttl.column > column) {
+                    ttl.column -= 1;
+                }
+            }
+            AlterTable::SetNullable { column, nullable } => {
+                let column = position(&column)?;
+                table.set_nullable(column, nullable)?;
+                def.columns[column].column.nullable = nullable;
+            }
+            AlterTable::SetDefault { column, default } => {
+                let column = position(&column)?;
+                if let Some(default) = &default {
+                    self.check_default(default, def.columns[column].column)?;
+                }
+                def.columns[column].default = default;
+                return Ok(self.catalog.alter_table(def)?)
+            }
+        }
+        def.history = table.history().clone();
+        def.stats = None;
+        for (index, def) in table.indexes().iter().zip(&mut def.indexes) {
+            def.columns = index.columns().to_vec();
+            def.include = index.include().to_vec();
+        }
+        Ok(self.catalog.alter_table(def)?)
+    }
+
+    /// Gather statistics on the rows of the table called `name` for the planner, replacing any it had.
+    pub fn analyze(&mut self, name: &str) -> Result<TableStats, DatabaseError> {
+        let mut def = self.table_def(name)?.clone();
+        let stats = match def.kind {
+            TableKind::Clustered { .. } => self.open_clustered_table(name)?.analyze(ANALYZE_SAMPLE)?,
+            TableKind::Partitioned { .. } => self.open_partitioned_table(name)?.analyze(ANALYZE_SAMPLE)?,
+            _ => self.open_table(name)?.analyze(ANALYZE_SAMPLE)?,
+        };
+        def.stats = Some(stats.clone());
+        self.catalog.alter_table(def)?;
+        Ok(stats)
+    }
+
+    /// Make the rows of the table called `table` expire under `ttl`, or never with `None`. Nothing is deleted
+    /// until `expire` runs.
+    pub fn set_ttl(&mut self, table: &str, ttl: Option<Ttl>) -> Result<(), DatabaseError> {
+        let mut def = self.heap_def(table)?.clone();
+        def.ttl = ttl;
+        Ok(self.catalog.alter_table(def)?)
+    }
+
+    /// Delete at most `limit` of the rows that have expired under their tables' TTLs, returning how many;
+    /// fewer than `limit` means none are left. They are deleted as `delete_many` deletes rows, so their
+    /// triggers run and the foreign keys referencing them act. A timestamp column's time is compared with
+    /// the current time, and an integer column's with the seconds since the epoch.
+    pub fn expire(&mut self, limit: usize) -> Result<u64, DatabaseError> {
+        let micros = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as i64);
+        let expiring = self.catalog.tables().filter_map(|def| Some((def.name.clone(), def.ttl.clone()?)));
+        let expiring: Vec<_> = expiring.collect();
+        let mut deleted = 0;
+        for (name, ttl) in expiring {
+            if deleted == limit {
+                break
+            }
+            let seconds = self.table_def(&name)?.columns[ttl.column].column.column_type == ColumnType::Int;
+            let now = if seconds { micros / 1_000_000 } else { micros };
+            let rids = self.open_table(&name)?.expired(&ttl, now, limit - deleted)?;
+            self.delete_many(&name, &rids)?;
+            deleted += rids.len();
+        }
+        Ok(deleted as u64)
+    }
+
+    pub fn rename_table(&mut self, name: &str, new_name: &str) -> Result<(), DatabaseError> {
+        self.catalog.rename_table(name, new_name)?;
+        self.callbacks.iter_mut().filter(|c| c.table == name).for_each(|c| c.table = new_name.to_string());
+        Ok(())
+    }
+
+    /// Create the view called `name` over `select`, naming its columns `columns`, or if that is empty as
+    /// the query names them.
+    pub fn create_view(&mut self, name: &str, columns: &[String], select: &Select) -> Result<(), DatabaseError> {
+        let query = planner::plan_select(&self.catalog, select)?;
+        if !query.params.is_empty() {
+            return Err(DatabaseError::Unsupported("parameters in a view"))
+        }
+        let columns = match columns.is_empty() {
+            true => query.columns.clone(),
+            false if columns.len() == query.columns.len() => columns.to_vec(),
+            false => return Err(DatabaseError::Plan(PlanError::InvalidView(name.to_string()))),
+        };
+        let mut reads: Vec<String> = query.plan.tables().into_iter().map(str::to_string).collect();
+        reads.extend(query.views);
+        reads.sort();
+        reads.dedup();
+        let def = ViewDef { name: name.to_string(), columns, query: select.to_string(), reads };
+        Ok(self.catalog.create_view(def)?)
+    }
+
+    pub fn drop_view(&mut self, name: &str) -> Result<(), DatabaseError> {
+        self.catalog.drop_view(name)?;
+        Ok(())
+    }
+
+    /// Add `trigger` to the table called `table`, kept in the catalog. Its body is a script of `INSERT`,
+    /// `UPDATE` and `DELETE` statements, in which `new.a` and `old.a` are the values of the column `a` in the
+    /// rows it runs for.
+    pub fn create_trigger(&mut self, table: &str, trigger: TriggerDef) -> Result<(), DatabaseError> {
+        let writes = |s: &sql::Statement| {
+            matches!(s, sql::Statement::Insert(_) | sql::Statement::Update(_) | sql::Statement::Delete(_))
+        };
+        self.heap_def(table)?;
+        if !sql::parse(&trigger.body)?.iter().all(writes) {
+            return Err(DatabaseError::Unsupported("trigger statements other than INSERT, UPDATE and DELETE"))
+        }
+        if self.callbacks.iter().any(|c| c.table == table && c.name == trigger.name) {
+            return Err(DatabaseError::Catalog(CatalogError::DuplicateTrigger(trigger.name)))
+        }
+        Ok(self.catalog.add_trigger(table, trigger)?)
+    }
+
+    /// Add a trigger called `name` to the table called `table`, running `callback` at `timing` to each row
+    /// written by an `event`. It is not kept in the catalog, and lasts only as long as this `Database`.
+    pub fn register_trigger(
+        &mut self,
+        table: &str,
+        name: &str,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        callback: impl Fn(&mut Database<S>, &mut TriggerRow) -> Result<(), DatabaseError> + 'static,
+    ) -> Result<(), DatabaseError> {
+        let kept = self.heap_def(table)?.triggers.iter().any(|t| t.name == name);
+        if kept || self.callbacks.iter().any(|c| c.table == table && c.name == name) {
+            return Err(DatabaseError::Catalog(CatalogError::DuplicateTrigger(name.to_string())))
+        }
+        let (table, name, callback) = (table.to_string(), name.to_string(), Rc::new(callback));
+        self.callbacks.push(Callback { table, name, timing, event, callback });
+        Ok(())
+    }
+
+    /// Drop the trigger called `name` from the table called `table`, whether it is kept in the catalog or
+    /// was registered.
+    pub fn drop_trigger(&mut self, table: &str, name: &str) -> Result<(), DatabaseError> {
+        match self.callbacks.iter().position(|c| c.table == table && c.name == name) {
+            Some(position) => _ = self.callbacks.remove(position),
+            None => _ = self.catalog.drop_trigger(table, name)?,
+        }
+        Ok(())
+    }
+
+    /// Insert `row` into the table called `table`, once each of its foreign keys is found in the table the
+    /// key references.
+    pub fn insert(&mut self, table: &str, row: &[Value]) -> Result<Rid, DatabaseError> {
+        let row = self.before(table, TriggerEvent::Insert, None, row)?;
+        self.check_foreign_keys(table, &row, None, &[], &mut HashMap::new())?;
+        let rid = self.open_table(table)?.insert(&row)?;
+        self.after(table, TriggerEvent::Insert, None, Some(&row))?;
+        Ok(rid)
+    }
+
+    /// Insert every one of `rows` into the table called `table` at once, through `Table::insert_many`. The
+    /// foreign keys of all of them are checked first, and a key may be one that another of them gives
+    /// itself; a row failing a check or a constraint fails them all, inserting none.
+    pub fn insert_many(&mut self, table: &str, rows: &[Vec<Value>]) -> Result<Vec<Rid>, DatabaseError> {
+        let width = self.table_def(table)?.columns.len();
+        if rows.iter().any(|row| row.len() != width) {
+            return Err(DatabaseError::Table(TableError::Tuple(TupleError::WrongColumnCount)))
+        }
+        let rows: Cow<[Vec<Value>]> = match self.triggered(table, TriggerTiming::Before, TriggerEvent::Insert) {
+            false => Cow::Borrowed(rows),
+            true => {
+                let row = |row: &Vec<Value>| Ok(self.before(table, TriggerEvent::Insert, None, row)?.into_owned());
+                Cow::Owned(rows.iter().map(row).collect::<Result<_, DatabaseError>>()?)
+            }
+        };
+        let mut tables = HashMap::new();
+        for row in rows.iter() {
+            self.check_foreign_keys(table, row, None, &rows, &mut tables)?;
+        }
+        let rids = self.open_table(table)?.insert_many(&rows)?;
+        rows.iter().try_for_each(|row| self.after(table, TriggerEvent::Insert, None, Some(row)))?;
+        Ok(rids)
+    }
+
+    /// Replace the row at `rid` in the table called `table` with `row`. A foreign key it changes must be found
+    /// in the table the key references, and a key of it that another table's rows reference may not change.
+    pub fn update(&mut self, table: &str, rid: Rid, row: &[Value]) -> Result<(), DatabaseError> {
+        let mut opened = self.open_table(table)?;
+        let mut old = opened.get(&rid)?;
+        let row = self.before(table, TriggerEvent::Update, Some(&old), row)?;
+        if let Cow::Owned(_) = row {
+            // The triggers ran, and may have written to the table through tables of their own.
+            opened = self.open_table(table)?;
+            old = opened.get(&rid)?;
+        }
+        self.check_foreign_keys(table, &row, Some(&old), &[], &mut HashMap::new())?;
+        let def = self.table_def(table)?;
+        for (child, key) in self.catalog.referencing(table) {
+            let columns = &def.index(&key.references).expect("a foreign key references an index").columns;
+            let values: Vec<Value> = columns.iter().map(|&c| old[c].clone()).collect();
+            if values.iter().any(Value::is_null) || columns.iter().all(|&c| row.get(c) == Some(&old[c])) {
+                continue
+            }
+            let referencing = match child.name == table {
+                true => opened.lookup(&key.name, &values)?.into_iter().any(|r| r != rid),
+                false => !self.open_table(&child.name)?.lookup_first(&key.name, &values, 1)?.is_empty(),
+            };
+            if referencing {
+                return Err(DatabaseError::ForeignKeyViolation { constraint: key.name.clone(), key: values })
+            }
+        }
+        opened.update(&rid, &row)?;
+        self.after(table, TriggerEvent::Update, Some(&old), Some(&row))
+    }
+
+    /// Delete the row at `rid` from the table called `table`. The rows foreign keys reference it by are
+    /// deleted too by an `ON DELETE CASCADE` key, and have the key set to null by an `ON DELETE SET NULL`
+    /// one; a `RESTRICT` key referencing any row to be deleted fails the delete. Every row affected is found
+    /// before any is written, so a failed delete changes nothing.
+    pub fn delete(&mut self, table: &str, rid: Rid) -> Result<(), DatabaseError> {
+        self.delete_many(table, &[rid])
+    }
+
+    /// Delete the rows at `rids` from the table called `table` as one, as `delete` deletes one: a row that
+    /// any of them cascades to is deleted once, and a failure deletes none of them. The `BEFORE` triggers of
+    /// every row deleted or set to null run before any is written, and the `AFTER` triggers once all are.
+    pub fn delete_many(&mut self, table: &str, rids: &[Rid]) -> Result<(), DatabaseError> {
+        let mut tables = HashMap::new();
+        let mut deletes: Vec<_> = rids.iter().map(|&rid| (table.to_string(), rid)).collect();
+        let mut deleted: HashSet<_> = deletes.iter().cloned().collect();
+        let (mut nulls, mut triggering) = (Vec::new(), Vec::new());
+        let mut next = 0;
+        while let Some((name, rid)) = deletes.get(next).cloned() {
+            next += 1;
+            let row = self.cached(&mut tables, &name)?.get(&rid)?;
+            let timings = [TriggerTiming::Before, TriggerTiming::After];
+            if timings.into_iter().any(|t| self.triggered(&name, t, TriggerEvent::Delete)) {
+                triggering.push((name.clone(), row.clone()));
+            }
+            let def = self.table_def(&name)?;
+            for (child, key) in self.catalog.referencing(&name) {
+                let columns = &def.index(&key.references).expect("a foreign key references an index").columns;
+                let values: Vec<Value> = columns.iter().map(|&c| row[c].clone()).collect();
+                if values.iter().any(Value::is_null) {
+                    continue
+                }
+                for found in self.cached(&mut tables, &child.name)?.lookup(&key.name, &values)? {
+                    let found = (child.name.clone(), found);
+                    if deleted.contains(&found) {
+                        continue
+                    }
+                    match key.on_delete {
+                        OnDelete::Restrict => {
+                            let constraint = key.name.clone();
+                            return Err(DatabaseError::ForeignKeyViolation { constraint, key: values })
+                        }
+                        OnDelete::Cascade => {
+                            deleted.insert(found.clone());
+                            deletes.push(found);
+                        }
+                        OnDelete::SetNull => {
+                            let columns = &child.index(&key.name).expect("a foreign key has an index").columns;
+                            nulls.push((found, columns.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        // Each row set to null is updated once, with the columns of every key it is set to null by.
+        let (mut updates, mut positions) = (Vec::new(), HashMap::new());
+        for ((name, rid), columns) in nulls {
+            if deleted.contains(&(name.clone(), rid)) {
+                continue
+            }
+            let position = match positions.entry((name.clone(), rid)) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    let old = self.cached(&mut tables, &name)?.get(&rid)?;
+                    updates.push((name, rid, old.clone(), old));
+                    *entry.insert(updates.len() - 1)
+                }
+            };
+            columns.iter().for_each(|&c| updates[position].3[c] = Value::Null);
+        }
+        let mut fired = false;
+        for (name, old) in &triggering {
+            if self.triggered(name, TriggerTiming::Before, TriggerEvent::Delete) {
+                let mut row = TriggerRow { old: Some(old.clone()), new: None };
+                self.fire(name, TriggerTiming::Before, TriggerEvent::Delete, &mut row)?;
+                fired = true;
+            }
+        }
+        for (name, _, old, new) in &mut updates {
+            if let Cow::Owned(row) = self.before(name, TriggerEvent::Update, Some(old), new)? {
+                *new = row;
+                fired = true;
+            }
+        }
+        if fired {
+            // The triggers may have written to the tables opened so far, through tables of their own.
+            tables.clear();
+        }
+        for (name, rid, _, new) in &updates {
+            self.cached(&mut tables, name)?.update(rid, new)?;
+        }
+        for (name, rid) in deletes {
+            self.cached(&mut tables, &name)?.delete(&rid)?;
+        }
+        for (name, old) in &triggering {
+            self.after(name, TriggerEvent::Delete, Some(old), None)?;
+        }
+        for (name, _, old, new) in &updates {
+            self.after(name, TriggerEvent::Update, Some(old), Some(new))?;
+        }
+        Ok(())
+    }
+
+    /// Whether the table called `table` has a trigger running at `timing` to writes of `event`.
+    fn triggered(&self, table: &str, timing: TriggerTiming, event: TriggerEvent) -> bool {
+        let registered = self.callbacks.iter().any(|c| c.table == table && c.timing == timing && c.event == event);
+        registered || self.catalog.table(table).is_some_and(|def| !def.triggers(timing, event).is_empty())
+    }
+
+    /// The row to write to the table called `table` for `new`, in place of `old` if it replaces a row: `new`
+    /// as the `BEFORE` triggers of the table for `event` leave it, or `new` itself if it has none.
+    fn before<'a>(
+        &mut self,
+        table: &str,
+        event: TriggerEvent,
+        old: Option<&[Value]>,
+        new: &'a [Value],
+    ) -> Result<Cow<'a, [Value]>, DatabaseError> {
+        if !self.triggered(table, TriggerTiming::Before, event) {
+            return Ok(Cow::Borrowed(new))
+        }
+        let mut row = TriggerRow { old: old.map(<[Value]>::to_vec), new: Some(new.to_vec()) };
+        self.fire(table, TriggerTiming::Before, event, &mut row)?;
+        Ok(Cow::Owned(row.new.unwrap_or_default()))
+    }
+
+    /// Run the `AFTER` triggers of the table called `table` for `event`, which wrote `new` in place of `old`.
+    fn after(
+        &mut self,
+        table: &str,
+        event: TriggerEvent,
+        old: Option<&[Value]>,
+        new: Option<&[Value]>,
+    ) -> Result<(), DatabaseError> {
+        if !self.triggered(table, TriggerTiming::After, event) {
+            return Ok(())
+        }
+        let mut row = TriggerRow { old: old.map(<[Value]>::to_vec), new: new.map(<[Value]>::to_vec) };
+        self.fire(table, TriggerTiming::After, event, &mut row)
+    }
+
+    /// Run the triggers of the table called `table` that run at `timing` to writes of `event` for `row`, kept
+    /// and registered ones alike in the order of their names. A trigger's writes run the triggers of the
+    /// tables they write to in turn, up to `TRIGGER_DEPTH` deep.
+    fn fire(
+        &mut self,
+        table: &str,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        row: &mut TriggerRow,
+    ) -> Result<(), DatabaseError> {
+        let def = self.table_def(table)?;
+        let columns: Vec<String> = def.columns.iter().map(|c| c.name.clone()).collect();
+        let kept = def.triggers(timing, event).into_iter().map(|t| (t.name.clone(), TriggerBody::Sql(t.body.clone())));
+        let mut triggers: Vec<_> = kept.collect();
+        let registered = self.callbacks.iter().filter(|c| c.table == table && c.timing == timing && c.event == event);
+        triggers.extend(registered.map(|c| (c.name.clone(), TriggerBody::Callback(c.callback.clone()))));
+        triggers.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, trigger) in triggers {
+            if self.trigger_depth == TRIGGER_DEPTH {
+                return Err(DatabaseError::TriggerDepth(name))
+            }
+            self.trigger_depth += 1;
+            let ran = match trigger {
+                TriggerBody::Sql(body) => self.run_trigger(&body, &columns, row),
+                TriggerBody::Callback(callback) => callback(self, row),
+            };
+            self.trigger_depth -= 1;
+            ran?;
+        }
+        Ok(())
+    }
+
+    /// Run the statements of a trigger's `body` for `row` of a table with `columns`, each `new.a` or `old.a`
+    /// in them replaced by the value of the column `a` in that row.
+    fn run_trigger(&mut self, body: &str, columns: &[String], row: &TriggerRow) -> Result<(), DatabaseError> {
+        for mut statement in sql::parse(body)? {
+            let mut missing = None;
+            statement.visit_exprs(&mut |expr| {
+                let Expr::Column { table: Some(table), name } = expr else { return };
+                let values = match table.as_str() {
+                    "new" => &row.new,
+                    "old" => &row.old,
+                    _ => return,
+                };
+                match (values, columns.iter().position(|c| c == name)) {
+                    (Some(values), Some(column)) => *expr = Expr::Literal(values[column].clone()),
+                    _ => missing = Some(format!("{table}.{name}")),
+                }
+            });
+            if let Some(column) = missing {
+                return Err(DatabaseError::NoSuchColumn(column))
+            }
+            self.run(statement)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the foreign keys of `row`, to be written to the table called `table` in place of `old` if
+    /// it replaces a row, are found in the tables they reference. A key with a null, one `old` has too, and one
+    /// a row of the table gives itself or one of the `batch` written along with it gives all pass. The
+    /// referenced tables are opened into `tables`.
+    fn check_foreign_keys(
+        &self,
+        table: &str,
+        row: &[Value],
+        old: Option<&[Value]>,
+        batch: &[Vec<Value>],
+        tables: &mut HashMap<String, Table<'store, S>>,
+    ) -> Result<(), DatabaseError> {
+        let def = self.table_def(table)?;
+        if row.len() != def.columns.len() {
+            return Err(DatabaseError::Table(TableError::Tuple(TupleError::WrongColumnCount)))
+        }
+        for key in &def.foreign_keys {
+            let columns = &def.index(&key.name).expect("a foreign key has an index").columns;
+            let values: Vec<Value> = columns.iter().map(|&c| row[c].clone()).collect();
+            if values.iter().any(Value::is_null) || old.is_some_and(|old| columns.iter().all(|&c| old[c] == row[c])) {
+                continue
+            }
+            let parent = self.table_def(&key.table)?;
+            let references = &parent.index(&key.references).expect("a foreign key references an index").columns;
+            let mut written = std::iter::once(row).chain(batch.iter().map(Vec::as_slice));
+            if key.table == table && written.any(|r| references.iter().map(|&c| &r[c]).eq(&values)) {
+                continue
+            }
+            if self.cached(tables, &key.table)?.lookup_first(&key.references, &values, 1)?.is_empty() {
+                return Err(DatabaseError::ForeignKeyViolation { constraint: key.name.clone(), key: values })
+            }
+        }
+        Ok(())
+    }
+
+    /// The table called `name` from `tables`, opened and added to them if it is not there yet.
+    fn cached<'a>(
+        &self,
+        tables: &'a mut HashMap<String, Table<'store, S>>,
+        name: &str,
+    ) -> Result<&'a mut Table<'store, S>, DatabaseError> {
+        Ok(match tables.entry(name.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(self.open_table(name)?),
+        })
+    }
+
+    /// Copy the records of the CSV `input` into the table called `table` as rows, returning how many. Each
+    /// field is read as a value of its column's type, and an unquoted field holding `options.null` as null; a
+    /// column the header leaves out takes its default. Rows go to `insert_many` `options.batch_rows` at a
+    /// time, and the store is flushed after each batch, committing it over a `ShadowStorage`; a batch
+    /// that fails writes none of its rows, and what comes after it is not read.
+    pub fn copy_from_csv(&mut self, table: &str, input: impl Read, options: &CsvOptions) -> Result<u64, DatabaseError> {
+        let def = self.table_def(table)?.clone();
+        let mut records = Records::new(BufReader::new(input), options.delimiter);
+        let columns: Vec<usize> = match options.header {
+            false => (0..def.columns.len()).collect(),
+            true => {
+                let Some((line, header)) = records.next_record()? else { return Ok(0) };
+                let mut columns = Vec::with_capacity(header.len());
+                for field in header {
+                    match def.column(&field.text) {
+                        Some(c) if !columns.contains(&c) => columns.push(c),
+                        _ => return Err(DatabaseError::Csv { line, error: CsvError::NoSuchColumn(field.text) }),
+                    }
+                }
+                columns
+            }
+        };
+        records.expect_fields(columns.len());
+        let (mut count, mut rows, mut lines) = (0, Vec::new(), (0, 0));
+        while let Some((line, fields)) = records.next_record()? {
+            if fields.len() != columns.len() {
+                let error = CsvError::FieldCount { expected: columns.len(), found: fields.len() };
+                return Err(DatabaseError::Csv { line, error })
+            }
+            let mut row = vec![None; def.columns.len()];
+            for (&c, field) in columns.iter().zip(fields) {
+                let column = &def.columns[c];
+                row[c] = Some(match field {
+                    Field { text, quoted: false } if text == options.null => Value::Null,
+                    Field { text, .. } => csv_value(&text, column.column.column_type).ok_or_else(|| {
+                        let error = CsvError::InvalidValue { column: column.name.clone(), value: text.clone() };
+                        DatabaseError::Csv { line, error }
+                    })?,
+                });
+            }
+            let row = row.into_iter().zip(&def.columns).map(|(value, column)| match (value, &column.default) {
+                (Some(value), _) => Ok(value),
+                (None, Some(default)) => self.evaluate_default(default, column.column),
+                (None, None) => Ok(Value::Null),
+            });
+            rows.push(row.collect::<Result<Vec<_>, _>>()?);
+            lines = (if rows.len() == 1 { line } else { lines.0 }, line);
+            if rows.len() >= options.batch_rows.max(1) {
+                count += self.copy_batch(table, &rows, lines)?;
+                rows.clear();
+            }
+        }
+        if !rows.is_empty() {
+            count += self.copy_batch(table, &rows, lines)?;
+        }
+        Ok(count)
+    }
+
+    /// Insert and commit the rows read from `lines`, the first and last line of their CSV.
+    fn copy_batch(&mut self, table: &str, rows: &[Vec<Value>], lines: (u64, u64)) -> Result<u64, DatabaseError> {
+        if let Err(e) = self.insert_batch(table, rows) {
+            let error = CsvError::Rejected { last_line: lines.1, error: Box::new(e) };
+            return Err(DatabaseError::Csv { line: lines.0, error })
+        }
+        self.store.flush()?;
+        Ok(rows.len() as u64)
+    }
+
+    /// Write the rows of the table called `table` to `out` as CSV records, returning how many, after a header
+    /// naming its columns if `options.header` says to. Values are written as a cast to text writes them, and
+    /// bytes in hex after `\x`, which is how `copy_from_csv` reads them back.
+    pub fn copy_to_csv(&self, table: &str, mut out: impl Write, options: &CsvOptions) -> Result<u64, DatabaseError> {
+        let def = self.table_def(table)?;
+        let failed = |line| move |e: io::Error| DatabaseError::Csv { line, error: CsvError::Io(e.kind()) };
+        let mut line = 1;
+        if options.header {
+            let names = def.columns.iter().map(|c| Some(c.name.as_str()));
+            write_record(&mut out, names, options).map_err(failed(line))?;
+            line += 1;
+        }
+        let mut count = 0;
+        self.each_row(table, |row| {
+            let texts = row.iter().map(csv_text).collect::<Result<Vec<_>, _>>()?;
+            write_record(&mut out, texts.iter().map(Option::as_deref), options).map_err(failed(line))?;
+            line += 1 + texts.iter().flatten().map(|text| text.matches('\n').count() as u64).sum::<u64>();
+            count += 1;
+            Ok(())
+        })?;
+        out.flush().map_err(failed(line))?;
+        Ok(count)
+    }
+
+    /// Run the DDL statement in `sql`: `CREATE TABLE` with its constraints, `CREATE [UNIQUE] INDEX`, `CREATE
+    /// VIEW`, `CREATE SEQUENCE`, `CREATE TRIGGER`, `DROP TABLE`, `DROP INDEX`, `DROP VIEW`, `DROP SEQUENCE`,
+    /// `DROP TRIGGER` or `ALTER TABLE`; or an `INSERT` of constant values or of a query's rows, an `UPDATE` or
+    /// a `DELETE`, returning the number of rows it inserted, updated or deleted. A constraint with no name of
+    /// its own gets one made from the table's: `t_pkey` for a primary key and `t_a_b_key` for `UNIQUE (a, b)`.
+    pub fn execute(&mut self, sql: &str) -> Result<u64, DatabaseError> {
+        self.execute_with(sql, &[])
+    }
+
+    /// Run the statement in `sql` as `execute` does, with `params` as the values of its bind parameters, each
+    /// put in place of its parameter before the statement runs.
+    pub fn execute_with(&mut self, sql: &str, params: &[Value]) -> Result<u64, DatabaseError> {
+        let mut statement = sql::parse_statement(sql)?;
+        let mut missing = None;
+        statement.visit_exprs(&mut |expr| {
+            let Expr::Parameter(n) = *expr else { return };
+            match params.get(n) {
+                Some(value) => *expr = Expr::Literal(value.clone()),
+                None => missing = missing.or(Some(n)),
+            }
+        });
+        if let Some(n) = missing {
+            return Err(DatabaseError::Exec(ExecError::MissingParameter(n)))
+        }
+        self.run(statement)
+    }
+
+    /// The type each bind parameter of the `INSERT`, `UPDATE` or `DELETE` in `sql` must have, numbered from 0,
+    /// or `None` for one that may hold any value, as `Statement::params` gives them for a query. A value
+    /// inserted or assigned as a parameter on its own takes its column's type; the parameters of a filter, or
+    /// of the query an `INSERT` takes its rows from, are typed as the query planner types them.
+    pub fn write_params(&self, sql: &str) -> Result<Vec<Option<ColumnType>>, DatabaseError> {
+        let (table, values, filter) = match sql::parse_statement(sql)? {
+            sql::Statement::Insert(insert) => {
+                let def = self.table_def(&insert.table)?;
+                let names: Vec<String> = match insert.columns.is_empty() {
+                    true => def.columns.iter().map(|c| c.name.clone()).collect(),
+                    false => insert.columns.clone(),
+                };
+                match insert.source {
+                    InsertSource::Values(rows) => {
+                        let values = rows.into_iter().flat_map(|row| names.clone().into_iter().zip(row));
+                        (insert.table, values.collect(), None)
+                    }
+                    InsertSource::Query(select) => return Ok(self.plan(&select)?.0.params),
+                }
+            }
+            sql::Statement::Update(update) => (update.table, update.assignments, update.filter),
+            sql::Statement::Delete(delete) => (delete.table, Vec::new(), delete.filter),
+            _ => return Ok(Vec::new()),
+        };
+        let def = self.table_def(&table)?;
+        let select = Select {
+            with: None,
+            distinct: None,
+            items: vec![sql::SelectItem::Wildcard(None)],
+            from: Some(sql::FromItem::Table { name: table.clone(), alias: None }),
+            filter,
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+        };
+        let mut params = self.plan(&select)?.0.params;
+        for (name, value) in values {
+            let (Expr::Parameter(n), Some(column)) = (value, def.column(&name)) else { continue };
+            if params.len() <= n {
+                params.resize(n + 1, None);
+            }
+            params[n].get_or_insert(def.columns[column].column.column_type);
+        }
+        Ok(params)
+    }
+
+    /// Run `statement` as `execute` runs the statement it parses.
+    fn run(&mut self, statement: sql::Statement) -> Result<u64, DatabaseError> {
+        match statement {
+            sql::Statement::CreateTable(create) => {
+                if create.if_not_exists && self.catalog.table(&create.name).is_some() {
+                    return Ok(0)
+                }
+                if create.without_rowid {
+                    return self.create_without_rowid(&create).map(|_| 0)
+                }
+                if let Some(partitioning) = &create.partition_by {
+                    return self.create_partitioned(&create, partitioning).map(|_| 0)
+                }
+                let columns = create.columns.iter().map(|c| self.column_def(&create.name, c));
+                self.create_table(&create.name, columns.collect::<Result<_, _>>()?)?;
+                let mut identities = create.columns.iter().filter(|c| c.identity);
+                let declared = identities
+                    .try_for_each(|c| self.add_identity(&create.name, &c.name))
+                    .and_then(|_| create.constraints.iter().try_for_each(|c| self.add_declared(&create.name, c)))
+                    .and_then(|_| match &create.ttl {
+                        Some((column, lifetime)) => self.declare_ttl(&create.name, column, lifetime),
+                        None => Ok(()),
+                    });
+                if let Err(e) = declared {
+                    self.drop_table(&create.name)?;
+                    return Err(e)
+                }
+            }
+            sql::Statement::CreateIndex(create) => {
+                let def = self.table_def(&create.table)?;
+                let position = |name: &String| def.column(name).ok_or(DatabaseError::NoSuchColumn(name.clone()));
+                let names: Vec<String> = def.columns.iter().map(|c| c.name.clone()).collect();
+                // A key given no collation of its own takes its column's, and a path into a document binary.
+                let key = |expr: &Expr| match expr {
+                    Expr::Collate { expr, collation } => match &**expr {
+                        Expr::Column { table: None, name } => Ok((position(name)?, None, *collation)),
+                        _ => Err(DatabaseError::Unsupported("collations on index keys other than columns")),
+                    },
+                    Expr::Column { table: None, name } => {
+                        let column = position(name)?;
+                        Ok((column, None, def.columns[column].collation))
+                    }
+                    expr => match planner::bind_row(&def.name, &names, ("", &[]), expr)?.json_path() {
+                        Some((column, path)) => Ok((column, Some(path), Collation::Binary)),
+                        None => Err(DatabaseError::Unsupported("index keys other than columns and paths into them")),
+                    },
+                };
+                let keys = create.columns.iter().map(key).collect::<Result<Vec<_>, DatabaseError>>()?;
+                let columns = keys.iter().map(|k| k.0).collect();
+                let paths = keys.iter().map(|k| k.1.clone()).collect();
+                let collations = keys.iter().map(|k| k.2).collect();
+                let include = create.include.iter().map(position).collect::<Result<_, _>>()?;
+                let constraint = create.unique.then_some(Constraint::Unique);
+                let (table, name) = (&create.table, &create.name);
+                self.add_index(table, name, columns, paths, collations, include, constraint, create.kind)?;
+            }
+            sql::Statement::DropTable { name, if_exists } => {
+                if !if_exists || self.catalog.table(&name).is_some() {
+                    self.drop_table(&name)?;
+                }
+            }
+            sql::Statement::DropIndex { name, table } => {
+                self.drop_index(&table, &name)?;
+            }
+            sql::Statement::CreateView(create) => self.create_view(&create.name, &create.columns, &create.query)?,
+            sql::Statement::DropView { name, if_exists } => {
+                if !if_exists || self.catalog.view(&name).is_some() {
+                    self.drop_view(&name)?;
+                }
+            }
+            sql::Statement::CreateSequence(create) => {
+                let increment = create.increment.unwrap_or(1);
+                let start = create.start.unwrap_or(if increment < 0 { -1 } else { 1 });
+                self.create_sequence(&create.name, start, increment, create.cache.unwrap_or(SEQUENCE_CACHE))?;
+            }
+            sql::Statement::DropSequence { name, if_exists } => {
+                if !if_exists || self.catalog.sequence(&name).is_some() {
+                    self.drop_sequence(&name)?;
+                }
+            }
+            sql::Statement::CreateTrigger(create) => {
+                let sql::CreateTrigger { name, timing, event, table, body } = create;
+                self.create_trigger(&table, TriggerDef { name, timing, event, body })?;
+            }
+            sql::Statement::DropTrigger { name, table } => self.drop_trigger(&table, &name)?,
+            sql::Statement::AlterTable { table, change } => {
+                let change = match change {
+                    AlterColumn::Add(c) if c.identity => {
+                        return Err(DatabaseError::Unsupported("adding an identity column"))
+                    }
+                    AlterColumn::Add(c) => {
+                        let column = self.column_def(&table, &c)?;
+                        let default = match &column.default {
+                            Some(default) => self.evaluate_default(default, column.column)?,
+                            None => Value::Null,
+                        };
+                        AlterTable::AddColumn { column, default }
+                    }
+                    AlterColumn::Drop(column) => AlterTable::DropColumn(column),
+                    AlterColumn::SetNullable { column, nullable } => AlterTable::SetNullable { column, nullable },
+                    AlterColumn::SetDefault { column, default } => {
+                        AlterTable::SetDefault { column, default: default.map(|e| e.to_string()) }
+                    }
+                    AlterColumn::AddConstraint(constraint) => {
+                        return self.add_declared(&table, &constraint).map(|_| 0)
+                    }
+                    AlterColumn::DropConstraint(name) => return self.drop_constraint(&table, &name).map(|_| 0),
+                    AlterColumn::AddPartition(lower) => {
+                        let def = self.table_def(&table)?;
+                        let TableKind::Partitioned { by } = def.kind else {
+                            return Err(DatabaseError::NotAPartitionedTable(table))
+                        };
+                        let lower = evaluate(&lower, def.columns[by.column()].column)?;
+                        return self.add_partition(&table, lower).map(|_| 0)
+                    }
+                };
+                self.alter_table(&table, change)?;
+            }
+            sql::Statement::Select(_) => return Err(DatabaseError::Unsupported("SELECT outside `query`")),
+            sql::Statement::Explain { .. } => return Err(DatabaseError::Unsupported("EXPLAIN outside `query`")),
+            sql::Statement::Insert(insert) => return self.insert_values(&insert),
+            sql::Statement::Update(update) => return self.update_where(&update),
+            sql::Statement::Delete(delete) => return self.delete_where(&delete),
+        }
+        Ok(0)
+    }
+
+    /// Create the clustered table that `create`, a `CREATE TABLE ... WITHOUT ROWID`, declares, keyed by its
+    /// primary key, whose columns are made not nullable. The primary key is the only constraint such a table
+    /// can have, and its rows cannot expire.
+    fn create_without_rowid(&mut self, create: &sql::CreateTable) -> Result<(), DatabaseError> {
+        let [TableConstraint { kind: ConstraintKind::PrimaryKey(names), .. }] = &create.constraints[..] else {
+            return Err(DatabaseError::Unsupported("WITHOUT ROWID tables with constraints but one primary key"))
+        };
+        if create.ttl.is_some() {
+            return Err(DatabaseError::Unsupported("TTLs on WITHOUT ROWID tables"))
+        }
+        let columns = create.columns.iter().map(|c| self.column_def(&create.name, c));
+        let mut columns = columns.collect::<Result<Vec<_>, _>>()?;
+        let position = |name: &String| {
+            columns.iter().position(|c| c.name == *name).ok_or(DatabaseError::NoSuchColumn(name.clone()))
+        };
+        let primary_key = names.iter().map(position).collect::<Result<Vec<_>, _>>()?;
+        primary_key.iter().for_each(|&c| columns[c].column.nullable = false);
+        self.create_clustered_table(&create.name, columns, primary_key)?;
+        let mut identities = create.columns.iter().filter(|c| c.identity);
+        if let Err(e) = identities.try_for_each(|c| self.add_identity(&create.name, &c.name)) {
+            self.drop_table(&create.name)?;
+            return Err(e)
+        }
+        Ok(())
+    }
+
+    /// Create the partitioned table that `create`, a `CREATE TABLE ... PARTITION BY`, declares. Such a table
+    /// can have no constraints, and its rows cannot expire.
+    fn create_partitioned(
+        &mut self,
+        create: &sql::CreateTable,
+        partitioning: &sql::Partitioning,
+    ) -> Result<(), DatabaseError> {
+        if create.without_rowid {
+            return Err(DatabaseError::Unsupported("partitioned WITHOUT ROWID tables"))
+        }
+        if !create.constraints.is_empty() {
+            return Err(DatabaseError::Unsupported("constraints on partitioned tables"))
+        }
+        if create.ttl.is_some() {
+            return Err(DatabaseError::Unsupported("TTLs on partitioned tables"))
+        }
+        let columns = create.columns.iter().map(|c| self.column_def(&create.name, c));
+        let columns = columns.collect::<Result<Vec<_>, _>>()?;
+        let position = |name: &String| {
+            columns.iter().position(|c| c.name == *name).ok_or(DatabaseError::NoSuchColumn(name.clone()))
+        };
+        let by = match partitioning {
+            sql::Partitioning::Range(column) => PartitionBy::Range(position(column)?),
+            sql::Partitioning::Hash { column, partitions } => {
+                PartitionBy::Hash { column: position(column)?, partitions: *partitions }
+            }
+        };
+        self.create_partitioned_table(&create.name, columns, by)?;
+        let mut identities = create.columns.iter().filter(|c| c.identity);
+        if let Err(e) = identities.try_for_each(|c| self.add_identity(&create.name, &c.name)) {
+            self.drop_table(&create.name)?;
+            return Err(e)
+        }
+        Ok(())
+    }
+
+    /// Insert the rows of `insert`, each value cast to its column's type as in an assignment. A column the
+    /// insert leaves out takes its default, evaluated anew for each row. The rows of a query are all read
+    /// before any is written, so a query may read the table it inserts into, and rows go to `insert_batch`
+    /// `INSERT_BATCH` at a time.
+    fn insert_values(&mut self, insert: &sql::Insert) -> Result<u64, DatabaseError> {
+        let def = self.table_def(&insert.table)?.clone();
+        let columns: Vec<usize> = match insert.columns.is_empty() {
+            true => (0..def.columns.len()).collect(),
+            false => {
+                let position = |name: &String| def.column(name).ok_or(DatabaseError::NoSuchColumn(name.clone()));
+                insert.columns.iter().map(position).collect::<Result<_, _>>()?
+            }
+        };
+        let defaults: Vec<(Column, Option<String>)> =
+            def.columns.iter().map(|c| (c.column, c.default.clone())).collect();
+        let sources = match &insert.source {
+            InsertSource::Values(rows) => {
+                let mut constant = |v: &Expr| -> Result<_, DatabaseError> {
+                    let mut v = v.clone();
+                    next_values(&mut v, &mut |name| self.next_value(name))?;
+                    Ok(planner::bind_constant(&v)?.eval(&[])?)
+                };
+                let values = rows.iter().map(|values| values.iter().map(&mut constant).collect());
+                values.collect::<Result<Vec<Vec<_>>, _>>()?
+            }
+            InsertSource::Query(select) => self.prepared((**select).clone())?.query(self, &[])?.into_result()?.rows,
+        };
+        let (count, mut rows) = (sources.len() as u64, Vec::with_capacity(sources.len().min(INSERT_BATCH)));
+        if insert.on_conflict.is_some() && def.kind != TableKind::Heap {
+            return Err(DatabaseError::Unsupported("ON CONFLICT on tables other than heap tables"))
+        }
+        let mut upsert = insert.on_conflict.as_ref().map(|on| Upsert::new(&def, on)).transpose()?;
+        let mut upserted = 0;
+        for values in sources {
+            if values.len() != columns.len() {
+                return Err(DatabaseError::Table(TableError::Tuple(TupleError::WrongColumnCount)))
+            }
+            let mut row = vec![None; defaults.len()];
+            for (&c, value) in columns.iter().zip(values) {
+                row[c] = Some(exec::expr::cast(value, defaults[c].0.column_type)?);
+            }
+            let row = row.into_iter().zip(&defaults).map(|(value, (column, default))| match (value, default) {
+                (Some(value), _) => Ok(value),
+                (None, Some(default)) => self.evaluate_default(default, *column),
+                (None, None) => Ok(Value::Null),
+            });
+            let row = row.collect::<Result<Vec<_>, _>>()?;
+            if let Some(upsert) = &mut upsert {
+                upserted += self.upsert(&insert.table, row, upsert)?;
+                continue
+            }
+            rows.push(row);
+            if rows.len() == INSERT_BATCH {
+                self.insert_batch(&insert.table, &rows)?;
+                rows.clear();
+            }
+        }
+        if upsert.is_some() {
+            return Ok(upserted)
+        }
+        self.insert_batch(&insert.table, &rows)?;
+        Ok(count)
+    }
+
+    /// Insert `rows` into the table called `table` as one: through `insert_many` into a heap table, and into
+    /// a clustered or partitioned table a row at a time, deleting the rows inserted before one that fails.
+    fn insert_batch(&mut self, table: &str, rows: &[Vec<Value>]) -> Result<(), DatabaseError> {
+        match self.table_def(table)?.kind {
+            TableKind::Clustered { .. } => {}
+            TableKind::Partitioned { .. } => {
+                let mut opened = self.open_partitioned_table(table)?;
+                let mut inserted = Vec::with_capacity(rows.len());
+                for row in rows {
+                    match opened.insert(row) {
+                        Ok(rid) => inserted.push(rid),
+                        Err(e) => {
+                            inserted.iter().try_for_each(|rid| opened.delete(rid))?;
+                            return Err(e.into())
+                        }
+                    }
+                }
+                return Ok(())
+            }
+            _ => return self.insert_many(table, rows).map(|_| ()),
+        }
+        let mut opened = self.open_clustered_table(table)?;
+        for (i, row) in rows.iter().enumerate() {
+            if let Err(e) = opened.insert(row) {
+                for row in &rows[..i] {
+                    opened.delete(&opened.key_values(row))?;
+                }
+                return Err(e.into())
+            }
+        }
+        Ok(())
+    }
+
+    /// Insert `row` into the table called `table` unless it conflicts with a row there in a unique index
+    /// `upsert` handles, and otherwise do what `upsert` does with the conflict. A conflict is found by the
+    /// insert itself, which checks the unique indexes before writing anything, and resolved from the key the
+    /// violation names. Returns the number of rows inserted or updated.
+    fn upsert(&mut self, table: &str, row: Vec<Value>, upsert: &Upsert) -> Result<u64, DatabaseError> {
+        let (index, key) = match self.insert(table, &row) {
+            Ok(_) => return Ok(1),
+            Err(DatabaseError::Table(TableError::UniqueViolation { index, key }))
+                if upsert.index.as_ref().is_none_or(|i| *i == index) => (index, key),
+            Err(e) => return Err(e),
+        };
+        if !upsert.update {
+            return Ok(0)
+        }
+        let opened = self.open_table(table)?;
+        let rid = opened.lookup(&index, &key)?[0];
+        let old = opened.get(&rid)?;
+        let eval = |expr: &exec::Expr| {
+            let mut expr = expr.clone();
+            expr.bind_outer(&row);
+            expr.eval(&old)
+        };
+        if upsert.filter.as_ref().map(eval).transpose()?.is_some_and(|v| v != Value::Bool(true)) {
+            return Ok(0)
+        }
+        let mut new = old.clone();
+        for &(column, ref expr, column_type) in &upsert.assignments {
+            new[column] = exec::expr::cast(eval(expr)?, column_type)?;
+        }
+        self.update(table, rid, &new)?;
+        Ok(1)
+    }
+
+    /// Update the rows of the table `update` names that its filter holds for, each assigned value cast to
+    /// its column's type. Every row is found and given its new values before any is written; then each is
+    /// written as `update` writes it, and a row that fails stops the statement after the rows before it.
+    fn update_where(&mut self, update: &sql::Update) -> Result<u64, DatabaseError> {
+        let def = self.table_def(&update.table)?;
+        let column = |name: &String| Expr::Column { table: Some(update.table.clone()), name: name.clone() };
+        let mut exprs: Vec<Expr> = def.columns.iter().map(|c| column(&c.name)).collect();
+        for (name, expr) in &update.assignments {
+            let position = def.column(name).ok_or(DatabaseError::NoSuchColumn(name.clone()))?;
+            exprs[position] = expr.clone();
+        }
+        let types: Vec<ColumnType> = def.columns.iter().map(|c| c.column.column_type).collect();
+        let kind = def.kind.clone();
+        let rows = self.write_rows(&update.table, update.filter.as_ref(), &exprs)?;
+        let cast = |row: &[Value]| {
+            let row = row.iter().zip(&types).map(|(value, &t)| exec::expr::cast(value.clone(), t));
+            row.collect::<Result<Vec<_>, _>>()
+        };
+        match kind {
+            TableKind::Clustered { .. } => {
+                let rows = rows.iter().map(|(key, row)| Ok((key.clone(), cast(row)?)));
+                return self.update_clustered(&update.table, rows.collect::<Result<_, DatabaseError>>()?)
+            }
+            TableKind::Partitioned { .. } => {
+                let mut opened = self.open_partitioned_table(&update.table)?;
+                for (rid, row) in &rows {
+                    opened.update(&partition_rid(rid), &cast(row)?)?;
+                }
+                return Ok(rows.len() as u64)
+            }
+            _ => {}
+        }
+        for (rid, row) in &rows {
+            self.update(&update.table, heap_rid(rid), &cast(row)?)?;
+        }
+        Ok(rows.len() as u64)
+    }
+
+    /// Give the rows of the clustered table called `table` under the encoded primary keys in `rows` the new
+    /// values beside them. A row keeping its key is replaced where it is; the rows whose keys change are all
+    /// deleted before any is inserted under its new key, so one row may take a key another gives up.
+    fn update_clustered(&mut self, table: &str, rows: Vec<WriteRow>) -> Result<u64, DatabaseError> {
+        let mut opened = self.open_clustered_table(table)?;
+        let mut moved = Vec::new();
+        for (key, row) in &rows {
+            match opened.key(row) == *key {
+                true => opened.update(row)?,
+                false => moved.push((key, row)),
+            }
+        }
+        for (key, _) in &moved {
+            opened.delete(&opened.decode_key(key)?)?;
+        }
+        for (_, row) in &moved {
+            opened.insert(row)?;
+        }
+        Ok(rows.len() as u64)
+    }
+
+    /// Delete the rows of the table `delete` names that its filter holds for, all of them as one through
+    /// `delete_many`.
+    fn delete_where(&mut self, delete: &sql::Delete) -> Result<u64, DatabaseError> {
+        let rows = self.write_rows(&delete.table, delete.filter.as_ref(), &[])?;
+        if let TableKind::Clustered { .. } = self.table_def(&delete.table)?.kind {
+            let mut opened = self.open_clustered_table(&delete.table)?;
+            for (key, _) in &rows {
+                opened.delete(&opened.decode_key(key)?)?;
+            }
+            return Ok(rows.len() as u64)
+        }
+        if let TableKind::Partitioned { .. } = self.table_def(&delete.table)?.kind {
+            let mut opened = self.open_partitioned_table(&delete.table)?;
+            rows.iter().try_for_each(|(rid, _)| opened.delete(&partition_rid(rid)))?;
+            return Ok(rows.len() as u64)
+        }
+        let rids: Vec<Rid> = rows.iter().map(|(rid, _)| heap_rid(rid)).collect();
+        self.delete_many(&delete.table, &rids)?;
+        Ok(rids.len() as u64)
+    }
+
+    /// The rid as bytes of every row of the table called `table` that `filter` holds for, its partition rid
+    /// for a partitioned table or for a clustered table the encoding of its primary key, with the values of
+    /// `exprs` over the row, as `Planner::plan_write` plans them.
+    fn write_rows(
+        &self,
+        table: &str,
+        filter: Option<&Expr>,
+        exprs: &[Expr],
+    ) -> Result<Vec<WriteRow>, DatabaseError> {
+        let query = Planner::new(&self.catalog).plan_write(table, filter, exprs)?;
+        let mut context = Context::new(&self.temp);
+        context.recursion_limit = self.recursion_limit;
+        context.memory = MemoryBudget::new(self.memory_limit);
+        self.open_tables(&query.plan, &mut context)?;
+        let rows = exec::collect(&mut *query.plan.open(&context)?)?;
+        let rows = rows.into_iter().map(|mut row| {
+            let Value::Bytes(rid) = row.remove(0) else { unreachable!("a write's rows start with their rid") };
+            (rid, row)
+        });
+        Ok(rows.collect())
+    }
+
+    /// Add `constraint`, as declared in SQL, to the table called `table`.
+    fn add_declared(&mut self, table: &str, constraint: &TableConstraint) -> Result<(), DatabaseError> {
+        let resolve = |table: &TableDef, names: &[String]| {
+            let position = |name: &String| table.column(name).ok_or(DatabaseError::NoSuchColumn(name.clone()));
+            names.iter().map(position).collect::<Result<Vec<_>, _>>()
+        };
+        let def = self.table_def(table)?;
+        let name = |suffix, names: &[String]| match &constraint.name {
+            Some(name) => name.clone(),
+            None if names.is_empty() => format!("{table}_{suffix}"),
+            None => format!("{table}_{}_{suffix}", names.join("_")),
+        };
+        match &constraint.kind {
+            ConstraintKind::PrimaryKey(names) => {
+                let columns = resolve(def, names)?;
+                self.add_constraint(table, &name("pkey", &[]), Constraint::PrimaryKey, columns)
+            }
+            ConstraintKind::Unique(names) => {
+                let columns = resolve(def, names)?;
+                self.add_constraint(table, &name("key", names), Constraint::Unique, columns)
+            }
+            ConstraintKind::ForeignKey { columns, table: referenced, references, on_delete } => {
+                let name = name("fkey", columns);
+                let columns = resolve(def, columns)?;
+                let parent = self.table_def(referenced)?;
+                let references = match references.is_empty() {
+                    false => resolve(parent, references)?,
+                    true => match parent.indexes.iter().find(|i| i.constraint == Some(Constraint::PrimaryKey)) {
+                        Some(primary_key) => primary_key.columns.clone(),
+                        None => return Err(DatabaseError::NoUniqueKey(referenced.clone())),
+                    },
+                };
+                let on_delete = match on_delete {
+                    ReferentialAction::Restrict => OnDelete::Restrict,
+                    ReferentialAction::Cascade => OnDelete::Cascade,
+                    ReferentialAction::SetNull => OnDelete::SetNull,
+                };
+                self.add_foreign_key(table, &name, columns, referenced, references, on_delete)
+            }
+        }
+    }
+
+    /// Run the `SELECT` or `EXPLAIN` in `sql`, with `params` as the values of its bind parameters. The rows
+    /// of an `EXPLAIN` are the lines of its text, in one column called `plan`.
+    pub fn query(&self, sql: &str, params: &[Value]) -> Result<QueryResult, DatabaseError> {
+        match sql::parse_statement(sql)? {
+            sql::Statement::Select(select) => self.prepared(*select)?.query(self, params)?.into_result(),
+            sql::Statement::Explain { analyze, select } => {
+                let lines = self.prepared(*select)?.explain(self, params, analyze)?;
+                let rows = lines.into_iter().map(|line| vec![Value::Text(line)]).collect();
+                Ok(QueryResult { columns: vec!["plan".to_string()], rows })
+            }
+            _ => Err(DatabaseError::NotAQuery),
+        }
+    }
+
+    /// Parse and plan the `SELECT` in `sql`, to be run by the statement.
+    pub fn prepare(&self, sql: &str) -> Result<Statement, DatabaseError> {
+        let sql::Statement::Select(select) = sql::parse_statement(sql)? else { return Err(DatabaseError::NotAQuery) };
+        self.prepared(*select)
+    }
+
+    fn prepared(&self, select: Select) -> Result<Statement, DatabaseError> {
+        let (query, tables, views) = self.plan(&select)?;
+        Ok(Statement { select, query, tables, views, cancel: CancelHandle::new() })
+    }
+
+    /// Plan `select`, along with the catalog entries of the tables and views the plan reads.
+    fn plan(&self, select: &Select) -> Result<(Query, Vec<TableDef>, Vec<ViewDef>), DatabaseError> {
+        let query = Planner::new(&self.catalog).parallel(self.max_parallel_workers).plan_select(select)?;
+        let tables = query.plan.tables().into_iter().map(|name| self.table_def(name).cloned());
+        let tables = tables.collect::<Result<_, _>>()?;
+        let views = query.views.iter().filter_map(|name| self.catalog.view(name).cloned()).collect();
+        Ok((query, tables, views))
+    }
+
+    /// The catalog's definition of the heap table called `name`.
+    fn heap_def(&self, name: &str) -> Result<&TableDef, DatabaseError> {
+        let def = self.table_def(name)?;
+        match def.kind {
+            TableKind::Heap => Ok(def),
+            _ => Err(DatabaseError::NotAHeapTable(name.to_string())),
+        }
+    }
+
+    fn table_def(&self, name: &str) -> Result<&TableDef, DatabaseError> {
+        self.catalog.table(name).ok_or_else(|| DatabaseError::Catalog(CatalogError::NoSuchTable(name.to_string())))
+    }
+
+    fn sequence_def(&self, name: &str) -> Result<&SequenceDef, DatabaseError> {
+        self.catalog.sequence(name).ok_or_else(|| DatabaseError::Catalog(CatalogError::NoSuchTable(name.to_string())))
+    }
+
+    /// The catalog's definition of the column `spec` declares for the table called `table`, its default
+    /// checked by evaluating it once. An identity column defaults to the next value of its sequence.
+    fn column_def(&self, table: &str, spec: &sql::ColumnSpec) -> Result<ColumnDef, DatabaseError> {
+        let column = ColumnDef::new(&spec.name, Column { column_type: spec.column_type, nullable: spec.nullable });
+        if spec.collation != Collation::Binary && spec.column_type != ColumnType::Text {
+            return Err(DatabaseError::Unsupported("collations on columns other than text"))
+        }
+        let column = column.with_collation(spec.collation);
+        if spec.identity {
+            if spec.column_type != ColumnType::Int || spec.default.is_some() {
+                return Err(DatabaseError::Unsupported("identity columns but of INT with no default"))
+            }
+            let sequence = Expr::Literal(Value::Text(identity_sequence(table, &spec.name)));
+            let default = Expr::Function { name: "nextval".to_string(), args: vec![sequence], distinct: false };
+            let column = ColumnDef { column: Column::new(ColumnType::Int), ..column };
+            return Ok(column.with_default(&default.to_string()))
+        }
+        let Some(default) = &spec.default else { return Ok(column) };
+        let default = default.to_string();
+        self.check_default(&default, column.column)?;
+        Ok(column.with_default(&default))
+    }
+
+    /// Give the table called `table` the TTL that `TTL column + lifetime` declares, evaluating `lifetime`
+    /// once: as an integer for an integer column, and an interval for any other.
+    fn declare_ttl(&mut self, table: &str, column: &str, lifetime: &Expr) -> Result<(), DatabaseError> {
+        let def = self.table_def(table)?;
+        let column = def.column(column).ok_or_else(|| DatabaseError::NoSuchColumn(column.to_string()))?;
+        let lifetime_type = match def.columns[column].column.column_type {
+            ColumnType::Int => ColumnType::Int,
+            _ => ColumnType::Interval,
+        };
+        let lifetime = evaluate(lifetime, Column::nullable(lifetime_type))?;
+        self.set_ttl(table, Some(Ttl { column, lifetime }))
+    }
+
+    /// The value of the default with SQL `default` for a new row, as a value for `column`, each `nextval` in
+    /// it taking the next value of its sequence.
+    fn evaluate_default(&mut self, default: &str, column: Column) -> Result<Value, DatabaseError> {
+        let mut expr = sql::parse_expr(default)?;
+        next_values(&mut expr, &mut |name| self.next_value(name))?;
+        evaluate(&expr, column)
+    }
+
+    /// Check that the default with SQL `default` gives a value for `column`, without taking a value of any
+    /// sequence it reads.
+    fn check_default(&self, default: &str, column: Column) -> Result<(), DatabaseError> {
+        let mut expr = sql::parse_expr(default)?;
+        next_values(&mut expr, &mut |name| Ok(self.sequence_def(name)?.next))?;
+        evaluate(&expr, column).map(|_| ())
+    }
+}
+
+/// An `ON CONFLICT` resolved against a table. The values and filter of `DO UPDATE` are bound over the row
+/// already there, with the row to be inserted as outer columns.
+struct Upsert {
+    /// The unique index whose conflicts it handles, or `None` for all of them.
+    index: Option<String>,
+    /// Whether it is `DO UPDATE` rather than `DO NOTHING`.
+    update: bool,
+    /// Each column `DO UPDATE` assigns, with its value and type.
+    assignments: Vec<(usize, exec::Expr, ColumnType)>,
+    filter: Option<exec::Expr>,
+}
+impl Upsert {
+    fn new(def: &TableDef, on: &OnConflict) -> Result<Upsert, DatabaseError> {
+        let position = |name: &String| def.column(name).ok_or(DatabaseError::NoSuchColumn(name.clone()));
+        let mut target = on.target.iter().map(position).collect::<Result<Vec<_>, _>>()?;
+        target.sort_unstable();
+        let index = match target.is_empty() {
+            true => None,
+            false => {
+                let matches = |index: &&IndexDef| {
+                    let mut columns = index.columns.clone();
+                    columns.sort_unstable();
+                    index.constraint.is_some() && columns == target
+                };
+                let index = def.indexes.iter().find(matches);
+                Some(index.ok_or_else(|| DatabaseError::NoConflictIndex(on.target.clone()))?.name.clone())
+            }
+        };
+        let ConflictAction::Update { assignments, filter } = &on.action else {
+            return Ok(Upsert { index, update: false, assignments: Vec::new(), filter: None })
+        };
+        if index.is_none() {
+            return Err(DatabaseError::NoConflictIndex(Vec::new()))
+        }
+        let columns: Vec<String> = def.columns.iter().map(|c| c.name.clone()).collect();
+        let bind = |expr| planner::bind_row(&def.name, &columns, ("excluded", &columns), expr);
+        let mut assigned = Vec::new();
+        for (name, expr) in assignments {
+            let column = position(name)?;
+            assigned.push((column, bind(expr)?, def.columns[column].column.column_type));
+        }
+        let filter = filter.as_ref().map(bind).transpose()?;
+        Ok(Upsert { index, update: true, assignments: assigned, filter })
+    }
+}
+
+/// The value of `expr`, which reads no columns, as a value for `column`.
+fn evaluate(expr: &Expr, column: Column) -> Result<Value, DatabaseError> {
+    let value = planner::bind_constant(expr)?.eval(&[])?;
+    Ok(exec::expr::cast(value, column.column_type)?)
+}
+
+/// A field of CSV as a value of `column_type`, if it can be read as one: bytes in hex after `\x`, or as
+/// the bytes of the text without it, and any other type as a cast from text reads it.
+fn csv_value(text: &str, column_type: ColumnType) -> Option<Value> {
+    match (column_type, text.strip_prefix("\\x")) {
+        (ColumnType::Bytes, Some(hex)) if hex.len() % 2 == 0 => {
+            let byte = |i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok());
+            (0..hex.len()).step_by(2).map(byte).collect::<Option<_>>().map(Value::Bytes)
+        }
+        (ColumnType::Bytes, Some(_)) => None,
+        (ColumnType::Bytes, None) => Some(Value::Bytes(text.as_bytes().to_vec())),
+        (column_type, _) => exec::expr::cast(Value::Text(text.to_string()), column_type).ok(),
+    }
+}
+
+/// `value` as a field of CSV, or `None` for null.
+fn csv_text(value: &Value) -> Result<Option<String>, DatabaseError> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bytes(bytes) => Ok(Some(bytes.iter().fold("\\x".to_string(), |hex, b| hex + &format!("{b:02x}")))),
+        value => match exec::expr::cast(value.clone(), ColumnType::Text)? {
+            Value::Text(text) => Ok(Some(text)),
+            value => Err(DatabaseError::Exec(ExecError::TypeMismatch(value))),
+        },
+    }
+}
+
+/// A row found for a write: its rid or partition rid as bytes, or for a clustered table its encoded primary key,
+/// and the values
+/// the write computed over it.
+type WriteRow = (Vec<u8>, Vec<Value>);
+
+/// The rid a write's row gives as bytes.
+fn heap_rid(bytes: &[u8]) -> Rid {
+    Rid::from_bytes(bytes.try_into().expect("a rid is read as its bytes"))
+}
+
+/// The partition rid a write's row of a partitioned table gives as bytes.
+fn partition_rid(bytes: &[u8]) -> PartitionRid {
+    PartitionRid::from_bytes(bytes.try_into().expect("a partition rid is read as its bytes"))
+}
+
+/// The name of the sequence of the identity column `column` of the table `table`.
+fn identity_sequence(table: &str, column: &str) -> String {
+    format!("{table}_{column}_seq")
+}
+
+/// Replace each `nextval('name')` in `expr`, left to right, with the value `next` gives for the sequence
+/// called `name`. Subqueries are left alone.
+fn next_values(
+    expr: &mut Expr,
+    next: &mut impl FnMut(&str) -> Result<i64, DatabaseError>,
+) -> Result<(), DatabaseError> {
+    match expr {
+        Expr::Function { name, args, .. } if name.eq_ignore_ascii_case("nextval") => {
+            let [Expr::Literal(Value::Text(sequence))] = args.as_slice() else {
+                return Err(DatabaseError::Unsupported("nextval of anything but a sequence's name"))
+            };
+            *expr = Expr::Literal(Value::Int(next(sequence)?));
+        }
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) | Expr::Subquery(_) | Expr::Exists(_) => {}
+        Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => next_values(expr, next)?,
+        Expr::InSubquery { expr, .. } | Expr::Collate { expr, .. } => next_values(expr, next)?,
+        Expr::Binary { left, right, .. } | Expr::Like { expr: left, pattern: right, .. } => {
+            next_values(left, next)?;
+            next_values(right, next)?;
+        }
+        Expr::InList { expr, list, .. } => {
+            next_values(expr, next)?;
+            list.iter_mut().try_for_each(|e| next_values(e, next))?;
+        }
+        Expr::Between { expr, low, high, .. } => [expr, low, high].into_iter().try_for_each(|e| next_values(e, next))?,
+        Expr::Function { args, .. } | Expr::Window { args, .. } => {
+            args.iter_mut().try_for_each(|e| next_values(e, next))?
+        }
+        Expr::Case { operand, branches, otherwise } => {
+            operand.iter_mut().try_for_each(|e| next_values(e, next))?;
+            for (when, then) in branches {
+                next_values(when, next)?;
+                next_values(then, next)?;
+            }
+            otherwise.iter_mut().try_for_each(|e| next_values(e, next))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::catalog::{CatalogError, ColumnDef, Constraint, TriggerEvent, TriggerTiming};
+    use crate::decimal::Decimal;
+    use crate::page_store::{PageError, PageStore, PAGE_SIZE};
+    use crate::shadow::ShadowStorage;
+    use crate::storage::TestStorage;
+    use crate::table::{TableError, Ttl};
+    use crate::temp::TempFile;
+    use crate::tuple::{Column, ColumnType, TupleError, Value};
+
+    use crate::exec::ExecError;
+    use crate::heap::Rid;
+    use crate::planner::PlanError;
+
+    use crate::csv::{CsvError, CsvOptions};
+
+    use super::{AlterTable, Database, DatabaseError};
+
+    fn columns() -> Vec<ColumnDef> {
+        vec![ColumnDef::new("id", Column::new(ColumnType::Int)), ColumnDef::new("name", Column::new(ColumnType::Text))]
+    }
+
+    fn row(i: i64) -> Vec<Value> {
+        vec![Value::Int(i), Value::Text(format!("user {i}").repeat(20))]
+    }
+
+    #[test]
+    fn test_tables_survive_reopen_and_drop_frees_pages() -> Result<(), DatabaseError> {
+        let store = PageStore::new(ShadowStorage::open(TestStorage::new()).map_err(PageError::Storage)?);
+        let mut db = Database::create(&store)?;
+        let mut users = db.create_table("users", columns())?;
+        for i in 0..500 {
+            users.insert(&row(i))?;
+        }
+        db.create_index("users", "by_id", vec![0], vec![])?;
+        let mut scratch = db.create_table("scratch", columns())?;
+        for i in 0..500 {
+            scratch.insert(&row(i))?;
+        }
+        db.rename_table("users", "accounts")?;
+        db.drop_table("scratch")?;
+        let freed = db.allocator().free_pages(&store)?.len();
+        assert!(freed > 20, "{freed} pages freed");
+
+        // Reopen from what the last DDL committed.
+        let shadow = ShadowStorage::open(store.into_storage().into_inner()).map_err(PageError::Storage)?;
+        let store = PageStore::new(shadow);
+        let db = Database::open(&store)?;
+        assert_eq!(db.catalog().tables().map(|t| t.name.as_str()).collect::<Vec<_>>(), vec!["accounts"]);
+        assert_eq!(db.allocator().free_pages(&store)?.len(), freed);
+        let accounts = db.open_table("accounts")?;
+        assert_eq!(accounts.scan().count(), 500);
+        let rids = accounts.lookup("by_id", &[Value::Int(42)])?;
+        assert_eq!(accounts.get(&rids[0])?, row(42));
+        Ok(())
+    }
+
+    #[test]
+    fn test_alter_table_keeps_old_rows_readable() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let mut users = db.create_table("users", columns())?;
+        let old = users.insert(&row(1))?;
+        db.create_index("users", "by_name", vec![1], vec![])?;
+
+        let score = ColumnDef::new("score", Column::new(ColumnType::Int));
+        db.alter_table("users", AlterTable::AddColumn { column: score, default: Value::Int(10) })?;
+        let mut users = db.open_table("users")?;
+        let new = users.insert(&[Value::Int(2), Value::Text("eve".to_string()), Value::Int(99)])?;
+        assert_eq!(users.get(&old)?, [row(1), vec![Value::Int(10)]].concat());
+
+        let drop_indexed = db.alter_table("users", AlterTable::DropColumn("name".to_string()));
+        assert_eq!(drop_indexed, Err(DatabaseError::Table(TableError::IndexedColumn(1))));
+        db.alter_table("users", AlterTable::DropColumn("id".to_string()))?;
+        let nullable = AlterTable::SetNullable { column: "score".to_string(), nullable: true };
+        db.alter_table("users", nullable)?;
+
+        let mut db = Database::open(&store)?;
+        let mut users = db.open_table("users")?;
+        users.insert(&[Value::Text("zed".to_string()), Value::Null])?;
+        assert_eq!(users.get(&old)?, vec![row(1)[1].clone(), Value::Int(10)]);
+        assert_eq!(users.get(&new)?, vec![Value::Text("eve".to_string()), Value::Int(99)]);
+        assert_eq!(users.lookup("by_name", &[Value::Text("eve".to_string())])?, vec![new]);
+        assert_eq!(users.scan().count(), 3);
+
+        let not_null = AlterTable::SetNullable { column: "score".to_string(), nullable: false };
+        let err = Err(DatabaseError::Table(TableError::Tuple(TupleError::NullNotAllowed { column: 1 })));
+        assert_eq!(db.alter_table("users", not_null), err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ddl_errors() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.create_table("users", columns())?;
+        db.create_table("orders", columns())?;
+        let duplicate = |name: &str| DatabaseError::Catalog(CatalogError::DuplicateTable(name.to_string()));
+        assert_eq!(db.create_table("users", columns()).err(), Some(duplicate("users")));
+        assert_eq!(db.rename_table("users", "orders"), Err(duplicate("orders")));
+        let missing = Err(DatabaseError::Catalog(CatalogError::NoSuchTable("gone".to_string())));
+        assert_eq!(db.drop_table("gone"), missing);
+        assert_eq!(db.rename_table("gone", "here"), missing);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let mut users = db.create_table("users", columns())?;
+        for i in 0..50 {
+            users.insert(&row(i))?;
+        }
+        let sql = "SELECT name, id AS n FROM users WHERE id >= $1 AND id < $2";
+        let result = db.query(sql, &[Value::Int(10), Value::Int(13)])?;
+        assert_eq!(result.columns, vec!["name", "n"]);
+        let expected: Vec<_> = (10..13).map(|i| vec![row(i)[1].clone(), Value::Int(i)]).collect();
+        assert_eq!(result.rows, expected);
+        assert_eq!(db.query("SELECT 1 = 1", &[])?.rows, vec![vec![Value::Bool(true)]]);
+        let sql = "SELECT id * 2 + 1, CASE WHEN id % 2 = 0 THEN 'even' ELSE 'odd' END, CAST(id AS TEXT) || '!' \
+            FROM users WHERE id BETWEEN 3 AND 5 AND name LIKE 'user _%' AND id NOT IN (4, 6)";
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(db.query(sql, &[])?.rows, vec![
+            vec![Value::Int(7), text("odd"), text("3!")],
+            vec![Value::Int(11), text("odd"), text("5!")],
+        ]);
+
+        let unbound = Err(DatabaseError::Exec(ExecError::MissingParameter(0)));
+        assert_eq!(db.query("SELECT * FROM users WHERE id = $1", &[]), unbound);
+        let missing = Err(DatabaseError::Plan(PlanError::NoSuchColumn("nope".to_string())));
+        assert_eq!(db.query("SELECT nope FROM users", &[]), missing);
+        assert_eq!(db.query("DROP TABLE users", &[]), Err(DatabaseError::NotAQuery));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepared_statements_replan_after_schema_changes() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let mut users = db.create_table("users", columns())?;
+        for i in 0..50 {
+            users.insert(&row(i))?;
+        }
+        let mut statement = db.prepare("SELECT name FROM users WHERE id = ? AND name <> ?")?;
+        assert_eq!(statement.params(), &[Some(ColumnType::Int), Some(ColumnType::Text)]);
+        let text = |s: &str| Value::Text(s.to_string());
+        for i in [3, 7] {
+            let rows = statement.query(&db, &[Value::Int(i), text("")])?.into_result()?.rows;
+            assert_eq!(rows, vec![vec![row(i)[1].clone()]]);
+        }
+        let mismatch = DatabaseError::ParameterType { parameter: 1, expected: ColumnType::Text };
+        assert_eq!(statement.query(&db, &[Value::Int(3), Value::Int(4)]).err(), Some(mismatch));
+        assert!(statement.query(&db, &[Value::Float(3.0), Value::Null])?.into_result()?.rows.is_empty());
+
+        assert_eq!(statement.plan().describe(), "Project(Filter(SeqScan(users)))");
+        db.create_index("users", "by_id", vec![0], vec![])?;
+        let rows = statement.query(&db, &[Value::Int(3), text("")])?.into_result()?.rows;
+        assert_eq!(rows, vec![vec![row(3)[1].clone()]]);
+        assert_eq!(statement.plan().describe(), "Project(Filter(IndexScan(users.by_id)))");
+
+        db.alter_table("users", AlterTable::DropColumn("name".to_string()))?;
+        let missing = DatabaseError::Plan(PlanError::NoSuchColumn("name".to_string()));
+        assert_eq!(statement.query(&db, &[Value::Int(3), text("")]).err(), Some(missing));
+        Ok(())
+    }
+
+    #[test]
+    fn test_statement_cursors() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.set_recursion_limit(usize::MAX);
+        // The rows are pulled as they are needed, so a query with no end gives as many as are taken.
+        let mut n =
+            db.prepare("WITH RECURSIVE n (i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) SELECT i * 10 AS x FROM n")?;
+        let rows = n.query(&db, &[])?;
+        assert_eq!(rows.columns(), &["x".to_string()]);
+        let first = rows.take(3).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(first, vec![vec![Value::Int(10)], vec![Value::Int(20)], vec![Value::Int(30)]]);
+        let mapped = n.query_map(&db, &[], |row| row[0].clone())?.take(2).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(mapped, vec![Value::Int(10), Value::Int(20)]);
+
+        // Cancelled part way, the cursor fails and ends, and the statement runs again after that.
+        let mut rows = n.query(&db, &[])?;
+        assert_eq!(rows.next(), Some(Ok(vec![Value::Int(10)])));
+        n.cancel_handle().cancel();
+        assert_eq!(rows.next(), Some(Err(DatabaseError::Cancelled)));
+        assert_eq!(rows.next(), None);
+        assert_eq!(n.query(&db, &[])?.nth(4), Some(Ok(vec![Value::Int(50)])));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancelling_statements() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (a INT)")?;
+        db.execute("INSERT INTO t VALUES (1), (2), (3)")?;
+        let mut count = db.prepare("SELECT count(*) FROM t")?;
+        // Cancelled between runs, the statement fails its next run, and runs again after that.
+        count.cancel_handle().cancel();
+        assert_eq!(count.query(&db, &[])?.into_result(), Err(DatabaseError::Cancelled));
+        assert_eq!(count.query(&db, &[])?.into_result()?.rows, vec![vec![Value::Int(3)]]);
+
+        // A query that would run for as long as it is allowed to is stopped from another thread.
+        db.set_recursion_limit(usize::MAX);
+        let mut runaway =
+            db.prepare("WITH RECURSIVE n (i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) SELECT count(*) FROM n")?;
+        let cancel = runaway.cancel_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            cancel.cancel();
+        });
+        assert_eq!(runaway.query(&db, &[])?.into_result(), Err(DatabaseError::Cancelled));
+        canceller.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_limit() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (a INT)")?;
+        let values: Vec<String> = (0..500).map(|i| format!("({})", i * 7 % 500)).collect();
+        db.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")))?;
+        db.set_memory_limit(64);
+        // A sort spills to keep to the limit, but the hash table of a join's partition cannot.
+        let sorted = db.query("SELECT a FROM t ORDER BY a", &[])?.rows;
+        assert_eq!(sorted, (0..500).map(|i| vec![Value::Int(i)]).collect::<Vec<_>>());
+        let join = "SELECT count(*) FROM t AS x JOIN t AS y ON x.a = y.a";
+        assert_eq!(db.query(join, &[]), Err(DatabaseError::Exec(ExecError::MemoryLimit(64))));
+        db.set_memory_limit(crate::exec::DEFAULT_QUERY_MEMORY_BYTES);
+        assert_eq!(db.query(join, &[])?.rows, vec![vec![Value::Int(500)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spills_go_to_a_temp_file() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let temp = TempFile::create(&std::env::temp_dir()).map_err(PageError::Storage)?;
+        let path = temp.path().to_path_buf();
+        db.set_temp_storage(Box::new(temp));
+        db.execute("CREATE TABLE t (a INT, b TEXT)")?;
+        let padding = "x".repeat(40);
+        for chunk in (0..20_000).collect::<Vec<_>>().chunks(1000) {
+            let values: Vec<String> = chunk.iter().map(|i| format!("({}, '{}')", i * 7 % 20_000, padding)).collect();
+            db.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")))?;
+        }
+        db.set_memory_limit(64 * 1024);
+        let sorted = db.query("SELECT a FROM t ORDER BY a", &[])?.rows;
+        assert_eq!(sorted, (0..20_000).map(|i| vec![Value::Int(i)]).collect::<Vec<_>>());
+        let grouped = db.query("SELECT a, count(*) FROM t GROUP BY a", &[])?.rows;
+        assert_eq!(grouped.len(), 20_000);
+
+        // The rows spilled are in the file, far more pages than the 40 frames of the store over it keep in
+        // memory, and every page is back with the space once the queries are done.
+        let spilled = std::fs::metadata(&path).unwrap().len() as usize / PAGE_SIZE;
+        assert!(spilled > 200, "{spilled} pages spilled");
+        assert_eq!(db.temp_space().pages_in_use(), 0);
+        let again = db.query("SELECT a FROM t ORDER BY a", &[])?.rows;
+        assert_eq!(again, sorted);
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize / PAGE_SIZE, spilled);
+        drop(db);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_workers() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (a INT, b INT)")?;
+        for chunk in (0..12000).collect::<Vec<_>>().chunks(1000) {
+            let values: Vec<String> = chunk.iter().map(|i| format!("({i}, {})", i % 97)).collect();
+            db.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")))?;
+        }
+        db.analyze("t")?;
+        let queries = [
+            "SELECT b, count(*), sum(a) FROM t WHERE a % 3 <> 0 GROUP BY b ORDER BY b",
+            "SELECT x.b, count(*) FROM t AS x JOIN t AS y ON x.a = y.a + 1 WHERE y.b < 50 GROUP BY x.b ORDER BY 2, 1",
+        ];
+        let serial: Vec<_> = queries.iter().map(|sql| db.query(sql, &[])).collect::<Result<_, _>>()?;
+        db.set_max_parallel_workers(4);
+        for (sql, serial) in queries.iter().zip(serial) {
+            let plan = db.query(&format!("EXPLAIN {sql}"), &[])?.rows;
+            let gathers = |line: &Vec<Value>| matches!(&line[0], Value::Text(line) if line.contains("Gather"));
+            assert!(plan.iter().any(gathers), "{plan:?}");
+            assert_eq!(db.query(sql, &[])?.rows, serial.rows);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_joins_and_statistics() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let mut users = db.create_table("users", columns())?;
+        let mut orders = db.create_table("orders", columns())?;
+        for i in 0..200 {
+            users.insert(&row(i))?;
+            orders.insert(&[Value::Int(i % 50), Value::Text(format!("order {i}"))])?;
+        }
+        assert_eq!(db.analyze("orders")?.row_count, 200);
+        let db = Database::open(&store)?;
+        let stats = db.catalog().table("orders").and_then(|t| t.stats.as_ref());
+        assert_eq!(stats.map(|s| s.columns[0].distinct), Some(50));
+
+        let sql = "SELECT u.id, o.name FROM users u JOIN orders o ON u.id = o.id \
+            WHERE u.id < 3 AND o.name <> 'order 1'";
+        let mut rows = db.query(sql, &[])?.rows;
+        rows.sort();
+        let ids = [0, 2, 50, 51, 52, 100, 101, 102, 150, 151, 152];
+        let order = |i: &i64| vec![Value::Int(i % 50), Value::Text(format!("order {i}"))];
+        let mut expected: Vec<_> = ids.iter().map(order).collect();
+        expected.sort();
+        assert_eq!(rows, expected);
+
+        // The ON condition only decides matches, so users 50 and 51 still come out once, with nulls, while a
+        // WHERE condition on the right side filters after the join.
+        let sql = "SELECT u.id, o.name FROM users u LEFT JOIN orders o ON u.id = o.id AND o.name <> 'order 98' \
+            WHERE u.id >= 48 AND u.id < 52";
+        let mut rows = db.query(sql, &[])?.rows;
+        rows.sort();
+        let mut expected: Vec<_> = [48, 148, 198, 49, 99, 149, 199].iter().map(order).collect();
+        expected.extend([50, 51].map(|i| vec![Value::Int(i), Value::Null]));
+        expected.sort();
+        assert_eq!(rows, expected);
+        let sql = "SELECT u.id FROM users u LEFT JOIN orders o ON u.id = o.id WHERE o.id IS NULL AND u.id < 60";
+        let rows = db.query(sql, &[])?.rows;
+        assert_eq!(rows, (50..60).map(|i| vec![Value::Int(i)]).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_and_unique_constraints() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (id INT PRIMARY KEY, email TEXT, CONSTRAINT t_email UNIQUE (email))")?;
+        let def = db.catalog().table("t").unwrap();
+        assert!(!def.columns[0].column.nullable);
+        let constraints: Vec<_> = def.indexes.iter().map(|i| (i.name.as_str(), i.constraint)).collect();
+        assert_eq!(constraints, vec![("t_pkey", Some(Constraint::PrimaryKey)), ("t_email", Some(Constraint::Unique))]);
+
+        let text = |s: &str| Value::Text(s.to_string());
+        let mut t = db.open_table("t")?;
+        t.insert(&[Value::Int(1), text("a")])?;
+        t.insert(&[Value::Int(2), Value::Null])?;
+        t.insert(&[Value::Int(3), Value::Null])?;
+        let duplicate = |index: &str, key| {
+            Err(TableError::UniqueViolation { index: index.to_string(), key: vec![key] })
+        };
+        assert_eq!(t.insert(&[Value::Int(1), text("b")]), duplicate("t_pkey", Value::Int(1)));
+        assert_eq!(t.insert(&[Value::Int(4), text("a")]), duplicate("t_email", text("a")));
+        let null = TableError::Tuple(TupleError::NullNotAllowed { column: 0 });
+        assert_eq!(t.insert(&[Value::Null, text("c")]), Err(null));
+
+        // A constraint on rows that already break it is refused and leaves the table as it was.
+        let nullable = vec![ColumnDef::new("id", Column::nullable(ColumnType::Int))];
+        db.create_table("u", nullable)?.insert(&[Value::Int(1)])?;
+        db.open_table("u")?.insert(&[Value::Int(1)])?;
+        let before = db.catalog().table("u").cloned();
+        let refused = db.add_constraint("u", "u_pkey", Constraint::PrimaryKey, vec![0]);
+        let duplicate = TableError::UniqueViolation { index: "u_pkey".to_string(), key: vec![Value::Int(1)] };
+        assert_eq!(refused, Err(DatabaseError::Table(duplicate)));
+        assert_eq!(db.catalog().table("u").cloned(), before);
+        db.drop_index("t", "t_email")?;
+        db.open_table("t")?.insert(&[Value::Int(4), text("a")])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_foreign_keys() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE users (id INT PRIMARY KEY, name TEXT)")?;
+        db.execute("CREATE TABLE orders (id INT PRIMARY KEY, user_id INT REFERENCES users ON DELETE CASCADE)")?;
+        db.execute("CREATE TABLE reviews (id INT, order_id INT, FOREIGN KEY (order_id) REFERENCES orders (id) \
+            ON DELETE SET NULL)")?;
+        db.execute("CREATE TABLE archive (user_id INT, CONSTRAINT archived FOREIGN KEY (user_id) REFERENCES users)")?;
+        let int = |v| Value::Int(v);
+        for row in [[int(1), Value::Null], [int(2), Value::Null]] {
+            db.insert("users", &row)?;
+        }
+        for row in [[int(10), int(1)], [int(11), int(2)], [int(12), Value::Null]] {
+            db.insert("orders", &row)?;
+        }
+        let violation = |constraint: &str, key| {
+            Err(DatabaseError::ForeignKeyViolation { constraint: constraint.to_string(), key: vec![key] })
+        };
+        assert_eq!(db.insert("orders", &[int(13), int(3)]).map(|_| ()), violation("orders_user_id_fkey", int(3)));
+        db.insert("reviews", &[int(100), int(10)])?;
+        db.insert("reviews", &[int(101), int(11)])?;
+        db.insert("archive", &[int(2)])?;
+
+        let rid = |db: &Database<_>, table: &str, id| -> Result<Rid, DatabaseError> {
+            Ok(db.open_table(table)?.lookup(&format!("{table}_pkey"), &[int(id)])?[0])
+        };
+        let ids = |db: &Database<_>, sql| -> Result<Vec<Vec<Value>>, DatabaseError> { Ok(db.query(sql, &[])?.rows) };
+        assert_eq!(db.delete("users", rid(&db, "users", 2)?), violation("archived", int(2)));
+        assert_eq!(ids(&db, "SELECT id FROM orders ORDER BY id")?.len(), 3);
+        // Deleting user 1 deletes order 10, which sets the order of review 100 to null.
+        db.delete("users", rid(&db, "users", 1)?)?;
+        assert_eq!(ids(&db, "SELECT id FROM orders ORDER BY id")?, vec![vec![int(11)], vec![int(12)]]);
+        let reviews = ids(&db, "SELECT id, order_id FROM reviews ORDER BY id")?;
+        assert_eq!(reviews, vec![vec![int(100), Value::Null], vec![int(101), int(11)]]);
+
+        let user = rid(&db, "users", 2)?;
+        assert_eq!(db.update("users", user, &[int(5), Value::Null]), violation("archived", int(2)));
+        db.update("users", user, &[int(2), Value::Text("ann".to_string())])?;
+        let referenced = |key: &str| Err(DatabaseError::Catalog(CatalogError::Referenced(key.to_string())));
+        assert_eq!(db.drop_table("users"), referenced("archived"));
+        assert_eq!(db.drop_index("orders", "orders_user_id_fkey"), referenced("orders_user_id_fkey"));
+
+        // Adding a foreign key checks the rows already there.
+        db.open_table("reviews")?.insert(&[int(102), int(99)])?;
+        db.execute("ALTER TABLE reviews DROP CONSTRAINT reviews_order_id_fkey")?;
+        let added = db.execute("ALTER TABLE reviews ADD FOREIGN KEY (order_id) REFERENCES orders").map(|_| ());
+        assert_eq!(added, violation("reviews_order_id_fkey", int(99)));
+        assert!(db.catalog().table("reviews").is_some_and(|t| t.foreign_keys.is_empty() && t.indexes.is_empty()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl_expiry() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE sessions (id INT PRIMARY KEY, seen_at TIMESTAMPTZ) TTL seen_at + INTERVAL '1 day'")?;
+        db.execute("CREATE INDEX by_seen ON sessions (seen_at)")?;
+        db.execute("CREATE TABLE events (note TEXT, session_id INT REFERENCES sessions ON DELETE CASCADE, at INT) \
+            TTL at + 60 * 60")?;
+        db.execute("CREATE TABLE log (id INT)")?;
+        db.execute("CREATE TRIGGER logged AFTER DELETE ON sessions BEGIN INSERT INTO log VALUES (old.id); END")?;
+        for i in 0..10 {
+            let ago = if i % 2 == 0 { "2 days" } else { "1 hour" };
+            db.execute(&format!("INSERT INTO sessions VALUES ({i}, now() - INTERVAL '{ago}')"))?;
+            db.execute(&format!("INSERT INTO events VALUES ('', {i}, 4000000000)"))?;
+        }
+        db.execute("INSERT INTO events VALUES ('old', NULL, 0), ('timeless', NULL, NULL)")?;
+        let ttl = Some(Ttl { column: 2, lifetime: Value::Int(3600) });
+        assert_eq!(db.catalog().table("events").and_then(|t| t.ttl.clone()), ttl);
+
+        // Tables expire in the order of their names, within the limit of the batch.
+        assert_eq!(db.expire(4)?, 4);
+        assert_eq!(db.expire(100)?, 2);
+        assert_eq!(db.expire(100)?, 0);
+        let ints = |db: &Database<_>, sql| -> Result<Vec<Value>, DatabaseError> {
+            Ok(db.query(sql, &[])?.rows.into_iter().map(|row| row[0].clone()).collect())
+        };
+        let odd: Vec<_> = (1..10).step_by(2).map(Value::Int).collect();
+        assert_eq!(ints(&db, "SELECT id FROM sessions ORDER BY id")?, odd);
+        assert_eq!(db.open_table("sessions")?.lookup("by_seen", &[])?.len(), 5);
+        // Deleting a session ran its trigger and cascaded to its events.
+        let even: Vec<_> = (0..10).step_by(2).map(Value::Int).collect();
+        assert_eq!(ints(&db, "SELECT id FROM log ORDER BY id")?, even);
+        let mut events = ints(&db, "SELECT session_id FROM events ORDER BY session_id")?;
+        assert_eq!(events.remove(0), Value::Null);
+        assert_eq!(events, odd);
+
+        // The TTL's column cannot be dropped, and follows it as the columns before it are.
+        let dropped = db.execute("ALTER TABLE events DROP COLUMN at");
+        assert_eq!(dropped, Err(DatabaseError::Unsupported("dropping the column of a table's TTL")));
+        db.execute("ALTER TABLE events DROP COLUMN note")?;
+        assert_eq!(db.catalog().table("events").and_then(|t| t.ttl.as_ref().map(|t| t.column)), Some(1));
+        let mismatch = db.execute("CREATE TABLE bad (a TEXT) TTL a + INTERVAL '1 day'");
+        assert_eq!(mismatch, Err(DatabaseError::Catalog(CatalogError::TtlMismatch)));
+        assert!(db.catalog().table("bad").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_defaults() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute(
+            "CREATE TABLE t (id INT, n INT DEFAULT 1 + 2 NOT NULL, s TEXT DEFAULT 'x', at TIMESTAMPTZ DEFAULT now())",
+        )?;
+        let micros = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros() as i64;
+        let before = micros();
+        db.execute("INSERT INTO t (id) VALUES (1)")?;
+        db.execute("INSERT INTO t (s, id) VALUES (NULL, 2.0)")?;
+        let after = micros();
+        let rows = db.query("SELECT id, n, s FROM t ORDER BY id", &[])?.rows;
+        let (int, text) = (Value::Int, |s: &str| Value::Text(s.to_string()));
+        assert_eq!(rows, vec![vec![int(1), int(3), text("x")], vec![int(2), int(3), Value::Null]]);
+        for row in db.query("SELECT at FROM t", &[])?.rows {
+            assert!(matches!(row[0], Value::TimestampTz(at) if (before..=after).contains(&at)));
+        }
+
+        // Rows from before a column was added read its default without being rewritten; changing the
+        // default later only affects rows inserted after.
+        db.execute("ALTER TABLE t ADD COLUMN f DOUBLE DEFAULT 2")?;
+        db.execute("INSERT INTO t (id) VALUES (3)")?;
+        db.execute("ALTER TABLE t ALTER f SET DEFAULT 5")?;
+        drop(db);
+        let mut db = Database::open(&store)?;
+        db.execute("INSERT INTO t (id) VALUES (4)")?;
+        db.execute("ALTER TABLE t ALTER COLUMN f DROP DEFAULT")?;
+        db.execute("INSERT INTO t VALUES (5, 0, 'y', now(), NULL)")?;
+        db.execute("INSERT INTO t (id) VALUES (6)")?;
+        let rows = db.query("SELECT f FROM t ORDER BY id", &[])?.rows;
+        let floats: Vec<_> = [2.0, 2.0, 2.0, 5.0].into_iter().map(|f| vec![Value::Float(f)]).collect();
+        assert_eq!(rows, [floats, vec![vec![Value::Null]; 2]].concat());
+
+        let invalid = db.execute("CREATE TABLE u (a INT DEFAULT 'abc')");
+        assert_eq!(invalid, Err(DatabaseError::Exec(ExecError::InvalidCast(text("abc"), ColumnType::Int))));
+        let reads_column = db.execute("CREATE TABLE u (a INT, b INT DEFAULT a)");
+        assert_eq!(reads_column, Err(DatabaseError::Plan(PlanError::NoSuchColumn("a".to_string()))));
+        assert!(db.catalog().table("u").is_none());
+        db.execute("ALTER TABLE t ALTER n DROP DEFAULT")?;
+        let missing = db.execute("INSERT INTO t (id) VALUES (7)");
+        assert_eq!(missing, Err(DatabaseError::Table(TableError::Tuple(TupleError::NullNotAllowed { column: 1 }))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequences_and_identity_columns() -> Result<(), DatabaseError> {
+        let store = PageStore::new(ShadowStorage::open(TestStorage::new()).map_err(PageError::Storage)?);
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE SEQUENCE s START WITH 10 INCREMENT BY 5 CACHE 3")?;
+        let values = (0..4).map(|_| db.next_value("s")).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(values, vec![10, 15, 20, 25]);
+        // The second range, from 25 to 35, is committed before 25 is given out.
+        assert_eq!(db.catalog().sequence("s").map(|s| s.next), Some(40));
+
+        // Reopening from what was committed skips the rest of the range.
+        drop(db);
+        let shadow = ShadowStorage::open(store.into_storage().into_inner()).map_err(PageError::Storage)?;
+        let store = PageStore::new(shadow);
+        let mut db = Database::open(&store)?;
+        assert_eq!(db.next_value("s")?, 40);
+        db.execute("CREATE SEQUENCE down INCREMENT -2")?;
+        assert_eq!((db.next_value("down")?, db.next_value("down")?), (-1, -3));
+
+        db.execute("CREATE TABLE t (id INT AUTOINCREMENT PRIMARY KEY, code INT GENERATED BY DEFAULT AS IDENTITY, \
+            s INT)")?;
+        db.execute("INSERT INTO t (s) VALUES (nextval('s')), (nextval('s') * 2)")?;
+        db.execute("INSERT INTO t (id, s) VALUES (10, NULL)")?;
+        db.execute("INSERT INTO t (s) SELECT s FROM t WHERE s < 100")?;
+        let rows = db.query("SELECT id, code, s FROM t ORDER BY code", &[])?.rows;
+        let ints = |v: [Option<i64>; 3]| v.into_iter().map(|v| v.map_or(Value::Null, Value::Int)).collect::<Vec<_>>();
+        assert_eq!(rows, vec![
+            ints([Some(1), Some(1), Some(45)]),
+            ints([Some(2), Some(2), Some(100)]),
+            ints([Some(10), Some(3), None]),
+            ints([Some(3), Some(4), Some(45)]),
+        ]);
+        assert!(db.catalog().table("t").unwrap().columns.iter().all(|c| c.name == "s" || !c.column.nullable));
+        let owned = Err(DatabaseError::Catalog(CatalogError::Referenced("t".to_string())));
+        assert_eq!(db.drop_sequence("t_id_seq"), owned);
+        db.rename_table("t", "u")?;
+        db.execute("DROP TABLE u")?;
+        assert_eq!(db.catalog().sequences().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["down", "s"]);
+        db.execute("DROP SEQUENCE s")?;
+        db.execute("DROP SEQUENCE IF EXISTS s")?;
+
+        let missing = Err(DatabaseError::Catalog(CatalogError::NoSuchTable("s".to_string())));
+        assert_eq!(db.execute("CREATE TABLE v (a INT DEFAULT nextval('s'))").map(|_| ()), missing);
+        assert_eq!(db.execute("CREATE SEQUENCE z INCREMENT 0"), Err(DatabaseError::InvalidSequence("z".to_string())));
+        db.create_sequence("last", i64::MAX - 1, 1, 2)?;
+        assert_eq!(db.next_value("last"), Err(DatabaseError::SequenceExhausted("last".to_string())));
+        let text_identity = db.execute("CREATE TABLE v (a TEXT AUTOINCREMENT)");
+        assert_eq!(text_identity, Err(DatabaseError::Unsupported("identity columns but of INT with no default")));
+        assert!(db.catalog().table("v").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dates_and_times() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE events (id INT PRIMARY KEY, day DATE NOT NULL, at TIMESTAMP, took INTERVAL)")?;
+        db.execute("CREATE INDEX events_by_day ON events (day, at)")?;
+        db.execute("INSERT INTO events VALUES (1, '2024-02-28', '2024-02-28 23:00', '90 minutes'), \
+            (2, '2024-02-29', '2024-02-29T08:30:00', '1 day'), (3, DATE '2024-03-01', NULL, NULL), \
+            (4, '2024-02-29', '2024-02-29 18:00', '2 hours')")?;
+        let text = |s: &str| Value::Text(s.to_string());
+        let rows = |db: &Database<_>, sql| db.query(sql, &[]).map(|r| r.rows);
+
+        let sql = "SELECT id, CAST(at + took AS TEXT) FROM events WHERE day = DATE '2024-02-29' ORDER BY at DESC";
+        assert_eq!(rows(&db, sql)?, vec![
+            vec![Value::Int(4), text("2024-02-29 20:00:00")],
+            vec![Value::Int(2), text("2024-03-01 08:30:00")],
+        ]);
+        let sql = "SELECT id FROM events WHERE at > TIMESTAMP '2024-02-29' - INTERVAL '2 hours' \
+            AND day < DATE '2024-03-01'";
+        assert_eq!(rows(&db, sql)?.len(), 3);
+        let sql = "SELECT to_char(day, 'Dy DD Mon'), date_part('dow', day), CAST(day - DATE '2024-01-01' AS TEXT) \
+            FROM events WHERE id = 3";
+        assert_eq!(rows(&db, sql)?, vec![vec![text("Fri 01 Mar"), Value::Float(5.0), text("60")]]);
+        let sql = "SELECT date_trunc('month', day), count(*), max(took) FROM events GROUP BY date_trunc('month', day)";
+        let month = |s: &str| Value::Timestamp(crate::datetime::parse_timestamp(s, false).unwrap());
+        let took = Value::Interval(crate::datetime::parse_interval("1 day").unwrap());
+        let mut groups = rows(&db, sql)?;
+        groups.sort();
+        assert_eq!(groups, vec![
+            vec![month("2024-02-01"), Value::Int(3), took],
+            vec![month("2024-03-01"), Value::Int(1), Value::Null],
+        ]);
+
+        let invalid = db.execute("INSERT INTO events VALUES (5, '2024-02-30', NULL, NULL)");
+        assert_eq!(invalid, Err(DatabaseError::Exec(ExecError::InvalidCast(text("2024-02-30"), ColumnType::Date))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimals() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE prices (id INT PRIMARY KEY, price DECIMAL(8, 2) NOT NULL, rate NUMERIC)")?;
+        db.execute("CREATE INDEX prices_by_price ON prices (price)")?;
+        db.execute("INSERT INTO prices VALUES (1, 19.99, 0.1), (2, '5.005', 1), (3, 7, NUMERIC '-2.50'), \
+            (4, -0.004, NULL)")?;
+        let text = |s: &str| Value::Text(s.to_string());
+        let rows = |db: &Database<_>, sql| db.query(sql, &[]).map(|r| r.rows);
+
+        // Values are stored at the column's scale, rounded half away from zero.
+        let shown = rows(&db, "SELECT CAST(price AS TEXT) FROM prices ORDER BY price DESC")?;
+        assert_eq!(shown, ["19.99", "7.00", "5.01", "0.00"].map(|s| vec![text(s)]));
+        let sql = "SELECT CAST(sum(price) AS TEXT), CAST(sum(rate) + NUMERIC '0.2' AS TEXT), CAST(avg(rate) AS TEXT) \
+            FROM prices";
+        assert_eq!(rows(&db, sql)?, vec![vec![text("32.00"), text("-1.20"), text("-0.4666666666666667")]]);
+        assert_eq!(rows(&db, "SELECT id FROM prices WHERE price = 5.01")?, vec![vec![Value::Int(2)]]);
+        assert_eq!(rows(&db, "SELECT id FROM prices WHERE price BETWEEN 6 AND NUMERIC '19.99' ORDER BY id")?, vec![
+            vec![Value::Int(1)],
+            vec![Value::Int(3)],
+        ]);
+        let sql = "SELECT CAST(price / 3 AS TEXT) FROM prices WHERE id = 3";
+        assert_eq!(rows(&db, sql)?, vec![vec![text("2.3333333333333333")]]);
+
+        db.execute("UPDATE prices SET price = price * NUMERIC '1.1' WHERE id = 1")?;
+        let price = Value::Decimal(Decimal::parse("21.99").unwrap());
+        assert_eq!(rows(&db, "SELECT price FROM prices WHERE id = 1")?, vec![vec![price]]);
+        // A literal with a point is a decimal, so arithmetic on literals is exact and stays decimal.
+        assert_eq!(rows(&db, "SELECT 0.1 + 0.2 = 0.3, 0.1 + 0.2")?, vec![vec![
+            Value::Bool(true),
+            Value::Decimal(Decimal::parse("0.3").unwrap()),
+        ]]);
+        let sql = "SELECT price + 0.001, price * 1.5e0 FROM prices WHERE id = 3";
+        assert_eq!(rows(&db, sql)?, vec![vec![Value::Decimal(Decimal::parse("7.001").unwrap()), Value::Float(10.5)]]);
+        // So is an integer too large for a BIGINT, unless negated into the smallest one.
+        assert_eq!(rows(&db, "SELECT -9223372036854775808, 9223372036854775808")?, vec![vec![
+            Value::Int(i64::MIN),
+            Value::Decimal(Decimal::new(1 << 63, 0)),
+        ]]);
+        let overflow = db.query("SELECT -(-9223372036854775808)", &[]).err();
+        assert_eq!(overflow, Some(DatabaseError::Exec(ExecError::Overflow)));
+        let big = ColumnType::Decimal { precision: 8, scale: 2 };
+        let overflow = db.execute("INSERT INTO prices VALUES (5, 1000000, NULL)");
+        assert_eq!(overflow, Err(DatabaseError::Exec(ExecError::InvalidCast(Value::Int(1_000_000), big))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE docs (id INT PRIMARY KEY, doc JSONB)")?;
+        db.execute("CREATE UNIQUE INDEX docs_by_name ON docs ((doc->>'name'))")?;
+        db.execute("CREATE INDEX docs_by_tag ON docs ((doc#>'{tags,0}'), id)")?;
+        db.execute(r#"INSERT INTO docs VALUES (1, '{"name": "ann", "tags": ["a", "b"], "age": 30}'),
+            (2, JSON '{"age": 4.50, "name": "bob", "tags": ["b"]}'), (3, '[1, 2]'), (4, NULL)"#)?;
+        let text = |s: &str| Value::Text(s.to_string());
+        let rows = |db: &Database<_>, sql| db.query(sql, &[]).map(|r| r.rows);
+
+        let sql = "SELECT id, doc->'age', doc#>>'{tags,-1}' FROM docs WHERE doc->'tags'->>0 = 'b'";
+        let Value::Json(age) = &rows(&db, sql)?[0][1] else { panic!("not a document") };
+        assert_eq!(age.to_string(), "4.5");
+        assert_eq!(rows(&db, "SELECT CAST(doc AS TEXT) FROM docs WHERE id = 3")?, vec![vec![text("[1, 2]")]]);
+        assert_eq!(rows(&db, "SELECT doc->'x', doc->>1 FROM docs WHERE id = 3")?, vec![vec![Value::Null, text("2")]]);
+        let plan = |db: &Database<_>, sql| -> Result<_, DatabaseError> { Ok(rows(db, sql)?[1][0].clone()) };
+        let sql = "EXPLAIN SELECT id FROM docs WHERE 'bob' = doc->>'name'";
+        let Value::Text(line) = plan(&db, sql)? else { panic!("not a line of text") };
+        assert!(line.starts_with("-> IndexScan on docs using docs_by_name"), "{line}");
+        assert_eq!(rows(&db, "SELECT id FROM docs WHERE 'bob' = doc->>'name'")?, vec![vec![Value::Int(2)]]);
+        let duplicate = db.execute(r#"INSERT INTO docs VALUES (5, '{"name": "ann"}')"#);
+        assert!(matches!(duplicate, Err(DatabaseError::Table(TableError::UniqueViolation { .. }))));
+        let unsupported = db.execute("CREATE INDEX docs_by_sum ON docs ((id + 1))");
+        assert_eq!(unsupported, Err(DatabaseError::Unsupported("index keys other than columns and paths into them")));
+        let bad = db.execute("CREATE INDEX docs_by_id ON docs ((id->>'a'))");
+        assert_eq!(bad, Err(DatabaseError::Table(TableError::InvalidPath)));
+
+        // The paths are kept in the catalog, so the indexes reopen keyed as they were built.
+        let db = Database::open(&store)?;
+        let sql = "EXPLAIN SELECT id FROM docs WHERE doc#>'{tags,0}' = JSON '\"a\"'";
+        let Value::Text(line) = plan(&db, sql)? else { panic!("not a line of text") };
+        assert!(line.starts_with("-> IndexScan on docs using docs_by_tag"), "{line}");
+        assert_eq!(rows(&db, "SELECT id FROM docs WHERE doc#>'{tags,0}' = JSON '\"a\"'")?, vec![vec![Value::Int(1)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collations() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE people (id INT PRIMARY KEY, name TEXT COLLATE nocase UNIQUE, tag TEXT)")?;
+        db.execute("INSERT INTO people VALUES (1, 'Ann', 'b'), (2, 'bob', 'A'), (3, 'Carl', 'a')")?;
+        let text = |s: &str| Value::Text(s.to_string());
+        let column = |db: &Database<_>, sql: &str| -> Result<Vec<Value>, DatabaseError> {
+            Ok(db.query(sql, &[])?.rows.into_iter().map(|mut r| r.remove(0)).collect())
+        };
+        let ids = |ids: &[i64]| ids.iter().map(|&i| Value::Int(i)).collect::<Vec<_>>();
+
+        // A column's collation decides how it compares and sorts, unless `COLLATE` says otherwise.
+        assert_eq!(column(&db, "SELECT id FROM people WHERE name = 'BOB'")?, ids(&[2]));
+        assert_eq!(column(&db, "SELECT id FROM people WHERE 'ann' IN (name, tag)")?, ids(&[1]));
+        assert_eq!(column(&db, "SELECT id FROM people WHERE name BETWEEN 'B' AND 'CZ' ORDER BY id")?, ids(&[2, 3]));
+        assert_eq!(column(&db, "SELECT id FROM people WHERE name COLLATE binary = 'BOB'")?, ids(&[]));
+        assert_eq!(column(&db, "SELECT id FROM people WHERE tag = 'B'")?, ids(&[]));
+        assert_eq!(column(&db, "SELECT id FROM people WHERE tag COLLATE nocase = 'B'")?, ids(&[1]));
+        assert_eq!(column(&db, "SELECT id FROM people ORDER BY name DESC")?, ids(&[3, 2, 1]));
+        assert_eq!(column(&db, "SELECT id FROM people ORDER BY name COLLATE binary")?, ids(&[1, 3, 2]));
+        let names = column(&db, "SELECT n FROM (SELECT name AS n FROM people) p ORDER BY 1")?;
+        assert_eq!(names, [text("Ann"), text("bob"), text("Carl")]);
+        let tags = column(&db, "SELECT DISTINCT tag FROM people ORDER BY tag COLLATE nocase")?;
+        assert_eq!((tags.len(), &tags[2]), (3, &text("b")));
+
+        // The unique index keeps the names' sort keys, so it tells names apart only as they compare.
+        let duplicate = db.execute("INSERT INTO people VALUES (4, 'ANN', NULL)");
+        assert!(matches!(duplicate, Err(DatabaseError::Table(TableError::UniqueViolation { .. }))));
+        db.execute("CREATE INDEX people_by_tag ON people (tag COLLATE nocase)")?;
+        let unsupported = db.execute("CREATE TABLE numbers (n INT COLLATE nocase)");
+        assert_eq!(unsupported, Err(DatabaseError::Unsupported("collations on columns other than text")));
+
+        // The collations are kept in the catalog, so the indexes reopen keyed as they were built.
+        let db = Database::open(&store)?;
+        for (sql, index) in [("name = 'carl'", "people_name_key"), ("tag COLLATE nocase = 'B'", "people_by_tag")] {
+            let explained = column(&db, &format!("EXPLAIN SELECT id FROM people WHERE {sql}"))?;
+            let Value::Text(line) = &explained[1] else { panic!("not a line of text") };
+            assert!(line.starts_with(&format!("-> IndexScan on people using {index}")), "{line}");
+        }
+        assert_eq!(column(&db, "SELECT id FROM people WHERE name = 'carl'")?, ids(&[3]));
+        assert_eq!(column(&db, "SELECT id FROM people WHERE tag COLLATE nocase = 'B'")?, ids(&[1]));
+        let explained = column(&db, "EXPLAIN SELECT id FROM people WHERE tag = 'b'")?;
+        assert!(explained.iter().all(|l| !matches!(l, Value::Text(l) if l.contains("people_by_tag"))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_select_and_many_rows() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (id INT PRIMARY KEY, parent INT REFERENCES t, s TEXT DEFAULT 'x')")?;
+        let values: Vec<_> = (0..5000).map(|i| format!("({i}, {})", i / 2)).collect();
+        db.execute(&format!("INSERT INTO t (id, parent) VALUES {}", values.join(", ")))?;
+        let count = |db: &Database<_>, sql| -> Result<_, DatabaseError> { Ok(db.query(sql, &[])?.rows[0].remove(0)) };
+        assert_eq!(count(&db, "SELECT count(*) FROM t WHERE s = 'x'")?, Value::Int(5000));
+
+        // A query's rows are all read before any is written, even from the table they go to.
+        db.execute("INSERT INTO t (s, id) SELECT 'y', id + 5000 FROM t WHERE id < 100")?;
+        assert_eq!(count(&db, "SELECT count(*) FROM t")?, Value::Int(5100));
+        assert_eq!(count(&db, "SELECT count(*) FROM t WHERE s = 'y' AND parent IS NULL")?, Value::Int(100));
+
+        // A row breaking a constraint fails its whole batch.
+        let key = vec![Value::Int(99_999)];
+        let violation = Err(DatabaseError::ForeignKeyViolation { constraint: "t_parent_fkey".to_string(), key });
+        assert_eq!(db.execute("INSERT INTO t (id, parent) VALUES (6000, 6001), (6001, 99999)"), violation);
+        let duplicate = db.execute("INSERT INTO t (id) SELECT id * 0 + 7000 FROM t WHERE id < 2");
+        assert!(matches!(duplicate, Err(DatabaseError::Table(TableError::UniqueViolation { .. }))));
+        assert_eq!(count(&db, "SELECT count(*) FROM t WHERE id >= 6000")?, Value::Int(0));
+        let wrong = db.execute("INSERT INTO t (id, parent) SELECT id FROM t");
+        assert_eq!(wrong, Err(DatabaseError::Table(TableError::Tuple(TupleError::WrongColumnCount))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_and_delete() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (id INT PRIMARY KEY, parent INT REFERENCES t ON DELETE CASCADE, n INT)")?;
+        db.execute("CREATE INDEX t_by_n ON t (n)")?;
+        let parent = |i: i64| if i < 10 { "NULL".to_string() } else { (i / 10).to_string() };
+        let values: Vec<_> = (0..100).map(|i| format!("({i}, {}, {})", parent(i), i % 5)).collect();
+        assert_eq!(db.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")))?, 100);
+        let ints = |db: &Database<_>, sql| -> Result<Vec<i64>, DatabaseError> {
+            let rows = db.query(sql, &[])?.rows;
+            Ok(rows.iter().map(|r| if let Value::Int(v) = r[0] { v } else { -1 }).collect())
+        };
+
+        // Every row is found before any is written, so a row moved into the filter's range is not seen again.
+        assert_eq!(db.execute("UPDATE t SET n = n + 5.0 WHERE n < 5")?, 100);
+        assert_eq!(db.execute("UPDATE t SET n = id WHERE id = 42")?, 1);
+        assert_eq!(ints(&db, "SELECT id FROM t WHERE n = 42")?, vec![42]);
+        assert_eq!(ints(&db, "SELECT count(*) FROM t WHERE n = 7")?, vec![19]);
+        let subquery = "UPDATE t SET n = 0 WHERE id IN (SELECT parent FROM t WHERE n = 9)";
+        assert_eq!(db.execute(subquery)?, 9);
+        assert_eq!(db.execute("UPDATE t SET n = 1 WHERE FALSE")?, 0);
+        let missing = db.execute("UPDATE t SET m = 1");
+        assert_eq!(missing, Err(DatabaseError::NoSuchColumn("m".to_string())));
+        let taken = db.execute("UPDATE t SET id = 1 WHERE id = 95");
+        assert!(matches!(taken, Err(DatabaseError::Table(TableError::UniqueViolation { .. }))));
+
+        // Deleting rows 1 and 2 cascades to 10 to 29, and deleting all of them at once deletes each once.
+        assert_eq!(db.execute("DELETE FROM t WHERE id = 1 OR id = 2 OR id = 15")?, 3);
+        assert_eq!(ints(&db, "SELECT count(*) FROM t")?, vec![78]);
+        assert!(ints(&db, "SELECT id FROM t WHERE id >= 10 AND id < 30")?.is_empty());
+        assert_eq!(db.execute("DELETE FROM t WHERE id >= 95")?, 5);
+        assert_eq!(db.execute("DELETE FROM t")?, 73);
+        assert_eq!(ints(&db, "SELECT count(*) FROM t WHERE n = 7")?, vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_without_rowid_tables() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (region TEXT, id INT, note TEXT, PRIMARY KEY (region, id)) WITHOUT ROWID")?;
+        let values: Vec<_> = (0..300).rev().map(|i| format!("('{}', {i}, 'n{i}')", ["east", "west"][i % 2])).collect();
+        assert_eq!(db.execute(&format!("INSERT INTO t VALUES {}", values.join(", ")))?, 300);
+        let ints = |db: &Database<_>, sql| -> Result<Vec<i64>, DatabaseError> {
+            let rows = db.query(sql, &[])?.rows;
+            Ok(rows.iter().map(|r| if let Value::Int(v) = r[0] { v } else { -1 }).collect())
+        };
+
+        // The rows come out of the tree in primary key order, and fixing the key's leading column reads only
+        // the rows under it.
+        let sql = "SELECT id FROM t WHERE region = 'east' ORDER BY region, id LIMIT 3";
+        assert_eq!(ints(&db, sql)?, vec![0, 2, 4]);
+        assert_eq!(db.prepare(sql)?.plan().describe(), "Limit(Project(ClusteredScan(t)))");
+        let lines = db.query("EXPLAIN SELECT note FROM t WHERE region = 'west' AND id = 7", &[])?.rows;
+        assert!(lines.iter().any(|l| matches!(&l[0], Value::Text(l) if l.contains("ClusteredScan on t"))));
+        let note = db.query("SELECT note FROM t WHERE region = 'west' AND id = 7", &[])?.rows;
+        assert_eq!(note, vec![vec![Value::Text("n7".to_string())]]);
+        assert_eq!(ints(&db, "SELECT count(*) FROM t WHERE id < 100")?, vec![100]);
+
+        // Keys that move are all deleted before any is inserted, so a row may take the key of one moving on.
+        assert_eq!(db.execute("UPDATE t SET note = 'low' WHERE region = 'east' AND id < 10")?, 5);
+        assert_eq!(db.execute("UPDATE t SET id = id + 2 WHERE region = 'west'")?, 150);
+        assert_eq!(ints(&db, "SELECT min(id), max(id) FROM t WHERE region = 'west'")?, vec![3]);
+        assert_eq!(ints(&db, "SELECT count(*) FROM t WHERE note = 'low'")?, vec![5]);
+        let taken = db.execute("INSERT INTO t VALUES ('north', 1, 'a'), ('east', 0, 'b')");
+        assert_eq!(taken, Err(DatabaseError::Table(TableError::DuplicateKey)));
+        assert_eq!(ints(&db, "SELECT count(*) FROM t WHERE region = 'north'")?, vec![0]);
+        assert!(db.execute("INSERT INTO t VALUES (NULL, 1, 'a')").is_err());
+        let upsert = db.execute("INSERT INTO t VALUES ('east', 0, 'b') ON CONFLICT DO NOTHING");
+        assert!(matches!(upsert, Err(DatabaseError::Unsupported(_))));
+        assert_eq!(db.execute("DELETE FROM t WHERE region = 'east' AND id >= 100")?, 100);
+        assert_eq!(db.analyze("t")?.row_count, 200);
+
+        // What only a heap table has is refused, whether asked for through SQL or by opening it.
+        let index = db.execute("CREATE INDEX by_note ON t (note)");
+        assert_eq!(index, Err(DatabaseError::NotAHeapTable("t".to_string())));
+        assert!(matches!(db.execute("CREATE TABLE u (a INT) WITHOUT ROWID"), Err(DatabaseError::Unsupported(_))));
+        assert!(db.catalog().table("u").is_none());
+        assert!(matches!(db.open_table("t"), Err(DatabaseError::NotAHeapTable(_))));
+
+        drop(db);
+        let mut db = Database::open(&store)?;
+        assert_eq!(ints(&db, "SELECT id FROM t WHERE region = 'west' ORDER BY id DESC LIMIT 1")?, vec![301]);
+        let free = db.allocator().free_pages(&store)?.len();
+        db.execute("DROP TABLE t")?;
+        assert!(db.allocator().free_pages(&store)?.len() > free);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partitioned_tables() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE events (day INT NOT NULL, note TEXT) PARTITION BY RANGE (day)")?;
+        let none = db.execute("INSERT INTO events VALUES (1, 'early')");
+        assert_eq!(none, Err(DatabaseError::Table(TableError::NoPartition)));
+        for lower in [0, 10, 20, 30] {
+            db.execute(&format!("ALTER TABLE events ADD PARTITION FROM {lower}"))?;
+        }
+        let lower = db.execute("ALTER TABLE events ADD PARTITION FROM 5");
+        assert_eq!(lower, Err(DatabaseError::Table(TableError::InvalidPartition)));
+        let values: Vec<_> = (0..40).map(|i| format!("({i}, 'n{i}')")).collect();
+        assert_eq!(db.execute(&format!("INSERT INTO events VALUES {}", values.join(", ")))?, 40);
+        let ints = |db: &Database<_>, sql| -> Result<Vec<i64>, DatabaseError> {
+            let rows = db.query(sql, &[])?.rows;
+            Ok(rows.iter().map(|r| if let Value::Int(v) = r[0] { v } else { -1 }).collect())
+        };
+
+        // A predicate on the partition column prunes the partitions scanned, and still filters their rows.
+        let sql = "SELECT day FROM events WHERE day >= 12 AND day < 15 ORDER BY day";
+        assert_eq!(ints(&db, sql)?, vec![12, 13, 14]);
+        assert_eq!(db.prepare(sql)?.plan().describe(), "Project(OrderBy(Filter(PartitionScan(events))))");
+        let lines = db.query(&format!("EXPLAIN {sql}"), &[])?.rows;
+        assert!(lines.iter().any(|l| matches!(&l[0], Value::Text(l) if l.contains("events pruning partitions"))));
+        let lines = db.query("EXPLAIN SELECT day FROM events WHERE note = 'n3'", &[])?.rows;
+        assert!(!lines.iter().any(|l| matches!(&l[0], Value::Text(l) if l.contains("pruning"))));
+        assert_eq!(ints(&db, "SELECT count(*) FROM events WHERE day = 25")?, vec![1]);
+        assert_eq!(ints(&db, "SELECT count(*) FROM events WHERE day > 100")?, vec![0]);
+
+        // A row whose partition column changes moves to the partition taking its new value.
+        assert_eq!(db.execute("UPDATE events SET day = day + 40 WHERE day < 3")?, 3);
+        assert_eq!(ints(&db, "SELECT count(*) FROM events WHERE day >= 30")?, vec![13]);
+        assert_eq!(ints(&db, "SELECT count(*) FROM events WHERE day < 10")?, vec![7]);
+        assert_eq!(db.execute("DELETE FROM events WHERE day >= 30 AND note <> 'n0'")?, 12);
+        assert_eq!(db.analyze("events")?.row_count, 28);
+
+        db.execute("CREATE TABLE notes (id INT, body TEXT) PARTITION BY HASH (id) PARTITIONS 4")?;
+        let values: Vec<_> = (0..100).map(|i| format!("({i}, 'b{i}')")).collect();
+        db.execute(&format!("INSERT INTO notes VALUES {}", values.join(", ")))?;
+        let lines = db.query("EXPLAIN SELECT body FROM notes WHERE id = 42", &[])?.rows;
+        assert!(lines.iter().any(|l| matches!(&l[0], Value::Text(l) if l.contains("notes pruning partitions"))));
+        let body = db.query("SELECT body FROM notes WHERE id = 42", &[])?.rows;
+        assert_eq!(body, vec![vec![Value::Text("b42".to_string())]]);
+        assert_eq!(ints(&db, "SELECT count(*) FROM notes WHERE id < 50")?, vec![50]);
+        let range = db.execute("ALTER TABLE notes ADD PARTITION FROM 3");
+        assert_eq!(range, Err(DatabaseError::Table(TableError::InvalidPartition)));
+
+        // What partitioned tables cannot have is refused.
+        let nullable = db.execute("CREATE TABLE u (a INT) PARTITION BY RANGE (a)");
+        assert_eq!(nullable, Err(DatabaseError::Table(TableError::NullableKey(0))));
+        let keyed = db.execute("CREATE TABLE u (a INT PRIMARY KEY) PARTITION BY HASH (a) PARTITIONS 2");
+        assert!(matches!(keyed, Err(DatabaseError::Unsupported(_))));
+        assert!(db.catalog().table("u").is_none());
+        let heap = db.execute("CREATE TABLE h (a INT)").and_then(|_| db.execute("ALTER TABLE h ADD PARTITION FROM 1"));
+        assert_eq!(heap, Err(DatabaseError::NotAPartitionedTable("h".to_string())));
+        assert!(matches!(db.open_table("events"), Err(DatabaseError::NotAHeapTable(_))));
+
+        drop(db);
+        let mut db = Database::open(&store)?;
+        assert_eq!(ints(&db, "SELECT day FROM events WHERE day >= 30")?, vec![40]);
+        let free = db.allocator().free_pages(&store)?.len();
+        db.execute("DROP TABLE events")?;
+        db.execute("DROP TABLE notes")?;
+        assert!(db.allocator().free_pages(&store)?.len() >= free + 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (id INT PRIMARY KEY, name TEXT, hits INT NOT NULL, UNIQUE (name, hits))")?;
+        db.execute("INSERT INTO t VALUES (1, 'a', 1), (2, 'b', 1)")?;
+        let rows = |db: &Database<_>| -> Result<Vec<Vec<Value>>, DatabaseError> {
+            Ok(db.query("SELECT id, name, hits FROM t ORDER BY id", &[])?.rows)
+        };
+        let row = |id, name: &str, hits| vec![Value::Int(id), Value::Text(name.to_string()), Value::Int(hits)];
+
+        let upsert = "INSERT INTO t VALUES (1, 'x', 5), (3, 'c', 1) \
+            ON CONFLICT (id) DO UPDATE SET hits = hits + excluded.hits, name = t.name || excluded.name";
+        assert_eq!(db.execute(upsert)?, 2);
+        assert_eq!(rows(&db)?, vec![row(1, "ax", 6), row(2, "b", 1), row(3, "c", 1)]);
+        // The filter reads both rows; a row it fails for is neither inserted nor updated.
+        let filtered = "INSERT INTO t VALUES (2, 'y', 1), (3, 'z', 1) ON CONFLICT (id) DO UPDATE SET hits = 0 \
+            WHERE excluded.name = 'z' AND t.hits = 1";
+        assert_eq!(db.execute(filtered)?, 1);
+        assert_eq!(rows(&db)?, vec![row(1, "ax", 6), row(2, "b", 1), row(3, "c", 0)]);
+
+        // Without a target every unique index is handled; with one, a conflict in another still fails.
+        let nothing = "INSERT INTO t VALUES (2, 'q', 1), (4, 'b', 1), (5, 'e', 2) ON CONFLICT DO NOTHING";
+        assert_eq!(db.execute(nothing)?, 1);
+        let other = db.execute("INSERT INTO t VALUES (6, 'b', 1) ON CONFLICT (id) DO NOTHING");
+        let index = |e: DatabaseError| match e {
+            DatabaseError::Table(TableError::UniqueViolation { index, .. }) => Some(index),
+            _ => None,
+        };
+        assert_eq!(other.err().and_then(index).as_deref(), Some("t_name_hits_key"));
+        assert_eq!(db.execute("INSERT INTO t VALUES (7, 'b', 1) ON CONFLICT (hits, name) DO NOTHING")?, 0);
+        assert_eq!(rows(&db)?.len(), 4);
+
+        let missing = |target: &[&str]| Err(DatabaseError::NoConflictIndex(target.iter().map(|&t| t.into()).collect()));
+        assert_eq!(db.execute("INSERT INTO t VALUES (8, 'h', 1) ON CONFLICT (name) DO NOTHING"), missing(&["name"]));
+        assert_eq!(db.execute("INSERT INTO t VALUES (8, 'h', 1) ON CONFLICT DO UPDATE SET hits = 1"), missing(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_triggers() -> Result<(), DatabaseError> {
+        let store = PageStore::new(ShadowStorage::open(TestStorage::new()).map_err(PageError::Storage)?);
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE accounts (id INT PRIMARY KEY, balance INT)")?;
+        db.execute("CREATE TABLE entries (account INT REFERENCES accounts ON DELETE CASCADE, amount INT)")?;
+        db.execute("CREATE TABLE log (event TEXT, id INT, amount INT)")?;
+        db.execute("CREATE TRIGGER log_insert AFTER INSERT ON accounts BEGIN \
+            INSERT INTO log VALUES ('insert', new.id, new.balance); END")?;
+        db.execute("CREATE TRIGGER log_update AFTER UPDATE ON accounts FOR EACH ROW BEGIN \
+            INSERT INTO log VALUES ('update', old.id, new.balance - old.balance); END")?;
+        db.execute("CREATE TRIGGER refund BEFORE DELETE ON entries BEGIN \
+            INSERT INTO log (event, id, amount) VALUES ('refund', old.account, old.amount); END")?;
+        // Runs before `log_insert`, so it logs the balance as clamped.
+        db.register_trigger("accounts", "clamp", TriggerTiming::Before, TriggerEvent::Insert, |_, row| {
+            let new = row.new.as_mut().expect("an insert has a new row");
+            match new[0] {
+                Value::Int(id) if id > 100 => return Err(DatabaseError::Rejected(format!("account {id}"))),
+                _ => {}
+            }
+            if let Value::Int(balance) = &mut new[1] {
+                *balance = (*balance).max(0);
+            }
+            Ok(())
+        })?;
+        let (after, delete) = (TriggerTiming::After, TriggerEvent::Delete);
+        let duplicate = db.register_trigger("accounts", "log_insert", after, delete, |_, _| Ok(()));
+        let catalog = |e| Err(DatabaseError::Catalog(e));
+        assert_eq!(duplicate, catalog(CatalogError::DuplicateTrigger("log_insert".to_string())));
+        let select = db.execute("CREATE TRIGGER t AFTER INSERT ON log BEGIN SELECT 1; END");
+        assert_eq!(select, Err(DatabaseError::Unsupported("trigger statements other than INSERT, UPDATE and DELETE")));
+
+        let int = |v| Value::Int(v);
+        db.execute("INSERT INTO accounts VALUES (1, 10), (2, -5)")?;
+        let rejected = db.insert("accounts", &[int(200), int(1)]).map(|_| ());
+        assert_eq!(rejected, Err(DatabaseError::Rejected("account 200".to_string())));
+        db.execute("INSERT INTO entries VALUES (1, 3), (1, 4)")?;
+        db.execute("UPDATE accounts SET balance = balance + 5 WHERE id = 1")?;
+        // Deleting account 1 cascades to its entries, which run their own trigger.
+        db.execute("DELETE FROM accounts WHERE id = 1")?;
+        let log = |db: &Database<_>| -> Result<Vec<Vec<Value>>, DatabaseError> {
+            Ok(db.query("SELECT * FROM log ORDER BY event, id, amount", &[])?.rows)
+        };
+        let entry = |event: &str, id, amount| vec![Value::Text(event.to_string()), int(id), int(amount)];
+        let inserts = [entry("insert", 1, 10), entry("insert", 2, 0)];
+        let expected = [&inserts[..], &[entry("refund", 1, 3), entry("refund", 1, 4), entry("update", 1, 5)]].concat();
+        assert_eq!(log(&db)?, expected);
+        db.execute("CREATE TRIGGER gone AFTER DELETE ON accounts BEGIN DELETE FROM log WHERE id = new.id; END")?;
+        assert_eq!(db.execute("DELETE FROM accounts"), Err(DatabaseError::NoSuchColumn("new.id".to_string())));
+        db.drop_trigger("accounts", "gone")?;
+
+        // A trigger running itself fails the statement, whose writes never commit.
+        db.execute("CREATE TRIGGER again AFTER INSERT ON log BEGIN \
+            INSERT INTO log VALUES (new.event, new.id, 0); END")?;
+        let depth = db.execute("INSERT INTO accounts VALUES (3, 1)");
+        assert_eq!(depth, Err(DatabaseError::TriggerDepth("again".to_string())));
+        let shadow = ShadowStorage::open(store.into_storage().into_inner()).map_err(PageError::Storage)?;
+        let store = PageStore::new(shadow);
+        let mut db = Database::open(&store)?;
+        assert_eq!(log(&db)?, expected);
+        let names = |db: &Database<_>, table| -> Vec<String> {
+            db.catalog().table(table).unwrap().triggers.iter().map(|t| t.name.clone()).collect()
+        };
+        assert_eq!(names(&db, "log"), vec!["again".to_string()]);
+        db.execute("DROP TRIGGER again ON log")?;
+        assert_eq!(db.drop_trigger("log", "again"), catalog(CatalogError::NoSuchTrigger("again".to_string())));
+        // The callback was not kept, so nothing clamps the balance any more.
+        db.insert("accounts", &[int(4), int(-1)])?;
+        assert_eq!(db.query("SELECT * FROM log WHERE id = 4", &[])?.rows, vec![entry("insert", 4, -1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_views() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let mut users = db.create_table("users", columns())?;
+        for i in 0..10 {
+            users.insert(&row(i))?;
+        }
+        db.execute("CREATE VIEW evens (n, label) AS SELECT id, name FROM users WHERE id % 2 = 0")?;
+        db.execute("CREATE VIEW big AS SELECT n * 10 AS big, label FROM evens WHERE n > 4")?;
+        let rows = db.query("SELECT e.n, b.big FROM evens AS e JOIN big AS b ON b.label = e.label ORDER BY 1", &[])?;
+        assert_eq!(rows.columns, vec!["n", "big"]);
+        let int = Value::Int;
+        assert_eq!(rows.rows, vec![vec![int(6), int(60)], vec![int(8), int(80)]]);
+        let rows = db.query("SELECT count(*) FROM evens WHERE label LIKE 'user%'", &[])?.rows;
+        assert_eq!(rows, vec![vec![int(5)]]);
+
+        // A prepared statement over a view plans again once the view is replaced.
+        let mut statement = db.prepare("SELECT big FROM big ORDER BY big")?;
+        assert_eq!(statement.query(&db, &[])?.into_result()?.rows, vec![vec![int(60)], vec![int(80)]]);
+        db.execute("DROP VIEW big")?;
+        db.execute("CREATE VIEW big AS SELECT n AS big FROM evens WHERE n < 3")?;
+        assert_eq!(statement.query(&db, &[])?.into_result()?.rows, vec![vec![int(0)], vec![int(2)]]);
+
+        // Views survive reopening, and what they read cannot be dropped or renamed from under them.
+        drop(db);
+        let mut db = Database::open(&store)?;
+        assert_eq!(db.query("SELECT * FROM big", &[])?.rows, vec![vec![int(0)], vec![int(2)]]);
+        let referenced = |view: &str| Err(DatabaseError::Catalog(CatalogError::Referenced(view.to_string())));
+        assert_eq!(db.execute("DROP VIEW evens").map(|_| ()), referenced("big"));
+        assert_eq!(db.drop_table("users"), referenced("big"));
+        assert_eq!(db.rename_table("users", "people"), referenced("big"));
+        let duplicate = Err(DatabaseError::Catalog(CatalogError::DuplicateTable("users".to_string())));
+        assert_eq!(db.execute("CREATE VIEW users AS SELECT 1"), duplicate);
+        let invalid = Err(DatabaseError::Plan(PlanError::InvalidView("pair".to_string())));
+        assert_eq!(db.execute("CREATE VIEW pair (a, b) AS SELECT id FROM users"), invalid);
+        let missing = Err(DatabaseError::Plan(PlanError::NoSuchTable("gone".to_string())));
+        assert_eq!(db.execute("CREATE VIEW v AS SELECT * FROM gone"), missing);
+        db.execute("DROP VIEW big")?;
+        db.execute("DROP VIEW IF EXISTS big")?;
+        db.execute("DROP VIEW evens")?;
+        db.drop_table("users")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let mut users = db.create_table("users", columns())?;
+        for i in 0..100 {
+            users.insert(&row(i))?;
+        }
+        db.create_index("users", "by_id", vec![0], vec![])?;
+        db.analyze("users")?;
+        let lines = |sql: &str, params: &[Value]| -> Result<Vec<String>, DatabaseError> {
+            let result = db.query(sql, params)?;
+            assert_eq!(result.columns, vec!["plan"]);
+            let text = |row: Vec<Value>| match &row[..] {
+                [Value::Text(line)] => line.clone(),
+                row => panic!("not a line of text: {row:?}"),
+            };
+            Ok(result.rows.into_iter().map(text).collect())
+        };
+
+        let plan = lines("EXPLAIN SELECT name FROM users WHERE id = $1", &[])?;
+        assert_eq!(plan.len(), 2);
+        assert!(plan[0].starts_with("Project  (rows=1 cost="), "{}", plan[0]);
+        assert!(plan[1].starts_with("-> IndexScan on users using by_id  (rows=1 cost="), "{}", plan[1]);
+        let statement = db.prepare("SELECT u.name FROM users u JOIN users v ON u.id = v.id WHERE v.id < 10")?;
+        assert_eq!(statement.query.estimates.len(), statement.plan().nodes().len());
+
+        let sql = "EXPLAIN ANALYZE SELECT u.name FROM users u JOIN users v ON u.name = v.name WHERE v.id < $1 LIMIT 3";
+        let analyzed = lines(sql, &[Value::Int(10)])?;
+        assert!(analyzed[0].starts_with("Limit  (rows=3 cost="), "{}", analyzed[0]);
+        assert!(analyzed[0].contains("(actual rows=3 loops=1 time="), "{}", analyzed[0]);
+        let nodes: Vec<_> = analyzed.iter().map(|line| line.split("  (").next().unwrap()).collect();
+        assert_eq!(nodes, vec![
+            "Limit",
+            "-> Project",
+            "   -> HashJoin",
+            "      -> SeqScan on users",
+            "      -> Filter",
+            "         -> SeqScan on users",
+        ]);
+        assert!(analyzed[5].contains("(actual rows=100 loops=1 "), "{}", analyzed[5]);
+        assert!(analyzed.iter().all(|line| line.contains(" loops=1 ")), "{analyzed:?}");
+        assert_eq!(db.query("EXPLAIN ANALYZE SELECT * FROM users WHERE id = $1", &[]), Err(missing_parameter()));
+        assert_eq!(db.execute("EXPLAIN SELECT 1"), Err(DatabaseError::Unsupported("EXPLAIN outside `query`")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_copy() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE t (id INT PRIMARY KEY, name TEXT DEFAULT 'anon', data BYTEA, score FLOAT)")?;
+        let input = "score,id,data\n1.5,1,\\x00ff\n,2,\"raw, \"\"text\"\"\"\n\"2\",3,\n";
+        assert_eq!(db.copy_from_csv("t", input.as_bytes(), &CsvOptions::default())?, 3);
+        let rows = db.query("SELECT * FROM t ORDER BY id", &[])?.rows;
+        assert_eq!(rows, vec![
+            vec![Value::Int(1), Value::Text("anon".into()), Value::Bytes(vec![0, 255]), Value::Float(1.5)],
+            vec![Value::Int(2), Value::Text("anon".into()), Value::Bytes(b"raw, \"text\"".to_vec()), Value::Null],
+            vec![Value::Int(3), Value::Text("anon".into()), Value::Null, Value::Float(2.0)],
+        ]);
+
+        let options = CsvOptions { header: false, delimiter: b';', batch_rows: 2, ..CsvOptions::default() };
+        let invalid = CsvError::InvalidValue { column: "id".to_string(), value: "four".to_string() };
+        let input = "4;a;;\n\nfour;b;;\n";
+        let invalid = DatabaseError::Csv { line: 3, error: invalid };
+        assert_eq!(db.copy_from_csv("t", input.as_bytes(), &options), Err(invalid));
+        let input = "4;\"two\nlines\";;\n5;e;;0\n6;f;;\n1;dup;;\n7;g;;\n";
+        match db.copy_from_csv("t", input.as_bytes(), &options) {
+            Err(DatabaseError::Csv { line: 4, error: CsvError::Rejected { last_line: 5, error } }) => {
+                assert!(matches!(*error, DatabaseError::Table(TableError::UniqueViolation { .. })), "{error:?}")
+            }
+            result => panic!("not rejected: {result:?}"),
+        }
+        let input = "id\n8\n8,x\n";
+        let count = CsvError::FieldCount { expected: 1, found: 2 };
+        let count = DatabaseError::Csv { line: 3, error: count };
+        assert_eq!(db.copy_from_csv("t", input.as_bytes(), &CsvOptions::default()), Err(count));
+        let missing = DatabaseError::Csv { line: 1, error: CsvError::NoSuchColumn("id".to_string()) };
+        assert_eq!(db.copy_from_csv("t", "id,name,id\n".as_bytes(), &CsvOptions::default()), Err(missing));
+
+        let mut out = Vec::new();
+        assert_eq!(db.copy_to_csv("t", &mut out, &CsvOptions::default())?, 5);
+        assert_eq!(String::from_utf8(out).unwrap(), "id,name,data,score\n\
+            1,anon,\\x00ff,1.5\n\
+            2,anon,\\x7261772c20227465787422,\n\
+            3,anon,,2\n\
+            4,\"two\nlines\",,\n\
+            5,e,,0\n");
+
+        // With one column a null is written as a line with nothing on it, which reads back as a null.
+        db.execute("CREATE TABLE one (a TEXT)")?;
+        db.execute("INSERT INTO one VALUES ('x'), (NULL), ('')")?;
+        let mut out = Vec::new();
+        db.copy_to_csv("one", &mut out, &CsvOptions::default())?;
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), "a\nx\n\n\"\"\n");
+        db.execute("DELETE FROM one")?;
+        assert_eq!(db.copy_from_csv("one", out.as_slice(), &CsvOptions::default())?, 3);
+        let rows = db.query("SELECT a FROM one", &[])?.rows;
+        assert_eq!(rows, vec![vec![Value::Text("x".into())], vec![Value::Null], vec![Value::Text(String::new())]]);
+        Ok(())
+    }
+
+    fn missing_parameter() -> DatabaseError {
+        DatabaseError::Exec(ExecError::MissingParameter(0))
+    }
+}
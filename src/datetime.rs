@@ -0,0 +1,518 @@
+//! Dates, times of day, timestamps and intervals: their calendar arithmetic, parsing and formatting.
+//!
+//! A date is a number of days since 1970-01-01 in the proleptic Gregorian calendar, a time of day a
+//! number of microseconds since midnight, and a timestamp a number of microseconds since midnight on
+//! 1970-01-01. A timestamp with a time zone is the same number read as UTC: an offset given when one is
+//! parsed moves it to UTC, and one is formatted with the offset `+00`, the only zone sessions have.
+//!
+//! An interval is a number of months and a number of microseconds, kept apart because months differ in
+//! length: adding one to a timestamp moves it by calendar months first, keeping the day of the month
+//! unless the month is too short for it, and then by the microseconds. Intervals compare by their length
+//! with a month counted as 30 days, and then by their months, so that `1 mon` and `30 days` differ.
+//!
+//! Text parses as ISO 8601: `YYYY-MM-DD`, `HH:MM[:SS[.ffffff]]`, and a date and time separated by `T` or
+//! a space, which for a zoned timestamp may end in `Z` or an offset like `+02` or `-05:30`. An interval is
+//! a list of numbers with units, such as `1 year 2 months`, and a time like `04:05:06`, negated by a
+//! trailing `ago`.
+pub const MICROS_PER_SECOND: i64 = 1_000_000;
+pub const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November",
+    "December",
+];
+const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Interval {
+    pub months: i32,
+    pub micros: i64,
+}
+impl Interval {
+    /// The interval's length in microseconds, with a month counted as 30 days.
+    pub fn length(self) -> i128 {
+        self.months as i128 * 30 * MICROS_PER_DAY as i128 + self.micros as i128
+    }
+
+    pub fn checked_add(self, other: Interval) -> Option<Interval> {
+        let months = self.months.checked_add(other.months)?;
+        Some(Interval { months, micros: self.micros.checked_add(other.micros)? })
+    }
+
+    pub fn checked_neg(self) -> Option<Interval> {
+        Some(Interval { months: self.months.checked_neg()?, micros: self.micros.checked_neg()? })
+    }
+
+    /// The interval scaled by `factor`, with the fraction of a month it leaves counted as 30 days.
+    pub fn checked_mul(self, factor: f64) -> Option<Interval> {
+        let months = self.months as f64 * factor;
+        let micros = self.micros as f64 * factor + months.fract() * (30 * MICROS_PER_DAY) as f64;
+        // The bounds are powers of two, exact as floats.
+        let fits = |v: f64, bound: f64| v.is_finite() && v >= -bound && v < bound;
+        if !fits(months.trunc(), 2f64.powi(31)) || !fits(micros.round(), 2f64.powi(63)) {
+            return None
+        }
+        Some(Interval { months: months.trunc() as i32, micros: micros.round() as i64 })
+    }
+}
+impl Ord for Interval {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.length(), self.months).cmp(&(other.length(), other.months))
+    }
+}
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date, which must be valid.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Years start in March here, so that the leap day is the last of its year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, of_era) = (year.div_euclid(400), year.rem_euclid(400));
+    let month = month as i64;
+    let of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    era * 146_097 + of_era * 365 + of_era / 4 - of_era / 100 + of_year - 719_468
+}
+
+/// The year, month and day of the date `days` after 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let (era, of_era) = (days.div_euclid(146_097), days.rem_euclid(146_097));
+    let year_of_era = (of_era - of_era / 1460 + of_era / 36_524 - of_era / 146_096) / 365;
+    let of_year = of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted = (5 * of_year + 2) / 153;
+    let day = (of_year - (153 * shifted + 2) / 5 + 1) as u32;
+    let month = if shifted < 10 { shifted + 3 } else { shifted - 9 } as u32;
+    let year = era * 400 + year_of_era + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// The timestamp `micros` moved by `interval`: by its months in the calendar, then by its microseconds.
+pub fn add_interval(micros: i64, interval: Interval) -> Option<i64> {
+    let (days, time) = (micros.div_euclid(MICROS_PER_DAY), micros.rem_euclid(MICROS_PER_DAY));
+    let (year, month, day) = civil_from_days(days);
+    let months = year * 12 + month as i64 - 1 + interval.months as i64;
+    let (year, month) = (months.div_euclid(12), months.rem_euclid(12) as u32 + 1);
+    let days = days_from_civil(year, month, day.min(days_in_month(year, month)));
+    days.checked_mul(MICROS_PER_DAY)?.checked_add(time)?.checked_add(interval.micros)
+}
+
+/// Parse `digits` as a number of exactly `len` decimal digits, or any number of them if `len` is 0.
+fn number(digits: &str, len: usize) -> Option<u32> {
+    let valid = !digits.is_empty() && (len == 0 || digits.len() == len) && digits.bytes().all(|b| b.is_ascii_digit());
+    valid.then(|| digits.parse().ok()).flatten()
+}
+
+/// Days since 1970-01-01 of a `YYYY-MM-DD` date.
+pub fn parse_date(text: &str) -> Option<i32> {
+    let mut parts = text.trim().splitn(3, '-');
+    let year = number(parts.next()?, 4)? as i64;
+    let month = number(parts.next()?, 2)?;
+    let day = number(parts.next()?, 2)?;
+    if year == 0 || !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None
+    }
+    Some(days_from_civil(year, month, day) as i32)
+}
+
+/// Microseconds since midnight of a `HH:MM[:SS[.ffffff]]` time of day.
+pub fn parse_time(text: &str) -> Option<i64> {
+    let micros = parse_clock(text.trim())?;
+    (micros < MICROS_PER_DAY).then_some(micros)
+}
+
+/// Microseconds of `HH:MM[:SS[.ffffff]]`, with any number of hours.
+fn parse_clock(text: &str) -> Option<i64> {
+    let mut parts = text.splitn(3, ':');
+    let hours = number(parts.next()?, 0)? as i64;
+    let minutes = number(parts.next()?, 2)? as i64;
+    let (seconds, fraction) = match parts.next() {
+        Some(seconds) => seconds.split_once('.').unwrap_or((seconds, "")),
+        None => ("00", ""),
+    };
+    let seconds = number(seconds, 2)? as i64;
+    if minutes > 59 || seconds > 59 || fraction.len() > 6 {
+        return None
+    }
+    let fraction = match fraction {
+        "" => 0,
+        digits => number(digits, 0)? as i64 * 10i64.pow(6 - digits.len() as u32),
+    };
+    let micros = hours.checked_mul(3600)?.checked_add(minutes * 60 + seconds)?.checked_mul(MICROS_PER_SECOND)?;
+    micros.checked_add(fraction)
+}
+
+/// Microseconds since 1970-01-01 of a date, optionally followed by a time of day and, if `zoned`, a UTC
+/// offset, which the result has been moved by.
+pub fn parse_timestamp(text: &str, zoned: bool) -> Option<i64> {
+    let text = text.trim();
+    let (date, rest) = match text.find(['T', 't', ' ']) {
+        Some(at) => (&text[..at], text[at + 1..].trim()),
+        None => (text, ""),
+    };
+    let days = parse_date(date)? as i64;
+    let (time, offset) = match rest.find(['Z', 'z', '+', '-']).or_else(|| rest.to_ascii_lowercase().find("utc")) {
+        Some(at) if zoned => (rest[..at].trim(), parse_offset(&rest[at..])?),
+        Some(_) => return None,
+        None => (rest, 0),
+    };
+    let time = if time.is_empty() { 0 } else { parse_time(time)? };
+    (days * MICROS_PER_DAY + time).checked_sub(offset)
+}
+
+/// Microseconds of a UTC offset: `Z`, `UTC`, or a sign and `HH`, `HHMM` or `HH:MM`.
+fn parse_offset(text: &str) -> Option<i64> {
+    if text.eq_ignore_ascii_case("z") || text.eq_ignore_ascii_case("utc") {
+        return Some(0)
+    }
+    let sign = match text.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits: String = text[1..].chars().filter(|c| *c != ':').collect();
+    let (hours, minutes) = match digits.len() {
+        2 => (number(&digits, 2)?, 0),
+        4 => (number(&digits[..2], 2)?, number(&digits[2..], 2)?),
+        _ => return None,
+    };
+    (hours <= 15 && minutes <= 59).then_some(sign * (hours as i64 * 60 + minutes as i64) * 60 * MICROS_PER_SECOND)
+}
+
+/// An interval written as numbers with units, an `HH:MM[:SS]` time, or both, with an optional `ago`.
+pub fn parse_interval(text: &str) -> Option<Interval> {
+    let mut words: Vec<&str> = text.split_whitespace().collect();
+    let ago = words.last().is_some_and(|w| w.eq_ignore_ascii_case("ago"));
+    if ago {
+        words.pop();
+    }
+    if words.is_empty() {
+        return None
+    }
+    let mut interval = Interval::default();
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        let part = if word.contains(':') {
+            let (negative, clock) = match word.strip_prefix('-') {
+                Some(clock) => (true, clock),
+                None => (false, word.strip_prefix('+').unwrap_or(word)),
+            };
+            let micros = parse_clock(clock)?;
+            Interval { months: 0, micros: if negative { -micros } else { micros } }
+        } else {
+            let amount: f64 = word.parse().ok()?;
+            let (months, micros) = unit(words.next()?)?;
+            match months {
+                0 => Interval { months: 0, micros: 1 }.checked_mul(amount * micros as f64)?,
+                months => Interval { months: 1, micros: 0 }.checked_mul(amount * months as f64)?,
+            }
+        };
+        interval = interval.checked_add(part)?;
+    }
+    if ago { interval.checked_neg() } else { Some(interval) }
+}
+
+/// The months or, if none, the microseconds in one of an interval's units.
+fn unit(word: &str) -> Option<(i32, i64)> {
+    let word = word.to_ascii_lowercase();
+    let word = word.trim_end_matches(',');
+    let singular = if word.len() > 2 { word.strip_suffix('s').unwrap_or(word) } else { word };
+    Some(match singular {
+        "microsecond" | "usec" | "us" => (0, 1),
+        "millisecond" | "msec" | "ms" => (0, 1000),
+        "second" | "sec" | "s" => (0, MICROS_PER_SECOND),
+        "minute" | "min" | "m" => (0, 60 * MICROS_PER_SECOND),
+        "hour" | "hr" | "h" => (0, 3600 * MICROS_PER_SECOND),
+        "day" | "d" => (0, MICROS_PER_DAY),
+        "week" | "w" => (0, 7 * MICROS_PER_DAY),
+        "month" | "mon" => (1, 0),
+        "year" | "yr" | "y" => (12, 0),
+        "decade" => (120, 0),
+        "century" | "centurie" => (1200, 0),
+        _ => return None,
+    })
+}
+
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+pub fn format_time(micros: i64) -> String {
+    clock(micros)
+}
+
+pub fn format_timestamp(micros: i64) -> String {
+    let (days, time) = (micros.div_euclid(MICROS_PER_DAY), micros.rem_euclid(MICROS_PER_DAY));
+    format!("{} {}", format_date(days as i32), clock(time))
+}
+
+pub fn format_timestamp_tz(micros: i64) -> String {
+    format_timestamp(micros) + "+00"
+}
+
+/// An interval as `1 year 2 mons 3 days 04:05:06`, leaving out the parts that are zero.
+pub fn format_interval(interval: Interval) -> String {
+    let mut parts = Vec::new();
+    let (years, months) = (interval.months / 12, interval.months % 12);
+    let (days, time) = (interval.micros / MICROS_PER_DAY, interval.micros % MICROS_PER_DAY);
+    for (n, one, many) in [(years as i64, "year", "years"), (months as i64, "mon", "mons"), (days, "day", "days")] {
+        if n != 0 {
+            parts.push(format!("{n} {}", if n == 1 { one } else { many }));
+        }
+    }
+    if time != 0 || parts.is_empty() {
+        parts.push(if time < 0 { format!("-{}", clock(-time)) } else { clock(time) });
+    }
+    parts.join(" ")
+}
+
+/// Non-negative microseconds as `HH:MM:SS`, followed by a fraction of a second if there is one.
+fn clock(micros: i64) -> String {
+    let seconds = micros / MICROS_PER_SECOND;
+    let mut text = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let fraction = micros % MICROS_PER_SECOND;
+    if fraction != 0 {
+        text += format!(".{fraction:06}").trim_end_matches('0');
+    }
+    text
+}
+
+/// The fields of a timestamp, for formatting and for `date_part`.
+struct Fields {
+    year: i64,
+    month: u32,
+    day: u32,
+    /// Day of the week, 0 for Sunday.
+    weekday: u32,
+    /// Day of the year, from 1.
+    yearday: u32,
+    /// Microseconds since midnight.
+    time: i64,
+}
+impl Fields {
+    fn of(micros: i64) -> Fields {
+        let days = micros.div_euclid(MICROS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        let weekday = (days + 4).rem_euclid(7) as u32;
+        let yearday = (days - days_from_civil(year, 1, 1)) as u32 + 1;
+        Fields { year, month, day, weekday, yearday, time: micros.rem_euclid(MICROS_PER_DAY) }
+    }
+}
+
+/// The timestamp `micros` formatted by `pattern`, as `to_char` does: `YYYY`, `YY`, `MM`, `DD`, `DDD`,
+/// `HH24`, `HH12` or `HH`, `MI`, `SS`, `MS`, `US`, `AM` or `PM`, `Month`, `Mon`, `Day`, `Dy` and `D` are
+/// replaced by the fields they stand for, names unpadded and in the case the pattern writes them in, text
+/// in double quotes is copied without its quotes, and anything else is copied as it is.
+pub fn to_char(micros: i64, pattern: &str) -> String {
+    let fields = Fields::of(micros);
+    let seconds = fields.time / MICROS_PER_SECOND;
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let hour12 = if hour % 12 == 0 { 12 } else { hour % 12 };
+    // Each name in the case its pattern is written in: all capitals, all lower case, or capitalized.
+    let named = |name: &str, written: &str| {
+        if written.chars().all(|c| c.is_ascii_uppercase()) {
+            name.to_ascii_uppercase()
+        } else if written.chars().all(|c| c.is_ascii_lowercase()) {
+            name.to_ascii_lowercase()
+        } else {
+            name.to_string()
+        }
+    };
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(c) = rest.chars().next() {
+        if c == '"' {
+            let quoted = &rest[1..];
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            out.push_str(&quoted[..end]);
+            rest = quoted.get(end + 1..).unwrap_or("");
+            continue
+        }
+        let token = |t: &str| rest.get(..t.len()).filter(|r| r.eq_ignore_ascii_case(t));
+        let (month, weekday) = (MONTHS[fields.month as usize - 1], DAYS[fields.weekday as usize]);
+        let (len, text) = match () {
+            _ if rest.starts_with("HH24") => (4, format!("{hour:02}")),
+            _ if rest.starts_with("HH12") => (4, format!("{hour12:02}")),
+            _ if rest.starts_with("YYYY") => (4, format!("{:04}", fields.year)),
+            _ if rest.starts_with("DDD") => (3, format!("{:03}", fields.yearday)),
+            _ if rest.starts_with("HH") => (2, format!("{hour12:02}")),
+            _ if rest.starts_with("YY") => (2, format!("{:02}", fields.year.rem_euclid(100))),
+            _ if rest.starts_with("MM") => (2, format!("{:02}", fields.month)),
+            _ if rest.starts_with("DD") => (2, format!("{:02}", fields.day)),
+            _ if rest.starts_with("MI") => (2, format!("{minute:02}")),
+            _ if rest.starts_with("SS") => (2, format!("{second:02}")),
+            _ if rest.starts_with("MS") => (2, format!("{:03}", fields.time / 1000 % 1000)),
+            _ if rest.starts_with("US") => (2, format!("{:06}", fields.time % MICROS_PER_SECOND)),
+            _ if token("AM").or(token("PM")).is_some() => (2, named(if hour < 12 { "AM" } else { "PM" }, &rest[..2])),
+            _ if token("Month").is_some() => (5, named(month, &rest[..5])),
+            _ if token("Mon").is_some() => (3, named(&month[..3], &rest[..3])),
+            _ if token("Day").is_some() => (3, named(weekday, &rest[..3])),
+            _ if token("Dy").is_some() => (2, named(&weekday[..3], &rest[..2])),
+            _ if rest.starts_with('D') => (1, (fields.weekday + 1).to_string()),
+            _ => (c.len_utf8(), c.to_string()),
+        };
+        out.push_str(&text);
+        rest = &rest[len..];
+    }
+    out
+}
+
+/// The field of the timestamp `micros` that `field` names, as `date_part` gives it, or `None` if it names
+/// no field.
+pub fn timestamp_part(micros: i64, field: &str) -> Option<f64> {
+    let fields = Fields::of(micros);
+    Some(match field.to_ascii_lowercase().as_str() {
+        "year" => fields.year as f64,
+        "quarter" => ((fields.month - 1) / 3 + 1) as f64,
+        "month" => fields.month as f64,
+        "day" => fields.day as f64,
+        "dow" => fields.weekday as f64,
+        "doy" => fields.yearday as f64,
+        "epoch" => micros as f64 / MICROS_PER_SECOND as f64,
+        field => return time_part(fields.time, field),
+    })
+}
+
+/// A field of a time of day: its hour, minute, second with its fraction, or the second in milliseconds or
+/// in microseconds.
+pub fn time_part(micros: i64, field: &str) -> Option<f64> {
+    let second = micros % (60 * MICROS_PER_SECOND);
+    Some(match field.to_ascii_lowercase().as_str() {
+        "hour" => (micros / (3600 * MICROS_PER_SECOND)) as f64,
+        "minute" => (micros / (60 * MICROS_PER_SECOND) % 60) as f64,
+        "second" => second as f64 / MICROS_PER_SECOND as f64,
+        "milliseconds" => second as f64 / 1000.0,
+        "microseconds" => second as f64,
+        "epoch" => micros as f64 / MICROS_PER_SECOND as f64,
+        _ => return None,
+    })
+}
+
+/// A field of an interval, its months read as years and months and its microseconds as days and a time.
+pub fn interval_part(interval: Interval, field: &str) -> Option<f64> {
+    let time = interval.micros % MICROS_PER_DAY;
+    Some(match field.to_ascii_lowercase().as_str() {
+        "year" => (interval.months / 12) as f64,
+        "month" => (interval.months % 12) as f64,
+        "day" => (interval.micros / MICROS_PER_DAY) as f64,
+        "epoch" => interval.length() as f64 / MICROS_PER_SECOND as f64,
+        field => time_part(time.abs(), field).map(|v| if time < 0 { -v } else { v })?,
+    })
+}
+
+/// The timestamp `micros` truncated to the start of the `field` it is in, as `date_trunc` does, or
+/// `None` if `field` names no field.
+pub fn truncate(micros: i64, field: &str) -> Option<i64> {
+    let fields = Fields::of(micros);
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let unit = |micros_per: i64| Some(micros - micros.rem_euclid(micros_per));
+    let start = |year, month| Some(days_from_civil(year, month, 1) * MICROS_PER_DAY);
+    match field.to_ascii_lowercase().as_str() {
+        "year" => start(fields.year, 1),
+        "quarter" => start(fields.year, (fields.month - 1) / 3 * 3 + 1),
+        "month" => start(fields.year, fields.month),
+        // Weeks start on Monday.
+        "week" => Some((days - (fields.weekday as i64 + 6) % 7) * MICROS_PER_DAY),
+        "day" => unit(MICROS_PER_DAY),
+        "hour" => unit(3600 * MICROS_PER_SECOND),
+        "minute" => unit(60 * MICROS_PER_SECOND),
+        "second" => unit(MICROS_PER_SECOND),
+        "milliseconds" => unit(1000),
+        "microseconds" => Some(micros),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_interval, civil_from_days, days_from_civil, format_date, format_interval, format_time, format_timestamp,
+        format_timestamp_tz, interval_part, parse_date, parse_interval, parse_time, parse_timestamp, timestamp_part,
+        to_char, truncate, Interval, MICROS_PER_DAY,
+    };
+
+    #[test]
+    fn test_calendar() {
+        for days in [-719_468, -1, 0, 59, 11_016, 19_782, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(parse_date("2024-02-29"), Some(19_782));
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-1-01"), None);
+        assert_eq!(format_date(-1), "1969-12-31");
+
+        let day = MICROS_PER_DAY;
+        let jan31 = parse_timestamp("2024-01-31 10:00", false).unwrap();
+        let month = Interval { months: 1, micros: 0 };
+        assert_eq!(add_interval(jan31, month).map(format_timestamp), Some("2024-02-29 10:00:00".to_string()));
+        let back = Interval { months: -13, micros: day };
+        assert_eq!(add_interval(jan31, back), parse_timestamp("2023-01-01 10:00", false));
+        assert_eq!(add_interval(i64::MAX - 1, Interval { months: 0, micros: 2 }), None);
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        let ts = parse_timestamp("2024-03-05T04:05:06.25", false).unwrap();
+        assert_eq!(format_timestamp(ts), "2024-03-05 04:05:06.25");
+        assert_eq!(parse_timestamp("2024-03-05", false), Some(ts - ts % MICROS_PER_DAY));
+        assert_eq!(parse_timestamp("2024-03-05 04:05:06.25Z", false), None);
+        assert_eq!(parse_timestamp("2024-03-05 06:35:06.25+02:30", true), Some(ts));
+        assert_eq!(parse_timestamp("2024-03-04 23:05:06.25 -0500", true), Some(ts));
+        let utc = parse_timestamp("2024-03-05 04:05:06.25 UTC", true).map(format_timestamp_tz);
+        assert_eq!(utc.unwrap(), "2024-03-05 04:05:06.25+00");
+        assert_eq!(parse_time("23:59:59.999999"), Some(MICROS_PER_DAY - 1));
+        assert_eq!((parse_time("24:00"), parse_time("12:60"), parse_time("1:2")), (None, None, None));
+        assert_eq!(format_time(parse_time("07:08").unwrap()), "07:08:00");
+
+        let interval = parse_interval("1 year 2 mons 3 days 04:05:06").unwrap();
+        assert_eq!(interval, Interval { months: 14, micros: 3 * MICROS_PER_DAY + parse_time("04:05:06").unwrap() });
+        assert_eq!(format_interval(interval), "1 year 2 mons 3 days 04:05:06");
+        assert_eq!(parse_interval("1.5 months"), Some(Interval { months: 1, micros: 15 * MICROS_PER_DAY }));
+        assert_eq!(parse_interval("90 minutes ago").map(format_interval).unwrap(), "-01:30:00");
+        assert_eq!(parse_interval("2 weeks, 1 ms").map(format_interval).unwrap(), "14 days 00:00:00.001");
+        assert_eq!(format_interval(Interval::default()), "00:00:00");
+        assert_eq!((parse_interval(""), parse_interval("3 fortnights"), parse_interval("1")), (None, None, None));
+        assert!(parse_interval("1 mon").unwrap() > parse_interval("30 days").unwrap());
+        assert!(parse_interval("1 mon").unwrap() < parse_interval("30 days 1 us").unwrap());
+    }
+
+    #[test]
+    fn test_fields() {
+        let ts = parse_timestamp("2024-03-05 16:07:08.5", false).unwrap();
+        assert_eq!(to_char(ts, "YYYY-MM-DD HH24:MI:SS.MS"), "2024-03-05 16:07:08.500");
+        assert_eq!(to_char(ts, "Dy, DD Mon YY HH12 am \"day\" DDD D"), "Tue, 05 Mar 24 04 pm day 065 3");
+        assert_eq!(to_char(ts, "DAY month"), "TUESDAY march");
+        assert_eq!(timestamp_part(ts, "QUARTER"), Some(1.0));
+        assert_eq!(timestamp_part(ts, "second"), Some(8.5));
+        assert_eq!(timestamp_part(ts, "dow"), Some(2.0));
+        assert_eq!(timestamp_part(ts, "fortnight"), None);
+        let interval = parse_interval("-1 year -3 hours").unwrap();
+        assert_eq!((interval_part(interval, "year"), interval_part(interval, "hour")), (Some(-1.0), Some(-3.0)));
+
+        let at = |text| parse_timestamp(text, false).unwrap();
+        assert_eq!(truncate(ts, "month"), Some(at("2024-03-01")));
+        assert_eq!(truncate(ts, "quarter"), Some(at("2024-01-01")));
+        assert_eq!(truncate(ts, "week"), Some(at("2024-03-04")));
+        assert_eq!(truncate(ts, "hour"), Some(at("2024-03-05 16:00")));
+        assert_eq!(truncate(at("1969-12-31 23:59:59.5"), "minute"), Some(at("1969-12-31 23:59")));
+    }
+}
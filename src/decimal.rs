@@ -0,0 +1,526 @@
+//! Exact decimal numbers of any size, the values of `DECIMAL` columns.
+//!
+//! A `Decimal` is a sign, a natural number of any size and a scale, the number of its digits that come
+//! after the decimal point, so that 1.50 is 150 at a scale of 2. Sums, differences, products and
+//! remainders are exact. A quotient is rounded to `DIVISION_SCALE` digits after the point, or to as many
+//! as either operand has if that is more. Rounding is always half away from zero. Decimals that differ
+//! only in trailing zeros, like 1.5 and 1.50, are equal and hash alike, but each keeps its own scale, and
+//! shows it. No decimal has more than `MAX_DIGITS` digits on either side of the point; arithmetic that
+//! would make one fails instead.
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::varint;
+
+/// The most digits before the point, or after it, that a decimal may have, and the greatest precision a
+/// `DECIMAL` column may declare.
+pub const MAX_DIGITS: u32 = 1000;
+/// The fewest digits after the point a quotient is rounded to.
+pub const DIVISION_SCALE: u32 = 16;
+
+const BASE: u64 = 1_000_000_000;
+/// Decimal digits in a limb.
+const LIMB_DIGITS: u32 = 9;
+
+/// A natural number in base 10^9, least significant limb first and with no zero limbs at the top, so that
+/// zero has none at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Natural(Vec<u32>);
+impl Natural {
+    fn from_u128(mut v: u128) -> Natural {
+        let mut limbs = Vec::new();
+        while v > 0 {
+            limbs.push((v % BASE as u128) as u32);
+            v /= BASE as u128;
+        }
+        Natural(limbs)
+    }
+
+    fn to_u128(&self) -> Option<u128> {
+        self.0.iter().rev().try_fold(0u128, |n, limb| n.checked_mul(BASE as u128)?.checked_add(*limb as u128))
+    }
+
+    /// The number written in `digits`, which must all be ASCII digits.
+    fn parse(digits: &str) -> Natural {
+        let digits = digits.as_bytes();
+        let mut limbs = Vec::with_capacity(digits.len().div_ceil(LIMB_DIGITS as usize));
+        for chunk in digits.rchunks(LIMB_DIGITS as usize) {
+            limbs.push(chunk.iter().fold(0, |limb, d| limb * 10 + (d - b'0') as u32));
+        }
+        Natural(limbs).trim()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn trim(mut self) -> Natural {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    /// The number of its decimal digits, none for zero.
+    fn digits(&self) -> u32 {
+        match self.0.last() {
+            None => 0,
+            Some(top) => (self.0.len() as u32 - 1) * LIMB_DIGITS + top.ilog10() + 1,
+        }
+    }
+
+    fn add(&self, other: &Natural) -> Natural {
+        let mut limbs = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let sum = carry + *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64;
+            limbs.push((sum % BASE) as u32);
+            carry = sum / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        Natural(limbs)
+    }
+
+    /// `self - other`, where `other` is no greater.
+    fn sub(&self, other: &Natural) -> Natural {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0;
+        for (i, limb) in self.0.iter().enumerate() {
+            let mut difference = *limb as i64 - borrow - *other.0.get(i).unwrap_or(&0) as i64;
+            borrow = (difference < 0) as i64;
+            if difference < 0 {
+                difference += BASE as i64;
+            }
+            limbs.push(difference as u32);
+        }
+        Natural(limbs).trim()
+    }
+
+    fn mul(&self, other: &Natural) -> Natural {
+        if self.is_zero() || other.is_zero() {
+            return Natural::default()
+        }
+        let mut limbs = vec![0u64; self.0.len() + other.0.len()];
+        for (i, a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, b) in other.0.iter().enumerate() {
+                let product = limbs[i + j] + *a as u64 * *b as u64 + carry;
+                limbs[i + j] = product % BASE;
+                carry = product / BASE;
+            }
+            limbs[i + other.0.len()] = carry;
+        }
+        Natural(limbs.into_iter().map(|limb| limb as u32).collect()).trim()
+    }
+
+    /// `self * factor`, for a factor below the base.
+    fn mul_small(&self, factor: u32) -> Natural {
+        let mut limbs = Vec::with_capacity(self.0.len() + 1);
+        let mut carry = 0;
+        for limb in &self.0 {
+            let product = *limb as u64 * factor as u64 + carry;
+            limbs.push((product % BASE) as u32);
+            carry = product / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        Natural(limbs).trim()
+    }
+
+    /// `self * 10^digits`.
+    fn shift(&self, digits: u32) -> Natural {
+        if self.is_zero() {
+            return Natural::default()
+        }
+        let mut limbs = vec![0; (digits / LIMB_DIGITS) as usize];
+        limbs.extend_from_slice(&self.0);
+        Natural(limbs).mul_small(10u32.pow(digits % LIMB_DIGITS))
+    }
+
+    /// The quotient and remainder of `self / divisor`, by long division a decimal digit at a time.
+    fn divrem(&self, divisor: &Natural) -> (Natural, Natural) {
+        let mut quotient = String::with_capacity(self.digits() as usize);
+        let mut remainder = Natural::default();
+        for digit in self.to_string().bytes() {
+            remainder = remainder.mul_small(10).add(&Natural::from_u128((digit - b'0') as u128));
+            let mut times = b'0';
+            while remainder >= *divisor {
+                remainder = remainder.sub(divisor);
+                times += 1;
+            }
+            quotient.push(times as char);
+        }
+        (Natural::parse(&quotient), remainder)
+    }
+}
+impl Ord for Natural {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.len().cmp(&other.0.len()).then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+impl PartialOrd for Natural {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl fmt::Display for Natural {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some((top, rest)) = self.0.split_last() else { return write!(f, "0") };
+        write!(f, "{top}")?;
+        rest.iter().rev().try_for_each(|limb| write!(f, "{limb:09}"))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Decimal {
+    /// Never set for zero.
+    negative: bool,
+    magnitude: Natural,
+    scale: u32,
+}
+impl Decimal {
+    /// `unscaled / 10^scale`.
+    pub fn new(unscaled: i128, scale: u32) -> Decimal {
+        Decimal::from_parts(unscaled < 0, Natural::from_u128(unscaled.unsigned_abs()), scale)
+    }
+
+    fn from_parts(negative: bool, magnitude: Natural, scale: u32) -> Decimal {
+        Decimal { negative: negative && !magnitude.is_zero(), magnitude, scale }
+    }
+
+    /// The decimal written in `text`, as in `-12.50` or `1.5e3`, surrounding whitespace aside, or `None`
+    /// if it is not one or has too many digits.
+    pub fn parse(text: &str) -> Option<Decimal> {
+        let text = text.trim();
+        let (negative, text) = match text.as_bytes().first()? {
+            b'-' => (true, &text[1..]),
+            b'+' => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (number, exponent) = match text.find(['e', 'E']) {
+            Some(at) => (&text[..at], text[at + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let digits = [whole, fraction].concat();
+        if digits.is_empty() || !digits.bytes().all(|d| d.is_ascii_digit()) {
+            return None
+        }
+        let scale = fraction.len() as i64 - exponent.clamp(-2 * MAX_DIGITS as i64, 2 * MAX_DIGITS as i64);
+        let mut magnitude = Natural::parse(&digits);
+        if scale < 0 {
+            magnitude = magnitude.shift(u32::try_from(-scale).ok()?);
+        }
+        Decimal::from_parts(negative, magnitude, scale.max(0) as u32).checked()
+    }
+
+    /// The decimal a float shows as, in the fewest digits that read back as the same float, or `None` for
+    /// infinities and NaN.
+    pub fn from_f64(v: f64) -> Option<Decimal> {
+        v.is_finite().then(|| Decimal::parse(&format!("{v:e}"))).flatten()
+    }
+
+    /// The nearest float.
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// Rounded to an integer, or `None` if that does not fit.
+    pub fn to_i64(&self) -> Option<i64> {
+        let rounded = self.round(0)?;
+        let magnitude = i128::try_from(rounded.magnitude.to_u128()?).ok()?;
+        i64::try_from(if rounded.negative { -magnitude } else { magnitude }).ok()
+    }
+
+    /// Digits after the point.
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The digits of the decimal without its point or sign, `0` for zero.
+    pub(crate) fn digits(&self) -> String {
+        self.magnitude.to_string()
+    }
+
+    /// The decimal with the digits `digits` and the scale `scale`.
+    pub(crate) fn from_digits(negative: bool, digits: &str, scale: u32) -> Decimal {
+        Decimal::from_parts(negative, Natural::parse(digits), scale)
+    }
+
+    /// Rounded or padded with zeros to `scale` digits after the point, or `None` if that makes too many
+    /// digits.
+    pub fn round(&self, scale: u32) -> Option<Decimal> {
+        if scale >= self.scale {
+            let magnitude = self.magnitude.shift(scale - self.scale);
+            return Decimal::from_parts(self.negative, magnitude, scale).checked()
+        }
+        let digits = self.digits();
+        let dropped = (self.scale - scale) as usize;
+        let (kept, rest) = digits.split_at(digits.len().saturating_sub(dropped));
+        let mut magnitude = Natural::parse(kept);
+        // Only the first digit dropped, when a whole digit is, can make the rest half or more.
+        if digits.len() >= dropped && rest.as_bytes()[0] >= b'5' {
+            magnitude = magnitude.add(&Natural::from_u128(1));
+        }
+        Decimal::from_parts(self.negative, magnitude, scale).checked()
+    }
+
+    /// The same decimal with no trailing zeros after the point, so that 1.50 becomes 1.5 and 2.0 becomes 2.
+    pub fn normalize(&self) -> Decimal {
+        if self.is_zero() {
+            return Decimal::default()
+        }
+        let digits = self.digits();
+        let zeros = digits.bytes().rev().take_while(|d| *d == b'0').count().min(self.scale as usize);
+        Decimal::from_digits(self.negative, &digits[..digits.len() - zeros], self.scale - zeros as u32)
+    }
+
+    /// Rounded to `scale` digits after the point, or `None` if it then has more than `precision` digits.
+    pub fn rescale(&self, precision: u32, scale: u32) -> Option<Decimal> {
+        self.round(scale).filter(|d| d.magnitude.digits() <= precision)
+    }
+
+    pub fn checked_add(&self, other: &Decimal) -> Option<Decimal> {
+        let (scale, a, b) = self.align(other);
+        let (negative, magnitude) = match (self.negative == other.negative, a.cmp(&b)) {
+            (true, _) => (self.negative, a.add(&b)),
+            (false, Ordering::Less) => (other.negative, b.sub(&a)),
+            (false, _) => (self.negative, a.sub(&b)),
+        };
+        Decimal::from_parts(negative, magnitude, scale).checked()
+    }
+
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Decimal> {
+        self.checked_add(&-other.clone())
+    }
+
+    pub fn checked_mul(&self, other: &Decimal) -> Option<Decimal> {
+        let magnitude = self.magnitude.mul(&other.magnitude);
+        Decimal::from_parts(self.negative != other.negative, magnitude, self.scale + other.scale).checked()
+    }
+
+    /// `self / other` rounded as the module describes, or `None` to divide by zero.
+    pub fn checked_div(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return None
+        }
+        let scale = DIVISION_SCALE.max(self.scale).max(other.scale);
+        // One digit more than is kept, to round by; the quotient's digits past it cannot change that.
+        let numerator = self.magnitude.shift(scale + 1 + other.scale - self.scale);
+        let (quotient, _) = numerator.divrem(&other.magnitude);
+        Decimal::from_parts(self.negative != other.negative, quotient, scale + 1).round(scale)
+    }
+
+    /// The remainder of dividing toward zero, with the sign of `self`, or `None` to divide by zero.
+    pub fn checked_rem(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return None
+        }
+        let (scale, a, b) = self.align(other);
+        Some(Decimal::from_parts(self.negative, a.divrem(&b).1, scale))
+    }
+
+    pub fn abs(&self) -> Decimal {
+        Decimal { negative: false, ..self.clone() }
+    }
+
+    /// The magnitudes of `self` and `other` at the greater of their scales, and that scale.
+    fn align(&self, other: &Decimal) -> (u32, Natural, Natural) {
+        let scale = self.scale.max(other.scale);
+        (scale, self.magnitude.shift(scale - self.scale), other.magnitude.shift(scale - other.scale))
+    }
+
+    /// The decimal, if it has no more digits than a decimal may.
+    fn checked(self) -> Option<Decimal> {
+        let whole = self.magnitude.digits().saturating_sub(self.scale);
+        (whole <= MAX_DIGITS && self.scale <= MAX_DIGITS).then_some(self)
+    }
+
+    /// -1, 0 or 1 as the decimal is negative, zero or positive.
+    fn signum(&self) -> i8 {
+        match (self.negative, self.is_zero()) {
+            (true, _) => -1,
+            (false, true) => 0,
+            (false, false) => 1,
+        }
+    }
+
+    /// Append the decimal as the row format stores it: its scale, then its number of limbs shifted left
+    /// with its sign in the low bit, each as a varint, then its limbs as four little-endian bytes each,
+    /// least significant first.
+    pub(crate) fn write(&self, buf: &mut Vec<u8>) {
+        varint::write_u64(buf, self.scale as u64);
+        varint::write_u64(buf, (self.magnitude.0.len() as u64) << 1 | self.negative as u64);
+        for limb in &self.magnitude.0 {
+            buf.extend_from_slice(&limb.to_le_bytes());
+        }
+    }
+
+    /// Decode a decimal written by `write` from the start of `buf`, returning it and the number of bytes
+    /// it took.
+    pub(crate) fn read(buf: &[u8]) -> Option<(Decimal, usize)> {
+        let (scale, mut at) = varint::read_u64(buf)?;
+        let (header, len) = varint::read_u64(&buf[at..])?;
+        at += len;
+        let limbs = usize::try_from(header >> 1).ok()?;
+        let bytes = buf.get(at..at.checked_add(limbs.checked_mul(4)?)?)?;
+        let limbs: Vec<u32> = bytes.chunks(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).collect();
+        if limbs.iter().any(|limb| *limb as u64 >= BASE) || limbs.last() == Some(&0) || header == 1 {
+            return None
+        }
+        let (negative, scale) = (header & 1 == 1, u32::try_from(scale).ok()?);
+        Some((Decimal { negative, magnitude: Natural(limbs), scale }.checked()?, at + bytes.len()))
+    }
+}
+impl From<i64> for Decimal {
+    fn from(v: i64) -> Decimal {
+        Decimal::new(v as i128, 0)
+    }
+}
+impl std::ops::Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        let negative = !self.negative;
+        Decimal::from_parts(negative, self.magnitude, self.scale)
+    }
+}
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (_, a, b) = self.align(other);
+        match self.signum().cmp(&other.signum()) {
+            Ordering::Equal if self.negative => b.cmp(&a),
+            Ordering::Equal => a.cmp(&b),
+            ordering => ordering,
+        }
+    }
+}
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Decimal {}
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal decimals have the same digits once trailing zeros after the point are dropped.
+        if self.is_zero() {
+            return 0.hash(state)
+        }
+        let digits = self.digits();
+        let zeros = digits.bytes().rev().take_while(|d| *d == b'0').count().min(self.scale as usize);
+        (self.negative, &digits[..digits.len() - zeros], self.scale as usize - zeros).hash(state);
+    }
+}
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.digits();
+        let sign = if self.negative { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{sign}{digits}")
+        }
+        let digits = format!("{digits:0>width$}", width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{sign}{whole}.{fraction}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Decimal, MAX_DIGITS};
+
+    fn decimal(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        for text in ["0", "1.50", "-0.001", "123456789012345678901234567890.123456789"] {
+            assert_eq!(decimal(text).to_string(), text);
+        }
+        assert_eq!(decimal(" +1.5e3 ").to_string(), "1500");
+        assert_eq!(decimal("-.25E-2").to_string(), "-0.0025");
+        assert_eq!(decimal("-0.00").to_string(), "0.00");
+        assert_eq!(Decimal::from_f64(0.1).map(|d| d.to_string()), Some("0.1".to_string()));
+        assert_eq!(Decimal::from_f64(-2.5e-7).map(|d| d.to_string()), Some("-0.00000025".to_string()));
+        assert_eq!(Decimal::from_f64(f64::NAN), None);
+        for text in ["", ".", "1.2.3", "1e", "abc", "--1"] {
+            assert_eq!(Decimal::parse(text), None, "{text:?}");
+        }
+        assert_eq!(Decimal::parse(&format!("1e{MAX_DIGITS}")), None);
+        assert_eq!(decimal("12.5").to_i64(), Some(13));
+        assert_eq!(decimal("-12.5").to_i64(), Some(-13));
+        assert_eq!(decimal("9223372036854775808").to_i64(), None);
+        assert_eq!(decimal("-9223372036854775808").to_i64(), Some(i64::MIN));
+    }
+
+    #[test]
+    fn test_exact_arithmetic() {
+        let sum = decimal("0.1").checked_add(&decimal("0.2")).unwrap();
+        assert_eq!((sum.to_string(), sum == decimal("0.3")), ("0.3".to_string(), true));
+        assert_eq!(decimal("1.25").checked_sub(&decimal("3")).unwrap().to_string(), "-1.75");
+        assert_eq!(decimal("-1.5").checked_mul(&decimal("-0.25")).unwrap().to_string(), "0.375");
+        let big = decimal("99999999999999999999");
+        assert_eq!(big.checked_mul(&big).unwrap().to_string(), "9999999999999999999800000000000000000001");
+        assert_eq!(decimal("1").checked_div(&decimal("3")).unwrap().to_string(), "0.3333333333333333");
+        assert_eq!(decimal("2").checked_div(&decimal("-3")).unwrap().to_string(), "-0.6666666666666667");
+        assert_eq!(decimal("1").checked_div(&decimal("0")), None);
+        assert_eq!(decimal("-7.5").checked_rem(&decimal("2")).unwrap().to_string(), "-1.5");
+        let huge = Decimal::parse(&"9".repeat(MAX_DIGITS as usize)).unwrap();
+        assert_eq!(huge.checked_add(&decimal("1")), None);
+    }
+
+    #[test]
+    fn test_rounding_and_equality() {
+        let round = |text: &str, scale| decimal(text).round(scale).unwrap().to_string();
+        assert_eq!(round("2.345", 2), "2.35");
+        assert_eq!(round("-2.345", 2), "-2.35");
+        assert_eq!(round("2.344", 2), "2.34");
+        assert_eq!(round("0.5", 0), "1");
+        assert_eq!(round("0.004", 2), "0.00");
+        assert_eq!(round("0.0005", 2), "0.00");
+        assert_eq!(round("1.5", 3), "1.500");
+        assert_eq!(decimal("123.45").rescale(5, 2).map(|d| d.to_string()), Some("123.45".to_string()));
+        assert_eq!(decimal("999.995").rescale(5, 2), None);
+        let normalized = ["1.50", "200", "2.000", "-0.00"].map(|d| decimal(d).normalize().to_string());
+        assert_eq!(normalized, ["1.5", "200", "2", "0"]);
+
+        assert_eq!(decimal("1.50"), decimal("1.5"));
+        assert!(decimal("-2") < decimal("-1.99") && decimal("-0.01") < decimal("0") && decimal("0.1") < decimal("1"));
+        let set: HashSet<Decimal> = ["1.5", "1.50", "1.500", "-0", "0.0"].into_iter().map(decimal).collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        for text in ["0", "-1.50", "123456789012345678901234567890.5"] {
+            let mut buf = Vec::new();
+            decimal(text).write(&mut buf);
+            let len = buf.len();
+            buf.push(7);
+            let (read, used) = Decimal::read(&buf).unwrap();
+            assert_eq!((read.to_string(), used), (text.to_string(), len));
+        }
+        assert!(Decimal::read(&[0, 1]).is_none());
+    }
+}
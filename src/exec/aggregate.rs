@@ -0,0 +1,469 @@
+//! Aggregation: `count`, `sum`, `avg`, `min` and `max` over groups of rows.
+//!
+//! Every function but `count(*)` skips null arguments, and all but `count` return null for a group with
+//! no other values. `sum` of integers is an integer, or an error if it overflows, is an exact decimal
+//! once any argument is a decimal, and is a float once any is a float; `avg` is a decimal when `sum` is
+//! and otherwise a float. `min` and `max` compare as SQL comparison does, so that
+//! integers and floats mix but other types do not. `First`, which SQL cannot call, keeps the first value
+//! of its group as it is, null or not, for `DISTINCT ON` to keep the columns of each group's first row.
+//!
+//! `StreamAggregate` needs its input ordered, or at least grouped, by the grouping keys: it folds rows
+//! into one group until the keys change, then emits it. Null keys group together. Without grouping keys
+//! the whole input is one group, which gives a row even when the input is empty.
+//!
+//! `HashAggregate` takes its input in any order, keeping each group's accumulators in a hash table by
+//! its keys, and emits the groups in the order their first rows came. Once the table outgrows the memory
+//! budget, rows of groups already in it still go to their groups, but the rows of new groups are written
+//! to temporary space, split into partitions by the hash of their keys. Each partition is aggregated in
+//! turn after the groups in memory are emitted; no group has rows in more than one of them, and a
+//! partition still bigger than the budget is aggregated in memory all the same. The groups count against
+//! the query's memory budget as well: new groups spill once the query has no room for another, and a
+//! partition the query has no room for fails with `ExecError::MemoryLimit`.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::decimal::Decimal;
+use crate::memory::{MemoryBudget, Reservation};
+use crate::page_store::PageId;
+use crate::sort::{RunReader, RunWriter};
+use crate::storage::Storage;
+use crate::temp::{TempScope, TempSpace};
+use crate::tuple::Value;
+
+use super::spill::{partition, row_bytes, PARTITIONS};
+use super::{ExecError, Expr, Operator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    First,
+}
+impl AggregateFunction {
+    /// The aggregate function called `name` in SQL, in any case.
+    pub fn named(name: &str) -> Option<AggregateFunction> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "count" => AggregateFunction::Count,
+            "sum" => AggregateFunction::Sum,
+            "avg" => AggregateFunction::Avg,
+            "min" => AggregateFunction::Min,
+            "max" => AggregateFunction::Max,
+            _ => return None,
+        })
+    }
+}
+
+/// One aggregate to compute for each group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    /// What it aggregates, over the input row. `None` only for `count(*)`, which counts rows.
+    pub arg: Option<Expr>,
+}
+
+/// The running value of an aggregate over the rows of a group so far.
+#[derive(Debug, Clone)]
+pub(super) struct Accumulator {
+    /// Values aggregated, not counting nulls.
+    count: i64,
+    /// The sum of the integers, of the floats, and of the decimals.
+    int: i128,
+    float: f64,
+    decimal: Decimal,
+    /// Whether any value was a float, and whether any was a decimal.
+    floats: bool,
+    decimals: bool,
+    /// The least or greatest value yet, or the first.
+    extreme: Option<Value>,
+}
+impl Accumulator {
+    pub(super) fn new() -> Accumulator {
+        let decimal = Decimal::default();
+        Accumulator { count: 0, int: 0, float: 0.0, decimal, floats: false, decimals: false, extreme: None }
+    }
+
+    pub(super) fn add(&mut self, function: AggregateFunction, value: Value) -> Result<(), ExecError> {
+        if value.is_null() && function != AggregateFunction::First {
+            return Ok(())
+        }
+        match (function, &value) {
+            (AggregateFunction::Count, _) => {}
+            (AggregateFunction::First, _) => {
+                if self.count == 0 {
+                    self.extreme = Some(value);
+                }
+            }
+            (AggregateFunction::Sum | AggregateFunction::Avg, Value::Int(v)) => self.int += *v as i128,
+            (AggregateFunction::Sum | AggregateFunction::Avg, Value::Float(v)) => {
+                self.float += v;
+                self.floats = true;
+            }
+            (AggregateFunction::Sum | AggregateFunction::Avg, Value::Decimal(v)) => {
+                self.decimal = self.decimal.checked_add(v).ok_or(ExecError::Overflow)?;
+                self.decimals = true;
+            }
+            (AggregateFunction::Sum | AggregateFunction::Avg, _) => return Err(ExecError::TypeMismatch(value)),
+            (AggregateFunction::Min | AggregateFunction::Max, _) => {
+                let wanted = if function == AggregateFunction::Min { Ordering::Less } else { Ordering::Greater };
+                let replace = match &self.extreme {
+                    None => true,
+                    Some(extreme) => value.compare(extreme).ok_or(ExecError::TypeMismatch(value.clone()))? == wanted,
+                };
+                if replace {
+                    self.extreme = Some(value);
+                }
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    pub(super) fn finish(self, function: AggregateFunction) -> Result<Value, ExecError> {
+        if self.count == 0 && function != AggregateFunction::Count {
+            return Ok(Value::Null)
+        }
+        // Integers summed with decimals and no floats make an exact decimal sum, and with floats a float one.
+        let exact = || Decimal::new(self.int, 0).checked_add(&self.decimal).ok_or(ExecError::Overflow);
+        let float = self.int as f64 + self.decimal.to_f64() + self.float;
+        Ok(match function {
+            AggregateFunction::Count => Value::Int(self.count),
+            AggregateFunction::Sum if self.floats => Value::Float(float),
+            AggregateFunction::Sum if self.decimals => Value::Decimal(exact()?),
+            AggregateFunction::Sum => Value::Int(i64::try_from(self.int).map_err(|_| ExecError::Overflow)?),
+            AggregateFunction::Avg if self.decimals && !self.floats => {
+                Value::Decimal(exact()?.checked_div(&Decimal::from(self.count)).ok_or(ExecError::Overflow)?)
+            }
+            AggregateFunction::Avg => Value::Float(float / self.count as f64),
+            AggregateFunction::Min | AggregateFunction::Max | AggregateFunction::First => {
+                self.extreme.unwrap_or(Value::Null)
+            }
+        })
+    }
+}
+
+fn accumulators(aggregates: &[Aggregate]) -> Vec<Accumulator> {
+    aggregates.iter().map(|_| Accumulator::new()).collect()
+}
+
+/// Add `row` to the accumulators of its group.
+fn accumulate(aggregates: &[Aggregate], accumulators: &mut [Accumulator], row: &[Value]) -> Result<(), ExecError> {
+    for (aggregate, accumulator) in aggregates.iter().zip(accumulators) {
+        let value = match &aggregate.arg {
+            Some(arg) => arg.eval(row)?,
+            None => Value::Bool(true),
+        };
+        accumulator.add(aggregate.function, value)?;
+    }
+    Ok(())
+}
+
+/// The output row of the group with `keys`: the keys followed by the value of each aggregate.
+fn finish(
+    aggregates: &[Aggregate],
+    mut keys: Vec<Value>,
+    accumulators: Vec<Accumulator>,
+) -> Result<Vec<Value>, ExecError> {
+    for (aggregate, accumulator) in aggregates.iter().zip(accumulators) {
+        keys.push(accumulator.finish(aggregate.function)?);
+    }
+    Ok(keys)
+}
+
+/// For each group of consecutive input rows with equal `group_by` keys, the keys followed by the value of
+/// each of `aggregates` over the group.
+pub struct StreamAggregate<'a> {
+    input: Box<dyn Operator + 'a>,
+    group_by: &'a [Expr],
+    aggregates: &'a [Aggregate],
+    /// The keys of the group being read, and its accumulators.
+    group: Option<(Vec<Value>, Vec<Accumulator>)>,
+    done: bool,
+}
+impl<'a> StreamAggregate<'a> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        group_by: &'a [Expr],
+        aggregates: &'a [Aggregate],
+    ) -> StreamAggregate<'a> {
+        StreamAggregate { input, group_by, aggregates, group: None, done: false }
+    }
+}
+impl Operator for StreamAggregate<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        while !self.done {
+            let Some(row) = self.input.next()? else {
+                self.done = true;
+                return match self.group.take() {
+                    Some((keys, accumulators)) => finish(self.aggregates, keys, accumulators).map(Some),
+                    None if self.group_by.is_empty() => {
+                        finish(self.aggregates, Vec::new(), accumulators(self.aggregates)).map(Some)
+                    }
+                    None => Ok(None),
+                };
+            };
+            let keys = self.group_by.iter().map(|e| e.eval(&row)).collect::<Result<Vec<_>, _>>()?;
+            let finished = match &self.group {
+                Some((current, _)) if *current == keys => None,
+                _ => self.group.replace((keys, accumulators(self.aggregates))),
+            };
+            let (_, group) = self.group.as_mut().unwrap();
+            accumulate(self.aggregates, group, &row)?;
+            if let Some((keys, accumulators)) = finished {
+                return finish(self.aggregates, keys, accumulators).map(Some)
+            }
+        }
+        Ok(None)
+    }
+
+    fn stop(&mut self) {
+        self.input.stop();
+        self.group = None;
+        self.done = true;
+    }
+}
+
+/// Groups held in memory, in the order their first rows came.
+#[derive(Default)]
+struct Groups {
+    positions: HashMap<Vec<Value>, usize>,
+    groups: Vec<(Vec<Value>, Vec<Accumulator>)>,
+}
+impl Groups {
+    fn get_mut(&mut self, keys: &[Value]) -> Option<&mut Vec<Accumulator>> {
+        let position = *self.positions.get(keys)?;
+        Some(&mut self.groups[position].1)
+    }
+
+    fn insert(&mut self, keys: Vec<Value>, accumulators: Vec<Accumulator>) -> &mut Vec<Accumulator> {
+        self.positions.insert(keys.clone(), self.groups.len());
+        self.groups.push((keys, accumulators));
+        &mut self.groups.last_mut().unwrap().1
+    }
+}
+
+/// The same rows as `StreamAggregate` for input in any order, found by hashing the `group_by` keys.
+pub struct HashAggregate<'a, T: Storage> {
+    input: Option<Box<dyn Operator + 'a>>,
+    group_by: &'a [Expr],
+    aggregates: &'a [Aggregate],
+    memory_bytes: usize,
+    memory: &'a MemoryBudget,
+    /// What the groups in memory hold of the query's budget.
+    reserved: Reservation<'a>,
+    scope: TempScope<'a, T>,
+    /// The groups of the input or partition being emitted.
+    groups: std::vec::IntoIter<(Vec<Value>, Vec<Accumulator>)>,
+    /// The pages of each spilled partition's rows still to aggregate.
+    partitions: std::vec::IntoIter<Vec<PageId>>,
+    spilled: bool,
+}
+impl<'a, T: Storage> HashAggregate<'a, T> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        group_by: &'a [Expr],
+        aggregates: &'a [Aggregate],
+        temp: &'a TempSpace<T>,
+        memory_bytes: usize,
+        memory: &'a MemoryBudget,
+    ) -> HashAggregate<'a, T> {
+        HashAggregate {
+            input: Some(input),
+            group_by,
+            aggregates,
+            memory_bytes,
+            memory,
+            reserved: memory.reservation(),
+            scope: temp.scope(),
+            groups: Vec::new().into_iter(),
+            partitions: Vec::new().into_iter(),
+            spilled: false,
+        }
+    }
+
+    /// Whether the groups outgrew the memory budget, so that rows were spilled to partitions.
+    pub fn spilled(&self) -> bool {
+        self.spilled
+    }
+
+    fn keys(&self, row: &[Value]) -> Result<Vec<Value>, ExecError> {
+        self.group_by.iter().map(|e| e.eval(row)).collect()
+    }
+
+    fn group_bytes(&self, keys: &[Value]) -> usize {
+        2 * row_bytes(keys) + self.aggregates.len() * std::mem::size_of::<Accumulator>()
+    }
+
+    /// Aggregate the whole input, spilling the rows of the groups that do not fit to partitions.
+    fn build(&mut self, mut input: Box<dyn Operator + 'a>) -> Result<(), ExecError> {
+        let mut groups = Groups::default();
+        let mut writers = Vec::new();
+        while let Some(row) = input.next()? {
+            let keys = self.keys(&row)?;
+            if let Some(group) = groups.get_mut(&keys) {
+                accumulate(self.aggregates, group, &row)?;
+                continue
+            }
+            let bytes = self.group_bytes(&keys);
+            if self.reserved.bytes() > self.memory_bytes || !self.reserved.try_grow(bytes) {
+                if writers.is_empty() {
+                    self.spilled = true;
+                    writers = (0..PARTITIONS).map(|_| RunWriter::new()).collect();
+                }
+                writers[partition(&keys)].push(&mut self.scope, &row)?;
+                continue
+            }
+            let group = groups.insert(keys, accumulators(self.aggregates));
+            accumulate(self.aggregates, group, &row)?;
+        }
+        if self.group_by.is_empty() && groups.groups.is_empty() {
+            groups.insert(Vec::new(), accumulators(self.aggregates));
+        }
+        let mut partitions = Vec::with_capacity(writers.len());
+        for writer in writers {
+            partitions.push(writer.finish(&mut self.scope)?);
+        }
+        self.groups = groups.groups.into_iter();
+        self.partitions = partitions.into_iter();
+        Ok(())
+    }
+}
+impl<T: Storage> Operator for HashAggregate<'_, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if let Some(input) = self.input.take() {
+            self.build(input)?;
+        }
+        loop {
+            if let Some((keys, accumulators)) = self.groups.next() {
+                return finish(self.aggregates, keys, accumulators).map(Some)
+            }
+            let Some(pages) = self.partitions.next() else { return Ok(None) };
+            let mut groups = Groups::default();
+            self.reserved.clear();
+            let mut reader = RunReader::<Vec<Value>>::new(pages);
+            while let Some(row) = reader.next_item(&mut self.scope)? {
+                let keys = self.keys(&row)?;
+                let group = match groups.get_mut(&keys) {
+                    Some(group) => group,
+                    None => {
+                        if !self.reserved.try_grow(self.group_bytes(&keys)) {
+                            return Err(ExecError::MemoryLimit(self.memory.limit()))
+                        }
+                        groups.insert(keys, accumulators(self.aggregates))
+                    }
+                };
+                accumulate(self.aggregates, group, &row)?;
+            }
+            self.groups = groups.groups.into_iter();
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut input) = self.input.take() {
+            input.stop();
+        }
+        self.groups = Vec::new().into_iter();
+        self.partitions = Vec::new().into_iter();
+        self.reserved.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{collect, ExecError, Expr, Values};
+    use crate::memory::MemoryBudget;
+    use crate::storage::TestStorage;
+    use crate::temp::TempSpace;
+    use crate::tuple::Value;
+
+    use super::{Aggregate, AggregateFunction, HashAggregate, StreamAggregate};
+
+    #[test]
+    fn test_stream_aggregate() -> Result<(), ExecError> {
+        let rows: Vec<Vec<Expr>> = [(1, Value::Int(5)), (1, Value::Null), (1, Value::Float(2.5)), (2, Value::Null)]
+            .into_iter()
+            .map(|(k, v)| vec![Expr::Literal(Value::Int(k)), Expr::Literal(v)])
+            .collect();
+        let aggregate = |function, arg: Option<usize>| Aggregate { function, arg: arg.map(Expr::Column) };
+        let aggregates = [
+            aggregate(AggregateFunction::Count, None),
+            aggregate(AggregateFunction::Count, Some(1)),
+            aggregate(AggregateFunction::Sum, Some(1)),
+            aggregate(AggregateFunction::Avg, Some(1)),
+            aggregate(AggregateFunction::Min, Some(1)),
+            aggregate(AggregateFunction::Max, Some(1)),
+        ];
+        let keys = [Expr::Column(0)];
+        let mut grouped = StreamAggregate::new(Box::new(Values::new(&rows)), &keys, &aggregates);
+        let (int, float) = (Value::Int, Value::Float);
+        assert_eq!(collect(&mut grouped)?, vec![
+            vec![int(1), int(3), int(2), float(7.5), float(3.75), float(2.5), int(5)],
+            vec![int(2), int(1), int(0), Value::Null, Value::Null, Value::Null, Value::Null],
+        ]);
+
+        // Without keys an empty input is still one group.
+        let none = Vec::new();
+        let mut total = StreamAggregate::new(Box::new(Values::new(&none)), &[], &aggregates[..3]);
+        assert_eq!(collect(&mut total)?, vec![vec![int(0), int(0), Value::Null]]);
+
+        let overflow = vec![vec![Expr::Literal(int(i64::MAX))], vec![Expr::Literal(int(1))]];
+        let sum = [aggregate(AggregateFunction::Sum, Some(0))];
+        let mut total = StreamAggregate::new(Box::new(Values::new(&overflow)), &[], &sum);
+        assert_eq!(collect(&mut total), Err(ExecError::Overflow));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_aggregate_spills() -> Result<(), ExecError> {
+        let temp = TempSpace::new(TestStorage::new());
+        // Keys in no order, every tenth one null, so that the nulls make a group of their own.
+        let key = |i: i64| if i % 10 == 0 { Value::Null } else { Value::Int(i * 7 % 1000) };
+        let rows: Vec<Vec<Expr>> =
+            (0..5000).map(|i| vec![Expr::Literal(key(i)), Expr::Literal(Value::Int(i))]).collect();
+        let aggregates = [
+            Aggregate { function: AggregateFunction::Count, arg: None },
+            Aggregate { function: AggregateFunction::Sum, arg: Some(Expr::Column(1)) },
+        ];
+        let keys = [Expr::Column(0)];
+        let memory = MemoryBudget::new(usize::MAX);
+        let hash = |memory_bytes, memory| {
+            HashAggregate::new(Box::new(Values::new(&rows)), &keys, &aggregates, &temp, memory_bytes, memory)
+        };
+        let mut hashed = hash(16 * 1024, &memory);
+        let mut groups = collect(&mut hashed)?;
+        assert!(hashed.spilled());
+        drop(hashed);
+        assert_eq!(temp.pages_in_use(), 0);
+
+        let mut sorted = rows.clone();
+        sorted.sort_by_key(|row| match &row[0] {
+            Expr::Literal(v) => v.clone(),
+            _ => unreachable!(),
+        });
+        let mut streamed = collect(&mut StreamAggregate::new(Box::new(Values::new(&sorted)), &keys, &aggregates))?;
+        groups.sort();
+        streamed.sort();
+        assert_eq!(groups.len(), 901);
+        assert_eq!(groups, streamed);
+
+        // The query's budget spills new groups as well, and fails a partition it has no room for.
+        let small = MemoryBudget::new(64 * 1024);
+        let mut hashed = hash(usize::MAX, &small);
+        let mut groups = collect(&mut hashed)?;
+        assert!(hashed.spilled());
+        groups.sort();
+        assert_eq!(groups, streamed);
+        drop(hashed);
+        let tiny = MemoryBudget::new(64);
+        assert_eq!(collect(&mut hash(usize::MAX, &tiny)), Err(ExecError::MemoryLimit(64)));
+        assert_eq!((small.used(), tiny.used(), temp.pages_in_use()), (0, 0, 0));
+
+        // Without keys an empty input is still one group.
+        let none = Vec::new();
+        let mut total = HashAggregate::new(Box::new(Values::new(&none)), &[], &aggregates, &temp, 16 * 1024, &memory);
+        assert_eq!(collect(&mut total)?, vec![vec![Value::Int(0), Value::Null]]);
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Subqueries run for each row of an input. `Apply` binds the subquery's outer columns to the values of
+//! the input row, opens it afresh and pulls only as many rows as it needs: one to tell whether any
+//! exist, two to tell a scalar subquery's one row from too many, and for `IN` up to the first equal
+//! value. A subquery with no outer columns gives the same rows for every input row, so it runs once and
+//! its rows are kept for the rest.
+use crate::sql::ast::BinaryOp;
+use crate::storage::Storage;
+use crate::tuple::Value;
+
+use super::expr::binary;
+use super::{Context, ExecError, Operator, Plan};
+
+/// What an `Apply` computes from the rows of its subquery.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApplyKind {
+    /// The value of its first column in its one row, or null if it has none. More than one row is an error.
+    Scalar,
+    /// Whether it has any row.
+    Exists,
+    /// Whether this expression over the input row equals the first column of any of its rows. Null rather
+    /// than false if either side of any comparison is null, as for `IN` with a list.
+    In(super::Expr),
+}
+impl ApplyKind {
+    pub(super) fn prefix(&self) -> &'static str {
+        match self {
+            ApplyKind::Scalar => "Scalar",
+            ApplyKind::Exists => "Exists",
+            ApplyKind::In(_) => "In",
+        }
+    }
+}
+
+pub struct Apply<'a, 'store, S: Storage, T: Storage> {
+    input: Box<dyn Operator + 'a>,
+    subquery: &'a Plan,
+    kind: &'a ApplyKind,
+    context: &'a Context<'a, 'store, S, T>,
+    correlated: bool,
+    /// The first column of every row of an uncorrelated subquery, once it has run.
+    rows: Option<Vec<Value>>,
+}
+impl<'a, 'store, S: Storage, T: Storage> Apply<'a, 'store, S, T> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        subquery: &'a Plan,
+        kind: &'a ApplyKind,
+        context: &'a Context<'a, 'store, S, T>,
+    ) -> Apply<'a, 'store, S, T> {
+        Apply { input, subquery, kind, context, correlated: subquery.reads_outer(), rows: None }
+    }
+
+    /// The value of the subquery for `row`.
+    fn value(&mut self, row: &[Value]) -> Result<Value, ExecError> {
+        let test = match self.kind {
+            ApplyKind::In(expr) => Some(expr.eval(row)?),
+            _ => None,
+        };
+        if !self.correlated {
+            if self.rows.is_none() {
+                let mut operator = self.subquery.open(self.context)?;
+                let mut rows = Vec::new();
+                while let Some(mut row) = operator.next()? {
+                    rows.push(row.swap_remove(0));
+                }
+                self.rows = Some(rows);
+            }
+            let mut rows = self.rows.iter().flatten().map(|v| Ok(Some(v.clone())));
+            return evaluate(self.kind, test, &mut || rows.next().unwrap_or(Ok(None)))
+        }
+        let mut plan = self.subquery.clone();
+        plan.bind_outer(row);
+        if let Some(profile) = self.context.profile {
+            profile.alias(&plan, self.subquery);
+        }
+        let context: &Context<S, T> = self.context;
+        let mut operator = plan.open(context)?;
+        let value = evaluate(self.kind, test, &mut || Ok(operator.next()?.map(|mut row| row.swap_remove(0))));
+        operator.stop();
+        value
+    }
+}
+
+/// What `kind` makes of the first column of the rows `next` gives, `test` being the value `IN` looks for.
+fn evaluate(
+    kind: &ApplyKind,
+    test: Option<Value>,
+    next: &mut dyn FnMut() -> Result<Option<Value>, ExecError>,
+) -> Result<Value, ExecError> {
+    match (kind, test) {
+        (ApplyKind::Exists, _) => Ok(Value::Bool(next()?.is_some())),
+        (ApplyKind::Scalar, _) => {
+            let Some(value) = next()? else { return Ok(Value::Null) };
+            match next()? {
+                Some(_) => Err(ExecError::SubqueryRows),
+                None => Ok(value),
+            }
+        }
+        (ApplyKind::In(_), Some(test)) => {
+            let mut result = Value::Bool(false);
+            while let Some(value) = next()? {
+                match binary(BinaryOp::Eq, test.clone(), value)? {
+                    Value::Bool(true) => return Ok(Value::Bool(true)),
+                    Value::Null => result = Value::Null,
+                    _ => {}
+                }
+            }
+            Ok(result)
+        }
+        (ApplyKind::In(_), None) => unreachable!("IN without a value to look for"),
+    }
+}
+
+impl<S: Storage, T: Storage> Operator for Apply<'_, '_, S, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        let Some(mut row) = self.input.next()? else { return Ok(None) };
+        let value = self.value(&row)?;
+        row.push(value);
+        Ok(Some(row))
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.input.limit(rows)
+    }
+
+    fn stop(&mut self) {
+        self.input.stop();
+        self.rows = None;
+    }
+}
@@ -0,0 +1,150 @@
+//! Common table expressions read more than once, and recursive ones.
+//!
+//! A `With` materializes the rows of its common table expressions before its input reads any row, each
+//! under its id in the `Context`, where every `CteScan` of that id reads them. A `Recursive` query runs its
+//! base term, then its recursive term over and over, each round with `CteScan`s of its id reading the
+//! rows the round before added, until a round adds none. Without `ALL`, a row equal to one already added
+//! is dropped, which is what lets a query over a cycle end. A query still adding rows after the context's
+//! recursion limit of rounds fails. Materialized rows, a round's rows and the rows seen without `ALL` are
+//! all held in memory.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::storage::Storage;
+use crate::tuple::Value;
+
+use super::{collect, Context, ExecError, Operator, Plan};
+
+/// The rows of `ctes`, materialized first, and then the rows of `input`, which reads them.
+pub struct With<'a, 'store, S: Storage, T: Storage> {
+    input: &'a Plan,
+    ctes: &'a [(usize, Plan)],
+    context: &'a Context<'a, 'store, S, T>,
+    operator: Option<Box<dyn Operator + 'a>>,
+    limit: Option<usize>,
+}
+impl<'a, 'store, S: Storage, T: Storage> With<'a, 'store, S, T> {
+    pub fn new(
+        input: &'a Plan,
+        ctes: &'a [(usize, Plan)],
+        context: &'a Context<'a, 'store, S, T>,
+    ) -> With<'a, 'store, S, T> {
+        With { input, ctes, context, operator: None, limit: None }
+    }
+}
+impl<S: Storage, T: Storage> Operator for With<'_, '_, S, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if self.operator.is_none() {
+            for (id, plan) in self.ctes {
+                let rows = collect(&mut *plan.open(self.context)?)?;
+                self.context.ctes.lock().unwrap().insert(*id, Arc::new(rows));
+            }
+            let mut operator = self.input.open(self.context)?;
+            if let Some(rows) = self.limit {
+                operator.limit(rows);
+            }
+            self.operator = Some(operator);
+        }
+        self.operator.as_mut().unwrap().next()
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.limit = Some(rows);
+    }
+
+    fn stop(&mut self) {
+        if let Some(operator) = &mut self.operator {
+            operator.stop();
+        }
+    }
+}
+
+/// The rows materialized under `id` by the `With` or `Recursive` above.
+pub struct CteScan<'a, 'store, S: Storage, T: Storage> {
+    id: usize,
+    context: &'a Context<'a, 'store, S, T>,
+    rows: Option<Arc<Vec<Vec<Value>>>>,
+    at: usize,
+}
+impl<'a, 'store, S: Storage, T: Storage> CteScan<'a, 'store, S, T> {
+    pub fn new(id: usize, context: &'a Context<'a, 'store, S, T>) -> CteScan<'a, 'store, S, T> {
+        CteScan { id, context, rows: None, at: 0 }
+    }
+}
+impl<S: Storage, T: Storage> Operator for CteScan<'_, '_, S, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if self.rows.is_none() {
+            let rows = self.context.ctes.lock().unwrap().get(&self.id).cloned();
+            self.rows = Some(rows.expect("a common table expression materialized before it is read"));
+        }
+        let row = self.rows.as_ref().unwrap().get(self.at).cloned();
+        self.at += 1;
+        Ok(row)
+    }
+
+    fn stop(&mut self) {
+        self.rows = Some(Arc::new(Vec::new()));
+    }
+}
+
+/// The rows of `base`, then of `step` run over the rows of each round before it until a round adds none.
+pub struct Recursive<'a, 'store, S: Storage, T: Storage> {
+    id: usize,
+    step: &'a Plan,
+    distinct: bool,
+    context: &'a Context<'a, 'store, S, T>,
+    /// The term being run, the base term first.
+    operator: Option<Box<dyn Operator + 'a>>,
+    /// The rows this round has added so far.
+    round: Vec<Vec<Value>>,
+    rounds: usize,
+    /// Every row added, without `ALL`.
+    seen: HashSet<Vec<Value>>,
+}
+impl<'a, 'store, S: Storage, T: Storage> Recursive<'a, 'store, S, T> {
+    pub fn new(
+        base: Box<dyn Operator + 'a>,
+        id: usize,
+        step: &'a Plan,
+        distinct: bool,
+        context: &'a Context<'a, 'store, S, T>,
+    ) -> Recursive<'a, 'store, S, T> {
+        let (round, seen) = (Vec::new(), HashSet::new());
+        Recursive { id, step, distinct, context, operator: Some(base), round, rounds: 0, seen }
+    }
+}
+impl<S: Storage, T: Storage> Operator for Recursive<'_, '_, S, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        loop {
+            if let Some(operator) = &mut self.operator {
+                match operator.next()? {
+                    Some(row) if self.distinct && !self.seen.insert(row.clone()) => continue,
+                    Some(row) => {
+                        self.round.push(row.clone());
+                        return Ok(Some(row))
+                    }
+                    None => self.operator = None,
+                }
+            }
+            if self.round.is_empty() {
+                return Ok(None)
+            }
+            if self.rounds == self.context.recursion_limit {
+                return Err(ExecError::RecursionLimit(self.rounds))
+            }
+            self.rounds += 1;
+            let round = Arc::new(std::mem::take(&mut self.round));
+            self.context.ctes.lock().unwrap().insert(self.id, round);
+            self.operator = Some(self.step.open(self.context)?);
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(operator) = &mut self.operator {
+            operator.stop();
+        }
+        self.operator = None;
+        self.round = Vec::new();
+        self.seen = HashSet::new();
+    }
+}
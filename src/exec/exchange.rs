@@ -0,0 +1,334 @@
+//! Parallel execution. A `Gather` runs its input on several worker threads at once and outputs the rows
+//! of all of them, in no particular order. Every worker runs the whole input plan, but the scans in it share
+//! their tables' pages out between the workers, each worker loading the next page none of the others has,
+//! so that between them they read every row once. Filters and projections are right over any share of the
+//! rows as they are. A hash join or aggregate needs all the rows with one key in the same worker, which a
+//! `Repartition` under it gives: each worker's repartition keeps the rows whose key hashes to the worker's
+//! partition and hands every other row to the worker it hashes to, so that each worker joins or aggregates
+//! the keys of its own partition. Only scans of heap tables may be under a gather.
+//!
+//! The workers run on scoped threads, which must have finished before the gather returns, so the gather
+//! collects all its workers' rows on its first `next`: it keeps them in memory while its budget, and the
+//! query's, has room, and spills the rest to temporary space. A worker that fails stops the others, and the
+//! gather fails with its error. Workers look between rows for whether to stop, so they stop soon after the
+//! gather has as many rows as it was promised it needs, or after one of them fails.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::memory::Reservation;
+use crate::page_store::PageId;
+use crate::sort::{RunReader, RunWriter};
+use crate::storage::Storage;
+use crate::temp::TempScope;
+use crate::tuple::Value;
+
+use super::join::join_key;
+use super::profile::address;
+use super::spill::{key_hash, row_bytes};
+use super::{Context, ExecError, Expr, Operator, Plan};
+
+/// The rows a gather kept in memory, and the run of those it spilled.
+type Gathered = (std::vec::IntoIter<Vec<Value>>, RunReader<Vec<Value>>);
+
+/// What the workers of the gathers running in a context share, by the address of the node sharing it.
+#[derive(Default)]
+pub(super) struct Exchange {
+    /// The next page for the scans of each shared scan node to load.
+    scans: Mutex<HashMap<usize, Arc<AtomicUsize>>>,
+    /// The ends of each repartition's channels that no worker has taken yet.
+    repartitions: Mutex<HashMap<usize, Vec<Endpoint>>>,
+}
+impl Exchange {
+    /// The count the scan `plan` takes its pages from, if it runs under a gather.
+    pub(super) fn scan(&self, plan: &Plan) -> Option<Arc<AtomicUsize>> {
+        self.scans.lock().unwrap().get(&address(plan)).cloned()
+    }
+
+    /// One worker's end of the repartition `plan`, if it runs under a gather with an end left.
+    pub(super) fn endpoint(&self, plan: &Plan) -> Option<Endpoint> {
+        self.repartitions.lock().unwrap().get_mut(&address(plan))?.pop()
+    }
+
+    /// Share the scans and repartitions under `plan` between `workers` workers.
+    fn share(&self, plan: &Plan, workers: usize) {
+        let (mut scans, mut repartitions) = (self.scans.lock().unwrap(), self.repartitions.lock().unwrap());
+        for node in plan.nodes() {
+            match node {
+                Plan::SeqScan { .. } => {
+                    scans.insert(address(node), Arc::default());
+                }
+                Plan::Repartition { .. } => {
+                    repartitions.insert(address(node), endpoints(workers));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Stop sharing the nodes under `plan`, letting go of the ends of channels no worker took.
+    fn unshare(&self, plan: &Plan) {
+        let (mut scans, mut repartitions) = (self.scans.lock().unwrap(), self.repartitions.lock().unwrap());
+        for node in plan.nodes() {
+            scans.remove(&address(node));
+            repartitions.remove(&address(node));
+        }
+    }
+}
+
+/// One worker's end of a repartition: the channel the rows of its partition come to it on, and the
+/// channels of every partition, its own among them, to send rows on.
+pub(super) struct Endpoint {
+    partition: usize,
+    senders: Vec<Sender<Vec<Value>>>,
+    receiver: Receiver<Vec<Value>>,
+}
+
+fn endpoints(workers: usize) -> Vec<Endpoint> {
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..workers).map(|_| mpsc::channel()).unzip();
+    let endpoint = |(partition, receiver)| Endpoint { partition, senders: senders.clone(), receiver };
+    receivers.into_iter().enumerate().map(endpoint).collect()
+}
+
+/// The rows of `workers` runs of `input` at once, each on a thread of its own with the scans under it
+/// sharing out their pages.
+pub struct Gather<'a, 'store, S: Storage, T: Storage> {
+    input: &'a Plan,
+    workers: usize,
+    context: &'a Context<'a, 'store, S, T>,
+    limit: usize,
+    /// What the rows kept in memory hold of the query's budget.
+    reserved: Reservation<'a>,
+    scope: TempScope<'a, T>,
+    /// Once the workers have run, what they gathered.
+    rows: Option<Gathered>,
+}
+impl<'a, 'store, S: Storage, T: Storage> Gather<'a, 'store, S, T> {
+    pub fn new(input: &'a Plan, workers: usize, context: &'a Context<'a, 'store, S, T>) -> Gather<'a, 'store, S, T> {
+        let (reserved, scope) = (context.memory.reservation(), context.temp.scope());
+        Gather { input, workers, context, limit: usize::MAX, reserved, scope, rows: None }
+    }
+
+    /// Run the workers to the end, or until they have made `limit` rows, and collect their rows.
+    fn gather(&mut self) -> Result<(Vec<Vec<Value>>, Vec<PageId>), ExecError> {
+        let (input, context, limit) = (self.input, self.context, self.limit);
+        let (reserved, scope) = (&mut self.reserved, &mut self.scope);
+        let stop = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+        context.exchange.share(input, self.workers);
+        let gathered = std::thread::scope(|threads| {
+            for _ in 0..self.workers {
+                let (sender, stop) = (sender.clone(), &stop);
+                threads.spawn(move || {
+                    if let Err(e) = work(input, context, limit, stop, &sender) {
+                        let _ = sender.send(Err(e));
+                    }
+                });
+            }
+            drop(sender);
+            let (mut rows, mut spilled, mut result) = (Vec::new(), RunWriter::new(), Ok(()));
+            let mut count = 0;
+            for message in receiver {
+                if result.is_err() || count == limit {
+                    continue
+                }
+                result = message.and_then(|row| {
+                    count += 1;
+                    if reserved.try_grow(row_bytes(&row)) && reserved.bytes() <= context.memory_bytes {
+                        rows.push(row);
+                        return Ok(())
+                    }
+                    Ok(spilled.push(scope, &row)?)
+                });
+                if result.is_err() || count == limit {
+                    // Workers blocked on a repartition's rows from workers that never started are let go.
+                    stop.store(true, Ordering::Relaxed);
+                    context.exchange.unshare(input);
+                }
+            }
+            result.and_then(|()| Ok((rows, spilled.finish(scope)?)))
+        });
+        context.exchange.unshare(input);
+        gathered
+    }
+}
+
+/// Run one worker's `input`, sending its rows to the gather until they run out or it is told to stop.
+fn work<'a, 'store, S: Storage, T: Storage>(
+    input: &'a Plan,
+    context: &'a Context<'a, 'store, S, T>,
+    limit: usize,
+    stop: &AtomicBool,
+    sender: &Sender<Result<Vec<Value>, ExecError>>,
+) -> Result<(), ExecError> {
+    let mut operator = input.open(context)?;
+    operator.limit(limit);
+    while !stop.load(Ordering::Relaxed) {
+        let Some(row) = operator.next()? else { return Ok(()) };
+        if sender.send(Ok(row)).is_err() {
+            break
+        }
+    }
+    operator.stop();
+    Ok(())
+}
+
+impl<S: Storage, T: Storage> Operator for Gather<'_, '_, S, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if self.rows.is_none() {
+            let (rows, pages) = self.gather()?;
+            self.rows = Some((rows.into_iter(), RunReader::new(pages)));
+        }
+        let (rows, spilled) = self.rows.as_mut().unwrap();
+        if let Some(row) = rows.next() {
+            return Ok(Some(row))
+        }
+        self.reserved.clear();
+        Ok(spilled.next_item(&mut self.scope)?)
+    }
+
+    /// No worker needs to make more than `rows` rows either, nor the gather collect more.
+    fn limit(&mut self, rows: usize) {
+        self.limit = self.limit.min(rows);
+    }
+
+    fn stop(&mut self) {
+        self.rows = Some((Vec::new().into_iter(), RunReader::new(Vec::new())));
+        self.reserved.clear();
+    }
+}
+
+/// This worker's partition of the rows of `input` by `keys`: the rows of its own input whose keys hash
+/// to its partition, then the rows of that partition the other workers' repartitions hand it. Outside a
+/// gather, every row is this worker's.
+pub struct Repartition<'a> {
+    input: Option<Box<dyn Operator + 'a>>,
+    keys: &'a [Expr],
+    endpoint: Option<Endpoint>,
+}
+impl<'a> Repartition<'a> {
+    pub(super) fn new(input: Box<dyn Operator + 'a>, keys: &'a [Expr], endpoint: Option<Endpoint>) -> Repartition<'a> {
+        Repartition { input: Some(input), keys, endpoint }
+    }
+}
+impl Operator for Repartition<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        let Some(endpoint) = &mut self.endpoint else {
+            return self.input.as_mut().map_or(Ok(None), |input| input.next())
+        };
+        while let Some(input) = &mut self.input {
+            // Rows from the other workers come out as they arrive, rather than piling up until the input ends.
+            if let Ok(row) = endpoint.receiver.try_recv() {
+                return Ok(Some(row))
+            }
+            let Some(row) = input.next()? else {
+                // The channel closes once no worker, this one included, has rows left to send on it.
+                self.input = None;
+                endpoint.senders.clear();
+                break
+            };
+            // Keys that compare equal hash alike, nulls and all. The hash's high bits choose the worker, so
+            // that a worker's keys still spread over every partition of a join or aggregate that spills.
+            let key = join_key(self.keys, &row)?.unwrap_or_default();
+            let partition = (key_hash(&key) >> 32) as usize % endpoint.senders.len();
+            if partition == endpoint.partition {
+                return Ok(Some(row))
+            }
+            // Only a worker that has stopped takes no more rows, once the gather is done with them.
+            let _ = endpoint.senders[partition].send(row);
+        }
+        Ok(endpoint.receiver.recv().ok())
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut input) = self.input.take() {
+            input.stop();
+        }
+        self.endpoint = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::sql::ast::BinaryOp;
+    use crate::storage::TestStorage;
+    use crate::table::Table;
+    use crate::temp::TempSpace;
+    use crate::tuple::{Column, ColumnType, Schema, Value};
+
+    use crate::exec::{collect, Aggregate, AggregateFunction, Context, ExecError, Expr, JoinType, Plan};
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+    }
+
+    fn scan(table: &str) -> Box<Plan> {
+        Box::new(Plan::SeqScan { table: table.to_string(), rid: false })
+    }
+
+    fn repartition(input: Box<Plan>, key: Expr) -> Box<Plan> {
+        Box::new(Plan::Repartition { input, keys: vec![key] })
+    }
+
+    #[test]
+    fn test_gathers_agree_with_one_worker() -> Result<(), ExecError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let schema = || Schema::new(vec![Column::new(ColumnType::Int), Column::nullable(ColumnType::Int)]);
+        let (mut t, mut u) = (Table::create(&store, allocator, schema())?, Table::create(&store, allocator, schema())?);
+        for i in 0..3000 {
+            t.insert(&[Value::Int(i), if i % 10 == 0 { Value::Null } else { Value::Int(i % 97) }])?;
+        }
+        for i in 0..500 {
+            u.insert(&[Value::Int(i), Value::Int(i * 2)])?;
+        }
+        let temp = TempSpace::new(TestStorage::new());
+        let mut context = Context::new(&temp);
+        context.tables.insert("t".to_string(), t);
+        context.tables.insert("u".to_string(), u);
+        let run = |plan: Plan| -> Result<_, ExecError> {
+            let gather = Plan::Gather { input: Box::new(plan.clone()), workers: 4 };
+            let mut serial = collect(&mut *plan.open(&context)?)?;
+            let mut parallel = collect(&mut *gather.open(&context)?)?;
+            serial.sort();
+            parallel.sort();
+            Ok((serial, parallel))
+        };
+
+        let predicate = binary(BinaryOp::Lt, Expr::Column(1), Expr::Literal(Value::Int(50)));
+        let (serial, parallel) = run(Plan::Filter { input: scan("t"), predicate })?;
+        assert_eq!((parallel.len(), &parallel), ((0..3000).filter(|i| i % 10 != 0 && i % 97 < 50).count(), &serial));
+
+        // The groups of each worker's partition of the keys, nulls among them.
+        let count = Aggregate { function: AggregateFunction::Count, arg: None };
+        let input = repartition(scan("t"), Expr::Column(1));
+        let plan = Plan::HashAggregate { input, group_by: vec![Expr::Column(1)], aggregates: vec![count] };
+        let (serial, parallel) = run(plan)?;
+        assert_eq!((parallel.len(), &parallel), (98, &serial));
+
+        let (left, right) = (repartition(scan("t"), Expr::Column(1)), repartition(scan("u"), Expr::Column(1)));
+        let (left_keys, right_keys) = (vec![Expr::Column(1)], vec![Expr::Column(1)]);
+        let join = JoinType::Left { right_columns: 2 };
+        let plan = Plan::HashJoin { left, right, left_keys, right_keys, residual: None, join };
+        let (serial, parallel) = run(plan)?;
+        let matches = (0..3000).filter(|i| i % 10 != 0 && i % 97 % 2 == 0).count();
+        assert_eq!((parallel.len(), &parallel), (3000, &serial));
+        assert_eq!(parallel.iter().filter(|row| !row[2].is_null()).count(), matches);
+        assert_eq!(temp.pages_in_use(), 0);
+
+        // A limit is the most rows any worker makes, and the most the gather keeps.
+        let gather = Plan::Gather { input: scan("t"), workers: 4 };
+        let limited = Plan::Limit { input: Box::new(gather), limit: Some(Expr::Literal(Value::Int(10))), offset: None };
+        assert_eq!(collect(&mut *limited.open(&context)?)?.len(), 10);
+
+        // A worker that fails stops the others and fails the gather, repartitions and all.
+        let input = repartition(scan("t"), Expr::Column(1));
+        let failing = Plan::Filter { input, predicate: Expr::Column(0) };
+        let gather = Plan::Gather { input: Box::new(failing), workers: 4 };
+        assert!(matches!(collect(&mut *gather.open(&context)?), Err(ExecError::TypeMismatch(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,896 @@
+//! Scalar expressions over the columns of one row, with every name already resolved to a position.
+//!
+//! Evaluation follows SQL's three-valued logic: a comparison with a null is null, `AND` is false if either
+//! side is false and otherwise null if either side is, `OR` likewise with true, and `NOT` of null is null.
+//! Arithmetic, `||`, `LIKE` and casts of a null are null too. Operands of a type the operation cannot take
+//! are an error rather than null.
+//!
+//! Integer arithmetic stays in integers, dividing toward zero, and is an error rather than wrapping when
+//! the result does not fit; an integer with a float is float arithmetic. Dividing by zero is an error for
+//! either. A date plus or minus an integer is a date that many days away, and the difference of two dates
+//! a number of days. A date or timestamp plus or minus an interval is a timestamp, of the same kind for a
+//! timestamp, and the difference of two timestamps an interval; a time of day moved by an interval wraps
+//! around midnight. Intervals add to each other and scale by numbers. Decimal arithmetic is exact, as
+//! the `decimal` module has it, and so is a decimal with an integer; a decimal with a float is float
+//! arithmetic.
+//!
+//! `->` and `->>` follow a key or a position from a JSON document, and `#>` and `#>>` a path of them
+//! written as a text array, as the `json` module does; where that leads nowhere, or the left side is a
+//! JSON null for the text forms, the result is null.
+//!
+//! Text compares by its bytes. To compare it under a collation, each side is wrapped in a `Collate`,
+//! which gives its sort key; the planner does that for comparisons of collated columns.
+//!
+//! `LIKE` matches the whole string, `%` standing for any run of characters and `_` for any one, with case
+//! mattering. A `CASE` with an operand picks the first branch whose value `=` finds equal to it, and one
+//! without picks the first whose condition is true; with no `ELSE`, nothing picked is null.
+//!
+//! Function calls other than aggregates are `Call`s of a `Function`. `now()` is the time the call is
+//! evaluated, as microseconds since the Unix epoch, and the others are null for a null argument, except
+//! `coalesce`, which is its first argument that is not null. `to_char`, `date_part` and `date_trunc`
+//! format, take apart and truncate dates and timestamps as the `datetime` module does.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::collation::Collation;
+use crate::datetime::{self, Interval, MICROS_PER_DAY};
+use crate::decimal::Decimal;
+use crate::json::{self, Json, JsonPath, Step};
+use crate::sql::ast::{BinaryOp, UnaryOp};
+use crate::tuple::{ColumnType, Value};
+
+use super::ExecError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// The value of the input row's column at this position.
+    Column(usize),
+    Literal(Value),
+    /// A bind parameter, numbered from 0, replaced by its value when the plan is bound.
+    Parameter(usize),
+    /// In a subquery, the column at this position of the row of the enclosing query that the subquery is
+    /// run for, replaced by its value before each run.
+    Outer(usize),
+    Unary { op: UnaryOp, expr: Box<Expr> },
+    Binary { op: BinaryOp, left: Box<Expr>, right: Box<Expr> },
+    IsNull { expr: Box<Expr>, negated: bool },
+    Like { expr: Box<Expr>, pattern: Box<Expr>, negated: bool },
+    Case { operand: Option<Box<Expr>>, branches: Vec<(Expr, Expr)>, otherwise: Option<Box<Expr>> },
+    Cast { expr: Box<Expr>, to: ColumnType },
+    Call { function: Function, args: Vec<Expr> },
+    /// Text's sort key under `collation`, for comparisons and sorts to order by; a value of another type
+    /// is itself.
+    Collate { expr: Box<Expr>, collation: Collation },
+}
+impl Expr {
+    pub fn eval(&self, row: &[Value]) -> Result<Value, ExecError> {
+        match self {
+            Expr::Column(c) => Ok(row[*c].clone()),
+            Expr::Literal(v) => Ok(v.clone()),
+            Expr::Parameter(n) => Err(ExecError::MissingParameter(*n)),
+            Expr::Outer(_) => Err(ExecError::Unsupported("outer columns outside a subquery")),
+            Expr::Unary { op: UnaryOp::Not, expr } => match expr.eval(row)? {
+                Value::Bool(v) => Ok(Value::Bool(!v)),
+                Value::Null => Ok(Value::Null),
+                v => Err(ExecError::TypeMismatch(v)),
+            },
+            Expr::Unary { op: UnaryOp::Neg, expr } => match expr.eval(row)? {
+                Value::Int(v) => v.checked_neg().map(Value::Int).ok_or(ExecError::Overflow),
+                Value::Float(v) => Ok(Value::Float(-v)),
+                Value::Decimal(v) => Ok(Value::Decimal(-v)),
+                Value::Interval(v) => v.checked_neg().map(Value::Interval).ok_or(ExecError::Overflow),
+                Value::Null => Ok(Value::Null),
+                v => Err(ExecError::TypeMismatch(v)),
+            },
+            Expr::Binary { op: BinaryOp::And, left, right } => {
+                logic(left.eval(row)?, || right.eval(row), false)
+            }
+            Expr::Binary { op: BinaryOp::Or, left, right } => logic(left.eval(row)?, || right.eval(row), true),
+            Expr::Binary { op, left, right } => binary(*op, left.eval(row)?, right.eval(row)?),
+            Expr::IsNull { expr, negated } => Ok(Value::Bool(expr.eval(row)?.is_null() != *negated)),
+            Expr::Like { expr, pattern, negated } => match (expr.eval(row)?, pattern.eval(row)?) {
+                (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+                (Value::Text(text), Value::Text(pattern)) => Ok(Value::Bool(like(&text, &pattern) != *negated)),
+                (Value::Text(_), v) | (v, _) => Err(ExecError::TypeMismatch(v)),
+            },
+            Expr::Case { operand, branches, otherwise } => {
+                let operand = operand.as_ref().map(|e| e.eval(row)).transpose()?;
+                for (when, then) in branches {
+                    let picked = match &operand {
+                        Some(operand) => binary(BinaryOp::Eq, operand.clone(), when.eval(row)?)? == Value::Bool(true),
+                        None => when.test(row)?,
+                    };
+                    if picked {
+                        return then.eval(row)
+                    }
+                }
+                otherwise.as_ref().map_or(Ok(Value::Null), |e| e.eval(row))
+            }
+            Expr::Cast { expr, to } => cast(expr.eval(row)?, *to),
+            Expr::Collate { expr, collation } => match expr.eval(row)? {
+                Value::Text(text) => Ok(Value::Bytes(collation.sort_key(&text))),
+                value => Ok(value),
+            },
+            Expr::Call { function: Function::Coalesce, args } => {
+                for arg in args {
+                    let value = arg.eval(row)?;
+                    if !value.is_null() {
+                        return Ok(value)
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Expr::Call { function, args } => {
+                let args = args.iter().map(|e| e.eval(row)).collect::<Result<Vec<_>, _>>()?;
+                function.call(args)
+            }
+        }
+    }
+
+    /// Whether the expression is true for `row`; false and null both reject it.
+    pub fn test(&self, row: &[Value]) -> Result<bool, ExecError> {
+        match self.eval(row)? {
+            Value::Bool(v) => Ok(v),
+            Value::Null => Ok(false),
+            v => Err(ExecError::TypeMismatch(v)),
+        }
+    }
+
+    /// Whether `row` satisfies the expression as a constraint: unlike a filter, a constraint only rejects
+    /// the row when it is false, so null passes.
+    pub fn check(&self, row: &[Value]) -> Result<bool, ExecError> {
+        match self.eval(row)? {
+            Value::Bool(v) => Ok(v),
+            Value::Null => Ok(true),
+            v => Err(ExecError::TypeMismatch(v)),
+        }
+    }
+
+    /// Replace every parameter with its value in `params`.
+    pub fn bind(&mut self, params: &[Value]) -> Result<(), ExecError> {
+        if let Expr::Parameter(n) = self {
+            *self = Expr::Literal(params.get(*n).cloned().ok_or(ExecError::MissingParameter(*n))?);
+        }
+        self.children_mut().into_iter().try_for_each(|e| e.bind(params))
+    }
+
+    /// The expressions this one is made of, left to right.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter(_) | Expr::Outer(_) => vec![],
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => vec![expr],
+            Expr::Collate { expr, .. } => vec![expr],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Like { expr, pattern, .. } => vec![expr, pattern],
+            Expr::Case { operand, branches, otherwise } => {
+                let branches = branches.iter().flat_map(|(when, then)| [when, then]);
+                operand.as_deref().into_iter().chain(branches).chain(otherwise.as_deref()).collect()
+            }
+            Expr::Call { args, .. } => args.iter().collect(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter(_) | Expr::Outer(_) => vec![],
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => vec![expr],
+            Expr::Collate { expr, .. } => vec![expr],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Like { expr, pattern, .. } => vec![expr, pattern],
+            Expr::Case { operand, branches, otherwise } => {
+                let branches = branches.iter_mut().flat_map(|(when, then)| [when, then]);
+                operand.as_deref_mut().into_iter().chain(branches).chain(otherwise.as_deref_mut()).collect()
+            }
+            Expr::Call { args, .. } => args.iter_mut().collect(),
+        }
+    }
+
+    /// Call `f` with every column the expression reads.
+    pub fn for_each_column(&self, f: &mut impl FnMut(usize)) {
+        if let Expr::Column(c) = self {
+            f(*c)
+        }
+        self.children().into_iter().for_each(|e| e.for_each_column(f))
+    }
+
+    /// Renumber every column the expression reads with `f`.
+    pub fn map_columns(&mut self, f: &impl Fn(usize) -> usize) {
+        if let Expr::Column(c) = self {
+            *c = f(*c)
+        }
+        self.children_mut().into_iter().for_each(|e| e.map_columns(f))
+    }
+
+    /// Renumber every outer column the expression reads with `f`.
+    pub fn map_outer(&mut self, f: &impl Fn(usize) -> usize) {
+        if let Expr::Outer(c) = self {
+            *c = f(*c)
+        }
+        self.children_mut().into_iter().for_each(|e| e.map_outer(f))
+    }
+
+    /// Replace every outer column with its value in `row`.
+    pub fn bind_outer(&mut self, row: &[Value]) {
+        if let Expr::Outer(c) = self {
+            *self = Expr::Literal(row[*c].clone());
+        }
+        self.children_mut().into_iter().for_each(|e| e.bind_outer(row))
+    }
+
+    /// Whether the expression reads an outer column.
+    pub fn reads_outer(&self) -> bool {
+        matches!(self, Expr::Outer(_)) || self.children().into_iter().any(Expr::reads_outer)
+    }
+
+    /// The column and the path into it that the expression extracts, if it is a column followed by JSON
+    /// operators with literal keys, positions and paths, as `doc -> 'a' ->> 0` is.
+    pub fn json_path(&self) -> Option<(usize, JsonPath)> {
+        let Expr::Binary { op, left, right } = self else { return None };
+        let (column, mut path) = match &**left {
+            Expr::Column(c) => (*c, JsonPath { steps: Vec::new(), text: false }),
+            left => left.json_path().filter(|(_, path)| !path.text)?,
+        };
+        match (op, &**right) {
+            (BinaryOp::Extract | BinaryOp::ExtractText, Expr::Literal(Value::Text(key))) => {
+                path.steps.push(Step::Key(key.clone()))
+            }
+            (BinaryOp::Extract | BinaryOp::ExtractText, Expr::Literal(Value::Int(index))) => {
+                path.steps.push(Step::Index(*index))
+            }
+            (BinaryOp::ExtractPath | BinaryOp::ExtractPathText, Expr::Literal(Value::Text(steps))) => {
+                path.steps.extend(json::parse_path(steps)?)
+            }
+            _ => return None,
+        }
+        path.text = matches!(op, BinaryOp::ExtractText | BinaryOp::ExtractPathText);
+        Some((column, path))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    /// The time of the call, as a timestamp with time zone.
+    Now,
+    Coalesce,
+    Lower,
+    Upper,
+    /// The length of text in characters, or of bytes in bytes.
+    Length,
+    Abs,
+    /// A date or timestamp formatted by a pattern.
+    ToChar,
+    /// A field of a date, time, timestamp or interval, as a float.
+    DatePart,
+    /// A date or timestamp truncated to the start of a field.
+    DateTrunc,
+}
+impl Function {
+    /// The function called `name` in SQL, in any case.
+    pub fn named(name: &str) -> Option<Function> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "now" => Function::Now,
+            "coalesce" => Function::Coalesce,
+            "lower" => Function::Lower,
+            "upper" => Function::Upper,
+            "length" => Function::Length,
+            "abs" => Function::Abs,
+            "to_char" => Function::ToChar,
+            "date_part" => Function::DatePart,
+            "date_trunc" => Function::DateTrunc,
+            _ => return None,
+        })
+    }
+
+    /// Whether the function takes `n` arguments.
+    pub fn takes(self, n: usize) -> bool {
+        match self {
+            Function::Now => n == 0,
+            Function::Coalesce => n > 0,
+            Function::Lower | Function::Upper | Function::Length | Function::Abs => n == 1,
+            Function::ToChar | Function::DatePart | Function::DateTrunc => n == 2,
+        }
+    }
+
+    fn call(self, args: Vec<Value>) -> Result<Value, ExecError> {
+        if self == Function::Now {
+            let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            return Ok(Value::TimestampTz(since.as_micros() as i64))
+        }
+        if matches!(self, Function::ToChar | Function::DatePart | Function::DateTrunc) {
+            let mut args = args.into_iter();
+            let (first, second) = (args.next().unwrap_or(Value::Null), args.next().unwrap_or(Value::Null));
+            return temporal_call(self, first, second)
+        }
+        let Some(arg) = args.into_iter().next() else { return Ok(Value::Null) };
+        Ok(match (self, arg) {
+            (_, Value::Null) => Value::Null,
+            (Function::Lower, Value::Text(v)) => Value::Text(v.to_lowercase()),
+            (Function::Upper, Value::Text(v)) => Value::Text(v.to_uppercase()),
+            (Function::Length, Value::Text(v)) => Value::Int(v.chars().count() as i64),
+            (Function::Length, Value::Bytes(v)) => Value::Int(v.len() as i64),
+            (Function::Abs, Value::Int(v)) => v.checked_abs().map(Value::Int).ok_or(ExecError::Overflow)?,
+            (Function::Abs, Value::Float(v)) => Value::Float(v.abs()),
+            (Function::Abs, Value::Decimal(v)) => Value::Decimal(v.abs()),
+            (_, v) => return Err(ExecError::TypeMismatch(v)),
+        })
+    }
+}
+
+/// `to_char(value, pattern)`, `date_part(field, value)` or `date_trunc(field, value)`.
+fn temporal_call(function: Function, first: Value, second: Value) -> Result<Value, ExecError> {
+    let text = |v: Value| match v {
+        Value::Text(v) => Ok(v),
+        v => Err(ExecError::TypeMismatch(v)),
+    };
+    let (field, value) = match function {
+        _ if first.is_null() || second.is_null() => return Ok(Value::Null),
+        Function::ToChar => {
+            let micros = match first {
+                Value::Date(v) => v as i64 * MICROS_PER_DAY,
+                Value::Timestamp(v) | Value::TimestampTz(v) => v,
+                v => return Err(ExecError::TypeMismatch(v)),
+            };
+            return Ok(Value::Text(datetime::to_char(micros, &text(second)?)))
+        }
+        _ => (text(first)?, second),
+    };
+    let invalid = || ExecError::InvalidField(field.clone());
+    if function == Function::DatePart {
+        let part = match value {
+            Value::Date(v) => datetime::timestamp_part(v as i64 * MICROS_PER_DAY, &field),
+            Value::Timestamp(v) | Value::TimestampTz(v) => datetime::timestamp_part(v, &field),
+            Value::Time(v) => datetime::time_part(v, &field),
+            Value::Interval(v) => datetime::interval_part(v, &field),
+            v => return Err(ExecError::TypeMismatch(v)),
+        };
+        return part.map(Value::Float).ok_or_else(invalid)
+    }
+    match value {
+        Value::Date(v) => datetime::truncate(v as i64 * MICROS_PER_DAY, &field).map(Value::Timestamp),
+        Value::Timestamp(v) => datetime::truncate(v, &field).map(Value::Timestamp),
+        Value::TimestampTz(v) => datetime::truncate(v, &field).map(Value::TimestampTz),
+        v => return Err(ExecError::TypeMismatch(v)),
+    }
+    .ok_or_else(invalid)
+}
+
+/// `AND` when `decisive` is false and `OR` when it is true: `decisive` on either side decides the result
+/// without looking at the other.
+fn logic(left: Value, right: impl FnOnce() -> Result<Value, ExecError>, decisive: bool) -> Result<Value, ExecError> {
+    let left = match left {
+        Value::Bool(v) if v == decisive => return Ok(Value::Bool(decisive)),
+        Value::Bool(_) => Some(()),
+        Value::Null => None,
+        v => return Err(ExecError::TypeMismatch(v)),
+    };
+    match right()? {
+        Value::Bool(v) if v == decisive => Ok(Value::Bool(decisive)),
+        Value::Bool(_) if left.is_some() => Ok(Value::Bool(!decisive)),
+        Value::Bool(_) | Value::Null => Ok(Value::Null),
+        v => Err(ExecError::TypeMismatch(v)),
+    }
+}
+
+pub(super) fn binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, ExecError> {
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null)
+    }
+    let result = match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+            return arithmetic(op, left, right)
+        }
+        BinaryOp::Concat => {
+            return match (left, right) {
+                (Value::Text(a), Value::Text(b)) => Ok(Value::Text(a + &b)),
+                (Value::Bytes(a), Value::Bytes(b)) => Ok(Value::Bytes([a, b].concat())),
+                (Value::Text(_) | Value::Bytes(_), v) | (v, _) => Err(ExecError::TypeMismatch(v)),
+            }
+        }
+        BinaryOp::Extract | BinaryOp::ExtractText | BinaryOp::ExtractPath | BinaryOp::ExtractPathText => {
+            return extract(op, left, right)
+        }
+        BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => {
+            let ordering = left.compare(&right).ok_or(ExecError::TypeMismatch(right))?;
+            match op {
+                BinaryOp::Eq => ordering.is_eq(),
+                BinaryOp::NotEq => ordering.is_ne(),
+                BinaryOp::Lt => ordering.is_lt(),
+                BinaryOp::LtEq => ordering.is_le(),
+                BinaryOp::Gt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }
+        }
+        op => return Err(ExecError::Unsupported(op.symbol())),
+    };
+    Ok(Value::Bool(result))
+}
+
+/// `left -> right` and the other JSON operators, for `left` and `right` not null.
+fn extract(op: BinaryOp, left: Value, right: Value) -> Result<Value, ExecError> {
+    if !matches!(left, Value::Json(_)) {
+        return Err(ExecError::TypeMismatch(left))
+    }
+    let steps = match (op, right) {
+        (BinaryOp::Extract | BinaryOp::ExtractText, Value::Text(key)) => vec![Step::Key(key)],
+        (BinaryOp::Extract | BinaryOp::ExtractText, Value::Int(index)) => vec![Step::Index(index)],
+        (_, Value::Text(path)) => json::parse_path(&path).ok_or(ExecError::InvalidPath(path))?,
+        (_, v) => return Err(ExecError::TypeMismatch(v)),
+    };
+    let text = matches!(op, BinaryOp::ExtractText | BinaryOp::ExtractPathText);
+    Ok(JsonPath { steps, text }.extract(&left))
+}
+
+fn arithmetic(op: BinaryOp, left: Value, right: Value) -> Result<Value, ExecError> {
+    if let Some(result) = temporal(op, &left, &right) {
+        return result
+    }
+    let (a, b) = match (left, right) {
+        (Value::Int(a), Value::Int(b)) => {
+            if matches!(op, BinaryOp::Div | BinaryOp::Rem) && b == 0 {
+                return Err(ExecError::DivisionByZero)
+            }
+            let result = match op {
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Sub => a.checked_sub(b),
+                BinaryOp::Mul => a.checked_mul(b),
+                BinaryOp::Div => a.checked_div(b),
+                _ => Some(a.wrapping_rem(b)),
+            };
+            return result.map(Value::Int).ok_or(ExecError::Overflow)
+        }
+        (Value::Decimal(a), Value::Decimal(b)) => return decimal(op, &a, &b),
+        (Value::Decimal(a), Value::Int(b)) => return decimal(op, &a, &Decimal::from(b)),
+        (Value::Int(a), Value::Decimal(b)) => return decimal(op, &Decimal::from(a), &b),
+        (Value::Int(a), Value::Float(b)) => (a as f64, b),
+        (Value::Float(a), Value::Int(b)) => (a, b as f64),
+        (Value::Float(a), Value::Float(b)) => (a, b),
+        (Value::Decimal(a), Value::Float(b)) => (a.to_f64(), b),
+        (Value::Float(a), Value::Decimal(b)) => (a, b.to_f64()),
+        (Value::Int(_) | Value::Float(_) | Value::Decimal(_), v) | (v, _) => return Err(ExecError::TypeMismatch(v)),
+    };
+    if matches!(op, BinaryOp::Div | BinaryOp::Rem) && b == 0.0 {
+        return Err(ExecError::DivisionByZero)
+    }
+    Ok(Value::Float(match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        _ => a % b,
+    }))
+}
+
+fn decimal(op: BinaryOp, a: &Decimal, b: &Decimal) -> Result<Value, ExecError> {
+    if matches!(op, BinaryOp::Div | BinaryOp::Rem) && b.is_zero() {
+        return Err(ExecError::DivisionByZero)
+    }
+    let result = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Sub => a.checked_sub(b),
+        BinaryOp::Mul => a.checked_mul(b),
+        BinaryOp::Div => a.checked_div(b),
+        _ => a.checked_rem(b),
+    };
+    result.map(Value::Decimal).ok_or(ExecError::Overflow)
+}
+
+/// Arithmetic on dates, times, timestamps and intervals, or `None` if neither operand is one.
+fn temporal(op: BinaryOp, left: &Value, right: &Value) -> Option<Result<Value, ExecError>> {
+    let (add, sub) = (op == BinaryOp::Add, op == BinaryOp::Sub);
+    let factor = |v: &Value| match v {
+        Value::Int(v) => Some(*v as f64),
+        Value::Float(v) => Some(*v),
+        Value::Decimal(v) => Some(v.to_f64()),
+        _ => None,
+    };
+    let result = match (left, right) {
+        (Value::Date(date), Value::Int(days)) | (Value::Int(days), Value::Date(date)) if add || sub => {
+            if sub && matches!(left, Value::Int(_)) {
+                return Some(Err(ExecError::TypeMismatch(right.clone())))
+            }
+            let days = if sub { days.checked_neg() } else { Some(*days) };
+            let date = days.and_then(|days| (*date as i64).checked_add(days));
+            date.and_then(|date| i32::try_from(date).ok()).map(Value::Date)
+        }
+        (Value::Date(a), Value::Date(b)) if sub => Some(Value::Int(*a as i64 - *b as i64)),
+        (Value::Date(_) | Value::Timestamp(_) | Value::TimestampTz(_), Value::Interval(interval)) if add || sub => {
+            let interval = if sub { interval.checked_neg() } else { Some(*interval) };
+            interval.and_then(|interval| shift(left, interval))
+        }
+        (Value::Interval(interval), Value::Date(_) | Value::Timestamp(_) | Value::TimestampTz(_)) if add => {
+            shift(right, *interval)
+        }
+        (Value::Time(time), Value::Interval(interval)) | (Value::Interval(interval), Value::Time(time))
+            if add || (sub && matches!(left, Value::Time(_))) =>
+        {
+            // Times of day wrap around midnight, and months do not move them.
+            let micros = interval.micros.rem_euclid(MICROS_PER_DAY);
+            Some(Value::Time((time + if sub { MICROS_PER_DAY - micros } else { micros }) % MICROS_PER_DAY))
+        }
+        (Value::Timestamp(a), Value::Timestamp(b)) | (Value::TimestampTz(a), Value::TimestampTz(b)) if sub => {
+            a.checked_sub(*b).map(|micros| Value::Interval(Interval { months: 0, micros }))
+        }
+        (Value::Time(a), Value::Time(b)) if sub => Some(Value::Interval(Interval { months: 0, micros: a - b })),
+        (Value::Interval(a), Value::Interval(b)) if add => a.checked_add(*b).map(Value::Interval),
+        (Value::Interval(a), Value::Interval(b)) if sub => {
+            b.checked_neg().and_then(|b| a.checked_add(b)).map(Value::Interval)
+        }
+        (Value::Interval(interval), v) | (v, Value::Interval(interval))
+            if op == BinaryOp::Mul && factor(v).is_some() =>
+        {
+            interval.checked_mul(factor(v)?).map(Value::Interval)
+        }
+        (Value::Interval(interval), v) if op == BinaryOp::Div && factor(v).is_some() => match factor(v)? {
+            0.0 => return Some(Err(ExecError::DivisionByZero)),
+            divisor => interval.checked_mul(1.0 / divisor).map(Value::Interval),
+        },
+        (Value::Date(_) | Value::Time(_) | Value::Timestamp(_) | Value::TimestampTz(_) | Value::Interval(_), v)
+        | (v, Value::Date(_) | Value::Time(_) | Value::Timestamp(_) | Value::TimestampTz(_) | Value::Interval(_)) => {
+            return Some(Err(ExecError::TypeMismatch(v.clone())))
+        }
+        _ => return None,
+    };
+    Some(result.ok_or(ExecError::Overflow))
+}
+
+/// The date or timestamp `value` moved by `interval`: a timestamp, or a timestamp with a time zone for one.
+fn shift(value: &Value, interval: Interval) -> Option<Value> {
+    match value {
+        Value::Date(v) => datetime::add_interval(*v as i64 * MICROS_PER_DAY, interval).map(Value::Timestamp),
+        Value::Timestamp(v) => datetime::add_interval(*v, interval).map(Value::Timestamp),
+        Value::TimestampTz(v) => datetime::add_interval(*v, interval).map(Value::TimestampTz),
+        _ => None,
+    }
+}
+
+/// Whether `pattern` matches the whole of `text`, with `%` matching any run of characters and `_` any one.
+fn like(text: &str, pattern: &str) -> bool {
+    let (text, pattern): (Vec<char>, Vec<char>) = (text.chars().collect(), pattern.chars().collect());
+    let (mut t, mut p) = (0, 0);
+    // Where the last `%` was, and where in the text its run ends so far.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '_' || c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => {
+                // Backtrack: let the last `%` take one more character.
+                let Some((star_p, star_t)) = star else { return false };
+                star = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            }
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+/// `value` as a value of type `to`. Floats and decimals round to the nearest integer, and text parses as a
+/// number, a boolean, or a date, time, timestamp or interval, surrounding whitespace aside. A timestamp
+/// cast to a date or a time keeps its day or its time of day. A number cast to a decimal of a declared
+/// scale is rounded to that scale, half away from zero, and cannot be cast if it then has more digits
+/// than the declared precision; a float is first the decimal it shows as. Text cast to JSON is parsed as
+/// a document, and a document cast to text is shown as one.
+pub fn cast(value: Value, to: ColumnType) -> Result<Value, ExecError> {
+    let invalid = |value: &Value| ExecError::InvalidCast(value.clone(), to);
+    Ok(match (&value, to) {
+        (Value::Null, _) => Value::Null,
+        (Value::Int(_), ColumnType::Int)
+        | (Value::Float(_), ColumnType::Float)
+        | (Value::Bool(_), ColumnType::Bool)
+        | (Value::Bytes(_), ColumnType::Bytes)
+        | (Value::Text(_), ColumnType::Text)
+        | (Value::Date(_), ColumnType::Date)
+        | (Value::Time(_), ColumnType::Time)
+        | (Value::Timestamp(_), ColumnType::Timestamp)
+        | (Value::TimestampTz(_), ColumnType::TimestampTz)
+        | (Value::Interval(_), ColumnType::Interval)
+        | (Value::Json(_), ColumnType::Json) => value,
+        (Value::Float(v), ColumnType::Int) => {
+            let rounded = v.round();
+            // The bounds are -2^63 and 2^63, which are exact as floats.
+            if !(rounded >= i64::MIN as f64 && rounded < i64::MAX as f64) {
+                return Err(invalid(&value))
+            }
+            Value::Int(rounded as i64)
+        }
+        (Value::Decimal(v), ColumnType::Int) => Value::Int(v.to_i64().ok_or_else(|| invalid(&value))?),
+        (Value::Bool(v), ColumnType::Int) => Value::Int(*v as i64),
+        (Value::Text(v), ColumnType::Int) => Value::Int(v.trim().parse().map_err(|_| invalid(&value))?),
+        (Value::Int(v), ColumnType::Float) => Value::Float(*v as f64),
+        (Value::Decimal(v), ColumnType::Float) => Value::Float(v.to_f64()),
+        (Value::Text(v), ColumnType::Float) => Value::Float(v.trim().parse().map_err(|_| invalid(&value))?),
+        (Value::Int(v), ColumnType::Bool) => Value::Bool(*v != 0),
+        (Value::Text(v), ColumnType::Bool) => match v.trim().to_ascii_lowercase().as_str() {
+            "true" | "t" | "yes" | "on" | "1" => Value::Bool(true),
+            "false" | "f" | "no" | "off" | "0" => Value::Bool(false),
+            _ => return Err(invalid(&value)),
+        },
+        (Value::Text(v), ColumnType::Bytes) => Value::Bytes(v.as_bytes().to_vec()),
+        (Value::Int(v), ColumnType::Text) => Value::Text(v.to_string()),
+        (Value::Float(v), ColumnType::Text) => Value::Text(v.to_string()),
+        (Value::Decimal(v), ColumnType::Text) => Value::Text(v.to_string()),
+        (Value::Bool(v), ColumnType::Text) => Value::Text(v.to_string()),
+        (Value::Bytes(v), ColumnType::Text) => match String::from_utf8(v.clone()) {
+            Ok(text) => Value::Text(text),
+            Err(_) => return Err(invalid(&value)),
+        },
+        (Value::Date(v), ColumnType::Text) => Value::Text(datetime::format_date(*v)),
+        (Value::Time(v), ColumnType::Text) => Value::Text(datetime::format_time(*v)),
+        (Value::Timestamp(v), ColumnType::Text) => Value::Text(datetime::format_timestamp(*v)),
+        (Value::TimestampTz(v), ColumnType::Text) => Value::Text(datetime::format_timestamp_tz(*v)),
+        (Value::Interval(v), ColumnType::Text) => Value::Text(datetime::format_interval(*v)),
+        (Value::Json(v), ColumnType::Text) => Value::Text(v.to_string()),
+        (Value::Text(v), ColumnType::Json) => Value::Json(Json::parse(v).ok_or_else(|| invalid(&value))?),
+        (Value::Text(v), ColumnType::Date) => Value::Date(datetime::parse_date(v).ok_or_else(|| invalid(&value))?),
+        (Value::Text(v), ColumnType::Time) => Value::Time(datetime::parse_time(v).ok_or_else(|| invalid(&value))?),
+        (Value::Text(v), ColumnType::Timestamp) => {
+            Value::Timestamp(datetime::parse_timestamp(v, false).ok_or_else(|| invalid(&value))?)
+        }
+        (Value::Text(v), ColumnType::TimestampTz) => {
+            Value::TimestampTz(datetime::parse_timestamp(v, true).ok_or_else(|| invalid(&value))?)
+        }
+        (Value::Text(v), ColumnType::Interval) => {
+            Value::Interval(datetime::parse_interval(v).ok_or_else(|| invalid(&value))?)
+        }
+        (Value::Date(v), ColumnType::Timestamp) => Value::Timestamp(*v as i64 * MICROS_PER_DAY),
+        (Value::Date(v), ColumnType::TimestampTz) => Value::TimestampTz(*v as i64 * MICROS_PER_DAY),
+        (Value::Timestamp(v) | Value::TimestampTz(v), ColumnType::Date) => {
+            Value::Date(i32::try_from(v.div_euclid(MICROS_PER_DAY)).map_err(|_| invalid(&value))?)
+        }
+        (Value::Timestamp(v) | Value::TimestampTz(v), ColumnType::Time) => Value::Time(v.rem_euclid(MICROS_PER_DAY)),
+        (Value::Timestamp(v), ColumnType::TimestampTz) => Value::TimestampTz(*v),
+        (Value::TimestampTz(v), ColumnType::Timestamp) => Value::Timestamp(*v),
+        (Value::Time(v), ColumnType::Interval) => Value::Interval(Interval { months: 0, micros: *v }),
+        (_, ColumnType::Decimal { precision, scale }) => {
+            let decimal = match &value {
+                Value::Int(v) => Some(Decimal::from(*v)),
+                Value::Float(v) => Decimal::from_f64(*v),
+                Value::Text(v) => Decimal::parse(v),
+                Value::Decimal(v) => Some(v.clone()),
+                _ => return Err(invalid(&value)),
+            };
+            let decimal = match precision {
+                0 => decimal,
+                _ => decimal.and_then(|d| d.rescale(precision, scale)),
+            };
+            Value::Decimal(decimal.ok_or_else(|| invalid(&value))?)
+        }
+        _ => return Err(invalid(&value)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+    use crate::exec::ExecError;
+    use crate::json::{Json, JsonPath, Step};
+    use crate::sql::ast::{BinaryOp, UnaryOp};
+    use crate::tuple::{ColumnType, Value};
+
+    use super::{cast, like, Expr, Function};
+
+    fn literal(value: Value) -> Box<Expr> {
+        Box::new(Expr::Literal(value))
+    }
+
+    fn binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, ExecError> {
+        Expr::Binary { op, left: literal(left), right: literal(right) }.eval(&[])
+    }
+
+    #[test]
+    fn test_operators_and_nulls() -> Result<(), ExecError> {
+        let (int, float, text) = (Value::Int, Value::Float, |s: &str| Value::Text(s.to_string()));
+        assert_eq!(binary(BinaryOp::Add, int(2), int(3))?, int(5));
+        assert_eq!(binary(BinaryOp::Div, int(-7), int(2))?, int(-3));
+        assert_eq!(binary(BinaryOp::Rem, int(-7), int(2))?, int(-1));
+        assert_eq!(binary(BinaryOp::Mul, int(3), float(0.5))?, float(1.5));
+        assert_eq!(binary(BinaryOp::Sub, Value::Null, int(1))?, Value::Null);
+        assert_eq!(binary(BinaryOp::Add, int(i64::MAX), int(1)), Err(ExecError::Overflow));
+        assert_eq!(binary(BinaryOp::Div, int(i64::MIN), int(-1)), Err(ExecError::Overflow));
+        assert_eq!(binary(BinaryOp::Div, int(1), int(0)), Err(ExecError::DivisionByZero));
+        assert_eq!(binary(BinaryOp::Rem, float(1.0), int(0)), Err(ExecError::DivisionByZero));
+        assert_eq!(binary(BinaryOp::Add, int(1), text("1")), Err(ExecError::TypeMismatch(text("1"))));
+        assert_eq!(binary(BinaryOp::Concat, text("ab"), text("c"))?, text("abc"));
+        let negate = |v| Expr::Unary { op: UnaryOp::Neg, expr: literal(v) }.eval(&[]);
+        assert_eq!((negate(int(2))?, negate(int(i64::MIN))), (int(-2), Err(ExecError::Overflow)));
+
+        // Three-valued logic: null is neither true nor false.
+        let (t, f, null) = (Value::Bool(true), Value::Bool(false), Value::Null);
+        assert_eq!(binary(BinaryOp::And, null.clone(), f.clone())?, f);
+        assert_eq!(binary(BinaryOp::And, null.clone(), t.clone())?, null);
+        assert_eq!(binary(BinaryOp::Or, null.clone(), t.clone())?, t);
+        assert_eq!(binary(BinaryOp::Eq, null.clone(), null.clone())?, null);
+        let unknown = Expr::Binary { op: BinaryOp::Lt, left: literal(int(1)), right: literal(null.clone()) };
+        assert_eq!((unknown.test(&[])?, unknown.check(&[])?), (false, true));
+
+        let row = [text("abc"), Value::Null];
+        let like_column = |column, pattern: &str, negated| {
+            Expr::Like { expr: Box::new(Expr::Column(column)), pattern: literal(text(pattern)), negated }
+        };
+        let matches = |pattern| like_column(0, pattern, false);
+        assert_eq!(matches("a%").eval(&row)?, t);
+        assert_eq!(matches("_b").eval(&row)?, f);
+        assert_eq!(like_column(1, "%", true).eval(&row)?, null);
+        for (text, pattern, expected) in [
+            ("", "%", true),
+            ("abcbc", "a%bc", true),
+            ("abcbd", "a%bc", false),
+            ("ab", "a%%b%", true),
+            ("héllo", "h_llo", true),
+            ("Hello", "hello", false),
+        ] {
+            assert_eq!(like(text, pattern), expected, "{text} LIKE {pattern}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_and_cast() -> Result<(), ExecError> {
+        let (int, text) = (Value::Int, |s: &str| Value::Text(s.to_string()));
+        let searched = |value| {
+            let negative = Expr::Binary { op: BinaryOp::Lt, left: literal(value), right: literal(int(0)) };
+            let branches = vec![
+                (negative, Expr::Literal(text("neg"))),
+                (Expr::Literal(Value::Bool(true)), Expr::Literal(text("other"))),
+            ];
+            Expr::Case { operand: None, branches, otherwise: None }
+        };
+        assert_eq!(searched(int(-1)).eval(&[])?, text("neg"));
+        // A null condition is not true, so the next branch is picked.
+        assert_eq!(searched(Value::Null).eval(&[])?, text("other"));
+        let simple = |value| Expr::Case {
+            operand: Some(literal(value)),
+            branches: vec![(Expr::Literal(int(1)), Expr::Literal(text("one")))],
+            otherwise: None,
+        };
+        assert_eq!((simple(Value::Float(1.0)).eval(&[])?, simple(Value::Null).eval(&[])?), (text("one"), Value::Null));
+
+        let cast = |value, to| Expr::Cast { expr: literal(value), to }.eval(&[]);
+        assert_eq!(cast(text(" 42 "), ColumnType::Int)?, int(42));
+        assert_eq!(cast(Value::Float(2.5), ColumnType::Int)?, int(3));
+        assert_eq!(cast(Value::Float(1.5), ColumnType::Text)?, text("1.5"));
+        assert_eq!(cast(text("Yes"), ColumnType::Bool)?, Value::Bool(true));
+        assert_eq!(cast(Value::Null, ColumnType::Bytes)?, Value::Null);
+        assert_eq!(cast(text("x"), ColumnType::Int), Err(ExecError::InvalidCast(text("x"), ColumnType::Int)));
+        let huge = Value::Float(1e19);
+        assert_eq!(cast(huge.clone(), ColumnType::Int), Err(ExecError::InvalidCast(huge, ColumnType::Int)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_calls() -> Result<(), ExecError> {
+        let (int, text) = (Value::Int, |s: &str| Value::Text(s.to_string()));
+        let call = |function, args: Vec<Value>| {
+            Expr::Call { function, args: args.into_iter().map(Expr::Literal).collect() }.eval(&[])
+        };
+        assert_eq!(call(Function::Coalesce, vec![Value::Null, int(2), int(3)])?, int(2));
+        assert_eq!(call(Function::Coalesce, vec![Value::Null])?, Value::Null);
+        assert_eq!(call(Function::Upper, vec![text("straße")])?, text("STRASSE"));
+        assert_eq!(call(Function::Length, vec![text("héllo")])?, int(5));
+        assert_eq!(call(Function::Length, vec![Value::Bytes(vec![0; 3])])?, int(3));
+        assert_eq!(call(Function::Abs, vec![Value::Null])?, Value::Null);
+        assert_eq!(call(Function::Abs, vec![int(i64::MIN)]), Err(ExecError::Overflow));
+        assert_eq!(call(Function::Lower, vec![int(1)]), Err(ExecError::TypeMismatch(int(1))));
+        assert!(matches!(call(Function::Now, vec![])?, Value::TimestampTz(now) if now > 1_600_000_000_000_000));
+        assert_eq!(Function::named("NOW"), Some(Function::Now));
+        assert!(!Function::Now.takes(1) && Function::Coalesce.takes(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dates_and_intervals() -> Result<(), ExecError> {
+        let text = |s: &str| Value::Text(s.to_string());
+        let cast = |value, to| Expr::Cast { expr: literal(value), to }.eval(&[]);
+        let date = cast(text("2024-01-31"), ColumnType::Date)?;
+        let timestamp = cast(text("2024-03-01 12:00"), ColumnType::Timestamp)?;
+        let interval = |s: &str| cast(text(s), ColumnType::Interval);
+        let shown = |value| cast(value, ColumnType::Text);
+
+        assert_eq!(shown(binary(BinaryOp::Add, date.clone(), Value::Int(30))?)?, text("2024-03-01"));
+        assert_eq!(binary(BinaryOp::Sub, cast(text("2024-03-01"), ColumnType::Date)?, date.clone())?, Value::Int(30));
+        let month_later = binary(BinaryOp::Add, date.clone(), interval("1 mon")?)?;
+        assert_eq!(shown(month_later)?, text("2024-02-29 00:00:00"));
+        let between = binary(BinaryOp::Sub, timestamp.clone(), cast(date.clone(), ColumnType::Timestamp)?)?;
+        assert_eq!(shown(between)?, text("30 days 12:00:00"));
+        let tz = cast(text("2024-03-01 14:00+02"), ColumnType::TimestampTz)?;
+        assert_eq!(shown(binary(BinaryOp::Sub, tz.clone(), interval("2 hours")?)?)?, text("2024-03-01 10:00:00+00"));
+        let late = cast(text("23:30"), ColumnType::Time)?;
+        assert_eq!(shown(binary(BinaryOp::Add, late, interval("1 hour")?)?)?, text("00:30:00"));
+        assert_eq!(shown(binary(BinaryOp::Mul, interval("1 day 02:00")?, Value::Float(1.5))?)?, text("1 day 15:00:00"));
+        assert_eq!(shown(binary(BinaryOp::Div, interval("1 mon")?, Value::Int(2))?)?, text("15 days"));
+        assert_eq!(binary(BinaryOp::Div, interval("1 day")?, Value::Int(0)), Err(ExecError::DivisionByZero));
+        assert_eq!(binary(BinaryOp::Add, date.clone(), date.clone()), Err(ExecError::TypeMismatch(date.clone())));
+        assert_eq!(binary(BinaryOp::Sub, Value::Int(1), date.clone()), Err(ExecError::TypeMismatch(date.clone())));
+        let far = cast(text("9999-12-31"), ColumnType::Date)?;
+        assert_eq!(binary(BinaryOp::Add, far, Value::Int(i64::MAX)), Err(ExecError::Overflow));
+
+        // A date is its midnight when compared with a timestamp.
+        assert_eq!(binary(BinaryOp::Lt, date.clone(), timestamp.clone())?, Value::Bool(true));
+        assert_eq!(binary(BinaryOp::Eq, cast(date.clone(), ColumnType::Timestamp)?, date.clone())?, Value::Bool(true));
+        assert_eq!(cast(timestamp.clone(), ColumnType::Date)?, cast(text("2024-03-01"), ColumnType::Date)?);
+        let invalid = Err(ExecError::InvalidCast(text("2024-02-30"), ColumnType::Date));
+        assert_eq!(cast(text("2024-02-30"), ColumnType::Date), invalid);
+
+        let call = |function, args: Vec<Value>| {
+            Expr::Call { function, args: args.into_iter().map(Expr::Literal).collect() }.eval(&[])
+        };
+        let formatted = call(Function::ToChar, vec![timestamp.clone(), text("DD Mon YYYY HH12 AM")])?;
+        assert_eq!(formatted, text("01 Mar 2024 12 PM"));
+        assert_eq!(call(Function::DatePart, vec![text("doy"), date.clone()])?, Value::Float(31.0));
+        assert_eq!(call(Function::DatePart, vec![text("hour"), interval("-3 hours")?])?, Value::Float(-3.0));
+        let truncated = call(Function::DateTrunc, vec![text("month"), tz])?;
+        assert_eq!(shown(truncated)?, text("2024-03-01 00:00:00+00"));
+        assert_eq!(call(Function::DateTrunc, vec![text("eon"), date]), Err(ExecError::InvalidField("eon".to_string())));
+        assert_eq!(call(Function::DatePart, vec![Value::Null, timestamp])?, Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimals() -> Result<(), ExecError> {
+        let text = |s: &str| Value::Text(s.to_string());
+        let decimal = |s: &str| Value::Decimal(Decimal::parse(s).unwrap());
+        let shown = |value| cast(value, ColumnType::Text);
+        let money = ColumnType::Decimal { precision: 6, scale: 2 };
+
+        assert_eq!(shown(binary(BinaryOp::Add, decimal("0.1"), decimal("0.20"))?)?, text("0.30"));
+        assert_eq!(shown(binary(BinaryOp::Mul, decimal("1.5"), Value::Int(-3))?)?, text("-4.5"));
+        assert_eq!(shown(binary(BinaryOp::Sub, Value::Int(1), decimal("0.001"))?)?, text("0.999"));
+        assert_eq!(shown(binary(BinaryOp::Div, Value::Int(10), decimal("4"))?)?, text("2.5000000000000000"));
+        assert_eq!(shown(binary(BinaryOp::Rem, decimal("7.5"), Value::Int(2))?)?, text("1.5"));
+        assert_eq!(binary(BinaryOp::Add, decimal("0.5"), Value::Float(0.25))?, Value::Float(0.75));
+        assert_eq!(binary(BinaryOp::Div, decimal("1"), decimal("0.0")), Err(ExecError::DivisionByZero));
+        assert_eq!(binary(BinaryOp::Eq, decimal("1.50"), decimal("1.5"))?, Value::Bool(true));
+        assert_eq!(binary(BinaryOp::Lt, decimal("2.5"), Value::Int(3))?, Value::Bool(true));
+        assert_eq!(shown(Expr::Unary { op: UnaryOp::Neg, expr: literal(decimal("2.50")) }.eval(&[])?)?, text("-2.50"));
+
+        assert_eq!(shown(cast(text(" 2.345 "), money)?)?, text("2.35"));
+        assert_eq!(shown(cast(decimal("-2.345"), money)?)?, text("-2.35"));
+        assert_eq!(shown(cast(Value::Float(0.1), money)?)?, text("0.10"));
+        assert_eq!(shown(cast(Value::Int(12), money)?)?, text("12.00"));
+        assert_eq!(shown(cast(decimal("1.239"), ColumnType::NUMERIC)?)?, text("1.239"));
+        assert_eq!(cast(decimal("9999.995"), money), Err(ExecError::InvalidCast(decimal("9999.995"), money)));
+        assert_eq!(cast(Value::Float(f64::NAN), money), Err(ExecError::InvalidCast(Value::Float(f64::NAN), money)));
+        assert_eq!(cast(decimal("-2.5"), ColumnType::Int)?, Value::Int(-3));
+        assert_eq!(cast(decimal("0.125"), ColumnType::Float)?, Value::Float(0.125));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json() -> Result<(), ExecError> {
+        let text = |s: &str| Value::Text(s.to_string());
+        let json = |s: &str| Value::Json(Json::parse(s).unwrap());
+        let doc = json(r#"{"a": {"b": [10, "x"]}, "c": null}"#);
+
+        assert_eq!(binary(BinaryOp::Extract, doc.clone(), text("a"))?, json(r#"{"b": [10, "x"]}"#));
+        assert_eq!(binary(BinaryOp::ExtractText, doc.clone(), text("c"))?, Value::Null);
+        assert_eq!(binary(BinaryOp::Extract, doc.clone(), text("z"))?, Value::Null);
+        assert_eq!(binary(BinaryOp::ExtractText, json("[1, \"y\"]"), Value::Int(-1))?, text("y"));
+        assert_eq!(binary(BinaryOp::ExtractPath, doc.clone(), text("{a,b,0}"))?, json("10"));
+        assert_eq!(binary(BinaryOp::ExtractPathText, doc.clone(), text("{a, b, 1}"))?, text("x"));
+        let invalid = Err(ExecError::InvalidPath("a.b".to_string()));
+        assert_eq!(binary(BinaryOp::ExtractPath, doc.clone(), text("a.b")), invalid);
+        assert_eq!(binary(BinaryOp::Extract, text("{}"), text("a")), Err(ExecError::TypeMismatch(text("{}"))));
+        assert_eq!(binary(BinaryOp::ExtractText, Value::Null, text("a"))?, Value::Null);
+
+        assert_eq!(cast(text(r#" {"b": 1.0, "a": [true]} "#), ColumnType::Json)?, json(r#"{"a": [true], "b": 1}"#));
+        assert_eq!(cast(json(r#"{"k": "\u00e9"}"#), ColumnType::Text)?, text(r#"{"k": "é"}"#));
+        let invalid = Err(ExecError::InvalidCast(text("{a: 1}"), ColumnType::Json));
+        assert_eq!(cast(text("{a: 1}"), ColumnType::Json), invalid);
+
+        let column = Box::new(Expr::Column(2));
+        let inner = Expr::Binary { op: BinaryOp::Extract, left: column, right: literal(text("a")) };
+        let (left, right) = (Box::new(inner), literal(text("{b,0}")));
+        let outer = Expr::Binary { op: BinaryOp::ExtractPathText, left, right };
+        let steps = vec![Step::Key("a".to_string()), Step::Key("b".to_string()), Step::Key("0".to_string())];
+        assert_eq!(outer.json_path(), Some((2, JsonPath { steps, text: true })));
+        let past_text = Expr::Binary { op: BinaryOp::Extract, left: Box::new(outer), right: literal(text("c")) };
+        assert_eq!(past_text.json_path(), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+//! Dropping the rows a predicate rejects.
+use crate::tuple::Value;
+
+use super::{ExecError, Expr, Operator};
+
+/// The input rows for which `predicate` is true.
+pub struct Filter<'a> {
+    input: Box<dyn Operator + 'a>,
+    predicate: &'a Expr,
+}
+impl<'a> Filter<'a> {
+    pub fn new(input: Box<dyn Operator + 'a>, predicate: &'a Expr) -> Filter<'a> {
+        Filter { input, predicate }
+    }
+}
+impl Operator for Filter<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        while let Some(row) = self.input.next()? {
+            if self.predicate.test(&row)? {
+                return Ok(Some(row))
+            }
+        }
+        Ok(None)
+    }
+
+    fn stop(&mut self) {
+        self.input.stop()
+    }
+}
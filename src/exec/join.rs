@@ -0,0 +1,482 @@
+//! Joins, inner and left outer. Each output row is a left row's columns followed by a right row's, or by
+//! nulls for a left row that matched nothing in a left join. A semi join outputs each left row that
+//! matches anything once, and an anti join each left row that matches nothing, both without the right
+//! row's columns.
+//!
+//! The block nested loop join reads as many left rows as fit in the context's memory budget, then runs
+//! the right input once past the whole block, opening the right plan afresh for every block. The hash
+//! join hashes the right input by its keys and streams the left input past the table. If the right
+//! input outgrows the budget the join turns into a grace hash join: both inputs are split by key hash
+//! into partitions written to temporary space, and each pair of partitions is joined in memory in turn.
+//! A partition still bigger than the budget is joined in memory all the same.
+//!
+//! The rows both joins hold count against the query's memory budget too. A block ends early, and the
+//! right input of a hash join spills, once the query has no room for another row; a spilled partition the
+//! query has no room for fails the join with `ExecError::MemoryLimit`.
+use std::collections::HashMap;
+
+use crate::memory::{MemoryBudget, Reservation};
+use crate::page_store::PageId;
+use crate::sort::{RunReader, RunWriter};
+use crate::storage::Storage;
+use crate::temp::{TempScope, TempSpace};
+use crate::tuple::Value;
+
+use super::spill::{partition, row_bytes, PARTITIONS};
+use super::{Context, ExecError, Expr, JoinType, Operator, Plan};
+
+/// Pad `row` with nulls for a right side of `width` columns that matched nothing.
+fn padded(mut row: Vec<Value>, width: usize) -> Vec<Value> {
+    row.resize(row.len() + width, Value::Null);
+    row
+}
+
+enum Phase<'a> {
+    /// Reading the next block of left rows.
+    Fill,
+    /// Running the right input past the block.
+    Pair { right: Box<dyn Operator + 'a>, row: Option<Vec<Value>>, position: usize },
+    /// Emitting the block's unmatched rows, from this position on, for a left or anti join.
+    Unmatched(usize),
+    Done,
+}
+
+/// The pairs of a left and a right row for which `predicate` is true, or every pair if there is none.
+pub struct NestedLoopJoin<'a, 'store, S: Storage, T: Storage> {
+    left: Box<dyn Operator + 'a>,
+    right: &'a Plan,
+    context: &'a Context<'a, 'store, S, T>,
+    predicate: Option<&'a Expr>,
+    join: JoinType,
+    /// Left rows of the current block, each with whether it has matched.
+    block: Vec<(Vec<Value>, bool)>,
+    /// What the block holds of the query's budget.
+    reserved: Reservation<'a>,
+    left_done: bool,
+    phase: Phase<'a>,
+}
+impl<'a, 'store, S: Storage, T: Storage> NestedLoopJoin<'a, 'store, S, T> {
+    pub fn new(
+        left: Box<dyn Operator + 'a>,
+        right: &'a Plan,
+        context: &'a Context<'a, 'store, S, T>,
+        predicate: Option<&'a Expr>,
+        join: JoinType,
+    ) -> NestedLoopJoin<'a, 'store, S, T> {
+        let (block, reserved) = (Vec::new(), context.memory.reservation());
+        NestedLoopJoin { left, right, context, predicate, join, block, reserved, left_done: false, phase: Phase::Fill }
+    }
+}
+impl<S: Storage, T: Storage> Operator for NestedLoopJoin<'_, '_, S, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        loop {
+            match &mut self.phase {
+                Phase::Done => return Ok(None),
+                Phase::Fill => {
+                    self.block.clear();
+                    self.reserved.clear();
+                    loop {
+                        let Some(row) = self.left.next()? else {
+                            self.left_done = true;
+                            break
+                        };
+                        // A block takes one row however little room the query has, so the join goes on.
+                        let fits = self.reserved.try_grow(row_bytes(&row));
+                        self.block.push((row, false));
+                        if !fits || self.reserved.bytes() >= self.context.memory_bytes {
+                            break
+                        }
+                    }
+                    self.phase = match self.block.is_empty() {
+                        true => Phase::Done,
+                        false => Phase::Pair { right: self.right.open(self.context)?, row: None, position: 0 },
+                    };
+                }
+                Phase::Pair { right, row, position } => {
+                    let Some(current) = row.as_ref().filter(|_| *position < self.block.len()) else {
+                        *row = right.next()?;
+                        *position = 0;
+                        if row.is_none() {
+                            self.phase = Phase::Unmatched(0);
+                        }
+                        continue
+                    };
+                    let (left, matched) = &mut self.block[*position];
+                    *position += 1;
+                    if *matched && matches!(self.join, JoinType::Semi | JoinType::Anti) {
+                        continue
+                    }
+                    let joined = [left.as_slice(), current].concat();
+                    if self.predicate.map_or(Ok(true), |p| p.test(&joined))? {
+                        *matched = true;
+                        match self.join {
+                            JoinType::Semi => return Ok(Some(left.clone())),
+                            JoinType::Anti => {}
+                            _ => return Ok(Some(joined)),
+                        }
+                    }
+                }
+                Phase::Unmatched(position) => {
+                    let right_columns = match self.join {
+                        JoinType::Left { right_columns } => right_columns,
+                        JoinType::Anti => 0,
+                        JoinType::Inner | JoinType::Semi => {
+                            self.phase = if self.left_done { Phase::Done } else { Phase::Fill };
+                            continue
+                        }
+                    };
+                    let Some(offset) = self.block[*position..].iter().position(|(_, matched)| !matched) else {
+                        self.phase = if self.left_done { Phase::Done } else { Phase::Fill };
+                        continue
+                    };
+                    *position += offset + 1;
+                    let row = std::mem::take(&mut self.block[*position - 1].0);
+                    return Ok(Some(padded(row, right_columns)))
+                }
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Phase::Pair { right, .. } = &mut self.phase {
+            right.stop();
+        }
+        self.left.stop();
+        self.block.clear();
+        self.reserved.clear();
+        self.phase = Phase::Done;
+    }
+}
+
+/// A spilled pair of partitions: the pages of the right rows and of the left rows with the same hashes.
+struct Partition {
+    right: Vec<PageId>,
+    left: Vec<PageId>,
+}
+
+enum Probe<'a> {
+    /// Streaming the left input past a table of the whole right input.
+    Input(Box<dyn Operator + 'a>),
+    /// Reading back the left rows of a spilled partition.
+    Run(RunReader<Vec<Value>>),
+    /// Between partitions, or not started.
+    None,
+}
+
+/// The pairs of rows whose `left_keys` over the left row equal `right_keys` over the right row, and for
+/// which `residual` is true if there is one. A row with a null key matches nothing.
+pub struct HashJoin<'a, T: Storage> {
+    left: Option<Box<dyn Operator + 'a>>,
+    right: Option<Box<dyn Operator + 'a>>,
+    left_keys: &'a [Expr],
+    right_keys: &'a [Expr],
+    residual: Option<&'a Expr>,
+    join: JoinType,
+    memory_bytes: usize,
+    memory: &'a MemoryBudget,
+    /// What the hash table holds of the query's budget.
+    reserved: Reservation<'a>,
+    scope: TempScope<'a, T>,
+    table: HashMap<Vec<Value>, Vec<Vec<Value>>>,
+    partitions: std::vec::IntoIter<Partition>,
+    probe: Probe<'a>,
+    /// The left row being matched, its key, and whether it has matched yet.
+    outer: Option<(Vec<Value>, Option<Vec<Value>>, bool)>,
+    position: usize,
+    spilled: bool,
+}
+impl<'a, T: Storage> HashJoin<'a, T> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        left: Box<dyn Operator + 'a>,
+        right: Box<dyn Operator + 'a>,
+        left_keys: &'a [Expr],
+        right_keys: &'a [Expr],
+        residual: Option<&'a Expr>,
+        join: JoinType,
+        temp: &'a TempSpace<T>,
+        memory_bytes: usize,
+        memory: &'a MemoryBudget,
+    ) -> HashJoin<'a, T> {
+        HashJoin {
+            left: Some(left),
+            right: Some(right),
+            left_keys,
+            right_keys,
+            residual,
+            join,
+            memory_bytes,
+            memory,
+            reserved: memory.reservation(),
+            scope: temp.scope(),
+            table: HashMap::new(),
+            partitions: Vec::new().into_iter(),
+            probe: Probe::None,
+            outer: None,
+            position: 0,
+            spilled: false,
+        }
+    }
+
+    /// Whether the right input outgrew the memory budget, so that the join spilled its partitions.
+    pub fn spilled(&self) -> bool {
+        self.spilled
+    }
+
+    /// Hash the right input, spilling both inputs to partitions if it does not fit the budget or the query's.
+    fn build(&mut self) -> Result<(), ExecError> {
+        let (Some(mut left), Some(mut right)) = (self.left.take(), self.right.take()) else { return Ok(()) };
+        loop {
+            let Some(row) = right.next()? else {
+                self.probe = Probe::Input(left);
+                return Ok(())
+            };
+            if let Some(key) = join_key(self.right_keys, &row)? {
+                let fits = self.reserved.try_grow(row_bytes(&row));
+                self.table.entry(key).or_default().push(row);
+                if !fits || self.reserved.bytes() > self.memory_bytes {
+                    break
+                }
+            }
+        }
+
+        self.spilled = true;
+        let mut rights: Vec<_> = (0..PARTITIONS).map(|_| RunWriter::new()).collect();
+        for (key, rows) in self.table.drain() {
+            for row in rows {
+                rights[partition(&key)].push(&mut self.scope, &row)?;
+            }
+        }
+        self.reserved.clear();
+        while let Some(row) = right.next()? {
+            if let Some(key) = join_key(self.right_keys, &row)? {
+                rights[partition(&key)].push(&mut self.scope, &row)?;
+            }
+        }
+        // Left rows with a null key match nothing, but left and anti joins still output them, so they go along
+        // with the first partition.
+        let mut lefts: Vec<_> = (0..PARTITIONS).map(|_| RunWriter::new()).collect();
+        while let Some(row) = left.next()? {
+            let key = join_key(self.left_keys, &row)?;
+            lefts[key.map_or(0, |k| partition(&k))].push(&mut self.scope, &row)?;
+        }
+        let mut partitions = Vec::with_capacity(PARTITIONS);
+        for (right, left) in rights.into_iter().zip(lefts) {
+            partitions.push(Partition { right: right.finish(&mut self.scope)?, left: left.finish(&mut self.scope)? });
+        }
+        self.partitions = partitions.into_iter();
+        Ok(())
+    }
+
+    /// The next left row to match, moving on to the next spilled partition when one runs out.
+    fn next_probe(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        loop {
+            match &mut self.probe {
+                Probe::Input(left) => return left.next(),
+                Probe::Run(reader) => {
+                    if let Some(row) = reader.next_item(&mut self.scope)? {
+                        return Ok(Some(row))
+                    }
+                }
+                Probe::None => {}
+            }
+            let Some(partition) = self.partitions.next() else { return Ok(None) };
+            self.table.clear();
+            self.reserved.clear();
+            let mut reader = RunReader::<Vec<Value>>::new(partition.right);
+            while let Some(row) = reader.next_item(&mut self.scope)? {
+                if let Some(key) = join_key(self.right_keys, &row)? {
+                    if !self.reserved.try_grow(row_bytes(&row)) {
+                        return Err(ExecError::MemoryLimit(self.memory.limit()))
+                    }
+                    self.table.entry(key).or_default().push(row);
+                }
+            }
+            self.probe = Probe::Run(RunReader::new(partition.left));
+        }
+    }
+}
+impl<T: Storage> Operator for HashJoin<'_, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        self.build()?;
+        loop {
+            let Some((row, key, matched)) = &mut self.outer else {
+                let Some(row) = self.next_probe()? else { return Ok(None) };
+                let key = join_key(self.left_keys, &row)?;
+                self.outer = Some((row, key, false));
+                self.position = 0;
+                continue
+            };
+            let right = key.as_ref().and_then(|k| self.table.get(k)).and_then(|rows| rows.get(self.position));
+            if let Some(right) = right {
+                self.position += 1;
+                let joined = [row.as_slice(), right].concat();
+                if self.residual.map_or(Ok(true), |p| p.test(&joined))? {
+                    *matched = true;
+                    match self.join {
+                        JoinType::Semi => return Ok(self.outer.take().map(|(row, ..)| row)),
+                        JoinType::Anti => self.outer = None,
+                        _ => return Ok(Some(joined)),
+                    }
+                }
+                continue
+            }
+            let (row, _, matched) = self.outer.take().unwrap();
+            match (self.join, matched) {
+                (JoinType::Left { right_columns }, false) => return Ok(Some(padded(row, right_columns))),
+                (JoinType::Anti, false) => return Ok(Some(row)),
+                _ => {}
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        let probe = std::mem::replace(&mut self.probe, Probe::None);
+        for mut input in self.left.take().into_iter().chain(self.right.take()) {
+            input.stop();
+        }
+        if let Probe::Input(mut left) = probe {
+            left.stop();
+        }
+        self.table.clear();
+        self.reserved.clear();
+        self.partitions = Vec::new().into_iter();
+        self.outer = None;
+    }
+}
+
+/// The values of `keys` over `row`, or `None` if any is null. Floats holding a whole number become
+/// integers, so that keys hash alike whenever `=` would find them equal.
+pub(super) fn join_key(keys: &[Expr], row: &[Value]) -> Result<Option<Vec<Value>>, ExecError> {
+    let mut values = Vec::with_capacity(keys.len());
+    for key in keys {
+        let value = match key.eval(row)? {
+            Value::Null => return Ok(None),
+            Value::Float(v) if v.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(&v) => {
+                Value::Int(v as i64)
+            }
+            value => value,
+        };
+        values.push(value);
+    }
+    Ok(Some(values))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{collect, Context, ExecError, Expr, JoinType, Operator, Plan, Values};
+    use crate::memory::MemoryBudget;
+    use crate::sql::ast::BinaryOp;
+    use crate::storage::TestStorage;
+    use crate::temp::TempSpace;
+    use crate::tuple::Value;
+
+    use super::HashJoin;
+
+    fn rows(values: &[(Value, i64)]) -> Vec<Vec<Expr>> {
+        values.iter().map(|(k, v)| vec![Expr::Literal(k.clone()), Expr::Literal(Value::Int(*v))]).collect()
+    }
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+    }
+
+    fn sorted(mut rows: Vec<Vec<Value>>) -> Vec<Vec<Value>> {
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn test_joins_agree() -> Result<(), ExecError> {
+        let temp = TempSpace::new(TestStorage::new());
+        let left = vec![(Value::Int(1), 10), (Value::Null, 11), (Value::Float(2.0), 12), (Value::Int(3), 13)];
+        let right = vec![(Value::Int(2), 20), (Value::Int(1), 21), (Value::Null, 22), (Value::Int(1), 23)];
+        let (left, right) = (Plan::Values { rows: rows(&left) }, Plan::Values { rows: rows(&right) });
+        let keys = vec![Expr::Column(0)];
+        let residual = binary(BinaryOp::NotEq, Expr::Column(3), Expr::Literal(Value::Int(23)));
+        let equal = binary(BinaryOp::Eq, Expr::Column(0), Expr::Column(2));
+        let predicate = Some(binary(BinaryOp::And, equal, residual.clone()));
+        let run = |join, memory_bytes| -> Result<_, ExecError> {
+            let context = Context { memory_bytes, ..Context::<TestStorage, _>::new(&temp) };
+            let (l, r) = (Box::new(left.clone()), Box::new(right.clone()));
+            let looped = Plan::NestedLoopJoin { left: l.clone(), right: r.clone(), predicate: predicate.clone(), join };
+            let hashed = Plan::HashJoin {
+                left: l,
+                right: r,
+                left_keys: keys.clone(),
+                right_keys: keys.clone(),
+                residual: Some(residual.clone()),
+                join,
+            };
+            let looped = collect(&mut *looped.open::<TestStorage, _>(&context)?)?;
+            let hashed = collect(&mut *hashed.open::<TestStorage, _>(&context)?)?;
+            Ok((looped, hashed))
+        };
+
+        let matched = vec![
+            vec![Value::Int(1), Value::Int(10), Value::Int(1), Value::Int(21)],
+            vec![Value::Float(2.0), Value::Int(12), Value::Int(2), Value::Int(20)],
+        ];
+        let unmatched = vec![
+            vec![Value::Null, Value::Int(11), Value::Null, Value::Null],
+            vec![Value::Int(3), Value::Int(13), Value::Null, Value::Null],
+        ];
+        let outer = sorted([matched.clone(), unmatched].concat());
+        // A budget of one byte makes every block one row and spills the hash join.
+        for memory_bytes in [1, 1 << 20] {
+            let (looped, hashed) = run(JoinType::Inner, memory_bytes)?;
+            assert_eq!((sorted(looped), sorted(hashed)), (matched.clone(), matched.clone()));
+            let (looped, hashed) = run(JoinType::Left { right_columns: 2 }, memory_bytes)?;
+            assert_eq!((sorted(looped), sorted(hashed)), (outer.clone(), outer.clone()));
+            let semi = vec![vec![Value::Int(1), Value::Int(10)], vec![Value::Float(2.0), Value::Int(12)]];
+            let (looped, hashed) = run(JoinType::Semi, memory_bytes)?;
+            assert_eq!((sorted(looped), sorted(hashed)), (semi.clone(), semi));
+            let anti = vec![vec![Value::Null, Value::Int(11)], vec![Value::Int(3), Value::Int(13)]];
+            let (looped, hashed) = run(JoinType::Anti, memory_bytes)?;
+            assert_eq!((sorted(looped), sorted(hashed)), (anti.clone(), anti));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_grace_hash_join_spills() -> Result<(), ExecError> {
+        let temp = TempSpace::new(TestStorage::new());
+        let numbers = |n: i64, text: &str| -> Vec<Vec<Expr>> {
+            let row = |i| vec![Expr::Literal(Value::Int(i)), Expr::Literal(Value::Text(format!("{text} {i}")))];
+            (0..n).map(row).collect()
+        };
+        let (left, right) = (numbers(3000, "left"), numbers(2000, "right"));
+        let keys = [Expr::Column(0)];
+        let join = |memory_bytes, memory| {
+            HashJoin::new(
+                Box::new(Values::new(&left)) as Box<dyn Operator>,
+                Box::new(Values::new(&right)),
+                &keys,
+                &keys,
+                None,
+                JoinType::Left { right_columns: 2 },
+                &temp,
+                memory_bytes,
+                memory,
+            )
+        };
+        let (unlimited, small) = (MemoryBudget::new(usize::MAX), MemoryBudget::new(32 * 1024));
+        // The join's own budget or the query's may be the one that spills it.
+        for (memory_bytes, memory) in [(64 * 1024, &unlimited), (usize::MAX, &small)] {
+            let mut join = join(memory_bytes, memory);
+            let rows = sorted(collect(&mut join)?);
+            assert!(join.spilled());
+            assert_eq!(rows.len(), 3000);
+            assert_eq!(rows[1999][3], Value::Text("right 1999".to_string()));
+            assert_eq!(rows.iter().filter(|r| r[2].is_null()).count(), 1000);
+            drop(join);
+            assert_eq!((memory.used(), temp.pages_in_use()), (0, 0));
+        }
+
+        // A partition the query has no room for fails the join.
+        let tiny = MemoryBudget::new(256);
+        assert_eq!(collect(&mut join(usize::MAX, &tiny)), Err(ExecError::MemoryLimit(256)));
+        assert_eq!((tiny.used(), temp.pages_in_use()), (0, 0));
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! `LIMIT` and `OFFSET`. The limit is passed down before the first row is pulled, so that a sort below
+//! keeps only the rows that can be output, and once it is reached the input is stopped rather than run out.
+use crate::tuple::Value;
+
+use super::{ExecError, Expr, Operator};
+
+/// The input rows after the first `offset`, and no more than `limit` of them. Both are evaluated, over no
+/// row, when the first row is pulled: a null limit is no limit, and a null offset skips nothing.
+pub struct Limit<'a> {
+    input: Box<dyn Operator + 'a>,
+    limit: Option<&'a Expr>,
+    offset: Option<&'a Expr>,
+    /// A limit from above, on top of this one.
+    outer: usize,
+    /// The rows still to skip and then to output, once evaluated.
+    remaining: Option<(usize, usize)>,
+}
+impl<'a> Limit<'a> {
+    pub fn new(input: Box<dyn Operator + 'a>, limit: Option<&'a Expr>, offset: Option<&'a Expr>) -> Limit<'a> {
+        Limit { input, limit, offset, outer: usize::MAX, remaining: None }
+    }
+}
+
+/// `expr` as a row count, or `None` if it is null.
+fn count(expr: &Expr) -> Result<Option<usize>, ExecError> {
+    match expr.eval(&[])? {
+        Value::Null => Ok(None),
+        Value::Int(v) if v >= 0 => Ok(Some(usize::try_from(v).unwrap_or(usize::MAX))),
+        value => Err(ExecError::InvalidLimit(value)),
+    }
+}
+
+impl Operator for Limit<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        let (skip, left) = match &mut self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                let limit = self.limit.map(count).transpose()?.flatten().unwrap_or(usize::MAX).min(self.outer);
+                let offset = self.offset.map(count).transpose()?.flatten().unwrap_or(0);
+                self.input.limit(offset.saturating_add(limit));
+                self.remaining.insert((offset, limit))
+            }
+        };
+        if *left == 0 {
+            return Ok(None)
+        }
+        while *skip > 0 {
+            if self.input.next()?.is_none() {
+                *left = 0;
+                return Ok(None)
+            }
+            *skip -= 1;
+        }
+        let Some(row) = self.input.next()? else {
+            *left = 0;
+            return Ok(None)
+        };
+        *left -= 1;
+        if *left == 0 {
+            self.input.stop();
+        }
+        Ok(Some(row))
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.outer = self.outer.min(rows);
+    }
+
+    fn stop(&mut self) {
+        self.input.stop();
+        self.remaining = Some((0, 0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{collect, ExecError, Expr, Operator, Values};
+    use crate::tuple::Value;
+
+    use super::Limit;
+
+    /// Counts the rows pulled from it, and whether it was stopped.
+    struct Counted<'a> {
+        input: Values<'a>,
+        pulled: &'a std::cell::Cell<usize>,
+        stopped: &'a std::cell::Cell<bool>,
+    }
+    impl Operator for Counted<'_> {
+        fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+            assert!(!self.stopped.get());
+            self.pulled.set(self.pulled.get() + 1);
+            self.input.next()
+        }
+
+        fn stop(&mut self) {
+            self.stopped.set(true)
+        }
+    }
+
+    #[test]
+    fn test_limit_and_offset() -> Result<(), ExecError> {
+        let rows: Vec<Vec<Expr>> = (0..10).map(|i| vec![Expr::Literal(Value::Int(i))]).collect();
+        let (pulled, stopped) = (std::cell::Cell::new(0), std::cell::Cell::new(false));
+        let int = |v| Expr::Literal(Value::Int(v));
+        let limit = |count: Option<Expr>, offset: Option<Expr>| -> Result<Vec<i64>, ExecError> {
+            pulled.set(0);
+            stopped.set(false);
+            let input = Counted { input: Values::new(&rows), pulled: &pulled, stopped: &stopped };
+            let mut limited = Limit::new(Box::new(input), count.as_ref(), offset.as_ref());
+            let rows = collect(&mut limited)?;
+            Ok(rows.into_iter().map(|row| if let Value::Int(v) = row[0] { v } else { unreachable!() }).collect())
+        };
+
+        assert_eq!(limit(Some(int(3)), Some(int(2)))?, vec![2, 3, 4]);
+        // Nothing past the last row output is pulled.
+        assert_eq!((pulled.get(), stopped.get()), (5, true));
+        assert_eq!(limit(Some(int(3)), Some(int(8)))?, vec![8, 9]);
+        assert_eq!(limit(Some(int(0)), None)?, Vec::<i64>::new());
+        assert_eq!(pulled.get(), 0);
+        assert_eq!(limit(Some(Expr::Literal(Value::Null)), Some(Expr::Literal(Value::Null)))?.len(), 10);
+        assert_eq!(limit(Some(int(-1)), None), Err(ExecError::InvalidLimit(Value::Int(-1))));
+        let text = Value::Text("1".to_string());
+        assert_eq!(limit(None, Some(Expr::Literal(text.clone()))), Err(ExecError::InvalidLimit(text)));
+        Ok(())
+    }
+}
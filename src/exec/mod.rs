@@ -0,0 +1,758 @@
+//! Query execution in the Volcano style: a plan is a tree of operators, and each operator makes its rows
+//! by pulling them one at a time from its inputs. Nothing is materialised between operators, so a scan
+//! under a filter under a projection holds one row at a time however large the table.
+//!
+//! A `Plan` is the owned description of the tree, with names resolved to tables and column positions by
+//! the planner. `Plan::open` turns it into operators reading from the tables in a `Context`, which also
+//! holds the temporary space and memory budget for operators that spill. The operators borrow both, so
+//! one plan can be opened and run any number of times, or reopened by an operator that reruns its input.
+//!
+//! A subquery in an expression runs under an `Apply`, which computes a value from the subquery's rows for
+//! each of its input's rows. The subquery reads the input row's columns as `Expr::Outer` columns.
+//!
+//! A common table expression read more than once is materialized by a `With` and read by `CteScan`s, and a
+//! recursive one runs as a `Recursive` whose recursive term reads the rows of the round before through
+//! `CteScan`s. The context holds the rows of both under the id the planner gives each.
+//!
+//! A `Profile` in the context counts the rows each operator outputs and the time spent in it, for
+//! `EXPLAIN ANALYZE`.
+//!
+//! A `Gather` runs its input on several threads at once, with the scans under it sharing out their tables'
+//! pages and `Repartition`s handing each row to the worker its key hashes to, as `exchange` describes. The
+//! context is shared by the workers, so what they count and reserve in it goes for the whole query.
+//!
+//! A `CancelHandle` in the context stops a plan part way: every operator checks it before making a row,
+//! and a scan before loading each page, so an operator draining its input, such as a sort, stops within
+//! a row or a page of being cancelled. The plan fails with `ExecError::Cancelled`.
+//!
+//! Joins output a left row's columns followed by the right row's, so an expression above a join reads
+//! the right input's first column at the position after the left input's last.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+use crate::cancel::CancelHandle;
+use crate::heap::HeapError;
+use crate::memory::MemoryBudget;
+use crate::page_store::PageError;
+use crate::sort::SortError;
+use crate::sql::ast;
+use crate::storage::Storage;
+use crate::table::{ClusteredTable, PartitionedTable, Table, TableError};
+use crate::temp::TempSpace;
+use crate::tuple::{ColumnType, Value};
+
+use exchange::Exchange;
+
+mod aggregate;
+mod apply;
+mod cte;
+mod exchange;
+pub mod expr;
+mod filter;
+mod join;
+mod limit;
+mod order;
+mod profile;
+mod project;
+mod scan;
+mod spill;
+mod window;
+
+pub use aggregate::{Aggregate, AggregateFunction, HashAggregate, StreamAggregate};
+pub use apply::{Apply, ApplyKind};
+pub use cte::{CteScan, Recursive, With};
+pub use exchange::{Gather, Repartition};
+pub use expr::{Expr, Function};
+pub use filter::Filter;
+pub use join::{HashJoin, NestedLoopJoin};
+pub use limit::Limit;
+pub use order::{OrderBy, SortKey};
+pub use profile::{NodeStats, Profile};
+pub use project::Project;
+pub use scan::{ClusteredScan, IndexScan, PartitionScan, SeqScan, Values};
+pub use window::{Window, WindowFunction};
+
+#[derive(Debug, PartialEq)]
+pub enum ExecError {
+    Page(PageError),
+    Table(TableError),
+    Sort(SortError),
+    /// A value of a type the operation it was given to cannot take.
+    TypeMismatch(Value),
+    /// The plan reads a parameter that was not bound.
+    MissingParameter(usize),
+    /// The plan scans a table it was not opened with.
+    MissingTable(String),
+    /// An integer result too big for 64 bits.
+    Overflow,
+    DivisionByZero,
+    /// A value that cannot be cast to the type.
+    InvalidCast(Value, ColumnType),
+    /// A `LIMIT` or `OFFSET` that is not a non-negative integer.
+    InvalidLimit(Value),
+    /// A scalar subquery that returned more than one row.
+    SubqueryRows,
+    /// A recursive query still adding rows after this many rounds.
+    RecursionLimit(usize),
+    /// A field of a date, time or interval that `date_part` or `date_trunc` does not know.
+    InvalidField(String),
+    /// A path for `#>` or `#>>` that is not written as a text array like `'{a,0}'`.
+    InvalidPath(String),
+    /// An operator this executor cannot evaluate yet.
+    Unsupported(&'static str),
+    /// The context's `CancelHandle` was cancelled while the plan ran.
+    Cancelled,
+    /// An operator had to hold more rows in memory than the query's budget, of this many bytes, had room
+    /// for, and could not spill them.
+    MemoryLimit(usize),
+}
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::Page(e) => write!(f, "page error: {e:?}"),
+            ExecError::Table(e) => write!(f, "{e}"),
+            ExecError::Sort(e) => write!(f, "sort error: {e:?}"),
+            ExecError::TypeMismatch(value) => {
+                write!(f, "operation does not take {}", ast::Expr::Literal(value.clone()))
+            }
+            ExecError::MissingParameter(n) => write!(f, "parameter {} is not bound", n + 1),
+            ExecError::MissingTable(name) => write!(f, "table {name} is not open"),
+            ExecError::Overflow => write!(f, "integer out of range"),
+            ExecError::DivisionByZero => write!(f, "division by zero"),
+            ExecError::InvalidCast(value, to) => {
+                write!(f, "cannot cast {} to {}", ast::Expr::Literal(value.clone()), ast::type_name(*to))
+            }
+            ExecError::InvalidLimit(value) => {
+                write!(f, "LIMIT and OFFSET take a non-negative integer, not {}", ast::Expr::Literal(value.clone()))
+            }
+            ExecError::SubqueryRows => write!(f, "subquery used as an expression returned more than one row"),
+            ExecError::RecursionLimit(rounds) => write!(f, "recursive query still adding rows after {rounds} rounds"),
+            ExecError::InvalidField(field) => write!(f, "no field called {field}"),
+            ExecError::InvalidPath(path) => write!(f, "invalid path {path}"),
+            ExecError::Unsupported(what) => write!(f, "{what} is not supported yet"),
+            ExecError::Cancelled => write!(f, "cancelled"),
+            ExecError::MemoryLimit(bytes) => write!(f, "query needs more than its {bytes} bytes of memory"),
+        }
+    }
+}
+impl From<PageError> for ExecError {
+    fn from(e: PageError) -> Self {
+        ExecError::Page(e)
+    }
+}
+impl From<SortError> for ExecError {
+    fn from(e: SortError) -> Self {
+        match e {
+            SortError::Page(e) => ExecError::Page(e),
+            e => ExecError::Sort(e),
+        }
+    }
+}
+impl From<TableError> for ExecError {
+    fn from(e: TableError) -> Self {
+        match e {
+            TableError::Page(e) => ExecError::Page(e),
+            TableError::Heap(HeapError::Cancelled) => ExecError::Cancelled,
+            e => ExecError::Table(e),
+        }
+    }
+}
+
+/// A source of rows. `next` returns `None` once the rows run out, and keeps returning it after that.
+///
+/// The caller may also say how much it will pull, so that an operator can do less work. Operators that
+/// pass their input's rows through one for one forward both calls to it.
+pub trait Operator {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError>;
+
+    /// A promise, made before the first `next`, that no more than `rows` rows will be pulled. A sort then
+    /// keeps only the first `rows`, and an index scan looks up no more.
+    fn limit(&mut self, _rows: usize) {}
+
+    /// A promise that nothing more will be pulled, so the operator can let go of what it holds rather than
+    /// waiting to be dropped. `next` returns `None` after this.
+    fn stop(&mut self) {}
+}
+
+/// An operator failing with `ExecError::Cancelled`, rather than pulling its input, once `cancel` is
+/// cancelled.
+struct Cancellable<'a> {
+    input: Box<dyn Operator + 'a>,
+    cancel: &'a CancelHandle,
+}
+impl Operator for Cancellable<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if self.cancel.is_cancelled() {
+            return Err(ExecError::Cancelled)
+        }
+        self.input.next()
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.input.limit(rows);
+    }
+
+    fn stop(&mut self) {
+        self.input.stop();
+    }
+}
+
+/// Bytes of rows a join holds in memory, by default, before it spills or starts another block.
+pub const DEFAULT_MEMORY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Bytes of rows all the operators of a query hold in memory together, by default, before those that can
+/// spill do and those that cannot fail.
+pub const DEFAULT_QUERY_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+
+/// Workers a query may run parts of its plan on at once, by default: one, running none of it in parallel.
+pub const DEFAULT_MAX_PARALLEL_WORKERS: usize = 1;
+
+/// Rounds a recursive query may run its recursive term for, by default.
+pub const DEFAULT_RECURSION_LIMIT: usize = 1000;
+
+/// What a plan's operators run against.
+pub struct Context<'a, 'store, S: Storage, T: Storage> {
+    /// The tables the plan reads, by name.
+    pub tables: HashMap<String, Table<'store, S>>,
+    /// The clustered tables the plan reads, by name.
+    pub clustered: HashMap<String, ClusteredTable<'store, S>>,
+    /// The partitioned tables the plan reads, by name.
+    pub partitioned: HashMap<String, PartitionedTable<'store, S>>,
+    pub temp: &'a TempSpace<T>,
+    /// Bytes of rows one operator may hold in memory.
+    pub memory_bytes: usize,
+    /// Bytes of rows all the operators of the plan may hold in memory together.
+    pub memory: MemoryBudget,
+    /// Where to count what the operators of the plan being run do.
+    pub profile: Option<&'a Profile>,
+    /// Rounds a recursive query may run its recursive term for.
+    pub recursion_limit: usize,
+    /// The rows of each common table expression materialized so far, and of each recursive query's last
+    /// round, by id.
+    pub ctes: Mutex<HashMap<usize, Arc<Vec<Vec<Value>>>>>,
+    /// Checked by every operator before it makes a row and by scans before they load a page, failing the
+    /// plan with `ExecError::Cancelled` once it is cancelled.
+    pub cancel: Option<CancelHandle>,
+    /// What the workers of each gather in the plan share while it runs.
+    exchange: Exchange,
+}
+impl<'a, 'store, S: Storage, T: Storage> Context<'a, 'store, S, T> {
+    pub fn new(temp: &'a TempSpace<T>) -> Context<'a, 'store, S, T> {
+        Context {
+            tables: HashMap::new(),
+            clustered: HashMap::new(),
+            partitioned: HashMap::new(),
+            temp,
+            memory_bytes: DEFAULT_MEMORY_BYTES,
+            memory: MemoryBudget::new(DEFAULT_QUERY_MEMORY_BYTES),
+            profile: None,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            ctes: Mutex::default(),
+            cancel: None,
+            exchange: Exchange::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinType {
+    Inner,
+    /// Every left row at least once, with the right row's `right_columns` columns null if it matched none.
+    Left { right_columns: usize },
+    /// Every left row that matches a right row, once, without the right row's columns.
+    Semi,
+    /// Every left row that matches no right row, without the right row's columns.
+    Anti,
+}
+
+/// The expression of `bound`, unless it is unbounded.
+fn bounded(bound: &Bound<Expr>) -> Option<&Expr> {
+    match bound {
+        Bound::Included(expr) | Bound::Excluded(expr) => Some(expr),
+        Bound::Unbounded => None,
+    }
+}
+
+fn bounded_mut(bound: &mut Bound<Expr>) -> Option<&mut Expr> {
+    match bound {
+        Bound::Included(expr) | Bound::Excluded(expr) => Some(expr),
+        Bound::Unbounded => None,
+    }
+}
+
+impl JoinType {
+    fn prefix(self) -> &'static str {
+        match self {
+            JoinType::Inner => "",
+            JoinType::Left { .. } => "Left",
+            JoinType::Semi => "Semi",
+            JoinType::Anti => "Anti",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    /// Every row of the named heap table, followed by its rid as bytes if `rid`.
+    SeqScan { table: String, rid: bool },
+    /// The rows of the named table whose leading columns in `index` equal the values of `key`, and whose
+    /// next column is between `low` and `high` unless both are unbounded, each followed by its rid as bytes
+    /// if `rid`.
+    IndexScan { table: String, index: String, key: Vec<Expr>, low: Bound<Expr>, high: Bound<Expr>, rid: bool },
+    /// The rows of the named clustered table whose leading primary key columns equal the values of `key`, in
+    /// primary key order, each followed by the encoding of its primary key as bytes if `rid`.
+    ClusteredScan { table: String, key: Vec<Expr>, rid: bool },
+    /// The rows of the named partitioned table in the partitions that may hold rows whose partition column
+    /// `column` is between `low` and `high`, every partition if both are unbounded, each followed by its
+    /// partition rid as bytes if `rid`. Rows outside the bounds are not filtered out.
+    PartitionScan { table: String, column: usize, low: Bound<Expr>, high: Bound<Expr>, rid: bool },
+    /// Rows of constant expressions; a query without `FROM` reads one row with no columns.
+    Values { rows: Vec<Vec<Expr>> },
+    Filter { input: Box<Plan>, predicate: Expr },
+    Project { input: Box<Plan>, exprs: Vec<Expr> },
+    /// The pairs of rows from `left` and `right` that `predicate` holds for, comparing every pair. The right
+    /// plan is run once for each block of left rows.
+    NestedLoopJoin { left: Box<Plan>, right: Box<Plan>, predicate: Option<Expr>, join: JoinType },
+    /// The pairs of rows whose `left_keys`, over the left row, equal their `right_keys`, over the right row,
+    /// and that `residual` holds for, found by hashing the right input.
+    HashJoin {
+        left: Box<Plan>,
+        right: Box<Plan>,
+        left_keys: Vec<Expr>,
+        right_keys: Vec<Expr>,
+        residual: Option<Expr>,
+        join: JoinType,
+    },
+    /// For each group of input rows with equal `group_by` keys, the keys followed by the value of each of
+    /// `aggregates`. The input must arrive grouped by the keys, as when ordered by them.
+    StreamAggregate { input: Box<Plan>, group_by: Vec<Expr>, aggregates: Vec<Aggregate> },
+    /// The same rows as `StreamAggregate`, from input in any order, found by hashing the keys. Groups come
+    /// out in the order their first rows do while they fit in memory.
+    HashAggregate { input: Box<Plan>, group_by: Vec<Expr>, aggregates: Vec<Aggregate> },
+    /// The input rows sorted by `keys`, the first key first.
+    OrderBy { input: Box<Plan>, keys: Vec<SortKey> },
+    /// The input rows after the first `offset`, and no more than `limit` of them. Both evaluate to
+    /// integers over no row; a null limit is no limit.
+    Limit { input: Box<Plan>, limit: Option<Expr>, offset: Option<Expr> },
+    /// Each input row followed by the value `kind` computes from the rows of `subquery`, run with the
+    /// input row's values for its outer columns.
+    Apply { input: Box<Plan>, subquery: Box<Plan>, kind: ApplyKind },
+    /// Each input row followed by the value of each of `functions` over the rows with equal `partition_by`
+    /// keys. The input must arrive sorted by the partition keys and then by `order_by`.
+    Window { input: Box<Plan>, partition_by: Vec<Expr>, order_by: Vec<SortKey>, functions: Vec<WindowFunction> },
+    /// The rows of `input`, after materializing the rows of each of `ctes` under its id.
+    With { input: Box<Plan>, ctes: Vec<(usize, Plan)> },
+    /// The rows materialized under `id`, of the common table expression `name`: by the `With` above, or as
+    /// the working table of the `Recursive` above.
+    CteScan { name: String, id: usize },
+    /// The rows of `base`, then those of `step` for as long as it adds any, each round reading the rows of
+    /// the round before under `id`. With `distinct`, rows equal to one already added are dropped.
+    Recursive { id: usize, base: Box<Plan>, step: Box<Plan>, distinct: bool },
+    /// The rows of `workers` runs of `input` at once, each on a thread of its own, in no particular order.
+    /// The scans in `input` share out their tables' pages between the runs, and must all be `SeqScan`s.
+    Gather { input: Box<Plan>, workers: usize },
+    /// Under a `Gather`, the rows of `input` over every worker whose `keys` hash to this worker's partition;
+    /// elsewhere the rows of `input`.
+    Repartition { input: Box<Plan>, keys: Vec<Expr> },
+}
+impl Plan {
+    /// The tables the plan reads, each once, in the order it first reads them.
+    pub fn tables(&self) -> Vec<&str> {
+        let mut tables = Vec::new();
+        self.visit(&mut |plan| {
+            let (Plan::SeqScan { table, .. }
+            | Plan::IndexScan { table, .. }
+            | Plan::ClusteredScan { table, .. }
+            | Plan::PartitionScan { table, .. }) = plan
+            else {
+                return
+            };
+            if !tables.contains(&table.as_str()) {
+                tables.push(table.as_str())
+            }
+        });
+        tables
+    }
+
+    /// Replace every parameter in the plan's expressions with its value in `params`.
+    pub fn bind(&mut self, params: &[Value]) -> Result<(), ExecError> {
+        self.exprs_mut().into_iter().try_for_each(|e| e.bind(params))?;
+        self.inputs_mut().into_iter().try_for_each(|input| input.bind(params))
+    }
+
+    /// Replace every outer column of the plan with its value in `row`, leaving those of subqueries under
+    /// it, which are columns of their own input rows.
+    pub fn bind_outer(&mut self, row: &[Value]) {
+        self.exprs_mut().into_iter().for_each(|e| e.bind_outer(row));
+        self.outer_inputs_mut().into_iter().for_each(|input| input.bind_outer(row))
+    }
+
+    /// Renumber every outer column of the plan with `f`, leaving those of subqueries under it.
+    pub fn map_outer(&mut self, f: &impl Fn(usize) -> usize) {
+        self.exprs_mut().into_iter().for_each(|e| e.map_outer(f));
+        self.outer_inputs_mut().into_iter().for_each(|input| input.map_outer(f))
+    }
+
+    /// Whether the plan reads an outer column, not counting those of subqueries under it.
+    pub fn reads_outer(&self) -> bool {
+        self.exprs().into_iter().any(Expr::reads_outer) || self.outer_inputs().into_iter().any(Plan::reads_outer)
+    }
+
+    /// Build the operators for the plan, reading from the tables of `context` by name.
+    pub fn open<'a, 'store, S: Storage, T: Storage>(
+        &'a self,
+        context: &'a Context<'a, 'store, S, T>,
+    ) -> Result<Box<dyn Operator + 'a>, ExecError>
+    where
+        'store: 'a,
+    {
+        let table = |name: &String| context.tables.get(name).ok_or_else(|| ExecError::MissingTable(name.clone()));
+        let operator: Box<dyn Operator + 'a> = match self {
+            Plan::SeqScan { table: name, rid } => {
+                let scan = table(name)?.scan();
+                let scan = match &context.cancel {
+                    Some(cancel) => scan.cancel_with(cancel.clone()),
+                    None => scan,
+                };
+                let scan = match context.exchange.scan(self) {
+                    Some(next) => scan.shared(next),
+                    None => scan,
+                };
+                Box::new(SeqScan::new(scan, *rid))
+            }
+            Plan::IndexScan { table: name, index, key, low, high, rid } => {
+                Box::new(IndexScan::new(table(name)?, index, key, (low.as_ref(), high.as_ref()), *rid))
+            }
+            Plan::ClusteredScan { table: name, key, rid } => {
+                let table = context.clustered.get(name).ok_or_else(|| ExecError::MissingTable(name.clone()))?;
+                Box::new(ClusteredScan::new(table, key, *rid))
+            }
+            Plan::PartitionScan { table: name, column, low, high, rid } => {
+                let table = context.partitioned.get(name).ok_or_else(|| ExecError::MissingTable(name.clone()))?;
+                let range = (low.as_ref(), high.as_ref());
+                Box::new(PartitionScan::new(table, *column, range, *rid, context.cancel.clone()))
+            }
+            Plan::Values { rows } => Box::new(Values::new(rows)),
+            Plan::Filter { input, predicate } => Box::new(Filter::new(input.open(context)?, predicate)),
+            Plan::Project { input, exprs } => Box::new(Project::new(input.open(context)?, exprs)),
+            Plan::NestedLoopJoin { left, right, predicate, join } => {
+                Box::new(NestedLoopJoin::new(left.open(context)?, right, context, predicate.as_ref(), *join))
+            }
+            Plan::HashJoin { left, right, left_keys, right_keys, residual, join } => {
+                let (left, right) = (left.open(context)?, right.open(context)?);
+                let (residual, temp) = (residual.as_ref(), context.temp);
+                let (bytes, memory) = (context.memory_bytes, &context.memory);
+                Box::new(HashJoin::new(left, right, left_keys, right_keys, residual, *join, temp, bytes, memory))
+            }
+            Plan::StreamAggregate { input, group_by, aggregates } => {
+                Box::new(StreamAggregate::new(input.open(context)?, group_by, aggregates))
+            }
+            Plan::HashAggregate { input, group_by, aggregates } => {
+                let (temp, bytes, memory) = (context.temp, context.memory_bytes, &context.memory);
+                Box::new(HashAggregate::new(input.open(context)?, group_by, aggregates, temp, bytes, memory))
+            }
+            Plan::OrderBy { input, keys } => {
+                let (temp, memory) = (context.temp, &context.memory);
+                Box::new(OrderBy::new(input.open(context)?, keys, temp, context.memory_bytes, memory))
+            }
+            Plan::Limit { input, limit, offset } => {
+                Box::new(Limit::new(input.open(context)?, limit.as_ref(), offset.as_ref()))
+            }
+            Plan::Apply { input, subquery, kind } => {
+                Box::new(Apply::new(input.open(context)?, subquery, kind, context))
+            }
+            Plan::Window { input, partition_by, order_by, functions } => {
+                Box::new(Window::new(input.open(context)?, partition_by, order_by, functions))
+            }
+            Plan::With { input, ctes } => Box::new(With::new(input, ctes, context)),
+            Plan::CteScan { id, .. } => Box::new(CteScan::new(*id, context)),
+            Plan::Recursive { id, base, step, distinct } => {
+                Box::new(Recursive::new(base.open(context)?, *id, step, *distinct, context))
+            }
+            Plan::Gather { input, workers } => Box::new(Gather::new(input, *workers, context)),
+            Plan::Repartition { input, keys } => {
+                let endpoint = context.exchange.endpoint(self);
+                Box::new(Repartition::new(input.open(context)?, keys, endpoint))
+            }
+        };
+        let operator = match context.profile {
+            Some(profile) => profile.open(self, operator),
+            None => operator,
+        };
+        Ok(match &context.cancel {
+            Some(cancel) => Box::new(Cancellable { input: operator, cancel }),
+            None => operator,
+        })
+    }
+
+    /// The plan's nodes, each before its inputs and the left input before the right.
+    pub fn nodes(&self) -> Vec<&Plan> {
+        let mut nodes = Vec::new();
+        self.visit(&mut |plan| nodes.push(plan));
+        nodes
+    }
+
+    /// The plans whose rows this node reads, left first.
+    pub fn inputs(&self) -> Vec<&Plan> {
+        match self {
+            Plan::SeqScan { .. } | Plan::IndexScan { .. } | Plan::ClusteredScan { .. } | Plan::PartitionScan { .. } => {
+                Vec::new()
+            }
+            Plan::Values { .. } | Plan::CteScan { .. } => Vec::new(),
+            Plan::Filter { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::StreamAggregate { input, .. }
+            | Plan::HashAggregate { input, .. }
+            | Plan::OrderBy { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::Window { input, .. }
+            | Plan::Gather { input, .. }
+            | Plan::Repartition { input, .. } => vec![input],
+            Plan::NestedLoopJoin { left, right, .. } | Plan::HashJoin { left, right, .. } => vec![left, right],
+            Plan::Apply { input, subquery, .. } => vec![input, subquery],
+            Plan::With { input, ctes } => std::iter::once(&**input).chain(ctes.iter().map(|(_, cte)| cte)).collect(),
+            Plan::Recursive { base, step, .. } => vec![base, step],
+        }
+    }
+
+    /// The plans whose rows this node reads, left first, for rewriting them.
+    pub fn inputs_mut(&mut self) -> Vec<&mut Plan> {
+        match self {
+            Plan::SeqScan { .. } | Plan::IndexScan { .. } | Plan::ClusteredScan { .. } | Plan::PartitionScan { .. } => {
+                Vec::new()
+            }
+            Plan::Values { .. } | Plan::CteScan { .. } => Vec::new(),
+            Plan::Filter { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::StreamAggregate { input, .. }
+            | Plan::HashAggregate { input, .. }
+            | Plan::OrderBy { input, .. }
+            | Plan::Limit { input, .. }
+            | Plan::Window { input, .. }
+            | Plan::Gather { input, .. }
+            | Plan::Repartition { input, .. } => vec![input],
+            Plan::NestedLoopJoin { left, right, .. } | Plan::HashJoin { left, right, .. } => vec![left, right],
+            Plan::Apply { input, subquery, .. } => vec![input, subquery],
+            Plan::With { input, ctes } => {
+                std::iter::once(&mut **input).chain(ctes.iter_mut().map(|(_, cte)| cte)).collect()
+            }
+            Plan::Recursive { base, step, .. } => vec![base, step],
+        }
+    }
+
+    /// The inputs whose outer columns are the same as this node's: all but a subquery.
+    fn outer_inputs(&self) -> Vec<&Plan> {
+        match self {
+            Plan::Apply { input, .. } => vec![input],
+            plan => plan.inputs(),
+        }
+    }
+
+    fn outer_inputs_mut(&mut self) -> Vec<&mut Plan> {
+        match self {
+            Plan::Apply { input, .. } => vec![input],
+            plan => plan.inputs_mut(),
+        }
+    }
+
+    /// The node's own expressions, without those of its inputs.
+    fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Plan::SeqScan { .. } | Plan::With { .. } | Plan::CteScan { .. } | Plan::Recursive { .. } => Vec::new(),
+            Plan::Gather { .. } => Vec::new(),
+            Plan::IndexScan { key, low, high, .. } => key.iter().chain(bounded(low)).chain(bounded(high)).collect(),
+            Plan::ClusteredScan { key, .. } => key.iter().collect(),
+            Plan::PartitionScan { low, high, .. } => bounded(low).into_iter().chain(bounded(high)).collect(),
+            Plan::Values { rows } => rows.iter().flatten().collect(),
+            Plan::Filter { predicate, .. } => vec![predicate],
+            Plan::Project { exprs, .. } => exprs.iter().collect(),
+            Plan::NestedLoopJoin { predicate, .. } => predicate.iter().collect(),
+            Plan::HashJoin { left_keys, right_keys, residual, .. } => {
+                left_keys.iter().chain(right_keys).chain(residual).collect()
+            }
+            Plan::StreamAggregate { group_by, aggregates, .. } | Plan::HashAggregate { group_by, aggregates, .. } => {
+                group_by.iter().chain(aggregates.iter().filter_map(|a| a.arg.as_ref())).collect()
+            }
+            Plan::OrderBy { keys, .. } => keys.iter().map(|k| &k.expr).collect(),
+            Plan::Repartition { keys, .. } => keys.iter().collect(),
+            Plan::Limit { limit, offset, .. } => limit.iter().chain(offset).collect(),
+            Plan::Apply { kind: ApplyKind::In(expr), .. } => vec![expr],
+            Plan::Apply { .. } => Vec::new(),
+            Plan::Window { partition_by, order_by, functions, .. } => {
+                let args = functions.iter().filter_map(|f| match f {
+                    WindowFunction::Aggregate { aggregate, .. } => aggregate.arg.as_ref(),
+                    _ => None,
+                });
+                partition_by.iter().chain(order_by.iter().map(|k| &k.expr)).chain(args).collect()
+            }
+        }
+    }
+
+    fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Plan::SeqScan { .. } | Plan::With { .. } | Plan::CteScan { .. } | Plan::Recursive { .. } => Vec::new(),
+            Plan::Gather { .. } => Vec::new(),
+            Plan::IndexScan { key, low, high, .. } => {
+                key.iter_mut().chain(bounded_mut(low)).chain(bounded_mut(high)).collect()
+            }
+            Plan::ClusteredScan { key, .. } => key.iter_mut().collect(),
+            Plan::PartitionScan { low, high, .. } => bounded_mut(low).into_iter().chain(bounded_mut(high)).collect(),
+            Plan::Values { rows } => rows.iter_mut().flatten().collect(),
+            Plan::Filter { predicate, .. } => vec![predicate],
+            Plan::Project { exprs, .. } => exprs.iter_mut().collect(),
+            Plan::NestedLoopJoin { predicate, .. } => predicate.iter_mut().collect(),
+            Plan::HashJoin { left_keys, right_keys, residual, .. } => {
+                left_keys.iter_mut().chain(right_keys).chain(residual).collect()
+            }
+            Plan::StreamAggregate { group_by, aggregates, .. } | Plan::HashAggregate { group_by, aggregates, .. } => {
+                group_by.iter_mut().chain(aggregates.iter_mut().filter_map(|a| a.arg.as_mut())).collect()
+            }
+            Plan::OrderBy { keys, .. } => keys.iter_mut().map(|k| &mut k.expr).collect(),
+            Plan::Repartition { keys, .. } => keys.iter_mut().collect(),
+            Plan::Limit { limit, offset, .. } => limit.iter_mut().chain(offset).collect(),
+            Plan::Apply { kind: ApplyKind::In(expr), .. } => vec![expr],
+            Plan::Apply { .. } => Vec::new(),
+            Plan::Window { partition_by, order_by, functions, .. } => {
+                let args = functions.iter_mut().filter_map(|f| match f {
+                    WindowFunction::Aggregate { aggregate, .. } => aggregate.arg.as_mut(),
+                    _ => None,
+                });
+                partition_by.iter_mut().chain(order_by.iter_mut().map(|k| &mut k.expr)).chain(args).collect()
+            }
+        }
+    }
+
+    /// The node's operator and the table it reads, without its inputs or expressions.
+    pub fn label(&self) -> String {
+        match self {
+            Plan::SeqScan { table, .. } => format!("SeqScan on {table}"),
+            Plan::IndexScan { table, index, .. } => format!("IndexScan on {table} using {index}"),
+            Plan::ClusteredScan { table, .. } => format!("ClusteredScan on {table}"),
+            Plan::PartitionScan { table, low: Bound::Unbounded, high: Bound::Unbounded, .. } => {
+                format!("PartitionScan on {table}")
+            }
+            Plan::PartitionScan { table, .. } => format!("PartitionScan on {table} pruning partitions"),
+            Plan::Values { rows } => format!("Values ({} rows)", rows.len()),
+            Plan::Filter { .. } => "Filter".to_string(),
+            Plan::Project { .. } => "Project".to_string(),
+            Plan::NestedLoopJoin { join, .. } => format!("{}NestedLoopJoin", join.prefix()),
+            Plan::HashJoin { join, .. } => format!("{}HashJoin", join.prefix()),
+            Plan::StreamAggregate { .. } => "StreamAggregate".to_string(),
+            Plan::HashAggregate { .. } => "HashAggregate".to_string(),
+            Plan::OrderBy { .. } => "OrderBy".to_string(),
+            Plan::Limit { .. } => "Limit".to_string(),
+            Plan::Apply { kind, .. } => format!("{}Apply", kind.prefix()),
+            Plan::Window { .. } => "Window".to_string(),
+            Plan::With { .. } => "With".to_string(),
+            Plan::CteScan { name, .. } => format!("CteScan on {name}"),
+            Plan::Recursive { .. } => "Recursive".to_string(),
+            Plan::Gather { workers, .. } => format!("Gather ({workers} workers)"),
+            Plan::Repartition { .. } => "Repartition".to_string(),
+        }
+    }
+
+    /// A one-line outline of the plan: its operators and the tables they read, without expressions.
+    pub fn describe(&self) -> String {
+        match self {
+            Plan::SeqScan { table, .. } => format!("SeqScan({table})"),
+            Plan::IndexScan { table, index, .. } => format!("IndexScan({table}.{index})"),
+            Plan::ClusteredScan { table, .. } => format!("ClusteredScan({table})"),
+            Plan::PartitionScan { table, .. } => format!("PartitionScan({table})"),
+            Plan::Values { rows } => format!("Values({})", rows.len()),
+            Plan::Filter { input, .. } => format!("Filter({})", input.describe()),
+            Plan::Project { input, .. } => format!("Project({})", input.describe()),
+            Plan::NestedLoopJoin { left, right, join, .. } => {
+                format!("{}NestedLoopJoin({}, {})", join.prefix(), left.describe(), right.describe())
+            }
+            Plan::HashJoin { left, right, join, .. } => {
+                format!("{}HashJoin({}, {})", join.prefix(), left.describe(), right.describe())
+            }
+            Plan::StreamAggregate { input, .. } => format!("StreamAggregate({})", input.describe()),
+            Plan::HashAggregate { input, .. } => format!("HashAggregate({})", input.describe()),
+            Plan::OrderBy { input, .. } => format!("OrderBy({})", input.describe()),
+            Plan::Limit { input, .. } => format!("Limit({})", input.describe()),
+            Plan::Apply { input, subquery, kind } => {
+                format!("{}Apply({}, {})", kind.prefix(), input.describe(), subquery.describe())
+            }
+            Plan::Window { input, .. } => format!("Window({})", input.describe()),
+            Plan::With { input, ctes } => {
+                let ctes: Vec<_> = ctes.iter().map(|(_, cte)| cte.describe()).collect();
+                format!("With({}, {})", input.describe(), ctes.join(", "))
+            }
+            Plan::CteScan { name, .. } => format!("CteScan({name})"),
+            Plan::Recursive { base, step, .. } => format!("Recursive({}, {})", base.describe(), step.describe()),
+            Plan::Gather { input, .. } => format!("Gather({})", input.describe()),
+            Plan::Repartition { input, .. } => format!("Repartition({})", input.describe()),
+        }
+    }
+
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Plan)) {
+        f(self);
+        self.inputs().into_iter().for_each(|input| input.visit(f));
+    }
+}
+
+/// Pull every row out of `operator`.
+pub fn collect(operator: &mut dyn Operator) -> Result<Vec<Vec<Value>>, ExecError> {
+    let mut rows = Vec::new();
+    while let Some(row) = operator.next()? {
+        rows.push(row)
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::sql::ast::BinaryOp;
+    use crate::storage::TestStorage;
+    use crate::table::Table;
+    use crate::temp::TempSpace;
+    use crate::tuple::{Column, ColumnType, Schema, Value};
+
+    use super::{collect, Context, ExecError, Expr, Plan};
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+    }
+
+    #[test]
+    fn test_scan_filter_project() -> Result<(), ExecError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let schema = Schema::new(vec![Column::new(ColumnType::Int), Column::nullable(ColumnType::Int)]);
+        let mut table = Table::create(&store, allocator, schema)?;
+        for i in 0..100 {
+            let score = if i % 10 == 0 { Value::Null } else { Value::Int(i % 7) };
+            table.insert(&[Value::Int(i), score])?;
+        }
+        let temp = TempSpace::new(TestStorage::new());
+        let mut context = Context::new(&temp);
+        context.tables.insert("t".to_string(), table);
+
+        // score < 3 OR id >= $1: null scores only pass through the right side.
+        let predicate = binary(
+            BinaryOp::Or,
+            binary(BinaryOp::Lt, Expr::Column(1), Expr::Literal(Value::Int(3))),
+            binary(BinaryOp::GtEq, Expr::Column(0), Expr::Parameter(0)),
+        );
+        let scan = Box::new(Plan::SeqScan { table: "t".to_string(), rid: false });
+        let filter = Box::new(Plan::Filter { input: scan, predicate });
+        let mut plan = Plan::Project { input: filter, exprs: vec![Expr::Column(0)] };
+        assert_eq!(plan.tables(), vec!["t"]);
+        assert_eq!(collect(&mut *plan.open(&context)?), Err(ExecError::MissingParameter(0)));
+
+        plan.bind(&[Value::Int(95)])?;
+        let rows = collect(&mut *plan.open(&context)?)?;
+        let expected = (0..100).filter(|i| (i % 10 != 0 && i % 7 < 3) || *i >= 95).map(|i| vec![Value::Int(i)]);
+        assert_eq!(rows, expected.collect::<Vec<_>>());
+
+        let scan = Box::new(Plan::SeqScan { table: "t".to_string(), rid: false });
+        let mismatch = Plan::Filter { input: scan, predicate: Expr::Column(0) };
+        assert_eq!(collect(&mut *mismatch.open(&context)?), Err(ExecError::TypeMismatch(Value::Int(0))));
+        Ok(())
+    }
+}
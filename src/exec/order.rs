@@ -0,0 +1,243 @@
+//! Sorting rows for `ORDER BY`, on the external sorter so that results bigger than the memory budget
+//! sort through temporary space. The rows a sort buffers count against the query's budget as well, and
+//! it writes them out whenever the query has no room for more.
+//!
+//! Each row is sorted by a byte string encoding its sort keys, built from the order-preserving key
+//! encoding of indexes: the bytes of a descending key are inverted, which reverses their order because
+//! that encoding is self-delimiting, and a marker byte ahead of each key puts nulls before or after every
+//! other value whichever way the key sorts. Values of different types in one key order by type, as they
+//! do in `Value`'s order.
+use std::collections::BinaryHeap;
+
+use crate::memory::{MemoryBudget, Reservation};
+use crate::sort::{ExternalSorter, SortOptions, Sorted};
+use crate::storage::Storage;
+use crate::table::key;
+use crate::temp::TempSpace;
+use crate::tuple::Value;
+
+use super::spill::row_bytes;
+use super::{ExecError, Expr, Operator};
+
+const NULL_FIRST: u8 = 0;
+const PRESENT: u8 = 1;
+const NULL_LAST: u8 = 2;
+
+/// A row paired with the encoding of its sort keys, as the sorter holds it.
+type Keyed = (Vec<u8>, Vec<Value>);
+
+/// One key of an `ORDER BY`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub expr: Expr,
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+/// The bytes rows are sorted by: `keys` evaluated over `row`, encoded so that they compare bytewise in the
+/// order the keys ask for.
+pub(crate) fn sort_key(keys: &[SortKey], row: &[Value]) -> Result<Vec<u8>, ExecError> {
+    let mut bytes = Vec::new();
+    for key in keys {
+        let value = key.expr.eval(row)?;
+        if value.is_null() {
+            bytes.push(if key.nulls_first { NULL_FIRST } else { NULL_LAST });
+            continue
+        }
+        bytes.push(PRESENT);
+        // The encoding starts with a null marker of its own, which the byte above replaces.
+        let encoded = &key::encode(std::slice::from_ref(&value))[1..];
+        match key.descending {
+            true => bytes.extend(encoded.iter().map(|b| !b)),
+            false => bytes.extend_from_slice(encoded),
+        }
+    }
+    Ok(bytes)
+}
+
+/// The sorted rows, from the external sorter or, for a top-N sort that fit the budget, from memory.
+enum Output<'a, T: Storage> {
+    Sorted(Sorted<'a, T, Keyed>),
+    Memory(std::vec::IntoIter<Keyed>),
+}
+
+/// The input rows in the order of `keys`.
+///
+/// Under a limit of N rows only the first N are kept, in a heap that drops the greatest row whenever it
+/// grows past N, so the rest of the input never needs sorting. Should the N rows outgrow the memory budget,
+/// or the query's, the sort goes to the external sorter after all.
+pub struct OrderBy<'a, T: Storage> {
+    input: Option<Box<dyn Operator + 'a>>,
+    keys: &'a [SortKey],
+    temp: &'a TempSpace<T>,
+    memory_bytes: usize,
+    memory: &'a MemoryBudget,
+    /// What the rows of a top-N sort hold of the query's budget.
+    reserved: Reservation<'a>,
+    top: Option<usize>,
+    output: Option<Output<'a, T>>,
+}
+impl<'a, T: Storage> OrderBy<'a, T> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        keys: &'a [SortKey],
+        temp: &'a TempSpace<T>,
+        memory_bytes: usize,
+        memory: &'a MemoryBudget,
+    ) -> OrderBy<'a, T> {
+        let reserved = memory.reservation();
+        OrderBy { input: Some(input), keys, temp, memory_bytes, memory, reserved, top: None, output: None }
+    }
+
+    /// Whether the rows were sorted without writing any to temporary space. False until the first row.
+    pub fn in_memory(&self) -> bool {
+        match &self.output {
+            Some(Output::Sorted(sorted)) => sorted.in_memory(),
+            Some(Output::Memory(_)) => true,
+            None => false,
+        }
+    }
+
+    fn sort(&mut self, input: &mut dyn Operator) -> Result<Output<'a, T>, ExecError> {
+        let options = SortOptions { memory_bytes: self.memory_bytes, ..SortOptions::default() };
+        let mut sorter = ExternalSorter::with_budget(self.temp, options, self.memory)?;
+        if let Some(top) = self.top {
+            if top == 0 {
+                input.stop();
+                return Ok(Output::Memory(Vec::new().into_iter()))
+            }
+            let mut heap = BinaryHeap::new();
+            loop {
+                let Some(row) = input.next()? else { return Ok(Output::Memory(heap.into_sorted_vec().into_iter())) };
+                let keyed = (sort_key(self.keys, &row)?, row);
+                let fits = self.reserved.try_grow(keyed_bytes(&keyed));
+                heap.push(keyed);
+                if heap.len() > top {
+                    self.reserved.shrink(heap.pop().as_ref().map_or(0, keyed_bytes));
+                }
+                if !fits || self.reserved.bytes() > self.memory_bytes {
+                    self.reserved.clear();
+                    for keyed in heap {
+                        sorter.push(keyed)?;
+                    }
+                    break
+                }
+            }
+        }
+        while let Some(row) = input.next()? {
+            sorter.push((sort_key(self.keys, &row)?, row))?;
+        }
+        Ok(Output::Sorted(sorter.finish()?))
+    }
+}
+impl<T: Storage> Operator for OrderBy<'_, T> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if let Some(mut input) = self.input.take() {
+            self.output = Some(self.sort(input.as_mut())?);
+        }
+        let next = match &mut self.output {
+            Some(Output::Sorted(sorted)) => sorted.next().transpose()?,
+            Some(Output::Memory(rows)) => rows.next(),
+            None => None,
+        };
+        Ok(next.map(|(_, row)| row))
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.top = Some(self.top.map_or(rows, |top| top.min(rows)));
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut input) = self.input.take() {
+            input.stop();
+        }
+        self.output = None;
+        self.reserved.clear();
+    }
+}
+
+fn keyed_bytes((key, row): &Keyed) -> usize {
+    key.len() + row_bytes(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{collect, ExecError, Expr, Operator, Values};
+    use crate::memory::MemoryBudget;
+    use crate::storage::TestStorage;
+    use crate::temp::TempSpace;
+    use crate::tuple::Value;
+
+    use super::{OrderBy, SortKey};
+
+    #[test]
+    fn test_order_by_keys_and_nulls() -> Result<(), ExecError> {
+        let temp = TempSpace::new(TestStorage::new());
+        let text = |s: &str| Value::Text(s.to_string());
+        let values = [
+            (Value::Int(1), text("b")),
+            (Value::Null, text("a")),
+            (Value::Int(-3), Value::Null),
+            (Value::Int(1), text("ab")),
+            (Value::Int(1), text("a")),
+        ];
+        let literals = |(a, b): &(Value, Value)| vec![Expr::Literal(a.clone()), Expr::Literal(b.clone())];
+        let rows: Vec<Vec<Expr>> = values.iter().map(literals).collect();
+        let key = |column, descending, nulls_first| SortKey { expr: Expr::Column(column), descending, nulls_first };
+        let memory = MemoryBudget::new(usize::MAX);
+        let sort = |keys: &[SortKey], memory_bytes| -> Result<_, ExecError> {
+            let mut order = OrderBy::new(Box::new(Values::new(&rows)), keys, &temp, memory_bytes, &memory);
+            let sorted = collect(&mut order)?;
+            Ok((sorted, order.in_memory()))
+        };
+        let row = |i: usize| vec![values[i].0.clone(), values[i].1.clone()];
+        let expected = |order: [usize; 5]| order.map(row).to_vec();
+
+        let keys = [key(0, false, false), key(1, true, true)];
+        let expected_order = expected([2, 0, 3, 4, 1]);
+        assert_eq!(sort(&keys, 1 << 20)?, (expected_order.clone(), true));
+        // A budget of one byte writes every row to a run of its own.
+        assert_eq!(sort(&keys, 1)?, (expected_order, false));
+        let keys = [key(0, true, true), key(1, false, false)];
+        assert_eq!(sort(&keys, 1 << 20)?.0, expected([1, 4, 3, 0, 2]));
+        assert_eq!(temp.pages_in_use(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_n_sort_keeps_to_memory() -> Result<(), ExecError> {
+        let temp = TempSpace::new(TestStorage::new());
+        let rows: Vec<Vec<Expr>> = (0..1000).map(|i| vec![Expr::Literal(Value::Int(i * 7919 % 1000))]).collect();
+        let keys = [SortKey { expr: Expr::Column(0), descending: true, nulls_first: false }];
+        let (memory_bytes, memory) = (4096, MemoryBudget::new(usize::MAX));
+
+        let mut full = OrderBy::new(Box::new(Values::new(&rows)), &keys, &temp, memory_bytes, &memory);
+        assert_eq!(collect(&mut full)?.len(), 1000);
+        assert!(!full.in_memory());
+
+        let mut top = OrderBy::new(Box::new(Values::new(&rows)), &keys, &temp, memory_bytes, &memory);
+        top.limit(3);
+        let expected: Vec<_> = [999, 998, 997].into_iter().map(|v| vec![Value::Int(v)]).collect();
+        assert_eq!(collect(&mut top)?, expected);
+        assert!(top.in_memory());
+
+        // Past the budget the kept rows go to the external sorter.
+        let mut top = OrderBy::new(Box::new(Values::new(&rows)), &keys, &temp, memory_bytes, &memory);
+        top.limit(500);
+        let sorted = std::iter::from_fn(|| top.next().transpose()).take(500).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!((sorted[0].clone(), sorted[499].clone()), (vec![Value::Int(999)], vec![Value::Int(500)]));
+        assert!(!top.in_memory());
+        drop(top);
+
+        // So does all of the input once the query's budget has no room for more.
+        let small = MemoryBudget::new(1024);
+        let mut top = OrderBy::new(Box::new(Values::new(&rows)), &keys, &temp, usize::MAX, &small);
+        top.limit(500);
+        let sorted = std::iter::from_fn(|| top.next().transpose()).take(500).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(sorted[499], vec![Value::Int(500)]);
+        assert!(!top.in_memory());
+        drop(top);
+        assert_eq!((small.used(), temp.pages_in_use()), (0, 0));
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+//! Counting what each operator of a plan does as it runs, for `EXPLAIN ANALYZE`. A `Profile` made for a
+//! plan and put in the `Context` it is opened with wraps every operator `Plan::open` builds in one that
+//! counts its rows and the time spent in it.
+//!
+//! Nodes are told apart by where they are in memory. A correlated subquery runs as a copy of its plan
+//! bound to each outer row, and the copy's nodes are counted as the nodes of the plan it copies. The nodes
+//! under a `Gather` run once on each of its workers, which count into the same profile.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::tuple::Value;
+
+use super::{ExecError, Operator, Plan};
+
+/// What one node of a plan did, over every time it was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NodeStats {
+    /// Rows it output, over all its loops.
+    pub rows: u64,
+    /// Times it was opened: once, unless it is the right input of a nested loop join.
+    pub loops: u64,
+    /// Time spent pulling its rows, its inputs' time included.
+    pub time: Duration,
+}
+
+pub struct Profile {
+    /// The number of each node, in the order `Plan::nodes` lists them, by its address.
+    nodes: Mutex<HashMap<usize, usize>>,
+    stats: Mutex<Vec<NodeStats>>,
+}
+impl Profile {
+    pub fn new(plan: &Plan) -> Profile {
+        let nodes: HashMap<_, _> = plan.nodes().into_iter().enumerate().map(|(i, n)| (address(n), i)).collect();
+        let stats = Mutex::new(vec![NodeStats::default(); nodes.len()]);
+        Profile { nodes: Mutex::new(nodes), stats }
+    }
+
+    /// Count the nodes of `copy` as those of `original`, which it is a copy of.
+    pub(super) fn alias(&self, copy: &Plan, original: &Plan) {
+        let mut nodes = self.nodes.lock().unwrap();
+        for (copy, original) in copy.nodes().into_iter().zip(original.nodes()) {
+            if let Some(&node) = nodes.get(&address(original)) {
+                nodes.insert(address(copy), node);
+            }
+        }
+    }
+
+    /// What each node did, in the order `Plan::nodes` lists them.
+    pub fn stats(&self) -> Vec<NodeStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Count an opening of the node `plan`, and wrap its operator to count its rows.
+    pub(super) fn open<'a>(&'a self, plan: &Plan, operator: Box<dyn Operator + 'a>) -> Box<dyn Operator + 'a> {
+        let Some(&node) = self.nodes.lock().unwrap().get(&address(plan)) else { return operator };
+        self.stats.lock().unwrap()[node].loops += 1;
+        Box::new(Profiled { input: operator, profile: self, node })
+    }
+}
+
+pub(super) fn address(plan: &Plan) -> usize {
+    plan as *const Plan as usize
+}
+
+struct Profiled<'a> {
+    input: Box<dyn Operator + 'a>,
+    profile: &'a Profile,
+    node: usize,
+}
+impl Operator for Profiled<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        let start = Instant::now();
+        let row = self.input.next();
+        let mut stats = self.profile.stats.lock().unwrap();
+        let stats = &mut stats[self.node];
+        stats.time += start.elapsed();
+        if let Ok(Some(_)) = row {
+            stats.rows += 1;
+        }
+        row
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.input.limit(rows)
+    }
+
+    fn stop(&mut self) {
+        self.input.stop()
+    }
+}
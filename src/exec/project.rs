@@ -0,0 +1,29 @@
+//! Reshaping rows: each output row is made from the input row it stands for.
+use crate::tuple::Value;
+
+use super::{ExecError, Expr, Operator};
+
+/// One output row per input row, made of `exprs` evaluated over it.
+pub struct Project<'a> {
+    input: Box<dyn Operator + 'a>,
+    exprs: &'a [Expr],
+}
+impl<'a> Project<'a> {
+    pub fn new(input: Box<dyn Operator + 'a>, exprs: &'a [Expr]) -> Project<'a> {
+        Project { input, exprs }
+    }
+}
+impl Operator for Project<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        let Some(row) = self.input.next()? else { return Ok(None) };
+        Ok(Some(self.exprs.iter().map(|e| e.eval(&row)).collect::<Result<_, _>>()?))
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.input.limit(rows)
+    }
+
+    fn stop(&mut self) {
+        self.input.stop()
+    }
+}
@@ -0,0 +1,281 @@
+//! Operators at the leaves of a plan, which read rows from tables or make them from nothing.
+use std::ops::Bound;
+
+use crate::cancel::CancelHandle;
+use crate::datetime::MICROS_PER_DAY;
+use crate::decimal::Decimal;
+use crate::heap::Rid;
+use crate::storage::Storage;
+use crate::table::{clustered, ClusteredTable, PartitionRid, PartitionedTable, Predicate, Table, TableScan};
+use crate::tuple::{ColumnType, Value};
+
+use super::{ExecError, Expr, Operator};
+
+/// Every row of a heap table, in heap order, with its rid after its columns if `rid`.
+pub struct SeqScan<'a, 'store, S: Storage> {
+    scan: Option<TableScan<'a, 'store, S>>,
+    rid: bool,
+}
+impl<'a, 'store, S: Storage> SeqScan<'a, 'store, S> {
+    pub fn new(scan: TableScan<'a, 'store, S>, rid: bool) -> SeqScan<'a, 'store, S> {
+        SeqScan { scan: Some(scan), rid }
+    }
+}
+impl<S: Storage> Operator for SeqScan<'_, '_, S> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        let Some(scan) = &mut self.scan else { return Ok(None) };
+        Ok(scan.next().transpose()?.map(|(rid, row)| with_rid(row, rid, self.rid)))
+    }
+
+    fn stop(&mut self) {
+        self.scan = None;
+    }
+}
+
+/// The rows of a table whose leading indexed columns equal a key, and whose next one is within a range
+/// if it is bounded, in index order. The key's and bounds' expressions are evaluated when the first row is
+/// pulled, and each value converted to its key part's type; a null in the key or a bound matches nothing,
+/// as a comparison with a null never holds. A bound no value of its key part's type equals, such as a
+/// fraction for an integer column, is left open, so the range may hold rows it should not and the plan
+/// must filter the rows scanned by it as well. Under a limit it looks up no more rids than will be pulled.
+/// Each row is followed by its rid if `rid`.
+pub struct IndexScan<'a, 'store, S: Storage> {
+    table: &'a Table<'store, S>,
+    index: &'a str,
+    key: &'a [Expr],
+    range: (Bound<&'a Expr>, Bound<&'a Expr>),
+    rid: bool,
+    limit: usize,
+    rids: Option<std::vec::IntoIter<Rid>>,
+}
+impl<'a, 'store, S: Storage> IndexScan<'a, 'store, S> {
+    pub fn new(
+        table: &'a Table<'store, S>,
+        index: &'a str,
+        key: &'a [Expr],
+        range: (Bound<&'a Expr>, Bound<&'a Expr>),
+        rid: bool,
+    ) -> IndexScan<'a, 'store, S> {
+        IndexScan { table, index, key, range, rid, limit: usize::MAX, rids: None }
+    }
+
+    fn lookup(&self) -> Result<Vec<Rid>, ExecError> {
+        let index = self.table.indexes().iter().find(|i| i.name() == self.index);
+        let types = index.map(|i| i.key_types(self.table.schema()));
+        let column_type = |i: usize| types.as_ref().and_then(|t| t.get(i)).copied();
+        let mut values = Vec::with_capacity(self.key.len());
+        for (i, expr) in self.key.iter().enumerate() {
+            let value = expr.eval(&[])?;
+            let value = match column_type(i) {
+                Some(column_type) => coerce(value, column_type)?,
+                None => Some(value),
+            };
+            let Some(value) = value else { return Ok(Vec::new()) };
+            values.push(value);
+        }
+        let bound = |bound| evaluate_bound(bound, column_type(self.key.len()));
+        let (Some(low), Some(high)) = (bound(self.range.0)?, bound(self.range.1)?) else { return Ok(Vec::new()) };
+        Ok(self.table.lookup_range(self.index, &values, (low.as_ref(), high.as_ref()), self.limit)?)
+    }
+}
+impl<S: Storage> Operator for IndexScan<'_, '_, S> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if self.rids.is_none() {
+            self.rids = Some(self.lookup()?.into_iter());
+        }
+        let Some(rid) = self.rids.as_mut().and_then(|rids| rids.next()) else { return Ok(None) };
+        Ok(Some(with_rid(self.table.get(&rid)?, rid, self.rid)))
+    }
+
+    fn limit(&mut self, rows: usize) {
+        self.limit = self.limit.min(rows);
+    }
+
+    fn stop(&mut self) {
+        self.rids = Some(Vec::new().into_iter());
+    }
+}
+
+/// The rows in the partitions of a partitioned table that may hold rows whose partition column is within a
+/// range, partition by partition. The bounds are evaluated when the first row is pulled and converted to the
+/// column's type as an `IndexScan`'s are, and the partitions picked by the table's `prune`: a null bound
+/// picks none, and two bounds including the same value pick by equality, which is all that prunes hash
+/// partitions. The rows are not checked against the bounds, so the plan must filter them as well. Each row
+/// is followed by its partition rid if `rid`.
+pub struct PartitionScan<'a, 'store, S: Storage> {
+    table: &'a PartitionedTable<'store, S>,
+    column: usize,
+    range: (Bound<&'a Expr>, Bound<&'a Expr>),
+    rid: bool,
+    cancel: Option<CancelHandle>,
+    partitions: Option<std::vec::IntoIter<u32>>,
+    current: Option<(u32, TableScan<'a, 'store, S>)>,
+}
+impl<'a, 'store, S: Storage> PartitionScan<'a, 'store, S> {
+    pub fn new(
+        table: &'a PartitionedTable<'store, S>,
+        column: usize,
+        range: (Bound<&'a Expr>, Bound<&'a Expr>),
+        rid: bool,
+        cancel: Option<CancelHandle>,
+    ) -> PartitionScan<'a, 'store, S> {
+        PartitionScan { table, column, range, rid, cancel, partitions: None, current: None }
+    }
+
+    fn prune(&self) -> Result<Vec<u32>, ExecError> {
+        let column_type = Some(self.table.schema().column_type(self.column));
+        let (low, high) = (evaluate_bound(self.range.0, column_type)?, evaluate_bound(self.range.1, column_type)?);
+        let (Some(low), Some(high)) = (low, high) else { return Ok(Vec::new()) };
+        let predicate = match (low, high) {
+            (Bound::Included(low), Bound::Included(high)) if low == high => Predicate::Eq(low),
+            (low, high) => Predicate::Range(low, high),
+        };
+        Ok(self.table.prune(self.column, &predicate))
+    }
+}
+impl<S: Storage> Operator for PartitionScan<'_, '_, S> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if self.partitions.is_none() {
+            self.partitions = Some(self.prune()?.into_iter());
+        }
+        loop {
+            if let Some((partition, scan)) = &mut self.current {
+                if let Some((rid, mut row)) = scan.next().transpose()? {
+                    if self.rid {
+                        row.push(Value::Bytes(PartitionRid { partition: *partition, rid }.to_bytes().to_vec()));
+                    }
+                    return Ok(Some(row))
+                }
+            }
+            let Some(id) = self.partitions.as_mut().and_then(|ids| ids.next()) else {
+                self.current = None;
+                return Ok(None)
+            };
+            let scan = self.table.partition(id).expect("pruning names partitions of the table").scan();
+            let scan = match &self.cancel {
+                Some(cancel) => scan.cancel_with(cancel.clone()),
+                None => scan,
+            };
+            self.current = Some((id, scan));
+        }
+    }
+
+    fn stop(&mut self) {
+        (self.partitions, self.current) = (Some(Vec::new().into_iter()), None);
+    }
+}
+
+/// The rows of a clustered table whose leading primary key columns equal a key, in primary key order. The
+/// key's expressions are evaluated when the first row is pulled and each value converted to its column's
+/// type; a null, or a value no value of the column's type equals, matches nothing. Each row is followed by
+/// the encoding of its primary key if `rid`, which is what identifies a row of a clustered table.
+pub struct ClusteredScan<'a, 'store, S: Storage> {
+    table: &'a ClusteredTable<'store, S>,
+    key: &'a [Expr],
+    rid: bool,
+    scan: Option<Option<clustered::ClusteredScan<'a, 'store, S>>>,
+}
+impl<'a, 'store, S: Storage> ClusteredScan<'a, 'store, S> {
+    pub fn new(table: &'a ClusteredTable<'store, S>, key: &'a [Expr], rid: bool) -> ClusteredScan<'a, 'store, S> {
+        ClusteredScan { table, key, rid, scan: None }
+    }
+
+    fn start(&self) -> Result<Option<clustered::ClusteredScan<'a, 'store, S>>, ExecError> {
+        let mut values = Vec::with_capacity(self.key.len());
+        for (expr, column) in self.key.iter().zip(self.table.primary_key()) {
+            let Some(value) = coerce(expr.eval(&[])?, self.table.schema().column_type(*column))? else {
+                return Ok(None)
+            };
+            values.push(value);
+        }
+        Ok(Some(self.table.scan_prefix(&values)?))
+    }
+}
+impl<S: Storage> Operator for ClusteredScan<'_, '_, S> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        if self.scan.is_none() {
+            self.scan = Some(self.start()?);
+        }
+        let Some(Some(scan)) = &mut self.scan else { return Ok(None) };
+        let Some(mut row) = scan.next().transpose()? else { return Ok(None) };
+        if self.rid {
+            row.push(Value::Bytes(self.table.key(&row)));
+        }
+        Ok(Some(row))
+    }
+
+    fn stop(&mut self) {
+        self.scan = Some(None);
+    }
+}
+
+/// `row`, followed by `rid` as bytes if `with`.
+fn with_rid(mut row: Vec<Value>, rid: Rid, with: bool) -> Vec<Value> {
+    if with {
+        row.push(Value::Bytes(rid.to_bytes().to_vec()));
+    }
+    row
+}
+
+/// The value of `bound`'s expression as a bound on a key part of `column_type`: `None` if the value is null,
+/// so nothing is within the bound, and unbounded if no value of the type equals it.
+fn evaluate_bound(bound: Bound<&Expr>, column_type: Option<ColumnType>) -> Result<Option<Bound<Value>>, ExecError> {
+    let (Bound::Included(expr) | Bound::Excluded(expr)) = bound else { return Ok(Some(Bound::Unbounded)) };
+    let value = expr.eval(&[])?;
+    if value.is_null() {
+        return Ok(None)
+    }
+    let value = match column_type {
+        Some(column_type) => coerce(value, column_type)?,
+        None => Some(value),
+    };
+    Ok(Some(match (value, bound) {
+        (Some(value), Bound::Included(_)) => Bound::Included(value),
+        (Some(value), _) => Bound::Excluded(value),
+        (None, _) => Bound::Unbounded,
+    }))
+}
+
+/// `value` as a value of type `to` equal to it, or `None` if no value of that type can equal it.
+fn coerce(value: Value, to: ColumnType) -> Result<Option<Value>, ExecError> {
+    match (value, to) {
+        (Value::Null, _) => Ok(None),
+        (Value::Int(v), ColumnType::Float) => Ok(Some(Value::Float(v as f64))),
+        (Value::Float(v), ColumnType::Int) => {
+            let exact = v.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(&v);
+            Ok(exact.then_some(Value::Int(v as i64)))
+        }
+        (Value::Date(v), ColumnType::Timestamp) => Ok(Some(Value::Timestamp(v as i64 * MICROS_PER_DAY))),
+        (Value::Date(v), ColumnType::TimestampTz) => Ok(Some(Value::TimestampTz(v as i64 * MICROS_PER_DAY))),
+        (Value::Timestamp(v), ColumnType::TimestampTz) => Ok(Some(Value::TimestampTz(v))),
+        (Value::TimestampTz(v), ColumnType::Timestamp) => Ok(Some(Value::Timestamp(v))),
+        (Value::Int(v), ColumnType::Decimal { .. }) => Ok(Some(Value::Decimal(Decimal::from(v)))),
+        (Value::Float(v), ColumnType::Decimal { .. }) => Ok(Decimal::from_f64(v).map(Value::Decimal)),
+        (Value::Decimal(v), ColumnType::Int) => {
+            Ok(v.to_i64().filter(|i| Decimal::from(*i) == v).map(Value::Int))
+        }
+        (Value::Decimal(v), ColumnType::Float) => Ok(Some(Value::Float(v.to_f64()))),
+        (Value::Timestamp(v) | Value::TimestampTz(v), ColumnType::Date) => {
+            let days = i32::try_from(v / MICROS_PER_DAY).ok();
+            Ok(days.filter(|_| v % MICROS_PER_DAY == 0).map(Value::Date))
+        }
+        (value, to) if value.column_type().is_some_and(|t| t.same_kind(to)) => Ok(Some(value)),
+        (value, _) => Err(ExecError::TypeMismatch(value)),
+    }
+}
+
+/// Rows of expressions that read no columns, evaluated as they are pulled.
+pub struct Values<'a> {
+    rows: std::slice::Iter<'a, Vec<Expr>>,
+}
+impl<'a> Values<'a> {
+    pub fn new(rows: &'a [Vec<Expr>]) -> Values<'a> {
+        Values { rows: rows.iter() }
+    }
+}
+impl Operator for Values<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        let Some(row) = self.rows.next() else { return Ok(None) };
+        Ok(Some(row.iter().map(|e| e.eval(&[])).collect::<Result<_, _>>()?))
+    }
+}
@@ -0,0 +1,109 @@
+//! Rows in temporary space. Operators that spill write whole rows to runs, each value with a tag byte
+//! saying its type, since a run has no schema to decode against: null is 0 and every other type one more
+//! than its stored-schema tag, followed by the same data the row format keeps for it.
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::datetime::Interval;
+use crate::decimal::Decimal;
+use crate::json::Json;
+use crate::sort::Spill;
+use crate::tuple::{ColumnType, Value};
+use crate::varint;
+
+impl Spill for Vec<Value> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for value in self {
+            out.push(value.column_type().map_or(0, |t| t.tag() + 1));
+            match value {
+                Value::Null => {}
+                Value::Int(v) => varint::write_i64(out, *v),
+                Value::Float(v) => out.extend_from_slice(&v.to_le_bytes()),
+                Value::Bool(v) => out.push(*v as u8),
+                Value::Bytes(v) => varint::write_prefixed(out, v),
+                Value::Text(v) => varint::write_prefixed(out, v.as_bytes()),
+                Value::Date(v) => varint::write_i64(out, *v as i64),
+                Value::Time(v) | Value::Timestamp(v) | Value::TimestampTz(v) => varint::write_i64(out, *v),
+                Value::Interval(v) => {
+                    varint::write_i64(out, v.months as i64);
+                    varint::write_i64(out, v.micros);
+                }
+                Value::Decimal(v) => v.write(out),
+                Value::Json(v) => varint::write_prefixed(out, v.as_bytes()),
+            }
+        }
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<Self> {
+        let mut row = Vec::new();
+        while let Some((&tag, rest)) = bytes.split_first() {
+            bytes = rest;
+            let (value, len) = match tag.checked_sub(1).map(ColumnType::from_tag) {
+                None => (Value::Null, 0),
+                Some(None) => return None,
+                Some(Some(ColumnType::Int)) => varint::read_i64(bytes).map(|(v, len)| (Value::Int(v), len))?,
+                Some(Some(ColumnType::Float)) => {
+                    (Value::Float(f64::from_le_bytes(bytes.get(..8)?.try_into().ok()?)), 8)
+                }
+                Some(Some(ColumnType::Bool)) => (Value::Bool(*bytes.first()? != 0), 1),
+                Some(Some(ColumnType::Bytes)) => {
+                    let (v, len) = varint::read_prefixed(bytes)?;
+                    (Value::Bytes(v.to_vec()), len)
+                }
+                Some(Some(ColumnType::Text)) => {
+                    let (v, len) = varint::read_prefixed(bytes)?;
+                    (Value::Text(String::from_utf8(v.to_vec()).ok()?), len)
+                }
+                Some(Some(ColumnType::Date)) => {
+                    let (v, len) = varint::read_i64(bytes)?;
+                    (Value::Date(i32::try_from(v).ok()?), len)
+                }
+                Some(Some(ColumnType::Time)) => varint::read_i64(bytes).map(|(v, len)| (Value::Time(v), len))?,
+                Some(Some(ColumnType::Timestamp)) => {
+                    varint::read_i64(bytes).map(|(v, len)| (Value::Timestamp(v), len))?
+                }
+                Some(Some(ColumnType::TimestampTz)) => {
+                    varint::read_i64(bytes).map(|(v, len)| (Value::TimestampTz(v), len))?
+                }
+                Some(Some(ColumnType::Interval)) => {
+                    let (months, len) = varint::read_i64(bytes)?;
+                    let (micros, end) = varint::read_i64(&bytes[len..])?;
+                    (Value::Interval(Interval { months: i32::try_from(months).ok()?, micros }), len + end)
+                }
+                Some(Some(ColumnType::Decimal { .. })) => {
+                    Decimal::read(bytes).map(|(v, len)| (Value::Decimal(v), len))?
+                }
+                Some(Some(ColumnType::Json)) => {
+                    let (v, len) = varint::read_prefixed(bytes)?;
+                    (Value::Json(Json::from_bytes(v)?), len)
+                }
+            };
+            bytes = &bytes[len..];
+            row.push(value);
+        }
+        Some(row)
+    }
+}
+
+/// Partitions an operator that hashes its rows splits them into when they outgrow its memory budget.
+pub(super) const PARTITIONS: usize = 16;
+
+/// The partition of the rows whose key is `key`.
+pub(super) fn partition(key: &[Value]) -> usize {
+    key_hash(key) as usize % PARTITIONS
+}
+
+pub(super) fn key_hash(key: &[Value]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Roughly the bytes `row` takes in memory, for operators keeping to a budget.
+pub(crate) fn row_bytes(row: &[Value]) -> usize {
+    let heap: usize = row.iter().map(|v| match v {
+        Value::Bytes(v) => v.len(),
+        Value::Text(v) => v.len(),
+        _ => 0,
+    }).sum();
+    std::mem::size_of::<Vec<Value>>() + std::mem::size_of_val(row) + heap
+}
@@ -0,0 +1,239 @@
+//! Window functions: values computed for each row from the rows of its partition, the input rows with
+//! equal partition keys, rather than from a group that replaces them.
+//!
+//! `Window` needs its input sorted by the partition keys and then the window's `ORDER BY`, as an `OrderBy`
+//! under it sorts it, and reads one partition at a time into memory. Rows equal in `ORDER BY` are peers.
+//! `row_number` numbers the rows of a partition from 1, `rank` gives each row the number of its first
+//! peer, and `dense_rank` numbers the sets of peers without gaps. An aggregate reads the rows of its frame
+//! around each row, counted in rows or, for `RANGE`, in sets of peers; a frame that grows at its end only
+//! adds the new rows to the value for the row before.
+use crate::sql::ast::{Frame, FrameBound};
+use crate::tuple::Value;
+
+use super::aggregate::{Accumulator, Aggregate};
+use super::order::{sort_key, SortKey};
+use super::{ExecError, Expr, Operator};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    /// An aggregate over the rows of `frame` around each row.
+    Aggregate { aggregate: Aggregate, frame: Frame },
+}
+impl WindowFunction {
+    /// The ranking function called `name` in SQL, in any case. Aggregates are called by their own names.
+    pub fn named(name: &str) -> Option<WindowFunction> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "row_number" => WindowFunction::RowNumber,
+            "rank" => WindowFunction::Rank,
+            "dense_rank" => WindowFunction::DenseRank,
+            _ => return None,
+        })
+    }
+}
+
+/// Each input row followed by the value of each of `functions` for it, over its partition.
+pub struct Window<'a> {
+    input: Box<dyn Operator + 'a>,
+    partition_by: &'a [Expr],
+    order_by: &'a [SortKey],
+    functions: &'a [WindowFunction],
+    /// The keys and row of the first row of the next partition, read to find the end of the one before.
+    next: Option<(Vec<Value>, Vec<Value>)>,
+    output: std::vec::IntoIter<Vec<Value>>,
+    done: bool,
+}
+impl<'a> Window<'a> {
+    pub fn new(
+        input: Box<dyn Operator + 'a>,
+        partition_by: &'a [Expr],
+        order_by: &'a [SortKey],
+        functions: &'a [WindowFunction],
+    ) -> Window<'a> {
+        Window { input, partition_by, order_by, functions, next: None, output: Vec::new().into_iter(), done: false }
+    }
+
+    /// Read the rows of the next partition, or none if the input has run out.
+    fn partition(&mut self) -> Result<Vec<Vec<Value>>, ExecError> {
+        let mut rows = Vec::new();
+        let mut keys = None;
+        if let Some((next_keys, row)) = self.next.take() {
+            keys = Some(next_keys);
+            rows.push(row);
+        }
+        while let Some(row) = self.input.next()? {
+            let row_keys = self.partition_by.iter().map(|e| e.eval(&row)).collect::<Result<Vec<_>, _>>()?;
+            match &keys {
+                Some(keys) if *keys != row_keys => {
+                    self.next = Some((row_keys, row));
+                    return Ok(rows)
+                }
+                Some(_) => {}
+                None => keys = Some(row_keys),
+            }
+            rows.push(row);
+        }
+        self.done = true;
+        Ok(rows)
+    }
+
+    /// The rows of one partition, each followed by the values of the functions for it.
+    fn compute(&self, mut rows: Vec<Vec<Value>>) -> Result<Vec<Vec<Value>>, ExecError> {
+        let n = rows.len();
+        // Where each row's set of peers starts and ends, and how many sets come before it.
+        let (mut first, mut end, mut sets) = (vec![0; n], vec![n; n], vec![0; n]);
+        let mut previous: Option<Vec<u8>> = None;
+        for (i, row) in rows.iter().enumerate() {
+            let key = sort_key(self.order_by, row)?;
+            if previous.as_ref().is_some_and(|p| *p == key) {
+                first[i] = first[i - 1];
+                sets[i] = sets[i - 1];
+            } else {
+                first[i] = i;
+                sets[i] = if i == 0 { 0 } else { sets[i - 1] + 1 };
+                end[..i].iter_mut().rev().take_while(|e| **e == n).for_each(|e| *e = i);
+            }
+            previous = Some(key);
+        }
+        let mut columns = Vec::with_capacity(self.functions.len());
+        for function in self.functions {
+            let int = |v: usize| Value::Int(v as i64 + 1);
+            columns.push(match function {
+                WindowFunction::RowNumber => (0..n).map(int).collect(),
+                WindowFunction::Rank => first.iter().copied().map(int).collect(),
+                WindowFunction::DenseRank => sets.iter().copied().map(int).collect(),
+                WindowFunction::Aggregate { aggregate, frame } => {
+                    let mut args = Vec::with_capacity(n);
+                    for row in &rows {
+                        args.push(match &aggregate.arg {
+                            Some(arg) => arg.eval(row)?,
+                            None => Value::Bool(true),
+                        });
+                    }
+                    let mut values = Vec::with_capacity(n);
+                    // The frame the accumulator holds the values of.
+                    let (mut accumulator, mut from, mut to) = (Accumulator::new(), 0, 0);
+                    for i in 0..n {
+                        let (start, stop) = bounds(*frame, i, n, (first[i], end[i]));
+                        if start != from || stop < to {
+                            (accumulator, from, to) = (Accumulator::new(), start, start);
+                        }
+                        for value in &args[to..stop] {
+                            accumulator.add(aggregate.function, value.clone())?;
+                        }
+                        to = stop;
+                        values.push(accumulator.clone().finish(aggregate.function)?);
+                    }
+                    values
+                }
+            });
+        }
+        for (i, row) in rows.iter_mut().enumerate() {
+            row.extend(columns.iter().map(|c| c[i].clone()));
+        }
+        Ok(rows)
+    }
+}
+impl Operator for Window<'_> {
+    fn next(&mut self) -> Result<Option<Vec<Value>>, ExecError> {
+        loop {
+            if let Some(row) = self.output.next() {
+                return Ok(Some(row))
+            }
+            if self.done {
+                return Ok(None)
+            }
+            let rows = self.partition()?;
+            self.output = self.compute(rows)?.into_iter();
+        }
+    }
+
+    fn stop(&mut self) {
+        self.input.stop();
+        self.next = None;
+        self.output = Vec::new().into_iter();
+        self.done = true;
+    }
+}
+
+/// The rows of its partition of `n` rows that the frame of row `i`, whose peers are `peers`, reads: from
+/// the first to before the second.
+fn bounds(frame: Frame, i: usize, n: usize, (first, end): (usize, usize)) -> (usize, usize) {
+    let offset = |k: u64| usize::try_from(k).unwrap_or(usize::MAX);
+    let start = match frame.start {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(k) => i.saturating_sub(offset(k)),
+        FrameBound::CurrentRow if frame.range => first,
+        FrameBound::CurrentRow => i,
+        FrameBound::Following(k) => i.saturating_add(offset(k)).min(n),
+        FrameBound::UnboundedFollowing => n,
+    };
+    let stop = match frame.end {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(k) => (i + 1).saturating_sub(offset(k)),
+        FrameBound::CurrentRow if frame.range => end,
+        FrameBound::CurrentRow => i + 1,
+        FrameBound::Following(k) => (i + 1).saturating_add(offset(k)).min(n),
+        FrameBound::UnboundedFollowing => n,
+    };
+    (start, stop.max(start))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sql::ast::{Frame, FrameBound};
+    use crate::tuple::Value;
+
+    use super::super::scan::Values;
+    use super::super::{collect, Aggregate, AggregateFunction, ExecError, Expr, SortKey};
+    use super::{Window, WindowFunction};
+
+    #[test]
+    fn test_ranks_and_frames() -> Result<(), ExecError> {
+        // (partition, order key, value), sorted by the first two.
+        let input = [(1, 1, 10), (1, 2, 20), (1, 2, 30), (1, 3, 40), (2, 1, 5)];
+        let rows: Vec<Vec<Expr>> = input
+            .iter()
+            .map(|&(p, k, v)| [p, k, v].into_iter().map(|x| Expr::Literal(Value::Int(x))).collect())
+            .collect();
+        let partition_by = [Expr::Column(0)];
+        let order_by = [SortKey { expr: Expr::Column(1), descending: false, nulls_first: true }];
+        let sum = Aggregate { function: AggregateFunction::Sum, arg: Some(Expr::Column(2)) };
+        let frame = |range, start, end| Frame { range, start, end };
+        let functions = [
+            WindowFunction::RowNumber,
+            WindowFunction::Rank,
+            WindowFunction::DenseRank,
+            // The default frame: up to the row's last peer.
+            WindowFunction::Aggregate {
+                aggregate: sum.clone(),
+                frame: frame(true, FrameBound::UnboundedPreceding, FrameBound::CurrentRow),
+            },
+            WindowFunction::Aggregate {
+                aggregate: sum.clone(),
+                frame: frame(false, FrameBound::UnboundedPreceding, FrameBound::CurrentRow),
+            },
+            WindowFunction::Aggregate {
+                aggregate: sum,
+                frame: frame(false, FrameBound::Preceding(1), FrameBound::Following(1)),
+            },
+            WindowFunction::Aggregate {
+                aggregate: Aggregate { function: AggregateFunction::Count, arg: None },
+                frame: frame(false, FrameBound::Following(1), FrameBound::UnboundedFollowing),
+            },
+        ];
+        let mut window = Window::new(Box::new(Values::new(&rows)), &partition_by, &order_by, &functions);
+        let values: Vec<Vec<Value>> = collect(&mut window)?.into_iter().map(|row| row[3..].to_vec()).collect();
+        let ints = |v: [i64; 7]| v.map(Value::Int).to_vec();
+        assert_eq!(values, vec![
+            ints([1, 1, 1, 10, 10, 30, 3]),
+            ints([2, 2, 2, 60, 30, 60, 2]),
+            ints([3, 2, 2, 60, 60, 90, 1]),
+            ints([4, 4, 3, 100, 100, 70, 0]),
+            ints([1, 1, 1, 5, 5, 5, 0]),
+        ]);
+        Ok(())
+    }
+}
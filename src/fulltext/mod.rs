@@ -0,0 +1,335 @@
+//! Full-text search: an inverted index from terms to the records containing them.
+//!
+//! Documents are identified by the `Rid` of the record they belong to. Everything lives in one B+tree:
+//! a posting per (term, document) holding the term's positions in the document, keyed so each term's
+//! postings are contiguous and ordered by `Rid`; a token count per document; and corpus totals for
+//! scoring. Queries combine terms with AND, OR and phrases and return documents ranked by BM25.
+//!
+//! The index does not keep document text, so removing a document takes the text it was indexed with.
+mod tokenizer;
+
+use std::collections::HashMap;
+
+use crate::allocator::PageAllocator;
+use crate::btree::{BTree, BTreeError};
+use crate::heap::Rid;
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::storage::Storage;
+
+pub use tokenizer::{tokenize, MAX_TERM_LEN};
+
+const POSTING: u8 = b'p';
+const DOCUMENT: u8 = b'd';
+const TOTALS: &[u8] = b"t";
+/// Positions kept per posting; the term frequency is still counted exactly beyond this.
+const MAX_POSITIONS: usize = 200;
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+#[derive(Debug, PartialEq)]
+pub enum FullTextError {
+    Page(PageError),
+    DuplicateDocument,
+}
+impl From<PageError> for FullTextError {
+    fn from(e: PageError) -> Self {
+        FullTextError::Page(e)
+    }
+}
+impl From<BTreeError> for FullTextError {
+    fn from(e: BTreeError) -> Self {
+        match e {
+            BTreeError::Page(e) => FullTextError::Page(e),
+            BTreeError::EntryTooLarge => unreachable!("terms and positions are capped to fit a cell"),
+            BTreeError::Unsorted => unreachable!("full-text indexes are never bulk loaded"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Documents containing the term. The term is normalized like document text.
+    Term(String),
+    /// Documents containing the terms consecutively, in order.
+    Phrase(Vec<String>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+impl Query {
+    pub fn term(term: &str) -> Query {
+        Query::Term(term.to_lowercase())
+    }
+
+    /// A phrase query over the terms of `text`.
+    pub fn phrase(text: &str) -> Query {
+        Query::Phrase(tokenize(text).into_iter().map(|(t, _)| t).collect())
+    }
+}
+
+struct Posting {
+    rid: Rid,
+    frequency: u32,
+    positions: Vec<u32>,
+}
+
+pub struct FullTextIndex<'store, S: Storage> {
+    tree: BTree<'store, S>,
+}
+impl<'store, S: Storage> FullTextIndex<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<FullTextIndex<'store, S>, FullTextError> {
+        Ok(FullTextIndex { tree: BTree::create(store, allocator)? })
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, meta: PageId) -> Result<FullTextIndex<'store, S>, FullTextError> {
+        Ok(FullTextIndex { tree: BTree::open(store, allocator, meta)? })
+    }
+
+    /// The page to pass to `open` to find this index again.
+    pub fn meta_page(&self) -> PageId {
+        self.tree.meta_page()
+    }
+
+    /// Index `text` as the document for `rid`.
+    pub fn insert(&self, rid: Rid, text: &str) -> Result<(), FullTextError> {
+        if self.tree.get(&document_key(rid))?.is_some() {
+            return Err(FullTextError::DuplicateDocument)
+        }
+        let tokens = tokenize(text);
+        for (term, positions) in group(&tokens) {
+            let mut value = (positions.len() as u32).to_le_bytes().to_vec();
+            for position in positions.iter().take(MAX_POSITIONS) {
+                value.extend_from_slice(&position.to_le_bytes());
+            }
+            self.tree.insert(&posting_key(term, rid), &value)?;
+        }
+        let length = tokens.last().map_or(0, |(_, p)| p + 1);
+        self.tree.insert(&document_key(rid), &length.to_le_bytes())?;
+        let (documents, total) = self.totals()?;
+        self.set_totals(documents + 1, total + length as u64)
+    }
+
+    /// Remove the document for `rid`, which must have been indexed with `text`. Returns whether it
+    /// was present.
+    pub fn delete(&self, rid: Rid, text: &str) -> Result<bool, FullTextError> {
+        let Some(length) = self.tree.delete(&document_key(rid))? else { return Ok(false) };
+        for (term, _) in group(&tokenize(text)) {
+            self.tree.delete(&posting_key(term, rid))?;
+        }
+        let length = u32::from_le_bytes(length[..4].try_into().unwrap());
+        let (documents, total) = self.totals()?;
+        self.set_totals(documents - 1, total - length as u64)?;
+        Ok(true)
+    }
+
+    /// Documents matching `query`, best first.
+    pub fn search(&self, query: &Query) -> Result<Vec<(Rid, f64)>, FullTextError> {
+        let (documents, total) = self.totals()?;
+        let average = if documents == 0 { 0.0 } else { total as f64 / documents as f64 };
+        let mut lengths = HashMap::new();
+        let mut scorer = Scorer { index: self, documents, average, lengths: &mut lengths };
+        let mut results: Vec<(Rid, f64)> = scorer.evaluate(query)?.into_iter().collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(results)
+    }
+
+    fn postings(&self, term: &str) -> Result<Vec<Posting>, FullTextError> {
+        let mut start = vec![POSTING];
+        start.extend_from_slice(term.as_bytes());
+        start.push(0);
+        let mut end = start.clone();
+        *end.last_mut().unwrap() = 1;
+        self.tree.range(start.clone()..end)
+            .map(|entry| {
+                let (key, value) = entry?;
+                let rid = Rid::from_bytes(key[start.len()..].try_into().unwrap());
+                let frequency = u32::from_le_bytes(value[..4].try_into().unwrap());
+                let positions = value[4..].chunks(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+                Ok(Posting { rid, frequency, positions })
+            })
+            .collect()
+    }
+
+    fn document_length(&self, rid: Rid) -> Result<u32, FullTextError> {
+        let value = self.tree.get(&document_key(rid))?.expect("postings only name indexed documents");
+        Ok(u32::from_le_bytes(value[..4].try_into().unwrap()))
+    }
+
+    fn totals(&self) -> Result<(u64, u64), FullTextError> {
+        Ok(match self.tree.get(TOTALS)? {
+            Some(v) => (u64::from_le_bytes(v[..8].try_into().unwrap()), u64::from_le_bytes(v[8..16].try_into().unwrap())),
+            None => (0, 0),
+        })
+    }
+
+    fn set_totals(&self, documents: u64, tokens: u64) -> Result<(), FullTextError> {
+        let mut value = documents.to_le_bytes().to_vec();
+        value.extend_from_slice(&tokens.to_le_bytes());
+        self.tree.insert(TOTALS, &value)?;
+        Ok(())
+    }
+}
+
+struct Scorer<'a, 'store, S: Storage> {
+    index: &'a FullTextIndex<'store, S>,
+    documents: u64,
+    average: f64,
+    lengths: &'a mut HashMap<Rid, u32>,
+}
+impl<S: Storage> Scorer<'_, '_, S> {
+    fn evaluate(&mut self, query: &Query) -> Result<HashMap<Rid, f64>, FullTextError> {
+        match query {
+            Query::Term(term) => {
+                let postings = self.index.postings(term)?;
+                let idf = self.idf(postings.len());
+                postings.iter().map(|p| Ok((p.rid, self.bm25(p, idf)?))).collect()
+            }
+            Query::Phrase(terms) => self.phrase(terms),
+            Query::And(queries) => {
+                let mut results: Option<HashMap<Rid, f64>> = None;
+                for query in queries {
+                    let matches = self.evaluate(query)?;
+                    results = Some(match results {
+                        None => matches,
+                        Some(acc) => acc.into_iter().filter_map(|(rid, s)| matches.get(&rid).map(|t| (rid, s + t))).collect(),
+                    });
+                }
+                Ok(results.unwrap_or_default())
+            }
+            Query::Or(queries) => {
+                let mut results = HashMap::new();
+                for query in queries {
+                    for (rid, score) in self.evaluate(query)? {
+                        *results.entry(rid).or_insert(0.0) += score;
+                    }
+                }
+                Ok(results)
+            }
+        }
+    }
+
+    fn phrase(&mut self, terms: &[String]) -> Result<HashMap<Rid, f64>, FullTextError> {
+        let mut lists = Vec::new();
+        for term in terms {
+            let postings = self.index.postings(term)?;
+            let idf = self.idf(postings.len());
+            lists.push((idf, postings.into_iter().map(|p| (p.rid, p)).collect::<HashMap<_, _>>()));
+        }
+        let Some(((_, first), rest)) = lists.split_first() else { return Ok(HashMap::new()) };
+        let mut results = HashMap::new();
+        for (rid, posting) in first {
+            let others: Option<Vec<&Posting>> = rest.iter().map(|(_, list)| list.get(rid)).collect();
+            let Some(others) = others else { continue };
+            let found = posting.positions.iter().any(|start| {
+                others.iter().enumerate().all(|(i, p)| p.positions.binary_search(&(start + i as u32 + 1)).is_ok())
+            });
+            if found {
+                let mut score = 0.0;
+                for (idf, list) in &lists {
+                    score += self.bm25(&list[rid], *idf)?;
+                }
+                results.insert(*rid, score);
+            }
+        }
+        Ok(results)
+    }
+
+    fn idf(&self, matching: usize) -> f64 {
+        let (n, m) = (self.documents as f64, matching as f64);
+        (1.0 + (n - m + 0.5) / (m + 0.5)).ln()
+    }
+
+    fn bm25(&mut self, posting: &Posting, idf: f64) -> Result<f64, FullTextError> {
+        let length = match self.lengths.get(&posting.rid) {
+            Some(length) => *length,
+            None => {
+                let length = self.index.document_length(posting.rid)?;
+                self.lengths.insert(posting.rid, length);
+                length
+            }
+        };
+        let tf = posting.frequency as f64;
+        let norm = 1.0 - B + B * length as f64 / self.average.max(1.0);
+        Ok(idf * tf * (K1 + 1.0) / (tf + K1 * norm))
+    }
+}
+
+/// Distinct terms of a token stream with their positions.
+fn group(tokens: &[(String, u32)]) -> Vec<(&str, Vec<u32>)> {
+    let mut by_term: HashMap<&str, Vec<u32>> = HashMap::new();
+    for (term, position) in tokens {
+        by_term.entry(term.as_str()).or_default().push(*position);
+    }
+    by_term.into_iter().collect()
+}
+
+/// `p term 0 rid`; encoded rids sort like `Rid`, so postings are in rid order.
+fn posting_key(term: &str, rid: Rid) -> Vec<u8> {
+    let mut key = vec![POSTING];
+    key.extend_from_slice(term.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&rid.to_bytes());
+    key
+}
+
+fn document_key(rid: Rid) -> Vec<u8> {
+    let mut key = vec![DOCUMENT];
+    key.extend_from_slice(&rid.to_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::heap::Rid;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{FullTextError, FullTextIndex, Query};
+
+    fn rid(n: usize) -> Rid {
+        Rid { page: PageId::new(n / 10), slot: (n % 10) as u16 }
+    }
+
+    const DOCS: [&str; 5] = [
+        "The quick brown fox jumps over the lazy dog",
+        "A brown dog chases the quick red fox through the brown field",
+        "Lazy afternoons: the dog sleeps",
+        "Quick thinking saves the day",
+        "Brown bread and quick brown sauce",
+    ];
+
+    fn ids(results: Vec<(Rid, f64)>) -> Vec<Rid> {
+        results.into_iter().map(|(r, _)| r).collect()
+    }
+
+    #[test]
+    fn test_queries() -> Result<(), FullTextError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = FullTextIndex::create(&store, allocator)?;
+        for (i, text) in DOCS.iter().enumerate() {
+            index.insert(rid(i), text)?;
+        }
+        assert_eq!(index.insert(rid(0), "again"), Err(FullTextError::DuplicateDocument));
+
+        let mut fox = ids(index.search(&Query::term("FOX"))?);
+        fox.sort();
+        assert_eq!(fox, vec![rid(0), rid(1)]);
+        let quick_and_brown = index.search(&Query::And(vec![Query::term("quick"), Query::term("brown")]))?;
+        assert_eq!(quick_and_brown.len(), 3);
+        // Three occurrences of the two terms in a short document outrank the rest.
+        assert_eq!(quick_and_brown[0].0, rid(4));
+        assert_eq!(ids(index.search(&Query::phrase("brown fox"))?), vec![rid(0)]);
+        assert_eq!(ids(index.search(&Query::phrase("quick brown"))?).len(), 2);
+        let either = index.search(&Query::Or(vec![Query::term("sleeps"), Query::term("thinking")]))?;
+        assert_eq!(either.len(), 2);
+        assert!(index.search(&Query::term("missing"))?.is_empty());
+
+        let index = FullTextIndex::open(&store, allocator, index.meta_page())?;
+        assert!(index.delete(rid(0), DOCS[0])?);
+        assert!(!index.delete(rid(0), DOCS[0])?);
+        assert!(index.search(&Query::phrase("brown fox"))?.is_empty());
+        assert_eq!(ids(index.search(&Query::term("fox"))?), vec![rid(1)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+//! Splits text into lowercase alphanumeric terms.
+
+/// Terms longer than this many bytes are dropped, though they still take up a position.
+pub const MAX_TERM_LEN: usize = 64;
+
+/// The terms of `text` with their positions, counted in tokens from zero.
+pub fn tokenize(text: &str) -> Vec<(String, u32)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .enumerate()
+        .map(|(position, word)| (word.to_lowercase(), position as u32))
+        .filter(|(term, _)| term.len() <= MAX_TERM_LEN)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokenize;
+
+    #[test]
+    fn test_tokenize() {
+        let terms = tokenize("The quick, brown FOX -- jumps! Über-fast.");
+        let words: Vec<_> = terms.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(words, ["the", "quick", "brown", "fox", "jumps", "über", "fast"]);
+        assert_eq!(terms.last().unwrap().1, 6);
+        assert_eq!(tokenize(&format!("a {} b", "x".repeat(100))), vec![("a".to_string(), 0), ("b".to_string(), 2)]);
+    }
+}
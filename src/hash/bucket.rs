@@ -0,0 +1,308 @@
+//! Bucket page format shared by the hash indexes.
+//!
+//! A bucket is a slotted page of (key, value) entries with a small header in the reserved area: a magic
+//! number, the local depth used by extendible hashing, the next page of the bucket's overflow chain, and
+//! a bloom filter. A bucket is the head page plus every overflow page chained from it.
+//!
+//! The head page's filter covers every key in the bucket, so a lookup for an absent key reads only the
+//! head page instead of walking the overflow chain. Removing a key leaves its bits set; the filter is
+//! rebuilt whenever the bucket is rewritten by a split.
+use std::ops::{Deref, DerefMut};
+
+use crate::allocator::PageAllocator;
+use crate::bloom;
+use crate::bytes::{read_u32, read_u64, write_u32, write_u64};
+use crate::page_store::{Data, PageError, PageId, PageStore, PAGE_SIZE};
+use crate::slotted_page::{SlotId, SlottedPage, SlottedPageError, HEADER_LEN, SLOT_LEN};
+use crate::storage::Storage;
+use crate::varint;
+
+use super::HashIndexError;
+
+const MAGIC: u32 = 0x4842_4b54;
+const DEPTH: usize = 4;
+const OVERFLOW: usize = 8;
+const FILTER: usize = 16;
+const FILTER_LEN: usize = 64;
+const FILTER_HASHES: u32 = 3;
+const BUCKET_HEADER_LEN: usize = FILTER + FILTER_LEN;
+const NO_PAGE: u64 = u64::MAX;
+
+/// An owned (key, value) pair read out of a bucket.
+pub(crate) type Entry = (Vec<u8>, Vec<u8>);
+
+/// Largest encoded entry accepted, leaving room for several entries per bucket page.
+pub(crate) const MAX_ENTRY_LEN: usize = (PAGE_SIZE - HEADER_LEN - BUCKET_HEADER_LEN) / 4 - SLOT_LEN;
+
+pub(crate) struct Bucket<T> {
+    page: SlottedPage<T>
+}
+impl<T: Deref<Target = Data>> Bucket<T> {
+    pub(crate) fn new(data: T) -> Result<Bucket<T>, HashIndexError> {
+        let page = SlottedPage::new(data);
+        if page.reserved().len() != BUCKET_HEADER_LEN || read_u32(page.reserved(), 0) != MAGIC {
+            return Err(HashIndexError::Page(PageError::WrongPageType))
+        }
+        Ok(Bucket { page })
+    }
+
+    pub(crate) fn local_depth(&self) -> u8 {
+        self.page.reserved()[DEPTH]
+    }
+
+    pub(crate) fn overflow(&self) -> Option<PageId> {
+        match read_u64(self.page.reserved(), OVERFLOW) {
+            NO_PAGE => None,
+            page => Some(PageId::new(page as usize)),
+        }
+    }
+
+    /// False if no key equal to `key` was ever added to this bucket since it was last rewritten.
+    pub(crate) fn may_contain(&self, key: &[u8]) -> bool {
+        bloom::may_contain(&self.page.reserved()[FILTER..], FILTER_HASHES, key)
+    }
+
+    pub(crate) fn find(&self, key: &[u8]) -> Option<(SlotId, &[u8])> {
+        self.page.iter().find(|(_, e)| entry_key(e) == key).map(|(slot, e)| (slot, entry_value(e)))
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&[u8], &[u8])> + '_ {
+        self.page.iter().map(|(_, e)| (entry_key(e), entry_value(e)))
+    }
+}
+impl<T: DerefMut<Target = Data>> Bucket<T> {
+    pub(crate) fn init(data: T, local_depth: u8) -> Bucket<T> {
+        let mut page = SlottedPage::init(data, BUCKET_HEADER_LEN);
+        let header = page.reserved_mut();
+        write_u32(header, 0, MAGIC);
+        header[DEPTH] = local_depth;
+        write_u64(header, OVERFLOW, NO_PAGE);
+        Bucket { page }
+    }
+
+    pub(crate) fn set_local_depth(&mut self, depth: u8) {
+        self.page.reserved_mut()[DEPTH] = depth;
+    }
+
+    pub(crate) fn set_overflow(&mut self, page: Option<PageId>) {
+        write_u64(self.page.reserved_mut(), OVERFLOW, page.map(|p| p.offset() as u64).unwrap_or(NO_PAGE));
+    }
+
+    /// Add an entry, returning false if the page has no room for it.
+    pub(crate) fn try_insert(&mut self, key: &[u8], value: &[u8]) -> bool {
+        match self.page.insert(&encode_entry(key, value)) {
+            Ok(_) => true,
+            Err(SlottedPageError::PageFull) => false,
+            Err(e) => panic!("bucket insert failed: {e:?}"),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, slot: SlotId) {
+        self.page.delete(slot).expect("slot came from find");
+    }
+
+    pub(crate) fn add_to_filter(&mut self, key: &[u8]) {
+        bloom::insert(&mut self.page.reserved_mut()[FILTER..], FILTER_HASHES, key);
+    }
+
+    /// Drop every entry and reset the filter, keeping the rest of the header.
+    pub(crate) fn clear(&mut self) {
+        self.page.clear();
+        self.page.reserved_mut()[FILTER..].fill(0);
+    }
+}
+
+pub(crate) fn check_entry(key: &[u8], value: &[u8]) -> Result<(), HashIndexError> {
+    if entry_len(key, value) > MAX_ENTRY_LEN {
+        return Err(HashIndexError::EntryTooLarge)
+    }
+    Ok(())
+}
+
+fn entry_len(key: &[u8], value: &[u8]) -> usize {
+    varint::encoded_len(key.len() as u64) + key.len() + value.len()
+}
+
+/// `[key_len varint][key][value]`.
+fn encode_entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(varint::MAX_LEN + key.len() + value.len());
+    varint::write_prefixed(&mut entry, key);
+    entry.extend_from_slice(value);
+    entry
+}
+
+fn entry_key(entry: &[u8]) -> &[u8] {
+    varint::read_prefixed(entry).expect("entries are written by encode_entry").0
+}
+
+fn entry_value(entry: &[u8]) -> &[u8] {
+    &entry[varint::read_prefixed(entry).expect("entries are written by encode_entry").1..]
+}
+
+/// Format a freshly allocated head page.
+pub(crate) fn create<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator, local_depth: u8) -> Result<PageId, HashIndexError> {
+    let page = allocator.allocate(store)?;
+    Bucket::init(page.try_write()?, local_depth);
+    Ok(page.id())
+}
+
+pub(crate) fn local_depth<S: Storage>(store: &PageStore<S>, head: PageId) -> Result<u8, HashIndexError> {
+    let page = store.pin_page(&head)?;
+    let depth = Bucket::new(page.try_read()?)?.local_depth();
+    Ok(depth)
+}
+
+pub(crate) fn get<S: Storage>(store: &PageStore<S>, head: PageId, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+    if !may_contain(store, head, key)? {
+        return Ok(None)
+    }
+    let mut next = Some(head);
+    while let Some(id) = next {
+        let page = store.pin_page(&id)?;
+        let bucket = Bucket::new(page.try_read()?)?;
+        if let Some((_, value)) = bucket.find(key) {
+            return Ok(Some(value.to_vec()))
+        }
+        next = bucket.overflow();
+    }
+    Ok(None)
+}
+
+pub(crate) fn remove<S: Storage>(store: &PageStore<S>, head: PageId, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+    if !may_contain(store, head, key)? {
+        return Ok(None)
+    }
+    remove_from_page(store, head, key)
+}
+
+/// Remove `key` from the chain starting at `start`, which need not be a head page, ignoring filters.
+fn remove_from_page<S: Storage>(store: &PageStore<S>, start: PageId, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+    let mut next = Some(start);
+    while let Some(id) = next {
+        let page = store.pin_page(&id)?;
+        let mut bucket = Bucket::new(page.try_write()?)?;
+        if let Some((slot, value)) = bucket.find(key) {
+            let value = value.to_vec();
+            bucket.remove(slot);
+            return Ok(Some(value))
+        }
+        next = bucket.overflow();
+    }
+    Ok(None)
+}
+
+fn may_contain<S: Storage>(store: &PageStore<S>, head: PageId, key: &[u8]) -> Result<bool, HashIndexError> {
+    let page = store.pin_page(&head)?;
+    let contains = Bucket::new(page.try_read()?)?.may_contain(key);
+    Ok(contains)
+}
+
+/// Every entry in the bucket, head page first.
+pub(crate) fn entries<S: Storage>(store: &PageStore<S>, head: PageId) -> Result<Vec<Entry>, HashIndexError> {
+    let mut all = Vec::new();
+    let mut next = Some(head);
+    while let Some(id) = next {
+        let page = store.pin_page(&id)?;
+        let bucket = Bucket::new(page.try_read()?)?;
+        all.extend(bucket.entries().map(|(k, v)| (k.to_vec(), v.to_vec())));
+        next = bucket.overflow();
+    }
+    Ok(all)
+}
+
+pub(crate) enum Insert {
+    Done(Option<Vec<u8>>),
+    /// The bucket had no room and overflow pages were not allowed; nothing was changed.
+    Full,
+}
+
+/// Insert or replace `key`. When every page of the bucket is full, a new overflow page is chained on if
+/// `allow_overflow` is set, otherwise `Insert::Full` is returned.
+pub(crate) fn insert<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator, head: PageId, key: &[u8], value: &[u8], allow_overflow: bool) -> Result<Insert, HashIndexError> {
+    let mut next = Some(head);
+    let mut last = head;
+    let mut target = None;
+    let mut old = None;
+    while let Some(id) = next {
+        let page = store.pin_page(&id)?;
+        let bucket = Bucket::new(page.try_read()?)?;
+        if let Some((_, v)) = bucket.find(key) {
+            old = Some((id, v.to_vec()));
+        }
+        if target.is_none() && bucket.page.free_space() >= entry_len(key, value) {
+            target = Some(id);
+        }
+        last = id;
+        next = bucket.overflow();
+    }
+
+    let replaced = match &old {
+        Some((id, v)) if v.len() >= value.len() => Some(*id),
+        _ => None,
+    };
+    let target = match target.or(replaced) {
+        Some(id) => id,
+        None if allow_overflow => {
+            let depth = local_depth(store, head)?;
+            let overflow = create(store, allocator, depth)?;
+            let page = store.pin_page(&last)?;
+            Bucket::new(page.try_write()?)?.set_overflow(Some(overflow));
+            overflow
+        }
+        None => return Ok(Insert::Full),
+    };
+
+    if let Some((id, _)) = &old {
+        remove_from_page(store, *id, key)?;
+    }
+    let page = store.pin_page(&target)?;
+    let inserted = Bucket::new(page.try_write()?)?.try_insert(key, value);
+    assert!(inserted, "target page was checked for room");
+    drop(page);
+    let page = store.pin_page(&head)?;
+    Bucket::new(page.try_write()?)?.add_to_filter(key);
+    Ok(Insert::Done(old.map(|(_, v)| v)))
+}
+
+/// Return the head page and every overflow page of the bucket to the allocator.
+pub(crate) fn free<S: Storage>(
+    store: &PageStore<S>,
+    allocator: &PageAllocator,
+    head: PageId,
+) -> Result<(), HashIndexError> {
+    let mut next = Some(head);
+    while let Some(id) = next {
+        next = {
+            let page = store.pin_page(&id)?;
+            let bucket = Bucket::new(page.try_read()?)?;
+            bucket.overflow()
+        };
+        allocator.free(store, id)?;
+    }
+    Ok(())
+}
+
+/// Replace the bucket's contents with `entries`, freeing its overflow pages and resetting its depth.
+pub(crate) fn rewrite<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator, head: PageId, local_depth: u8, entries: &[Entry]) -> Result<(), HashIndexError> {
+    let mut overflow = {
+        let page = store.pin_page(&head)?;
+        let mut bucket = Bucket::new(page.try_write()?)?;
+        let overflow = bucket.overflow();
+        bucket.clear();
+        bucket.set_overflow(None);
+        bucket.set_local_depth(local_depth);
+        overflow
+    };
+    while let Some(id) = overflow {
+        overflow = {
+            let page = store.pin_page(&id)?;
+            let bucket = Bucket::new(page.try_read()?)?;
+            bucket.overflow()
+        };
+        allocator.free(store, id)?;
+    }
+    for (key, value) in entries {
+        insert(store, allocator, head, key, value, true)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+//! Cuckoo hashing.
+//!
+//! Every key has two candidate buckets, picked by the low and the high half of its hash, and lives in one
+//! of them, so a lookup reads at most two bucket pages however the table was filled. An insert that finds
+//! both candidates full evicts entries from one of them to make room, and each evicted entry moves to its
+//! other candidate, evicting in turn. When such a chain runs past `MAX_KICKS` the table doubles: bucket `i`
+//! splits into `i` and `i + n` on one more bit of whichever half of the hash placed each entry, which keeps
+//! every entry in one of its candidates. Only when the directory cannot grow any further do buckets get
+//! overflow pages. Bucket pages use the same format as the other hash variants.
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u32, read_u64, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::storage::Storage;
+
+use super::bucket::{self, Entry, Insert};
+use super::directory::Directory;
+use super::{hash_key, HashIndex, HashIndexError};
+
+const MAGIC: u32 = 0x4843_4b4f;
+const DIR_PAGE_COUNT: usize = 4;
+const BUCKET_COUNT: usize = 8;
+const DIR_PAGES: usize = 16;
+const INITIAL_BUCKETS: usize = 4;
+/// Evictions tried before an insert gives up and doubles the table.
+const MAX_KICKS: usize = 32;
+
+pub struct CuckooHash<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    header: PageId,
+}
+
+/// The two candidate buckets for `hash` in a table of `buckets` buckets, a power of two.
+fn candidates(hash: u64, buckets: usize) -> [usize; 2] {
+    let mask = buckets as u64 - 1;
+    [(hash & mask) as usize, ((hash >> 32) & mask) as usize]
+}
+
+impl<'store, S: Storage> CuckooHash<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<CuckooHash<'store, S>, HashIndexError> {
+        let header = allocator.allocate(store)?;
+        let mut buf = header.try_write()?;
+        write_u32(&mut *buf, 0, MAGIC);
+        write_u64(&mut *buf, BUCKET_COUNT, INITIAL_BUCKETS as u64);
+        drop(buf);
+        let index = CuckooHash { store, allocator, header: header.id() };
+        drop(header);
+
+        let directory = index.directory();
+        directory.reserve(store, &allocator, INITIAL_BUCKETS)?;
+        for i in 0..INITIAL_BUCKETS {
+            let bucket = bucket::create(store, &allocator, 0)?;
+            directory.set(store, i, bucket)?;
+        }
+        Ok(index)
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, header: PageId) -> Result<CuckooHash<'store, S>, HashIndexError> {
+        let page = store.pin_page(&header)?;
+        if read_u32(&*page.try_read()?, 0) != MAGIC {
+            return Err(HashIndexError::Page(PageError::WrongPageType))
+        }
+        Ok(CuckooHash { store, allocator, header })
+    }
+
+    /// The page to pass to `open` to find this index again.
+    pub fn header_page(&self) -> PageId {
+        self.header
+    }
+
+    pub fn bucket_count(&self) -> Result<usize, HashIndexError> {
+        let page = self.store.pin_page(&self.header)?;
+        let count = read_u64(&*page.try_read()?, BUCKET_COUNT);
+        Ok(count as usize)
+    }
+
+    fn directory(&self) -> Directory {
+        Directory::new(self.header, DIR_PAGE_COUNT, DIR_PAGES)
+    }
+
+    /// Head pages of the candidate buckets for `key`.
+    fn heads(&self, key: &[u8]) -> Result<[PageId; 2], HashIndexError> {
+        let directory = self.directory();
+        let [a, b] = candidates(hash_key(key), self.bucket_count()?);
+        Ok([directory.get(self.store, a)?, directory.get(self.store, b)?])
+    }
+
+    /// Put an entry known to be absent into one of its candidates, moving other entries or growing the
+    /// table as needed.
+    fn place(&self, entry: Entry) -> Result<(), HashIndexError> {
+        let mut homeless = vec![entry];
+        let mut kicks = 0;
+        while let Some((key, value)) = homeless.pop() {
+            let heads = self.heads(&key)?;
+            if self.try_place(heads, &key, &value)? {
+                continue
+            }
+            if kicks == MAX_KICKS {
+                kicks = 0;
+                if self.grow()? {
+                    homeless.push((key, value));
+                } else {
+                    bucket::insert(self.store, &self.allocator, heads[0], &key, &value, true)?;
+                }
+                continue
+            }
+
+            // Alternate between the candidates so a chain of evictions walks through both.
+            let head = heads[kicks % 2];
+            let mut victims = bucket::entries(self.store, head)?;
+            let mut next = (hash_key(&key) as usize).wrapping_add(kicks);
+            loop {
+                let (victim, victim_value) = victims.swap_remove(next % victims.len());
+                bucket::remove(self.store, head, &victim)?;
+                homeless.push((victim, victim_value));
+                if let Insert::Done(_) = bucket::insert(self.store, &self.allocator, head, &key, &value, false)? {
+                    break
+                }
+                next = next.wrapping_add(1);
+            }
+            kicks += 1;
+        }
+        Ok(())
+    }
+
+    fn try_place(&self, heads: [PageId; 2], key: &[u8], value: &[u8]) -> Result<bool, HashIndexError> {
+        for head in heads {
+            if let Insert::Done(_) = bucket::insert(self.store, &self.allocator, head, key, value, false)? {
+                return Ok(true)
+            }
+        }
+        Ok(false)
+    }
+
+    /// Double the table. Returns false, changing nothing, if the directory cannot hold twice the buckets.
+    fn grow(&self) -> Result<bool, HashIndexError> {
+        let count = self.bucket_count()?;
+        let directory = self.directory();
+        if !directory.reserve(self.store, &self.allocator, count * 2)? {
+            return Ok(false)
+        }
+        for i in 0..count {
+            let head = directory.get(self.store, i)?;
+            let (stay, moved): (Vec<_>, Vec<_>) = bucket::entries(self.store, head)?.into_iter().partition(|(k, _)| {
+                // Keep each entry in the candidate that placed it, which is now either `i` or `i + count`.
+                let hash = hash_key(k);
+                let [a, b] = candidates(hash, count * 2);
+                (if candidates(hash, count)[0] == i { a } else { b }) == i
+            });
+            let sibling = bucket::create(self.store, &self.allocator, 0)?;
+            bucket::rewrite(self.store, &self.allocator, head, 0, &stay)?;
+            bucket::rewrite(self.store, &self.allocator, sibling, 0, &moved)?;
+            directory.set(self.store, i + count, sibling)?;
+        }
+        let page = self.store.pin_page(&self.header)?;
+        write_u64(&mut *page.try_write()?, BUCKET_COUNT, (count * 2) as u64);
+        Ok(true)
+    }
+}
+impl<S: Storage> HashIndex for CuckooHash<'_, S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        let [a, b] = self.heads(key)?;
+        match bucket::get(self.store, a, key)? {
+            Some(value) => Ok(Some(value)),
+            None if a != b => bucket::get(self.store, b, key),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        bucket::check_entry(key, value)?;
+        for head in self.heads(key)? {
+            if bucket::get(self.store, head, key)?.is_none() {
+                continue
+            }
+            // Replace in place if the bucket has room, otherwise move the key like a new one.
+            if let Insert::Done(old) = bucket::insert(self.store, &self.allocator, head, key, value, false)? {
+                return Ok(old)
+            }
+            let old = bucket::remove(self.store, head, key)?;
+            self.place((key.to_vec(), value.to_vec()))?;
+            return Ok(old)
+        }
+        self.place((key.to_vec(), value.to_vec()))?;
+        Ok(None)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        let [a, b] = self.heads(key)?;
+        match bucket::remove(self.store, a, key)? {
+            Some(old) => Ok(Some(old)),
+            None if a != b => bucket::remove(self.store, b, key),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::hash::bucket::Bucket;
+    use crate::hash::{hash_key, HashIndex, HashIndexError};
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{candidates, CuckooHash, INITIAL_BUCKETS};
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("order:{i}").into_bytes()
+    }
+
+    #[test]
+    fn test_every_key_in_a_candidate_bucket() -> Result<(), HashIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = CuckooHash::create(&store, allocator)?;
+        for i in 0..6000 {
+            assert_eq!(index.insert(&key(i), &i.to_le_bytes().repeat(8))?, None);
+        }
+        let count = index.bucket_count()?;
+        assert!(count > INITIAL_BUCKETS && count.is_power_of_two());
+
+        // No overflow pages, and each key sits on the head page of one of its two candidates.
+        let directory = index.directory();
+        let mut heads = Vec::new();
+        for i in 0..count {
+            let head = directory.get(&store, i)?;
+            let page = store.pin_page(&head)?;
+            let bucket = Bucket::new(page.try_read()?)?;
+            assert_eq!(bucket.overflow(), None);
+            heads.push(bucket.entries().map(|(k, _)| k.to_vec()).collect::<Vec<_>>());
+        }
+        for i in 0..6000 {
+            let [a, b] = candidates(hash_key(&key(i)), count);
+            assert!(heads[a].contains(&key(i)) || heads[b].contains(&key(i)));
+            assert_eq!(index.get(&key(i))?, Some(i.to_le_bytes().repeat(8)));
+        }
+        assert_eq!(heads.iter().map(Vec::len).sum::<usize>(), 6000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_delete_and_reopen() -> Result<(), HashIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = CuckooHash::create(&store, allocator)?;
+        for i in 0..1000 {
+            index.insert(&key(i), b"v1")?;
+        }
+        assert_eq!(index.insert(&key(3), b"v2")?, Some(b"v1".to_vec()));
+        assert_eq!(index.insert(&key(5), &[7; 200])?, Some(b"v1".to_vec()));
+        assert_eq!(index.delete(&key(4))?, Some(b"v1".to_vec()));
+        assert_eq!(index.delete(&key(4))?, None);
+
+        let index = CuckooHash::open(&store, allocator, index.header_page())?;
+        assert_eq!(index.get(&key(3))?, Some(b"v2".to_vec()));
+        assert_eq!(index.get(&key(4))?, None);
+        assert_eq!(index.get(&key(5))?, Some(vec![7; 200]));
+        assert_eq!(index.get(&key(999))?, Some(b"v1".to_vec()));
+        Ok(())
+    }
+}
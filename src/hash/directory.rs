@@ -0,0 +1,71 @@
+//! An array of page ids spread over directory pages, used by the hash indexes to map bucket numbers to
+//! bucket pages. The directory pages themselves are listed in the index's header page.
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u64, write_u16, write_u64};
+use crate::page_store::{PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+
+use super::HashIndexError;
+
+const ENTRIES_PER_PAGE: usize = PAGE_SIZE / 8;
+
+pub(crate) struct Directory {
+    header: PageId,
+    /// Offset in the header of the u16 count of directory pages.
+    count_at: usize,
+    /// Offset in the header where the directory page ids start.
+    pages_at: usize,
+}
+impl Directory {
+    pub(crate) fn new(header: PageId, count_at: usize, pages_at: usize) -> Directory {
+        Directory { header, count_at, pages_at }
+    }
+
+    fn location<S: Storage>(&self, store: &PageStore<S>, index: usize) -> Result<(PageId, usize), HashIndexError> {
+        let page = store.pin_page(&self.header)?;
+        let buf = page.try_read()?;
+        let dir = read_u64(&*buf, self.pages_at + (index / ENTRIES_PER_PAGE) * 8);
+        Ok((PageId::new(dir as usize), (index % ENTRIES_PER_PAGE) * 8))
+    }
+
+    pub(crate) fn get<S: Storage>(&self, store: &PageStore<S>, index: usize) -> Result<PageId, HashIndexError> {
+        let (dir, at) = self.location(store, index)?;
+        let page = store.pin_page(&dir)?;
+        let bucket = read_u64(&*page.try_read()?, at);
+        Ok(PageId::new(bucket as usize))
+    }
+
+    pub(crate) fn set<S: Storage>(&self, store: &PageStore<S>, index: usize, bucket: PageId) -> Result<(), HashIndexError> {
+        let (dir, at) = self.location(store, index)?;
+        let page = store.pin_page(&dir)?;
+        write_u64(&mut *page.try_write()?, at, bucket.offset() as u64);
+        Ok(())
+    }
+
+    /// The directory pages, in order.
+    pub(crate) fn pages<S: Storage>(&self, store: &PageStore<S>) -> Result<Vec<PageId>, HashIndexError> {
+        let page = store.pin_page(&self.header)?;
+        let buf = page.try_read()?;
+        let count = read_u16(&*buf, self.count_at) as usize;
+        Ok((0..count).map(|i| PageId::new(read_u64(&*buf, self.pages_at + i * 8) as usize)).collect())
+    }
+
+    /// Allocate directory pages until at least `len` entries fit. Returns false, changing nothing, if the
+    /// header has no room to list that many directory pages.
+    pub(crate) fn reserve<S: Storage>(&self, store: &PageStore<S>, allocator: &PageAllocator, len: usize) -> Result<bool, HashIndexError> {
+        let needed = len.div_ceil(ENTRIES_PER_PAGE);
+        let header = store.pin_page(&self.header)?;
+        let mut buf = header.try_write()?;
+        let mut count = read_u16(&*buf, self.count_at) as usize;
+        if self.pages_at + needed * 8 > PAGE_SIZE {
+            return Ok(false)
+        }
+        while count < needed {
+            let dir = allocator.allocate(store)?;
+            write_u64(&mut *buf, self.pages_at + count * 8, dir.id().offset() as u64);
+            count += 1;
+        }
+        write_u16(&mut *buf, self.count_at, count as u16);
+        Ok(true)
+    }
+}
@@ -0,0 +1,220 @@
+//! Extendible hashing.
+//!
+//! A header page records the global depth and the pages holding the directory. The directory has
+//! `2^global_depth` entries indexed by the low bits of a key's hash, each naming a bucket. When a bucket
+//! fills up it is split on the next hash bit, doubling the directory first if the bucket's local depth has
+//! caught up with the global depth. Deletes leave buckets in place; they are not merged back.
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u32, write_u16, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::storage::Storage;
+
+use super::bucket::{self, Insert};
+use super::directory::Directory;
+use super::{hash_key, HashIndex, HashIndexError};
+
+const MAGIC: u32 = 0x4845_5848;
+const GLOBAL_DEPTH: usize = 4;
+const DIR_PAGE_COUNT: usize = 6;
+const DIR_PAGES: usize = 8;
+/// Beyond this depth full buckets grow overflow chains instead of splitting.
+const MAX_GLOBAL_DEPTH: u8 = 16;
+
+pub struct ExtendibleHash<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    header: PageId,
+}
+impl<'store, S: Storage> ExtendibleHash<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<ExtendibleHash<'store, S>, HashIndexError> {
+        let header = allocator.allocate(store)?;
+        let dir = allocator.allocate(store)?;
+        let first = bucket::create(store, &allocator, 0)?;
+        write_u64(&mut *dir.try_write()?, 0, first.offset() as u64);
+
+        let mut buf = header.try_write()?;
+        write_u32(&mut *buf, 0, MAGIC);
+        buf[GLOBAL_DEPTH] = 0;
+        write_u16(&mut *buf, DIR_PAGE_COUNT, 1);
+        write_u64(&mut *buf, DIR_PAGES, dir.id().offset() as u64);
+        Ok(ExtendibleHash { store, allocator, header: header.id() })
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, header: PageId) -> Result<ExtendibleHash<'store, S>, HashIndexError> {
+        let page = store.pin_page(&header)?;
+        if read_u32(&*page.try_read()?, 0) != MAGIC {
+            return Err(HashIndexError::Page(PageError::WrongPageType))
+        }
+        Ok(ExtendibleHash { store, allocator, header })
+    }
+
+    /// The page to pass to `open` to find this index again.
+    pub fn header_page(&self) -> PageId {
+        self.header
+    }
+
+    pub fn global_depth(&self) -> Result<u8, HashIndexError> {
+        let page = self.store.pin_page(&self.header)?;
+        let depth = page.try_read()?[GLOBAL_DEPTH];
+        Ok(depth)
+    }
+
+    /// Return every page of the index, its header included, to the allocator. Nothing else may be using
+    /// the index.
+    pub fn free(self) -> Result<(), HashIndexError> {
+        let directory = self.directory();
+        // Buckets are shared by every directory entry their local depth leaves them under.
+        let mut buckets = (0..1usize << self.global_depth()?)
+            .map(|i| directory.get(self.store, i))
+            .collect::<Result<Vec<_>, _>>()?;
+        buckets.sort_unstable();
+        buckets.dedup();
+        for bucket in buckets {
+            bucket::free(self.store, &self.allocator, bucket)?;
+        }
+        for page in directory.pages(self.store)? {
+            self.allocator.free(self.store, page)?;
+        }
+        self.allocator.free(self.store, self.header)?;
+        Ok(())
+    }
+
+    fn bucket_for(&self, hash: u64) -> Result<PageId, HashIndexError> {
+        let depth = self.global_depth()?;
+        self.directory().get(self.store, (hash & mask(depth)) as usize)
+    }
+
+    fn directory(&self) -> Directory {
+        Directory::new(self.header, DIR_PAGE_COUNT, DIR_PAGES)
+    }
+
+    /// Double the directory: each new entry points at the same bucket as its lower-half twin.
+    fn double(&self) -> Result<(), HashIndexError> {
+        let depth = self.global_depth()?;
+        let size = 1usize << depth;
+        let directory = self.directory();
+        let reserved = directory.reserve(self.store, &self.allocator, size * 2)?;
+        assert!(reserved, "MAX_GLOBAL_DEPTH keeps the directory within the header's capacity");
+        for i in 0..size {
+            let bucket = directory.get(self.store, i)?;
+            directory.set(self.store, i + size, bucket)?;
+        }
+        let header = self.store.pin_page(&self.header)?;
+        header.try_write()?[GLOBAL_DEPTH] = depth + 1;
+        Ok(())
+    }
+
+    /// Split the bucket `head`, reached through hash `hash`, on hash bit `local_depth`.
+    fn split(&self, head: PageId, hash: u64, local_depth: u8) -> Result<(), HashIndexError> {
+        let entries = bucket::entries(self.store, head)?;
+        let (moved, stay): (Vec<_>, Vec<_>) = entries.into_iter().partition(|(k, _)| hash_key(k) >> local_depth & 1 == 1);
+        let sibling = bucket::create(self.store, &self.allocator, local_depth + 1)?;
+        bucket::rewrite(self.store, &self.allocator, head, local_depth + 1, &stay)?;
+        bucket::rewrite(self.store, &self.allocator, sibling, local_depth + 1, &moved)?;
+
+        let global_depth = self.global_depth()?;
+        let low = (hash & mask(local_depth)) as usize;
+        let step = 1usize << local_depth;
+        let directory = self.directory();
+        let mut index = low;
+        while index < 1usize << global_depth {
+            if index >> local_depth & 1 == 1 {
+                directory.set(self.store, index, sibling)?;
+            }
+            index += step;
+        }
+        Ok(())
+    }
+}
+impl<S: Storage> HashIndex for ExtendibleHash<'_, S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        let head = self.bucket_for(hash_key(key))?;
+        bucket::get(self.store, head, key)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        bucket::check_entry(key, value)?;
+        let hash = hash_key(key);
+        loop {
+            let head = self.bucket_for(hash)?;
+            let local_depth = bucket::local_depth(self.store, head)?;
+            let allow_overflow = local_depth >= MAX_GLOBAL_DEPTH;
+            match bucket::insert(self.store, &self.allocator, head, key, value, allow_overflow)? {
+                Insert::Done(old) => return Ok(old),
+                Insert::Full => {
+                    if local_depth == self.global_depth()? {
+                        self.double()?;
+                    }
+                    self.split(head, hash, local_depth)?;
+                }
+            }
+        }
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        let head = self.bucket_for(hash_key(key))?;
+        bucket::remove(self.store, head, key)
+    }
+}
+
+fn mask(depth: u8) -> u64 {
+    (1u64 << depth) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::hash::{HashIndex, HashIndexError};
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::ExtendibleHash;
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("user:{i}").into_bytes()
+    }
+
+    fn value(i: u32) -> Vec<u8> {
+        i.to_le_bytes().repeat(10)
+    }
+
+    #[test]
+    fn test_insert_get_delete() -> Result<(), HashIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = ExtendibleHash::create(&store, allocator)?;
+
+        for i in 0..5000 {
+            assert_eq!(index.insert(&key(i), &value(i))?, None);
+        }
+        assert!(index.global_depth()? >= 6);
+        for i in 0..5000 {
+            assert_eq!(index.get(&key(i))?, Some(value(i)));
+        }
+        assert_eq!(index.get(b"missing")?, None);
+
+        assert_eq!(index.insert(&key(9), b"replaced")?, Some(value(9)));
+        assert_eq!(index.delete(&key(9))?, Some(b"replaced".to_vec()));
+        assert_eq!(index.delete(&key(9))?, None);
+        assert_eq!(index.get(&key(9))?, None);
+
+        let index = ExtendibleHash::open(&store, allocator, index.header_page())?;
+        assert_eq!(index.get(&key(4999))?, Some(value(4999)));
+
+        index.free()?;
+        // Every page after the allocator's own is free, so the first new page comes after all of them.
+        let free = allocator.free_pages(&store)?.len();
+        (0..free).try_for_each(|_| allocator.allocate(&store).map(drop))?;
+        assert_eq!(allocator.allocate(&store)?.id(), PageId::new(free + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_entries_are_rejected() -> Result<(), HashIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = ExtendibleHash::create(&store, allocator)?;
+        assert_eq!(index.insert(b"k", &[0u8; 3000]), Err(HashIndexError::EntryTooLarge));
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+//! Linear hashing.
+//!
+//! Buckets are numbered from zero and addressed by the low bits of a key's hash. The table grows one
+//! bucket at a time: whenever the bytes stored exceed `LOAD_FACTOR` of the bucket pages, the bucket at the
+//! split pointer is split into itself and a new bucket at the end, and the pointer advances. When it
+//! wraps, the level goes up and addressing uses one more hash bit. Buckets that fill up between splits
+//! grow overflow chains. Bucket pages use the same format as extendible hashing.
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u32, read_u64, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+
+use super::bucket::{self, Insert};
+use super::directory::Directory;
+use super::{hash_key, HashIndex, HashIndexError};
+
+const MAGIC: u32 = 0x484c_4e52;
+const LEVEL: usize = 4;
+const DIR_PAGE_COUNT: usize = 6;
+const NEXT_SPLIT: usize = 8;
+const STORED_BYTES: usize = 16;
+const DIR_PAGES: usize = 24;
+const INITIAL_BUCKETS: usize = 4;
+/// Split once stored entries exceed this fraction, in percent, of the bucket pages' capacity.
+const LOAD_FACTOR: usize = 75;
+
+pub struct LinearHash<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    header: PageId,
+}
+struct State {
+    level: u8,
+    next_split: usize,
+    stored_bytes: u64,
+}
+impl State {
+    fn base(&self) -> usize {
+        INITIAL_BUCKETS << self.level
+    }
+
+    fn bucket_count(&self) -> usize {
+        self.base() + self.next_split
+    }
+
+    fn address(&self, hash: u64) -> usize {
+        let bucket = (hash % self.base() as u64) as usize;
+        if bucket < self.next_split {
+            (hash % (self.base() as u64 * 2)) as usize
+        } else {
+            bucket
+        }
+    }
+}
+impl<'store, S: Storage> LinearHash<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<LinearHash<'store, S>, HashIndexError> {
+        let header = allocator.allocate(store)?;
+        write_u32(&mut *header.try_write()?, 0, MAGIC);
+        let index = LinearHash { store, allocator, header: header.id() };
+        drop(header);
+
+        let directory = index.directory();
+        directory.reserve(store, &allocator, INITIAL_BUCKETS)?;
+        for i in 0..INITIAL_BUCKETS {
+            let bucket = bucket::create(store, &allocator, 0)?;
+            directory.set(store, i, bucket)?;
+        }
+        Ok(index)
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, header: PageId) -> Result<LinearHash<'store, S>, HashIndexError> {
+        let page = store.pin_page(&header)?;
+        if read_u32(&*page.try_read()?, 0) != MAGIC {
+            return Err(HashIndexError::Page(PageError::WrongPageType))
+        }
+        Ok(LinearHash { store, allocator, header })
+    }
+
+    /// The page to pass to `open` to find this index again.
+    pub fn header_page(&self) -> PageId {
+        self.header
+    }
+
+    pub fn bucket_count(&self) -> Result<usize, HashIndexError> {
+        Ok(self.state()?.bucket_count())
+    }
+
+    fn directory(&self) -> Directory {
+        Directory::new(self.header, DIR_PAGE_COUNT, DIR_PAGES)
+    }
+
+    fn state(&self) -> Result<State, HashIndexError> {
+        let page = self.store.pin_page(&self.header)?;
+        let buf = page.try_read()?;
+        Ok(State {
+            level: buf[LEVEL],
+            next_split: read_u64(&*buf, NEXT_SPLIT) as usize,
+            stored_bytes: read_u64(&*buf, STORED_BYTES),
+        })
+    }
+
+    fn set_state(&self, state: &State) -> Result<(), HashIndexError> {
+        let page = self.store.pin_page(&self.header)?;
+        let mut buf = page.try_write()?;
+        buf[LEVEL] = state.level;
+        write_u64(&mut *buf, NEXT_SPLIT, state.next_split as u64);
+        write_u64(&mut *buf, STORED_BYTES, state.stored_bytes);
+        Ok(())
+    }
+
+    fn bucket_for(&self, key: &[u8]) -> Result<PageId, HashIndexError> {
+        let state = self.state()?;
+        self.directory().get(self.store, state.address(hash_key(key)))
+    }
+
+    /// Split the bucket at the split pointer if the table is over its load factor.
+    fn maybe_split(&self, mut state: State) -> Result<(), HashIndexError> {
+        let capacity = (state.bucket_count() * PAGE_SIZE * LOAD_FACTOR / 100) as u64;
+        if state.stored_bytes <= capacity {
+            return self.set_state(&state)
+        }
+
+        let directory = self.directory();
+        let target = state.next_split + state.base();
+        if !directory.reserve(self.store, &self.allocator, target + 1)? {
+            return self.set_state(&state)
+        }
+        let head = directory.get(self.store, state.next_split)?;
+        let modulus = state.base() as u64 * 2;
+        let (moved, stay): (Vec<_>, Vec<_>) = bucket::entries(self.store, head)?
+            .into_iter()
+            .partition(|(k, _)| (hash_key(k) % modulus) as usize == target);
+        let sibling = bucket::create(self.store, &self.allocator, 0)?;
+        bucket::rewrite(self.store, &self.allocator, head, 0, &stay)?;
+        bucket::rewrite(self.store, &self.allocator, sibling, 0, &moved)?;
+        directory.set(self.store, target, sibling)?;
+
+        state.next_split += 1;
+        if state.next_split == state.base() {
+            state.level += 1;
+            state.next_split = 0;
+        }
+        self.set_state(&state)
+    }
+}
+impl<S: Storage> HashIndex for LinearHash<'_, S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        bucket::get(self.store, self.bucket_for(key)?, key)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        bucket::check_entry(key, value)?;
+        let head = self.bucket_for(key)?;
+        let old = match bucket::insert(self.store, &self.allocator, head, key, value, true)? {
+            Insert::Done(old) => old,
+            Insert::Full => unreachable!("overflow is always allowed"),
+        };
+        let mut state = self.state()?;
+        state.stored_bytes += (key.len() + value.len()) as u64;
+        if let Some(old) = &old {
+            state.stored_bytes -= (key.len() + old.len()) as u64;
+        }
+        self.maybe_split(state)?;
+        Ok(old)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError> {
+        let old = bucket::remove(self.store, self.bucket_for(key)?, key)?;
+        if let Some(old) = &old {
+            let mut state = self.state()?;
+            state.stored_bytes -= (key.len() + old.len()) as u64;
+            self.set_state(&state)?;
+        }
+        Ok(old)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::hash::{HashIndex, HashIndexError};
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{LinearHash, INITIAL_BUCKETS};
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("order:{i}").into_bytes()
+    }
+
+    #[test]
+    fn test_grows_one_bucket_at_a_time() -> Result<(), HashIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = LinearHash::create(&store, allocator)?;
+
+        let mut counts = vec![index.bucket_count()?];
+        for i in 0..6000 {
+            assert_eq!(index.insert(&key(i), &i.to_le_bytes().repeat(8))?, None);
+            counts.push(index.bucket_count()?);
+        }
+        assert_eq!(counts[0], INITIAL_BUCKETS);
+        assert!(counts.windows(2).all(|w| w[1] - w[0] <= 1));
+        assert!(*counts.last().unwrap() > 50);
+
+        for i in 0..6000 {
+            assert_eq!(index.get(&key(i))?, Some(i.to_le_bytes().repeat(8)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_delete_and_reopen() -> Result<(), HashIndexError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let index = LinearHash::create(&store, allocator)?;
+        for i in 0..1000 {
+            index.insert(&key(i), b"v1")?;
+        }
+        assert_eq!(index.insert(&key(3), b"v2")?, Some(b"v1".to_vec()));
+        assert_eq!(index.delete(&key(4))?, Some(b"v1".to_vec()));
+        assert_eq!(index.delete(&key(4))?, None);
+
+        let index = LinearHash::open(&store, allocator, index.header_page())?;
+        assert_eq!(index.get(&key(3))?, Some(b"v2".to_vec()));
+        assert_eq!(index.get(&key(4))?, None);
+        assert_eq!(index.get(&key(999))?, Some(b"v1".to_vec()));
+        Ok(())
+    }
+}
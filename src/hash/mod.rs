@@ -0,0 +1,60 @@
+//! Hash-based point indexes.
+//!
+//! Every variant stores its entries in the shared bucket page format from `bucket` and implements
+//! `HashIndex`, so callers can treat them interchangeably when range queries are not needed. Extendible and
+//! linear hashing suit write-heavy tables; cuckoo hashing bounds every lookup at two bucket reads in exchange
+//! for costlier inserts.
+mod bucket;
+mod directory;
+pub mod cuckoo;
+pub mod extendible;
+pub mod linear;
+
+use crate::page_store::PageError;
+
+pub use cuckoo::CuckooHash;
+pub use extendible::ExtendibleHash;
+pub use linear::LinearHash;
+
+/// Point lookup interface shared by the hash index variants. Keys are unique.
+pub trait HashIndex {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError>;
+
+    /// Insert or replace the value for `key`, returning the value it replaced.
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError>;
+
+    /// Remove `key`, returning its value if it was present.
+    fn delete(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HashIndexError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HashIndexError {
+    Page(PageError),
+    EntryTooLarge,
+}
+impl From<PageError> for HashIndexError {
+    fn from(e: PageError) -> Self {
+        HashIndexError::Page(e)
+    }
+}
+
+/// Whether an entry of `key` and `value` is small enough for a hash index to hold, rather than failing with
+/// `EntryTooLarge`.
+pub fn entry_fits(key: &[u8], value: &[u8]) -> bool {
+    bucket::check_entry(key, value).is_ok()
+}
+
+/// Stable 64-bit hash of a key: FNV-1a followed by a final avalanche so the low bits are well mixed.
+/// Hashes are persisted implicitly through bucket placement, so this must never change.
+pub(crate) fn hash_key(key: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key {
+        h ^= *byte as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
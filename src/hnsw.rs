@@ -0,0 +1,601 @@
+//! HNSW index for approximate nearest-neighbour search over float vectors.
+//!
+//! A hierarchical navigable small world graph (Malkov and Yashunin) links each vector to its near
+//! neighbours on layer 0 and on every layer up to a level drawn at random, with exponentially fewer
+//! vectors on each layer up. Searches start at the top layer's entry point, descend greedily to layer 0
+//! and finish with a best-first search there that keeps the `ef` closest vectors seen; larger `ef`
+//! trades speed for recall. Inserts search the same way with `ef_construction` and link the new vector
+//! to up to `m` neighbours per layer, `2 * m` on layer 0, chosen with the paper's diversity heuristic.
+//!
+//! Vectors are numbered in insertion order. Node records, holding the caller's id, the vector and its
+//! layer 0 links, are packed end to end across data pages, which records may straddle, so any dimension
+//! fits. Links for the upper layers are kept the same way in a second array with one record per node and
+//! layer. Each array lists its data pages in a chain of directory pages, which is read into memory when
+//! the index is opened. A meta page records the parameters, the entry point and the array heads.
+//! Vectors cannot be removed.
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+
+const META_MAGIC: u32 = 0x484e_5357;
+const DIM: usize = 4;
+const M: usize = 8;
+const EF_CONSTRUCTION: usize = 10;
+const METRIC: usize = 12;
+const MAX_LEVEL: usize = 13;
+const COUNT: usize = 16;
+const UPPER_COUNT: usize = 24;
+const ENTRY: usize = 32;
+const RANDOM: usize = 40;
+const NODE_HEAD: usize = 48;
+const UPPER_HEAD: usize = 56;
+const NO_ENTRY: u64 = u64::MAX;
+
+const DIRECTORY_MAGIC: u32 = 0x484e_5344;
+const NEXT: usize = 8;
+const PAGE_COUNT: usize = 16;
+const PAGES: usize = 24;
+const PAGES_PER_DIRECTORY: usize = (PAGE_SIZE - PAGES) / 8;
+const NO_PAGE: u64 = u64::MAX;
+
+/// Node record layout: the caller's id, the node's level, the index of its first upper layer record,
+/// then its layer 0 links as a count and `2 * m` slots, then the vector.
+const ID: usize = 0;
+const LEVEL: usize = 8;
+const UPPER: usize = 12;
+const LINKS: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum HnswError {
+    Page(PageError),
+    /// A vector does not have the index's dimension.
+    WrongDimension,
+    /// The dimension is zero, or `m` or `ef_construction` is out of range.
+    InvalidParameters,
+}
+impl From<PageError> for HnswError {
+    fn from(e: PageError) -> Self {
+        HnswError::Page(e)
+    }
+}
+
+/// How distance between vectors is measured. Smaller is closer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// Squared Euclidean distance.
+    Euclidean,
+    /// One minus the cosine of the angle between the vectors.
+    Cosine,
+}
+impl Metric {
+    fn distance(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Euclidean => a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum(),
+            Metric::Cosine => {
+                let (mut dot, mut a2, mut b2) = (0.0, 0.0, 0.0);
+                for (x, y) in a.iter().zip(b) {
+                    dot += x * y;
+                    a2 += x * x;
+                    b2 += y * y;
+                }
+                if a2 == 0.0 || b2 == 0.0 {
+                    return 1.0
+                }
+                1.0 - dot / (a2 * b2).sqrt()
+            }
+        }
+    }
+}
+
+/// Graph construction parameters, fixed when the index is created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HnswParams {
+    /// Links per node on each upper layer; layer 0 allows twice as many. Between 2 and 255.
+    pub m: usize,
+    /// Candidates kept while searching for a new vector's neighbours. At least 1, at most 65535.
+    pub ef_construction: usize,
+}
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams { m: 16, ef_construction: 200 }
+    }
+}
+
+pub struct HnswIndex<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    meta: PageId,
+    dim: usize,
+    metric: Metric,
+    params: HnswParams,
+    nodes: Segment,
+    upper: Segment,
+}
+impl<'store, S: Storage> HnswIndex<'store, S> {
+    pub fn create(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        dim: usize,
+        metric: Metric,
+        params: HnswParams,
+    ) -> Result<HnswIndex<'store, S>, HnswError> {
+        if dim == 0 || dim > u32::MAX as usize || !(2..=255).contains(&params.m) || !(1..=65535).contains(&params.ef_construction) {
+            return Err(HnswError::InvalidParameters)
+        }
+        let meta = allocator.allocate(store)?;
+        let nodes = Segment::create(store, &allocator, LINKS + 2 + 8 * params.m + 4 * dim)?;
+        let upper = Segment::create(store, &allocator, 2 + 4 * params.m)?;
+        let mut buf = meta.try_write()?;
+        write_u32(&mut *buf, 0, META_MAGIC);
+        write_u32(&mut *buf, DIM, dim as u32);
+        write_u16(&mut *buf, M, params.m as u16);
+        write_u16(&mut *buf, EF_CONSTRUCTION, params.ef_construction as u16);
+        buf[METRIC] = metric as u8;
+        write_u64(&mut *buf, ENTRY, NO_ENTRY);
+        write_u64(&mut *buf, RANDOM, 0x9e37_79b9_7f4a_7c15);
+        write_u64(&mut *buf, NODE_HEAD, nodes.head().offset() as u64);
+        write_u64(&mut *buf, UPPER_HEAD, upper.head().offset() as u64);
+        drop(buf);
+        Ok(HnswIndex { store, allocator, meta: meta.id(), dim, metric, params, nodes, upper })
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, meta: PageId) -> Result<HnswIndex<'store, S>, HnswError> {
+        let page = store.pin_page(&meta)?;
+        let buf = page.try_read()?;
+        if read_u32(&*buf, 0) != META_MAGIC {
+            return Err(HnswError::Page(PageError::WrongPageType))
+        }
+        let dim = read_u32(&*buf, DIM) as usize;
+        let metric = if buf[METRIC] == Metric::Cosine as u8 { Metric::Cosine } else { Metric::Euclidean };
+        let params = HnswParams { m: read_u16(&*buf, M) as usize, ef_construction: read_u16(&*buf, EF_CONSTRUCTION) as usize };
+        let nodes = Segment::open(store, PageId::new(read_u64(&*buf, NODE_HEAD) as usize), LINKS + 2 + 8 * params.m + 4 * dim)?;
+        let upper = Segment::open(store, PageId::new(read_u64(&*buf, UPPER_HEAD) as usize), 2 + 4 * params.m)?;
+        drop(buf);
+        Ok(HnswIndex { store, allocator, meta, dim, metric, params, nodes, upper })
+    }
+
+    /// The page to pass to `open` to find this index again.
+    pub fn meta_page(&self) -> PageId {
+        self.meta
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn params(&self) -> HnswParams {
+        self.params
+    }
+
+    /// Number of vectors in the index.
+    pub fn len(&self) -> Result<u64, HnswError> {
+        self.read_meta(COUNT)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, HnswError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Add `vector` under the caller's `id`, typically an encoded record id. Ids need not be unique.
+    pub fn insert(&mut self, id: u64, vector: &[f32]) -> Result<(), HnswError> {
+        if vector.len() != self.dim {
+            return Err(HnswError::WrongDimension)
+        }
+        let node = self.read_meta(COUNT)? as u32;
+        let level = self.random_level()?;
+        let upper = self.read_meta(UPPER_COUNT)?;
+
+        let mut record = vec![0; self.nodes.record_len];
+        write_u64(&mut record, ID, id);
+        record[LEVEL] = level;
+        write_u32(&mut record, UPPER, upper as u32);
+        for (i, x) in vector.iter().enumerate() {
+            record[self.vector_at() + 4 * i..][..4].copy_from_slice(&x.to_le_bytes());
+        }
+        self.nodes.write(self.store, &self.allocator, node as usize, 0, &record)?;
+        for i in 0..level as u64 {
+            self.upper.write(self.store, &self.allocator, (upper + i) as usize, 0, &vec![0; self.upper.record_len])?;
+        }
+        self.write_meta(COUNT, node as u64 + 1)?;
+        self.write_meta(UPPER_COUNT, upper + level as u64)?;
+
+        let entry = self.read_meta(ENTRY)?;
+        if entry == NO_ENTRY {
+            self.write_meta(ENTRY, node as u64)?;
+            return self.write_level(level)
+        }
+        let top = self.top_level()?;
+        let entry = entry as u32;
+        let cache = &mut Cache::new();
+        let mut nearest = vec![Scored { distance: self.metric.distance(vector, &self.vector(entry, cache)?), node: entry }];
+        for layer in (level as usize + 1..=top as usize).rev() {
+            nearest = self.search_layer(vector, nearest, 1, layer, cache)?;
+        }
+        for layer in (0..=level.min(top) as usize).rev() {
+            nearest = self.search_layer(vector, nearest, self.params.ef_construction, layer, cache)?;
+            let neighbours = self.select_neighbours(&nearest, self.max_links(layer), cache)?;
+            self.set_links(node, layer, &neighbours)?;
+            for &neighbour in &neighbours {
+                let mut links = self.links(neighbour, layer)?;
+                links.push(node);
+                if links.len() > self.max_links(layer) {
+                    // Re-choose the neighbour's links among the old ones and the new node.
+                    let from = self.vector(neighbour, cache)?;
+                    let mut scored = Vec::with_capacity(links.len());
+                    for link in links {
+                        scored.push(Scored { distance: self.metric.distance(&from, &self.vector(link, cache)?), node: link });
+                    }
+                    scored.sort();
+                    links = self.select_neighbours(&scored, self.max_links(layer), cache)?;
+                }
+                self.set_links(neighbour, layer, &links)?;
+            }
+        }
+        if level > top {
+            self.write_meta(ENTRY, node as u64)?;
+            self.write_level(level)?;
+        }
+        Ok(())
+    }
+
+    /// The ids and distances of about the `k` vectors nearest `query`, nearest first. The search keeps the
+    /// best `ef` candidates, raised to `k` if smaller: a larger `ef` is slower but misses fewer.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Result<Vec<(u64, f32)>, HnswError> {
+        if query.len() != self.dim {
+            return Err(HnswError::WrongDimension)
+        }
+        let entry = self.read_meta(ENTRY)?;
+        if entry == NO_ENTRY || k == 0 {
+            return Ok(Vec::new())
+        }
+        let entry = entry as u32;
+        let cache = &mut Cache::new();
+        let mut nearest = vec![Scored { distance: self.metric.distance(query, &self.vector(entry, cache)?), node: entry }];
+        for layer in (1..=self.top_level()? as usize).rev() {
+            nearest = self.search_layer(query, nearest, 1, layer, cache)?;
+        }
+        nearest = self.search_layer(query, nearest, ef.max(k), 0, cache)?;
+        nearest.truncate(k);
+        nearest.into_iter().map(|s| Ok((self.id(s.node)?, s.distance))).collect()
+    }
+
+    /// Best-first search of one layer from `entries`, returning the `ef` closest nodes found, closest first.
+    fn search_layer(&self, query: &[f32], entries: Vec<Scored>, ef: usize, layer: usize, cache: &mut Cache) -> Result<Vec<Scored>, HnswError> {
+        let mut visited: HashSet<u32> = entries.iter().map(|s| s.node).collect();
+        let mut candidates: BinaryHeap<Reverse<Scored>> = entries.iter().copied().map(Reverse).collect();
+        let mut found: BinaryHeap<Scored> = entries.into_iter().collect();
+        while found.len() > ef {
+            found.pop();
+        }
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            if found.len() >= ef && candidate.distance > found.peek().unwrap().distance {
+                break
+            }
+            for neighbour in self.links(candidate.node, layer)? {
+                if !visited.insert(neighbour) {
+                    continue
+                }
+                let distance = self.metric.distance(query, &self.vector(neighbour, cache)?);
+                if found.len() < ef || distance < found.peek().unwrap().distance {
+                    let scored = Scored { distance, node: neighbour };
+                    candidates.push(Reverse(scored));
+                    found.push(scored);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        Ok(found.into_sorted_vec())
+    }
+
+    /// Up to `m` of `candidates`, which are sorted closest first, preferring ones closer to the query than
+    /// to any already chosen so that links reach out in different directions. Candidates passed over fill
+    /// any places left.
+    fn select_neighbours(&self, candidates: &[Scored], m: usize, cache: &mut Cache) -> Result<Vec<u32>, HnswError> {
+        let mut chosen: Vec<(u32, Rc<[f32]>)> = Vec::with_capacity(m);
+        let mut passed = Vec::new();
+        for candidate in candidates {
+            if chosen.len() == m {
+                break
+            }
+            let vector = self.vector(candidate.node, cache)?;
+            if chosen.iter().all(|(_, c)| self.metric.distance(&vector, c) > candidate.distance) {
+                chosen.push((candidate.node, vector));
+            } else {
+                passed.push(candidate.node);
+            }
+        }
+        let mut chosen: Vec<u32> = chosen.into_iter().map(|(node, _)| node).collect();
+        let room = m - chosen.len();
+        chosen.extend(passed.into_iter().take(room));
+        Ok(chosen)
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 { 2 * self.params.m } else { self.params.m }
+    }
+
+    fn vector_at(&self) -> usize {
+        LINKS + 2 + 8 * self.params.m
+    }
+
+    fn vector(&self, node: u32, cache: &mut Cache) -> Result<Rc<[f32]>, HnswError> {
+        if let Some(vector) = cache.get(&node) {
+            return Ok(vector.clone())
+        }
+        let mut bytes = vec![0; 4 * self.dim];
+        self.nodes.read(self.store, node as usize, self.vector_at(), &mut bytes)?;
+        let vector: Rc<[f32]> = bytes.chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        cache.insert(node, vector.clone());
+        Ok(vector)
+    }
+
+    fn id(&self, node: u32) -> Result<u64, HnswError> {
+        let mut bytes = [0; 8];
+        self.nodes.read(self.store, node as usize, ID, &mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Where `node`'s links for `layer` are: the array, the record and the offset within it.
+    fn links_location(&self, node: u32, layer: usize) -> Result<(&Segment, usize, usize), HnswError> {
+        if layer == 0 {
+            return Ok((&self.nodes, node as usize, LINKS))
+        }
+        let mut upper = [0; 4];
+        self.nodes.read(self.store, node as usize, UPPER, &mut upper)?;
+        Ok((&self.upper, u32::from_le_bytes(upper) as usize + layer - 1, 0))
+    }
+
+    fn links(&self, node: u32, layer: usize) -> Result<Vec<u32>, HnswError> {
+        let (segment, index, at) = self.links_location(node, layer)?;
+        let mut bytes = vec![0; 2 + 4 * self.max_links(layer)];
+        segment.read(self.store, index, at, &mut bytes)?;
+        let count = read_u16(&bytes, 0) as usize;
+        Ok((0..count).map(|i| read_u32(&bytes, 2 + 4 * i)).collect())
+    }
+
+    fn set_links(&mut self, node: u32, layer: usize, links: &[u32]) -> Result<(), HnswError> {
+        let (_, index, at) = self.links_location(node, layer)?;
+        let mut bytes = vec![0; 2 + 4 * links.len()];
+        write_u16(&mut bytes, 0, links.len() as u16);
+        for (i, link) in links.iter().enumerate() {
+            write_u32(&mut bytes, 2 + 4 * i, *link);
+        }
+        let segment = if layer == 0 { &mut self.nodes } else { &mut self.upper };
+        segment.write(self.store, &self.allocator, index, at, &bytes)?;
+        Ok(())
+    }
+
+    /// Draw a level with probability falling by a factor of `m` per level, as the paper recommends.
+    fn random_level(&self) -> Result<u8, HnswError> {
+        let mut random = self.read_meta(RANDOM)?;
+        random ^= random << 13;
+        random ^= random >> 7;
+        random ^= random << 17;
+        self.write_meta(RANDOM, random)?;
+        let uniform = ((random >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        Ok((-uniform.ln() / (self.params.m as f64).ln()).min(u8::MAX as f64) as u8)
+    }
+
+    fn top_level(&self) -> Result<u8, HnswError> {
+        let page = self.store.pin_page(&self.meta)?;
+        let level = page.try_read()?[MAX_LEVEL];
+        Ok(level)
+    }
+
+    fn write_level(&self, level: u8) -> Result<(), HnswError> {
+        let page = self.store.pin_page(&self.meta)?;
+        page.try_write()?[MAX_LEVEL] = level;
+        Ok(())
+    }
+
+    fn read_meta(&self, at: usize) -> Result<u64, HnswError> {
+        let page = self.store.pin_page(&self.meta)?;
+        let value = read_u64(&*page.try_read()?, at);
+        Ok(value)
+    }
+
+    fn write_meta(&self, at: usize, value: u64) -> Result<(), HnswError> {
+        let page = self.store.pin_page(&self.meta)?;
+        write_u64(&mut *page.try_write()?, at, value);
+        Ok(())
+    }
+}
+
+/// A growable array of fixed-length records packed end to end over data pages, so a record can straddle
+/// a page boundary. The data pages are listed, in order, in a chain of directory pages.
+struct Segment {
+    record_len: usize,
+    directories: Vec<PageId>,
+    pages: Vec<PageId>,
+}
+impl Segment {
+    fn create<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator, record_len: usize) -> Result<Segment, PageError> {
+        let head = new_directory(store, allocator)?;
+        Ok(Segment { record_len, directories: vec![head], pages: Vec::new() })
+    }
+
+    fn open<S: Storage>(store: &PageStore<S>, head: PageId, record_len: usize) -> Result<Segment, PageError> {
+        let mut segment = Segment { record_len, directories: Vec::new(), pages: Vec::new() };
+        let mut next = head.offset() as u64;
+        while next != NO_PAGE {
+            let page = store.pin_page(&PageId::new(next as usize))?;
+            let buf = page.try_read()?;
+            if read_u32(&*buf, 0) != DIRECTORY_MAGIC {
+                return Err(PageError::WrongPageType)
+            }
+            let count = read_u16(&*buf, PAGE_COUNT) as usize;
+            segment.pages.extend((0..count).map(|i| PageId::new(read_u64(&*buf, PAGES + 8 * i) as usize)));
+            segment.directories.push(page.id());
+            next = read_u64(&*buf, NEXT);
+        }
+        Ok(segment)
+    }
+
+    fn head(&self) -> PageId {
+        self.directories[0]
+    }
+
+    /// Fill `out` from record `index`, starting `at` bytes into it. The record must have been written.
+    fn read<S: Storage>(&self, store: &PageStore<S>, index: usize, at: usize, out: &mut [u8]) -> Result<(), PageError> {
+        let mut offset = index * self.record_len + at;
+        let mut done = 0;
+        while done < out.len() {
+            let (page, within) = (offset / PAGE_SIZE, offset % PAGE_SIZE);
+            let take = (out.len() - done).min(PAGE_SIZE - within);
+            let pinned = store.pin_page(&self.pages[page])?;
+            out[done..done + take].copy_from_slice(&pinned.try_read()?[within..within + take]);
+            done += take;
+            offset += take;
+        }
+        Ok(())
+    }
+
+    /// Write `data` into record `index`, starting `at` bytes into it, adding pages as needed.
+    fn write<S: Storage>(&mut self, store: &PageStore<S>, allocator: &PageAllocator, index: usize, at: usize, data: &[u8]) -> Result<(), PageError> {
+        let mut offset = index * self.record_len + at;
+        let end = offset + data.len();
+        while self.pages.len() * PAGE_SIZE < end {
+            self.add_page(store, allocator)?;
+        }
+        let mut done = 0;
+        while done < data.len() {
+            let (page, within) = (offset / PAGE_SIZE, offset % PAGE_SIZE);
+            let take = (data.len() - done).min(PAGE_SIZE - within);
+            let pinned = store.pin_page(&self.pages[page])?;
+            pinned.try_write()?[within..within + take].copy_from_slice(&data[done..done + take]);
+            done += take;
+            offset += take;
+        }
+        Ok(())
+    }
+
+    fn add_page<S: Storage>(&mut self, store: &PageStore<S>, allocator: &PageAllocator) -> Result<(), PageError> {
+        if self.pages.len() == self.directories.len() * PAGES_PER_DIRECTORY {
+            let directory = new_directory(store, allocator)?;
+            let last = store.pin_page(self.directories.last().unwrap())?;
+            write_u64(&mut *last.try_write()?, NEXT, directory.offset() as u64);
+            self.directories.push(directory);
+        }
+        let page = allocator.allocate(store)?.id();
+        let directory = store.pin_page(self.directories.last().unwrap())?;
+        let mut buf = directory.try_write()?;
+        let count = self.pages.len() % PAGES_PER_DIRECTORY;
+        write_u64(&mut *buf, PAGES + 8 * count, page.offset() as u64);
+        write_u16(&mut *buf, PAGE_COUNT, count as u16 + 1);
+        self.pages.push(page);
+        Ok(())
+    }
+}
+
+fn new_directory<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator) -> Result<PageId, PageError> {
+    let page = allocator.allocate(store)?;
+    let mut buf = page.try_write()?;
+    write_u32(&mut *buf, 0, DIRECTORY_MAGIC);
+    write_u64(&mut *buf, NEXT, NO_PAGE);
+    Ok(page.id())
+}
+
+/// Vectors already read during one insert or search, by node.
+type Cache = HashMap<u32, Rc<[f32]>>;
+
+/// A node with its distance from the vector being searched for, ordered by distance.
+#[derive(Debug, Clone, Copy)]
+struct Scored {
+    distance: f32,
+    node: u32,
+}
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Scored {}
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then(self.node.cmp(&other.node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{HnswError, HnswIndex, HnswParams, Metric};
+
+    fn vectors(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            (x >> 40) as f32 / (1u64 << 24) as f32
+        };
+        (0..n).map(|_| (0..dim).map(|_| next()).collect()).collect()
+    }
+
+    #[test]
+    fn test_search_recall_matches_brute_force() -> Result<(), HnswError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let params = HnswParams { m: 8, ef_construction: 64 };
+        let mut index = HnswIndex::create(&store, allocator, 12, Metric::Euclidean, params)?;
+        let data = vectors(1000, 12, 7);
+        for (i, v) in data.iter().enumerate() {
+            index.insert(i as u64, v)?;
+        }
+        assert_eq!(index.insert(0, &[1.0]), Err(HnswError::WrongDimension));
+
+        let index = HnswIndex::open(&store, allocator, index.meta_page())?;
+        assert_eq!((index.len()?, index.dim(), index.params()), (1000, 12, params));
+        let mut hits = 0;
+        for query in vectors(30, 12, 99) {
+            let found = index.search(&query, 10, 50)?;
+            assert_eq!(found.len(), 10);
+            assert!(found.windows(2).all(|w| w[0].1 <= w[1].1));
+            let mut exact: Vec<_> = data.iter().enumerate().map(|(i, v)| (Metric::Euclidean.distance(&query, v), i as u64)).collect();
+            exact.sort_by(|a, b| a.0.total_cmp(&b.0));
+            hits += exact[..10].iter().filter(|(_, id)| found.iter().any(|(f, _)| f == id)).count();
+        }
+        assert!(hits >= 30 * 10 * 9 / 10, "recall {hits}/300");
+        // An exact match is found at distance zero.
+        assert_eq!(index.search(&data[567], 1, 20)?, vec![(567, 0.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wide_vectors_and_cosine() -> Result<(), HnswError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        assert!(HnswIndex::create(&store, allocator, 0, Metric::Cosine, HnswParams::default()).is_err());
+        // Each record is longer than a page.
+        let params = HnswParams { m: 6, ef_construction: 32 };
+        let mut index = HnswIndex::create(&store, allocator, 1536, Metric::Cosine, params)?;
+        assert_eq!(index.search(&vec![0.0; 1536], 5, 10)?, vec![]);
+        let data = vectors(200, 1536, 3);
+        for (i, v) in data.iter().enumerate() {
+            index.insert(100 + i as u64, v)?;
+        }
+        // Scaling a vector keeps its direction, so it is still its own nearest neighbour.
+        let scaled: Vec<f32> = data[42].iter().map(|x| x * 3.0).collect();
+        let found = index.search(&scaled, 3, 40)?;
+        assert_eq!(found[0].0, 142);
+        assert!(found[0].1.abs() < 1e-5);
+        Ok(())
+    }
+}
@@ -0,0 +1,672 @@
+//! JSON documents, the values of `JSON` columns.
+//!
+//! A `Json` keeps its document in a binary form that a path can be followed through without decoding
+//! anything off the path. Each value is a tag byte followed by its body:
+//!
+//! ```text
+//! | 0 null | 1 false | 2 true | 3 number | 4 string |
+//! | 5 array  | count u32 | end offset u32 per element | elements |
+//! | 6 object | count u32 | end offset u32 per key | end offset u32 per value | keys | values |
+//! ```
+//!
+//! A number is an exact decimal as `Decimal::write` writes it, with no trailing zeros after the point, and
+//! a string is its UTF-8, taking the rest of the value, whose length its container knows. End offsets are
+//! from the start of the elements, or of the keys, so that element `i` sits between the ends of `i - 1`
+//! and `i`. An object's keys are stored as plain UTF-8, sorted by their bytes with no duplicates, so a
+//! key is found by binary search; when a document repeats a key, the last value given wins.
+//!
+//! Every document therefore has one encoding, which is what equality, hashing and index keys go by: `1.0`
+//! equals `1`, and objects are equal whatever order their keys were written in. Documents order by their
+//! encoding too, which keeps equal ones together without being any order a person would pick.
+//!
+//! Documents show as text the way PostgreSQL shows `jsonb`, with a space after each `:` and `,`. A
+//! `JsonPath` is what the `->`, `->>`, `#>` and `#>>` operators follow, and what an index over a part of
+//! a document keys on.
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::bytes::read_u32;
+use crate::decimal::Decimal;
+use crate::tuple::{ColumnType, Value};
+use crate::varint;
+
+/// How deep arrays and objects may nest in a document.
+pub const MAX_DEPTH: usize = 256;
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const NUMBER: u8 = 3;
+const STRING: u8 = 4;
+const ARRAY: u8 = 5;
+const OBJECT: u8 = 6;
+
+/// The bytes before the end offsets of an array or object.
+const HEADER: usize = 5;
+
+/// An encoded JSON document.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Json(Vec<u8>);
+impl Json {
+    /// The document written in `text`, or `None` if it is not valid JSON, nests deeper than `MAX_DEPTH`
+    /// or has a number with more digits than a decimal may.
+    pub fn parse(text: &str) -> Option<Json> {
+        let mut parser = Parser { text: text.as_bytes(), at: 0 };
+        let tree = parser.value(0)?;
+        parser.whitespace();
+        if parser.at != text.len() {
+            return None
+        }
+        let mut bytes = Vec::new();
+        tree.encode(&mut bytes);
+        Some(Json(bytes))
+    }
+
+    /// The document whose encoding is `bytes`, or `None` if they are not the encoding of any.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Json> {
+        valid(bytes, 0).then(|| Json(bytes.to_vec()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The whole document, to follow paths from.
+    pub fn root(&self) -> Node<'_> {
+        Node(&self.0)
+    }
+}
+impl fmt::Debug for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Json({self})")
+    }
+}
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.root().fmt(f)
+    }
+}
+
+/// A value inside a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node<'a>(&'a [u8]);
+impl<'a> Node<'a> {
+    /// The value of `key`, if this is an object that has it.
+    pub fn get(self, key: &str) -> Option<Node<'a>> {
+        if self.0[0] != OBJECT {
+            return None
+        }
+        let count = read_u32(self.0, 1) as usize;
+        let (mut low, mut high) = (0, count);
+        while low < high {
+            let mid = (low + high) / 2;
+            match item(self.0, 2 * count, mid).cmp(key.as_bytes()) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Some(Node(item(self.0, 2 * count, count + mid))),
+            }
+        }
+        None
+    }
+
+    /// Element `index`, counting back from the end if it is negative, if this is an array that has it.
+    pub fn at(self, index: i64) -> Option<Node<'a>> {
+        if self.0[0] != ARRAY {
+            return None
+        }
+        let count = read_u32(self.0, 1) as usize;
+        let index = if index < 0 { count as i64 + index } else { index };
+        let index = usize::try_from(index).ok().filter(|i| *i < count)?;
+        Some(Node(item(self.0, count, index)))
+    }
+
+    /// The value `step` leads to from this one, if there is one.
+    pub fn step(self, step: &Step) -> Option<Node<'a>> {
+        match step {
+            Step::Key(key) if self.0[0] == ARRAY => self.at(key.parse().ok()?),
+            Step::Key(key) => self.get(key),
+            Step::Index(index) => self.at(*index),
+        }
+    }
+
+    /// The value as a document of its own.
+    pub fn to_json(self) -> Json {
+        Json(self.0.to_vec())
+    }
+
+    /// The value as `->>` gives it: a string's own text, nothing for null, and otherwise the value shown as
+    /// JSON.
+    pub fn to_text(self) -> Option<String> {
+        match self.0[0] {
+            NULL => None,
+            STRING => Some(text(&self.0[1..]).to_string()),
+            _ => Some(self.to_string()),
+        }
+    }
+}
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.0;
+        match bytes[0] {
+            NULL => f.write_str("null"),
+            FALSE => f.write_str("false"),
+            TRUE => f.write_str("true"),
+            NUMBER => write!(f, "{}", Decimal::read(&bytes[1..]).expect("documents hold valid numbers").0),
+            STRING => write_string(f, text(&bytes[1..])),
+            ARRAY => {
+                let count = read_u32(bytes, 1) as usize;
+                f.write_str("[")?;
+                for i in 0..count {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    Node(item(bytes, count, i)).fmt(f)?;
+                }
+                f.write_str("]")
+            }
+            _ => {
+                let count = read_u32(bytes, 1) as usize;
+                f.write_str("{")?;
+                for i in 0..count {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write_string(f, text(item(bytes, 2 * count, i)))?;
+                    f.write_str(": ")?;
+                    Node(item(bytes, 2 * count, count + i)).fmt(f)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// One step along a path: a key of an object, or a position in an array, counting back from the end if
+/// negative. A key that spells an integer also picks that position in an array, as the elements of a
+/// `#>` path do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Key(String),
+    Index(i64),
+}
+
+/// A path into the documents of a `JSON` column, which gives the part of each document it leads to, also
+/// as JSON, or as text the way `->>` does when `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    pub steps: Vec<Step>,
+    pub text: bool,
+}
+impl JsonPath {
+    /// The type of what `extract` gives.
+    pub fn column_type(&self) -> ColumnType {
+        if self.text { ColumnType::Text } else { ColumnType::Json }
+    }
+
+    /// The part of the document `value` the path leads to, or null if `value` is not a document or the
+    /// path leads nowhere in it.
+    pub fn extract(&self, value: &Value) -> Value {
+        let Value::Json(document) = value else { return Value::Null };
+        match self.steps.iter().try_fold(document.root(), |node, step| node.step(step)) {
+            Some(node) if self.text => node.to_text().map_or(Value::Null, Value::Text),
+            Some(node) => Value::Json(node.to_json()),
+            None => Value::Null,
+        }
+    }
+
+    /// Append the path as the catalog stores it: its number of steps shifted left with `text` in the low
+    /// bit, then each step as a byte, 0 for a key followed by its length-prefixed UTF-8 and 1 for a
+    /// position followed by it as a zigzag varint.
+    pub(crate) fn write(&self, buf: &mut Vec<u8>) {
+        varint::write_u64(buf, (self.steps.len() as u64) << 1 | self.text as u64);
+        for step in &self.steps {
+            match step {
+                Step::Key(key) => {
+                    buf.push(0);
+                    varint::write_prefixed(buf, key.as_bytes());
+                }
+                Step::Index(index) => {
+                    buf.push(1);
+                    varint::write_i64(buf, *index);
+                }
+            }
+        }
+    }
+
+    /// Decode a path written by `write` from the start of `buf`, returning it and the number of bytes it
+    /// took.
+    pub(crate) fn read(buf: &[u8]) -> Option<(JsonPath, usize)> {
+        let (header, mut at) = varint::read_u64(buf)?;
+        let mut steps = Vec::new();
+        for _ in 0..header >> 1 {
+            let tag = *buf.get(at)?;
+            at += 1;
+            let (step, len) = match tag {
+                0 => {
+                    let (key, len) = varint::read_prefixed(&buf[at..])?;
+                    (Step::Key(String::from_utf8(key.to_vec()).ok()?), len)
+                }
+                1 => varint::read_i64(&buf[at..]).map(|(index, len)| (Step::Index(index), len))?,
+                _ => return None,
+            };
+            steps.push(step);
+            at += len;
+        }
+        Some((JsonPath { steps, text: header & 1 == 1 }, at))
+    }
+}
+
+/// The steps of a path written as a text array, as in `{a,0,"b c"}`, the right side of `#>` and `#>>`,
+/// or `None` if it is not one. Elements may be double-quoted, with a backslash escaping the character
+/// after it, and are otherwise trimmed of surrounding whitespace.
+pub fn parse_path(text: &str) -> Option<Vec<Step>> {
+    let inner = text.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut steps = Vec::new();
+    if inner.trim().is_empty() {
+        return Some(steps)
+    }
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut element = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => element.push(chars.next()?),
+                    c => element.push(c),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                if matches!(c, '"' | '{' | '}' | '\\') {
+                    return None
+                }
+                element.push(c);
+            }
+            element.truncate(element.trim_end().len());
+            if element.is_empty() {
+                return None
+            }
+        }
+        steps.push(Step::Key(element));
+        match chars.next() {
+            None => return Some(steps),
+            Some(',') => {}
+            Some(_) => return None,
+        }
+    }
+}
+
+/// A parsed document before it is encoded.
+enum Tree {
+    Null,
+    Bool(bool),
+    Number(Decimal),
+    String(String),
+    Array(Vec<Tree>),
+    Object(BTreeMap<String, Tree>),
+}
+impl Tree {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Tree::Null => out.push(NULL),
+            Tree::Bool(v) => out.push(if *v { TRUE } else { FALSE }),
+            Tree::Number(v) => {
+                out.push(NUMBER);
+                v.write(out);
+            }
+            Tree::String(v) => {
+                out.push(STRING);
+                out.extend_from_slice(v.as_bytes());
+            }
+            Tree::Array(elements) => {
+                let elements: Vec<Vec<u8>> = elements.iter().map(Tree::encoded).collect();
+                container(out, ARRAY, elements.len(), &elements);
+            }
+            Tree::Object(entries) => {
+                let keys = entries.keys().map(|key| key.as_bytes().to_vec());
+                let items: Vec<Vec<u8>> = keys.chain(entries.values().map(Tree::encoded)).collect();
+                container(out, OBJECT, entries.len(), &items);
+            }
+        }
+    }
+
+    fn encoded(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+/// Append an array or object of `count` elements or entries, whose encoded items are `items`.
+fn container(out: &mut Vec<u8>, tag: u8, count: usize, items: &[Vec<u8>]) {
+    out.push(tag);
+    out.extend_from_slice(&(count as u32).to_le_bytes());
+    let mut end = 0;
+    for item in items {
+        end += item.len();
+        out.extend_from_slice(&(end as u32).to_le_bytes());
+    }
+    items.iter().for_each(|item| out.extend_from_slice(item));
+}
+
+/// Item `i` of the `items` of the array or object `bytes`.
+fn item(bytes: &[u8], items: usize, i: usize) -> &[u8] {
+    let data = HEADER + 4 * items;
+    let start = if i == 0 { 0 } else { read_u32(bytes, HEADER + 4 * (i - 1)) as usize };
+    &bytes[data + start..data + read_u32(bytes, HEADER + 4 * i) as usize]
+}
+
+fn text(bytes: &[u8]) -> &str {
+    std::str::from_utf8(bytes).expect("documents hold valid UTF-8")
+}
+
+/// `s` as a JSON string: quoted, with what JSON cannot hold as it is escaped.
+pub fn quote(s: &str) -> String {
+    struct Quoted<'a>(&'a str);
+    impl fmt::Display for Quoted<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write_string(f, self.0)
+        }
+    }
+    Quoted(s).to_string()
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\u{8}' => f.write_str("\\b")?,
+            '\u{c}' => f.write_str("\\f")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Whether `bytes` are the encoding of a value nested `depth` deep, in the one form `Json::parse` gives.
+fn valid(bytes: &[u8], depth: usize) -> bool {
+    let Some((&tag, body)) = bytes.split_first() else { return false };
+    match tag {
+        NULL | FALSE | TRUE => body.is_empty(),
+        NUMBER => match Decimal::read(body) {
+            Some((v, len)) => len == body.len() && v.normalize().scale() == v.scale(),
+            None => false,
+        },
+        STRING => std::str::from_utf8(body).is_ok(),
+        ARRAY | OBJECT if depth < MAX_DEPTH && bytes.len() >= HEADER => {
+            let count = read_u32(bytes, 1) as usize;
+            let (items, keys) = if tag == OBJECT { (count.saturating_mul(2), count) } else { (count, 0) };
+            let Some(data) = items.checked_mul(4).map(|len| HEADER + len).filter(|data| *data <= bytes.len()) else {
+                return false
+            };
+            let mut start = 0;
+            for i in 0..items {
+                let end = read_u32(bytes, HEADER + 4 * i) as usize;
+                if end < start || data + end > bytes.len() {
+                    return false
+                }
+                start = end;
+            }
+            if data + start != bytes.len() {
+                return false
+            }
+            (1..keys).all(|i| item(bytes, items, i - 1) < item(bytes, items, i))
+                && (0..keys).all(|i| std::str::from_utf8(item(bytes, items, i)).is_ok())
+                && (keys..items).all(|i| valid(item(bytes, items, i), depth + 1))
+        }
+        _ => false,
+    }
+}
+
+/// A recursive descent parser over the text of a document.
+struct Parser<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while matches!(self.text.get(self.at), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.at += 1;
+        }
+    }
+
+    /// Move past `byte` if it is next.
+    fn eat(&mut self, byte: u8) -> bool {
+        let next = self.text.get(self.at) == Some(&byte);
+        self.at += next as usize;
+        next
+    }
+
+    /// Move past `byte` if it is next after any whitespace.
+    fn symbol(&mut self, byte: u8) -> bool {
+        self.whitespace();
+        self.eat(byte)
+    }
+
+    fn word(&mut self, word: &str) -> bool {
+        let next = self.text[self.at..].starts_with(word.as_bytes());
+        self.at += if next { word.len() } else { 0 };
+        next
+    }
+
+    fn value(&mut self, depth: usize) -> Option<Tree> {
+        self.whitespace();
+        match *self.text.get(self.at)? {
+            b'n' if self.word("null") => Some(Tree::Null),
+            b't' if self.word("true") => Some(Tree::Bool(true)),
+            b'f' if self.word("false") => Some(Tree::Bool(false)),
+            b'"' => self.string().map(Tree::String),
+            b'-' | b'0'..=b'9' => self.number().map(Tree::Number),
+            b'[' if depth < MAX_DEPTH => {
+                self.at += 1;
+                let mut elements = Vec::new();
+                if !self.symbol(b']') {
+                    loop {
+                        elements.push(self.value(depth + 1)?);
+                        if self.symbol(b']') {
+                            break
+                        }
+                        if !self.symbol(b',') {
+                            return None
+                        }
+                    }
+                }
+                Some(Tree::Array(elements))
+            }
+            b'{' if depth < MAX_DEPTH => {
+                self.at += 1;
+                let mut entries = BTreeMap::new();
+                if !self.symbol(b'}') {
+                    loop {
+                        self.whitespace();
+                        if self.text.get(self.at) != Some(&b'"') {
+                            return None
+                        }
+                        let key = self.string()?;
+                        if !self.symbol(b':') {
+                            return None
+                        }
+                        entries.insert(key, self.value(depth + 1)?);
+                        if self.symbol(b'}') {
+                            break
+                        }
+                        if !self.symbol(b',') {
+                            return None
+                        }
+                    }
+                }
+                Some(Tree::Object(entries))
+            }
+            _ => None,
+        }
+    }
+
+    /// A number, in JSON's stricter syntax than `Decimal::parse` takes.
+    fn number(&mut self) -> Option<Decimal> {
+        let start = self.at;
+        let digits = |parser: &mut Self| {
+            let from = parser.at;
+            while parser.text.get(parser.at).is_some_and(u8::is_ascii_digit) {
+                parser.at += 1;
+            }
+            parser.at - from
+        };
+        self.eat(b'-');
+        let whole = digits(self);
+        if whole == 0 || whole > 1 && self.text[self.at - whole] == b'0' {
+            return None
+        }
+        if self.eat(b'.') && digits(self) == 0 {
+            return None
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            if digits(self) == 0 {
+                return None
+            }
+        }
+        let number = std::str::from_utf8(&self.text[start..self.at]).ok()?;
+        Decimal::parse(number).map(|v| v.normalize())
+    }
+
+    /// A string, starting at its opening quote.
+    fn string(&mut self) -> Option<String> {
+        self.at += 1;
+        let mut string = String::new();
+        loop {
+            let start = self.at;
+            while self.text.get(self.at).is_some_and(|b| !matches!(b, b'"' | b'\\') && *b >= 0x20) {
+                self.at += 1;
+            }
+            string.push_str(std::str::from_utf8(&self.text[start..self.at]).ok()?);
+            match *self.text.get(self.at)? {
+                b'"' => {
+                    self.at += 1;
+                    return Some(string)
+                }
+                b'\\' => {
+                    let escape = *self.text.get(self.at + 1)?;
+                    self.at += 2;
+                    string.push(match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return None,
+                    });
+                }
+                // Control characters must be escaped.
+                _ => return None,
+            }
+        }
+    }
+
+    /// The character of a `\u` escape, after the `\u`, taking the second half of a surrogate pair too.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high)
+        }
+        if !self.word("\\u") {
+            return None
+        }
+        let low = self.hex().filter(|low| (0xdc00..0xe000).contains(low))?;
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+
+    fn hex(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.at..self.at + 4)?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return None
+        }
+        self.at += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tuple::Value;
+
+    use super::{parse_path, Json, JsonPath, Step};
+
+    fn json(text: &str) -> Json {
+        Json::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let document = json(r#" {"b": [1, 2.50, -0.0, 1e2], "a": {"x": null, "y": true}, "b": "last", "c": []} "#);
+        assert_eq!(document.to_string(), r#"{"a": {"x": null, "y": true}, "b": "last", "c": []}"#);
+        assert_eq!(json("[1.50, -0, 1E+2, 0.5e-1]").to_string(), "[1.5, 0, 100, 0.05]");
+        assert_eq!(json(r#""tab\t \"q\" \u00e9 \ud83d\ude00 \u0001""#).to_string(), "\"tab\\t \\\"q\\\" é 😀 \\u0001\"");
+        assert_eq!(json(r#"{"b": 1, "a": 2}"#), json(r#"{"a":2,"b":1.0}"#));
+
+        let deep = "[".repeat(super::MAX_DEPTH) + &"]".repeat(super::MAX_DEPTH);
+        assert!(Json::parse(&deep).is_some());
+        let bad = ["", "01", "1.", "-", ".5", "[1,]", "{\"a\"}", "{a: 1}", "\"\n\"", "\"\\x\"", "[1] 2", "\"\\ud800\""];
+        for text in bad.into_iter().map(String::from).chain([format!("[{deep}]")]) {
+            assert_eq!(Json::parse(&text), None, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn test_encoding_is_checked() {
+        let document = json(r#"{"a": [1, "two", {"three": 3}], "b": false}"#);
+        assert_eq!(Json::from_bytes(document.as_bytes()), Some(document.clone()));
+        let bytes = document.as_bytes();
+        for len in 0..bytes.len() {
+            assert_eq!(Json::from_bytes(&bytes[..len]), None);
+        }
+        // Swapping the keys unsorts them.
+        let swapped = json(r#"{"a": 1, "b": 1}"#).as_bytes().to_vec();
+        let at = swapped.iter().position(|b| *b == b'a').unwrap();
+        let mut unsorted = swapped.clone();
+        unsorted.swap(at, at + 1);
+        assert_eq!(Json::from_bytes(&unsorted), None);
+    }
+
+    #[test]
+    fn test_paths() {
+        let document = Value::Json(json(r#"{"a": {"b": [10, "x", null]}, "3": "three", "s": "text"}"#));
+        let extract = |steps: Vec<Step>, text| JsonPath { steps, text }.extract(&document);
+        let key = |k: &str| Step::Key(k.to_string());
+        assert_eq!(extract(vec![key("a"), key("b"), Step::Index(0)], false), Value::Json(json("10")));
+        assert_eq!(extract(vec![key("a"), key("b"), Step::Index(-2)], true), Value::Text("x".to_string()));
+        assert_eq!(extract(vec![key("a"), key("b"), key("1")], true), Value::Text("x".to_string()));
+        assert_eq!(extract(vec![key("a"), key("b"), Step::Index(2)], false), Value::Json(json("null")));
+        assert_eq!(extract(vec![key("a"), key("b"), Step::Index(2)], true), Value::Null);
+        assert_eq!(extract(vec![key("a")], true), Value::Text(r#"{"b": [10, "x", null]}"#.to_string()));
+        assert_eq!(extract(vec![key("3")], true), Value::Text("three".to_string()));
+        assert_eq!(extract(vec![key("s"), Step::Index(0)], false), Value::Null);
+        assert_eq!(extract(vec![key("missing")], false), Value::Null);
+        assert_eq!(extract(Vec::new(), false), document);
+
+        assert_eq!(parse_path(r#"{a, 0 ,"b,\"c"}"#), Some(vec![key("a"), key("0"), key("b,\"c")]));
+        assert_eq!(parse_path("{}"), Some(Vec::new()));
+        assert_eq!(parse_path("{a,,b}"), None);
+        assert_eq!(parse_path("a,b"), None);
+
+        let path = JsonPath { steps: vec![key("a"), Step::Index(-1)], text: true };
+        let mut buf = Vec::new();
+        path.write(&mut buf);
+        assert_eq!(JsonPath::read(&buf), Some((path, buf.len())));
+    }
+}
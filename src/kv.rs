@@ -0,0 +1,347 @@
+//! Keyspaces: ordered key-value maps of byte strings, for using the store as a sorted map without SQL.
+//!
+//! A keyspace is a B+tree of its own, named in the catalog beside the tables, views and sequences and
+//! sharing their names, so keyspaces and SQL tables live in the same store. Keys are ordered bytewise and
+//! must fit in a cell of the tree; a value too long to sit in a cell beside its key is written to an
+//! overflow chain the way the catalog stores long definitions, and the cell holds the chain's first page.
+//!
+//! A keyspace created with `KeyspaceOptions::lsm` is kept in an LSM tree instead, for keyspaces written far
+//! more often than they are read: its writes are appended to the tree's log and held in its memtable until
+//! they fill it, rather than each rewriting a leaf. Its values are stored as a B+tree keyspace's are, those
+//! too long for an entry of the tree in overflow chains. The memtable belongs to the handle, so an LSM
+//! keyspace is written through one handle at a time, and a handle opened again after a rollback.
+//!
+//! Writes go to the store's pages like every other write, so over a `ShadowStorage` whatever is written
+//! between two flushes commits together with the second one: a batch of puts and deletes is atomic if the
+//! store is flushed only after the last of them.
+use std::fmt;
+use std::ops::RangeBounds;
+
+use crate::allocator::PageAllocator;
+use crate::btree::{BTree, BTreeError, BTreeRange};
+use crate::bytes::{read_u64, write_u64};
+use crate::lsm::{LsmError, LsmOptions, LsmRange, LsmTree};
+use crate::overflow;
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::storage::Storage;
+
+const INLINE: u8 = 0;
+const OVERFLOW: u8 = 1;
+
+#[derive(Debug, PartialEq)]
+pub enum KvError {
+    Page(PageError),
+    /// A key too long to be a key of the tree.
+    KeyTooLong,
+    /// A stored value cannot be decoded.
+    Corrupt,
+}
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KvError::Page(e) => write!(f, "page error: {e:?}"),
+            KvError::KeyTooLong => write!(f, "key too long"),
+            KvError::Corrupt => write!(f, "corrupt value"),
+        }
+    }
+}
+impl From<PageError> for KvError {
+    fn from(e: PageError) -> Self {
+        KvError::Page(e)
+    }
+}
+impl From<BTreeError> for KvError {
+    fn from(e: BTreeError) -> Self {
+        match e {
+            BTreeError::Page(e) => KvError::Page(e),
+            BTreeError::EntryTooLarge => KvError::KeyTooLong,
+            BTreeError::Unsorted => unreachable!("keyspaces are never bulk loaded"),
+        }
+    }
+}
+impl From<LsmError> for KvError {
+    fn from(e: LsmError) -> Self {
+        match e {
+            LsmError::Page(e) => KvError::Page(e),
+            LsmError::EntryTooLarge => KvError::KeyTooLong,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KeyspaceOptions {
+    /// Keep the keyspace in an LSM tree with these options, rather than in a B+tree.
+    pub lsm: Option<LsmOptions>,
+}
+
+pub struct Keyspace<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    tree: Tree<'store, S>,
+}
+
+/// What a keyspace keeps its keys in.
+enum Tree<'store, S: Storage> {
+    BTree(BTree<'store, S>),
+    Lsm(Box<LsmTree<'store, S>>),
+}
+
+impl<'store, S: Storage> Keyspace<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<Keyspace<'store, S>, KvError> {
+        Keyspace::create_with(store, allocator, &KeyspaceOptions::default())
+    }
+
+    /// Create an empty keyspace kept as `options` asks.
+    pub fn create_with(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        options: &KeyspaceOptions,
+    ) -> Result<Keyspace<'store, S>, KvError> {
+        let tree = match options.lsm {
+            Some(lsm) => Tree::Lsm(Box::new(LsmTree::create(store, allocator, lsm)?)),
+            None => Tree::BTree(BTree::create(store, allocator)?),
+        };
+        Ok(Keyspace { store, allocator, tree })
+    }
+
+    pub fn open(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        meta: PageId,
+    ) -> Result<Keyspace<'store, S>, KvError> {
+        Keyspace::open_with(store, allocator, meta, &KeyspaceOptions::default())
+    }
+
+    /// Open the keyspace at `meta`, created with `options`.
+    pub fn open_with(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        meta: PageId,
+        options: &KeyspaceOptions,
+    ) -> Result<Keyspace<'store, S>, KvError> {
+        let tree = match options.lsm {
+            Some(lsm) => Tree::Lsm(Box::new(LsmTree::open(store, allocator, meta, lsm)?)),
+            None => Tree::BTree(BTree::open(store, allocator, meta)?),
+        };
+        Ok(Keyspace { store, allocator, tree })
+    }
+
+    /// The page to pass to `open` to find this keyspace again.
+    pub fn meta_page(&self) -> PageId {
+        match &self.tree {
+            Tree::BTree(tree) => tree.meta_page(),
+            Tree::Lsm(tree) => tree.manifest_page(),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        let stored = match &self.tree {
+            Tree::BTree(tree) => tree.get(key)?,
+            Tree::Lsm(tree) => tree.get(key)?,
+        };
+        stored.map(|stored| self.load(&stored)).transpose()
+    }
+
+    /// Set the value of `key`, returning the value it had before, if any.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        let mut inline = vec![INLINE];
+        inline.extend_from_slice(value);
+        let replaced = match self.insert(key, &inline) {
+            Err(KvError::KeyTooLong) if !value.is_empty() => {
+                let head = overflow::write(self.store, &self.allocator, value)?;
+                let mut stub = vec![OVERFLOW; 9];
+                write_u64(&mut stub, 1, head.offset() as u64);
+                match self.insert(key, &stub) {
+                    Ok(replaced) => replaced,
+                    Err(e) => {
+                        overflow::free(self.store, &self.allocator, head)?;
+                        return Err(e)
+                    }
+                }
+            }
+            result => result?,
+        };
+        replaced.map(|stored| self.release(&stored)).transpose()
+    }
+
+    /// Remove `key`, returning the value it had, if any.
+    pub fn delete(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        let deleted = match &mut self.tree {
+            Tree::BTree(tree) => tree.delete(key)?,
+            Tree::Lsm(tree) => {
+                let deleted = tree.get(key)?;
+                if deleted.is_some() {
+                    tree.delete(key)?;
+                }
+                deleted
+            }
+        };
+        deleted.map(|stored| self.release(&stored)).transpose()
+    }
+
+    /// The keys in `range` with their values, in key order.
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> KvRange<'_, 'store, S> {
+        let entries = match &self.tree {
+            Tree::BTree(tree) => Entries::BTree(tree.range(range)),
+            Tree::Lsm(tree) => Entries::Lsm(tree.range(range)),
+        };
+        KvRange { keyspace: self, entries }
+    }
+
+    /// Every key with its value, in key order.
+    pub fn iter(&self) -> KvRange<'_, 'store, S> {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// Return every page of the keyspace, its overflow chains included, to the allocator.
+    pub fn free(self) -> Result<(), KvError> {
+        for entry in self.iter().entries {
+            let (_, stored) = entry?;
+            if let Some(head) = overflow_head(&stored)? {
+                overflow::free(self.store, &self.allocator, head)?;
+            }
+        }
+        match self.tree {
+            Tree::BTree(tree) => Ok(tree.free()?),
+            Tree::Lsm(tree) => Ok(tree.free()?),
+        }
+    }
+
+    /// Write `stored` as the value of `key` in the tree, returning the value stored before, if any; with
+    /// `KeyTooLong` if the two do not fit together in an entry of the tree.
+    fn insert(&mut self, key: &[u8], stored: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        match &mut self.tree {
+            Tree::BTree(tree) => Ok(tree.insert(key, stored)?),
+            Tree::Lsm(tree) => {
+                // An LSM tree writes blindly, so the value it replaces is looked up first.
+                let replaced = tree.get(key)?;
+                tree.put(key, stored)?;
+                Ok(replaced)
+            }
+        }
+    }
+
+    fn load(&self, stored: &[u8]) -> Result<Vec<u8>, KvError> {
+        match overflow_head(stored)? {
+            Some(head) => Ok(overflow::read(self.store, head)?),
+            None => Ok(stored[1..].to_vec()),
+        }
+    }
+
+    /// The value of a cell taken out of the tree, whose overflow chain, if it has one, is freed.
+    fn release(&self, stored: &[u8]) -> Result<Vec<u8>, KvError> {
+        let value = self.load(stored)?;
+        if let Some(head) = overflow_head(stored)? {
+            overflow::free(self.store, &self.allocator, head)?;
+        }
+        Ok(value)
+    }
+}
+
+/// The first page of the overflow chain a stored value is in, or `None` for a value stored inline.
+fn overflow_head(stored: &[u8]) -> Result<Option<PageId>, KvError> {
+    match stored.first() {
+        Some(&INLINE) => Ok(None),
+        Some(&OVERFLOW) if stored.len() == 9 => Ok(Some(PageId::new(read_u64(stored, 1) as usize))),
+        _ => Err(KvError::Corrupt),
+    }
+}
+
+pub struct KvRange<'kv, 'store, S: Storage> {
+    keyspace: &'kv Keyspace<'store, S>,
+    entries: Entries<'kv, 'store, S>,
+}
+impl<S: Storage> Iterator for KvRange<'_, '_, S> {
+    type Item = Result<(Vec<u8>, Vec<u8>), KvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        Some(entry.and_then(|(key, stored)| Ok((key, self.keyspace.load(&stored)?))))
+    }
+}
+
+/// The keys of a range in a keyspace's tree, with their values as stored.
+enum Entries<'kv, 'store, S: Storage> {
+    BTree(BTreeRange<'kv, 'store, S>),
+    Lsm(LsmRange<'kv>),
+}
+impl<S: Storage> Iterator for Entries<'_, '_, S> {
+    type Item = Result<(Vec<u8>, Vec<u8>), KvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Entries::BTree(entries) => Some(entries.next()?.map_err(KvError::from)),
+            Entries::Lsm(entries) => Some(entries.next()?.map_err(KvError::from)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::lsm::LsmOptions;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{KeyspaceOptions, KvError, Keyspace};
+
+    #[test]
+    fn test_keyspaces() -> Result<(), KvError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut kv = Keyspace::create(&store, allocator)?;
+        for i in 0..200u32 {
+            assert_eq!(kv.put(&i.to_be_bytes(), format!("v{i}").as_bytes())?, None);
+        }
+        let long = vec![7u8; 3 * 4096];
+        assert_eq!(kv.put(&5u32.to_be_bytes(), &long)?, Some(b"v5".to_vec()));
+        assert_eq!(kv.get(&5u32.to_be_bytes())?, Some(long.clone()));
+        assert_eq!(kv.delete(&6u32.to_be_bytes())?, Some(b"v6".to_vec()));
+        assert_eq!(kv.get(&6u32.to_be_bytes())?, None);
+
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| entries.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        let range = kv.range(4u32.to_be_bytes()..8u32.to_be_bytes()).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(range[1], (5u32.to_be_bytes().to_vec(), long.clone()));
+        assert_eq!(keys(range), [4u32, 5, 7].map(|i| i.to_be_bytes().to_vec()));
+
+        let mut kv = Keyspace::open(&store, allocator, kv.meta_page())?;
+        assert_eq!(kv.put(&5u32.to_be_bytes(), b"short")?, Some(long));
+        assert_eq!(kv.iter().count(), 199);
+        assert_eq!(kv.put(&[0u8; 4096], b""), Err(KvError::KeyTooLong));
+        kv.free()
+    }
+
+    #[test]
+    fn test_lsm_keyspaces() -> Result<(), KvError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let lsm = LsmOptions { memtable_bytes: 4096, l0_run_limit: 2, level_base_pages: 4, ..LsmOptions::default() };
+        let options = KeyspaceOptions { lsm: Some(lsm) };
+        let mut kv = Keyspace::create_with(&store, allocator, &options)?;
+        for i in 0..2000u32 {
+            assert_eq!(kv.put(&i.to_be_bytes(), format!("v{i}").as_bytes())?, None);
+        }
+        // Too long for an entry of the tree, so kept in an overflow chain.
+        let long = vec![7u8; 3 * 4096];
+        assert_eq!(kv.put(&5u32.to_be_bytes(), &long)?, Some(b"v5".to_vec()));
+        assert_eq!(kv.delete(&6u32.to_be_bytes())?, Some(b"v6".to_vec()));
+        assert_eq!(kv.delete(&6u32.to_be_bytes())?, None);
+        let range = kv.range(4u32.to_be_bytes()..8u32.to_be_bytes()).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(range.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>(), [4u32, 5, 7].map(|i| i.to_be_bytes()));
+        assert_eq!(range[1].1, long);
+
+        // Reopened, the log written since the last flush of the memtable is read back into it.
+        let mut kv = Keyspace::open_with(&store, allocator, kv.meta_page(), &options)?;
+        assert_eq!(kv.get(&5u32.to_be_bytes())?, Some(long.clone()));
+        assert_eq!(kv.get(&1999u32.to_be_bytes())?, Some(b"v1999".to_vec()));
+        assert_eq!(kv.put(&5u32.to_be_bytes(), b"short")?, Some(long));
+        assert_eq!(kv.iter().count(), 1999);
+        assert_eq!(kv.put(&[0u8; 4096], b""), Err(KvError::KeyTooLong));
+        kv.free()?;
+        // Every page after the allocator's own is free, so the first new page comes after all of them.
+        let free = allocator.free_pages(&store)?.len();
+        (0..free).try_for_each(|_| allocator.allocate(&store).map(drop))?;
+        assert_eq!(allocator.allocate(&store)?.id(), PageId::new(free + 1));
+        Ok(())
+    }
+}
@@ -1,2 +1,43 @@
+pub mod allocator;
+pub mod art;
+pub mod bitmap;
+pub mod blob;
+pub mod bloom;
+pub mod btree;
+mod bytes;
+pub mod cancel;
+pub mod catalog;
+pub mod collation;
+pub mod connection;
+pub mod csv;
+pub mod database;
+pub mod datetime;
+pub mod decimal;
+pub mod exec;
+pub mod fulltext;
+pub mod hash;
+pub mod heap;
+pub mod hnsw;
+pub mod json;
+pub mod kv;
+pub mod lsm;
+pub mod memory;
+mod overflow;
 pub mod page_store;
-pub mod storage;
\ No newline at end of file
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod pgwire;
+pub mod planner;
+pub mod resp;
+pub mod rtree;
+pub mod shadow;
+pub mod shell;
+pub mod skiplist;
+pub mod slotted_page;
+pub mod sql;
+pub mod sort;
+pub mod storage;
+pub mod table;
+pub mod temp;
+pub mod tuple;
+mod varint;
@@ -0,0 +1,99 @@
+//! The memtable log: every write to an LSM tree is appended here before it reaches the memtable, so
+//! the memtable can be rebuilt when the tree is reopened. Each flush starts a new log and frees the old one.
+//!
+//! Log pages go through the buffer pool like every other page, so appended records are durable once
+//! the store is flushed.
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u32, read_u64, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::slotted_page::SlottedPage;
+use crate::storage::Storage;
+
+use super::run::{decode, encode};
+use super::{Entry, LsmError};
+
+const MAGIC: u32 = 0x4c53_4c47;
+const NEXT: usize = 8;
+const HEADER_LEN: usize = 16;
+const NO_PAGE: u64 = u64::MAX;
+
+pub(crate) struct Log {
+    head: PageId,
+    tail: PageId,
+}
+impl Log {
+    pub(crate) fn create<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator) -> Result<Log, LsmError> {
+        let head = new_page(store, allocator)?;
+        Ok(Log { head, tail: head })
+    }
+
+    /// Reattach to the log starting at `head`, returning its records in the order they were written.
+    pub(crate) fn open<S: Storage>(store: &PageStore<S>, head: PageId) -> Result<(Log, Vec<Entry>), LsmError> {
+        let mut records = Vec::new();
+        let mut tail = head;
+        loop {
+            let page = store.pin_page(&tail)?;
+            let slotted = SlottedPage::new(page.try_read()?);
+            let header = slotted.reserved();
+            if header.len() != HEADER_LEN || read_u32(header, 0) != MAGIC {
+                return Err(LsmError::Page(PageError::WrongPageType))
+            }
+            records.extend(slotted.iter().map(|(_, cell)| decode(cell)));
+            match read_u64(header, NEXT) {
+                NO_PAGE => break,
+                next => tail = PageId::new(next as usize),
+            }
+        }
+        Ok((Log { head, tail }, records))
+    }
+
+    pub(crate) fn head(&self) -> PageId {
+        self.head
+    }
+
+    pub(crate) fn append<S: Storage>(&mut self, store: &PageStore<S>, allocator: &PageAllocator, key: &[u8], value: Option<&[u8]>) -> Result<(), LsmError> {
+        let cell = encode(key, value);
+        {
+            let page = store.pin_page(&self.tail)?;
+            let mut slotted = SlottedPage::new(page.try_write()?);
+            if slotted.insert(&cell).is_ok() {
+                return Ok(())
+            }
+        }
+        let next = new_page(store, allocator)?;
+        {
+            let page = store.pin_page(&next)?;
+            SlottedPage::new(page.try_write()?).insert(&cell).map_err(|_| LsmError::EntryTooLarge)?;
+        }
+        let page = store.pin_page(&self.tail)?;
+        write_u64(SlottedPage::new(page.try_write()?).reserved_mut(), NEXT, next.offset() as u64);
+        self.tail = next;
+        Ok(())
+    }
+
+    /// Return every page of the log to the allocator.
+    pub(crate) fn free<S: Storage>(self, store: &PageStore<S>, allocator: &PageAllocator) -> Result<(), LsmError> {
+        let mut current = Some(self.head);
+        while let Some(id) = current {
+            current = {
+                let page = store.pin_page(&id)?;
+                let slotted = SlottedPage::new(page.try_read()?);
+                match read_u64(slotted.reserved(), NEXT) {
+                    NO_PAGE => None,
+                    next => Some(PageId::new(next as usize)),
+                }
+            };
+            allocator.free(store, id)?;
+        }
+        Ok(())
+    }
+}
+
+fn new_page<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator) -> Result<PageId, LsmError> {
+    let page = allocator.allocate(store)?;
+    let mut slotted = SlottedPage::init(page.try_write()?, HEADER_LEN);
+    let header = slotted.reserved_mut();
+    write_u32(header, 0, MAGIC);
+    write_u64(header, NEXT, NO_PAGE);
+    Ok(page.id())
+}
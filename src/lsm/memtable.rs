@@ -0,0 +1,64 @@
+//! The memtable: recent writes held in memory, in whichever ordered index `LsmOptions` picks.
+use std::ops::Bound;
+
+use crate::art::Art;
+use crate::skiplist::SkipList;
+
+use super::Entry;
+
+/// In-memory index used for the memtable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemtableIndex {
+    SkipList,
+    /// An adaptive radix tree, which looks keys up faster when they are long and share prefixes.
+    Art,
+}
+
+pub(crate) enum Memtable {
+    SkipList(SkipList<Vec<u8>, Option<Vec<u8>>>),
+    Art(Art<Option<Vec<u8>>>),
+}
+impl Memtable {
+    pub(crate) fn new(index: MemtableIndex) -> Memtable {
+        match index {
+            MemtableIndex::SkipList => Memtable::SkipList(SkipList::new()),
+            MemtableIndex::Art => Memtable::Art(Art::new()),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            Memtable::SkipList(list) => list.is_empty(),
+            Memtable::Art(tree) => tree.is_empty(),
+        }
+    }
+
+    /// Look up `key`: `Some(None)` means the memtable holds a tombstone for it.
+    pub(crate) fn get(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        match self {
+            Memtable::SkipList(list) => list.get(key),
+            Memtable::Art(tree) => tree.get(key),
+        }
+    }
+
+    pub(crate) fn insert(&self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        match self {
+            Memtable::SkipList(list) => list.insert(key, value),
+            Memtable::Art(tree) => tree.insert(key, value),
+        };
+    }
+
+    pub(crate) fn range_from(&self, start: Bound<&[u8]>) -> Box<dyn Iterator<Item = Entry> + '_> {
+        match self {
+            Memtable::SkipList(list) => Box::new(list.range_from(start)),
+            Memtable::Art(tree) => Box::new(tree.range_from(start)),
+        }
+    }
+
+    pub(crate) fn into_sorted(self) -> Vec<Entry> {
+        match self {
+            Memtable::SkipList(list) => list.into_sorted(),
+            Memtable::Art(tree) => tree.into_sorted(),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! K-way merge of sorted entry streams.
+use std::iter::Peekable;
+
+use super::{Entry, LsmError};
+
+pub(crate) type Source<'a> = Box<dyn Iterator<Item = Result<Entry, LsmError>> + 'a>;
+
+/// Merges sources that are each in key order into one stream in key order. When several sources hold
+/// the same key, the entry from the earliest source wins and the others are skipped, so sources are
+/// passed newest first. Tombstones are passed through for the caller to interpret.
+pub(crate) struct MergeIter<'a> {
+    sources: Vec<Peekable<Source<'a>>>,
+}
+impl<'a> MergeIter<'a> {
+    pub(crate) fn new(sources: Vec<Source<'a>>) -> MergeIter<'a> {
+        MergeIter { sources: sources.into_iter().map(Iterator::peekable).collect() }
+    }
+}
+impl Iterator for MergeIter<'_> {
+    type Item = Result<Entry, LsmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut winner: Option<(usize, Vec<u8>)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Err(_)) => return source.next(),
+                Some(Ok((key, _))) if winner.as_ref().is_none_or(|(_, best)| key < best) => {
+                    winner = Some((i, key.clone()));
+                }
+                _ => {}
+            }
+        }
+        let entry = match self.sources[winner?.0].next() {
+            Some(Ok(entry)) => entry,
+            other => return other,
+        };
+        for source in &mut self.sources {
+            while matches!(source.peek(), Some(Ok((key, _))) if *key == entry.0) {
+                source.next();
+            }
+        }
+        Some(Ok(entry))
+    }
+}
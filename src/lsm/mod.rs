@@ -0,0 +1,470 @@
+//! Log-structured merge tree: an ordered key-value store for write-heavy keyspaces.
+//!
+//! Writes are appended to a log and applied to the memtable, an in-memory skip list or, if
+//! `LsmOptions::memtable_index` asks for one, an adaptive radix tree. When the memtable passes
+//! `LsmOptions::memtable_bytes` it is frozen, written out as an immutable sorted run in level 0,
+//! and the log is started afresh. Level 0 runs may overlap each other; once there are more than `l0_run_limit` of them
+//! they are merged together with level 1. Every deeper level holds a single run, and level `n` is merged
+//! into level `n + 1` when it grows past `level_base_pages * level_fanout^(n - 1)` pages. Deletes write
+//! tombstones, which are dropped when a merge writes the deepest level.
+//!
+//! Reads consult the memtable, then level 0 from newest to oldest, then the deeper levels, and stop at
+//! the first entry found; each run carries a bloom filter, so runs that cannot hold the key are skipped
+//! without reading any of their pages. Range scans merge all of them. The tree's state is recorded on a
+//! manifest page, so an `LsmTree` is an independent keyspace that can live in the same store as B+trees
+//! and heap files; a database's keyspace is kept in one when `KeyspaceOptions::lsm` asks for it.
+mod log;
+mod memtable;
+mod merge;
+mod run;
+
+use std::ops::{Bound, RangeBounds};
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+use crate::varint;
+
+use log::Log;
+use memtable::Memtable;
+use merge::{MergeIter, Source};
+use run::{Run, RunBuilder};
+
+pub use memtable::MemtableIndex;
+
+const MAGIC: u32 = 0x4c53_4d46;
+const LOG_HEAD: usize = 8;
+const LEVEL_COUNT: usize = 16;
+const LEVELS: usize = 18;
+/// Entries are capped at a quarter of a page so run pages stay well packed.
+const MAX_ENTRY_LEN: usize = PAGE_SIZE / 4;
+
+/// A key and its value, or `None` for a tombstone.
+pub(crate) type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+#[derive(Debug, PartialEq)]
+pub enum LsmError {
+    Page(PageError),
+    EntryTooLarge,
+}
+impl From<PageError> for LsmError {
+    fn from(e: PageError) -> Self {
+        LsmError::Page(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LsmOptions {
+    /// Flush the memtable once the keys and values written to it add up to this many bytes.
+    pub memtable_bytes: usize,
+    /// Merge level 0 into level 1 once it holds more runs than this.
+    pub l0_run_limit: usize,
+    /// Size, in pages, at which level 1 is merged into level 2.
+    pub level_base_pages: usize,
+    /// Growth factor of the size limit from one level to the next.
+    pub level_fanout: usize,
+    /// Target false positive rate of each run's bloom filter. Zero writes runs without filters.
+    pub bloom_false_positive_rate: f64,
+    /// In-memory index the memtable is kept in.
+    pub memtable_index: MemtableIndex,
+}
+impl Default for LsmOptions {
+    fn default() -> Self {
+        LsmOptions {
+            memtable_bytes: 256 * 1024,
+            l0_run_limit: 4,
+            level_base_pages: 256,
+            level_fanout: 10,
+            bloom_false_positive_rate: 0.01,
+            memtable_index: MemtableIndex::SkipList,
+        }
+    }
+}
+
+pub struct LsmTree<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    manifest: PageId,
+    options: LsmOptions,
+    memtable: Memtable,
+    memtable_bytes: usize,
+    log: Log,
+    /// Runs by level. Level 0 is ordered newest first.
+    levels: Vec<Vec<Run>>,
+}
+impl<'store, S: Storage> LsmTree<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator, options: LsmOptions) -> Result<LsmTree<'store, S>, LsmError> {
+        let manifest = allocator.allocate(store)?.id();
+        let log = Log::create(store, &allocator)?;
+        let tree = LsmTree {
+            store,
+            allocator,
+            manifest,
+            options,
+            memtable: Memtable::new(options.memtable_index),
+            memtable_bytes: 0,
+            log,
+            levels: vec![Vec::new()],
+        };
+        tree.write_manifest()?;
+        Ok(tree)
+    }
+
+    /// Reattach to the tree whose manifest is `manifest`, replaying its log into the memtable.
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, manifest: PageId, options: LsmOptions) -> Result<LsmTree<'store, S>, LsmError> {
+        let (log_head, run_metas) = {
+            let page = store.pin_page(&manifest)?;
+            let buf = page.try_read()?;
+            if read_u32(&*buf, 0) != MAGIC {
+                return Err(LsmError::Page(PageError::WrongPageType))
+            }
+            let mut at = LEVELS;
+            let mut levels = Vec::new();
+            for _ in 0..read_u16(&*buf, LEVEL_COUNT) {
+                let count = read_u16(&*buf, at) as usize;
+                at += 2;
+                levels.push((0..count).map(|i| PageId::new(read_u64(&*buf, at + i * 8) as usize)).collect::<Vec<_>>());
+                at += count * 8;
+            }
+            (PageId::new(read_u64(&*buf, LOG_HEAD) as usize), levels)
+        };
+        let levels = run_metas.into_iter()
+            .map(|metas| metas.into_iter().map(|meta| Run::open(store, meta)).collect())
+            .collect::<Result<Vec<Vec<Run>>, LsmError>>()?;
+
+        let (log, records) = Log::open(store, log_head)?;
+        let mut tree = LsmTree { store, allocator, manifest, options, memtable: Memtable::new(options.memtable_index), memtable_bytes: 0, log, levels };
+        for (key, value) in records {
+            tree.apply(key, value);
+        }
+        Ok(tree)
+    }
+
+    /// The page to pass to `open` to find this tree again.
+    pub fn manifest_page(&self) -> PageId {
+        self.manifest
+    }
+
+    /// The number of runs in each level, from level 0 down.
+    pub fn run_counts(&self) -> Vec<usize> {
+        self.levels.iter().map(Vec::len).collect()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, LsmError> {
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(value)
+        }
+        for run in self.levels.iter().flatten() {
+            if let Some(value) = run.get(self.store, key)? {
+                return Ok(value)
+            }
+        }
+        Ok(None)
+    }
+
+    /// Insert or replace the value for `key`. Unlike the B+tree this is a blind write: the old value is
+    /// not looked up.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), LsmError> {
+        self.write(key, Some(value))
+    }
+
+    /// Remove `key` if it is present.
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), LsmError> {
+        self.write(key, None)
+    }
+
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> LsmRange<'_> {
+        let own = |b: Bound<&K>| match b {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let start = own(range.start_bound());
+        let end = own(range.end_bound());
+
+        let memtable = self.memtable.range_from(start.as_ref().map(Vec::as_slice)).map(Ok);
+        let mut sources: Vec<Source> = vec![Box::new(memtable)];
+        for run in self.levels.iter().flatten() {
+            sources.push(Box::new(run.iter(self.store, start.clone())));
+        }
+        LsmRange { merged: MergeIter::new(sources), end, done: false }
+    }
+
+    pub fn iter(&self) -> LsmRange<'_> {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// Return every page of the tree to the allocator: its runs, its log and its manifest.
+    pub fn free(self) -> Result<(), LsmError> {
+        for run in self.levels.iter().flatten() {
+            run.free(self.store, &self.allocator)?;
+        }
+        self.log.free(self.store, &self.allocator)?;
+        Ok(self.allocator.free(self.store, self.manifest)?)
+    }
+
+    /// Write the memtable out as a new level 0 run, then compact any level that is over its limit.
+    pub fn flush(&mut self) -> Result<(), LsmError> {
+        if self.memtable.is_empty() {
+            return Ok(())
+        }
+        let frozen = std::mem::replace(&mut self.memtable, Memtable::new(self.options.memtable_index));
+        self.memtable_bytes = 0;
+        let mut builder = RunBuilder::new(self.store, &self.allocator, self.options.bloom_false_positive_rate);
+        for (key, value) in frozen.into_sorted() {
+            builder.add(&key, value.as_deref())?;
+        }
+        self.levels[0].insert(0, builder.finish()?);
+        let old_log = std::mem::replace(&mut self.log, Log::create(self.store, &self.allocator)?);
+        self.write_manifest()?;
+        old_log.free(self.store, &self.allocator)?;
+        self.compact()
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(), LsmError> {
+        if 1 + varint::encoded_len(key.len() as u64) + key.len() + value.map_or(0, |v| v.len()) > MAX_ENTRY_LEN {
+            return Err(LsmError::EntryTooLarge)
+        }
+        self.log.append(self.store, &self.allocator, key, value)?;
+        self.apply(key.to_vec(), value.map(<[u8]>::to_vec));
+        if self.memtable_bytes >= self.options.memtable_bytes {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        self.memtable_bytes += key.len() + value.as_ref().map_or(0, Vec::len);
+        self.memtable.insert(key, value);
+    }
+
+    fn compact(&mut self) -> Result<(), LsmError> {
+        if self.levels[0].len() > self.options.l0_run_limit {
+            self.merge_into_next(0)?;
+        }
+        let mut level = 1;
+        while level < self.levels.len() {
+            let limit = self.options.level_base_pages * self.options.level_fanout.pow(level as u32 - 1);
+            let pages: usize = self.levels[level].iter().map(Run::page_count).sum();
+            if pages > limit {
+                self.merge_into_next(level)?;
+            }
+            level += 1;
+        }
+        Ok(())
+    }
+
+    /// Merge every run in `level` and `level + 1` into a single run that replaces them in `level + 1`.
+    fn merge_into_next(&mut self, level: usize) -> Result<(), LsmError> {
+        if self.levels.len() == level + 1 {
+            self.levels.push(Vec::new());
+        }
+        let target = level + 1;
+        let deepest = self.levels[target + 1..].iter().all(Vec::is_empty);
+        let store = self.store;
+        let mut builder = RunBuilder::new(store, &self.allocator, self.options.bloom_false_positive_rate);
+        {
+            let sources = self.levels[level].iter().chain(&self.levels[target])
+                .map(|run| Box::new(run.iter(store, Bound::Unbounded)) as Source)
+                .collect();
+            for entry in MergeIter::new(sources) {
+                let (key, value) = entry?;
+                if value.is_some() || !deepest {
+                    builder.add(&key, value.as_deref())?;
+                }
+            }
+        }
+        let merged = builder.finish()?;
+        let mut replaced = std::mem::take(&mut self.levels[level]);
+        replaced.append(&mut self.levels[target]);
+        if merged.page_count() > 0 {
+            self.levels[target].push(merged);
+        } else {
+            replaced.push(merged);
+        }
+        self.write_manifest()?;
+        for run in replaced {
+            run.free(store, &self.allocator)?;
+        }
+        Ok(())
+    }
+
+    fn write_manifest(&self) -> Result<(), LsmError> {
+        let page = self.store.pin_page(&self.manifest)?;
+        let mut buf = page.try_write()?;
+        write_u32(&mut *buf, 0, MAGIC);
+        write_u64(&mut *buf, LOG_HEAD, self.log.head().offset() as u64);
+        write_u16(&mut *buf, LEVEL_COUNT, self.levels.len() as u16);
+        let mut at = LEVELS;
+        for level in &self.levels {
+            assert!(at + 2 + level.len() * 8 <= PAGE_SIZE, "compaction keeps the manifest within one page");
+            write_u16(&mut *buf, at, level.len() as u16);
+            at += 2;
+            for run in level {
+                write_u64(&mut *buf, at, run.meta_page().offset() as u64);
+                at += 8;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Live entries of an LSM tree in key order, with older versions and deleted keys filtered out.
+pub struct LsmRange<'tree> {
+    merged: MergeIter<'tree>,
+    end: Bound<Vec<u8>>,
+    done: bool,
+}
+impl Iterator for LsmRange<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>), LsmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let (key, value) = match self.merged.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e))
+                }
+                None => break,
+            };
+            let in_range = match &self.end {
+                Bound::Unbounded => true,
+                Bound::Included(end) => key <= *end,
+                Bound::Excluded(end) => key < *end,
+            };
+            if !in_range {
+                self.done = true;
+            } else if let Some(value) = value {
+                return Some(Ok((key, value)))
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{LsmError, LsmOptions, LsmTree, MemtableIndex};
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("event:{i:06}").into_bytes()
+    }
+
+    fn small() -> LsmOptions {
+        LsmOptions { memtable_bytes: 8 * 1024, l0_run_limit: 2, level_base_pages: 4, level_fanout: 4, ..LsmOptions::default() }
+    }
+
+    #[test]
+    fn test_flushes_and_compacts_across_levels() -> Result<(), LsmError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut tree = LsmTree::create(&store, allocator, small())?;
+
+        for i in 0..4000 {
+            tree.put(&key(i), &i.to_le_bytes().repeat(4))?;
+        }
+        for i in (0..4000).step_by(3) {
+            tree.put(&key(i), b"overwritten")?;
+        }
+        for i in (0..4000).step_by(5) {
+            tree.delete(&key(i))?;
+        }
+        let counts = tree.run_counts();
+        assert!(counts.len() >= 3, "{counts:?}");
+        assert!(counts[0] <= 2 && counts[1..].iter().all(|c| *c <= 1), "{counts:?}");
+
+        let expected = |i: u32| match (i % 5, i % 3) {
+            (0, _) => None,
+            (_, 0) => Some(b"overwritten".to_vec()),
+            _ => Some(i.to_le_bytes().repeat(4)),
+        };
+        for i in 0..4000 {
+            assert_eq!(tree.get(&key(i))?, expected(i), "key {i}");
+        }
+        let scanned = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        let live: Vec<_> = (0..4000).filter_map(|i| expected(i).map(|v| (key(i), v))).collect();
+        assert_eq!(scanned, live);
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_merges_memtable_and_runs() -> Result<(), LsmError> {
+        for memtable_index in [MemtableIndex::SkipList, MemtableIndex::Art] {
+            let store = PageStore::new(TestStorage::new());
+            let allocator = PageAllocator::create(&store, PageId::new(0))?;
+            let mut tree = LsmTree::create(&store, allocator, LsmOptions { memtable_index, ..LsmOptions::default() })?;
+            for i in 0..100 {
+                tree.put(&key(i), b"old")?;
+            }
+            tree.flush()?;
+            tree.delete(&key(11))?;
+            tree.put(&key(12), b"new")?;
+            tree.put(&key(1000), b"late")?;
+
+            let keys = |range: Vec<(Vec<u8>, Vec<u8>)>| range.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+            let scanned = tree.range(key(10)..key(14)).collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(keys(scanned.clone()), vec![key(10), key(12), key(13)]);
+            assert_eq!(scanned[1].1, b"new");
+            let tail = tree.range(key(98)..).collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(keys(tail), vec![key(98), key(99), key(1000)]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_replays_log() -> Result<(), LsmError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut tree = LsmTree::create(&store, allocator, small())?;
+        for i in 0..1000 {
+            tree.put(&key(i), b"value")?;
+        }
+        tree.delete(&key(999))?;
+        tree.put(&key(5), b"replaced")?;
+        let manifest = tree.manifest_page();
+        drop(tree);
+
+        let tree = LsmTree::open(&store, allocator, manifest, small())?;
+        assert_eq!(tree.get(&key(5))?, Some(b"replaced".to_vec()));
+        assert_eq!(tree.get(&key(999))?, None);
+        assert_eq!(tree.get(&key(998))?, Some(b"value".to_vec()));
+        assert_eq!(tree.iter().count(), 999);
+        assert_eq!(tree.get(b"missing")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_filters_rule_out_absent_keys() -> Result<(), LsmError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut tree = LsmTree::create(&store, allocator, small())?;
+        for i in (0..4000).step_by(2) {
+            tree.put(&key(i), b"value")?;
+        }
+        tree.flush()?;
+        let manifest = tree.manifest_page();
+        drop(tree);
+
+        let tree = LsmTree::open(&store, allocator, manifest, small())?;
+        let runs: Vec<_> = tree.levels.iter().flatten().collect();
+        assert!(runs.len() > 1);
+        assert!((0..4000).step_by(2).all(|i| runs.iter().any(|run| run.may_contain(&key(i)))));
+        let probes = (1..4000).step_by(2).flat_map(|i| runs.iter().map(move |run| run.may_contain(&key(i))));
+        let false_positives = probes.filter(|hit| *hit).count();
+        assert!(false_positives < 2000 * runs.len() / 20, "{false_positives}");
+
+        let without = LsmOptions { bloom_false_positive_rate: 0.0, ..small() };
+        let mut tree = LsmTree::create(&store, allocator, without)?;
+        tree.put(b"only", b"value")?;
+        tree.flush()?;
+        assert!(tree.levels[0][0].may_contain(b"absent"));
+        assert_eq!(tree.get(b"absent")?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+//! Sorted runs: immutable, key-ordered entry files laid out on pages.
+//!
+//! A run is a sequence of data pages holding entries in key order, plus a chain of index pages starting
+//! at the run's meta page that lists the first key of every data page. The index is loaded into memory
+//! when a run is opened, so a lookup reads exactly one data page. A run may also have a bloom filter over
+//! its keys, written to an overflow chain named by the meta page and loaded alongside the index, which
+//! lets lookups for absent keys skip that page read too.
+use std::ops::Bound;
+
+use crate::allocator::PageAllocator;
+use crate::bloom::BloomFilter;
+use crate::bytes::{read_u32, read_u64, write_u32, write_u64};
+use crate::hash::hash_key;
+use crate::overflow;
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::slotted_page::{SlottedPage, SlottedPageError};
+use crate::storage::Storage;
+use crate::varint;
+
+use super::{Entry, LsmError};
+
+const DATA_MAGIC: u32 = 0x4c53_4444;
+const DATA_HEADER_LEN: usize = 4;
+const INDEX_MAGIC: u32 = 0x4c53_4958;
+const INDEX_NEXT: usize = 8;
+const INDEX_ENTRIES: usize = 16;
+const INDEX_BLOOM: usize = 24;
+const INDEX_HEADER_LEN: usize = 32;
+const NO_PAGE: u64 = u64::MAX;
+
+const PUT: u8 = 0;
+const TOMBSTONE: u8 = 1;
+
+/// Cell layout shared by run data pages and the memtable log: `[kind u8][key_len varint][key][value]`.
+pub(crate) fn encode(key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(1 + varint::MAX_LEN + key.len() + value.map_or(0, |v| v.len()));
+    cell.push(if value.is_some() { PUT } else { TOMBSTONE });
+    varint::write_prefixed(&mut cell, key);
+    cell.extend_from_slice(value.unwrap_or_default());
+    cell
+}
+
+pub(crate) fn decode(cell: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
+    let (key, len) = varint::read_prefixed(&cell[1..]).expect("cells are written by encode");
+    let value = (cell[0] == PUT).then(|| cell[1 + len..].to_vec());
+    (key.to_vec(), value)
+}
+
+fn decoded_key(cell: &[u8]) -> &[u8] {
+    varint::read_prefixed(&cell[1..]).expect("cells are written by encode").0
+}
+
+pub(crate) struct Run {
+    meta: PageId,
+    /// First key and page id of every data page, in order.
+    index: Vec<(Vec<u8>, PageId)>,
+    index_pages: Vec<PageId>,
+    bloom: Option<(PageId, BloomFilter)>,
+}
+impl Run {
+    pub(crate) fn open<S: Storage>(store: &PageStore<S>, meta: PageId) -> Result<Run, LsmError> {
+        let mut run = Run { meta, index: Vec::new(), index_pages: Vec::new(), bloom: None };
+        let mut bloom = NO_PAGE;
+        let mut next = Some(meta);
+        while let Some(id) = next {
+            let page = store.pin_page(&id)?;
+            let slotted = SlottedPage::new(page.try_read()?);
+            let header = slotted.reserved();
+            if header.len() != INDEX_HEADER_LEN || read_u32(header, 0) != INDEX_MAGIC {
+                return Err(LsmError::Page(PageError::WrongPageType))
+            }
+            for (_, cell) in slotted.iter() {
+                let page = PageId::new(read_u64(cell, 0) as usize);
+                run.index.push((cell[8..].to_vec(), page));
+            }
+            if id == meta {
+                bloom = read_u64(header, INDEX_BLOOM);
+            }
+            run.index_pages.push(id);
+            next = match read_u64(header, INDEX_NEXT) {
+                NO_PAGE => None,
+                page => Some(PageId::new(page as usize)),
+            };
+        }
+        if bloom != NO_PAGE {
+            let head = PageId::new(bloom as usize);
+            let filter = BloomFilter::from_bytes(&overflow::read(store, head)?).ok_or(PageError::WrongPageType)?;
+            run.bloom = Some((head, filter));
+        }
+        Ok(run)
+    }
+
+    pub(crate) fn meta_page(&self) -> PageId {
+        self.meta
+    }
+
+    pub(crate) fn page_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Index of the data page that would hold `key`, if any page could.
+    fn page_for(&self, key: &[u8]) -> Option<usize> {
+        let after = self.index.partition_point(|(first, _)| first.as_slice() <= key);
+        after.checked_sub(1)
+    }
+
+    /// False if the run's bloom filter rules `key` out, so `get` need not read a data page.
+    pub(crate) fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom.as_ref().is_none_or(|(_, filter)| filter.may_contain(key))
+    }
+
+    /// Look up `key`: `Some(None)` means this run holds a tombstone for it.
+    pub(crate) fn get<S: Storage>(&self, store: &PageStore<S>, key: &[u8]) -> Result<Option<Option<Vec<u8>>>, LsmError> {
+        if !self.may_contain(key) {
+            return Ok(None)
+        }
+        let Some(index) = self.page_for(key) else { return Ok(None) };
+        let page = store.pin_page(&self.index[index].1)?;
+        let slotted = SlottedPage::new(page.try_read()?);
+        let count = slotted.slot_count();
+        let cell = |i: u16| slotted.get(i).expect("run pages have no empty slots");
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match decoded_key(cell(mid)).cmp(key) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Ok(Some(decode(cell(mid)).1)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Entries from `start` onward, in key order, including tombstones.
+    pub(crate) fn iter<'a, S: Storage>(&'a self, store: &'a PageStore<S>, start: Bound<Vec<u8>>) -> RunIter<'a, S> {
+        let next_page = match &start {
+            Bound::Included(k) | Bound::Excluded(k) => self.page_for(k).unwrap_or(0),
+            Bound::Unbounded => 0,
+        };
+        RunIter { run: self, store, next_page, start, buffered: Vec::new() }
+    }
+
+    /// Return every page of the run to the allocator.
+    pub(crate) fn free<S: Storage>(&self, store: &PageStore<S>, allocator: &PageAllocator) -> Result<(), LsmError> {
+        for (_, page) in &self.index {
+            allocator.free(store, *page)?;
+        }
+        for page in &self.index_pages {
+            allocator.free(store, *page)?;
+        }
+        if let Some((head, _)) = &self.bloom {
+            overflow::free(store, allocator, *head)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct RunIter<'a, S: Storage> {
+    run: &'a Run,
+    store: &'a PageStore<S>,
+    next_page: usize,
+    start: Bound<Vec<u8>>,
+    buffered: Vec<Entry>,
+}
+impl<S: Storage> RunIter<'_, S> {
+    fn load(&self, page: PageId) -> Result<Vec<Entry>, LsmError> {
+        let pinned = self.store.pin_page(&page)?;
+        let slotted = SlottedPage::new(pinned.try_read()?);
+        let mut entries: Vec<Entry> = slotted.iter()
+            .map(|(_, cell)| decode(cell))
+            .filter(|(k, _)| match &self.start {
+                Bound::Included(start) => k >= start,
+                Bound::Excluded(start) => k > start,
+                Bound::Unbounded => true,
+            })
+            .collect();
+        entries.reverse();
+        Ok(entries)
+    }
+}
+impl<S: Storage> Iterator for RunIter<'_, S> {
+    type Item = Result<Entry, LsmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffered.is_empty() {
+            let (_, page) = self.run.index.get(self.next_page)?;
+            self.next_page += 1;
+            match self.load(*page) {
+                Ok(entries) => self.buffered = entries,
+                Err(e) => {
+                    self.next_page = self.run.index.len();
+                    return Some(Err(e))
+                }
+            }
+        }
+        self.buffered.pop().map(Ok)
+    }
+}
+
+/// Writes entries, which must arrive in strictly increasing key order, into a new run.
+pub(crate) struct RunBuilder<'a, S: Storage> {
+    store: &'a PageStore<S>,
+    allocator: &'a PageAllocator,
+    index: Vec<(Vec<u8>, PageId)>,
+    entries: u64,
+    false_positive_rate: f64,
+    /// `hash_key` of every key added, for sizing and filling the bloom filter once the run is complete.
+    hashes: Vec<u64>,
+}
+impl<'a, S: Storage> RunBuilder<'a, S> {
+    /// A builder whose run gets a bloom filter with the given false positive rate, or none if it is zero.
+    pub(crate) fn new(store: &'a PageStore<S>, allocator: &'a PageAllocator, false_positive_rate: f64) -> RunBuilder<'a, S> {
+        RunBuilder { store, allocator, index: Vec::new(), entries: 0, false_positive_rate, hashes: Vec::new() }
+    }
+
+    pub(crate) fn add(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(), LsmError> {
+        if self.false_positive_rate > 0.0 {
+            self.hashes.push(hash_key(key));
+        }
+        let cell = encode(key, value);
+        if let Some((_, page)) = self.index.last() {
+            let pinned = self.store.pin_page(page)?;
+            let mut slotted = SlottedPage::new(pinned.try_write()?);
+            match slotted.insert(&cell) {
+                Ok(_) => {
+                    self.entries += 1;
+                    return Ok(())
+                }
+                Err(SlottedPageError::PageFull) => {}
+                Err(_) => return Err(LsmError::EntryTooLarge),
+            }
+        }
+        let page = self.allocator.allocate(self.store)?;
+        let mut slotted = SlottedPage::init(page.try_write()?, DATA_HEADER_LEN);
+        write_u32(slotted.reserved_mut(), 0, DATA_MAGIC);
+        slotted.insert(&cell).map_err(|_| LsmError::EntryTooLarge)?;
+        self.index.push((key.to_vec(), page.id()));
+        self.entries += 1;
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<Run, LsmError> {
+        let mut index_pages = vec![self.new_index_page()?];
+        for (first, page) in &self.index {
+            let mut cell = (page.offset() as u64).to_le_bytes().to_vec();
+            cell.extend_from_slice(first);
+            let inserted = {
+                let pinned = self.store.pin_page(index_pages.last().unwrap())?;
+                let mut slotted = SlottedPage::new(pinned.try_write()?);
+                slotted.insert(&cell).is_ok()
+            };
+            if !inserted {
+                let next = self.new_index_page()?;
+                let pinned = self.store.pin_page(index_pages.last().unwrap())?;
+                let mut slotted = SlottedPage::new(pinned.try_write()?);
+                write_u64(slotted.reserved_mut(), INDEX_NEXT, next.offset() as u64);
+                drop(slotted);
+                let pinned = self.store.pin_page(&next)?;
+                SlottedPage::new(pinned.try_write()?).insert(&cell).map_err(|_| LsmError::EntryTooLarge)?;
+                index_pages.push(next);
+            }
+        }
+        let bloom = if self.hashes.is_empty() {
+            None
+        } else {
+            let mut filter = BloomFilter::new(self.hashes.len(), self.false_positive_rate);
+            self.hashes.iter().for_each(|hash| filter.insert_hash(*hash));
+            Some((overflow::write(self.store, self.allocator, &filter.to_bytes())?, filter))
+        };
+        let meta = index_pages[0];
+        {
+            let pinned = self.store.pin_page(&meta)?;
+            let mut slotted = SlottedPage::new(pinned.try_write()?);
+            let header = slotted.reserved_mut();
+            write_u64(header, INDEX_ENTRIES, self.entries);
+            write_u64(header, INDEX_BLOOM, bloom.as_ref().map_or(NO_PAGE, |(head, _)| head.offset() as u64));
+        }
+        Ok(Run { meta, index: self.index, index_pages, bloom })
+    }
+
+    fn new_index_page(&self) -> Result<PageId, LsmError> {
+        let page = self.allocator.allocate(self.store)?;
+        let mut slotted = SlottedPage::init(page.try_write()?, INDEX_HEADER_LEN);
+        let header = slotted.reserved_mut();
+        write_u32(header, 0, INDEX_MAGIC);
+        write_u64(header, INDEX_NEXT, NO_PAGE);
+        write_u64(header, INDEX_BLOOM, NO_PAGE);
+        Ok(page.id())
+    }
+}
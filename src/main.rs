@@ -1,3 +1,111 @@
-fn main() {
-    println!("Hello, world!");
+//! `purpledb [DATABASE [SCRIPT]]`: a shell over the database in the file DATABASE, or in memory if none is
+//! named, reading statements from SCRIPT if one is named and from standard input otherwise. An interactive
+//! shell keeps the lines entered in `~/.purpledb_history`.
+//!
+//! `purpledb --listen ADDRESS [DATABASE]`: a server for PostgreSQL clients over the same database, listening
+//! on ADDRESS, such as `127.0.0.1:5432`.
+//!
+//! `purpledb --resp ADDRESS [DATABASE]`: a server for Redis clients, keeping their keys in the keyspace
+//! `redis` of the database.
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use purpledb::connection::{Connection, ConnectionOptions};
+use purpledb::{pgwire, resp};
+use purpledb::shell::Shell;
+
+/// The keyspace `--resp` serves.
+const RESP_KEYSPACE: &str = "redis";
+
+const USAGE: &str = "\
+usage: purpledb [DATABASE [SCRIPT]]
+       purpledb --listen ADDRESS [DATABASE]
+       purpledb --resp ADDRESS [DATABASE]";
+
+/// The file in the home directory an interactive shell keeps its history in.
+const HISTORY_FILE: &str = ".purpledb_history";
+
+/// The protocol to serve, and the address to listen on.
+enum Server {
+    Postgres(String),
+    Resp(String),
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let server = match args.first().map(String::as_str) {
+        Some("--listen") if args.len() >= 2 => Some(Server::Postgres(args.drain(..2).nth(1).unwrap())),
+        Some("--resp") if args.len() >= 2 => Some(Server::Resp(args.drain(..2).nth(1).unwrap())),
+        _ => None,
+    };
+    let most = if server.is_some() { 1 } else { 2 };
+    if args.len() > most || args.first().is_some_and(|a| a.starts_with('-')) {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2)
+    }
+    let connection = match args.first() {
+        Some(path) => Connection::open(path, &ConnectionOptions::default()),
+        None => Connection::open_in_memory(),
+    };
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("purpledb: cannot open the database: {e}");
+            return ExitCode::FAILURE
+        }
+    };
+    if let Some(server) = server {
+        return listen_on(connection, server)
+    }
+    let mut shell = Shell::new(connection, io::stdout().lock());
+    let (ran, interactive) = match args.get(1) {
+        Some(script) => match File::open(script) {
+            Ok(file) => (shell.run(BufReader::new(file), false), false),
+            Err(e) => {
+                eprintln!("purpledb: cannot read {script}: {e}");
+                return ExitCode::FAILURE
+            }
+        },
+        None => {
+            let interactive = io::stdin().is_terminal();
+            if let Some(home) = interactive.then(|| std::env::var_os("HOME")).flatten() {
+                let path = PathBuf::from(home).join(HISTORY_FILE);
+                if let Err(e) = shell.open_history(&path) {
+                    eprintln!("purpledb: cannot keep a history in {}: {e}", path.display());
+                }
+            }
+            (shell.run(io::stdin().lock(), interactive), interactive)
+        }
+    };
+    let failed = shell.failed();
+    let closed = shell.into_connection().close();
+    match (ran, closed) {
+        (Err(e), _) => eprintln!("purpledb: {e}"),
+        (_, Err(e)) => eprintln!("purpledb: cannot commit: {e}"),
+        (Ok(_), Ok(())) if failed && !interactive => return ExitCode::FAILURE,
+        (Ok(_), Ok(())) => return ExitCode::SUCCESS,
+    }
+    ExitCode::FAILURE
+}
+
+fn listen_on(mut connection: Connection, server: Server) -> ExitCode {
+    let (Server::Postgres(address) | Server::Resp(address)) = &server;
+    let served = match TcpListener::bind(address) {
+        Ok(listener) => match server {
+            Server::Postgres(_) => pgwire::serve(&mut connection, listener).map_err(|e| e.to_string()),
+            Server::Resp(_) => resp::serve(&mut connection, RESP_KEYSPACE, listener).map_err(|e| e.to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+    // Serving only ends when it fails.
+    if let Err(e) = served {
+        eprintln!("purpledb: cannot serve on {address}: {e}");
+    }
+    if let Err(e) = connection.close() {
+        eprintln!("purpledb: cannot commit: {e}");
+    }
+    ExitCode::FAILURE
 }
@@ -0,0 +1,105 @@
+//! Accounting for the memory one query's operators hold, against a limit for the whole query.
+//!
+//! A `MemoryBudget` is shared by the operators of a plan. Each takes a `Reservation` of it and grows the
+//! reservation by the bytes of what it buffers; a reservation that would take the budget past its limit is
+//! refused, and the operator spills what it holds to temporary space instead, or fails if it cannot. What a
+//! reservation holds goes back to the budget as the operator lets go of it, and all of it when the
+//! reservation is dropped, so an operator that fails or is abandoned part way leaves nothing reserved.
+//!
+//! The budget counts the bytes operators say they hold, as they measure them, rather than what the
+//! allocator hands out, so it bounds a query's memory only as well as they measure. Its counts are atomic,
+//! so the workers of a parallel plan reserve out of the one budget of their query.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+}
+impl MemoryBudget {
+    /// A budget of `limit` bytes, none of them reserved.
+    pub fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget { limit, used: AtomicUsize::new(0), peak: AtomicUsize::new(0) }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Bytes reserved now, over every reservation.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// The most bytes reserved at once so far.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// A new reservation of no bytes, growing out of this budget.
+    pub fn reservation(&self) -> Reservation<'_> {
+        Reservation { budget: self, bytes: 0 }
+    }
+}
+
+/// Bytes one operator holds out of a `MemoryBudget`.
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+impl Reservation<'_> {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Reserve `bytes` more if the budget has room for them, and return whether it had; a refused
+    /// reservation is left as it was.
+    pub fn try_grow(&mut self, bytes: usize) -> bool {
+        let limit = self.budget.limit;
+        let grow = |used: usize| used.checked_add(bytes).filter(|used| *used <= limit);
+        let Ok(used) = self.budget.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, grow) else { return false };
+        self.budget.peak.fetch_max(used + bytes, Ordering::Relaxed);
+        self.bytes += bytes;
+        true
+    }
+
+    /// Give back `bytes` of the reservation, or all of it if it holds fewer.
+    pub fn shrink(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.bytes);
+        self.budget.used.fetch_sub(bytes, Ordering::Relaxed);
+        self.bytes -= bytes;
+    }
+
+    /// Give back the whole reservation.
+    pub fn clear(&mut self) {
+        self.shrink(self.bytes);
+    }
+}
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryBudget;
+
+    #[test]
+    fn test_reservations_share_the_limit() {
+        let budget = MemoryBudget::new(100);
+        let mut a = budget.reservation();
+        let mut b = budget.reservation();
+        assert!(a.try_grow(60));
+        assert!(!b.try_grow(50));
+        assert!(b.try_grow(40));
+        assert_eq!((budget.used(), a.bytes(), b.bytes()), (100, 60, 40));
+        a.shrink(1000);
+        assert_eq!(a.bytes(), 0);
+        assert!(b.try_grow(50));
+        drop(b);
+        assert_eq!((budget.used(), budget.peak()), (0, 100));
+    }
+}
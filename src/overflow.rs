@@ -0,0 +1,62 @@
+//! Overflow chains: byte strings too long for a single page, cut into page-sized pieces linked in a chain.
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+
+const MAGIC: u32 = 0x4f56_464c;
+const NEXT: usize = 8;
+const LEN: usize = 16;
+const DATA: usize = 24;
+const CHUNK_LEN: usize = PAGE_SIZE - DATA;
+const NO_PAGE: u64 = u64::MAX;
+
+/// Write `data` to a new chain and return its first page.
+pub(crate) fn write<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator, data: &[u8]) -> Result<PageId, PageError> {
+    // Written back to front so each page can be filled in one go, link included.
+    let mut next = NO_PAGE;
+    let mut head = None;
+    for chunk in data.chunks(CHUNK_LEN).rev() {
+        let page = allocator.allocate(store)?;
+        let mut buf = page.try_write()?;
+        write_u32(&mut *buf, 0, MAGIC);
+        write_u64(&mut *buf, NEXT, next);
+        write_u16(&mut *buf, LEN, chunk.len() as u16);
+        buf[DATA..DATA + chunk.len()].copy_from_slice(chunk);
+        next = page.id().offset() as u64;
+        head = Some(page.id());
+    }
+    Ok(head.expect("only data too long for a page is chained"))
+}
+
+/// Read back the whole chain starting at `head`.
+pub(crate) fn read<S: Storage>(store: &PageStore<S>, head: PageId) -> Result<Vec<u8>, PageError> {
+    let mut data = Vec::new();
+    for_each_page(store, head, |buf| data.extend_from_slice(&buf[DATA..DATA + read_u16(buf, LEN) as usize]))?;
+    Ok(data)
+}
+
+/// Return every page of the chain starting at `head` to the allocator.
+pub(crate) fn free<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator, head: PageId) -> Result<(), PageError> {
+    let mut pages = Vec::new();
+    let mut page = head;
+    for_each_page(store, head, |buf| {
+        pages.push(page);
+        page = PageId::new(read_u64(buf, NEXT) as usize);
+    })?;
+    pages.into_iter().try_for_each(|page| allocator.free(store, page))
+}
+
+fn for_each_page<S: Storage>(store: &PageStore<S>, head: PageId, mut visit: impl FnMut(&[u8])) -> Result<(), PageError> {
+    let mut next = head.offset() as u64;
+    while next != NO_PAGE {
+        let page = store.pin_page(&PageId::new(next as usize))?;
+        let buf = page.try_read()?;
+        if read_u32(&*buf, 0) != MAGIC {
+            return Err(PageError::WrongPageType)
+        }
+        visit(&*buf);
+        next = read_u64(&*buf, NEXT);
+    }
+    Ok(())
+}
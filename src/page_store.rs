@@ -1,47 +1,121 @@
-use std::{cell::RefCell, collections::HashMap, ops::{Deref, DerefMut}};
+//! Buffer pool: a fixed number of page frames shared by every access method, with per-page latches.
+//!
+//! A page must be pinned to stay in its frame, and latched to be read or written: any number of readers
+//! or one writer at a time. `try_read` and `try_write` fail at once if the latch is taken, which suits
+//! code that owns the store. Code that shares the store between threads uses `read_latch` and
+//! `write_latch` instead, which wait for the latch and hold the pin themselves, so a latch can outlive
+//! the scope it was taken in as latch coupling needs. A reader that turns out to need write access can
+//! try to upgrade its latch, and a writer done writing can downgrade its latch to let readers in.
+use std::{collections::HashMap, mem::ManuallyDrop, ops::{Deref, DerefMut}, ptr, sync::{Condvar, Mutex, MutexGuard}};
 
 use crate::storage::{Storage, StorageError};
 
 pub struct PageStore<S: Storage> {
-    pool: RefCell<PoolInternal<S>>
+    pool: Mutex<PoolInternal<S>>,
+    /// Signalled whenever a latch is released, for threads waiting to take one.
+    released: Condvar,
 }
 impl<'store, S: Storage> PageStore<S> {
     pub fn new(storage: S) -> PageStore<S> {
         PageStore {
-            pool: RefCell::new(PoolInternal::new(storage))
+            pool: Mutex::new(PoolInternal::new(storage)),
+            released: Condvar::new(),
         }
     }
 
+    /// Give back the underlying storage. Pages that were never flushed are discarded.
+    pub fn into_storage(self) -> S {
+        self.pool.into_inner().unwrap().storage
+    }
+
+    fn pool(&self) -> MutexGuard<'_, PoolInternal<S>> {
+        self.pool.lock().unwrap()
+    }
+
     pub fn pin_page(&'store self, page: &PageId) -> Result<PinnedPage<'store, S>, PageError> {
-        self.pool.borrow_mut().pin_page(page)?;
-        Ok(PinnedPage { id: *page, store: &self })
+        self.pool().pin_page(page)?;
+        Ok(PinnedPage { id: *page, store: self })
     }
     
     pub fn allocate_page(&'store self, page: &PageId) -> Result<PinnedPage<'store, S>, PageError> {
-        self.pool.borrow_mut().create_and_pin_page(page)?;
-        Ok(PinnedPage { id: *page, store: &self })
+        self.pool().create_and_pin_page(page)?;
+        Ok(PinnedPage { id: *page, store: self })
+    }
+
+    /// Pin `page` and take its read latch, waiting while a writer holds it.
+    pub fn read_latch(&'store self, page: &PageId) -> Result<ReadLatch<'store, S>, PageError> {
+        let pinned = self.pin_page(page)?;
+        let data = self.wait_for(|pool| pool.try_get_read(page))?;
+        Ok(ReadLatch { pinned, data })
+    }
+
+    /// Pin `page` and take its write latch, waiting while anyone else holds it.
+    pub fn write_latch(&'store self, page: &PageId) -> Result<WriteLatch<'store, S>, PageError> {
+        let pinned = self.pin_page(page)?;
+        let data = self.wait_for(|pool| pool.try_get_write(page))?;
+        Ok(WriteLatch { pinned, data })
+    }
+
+    /// Retry `take` until the latch it wants stops being in use.
+    fn wait_for<T>(&self, take: impl Fn(&mut PoolInternal<S>) -> Result<T, PageError>) -> Result<T, PageError> {
+        let mut pool = self.pool();
+        loop {
+            match take(&mut pool) {
+                Err(PageError::PageInUseForRead | PageError::PageInUseForWrite) => {
+                    pool = self.released.wait(pool).unwrap();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// How many pins are held on `page`, counting the caller's own; 0 if it is not in the pool.
+    pub fn pin_count(&self, page: &PageId) -> usize {
+        self.pool().page_state.get(page).map_or(0, |meta| meta.pins)
     }
 
     fn unpin_page(&'store self, page: &PageId) -> Result<(), PageError> {
-        self.pool.borrow_mut().unpin_page(page)
+        self.pool().unpin_page(page)
     }
 
     fn try_get_read(&'store self, page: &PageId) -> Result<*const Data, PageError> {
-        self.pool.borrow_mut().try_get_read(page)
+        self.pool().try_get_read(page)
     }
 
     fn release_read(&'store self, page: &PageId) -> Result<(), PageError> {
-        self.pool.borrow_mut().release_read(page)
+        self.pool().release_read(page)?;
+        self.released.notify_all();
+        Ok(())
     }
 
     fn try_get_write(&'store self, page: &PageId) -> Result<*mut Data, PageError> {
-        self.pool.borrow_mut().try_get_write(page)
+        self.pool().try_get_write(page)
     }
 
     fn release_write(&'store self, page: &PageId) -> Result<(), PageError> {
-        self.pool.borrow_mut().release_write(page)
+        self.pool().release_write(page)?;
+        self.released.notify_all();
+        Ok(())
+    }
+
+    /// Write every dirty page back to storage and then ask storage to make it durable.
+    pub fn flush(&self) -> Result<(), PageError> {
+        self.pool().flush()
+    }
+
+    /// Drop every page in the pool without writing back the dirty ones, and then hand `discard` the
+    /// storage: to discard what was written to it since it was last synced, say. Fails, changing nothing,
+    /// while a page is pinned.
+    pub fn discard(&self, discard: impl FnOnce(&mut S)) -> Result<(), PageError> {
+        let mut pool = self.pool();
+        if pool.page_state.values().any(|meta| meta.pins > 0) {
+            return Err(PageError::PageInUseForRead)
+        }
+        let frames: Vec<usize> = pool.page_state.drain().map(|(_, meta)| meta.index).collect();
+        pool.free_frames.extend(frames);
+        discard(&mut pool.storage);
+        Ok(())
     }
-    
 }
 
 const POOL_SIZE: usize = 40;
@@ -49,41 +123,77 @@ struct PoolInternal<S: Storage> {
     storage: S,
     pages: Vec<Page>,
     page_state: HashMap<PageId, PageMeta>,
+    free_frames: Vec<usize>,
+    clock: u64,
 }
 impl<S: Storage> PoolInternal<S> {
     fn new(storage: S) -> PoolInternal<S> {
-        PoolInternal { storage, pages: Vec::with_capacity(POOL_SIZE), page_state: HashMap::new() }
+        PoolInternal {
+            storage,
+            pages: Vec::with_capacity(POOL_SIZE),
+            page_state: HashMap::new(),
+            free_frames: Vec::new(),
+            clock: 0,
+        }
     }
 
     fn allocate_page(&mut self) -> Result<PageMeta, PageError> {
-        if self.pages.len() >= POOL_SIZE {
-            return Err(PageError::PoolIsFull)
-        }
-        let index = self.pages.len();
-        self.pages.push(Page { buf: [0u8; 4096] });
+        let index = if let Some(index) = self.free_frames.pop() {
+            index
+        } else if self.pages.len() < POOL_SIZE {
+            self.pages.push(Page { buf: [0u8; PAGE_SIZE] });
+            self.pages.len() - 1
+        } else {
+            self.evict()?
+        };
         Ok(PageMeta {
             index,
             pins: 0,
             readers: 0,
-            writer: false
+            writer: false,
+            dirty: false,
+            last_used: self.tick(),
         })
     }
 
+    /// Reclaim the frame of the least recently used unpinned page, writing it back first if it is dirty.
+    fn evict(&mut self) -> Result<usize, PageError> {
+        let victim = self.page_state.iter()
+            .filter(|(_, meta)| meta.pins == 0)
+            .min_by_key(|(_, meta)| meta.last_used)
+            .map(|(id, _)| *id)
+            .ok_or(PageError::PoolIsFull)?;
+        let meta = &self.page_state[&victim];
+        if meta.dirty {
+            self.storage.write_page(&self.pages[meta.index].buf, &victim).map_err(PageError::Storage)?;
+        }
+        let meta = self.page_state.remove(&victim).unwrap();
+        Ok(meta.index)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
     fn create_and_pin_page(&mut self, page: &PageId) -> Result<(), PageError> {
-        self.storage.create_page(page).map_err(|e| PageError::Storage(e))?;
+        self.storage.create_page(page).map_err(PageError::Storage)?;
         self.pin_page(page)
     }
 
     fn pin_page(&mut self, page: &PageId) -> Result<(), PageError> {
+        let now = self.tick();
         if let Some(meta) = self.page_state.get_mut(page) {
             meta.pins += 1;
+            meta.last_used = now;
         } else {
             let mut meta = self.allocate_page()?;
+            if let Err(e) = self.storage.load_page(&mut self.pages[meta.index].buf, page) {
+                self.free_frames.push(meta.index);
+                return Err(PageError::Storage(e))
+            }
             meta.pins += 1;
-            self.page_state.insert(page.clone(), meta);
-            let meta = &self.page_state[page];
-            let index = meta.index;
-            self.storage.load_page(&mut self.pages[index].buf, page).map_err(|e| PageError::Storage(e))?;
+            self.page_state.insert(*page, meta);
         }
         Ok(())
     }
@@ -123,6 +233,7 @@ impl<S: Storage> PoolInternal<S> {
             return Err(PageError::PageInUseForRead)
         }
         meta.writer = true;
+        meta.dirty = true;
         let index = meta.index;
         Ok(&mut self.pages[index].buf)
     }
@@ -132,12 +243,47 @@ impl<S: Storage> PoolInternal<S> {
         meta.writer = false;
         Ok(())
     }
+
+    /// Turn the caller's read latch into the write latch, if no one else is reading.
+    fn try_upgrade(&mut self, page: &PageId) -> Result<*mut Data, PageError> {
+        let meta = self.get_meta(page)?;
+        if meta.readers > 1 {
+            return Err(PageError::PageInUseForRead)
+        }
+        meta.readers = 0;
+        meta.writer = true;
+        meta.dirty = true;
+        let index = meta.index;
+        Ok(&mut self.pages[index].buf)
+    }
+
+    fn downgrade(&mut self, page: &PageId) -> Result<(), PageError> {
+        let meta = self.get_meta(page)?;
+        meta.writer = false;
+        meta.readers = 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), PageError> {
+        for (id, meta) in self.page_state.iter_mut() {
+            if meta.writer {
+                return Err(PageError::PageInUseForWrite)
+            }
+            if meta.dirty {
+                self.storage.write_page(&self.pages[meta.index].buf, id).map_err(PageError::Storage)?;
+                meta.dirty = false;
+            }
+        }
+        self.storage.sync().map_err(PageError::Storage)
+    }
 }
 struct PageMeta {
     index: usize,
     pins: usize,
     readers: usize,
     writer: bool,
+    dirty: bool,
+    last_used: u64,
 }
 
 pub struct PinnedPage<'store, S: Storage> {
@@ -145,14 +291,18 @@ pub struct PinnedPage<'store, S: Storage> {
     store: &'store PageStore<S>
 }
 impl<'pin, 'store, S: Storage> PinnedPage<'store, S> {
-    fn try_read(&'pin self) -> Result<ConstPage<'pin, 'store, S>, PageError> {
+    pub fn id(&self) -> PageId {
+        self.id
+    }
+
+    pub fn try_read(&'pin self) -> Result<ConstPage<'pin, 'store, S>, PageError> {
         let data = self.store.try_get_read(&self.id)?;
-        Ok(ConstPage { pinned: self, data: data })
+        Ok(ConstPage { pinned: self, data })
     }
 
-    fn try_write(&'pin self) -> Result<MutPage<'pin, 'store, S>, PageError> {
+    pub fn try_write(&'pin self) -> Result<MutPage<'pin, 'store, S>, PageError> {
         let data = self.store.try_get_write(&self.id)?;
-        Ok(MutPage { pinned: self, data: data })
+        Ok(MutPage { pinned: self, data })
     }
 }
 impl<S: Storage> Drop for PinnedPage<'_, S> {
@@ -161,7 +311,7 @@ impl<S: Storage> Drop for PinnedPage<'_, S> {
     }
 }
 
-struct ConstPage<'pin, 'store, S: Storage> {
+pub struct ConstPage<'pin, 'store, S: Storage> {
     pinned: &'pin PinnedPage<'store, S>,
     data: *const Data
 }
@@ -181,7 +331,7 @@ impl<S: Storage> Deref for ConstPage<'_, '_, S> {
     }
 }
 
-struct MutPage<'pin, 'store, S: Storage> {
+pub struct MutPage<'pin, 'store, S: Storage> {
     pinned: &'pin PinnedPage<'store, S>,
     data: *mut Data
 }
@@ -209,6 +359,86 @@ impl<S: Storage> Drop for MutPage<'_, '_, S> {
     }
 }
 
+/// A pinned page whose read latch is held until the latch is dropped.
+pub struct ReadLatch<'store, S: Storage> {
+    pinned: PinnedPage<'store, S>,
+    data: *const Data,
+}
+impl<'store, S: Storage> ReadLatch<'store, S> {
+    pub fn id(&self) -> PageId {
+        self.pinned.id
+    }
+
+    /// Exchange this latch for the write latch, which fails if other readers hold the page.
+    pub fn try_upgrade(self) -> Result<WriteLatch<'store, S>, ReadLatch<'store, S>> {
+        let Ok(data) = self.pinned.store.pool().try_upgrade(&self.pinned.id) else { return Err(self) };
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so the pin is moved out exactly once and the read latch, which
+        // the pool has turned into the write latch, is not released.
+        let pinned = unsafe { ptr::read(&this.pinned) };
+        Ok(WriteLatch { pinned, data })
+    }
+}
+impl<S: Storage> Deref for ReadLatch<'_, S> {
+    type Target = Data;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the pool hands out no write latch while this read latch is held
+        unsafe {
+            &*self.data
+        }
+    }
+}
+impl<S: Storage> Drop for ReadLatch<'_, S> {
+    fn drop(&mut self) {
+        self.pinned.store.release_read(&self.pinned.id).unwrap()
+    }
+}
+
+/// A pinned page whose write latch is held until the latch is dropped.
+pub struct WriteLatch<'store, S: Storage> {
+    pinned: PinnedPage<'store, S>,
+    data: *mut Data,
+}
+impl<'store, S: Storage> WriteLatch<'store, S> {
+    pub fn id(&self) -> PageId {
+        self.pinned.id
+    }
+
+    /// Exchange this latch for a read latch without letting another writer in between.
+    pub fn downgrade(self) -> ReadLatch<'store, S> {
+        let this = ManuallyDrop::new(self);
+        this.pinned.store.pool().downgrade(&this.pinned.id).unwrap();
+        this.pinned.store.released.notify_all();
+        // SAFETY: as in `ReadLatch::try_upgrade`; the write latch is now a read latch.
+        let pinned = unsafe { ptr::read(&this.pinned) };
+        ReadLatch { pinned, data: this.data }
+    }
+}
+impl<S: Storage> Deref for WriteLatch<'_, S> {
+    type Target = Data;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the write latch is exclusive
+        unsafe {
+            &*self.data
+        }
+    }
+}
+impl<S: Storage> DerefMut for WriteLatch<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the write latch is exclusive
+        unsafe {
+            &mut *self.data
+        }
+    }
+}
+impl<S: Storage> Drop for WriteLatch<'_, S> {
+    fn drop(&mut self) {
+        self.pinned.store.release_write(&self.pinned.id).unwrap();
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum PageError {
     PageNotInPool,
@@ -216,13 +446,25 @@ pub enum PageError {
     PageInUseForRead,
     Storage(StorageError),
     PoolIsFull,
+    /// The page does not carry the format the caller expected.
+    WrongPageType,
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
 pub struct PageId {
     offset: usize
 }
-pub type Data = [u8; 4096];
+impl PageId {
+    pub fn new(offset: usize) -> PageId {
+        PageId { offset }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+pub const PAGE_SIZE: usize = 4096;
+pub type Data = [u8; PAGE_SIZE];
 pub struct Page {
     buf: Data 
 }
@@ -231,7 +473,7 @@ pub struct Page {
 mod tests {
     use crate::storage::TestStorage;
 
-    use super::{PageStore, PageId, PageError};
+    use super::{PageStore, PageId, PageError, POOL_SIZE};
 
     #[test]
     fn test_happy() -> Result<(), PageError> {
@@ -252,6 +494,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_eviction_writes_back() -> Result<(), PageError> {
+        let page_store = PageStore::new(TestStorage::new());
+        for offset in 0..POOL_SIZE * 2 {
+            let page = page_store.allocate_page(&PageId::new(offset))?;
+            (*page.try_write()?)[0] = offset as u8;
+        }
+
+        for offset in 0..POOL_SIZE * 2 {
+            let page = page_store.pin_page(&PageId::new(offset))?;
+            assert_eq!((*page.try_read()?)[0], offset as u8);
+        }
+
+        let pinned: Vec<_> = (0..POOL_SIZE).map(|o| page_store.pin_page(&PageId::new(o))).collect::<Result<_, _>>()?;
+        assert_eq!(page_store.pin_page(&PageId::new(POOL_SIZE)).err().unwrap(), PageError::PoolIsFull);
+        drop(pinned);
+
+        Ok(())
+    }
+
     #[test]
     fn test_writer_exclusion() -> Result<(), PageError> {
         let page_store = PageStore::new(TestStorage::new());
@@ -265,6 +527,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_latches_wait_upgrade_and_downgrade() -> Result<(), PageError> {
+        let page_store = PageStore::new(TestStorage::new());
+        drop(page_store.allocate_page(&PageId::new(0))?);
+        let id = PageId::new(0);
+
+        let reader = page_store.read_latch(&id)?;
+        let other = page_store.read_latch(&id)?;
+        let reader = reader.try_upgrade().err().unwrap();
+        drop(other);
+        let mut writer = reader.try_upgrade().ok().unwrap();
+        writer[0] = 1;
+        std::thread::scope(|s| {
+            // Waits for the latch rather than failing, and sees the write made before it was released.
+            let waiting = s.spawn(|| page_store.write_latch(&id).map(|mut page| { page[0] += 1; }));
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            let reader = writer.downgrade();
+            assert_eq!(page_store.read_latch(&id)?[0], 1);
+            drop(reader);
+            waiting.join().unwrap()
+        })?;
+        assert_eq!(page_store.read_latch(&id)?[0], 2);
+        Ok(())
+    }
+
     #[test]
     fn test_reader_exclusion() -> Result<(), PageError> {
         let page_store = PageStore::new(TestStorage::new());
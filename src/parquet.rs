@@ -0,0 +1,683 @@
+//! Parquet: tables and query results written out as Parquet files, for analytical tools to read.
+//!
+//! A file holds a row group for each `row_group_rows` rows, and a row group a column chunk of one data
+//! page for each column: its values in the plain encoding, uncompressed, after the definition levels of a
+//! column that may hold nulls, run-length encoded. The metadata in the footer is encoded in Thrift's
+//! compact protocol, as the format has it. A writer buffers a row group's rows before writing them.
+//!
+//! Column types map to Parquet's types and logical types: `Int` to `INT64`, `Float` to `DOUBLE`, `Bool` to
+//! `BOOLEAN` and `Bytes` to `BYTE_ARRAY`; `Text` and `Json` to `STRING` and `JSON` over `BYTE_ARRAY`;
+//! `Date` to `DATE` over `INT32`; and `Time`, `Timestamp` and `TimestampTz` to `TIME` and `TIMESTAMP` in
+//! microseconds over `INT64`, only `TimestampTz` adjusted to UTC. A decimal of at most 18 digits is a
+//! `DECIMAL` over `INT64`, and one of at most 38 over a 16 byte `FIXED_LEN_BYTE_ARRAY`. Intervals, which
+//! Parquet's `INTERVAL` cannot hold once negative or finer than milliseconds, decimals of more or of any
+//! number of digits, and query columns of no one type are written as their text, as `STRING`s.
+use std::io::Write;
+
+use crate::catalog::CatalogError;
+use crate::database::{Database, DatabaseError};
+use crate::exec::expr::cast;
+use crate::exec::ExecError;
+use crate::storage::Storage;
+use crate::tuple::{ColumnType, Value};
+
+/// Rows a row group holds, unless the options say otherwise.
+pub const DEFAULT_ROW_GROUP_ROWS: usize = 65_536;
+
+const MAGIC: &[u8] = b"PAR1";
+const CREATED_BY: &str = concat!("purpledb version ", env!("CARGO_PKG_VERSION"));
+
+// Thrift's compact protocol's types.
+const BOOL_TRUE: u8 = 1;
+const BOOL_FALSE: u8 = 2;
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+// The values of the format's enums used here.
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const UNCOMPRESSED: i32 = 0;
+const DATA_PAGE: i32 = 0;
+const CONVERTED_UTF8: i32 = 0;
+const CONVERTED_DECIMAL: i32 = 5;
+const CONVERTED_DATE: i32 = 6;
+const CONVERTED_TIMESTAMP_MICROS: i32 = 10;
+const CONVERTED_JSON: i32 = 19;
+
+#[derive(Debug, Clone)]
+pub struct ParquetOptions {
+    /// The most rows a row group holds, and so the most a writer buffers.
+    pub row_group_rows: usize,
+}
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions { row_group_rows: DEFAULT_ROW_GROUP_ROWS }
+    }
+}
+
+/// Write the rows of the table called `table` in `db` to `out` as a Parquet file, returning how many. A
+/// column that is `NOT NULL` is a required column of the file, and any other an optional one.
+pub fn write_table<S: Storage>(
+    db: &Database<S>,
+    table: &str,
+    out: impl Write,
+    options: &ParquetOptions,
+) -> Result<u64, DatabaseError> {
+    let def = db.catalog().table(table);
+    let def = def.ok_or_else(|| DatabaseError::Catalog(CatalogError::NoSuchTable(table.to_string())))?;
+    let columns = def.columns.iter().map(|c| ColumnSpec::new(&c.name, Some(c.column.column_type), c.column.nullable));
+    let mut writer = Writer::new(out, columns.collect(), options)?;
+    db.each_row(table, |row| writer.write_row(row))?;
+    writer.finish()
+}
+
+/// Run the `SELECT` in `sql` over `db`, with `params` as the values of its bind parameters, and write its
+/// rows to `out` as a Parquet file, returning how many. Every column of the file is optional.
+pub fn write_query<S: Storage>(
+    db: &Database<S>,
+    sql: &str,
+    params: &[Value],
+    out: impl Write,
+    options: &ParquetOptions,
+) -> Result<u64, DatabaseError> {
+    let mut statement = db.prepare(sql)?;
+    let columns = statement.columns().iter().zip(statement.types()).map(|(name, t)| ColumnSpec::new(name, *t, true));
+    let mut writer = Writer::new(out, columns.collect(), options)?;
+    for row in statement.query(db, params)? {
+        writer.write_row(row?)?;
+    }
+    writer.finish()
+}
+
+/// How the values of a column are stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Physical {
+    Boolean,
+    Int32,
+    Int64,
+    Double,
+    ByteArray,
+    /// A decimal's unscaled value, big-endian in 16 bytes.
+    Fixed16,
+}
+impl Physical {
+    fn id(self) -> i32 {
+        match self {
+            Physical::Boolean => 0,
+            Physical::Int32 => 1,
+            Physical::Int64 => 2,
+            Physical::Double => 5,
+            Physical::ByteArray => 6,
+            Physical::Fixed16 => 7,
+        }
+    }
+}
+
+/// A column as it is written.
+struct ColumnSpec {
+    name: String,
+    /// The type values are cast to before they are written.
+    column_type: ColumnType,
+    physical: Physical,
+    nullable: bool,
+}
+impl ColumnSpec {
+    fn new(name: &str, column_type: Option<ColumnType>, nullable: bool) -> ColumnSpec {
+        let (column_type, physical) = match column_type {
+            Some(ColumnType::Bool) => (ColumnType::Bool, Physical::Boolean),
+            Some(ColumnType::Date) => (ColumnType::Date, Physical::Int32),
+            Some(t @ (ColumnType::Int | ColumnType::Time | ColumnType::Timestamp | ColumnType::TimestampTz)) => {
+                (t, Physical::Int64)
+            }
+            Some(ColumnType::Float) => (ColumnType::Float, Physical::Double),
+            Some(t @ (ColumnType::Bytes | ColumnType::Json)) => (t, Physical::ByteArray),
+            Some(t @ ColumnType::Decimal { precision: 1..=18, .. }) => (t, Physical::Int64),
+            Some(t @ ColumnType::Decimal { precision: 19..=38, .. }) => (t, Physical::Fixed16),
+            _ => (ColumnType::Text, Physical::ByteArray),
+        };
+        ColumnSpec { name: name.to_string(), column_type, physical, nullable }
+    }
+
+    /// Write the column's element of the schema.
+    fn schema(&self, t: &mut Thrift) {
+        t.i32(1, self.physical.id());
+        if self.physical == Physical::Fixed16 {
+            t.i32(2, 16);
+        }
+        t.i32(3, if self.nullable { OPTIONAL } else { REQUIRED }).binary(4, self.name.as_bytes());
+        // The logical type is a union of structs; a time's unit is one of empty structs, micros the second.
+        let time = |t: &mut Thrift, id, utc| {
+            t.strukt(10, |t| {
+                t.strukt(id, |t| {
+                    t.bool(1, utc).strukt(2, |t| {
+                        t.strukt(2, |_| {});
+                    });
+                });
+            });
+        };
+        match self.column_type {
+            ColumnType::Text => {
+                t.i32(6, CONVERTED_UTF8).strukt(10, |t| {
+                    t.strukt(1, |_| {});
+                });
+            }
+            ColumnType::Json => {
+                t.i32(6, CONVERTED_JSON).strukt(10, |t| {
+                    t.strukt(12, |_| {});
+                });
+            }
+            ColumnType::Date => {
+                t.i32(6, CONVERTED_DATE).strukt(10, |t| {
+                    t.strukt(6, |_| {});
+                });
+            }
+            ColumnType::Time => time(t, 7, false),
+            ColumnType::Timestamp => time(t, 8, false),
+            ColumnType::TimestampTz => {
+                t.i32(6, CONVERTED_TIMESTAMP_MICROS);
+                time(t, 8, true)
+            }
+            ColumnType::Decimal { precision, scale } => {
+                t.i32(6, CONVERTED_DECIMAL).i32(7, scale as i32).i32(8, precision as i32).strukt(10, |t| {
+                    t.strukt(5, |t| {
+                        t.i32(1, scale as i32).i32(2, precision as i32);
+                    });
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The values of a column in the row group being buffered.
+#[derive(Default)]
+struct Chunk {
+    /// Whether each row's value is not null.
+    defined: Vec<bool>,
+    /// The values that are not null, plain encoded.
+    values: Vec<u8>,
+    /// How many values `values` holds.
+    count: usize,
+}
+impl Chunk {
+    fn push(&mut self, spec: &ColumnSpec, value: Value) -> Result<(), DatabaseError> {
+        let value = match value.column_type() {
+            Some(t) if t.same_kind(spec.column_type) => value,
+            _ => cast(value, spec.column_type)?,
+        };
+        self.defined.push(!matches!(value, Value::Null));
+        let bytes = |values: &mut Vec<u8>, bytes: &[u8]| {
+            values.extend((bytes.len() as u32).to_le_bytes());
+            values.extend(bytes);
+        };
+        match value {
+            Value::Null => return Ok(()),
+            Value::Bool(v) => {
+                if self.count.is_multiple_of(8) {
+                    self.values.push(0);
+                }
+                *self.values.last_mut().unwrap() |= (v as u8) << (self.count % 8);
+            }
+            Value::Int(v) | Value::Time(v) | Value::Timestamp(v) | Value::TimestampTz(v) => {
+                self.values.extend(v.to_le_bytes())
+            }
+            Value::Float(v) => self.values.extend(v.to_le_bytes()),
+            Value::Date(v) => self.values.extend(v.to_le_bytes()),
+            Value::Bytes(v) => bytes(&mut self.values, &v),
+            Value::Text(v) => bytes(&mut self.values, v.as_bytes()),
+            Value::Json(v) => bytes(&mut self.values, v.to_string().as_bytes()),
+            Value::Decimal(v) => {
+                let ColumnType::Decimal { scale, .. } = spec.column_type else { unreachable!() };
+                let rounded = v.round(scale);
+                let unscaled = rounded.and_then(|d| Some((d.is_negative(), d.digits().parse::<i128>().ok()?)));
+                let invalid = || ExecError::InvalidCast(Value::Decimal(v.clone()), spec.column_type);
+                let unscaled = match unscaled.ok_or_else(invalid)? {
+                    (negative, magnitude) if negative => -magnitude,
+                    (_, magnitude) => magnitude,
+                };
+                match spec.physical {
+                    Physical::Int64 => {
+                        self.values.extend(i64::try_from(unscaled).map_err(|_| invalid())?.to_le_bytes())
+                    }
+                    _ => self.values.extend(unscaled.to_be_bytes()),
+                }
+            }
+            value => unreachable!("{value:?} cast to a type written as another"),
+        }
+        self.count += 1;
+        Ok(())
+    }
+}
+
+/// Where a column chunk was written, and how many values it holds.
+struct ChunkMeta {
+    offset: u64,
+    size: u64,
+    values: u64,
+}
+
+struct RowGroup {
+    rows: u64,
+    chunks: Vec<ChunkMeta>,
+}
+
+/// A Parquet file being written, a row group at a time.
+struct Writer<W: Write> {
+    out: W,
+    /// Bytes written so far.
+    offset: u64,
+    columns: Vec<ColumnSpec>,
+    chunks: Vec<Chunk>,
+    /// Rows in the chunks, not yet written.
+    buffered: usize,
+    row_groups: Vec<RowGroup>,
+    rows: u64,
+    row_group_rows: usize,
+}
+impl<W: Write> Writer<W> {
+    fn new(out: W, columns: Vec<ColumnSpec>, options: &ParquetOptions) -> Result<Writer<W>, DatabaseError> {
+        let chunks = columns.iter().map(|_| Chunk::default()).collect();
+        let row_group_rows = options.row_group_rows.max(1);
+        let mut writer =
+            Writer { out, offset: 0, columns, chunks, buffered: 0, row_groups: Vec::new(), rows: 0, row_group_rows };
+        writer.write(MAGIC)?;
+        Ok(writer)
+    }
+
+    fn write_row(&mut self, row: Vec<Value>) -> Result<(), DatabaseError> {
+        for ((chunk, spec), value) in self.chunks.iter_mut().zip(&self.columns).zip(row) {
+            chunk.push(spec, value)?;
+        }
+        self.buffered += 1;
+        self.rows += 1;
+        if self.buffered >= self.row_group_rows {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    /// Write a column chunk of one data page for each column, from what the chunks hold.
+    fn write_row_group(&mut self) -> Result<(), DatabaseError> {
+        let mut metas = Vec::with_capacity(self.chunks.len());
+        for i in 0..self.chunks.len() {
+            let chunk = std::mem::take(&mut self.chunks[i]);
+            let mut page = Vec::new();
+            if self.columns[i].nullable {
+                let levels = levels(&chunk.defined);
+                page.extend((levels.len() as u32).to_le_bytes());
+                page.extend(levels);
+            }
+            page.extend(chunk.values);
+            let mut header = Vec::new();
+            let mut t = Thrift::new(&mut header);
+            t.i32(1, DATA_PAGE).i32(2, page.len() as i32).i32(3, page.len() as i32).strukt(5, |t| {
+                t.i32(1, chunk.defined.len() as i32).i32(2, PLAIN).i32(3, RLE).i32(4, RLE);
+            });
+            t.stop();
+            let offset = self.offset;
+            self.write(&header)?;
+            self.write(&page)?;
+            metas.push(ChunkMeta { offset, size: self.offset - offset, values: chunk.defined.len() as u64 });
+        }
+        self.row_groups.push(RowGroup { rows: self.buffered as u64, chunks: metas });
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Write the rows still buffered and the footer, returning how many rows the file holds.
+    fn finish(mut self) -> Result<u64, DatabaseError> {
+        if self.buffered > 0 {
+            self.write_row_group()?;
+        }
+        let mut footer = Vec::new();
+        let mut t = Thrift::new(&mut footer);
+        t.i32(1, 1).list(2, STRUCT, self.columns.len() + 1);
+        t.element(|t| {
+            t.binary(4, b"schema").i32(5, self.columns.len() as i32);
+        });
+        for spec in &self.columns {
+            t.element(|t| spec.schema(t));
+        }
+        t.i64(3, self.rows as i64).list(4, STRUCT, self.row_groups.len());
+        for group in &self.row_groups {
+            t.element(|t| {
+                t.list(1, STRUCT, group.chunks.len());
+                for (chunk, spec) in group.chunks.iter().zip(&self.columns) {
+                    t.element(|t| {
+                        t.i64(2, chunk.offset as i64).strukt(3, |t| {
+                            t.i32(1, spec.physical.id()).list(2, I32, 2).raw_i32(PLAIN).raw_i32(RLE);
+                            t.list(3, BINARY, 1).raw_binary(spec.name.as_bytes());
+                            t.i32(4, UNCOMPRESSED).i64(5, chunk.values as i64);
+                            t.i64(6, chunk.size as i64).i64(7, chunk.size as i64).i64(9, chunk.offset as i64);
+                        });
+                    });
+                }
+                t.i64(2, group.chunks.iter().map(|c| c.size).sum::<u64>() as i64).i64(3, group.rows as i64);
+            });
+        }
+        t.binary(6, CREATED_BY.as_bytes()).stop();
+        let length = (footer.len() as u32).to_le_bytes();
+        self.write(&footer)?;
+        self.write(&length)?;
+        self.write(MAGIC)?;
+        self.out.flush().map_err(|e| DatabaseError::Parquet(e.kind()))?;
+        Ok(self.rows)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), DatabaseError> {
+        self.out.write_all(bytes).map_err(|e| DatabaseError::Parquet(e.kind()))?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// Definition levels, one bit each, as runs of the RLE and bit-packing hybrid encoding.
+fn levels(defined: &[bool]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for run in defined.chunk_by(|a, b| a == b) {
+        varint(&mut out, (run.len() as u64) << 1);
+        out.push(run[0] as u8);
+    }
+    out
+}
+
+fn varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+/// A struct being written in Thrift's compact protocol.
+struct Thrift<'a> {
+    out: &'a mut Vec<u8>,
+    /// The id of the last field written.
+    last: i16,
+}
+impl<'a> Thrift<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Thrift<'a> {
+        Thrift { out, last: 0 }
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        match id - self.last {
+            delta @ 1..=15 => self.out.push((delta as u8) << 4 | kind),
+            _ => {
+                self.out.push(kind);
+                varint(self.out, ((id << 1) ^ (id >> 15)) as u16 as u64);
+            }
+        }
+        self.last = id;
+    }
+
+    fn bool(&mut self, id: i16, v: bool) -> &mut Self {
+        self.field(id, if v { BOOL_TRUE } else { BOOL_FALSE });
+        self
+    }
+
+    fn i32(&mut self, id: i16, v: i32) -> &mut Self {
+        self.field(id, I32);
+        self.raw_i32(v)
+    }
+
+    fn i64(&mut self, id: i16, v: i64) -> &mut Self {
+        self.field(id, I64);
+        varint(self.out, ((v << 1) ^ (v >> 63)) as u64);
+        self
+    }
+
+    fn binary(&mut self, id: i16, v: &[u8]) -> &mut Self {
+        self.field(id, BINARY);
+        self.raw_binary(v)
+    }
+
+    fn strukt(&mut self, id: i16, f: impl FnOnce(&mut Thrift)) -> &mut Self {
+        self.field(id, STRUCT);
+        self.element(f)
+    }
+
+    /// Start a list of `len` elements of `kind`, each to be written with a `raw_` method or `element`.
+    fn list(&mut self, id: i16, kind: u8, len: usize) -> &mut Self {
+        self.field(id, LIST);
+        match len {
+            0..15 => self.out.push((len as u8) << 4 | kind),
+            _ => {
+                self.out.push(0xf0 | kind);
+                varint(self.out, len as u64);
+            }
+        }
+        self
+    }
+
+    fn raw_i32(&mut self, v: i32) -> &mut Self {
+        varint(self.out, ((v << 1) ^ (v >> 31)) as u32 as u64);
+        self
+    }
+
+    fn raw_binary(&mut self, v: &[u8]) -> &mut Self {
+        varint(self.out, v.len() as u64);
+        self.out.extend(v);
+        self
+    }
+
+    /// Write a struct, its fields written by `f`, without a field header: as an element of a list.
+    fn element(&mut self, f: impl FnOnce(&mut Thrift)) -> &mut Self {
+        let mut inner = Thrift::new(self.out);
+        f(&mut inner);
+        inner.stop();
+        self
+    }
+
+    fn stop(&mut self) {
+        self.out.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{Database, DatabaseError};
+    use crate::page_store::PageStore;
+    use crate::storage::TestStorage;
+    use crate::tuple::Value;
+
+    use super::{write_query, write_table, ParquetOptions};
+
+    /// A value read back in Thrift's compact protocol.
+    #[derive(Debug, PartialEq)]
+    enum T {
+        Int(i64),
+        Bool(bool),
+        Binary(Vec<u8>),
+        List(Vec<T>),
+        Struct(Vec<(i16, T)>),
+    }
+    impl T {
+        fn get(&self, id: i16) -> &T {
+            let T::Struct(fields) = self else { panic!("not a struct: {self:?}") };
+            &fields.iter().find(|(i, _)| *i == id).unwrap_or_else(|| panic!("no field {id} in {self:?}")).1
+        }
+
+        fn int(&self) -> i64 {
+            let T::Int(v) = self else { panic!("not an integer: {self:?}") };
+            *v
+        }
+
+        fn list(&self) -> &[T] {
+            let T::List(v) = self else { panic!("not a list: {self:?}") };
+            v
+        }
+
+        fn text(&self) -> &str {
+            let T::Binary(v) = self else { panic!("not binary: {self:?}") };
+            std::str::from_utf8(v).unwrap()
+        }
+    }
+
+    fn varint(b: &[u8], at: &mut usize) -> u64 {
+        let (mut v, mut shift) = (0, 0);
+        loop {
+            *at += 1;
+            v |= ((b[*at - 1] & 0x7f) as u64) << shift;
+            if b[*at - 1] < 0x80 {
+                return v
+            }
+            shift += 7;
+        }
+    }
+
+    fn zigzag(v: u64) -> i64 {
+        (v >> 1) as i64 ^ -((v & 1) as i64)
+    }
+
+    fn read(b: &[u8], at: &mut usize, kind: u8) -> T {
+        match kind {
+            1 => T::Bool(true),
+            2 => T::Bool(false),
+            5 | 6 => T::Int(zigzag(varint(b, at))),
+            8 => {
+                let len = varint(b, at) as usize;
+                *at += len;
+                T::Binary(b[*at - len..*at].to_vec())
+            }
+            9 => {
+                let header = b[*at];
+                *at += 1;
+                let len = match header >> 4 {
+                    15 => varint(b, at),
+                    len => len as u64,
+                };
+                T::List((0..len).map(|_| read(b, at, header & 15)).collect())
+            }
+            12 => {
+                let (mut fields, mut last) = (Vec::new(), 0);
+                loop {
+                    let header = b[*at];
+                    *at += 1;
+                    if header == 0 {
+                        return T::Struct(fields)
+                    }
+                    last = match header >> 4 {
+                        0 => zigzag(varint(b, at)) as i16,
+                        delta => last + delta as i16,
+                    };
+                    fields.push((last, read(b, at, header & 15)));
+                }
+            }
+            kind => panic!("no type {kind}"),
+        }
+    }
+
+    /// The footer's metadata of the Parquet file `file`.
+    fn metadata(file: &[u8]) -> T {
+        assert!(file.starts_with(b"PAR1") && file.ends_with(b"PAR1"));
+        let length = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap()) as usize;
+        let mut at = file.len() - 8 - length;
+        let metadata = read(file, &mut at, 12);
+        assert_eq!(at, file.len() - 8);
+        metadata
+    }
+
+    /// The header and the data of the page of column `column` in row group `group`.
+    fn page<'a>(file: &'a [u8], metadata: &T, group: usize, column: usize) -> (T, &'a [u8]) {
+        let chunk = metadata.get(4).list()[group].get(1).list()[column].get(3);
+        let mut at = chunk.get(9).int() as usize;
+        let header = read(file, &mut at, 12);
+        let size = header.get(3).int() as usize;
+        assert_eq!(at + size - chunk.get(9).int() as usize, chunk.get(7).int() as usize);
+        (header, &file[at..at + size])
+    }
+
+    #[test]
+    fn test_write_table() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute(
+            "CREATE TABLE t (id INT PRIMARY KEY, name TEXT, flag BOOL, day DATE, at TIMESTAMPTZ, \
+             price DECIMAL(10, 2), big DECIMAL(30, 4), span INTERVAL, doc JSON)",
+        )?;
+        db.execute(
+            "INSERT INTO t VALUES \
+             (1, 'one', true, '2024-03-01', '2024-03-01 12:00:00+00', 12.5, -3.25, '1 day', '{\"a\": [1]}'), \
+             (2, NULL, false, NULL, NULL, -0.01, NULL, NULL, NULL), \
+             (3, 'three', true, NULL, NULL, NULL, 1, '-2 hours', 'null')",
+        )?;
+        let mut file = Vec::new();
+        assert_eq!(write_table(&db, "t", &mut file, &ParquetOptions { row_group_rows: 2 })?, 3);
+        let metadata = metadata(&file);
+        assert_eq!(metadata.get(3).int(), 3);
+        assert!(metadata.get(6).text().starts_with("purpledb version "));
+
+        let schema = metadata.get(2).list();
+        assert_eq!((schema[0].get(4).text(), schema[0].get(5).int()), ("schema", 9));
+        // Each column's name, physical type, repetition and converted type, if any.
+        let columns: Vec<_> = schema[1..]
+            .iter()
+            .map(|e| {
+                let converted = matches!(e, T::Struct(fields) if fields.iter().any(|(id, _)| *id == 6));
+                (e.get(4).text(), e.get(1).int(), e.get(3).int(), converted.then(|| e.get(6).int()))
+            })
+            .collect();
+        assert_eq!(columns, vec![
+            ("id", 2, 0, None),
+            ("name", 6, 1, Some(0)),
+            ("flag", 0, 1, None),
+            ("day", 1, 1, Some(6)),
+            ("at", 2, 1, Some(10)),
+            ("price", 2, 1, Some(5)),
+            ("big", 7, 1, Some(5)),
+            ("span", 6, 1, Some(0)),
+            ("doc", 6, 1, Some(19)),
+        ]);
+        assert_eq!((schema[6].get(7).int(), schema[6].get(8).int(), schema[7].get(2).int()), (2, 10, 16));
+        let utc = schema[5].get(10).get(8);
+        assert_eq!((utc.get(1), utc.get(2)), (&T::Bool(true), &T::Struct(vec![(2, T::Struct(vec![]))])));
+
+        let groups = metadata.get(4).list();
+        assert_eq!(groups.iter().map(|g| g.get(3).int()).collect::<Vec<_>>(), vec![2, 1]);
+        let (header, data) = page(&file, &metadata, 0, 0);
+        assert_eq!(header.get(5).get(1).int(), 2);
+        assert_eq!(data, [1i64.to_le_bytes(), 2i64.to_le_bytes()].concat());
+        // Optional columns' pages start with their definition levels: a run of one defined, one not.
+        let (_, data) = page(&file, &metadata, 0, 1);
+        assert_eq!(data, [&[4, 0, 0, 0, 2, 1, 2, 0][..], &3u32.to_le_bytes(), b"one"].concat());
+        let (_, data) = page(&file, &metadata, 0, 2);
+        assert_eq!(data, [2, 0, 0, 0, 4, 1, 0b01]);
+        let (_, data) = page(&file, &metadata, 0, 5);
+        assert_eq!(data, [&[2, 0, 0, 0, 4, 1][..], &1250i64.to_le_bytes(), &(-1i64).to_le_bytes()].concat());
+        let (_, data) = page(&file, &metadata, 1, 6);
+        assert_eq!(data, [&[2, 0, 0, 0, 2, 1][..], &10000i128.to_be_bytes()].concat());
+        let (_, data) = page(&file, &metadata, 1, 7);
+        assert_eq!(data, [&[2, 0, 0, 0, 2, 1][..], &9u32.to_le_bytes(), b"-02:00:00"].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_query() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        db.execute("CREATE TABLE v (x INT)")?;
+        db.execute("INSERT INTO v VALUES (1), (2)")?;
+        let mut file = Vec::new();
+        let sql = "SELECT x * $1 AS doubled, NULL AS nothing FROM v ORDER BY x";
+        assert_eq!(write_query(&db, sql, &[Value::Int(2)], &mut file, &ParquetOptions::default())?, 2);
+        let metadata = metadata(&file);
+        let schema = metadata.get(2).list();
+        let columns: Vec<_> = schema[1..].iter().map(|e| (e.get(4).text(), e.get(1).int(), e.get(3).int())).collect();
+        assert_eq!(columns, vec![("doubled", 2, 1), ("nothing", 6, 1)]);
+        let (_, data) = page(&file, &metadata, 0, 0);
+        assert_eq!(data, [&[2, 0, 0, 0, 4, 1][..], &2i64.to_le_bytes(), &4i64.to_le_bytes()].concat());
+        let (header, data) = page(&file, &metadata, 0, 1);
+        assert_eq!((header.get(5).get(1).int(), data), (2, &[2, 0, 0, 0, 4, 0][..]));
+        assert_eq!(
+            write_table(&db, "nope", Vec::new(), &ParquetOptions::default()),
+            Err(DatabaseError::Catalog(crate::catalog::CatalogError::NoSuchTable("nope".to_string())))
+        );
+        Ok(())
+    }
+}
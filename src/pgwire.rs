@@ -0,0 +1,1193 @@
+//! A server speaking version 3 of the PostgreSQL wire protocol, so that `psql` and Postgres client drivers
+//! can run SQL against a connection over TCP.
+//!
+//! Each client is read by a thread of its own, which answers the SSL and GSSAPI encryption requests a
+//! client may open with by refusing them, reads its startup message, and passes every message after it to
+//! the thread serving the connection; that thread answers them, one message at a time whichever client
+//! sent it, so each statement runs on its own as it would through the connection itself. Clients are not
+//! asked for a password, and the user and database they name are not checked.
+//!
+//! A Simple Query message may hold several statements, which run in turn until one fails. The extended
+//! protocol parses a statement into a prepared statement, named or not, binds values to its parameters for
+//! a portal, and executes the portal, for at most as many rows as the Execute message asks for, sending each
+//! row it asks for; a portal executed for fewer than all of its rows is suspended. The first Execute of a
+//! portal runs its statement to the end, and a query's portal keeps the rows it has not sent yet for the
+//! Executes after it, while executing the portal of a statement returning no rows again only completes it
+//! again. A portal lasts until it is closed or the next Sync. An error skips every message after it until
+//! the next Sync. A query, an `INSERT`, an `UPDATE` or a `DELETE` can have parameters, which are typed as its
+//! plan needs them, or as Parse declares them where the plan takes any value.
+//!
+//! Values are written as text, the way Postgres writes them, or in binary for the types whose binary form
+//! is a plain number or string of bytes, and for decimals, as base 10000 digits. Every statement commits on
+//! its own, so `BEGIN` and `COMMIT` are acknowledged without doing anything, as is `SET`, which drivers send
+//! to configure the session, while `ROLLBACK` fails, there being no transaction to undo.
+use std::collections::HashMap;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::str;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::vec;
+
+use crate::catalog::CatalogError;
+use crate::connection::Connection;
+use crate::database::{DatabaseError, Statement};
+use crate::datetime::{Interval, MICROS_PER_DAY};
+use crate::exec::expr::cast;
+use crate::exec::ExecError;
+use crate::planner::PlanError;
+use crate::sql;
+use crate::table::TableError;
+use crate::tuple::{ColumnType, TupleError, Value};
+
+const PROTOCOL_VERSION: i32 = 3 << 16;
+const SSL_REQUEST: i32 = 80877103;
+const GSS_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+/// The longest message a client may send.
+const MAX_MESSAGE: usize = 1 << 30;
+/// The parameters the server reports on startup.
+const PARAMETERS: [(&str, &str); 7] = [
+    ("server_version", "16.0"),
+    ("server_encoding", "UTF8"),
+    ("client_encoding", "UTF8"),
+    ("DateStyle", "ISO, MDY"),
+    ("TimeZone", "UTC"),
+    ("integer_datetimes", "on"),
+    ("standard_conforming_strings", "on"),
+];
+
+/// Type OIDs, as Postgres numbers its types.
+const BOOL: u32 = 16;
+const BYTEA: u32 = 17;
+const INT8: u32 = 20;
+const INT2: u32 = 21;
+const INT4: u32 = 23;
+const TEXT: u32 = 25;
+const JSON: u32 = 114;
+const FLOAT4: u32 = 700;
+const FLOAT8: u32 = 701;
+const UNKNOWN: u32 = 705;
+const BPCHAR: u32 = 1042;
+const VARCHAR: u32 = 1043;
+const DATE: u32 = 1082;
+const TIME: u32 = 1083;
+const TIMESTAMP: u32 = 1114;
+const TIMESTAMPTZ: u32 = 1184;
+const INTERVAL: u32 = 1186;
+const NUMERIC: u32 = 1700;
+
+/// Days from 1970-01-01, where dates here count from, to 2000-01-01, where they count from in binary.
+const EPOCH_DAYS: i32 = 10_957;
+const EPOCH_MICROS: i64 = EPOCH_DAYS as i64 * MICROS_PER_DAY;
+
+/// Serve the clients `listener` accepts over `connection`, until accepting one fails.
+pub fn serve(connection: &mut Connection, listener: TcpListener) -> io::Result<()> {
+    serve_clients(connection, listener, usize::MAX)
+}
+
+/// Serve the first `limit` clients `listener` accepts, returning once they have all gone.
+fn serve_clients(connection: &mut Connection, listener: TcpListener, limit: usize) -> io::Result<()> {
+    let (events, received) = mpsc::channel();
+    thread::spawn(move || {
+        for (id, stream) in listener.incoming().take(limit).enumerate() {
+            match stream {
+                Ok(stream) => {
+                    let events = events.clone();
+                    thread::spawn(move || read_client(id, stream, events));
+                }
+                Err(e) => {
+                    let _ = events.send(Event::AcceptFailed(e));
+                    return
+                }
+            }
+        }
+    });
+    let mut sessions = HashMap::new();
+    for event in received {
+        match event {
+            Event::Started(id, stream) => {
+                let mut session = Session::new(stream);
+                if session.start().is_ok() {
+                    sessions.insert(id, session);
+                }
+            }
+            Event::Message(id, tag, body) => {
+                let Some(session) = sessions.get_mut(&id) else { continue };
+                if !session.handle(connection, tag, &body) {
+                    sessions.remove(&id);
+                }
+            }
+            Event::Closed(id, error) => {
+                if let (Some(mut session), Some(message)) = (sessions.remove(&id), error) {
+                    let _ = session.send_error("08P01", &message).and_then(|()| session.output.flush());
+                }
+            }
+            Event::AcceptFailed(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// What a client's thread passes on to the thread serving the connection.
+enum Event {
+    /// A client finished its startup, and is answered through this stream.
+    Started(usize, TcpStream),
+    Message(usize, u8, Vec<u8>),
+    /// A client's stream ended, or failed, or the client broke the protocol in the way given.
+    Closed(usize, Option<String>),
+    AcceptFailed(io::Error),
+}
+
+fn read_client(id: usize, stream: TcpStream, events: Sender<Event>) {
+    let error = match read_messages(id, &stream, &events) {
+        Err(SessionError::Protocol(message)) => Some(message),
+        _ => None,
+    };
+    let _ = events.send(Event::Closed(id, error));
+}
+
+fn read_messages(id: usize, mut stream: &TcpStream, events: &Sender<Event>) -> Result<(), SessionError> {
+    let mut input = BufReader::new(stream);
+    match startup(&mut input, stream) {
+        Ok(true) => {}
+        Ok(false) => return Ok(()),
+        Err(SessionError::Protocol(message)) => {
+            let _ = stream.write_all(&frame(b'E', &error_response("08P01", &message)));
+            return Ok(())
+        }
+        Err(e) => return Err(e),
+    }
+    if events.send(Event::Started(id, stream.try_clone()?)).is_err() {
+        return Ok(())
+    }
+    while let Some((tag, body)) = read_message(&mut input)? {
+        if events.send(Event::Message(id, tag, body)).is_err() {
+            break
+        }
+    }
+    Ok(())
+}
+
+/// Read the client's startup message, refusing the encryption it may ask for first, and return whether it
+/// was one; a client asking to cancel a query has nothing more to say.
+fn startup(input: &mut impl Read, mut stream: &TcpStream) -> Result<bool, SessionError> {
+    loop {
+        let mut length = [0; 4];
+        if !read_or_end(input, &mut length)? {
+            return Ok(false)
+        }
+        let length = i32::from_be_bytes(length);
+        if !(8..=10_000).contains(&length) {
+            return Err(SessionError::Protocol(format!("a startup message of {length} bytes")))
+        }
+        let mut body = vec![0; length as usize - 4];
+        input.read_exact(&mut body)?;
+        let mut body = Body::new(&body);
+        match body.i32()? {
+            SSL_REQUEST | GSS_REQUEST => {
+                stream.write_all(b"N")?;
+                stream.flush()?;
+            }
+            CANCEL_REQUEST => return Ok(false),
+            PROTOCOL_VERSION => {
+                // The parameters, such as the user and database, pair up until an empty name.
+                while !body.cstr()?.is_empty() {
+                    body.cstr()?;
+                }
+                return Ok(true)
+            }
+            version => {
+                let message = format!("protocol {}.{} is not supported", version >> 16, version & 0xffff);
+                return Err(SessionError::Protocol(message))
+            }
+        }
+    }
+}
+
+/// The next message's type and body, or `None` if the stream ends before it.
+fn read_message(input: &mut impl Read) -> Result<Option<(u8, Vec<u8>)>, SessionError> {
+    let mut tag = [0];
+    if !read_or_end(input, &mut tag)? {
+        return Ok(None)
+    }
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let length = i32::from_be_bytes(length);
+    if length < 4 || length as usize > MAX_MESSAGE {
+        return Err(SessionError::Protocol(format!("a message of {length} bytes")))
+    }
+    let mut body = vec![0; length as usize - 4];
+    input.read_exact(&mut body)?;
+    Ok(Some((tag[0], body)))
+}
+
+/// Fill `buf`, returning false if the stream ends before the first byte of it.
+fn read_or_end(input: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    loop {
+        match input.read(&mut buf[..1]) {
+            Ok(0) => return Ok(false),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    input.read_exact(&mut buf[1..])?;
+    Ok(true)
+}
+
+#[derive(Debug)]
+enum SessionError {
+    /// The client's stream failed, and the session ends.
+    Io,
+    /// An error the client is told of, by its SQLSTATE code and message, and the session goes on after.
+    Statement(&'static str, String),
+    /// The client broke the protocol, and the session ends.
+    Protocol(String),
+}
+impl From<io::Error> for SessionError {
+    fn from(_: io::Error) -> Self {
+        SessionError::Io
+    }
+}
+impl From<DatabaseError> for SessionError {
+    fn from(e: DatabaseError) -> Self {
+        SessionError::Statement(sqlstate(&e), e.to_string())
+    }
+}
+impl From<sql::ParseError> for SessionError {
+    fn from(e: sql::ParseError) -> Self {
+        DatabaseError::Parse(e).into()
+    }
+}
+
+/// The SQLSTATE code Postgres would fail with for `e`, as near as there is one.
+fn sqlstate(e: &DatabaseError) -> &'static str {
+    match e {
+        DatabaseError::Parse(_) => "42601",
+        DatabaseError::Plan(PlanError::NoSuchTable(_)) | DatabaseError::Catalog(CatalogError::NoSuchTable(_)) => {
+            "42P01"
+        }
+        DatabaseError::Plan(PlanError::NoSuchColumn(_)) | DatabaseError::NoSuchColumn(_) => "42703",
+        DatabaseError::Catalog(CatalogError::DuplicateTable(_)) => "42P07",
+        DatabaseError::Plan(PlanError::Unsupported(_))
+        | DatabaseError::Exec(ExecError::Unsupported(_))
+        | DatabaseError::Unsupported(_)
+        | DatabaseError::NotAQuery => "0A000",
+        DatabaseError::Plan(_) => "42000",
+        DatabaseError::Table(TableError::DuplicateKey | TableError::UniqueViolation { .. }) => "23505",
+        DatabaseError::Table(TableError::Tuple(TupleError::NullNotAllowed { .. })) => "23502",
+        DatabaseError::ForeignKeyViolation { .. } => "23503",
+        DatabaseError::ParameterType { .. } | DatabaseError::Exec(ExecError::TypeMismatch(_)) => "42804",
+        DatabaseError::Exec(ExecError::DivisionByZero) => "22012",
+        DatabaseError::Exec(ExecError::Overflow) => "22003",
+        DatabaseError::Exec(ExecError::InvalidCast(..)) => "22P02",
+        DatabaseError::Exec(ExecError::MemoryLimit(_)) => "53200",
+        DatabaseError::Cancelled => "57014",
+        _ => "XX000",
+    }
+}
+
+/// A message body being read.
+struct Body<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+impl<'a> Body<'a> {
+    fn new(bytes: &'a [u8]) -> Body<'a> {
+        Body { bytes, at: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], SessionError> {
+        let bytes = self.bytes.get(self.at..self.at.saturating_add(n));
+        self.at += n;
+        bytes.ok_or_else(|| SessionError::Protocol("a message shorter than its contents".to_string()))
+    }
+
+    fn u8(&mut self) -> Result<u8, SessionError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, SessionError> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, SessionError> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn cstr(&mut self) -> Result<&'a str, SessionError> {
+        let rest = &self.bytes[self.at.min(self.bytes.len())..];
+        let end = rest.iter().position(|&b| b == 0);
+        let end = end.ok_or_else(|| SessionError::Protocol("a string with no end".to_string()))?;
+        self.at += end + 1;
+        str::from_utf8(&rest[..end]).map_err(|_| SessionError::Protocol("a string that is not UTF-8".to_string()))
+    }
+
+    /// A count, then that many format codes.
+    fn formats(&mut self) -> Result<Vec<Format>, SessionError> {
+        (0..self.i16()?)
+            .map(|_| match self.i16()? {
+                0 => Ok(Format::Text),
+                1 => Ok(Format::Binary),
+                code => Err(SessionError::Protocol(format!("format code {code}"))),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Binary,
+}
+
+/// The format of the `i`th value, given `formats` as a Bind message gives them: none for text throughout,
+/// one for every value, or one for each.
+fn format(formats: &[Format], i: usize) -> Format {
+    match formats {
+        [] => Format::Text,
+        [format] => *format,
+        formats => formats.get(i).copied().unwrap_or(Format::Text),
+    }
+}
+
+/// What a statement is, as far as answering it goes.
+#[derive(Clone)]
+enum Kind {
+    Empty,
+    Select(Box<Statement>),
+    Explain,
+    /// A statement `execute` runs, completing with this tag, and the type of each of its parameters as its
+    /// plan needs them.
+    Command(&'static str, Vec<Option<ColumnType>>),
+    /// A statement acknowledged with this tag and not run.
+    Ignored(&'static str),
+}
+impl Kind {
+    /// The type each parameter must have, as the statement's plan needs it.
+    fn params(&self) -> &[Option<ColumnType>] {
+        match self {
+            Kind::Select(statement) => statement.params(),
+            Kind::Command(_, params) => params,
+            _ => &[],
+        }
+    }
+
+    /// The names and types of the columns the statement outputs, if it outputs rows.
+    fn columns(&self) -> Option<(Vec<String>, Vec<Option<ColumnType>>)> {
+        match self {
+            Kind::Select(statement) => Some((statement.columns().to_vec(), statement.types().to_vec())),
+            Kind::Explain => Some((vec!["plan".to_string()], vec![Some(ColumnType::Text)])),
+            _ => None,
+        }
+    }
+}
+
+/// What the session or transaction statement in `sql` is, if it is one, as the parser knows none of them.
+fn session_command(sql: &str) -> Option<Result<Kind, SessionError>> {
+    let first = sql.split_whitespace().next()?.trim_end_matches(';').to_ascii_uppercase();
+    Some(Ok(match first.as_str() {
+        "SET" => Kind::Ignored("SET"),
+        "BEGIN" | "START" => Kind::Ignored("BEGIN"),
+        "COMMIT" | "END" => Kind::Ignored("COMMIT"),
+        "ROLLBACK" | "ABORT" => {
+            let message = "cannot roll back, as every statement commits on its own".to_string();
+            return Some(Err(SessionError::Statement("0A000", message)))
+        }
+        _ => return None,
+    }))
+}
+
+/// What the statement in `sql` is, preparing it if it is a `SELECT`.
+fn classify(connection: &Connection, sql: &str) -> Result<Kind, SessionError> {
+    if let Some(kind) = session_command(sql) {
+        return kind
+    }
+    if sql.trim().trim_end_matches(';').trim().is_empty() {
+        return Ok(Kind::Empty)
+    }
+    let write = |tag| Ok::<_, SessionError>(Kind::Command(tag, connection.database().write_params(sql)?));
+    Ok(match sql::parse_statement(sql)? {
+        sql::Statement::Select(_) => Kind::Select(Box::new(connection.prepare(sql)?)),
+        sql::Statement::Explain { .. } => Kind::Explain,
+        sql::Statement::Insert(_) => write("INSERT")?,
+        sql::Statement::Update(_) => write("UPDATE")?,
+        sql::Statement::Delete(_) => write("DELETE")?,
+        sql::Statement::CreateTable(_) => Kind::Command("CREATE TABLE", Vec::new()),
+        sql::Statement::CreateIndex(_) => Kind::Command("CREATE INDEX", Vec::new()),
+        sql::Statement::DropTable { .. } => Kind::Command("DROP TABLE", Vec::new()),
+        sql::Statement::CreateView(_) => Kind::Command("CREATE VIEW", Vec::new()),
+        sql::Statement::DropView { .. } => Kind::Command("DROP VIEW", Vec::new()),
+        sql::Statement::CreateSequence(_) => Kind::Command("CREATE SEQUENCE", Vec::new()),
+        sql::Statement::DropSequence { .. } => Kind::Command("DROP SEQUENCE", Vec::new()),
+        sql::Statement::DropIndex { .. } => Kind::Command("DROP INDEX", Vec::new()),
+        sql::Statement::CreateTrigger(_) => Kind::Command("CREATE TRIGGER", Vec::new()),
+        sql::Statement::DropTrigger { .. } => Kind::Command("DROP TRIGGER", Vec::new()),
+        sql::Statement::AlterTable { .. } => Kind::Command("ALTER TABLE", Vec::new()),
+    })
+}
+
+/// A statement a Parse message prepared.
+struct Prepared {
+    sql: String,
+    kind: Kind,
+    /// The type Parse declared each parameter with, or 0 where it declared none.
+    declared: Vec<u32>,
+}
+impl Prepared {
+    /// The type of each parameter, as the client is told of it.
+    fn param_types(&self) -> Vec<u32> {
+        let planned = self.kind.params();
+        (0..planned.len().max(self.declared.len()))
+            .map(|i| match self.declared.get(i) {
+                Some(&oid) if oid != 0 => oid,
+                _ => planned.get(i).copied().flatten().map_or(TEXT, type_oid),
+            })
+            .collect()
+    }
+}
+
+/// A prepared statement with values bound to its parameters, by a Bind message.
+struct Portal {
+    sql: String,
+    kind: Kind,
+    params: Vec<Value>,
+    /// The format of each column of its rows.
+    formats: Vec<Format>,
+    /// What is left of its statement's result once an Execute has run it.
+    result: Option<Outcome>,
+}
+
+/// The result of a portal's statement, as the Executes after the one that ran it see it.
+enum Outcome {
+    /// The rows of a query not sent yet.
+    Rows(vec::IntoIter<Vec<Value>>),
+    /// The command tag a statement returning no rows completed with, or `None` for an empty one.
+    Completed(Option<String>),
+}
+
+struct Session {
+    output: BufWriter<TcpStream>,
+    statements: HashMap<String, Prepared>,
+    portals: HashMap<String, Portal>,
+    /// Whether an error in the extended protocol is skipping messages until the next Sync.
+    skipping: bool,
+}
+impl Session {
+    fn new(stream: TcpStream) -> Session {
+        Session {
+            output: BufWriter::new(stream),
+            statements: HashMap::new(),
+            portals: HashMap::new(),
+            skipping: false,
+        }
+    }
+
+    /// Let the client in, telling it about the server.
+    fn start(&mut self) -> io::Result<()> {
+        self.send(b'R', &0i32.to_be_bytes())?;
+        for (name, value) in PARAMETERS {
+            let mut body = Vec::new();
+            put_cstr(&mut body, name);
+            put_cstr(&mut body, value);
+            self.send(b'S', &body)?;
+        }
+        let mut key = std::process::id().to_be_bytes().to_vec();
+        key.extend_from_slice(&0i32.to_be_bytes());
+        self.send(b'K', &key)?;
+        self.ready()
+    }
+
+    /// Answer the message of type `tag` with `body`, and return whether the session goes on after it.
+    fn handle(&mut self, connection: &mut Connection, tag: u8, body: &[u8]) -> bool {
+        match self.message(connection, tag, body) {
+            Ok(going) => going,
+            Err(SessionError::Statement(code, message)) => {
+                self.skipping = true;
+                self.send_error(code, &message).is_ok()
+            }
+            Err(SessionError::Protocol(message)) => {
+                let _ = self.send_error("08P01", &message).and_then(|()| self.output.flush());
+                false
+            }
+            Err(SessionError::Io) => false,
+        }
+    }
+
+    fn message(&mut self, connection: &mut Connection, tag: u8, body: &[u8]) -> Result<bool, SessionError> {
+        if self.skipping && !matches!(tag, b'S' | b'X') {
+            return Ok(true)
+        }
+        let mut body = Body::new(body);
+        match tag {
+            b'Q' => self.simple_query(connection, body.cstr()?)?,
+            b'P' => self.parse(connection, &mut body)?,
+            b'B' => self.bind(&mut body)?,
+            b'D' => self.describe(&mut body)?,
+            b'E' => self.execute(connection, &mut body)?,
+            b'C' => {
+                let (kind, name) = (body.u8()?, body.cstr()?);
+                match kind {
+                    b'S' => drop(self.statements.remove(name)),
+                    b'P' => drop(self.portals.remove(name)),
+                    kind => return Err(SessionError::Protocol(format!("cannot close a {:?}", kind as char))),
+                }
+                self.send(b'3', &[])?;
+            }
+            b'S' => {
+                self.skipping = false;
+                self.portals.clear();
+                self.ready()?;
+            }
+            b'H' => self.output.flush()?,
+            b'X' => return Ok(false),
+            tag => return Err(SessionError::Protocol(format!("a message of type {:?}", tag as char))),
+        }
+        Ok(true)
+    }
+
+    /// Run the statements in `sql` in turn until one fails, then say the session is ready for more.
+    fn simple_query(&mut self, connection: &mut Connection, sql: &str) -> Result<(), SessionError> {
+        match self.statements_of(connection, sql) {
+            Err(SessionError::Statement(code, message)) => self.send_error(code, &message)?,
+            result => result?,
+        }
+        self.ready()?;
+        Ok(())
+    }
+
+    fn statements_of(&mut self, connection: &mut Connection, sql: &str) -> Result<(), SessionError> {
+        if let Some(kind) = session_command(sql) {
+            return self.command(connection, &kind?, sql, &[])
+        }
+        let statements = sql::split(sql)?;
+        if statements.is_empty() {
+            return Ok(self.send(b'I', &[])?)
+        }
+        for text in statements {
+            match classify(connection, text)? {
+                Kind::Select(mut statement) => {
+                    self.send(b'T', &row_description(statement.columns(), statement.types(), &[]))?;
+                    let mut count = 0;
+                    for row in statement.query(connection.database(), &[])? {
+                        self.data_row(&row?, &[])?;
+                        count += 1;
+                    }
+                    self.complete(&format!("SELECT {count}"))?;
+                }
+                Kind::Explain => {
+                    let rows = connection.query(text, &[])?.rows;
+                    self.send(b'T', &row_description(&["plan".to_string()], &[Some(ColumnType::Text)], &[]))?;
+                    rows.iter().try_for_each(|row| self.data_row(row, &[]))?;
+                    self.complete("EXPLAIN")?;
+                }
+                kind => self.command(connection, &kind, text, &[])?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a statement that returns no rows, with `params` as the values of its parameters, and complete it.
+    fn command(
+        &mut self,
+        connection: &mut Connection,
+        kind: &Kind,
+        sql: &str,
+        params: &[Value],
+    ) -> Result<(), SessionError> {
+        let tag = run_command(connection, kind, sql, params)?;
+        self.completed(tag.as_deref())
+    }
+
+    /// Complete a statement returning no rows with `tag`, or as the empty statement with `None`.
+    fn completed(&mut self, tag: Option<&str>) -> Result<(), SessionError> {
+        match tag {
+            Some(tag) => self.complete(tag),
+            None => Ok(self.send(b'I', &[])?),
+        }
+    }
+
+    fn parse(&mut self, connection: &Connection, body: &mut Body) -> Result<(), SessionError> {
+        let (name, sql) = (body.cstr()?, body.cstr()?);
+        let declared = (0..body.i16()?).map(|_| Ok(body.i32()? as u32)).collect::<Result<Vec<_>, SessionError>>()?;
+        if !name.is_empty() && self.statements.contains_key(name) {
+            return Err(SessionError::Statement("42P05", format!("a statement called {name} is already prepared")))
+        }
+        let kind = classify(connection, sql)?;
+        self.statements.insert(name.to_string(), Prepared { sql: sql.to_string(), kind, declared });
+        Ok(self.send(b'1', &[])?)
+    }
+
+    fn bind(&mut self, body: &mut Body) -> Result<(), SessionError> {
+        let (portal, name) = (body.cstr()?, body.cstr()?);
+        let formats = body.formats()?;
+        let mut raw = Vec::new();
+        for _ in 0..body.i16()? {
+            let length = body.i32()?;
+            raw.push(if length < 0 { None } else { Some(body.bytes(length as usize)?) });
+        }
+        let results = body.formats()?;
+        let prepared = self.statements.get(name).ok_or_else(|| no_such("statement", name))?;
+        let planned = prepared.kind.params();
+        let types = prepared.param_types();
+        let params = raw
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let expected = planned.get(i).copied().flatten();
+                decode(*value, format(&formats, i), types.get(i).copied().unwrap_or(UNKNOWN), expected)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (sql, kind) = (prepared.sql.clone(), prepared.kind.clone());
+        self.portals.insert(portal.to_string(), Portal { sql, kind, params, formats: results, result: None });
+        Ok(self.send(b'2', &[])?)
+    }
+
+    fn describe(&mut self, body: &mut Body) -> Result<(), SessionError> {
+        let (kind, name) = (body.u8()?, body.cstr()?);
+        let (columns, formats) = match kind {
+            b'S' => {
+                let prepared = self.statements.get(name).ok_or_else(|| no_such("statement", name))?;
+                let types = prepared.param_types();
+                let mut description = (types.len() as i16).to_be_bytes().to_vec();
+                types.iter().for_each(|oid| description.extend_from_slice(&oid.to_be_bytes()));
+                let columns = prepared.kind.columns();
+                self.send(b't', &description)?;
+                (columns, Vec::new())
+            }
+            b'P' => {
+                let portal = self.portals.get(name).ok_or_else(|| no_such("portal", name))?;
+                (portal.kind.columns(), portal.formats.clone())
+            }
+            kind => return Err(SessionError::Protocol(format!("cannot describe a {:?}", kind as char))),
+        };
+        match columns {
+            Some((columns, types)) => self.send(b'T', &row_description(&columns, &types, &formats))?,
+            None => self.send(b'n', &[])?,
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, connection: &mut Connection, body: &mut Body) -> Result<(), SessionError> {
+        let (name, limit) = (body.cstr()?, body.i32()?);
+        let mut portal = self.portals.remove(name).ok_or_else(|| no_such("portal", name))?;
+        let limit = if limit > 0 { limit as usize } else { usize::MAX };
+        let executed = self.run_portal(connection, &mut portal, limit);
+        self.portals.insert(name.to_string(), portal);
+        executed
+    }
+
+    /// Send at most `limit` more of the rows of `portal`, running its statement if no Execute has yet.
+    fn run_portal(
+        &mut self,
+        connection: &mut Connection,
+        portal: &mut Portal,
+        limit: usize,
+    ) -> Result<(), SessionError> {
+        let result = match portal.result.take() {
+            Some(result) => result,
+            None => match &mut portal.kind {
+                Kind::Select(statement) => {
+                    let rows = statement.query(connection.database(), &portal.params)?;
+                    Outcome::Rows(rows.collect::<Result<Vec<_>, _>>()?.into_iter())
+                }
+                Kind::Explain => Outcome::Rows(connection.query(&portal.sql, &portal.params)?.rows.into_iter()),
+                kind => Outcome::Completed(run_command(connection, kind, &portal.sql, &portal.params)?),
+            },
+        };
+        let rows = match portal.result.insert(result) {
+            Outcome::Rows(rows) => rows,
+            Outcome::Completed(tag) => return self.completed(tag.as_deref()),
+        };
+        let mut count = 0;
+        for row in rows.by_ref().take(limit) {
+            self.data_row(&row, &portal.formats)?;
+            count += 1;
+        }
+        if rows.len() > 0 {
+            return Ok(self.send(b's', &[])?)
+        }
+        match portal.kind {
+            Kind::Select(_) => self.complete(&format!("SELECT {count}")),
+            _ => self.complete("EXPLAIN"),
+        }
+    }
+
+    fn data_row(&mut self, row: &[Value], formats: &[Format]) -> Result<(), SessionError> {
+        let mut body = (row.len() as i16).to_be_bytes().to_vec();
+        for (i, value) in row.iter().enumerate() {
+            match encode(value, format(formats, i))? {
+                Some(bytes) => {
+                    body.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+                    body.extend_from_slice(&bytes);
+                }
+                None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        Ok(self.send(b'D', &body)?)
+    }
+
+    fn complete(&mut self, tag: &str) -> Result<(), SessionError> {
+        let mut body = Vec::new();
+        put_cstr(&mut body, tag);
+        Ok(self.send(b'C', &body)?)
+    }
+
+    fn ready(&mut self) -> io::Result<()> {
+        self.send(b'Z', b"I")?;
+        self.output.flush()
+    }
+
+    fn send_error(&mut self, code: &str, message: &str) -> io::Result<()> {
+        self.send(b'E', &error_response(code, message))
+    }
+
+    fn send(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        self.output.write_all(&frame(tag, body))
+    }
+}
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Ends the client's thread too, if the client has not gone already.
+        let _ = self.output.flush();
+        let _ = self.output.get_ref().shutdown(Shutdown::Both);
+    }
+}
+
+/// Run a statement that returns no rows, with `params` as the values of its parameters, for the tag to
+/// complete it with, or `None` for the empty statement.
+fn run_command(
+    connection: &mut Connection,
+    kind: &Kind,
+    sql: &str,
+    params: &[Value],
+) -> Result<Option<String>, SessionError> {
+    Ok(Some(match kind {
+        Kind::Empty => return Ok(None),
+        Kind::Command(tag, _) => match (*tag, connection.execute_with(sql, params)?) {
+            ("INSERT", count) => format!("INSERT 0 {count}"),
+            (tag @ ("UPDATE" | "DELETE"), count) => format!("{tag} {count}"),
+            (tag, _) => tag.to_string(),
+        },
+        Kind::Ignored(tag) => tag.to_string(),
+        Kind::Select(_) | Kind::Explain => unreachable!("queries are run for their rows"),
+    }))
+}
+
+fn no_such(what: &str, name: &str) -> SessionError {
+    let code = if what == "portal" { "34000" } else { "26000" };
+    SessionError::Statement(code, format!("there is no {what} called {name:?}"))
+}
+
+fn frame(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![tag];
+    message.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+    message.extend_from_slice(body);
+    message
+}
+
+fn put_cstr(body: &mut Vec<u8>, s: &str) {
+    body.extend_from_slice(s.as_bytes());
+    body.push(0);
+}
+
+fn error_response(code: &str, message: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (field, value) in [(b'S', "ERROR"), (b'V', "ERROR"), (b'C', code), (b'M', message)] {
+        body.push(field);
+        put_cstr(&mut body, value);
+    }
+    body.push(0);
+    body
+}
+
+fn row_description(columns: &[String], types: &[Option<ColumnType>], formats: &[Format]) -> Vec<u8> {
+    let mut body = (columns.len() as i16).to_be_bytes().to_vec();
+    for (i, name) in columns.iter().enumerate() {
+        let oid = types.get(i).copied().flatten().map_or(TEXT, type_oid);
+        let size: i16 = match oid {
+            BOOL => 1,
+            DATE => 4,
+            INT8 | FLOAT8 | TIME | TIMESTAMP | TIMESTAMPTZ => 8,
+            INTERVAL => 16,
+            _ => -1,
+        };
+        put_cstr(&mut body, name);
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&0i16.to_be_bytes());
+        body.extend_from_slice(&oid.to_be_bytes());
+        body.extend_from_slice(&size.to_be_bytes());
+        body.extend_from_slice(&(-1i32).to_be_bytes());
+        body.extend_from_slice(&(format(formats, i) as i16).to_be_bytes());
+    }
+    body
+}
+
+fn type_oid(column_type: ColumnType) -> u32 {
+    match column_type {
+        ColumnType::Int => INT8,
+        ColumnType::Float => FLOAT8,
+        ColumnType::Bool => BOOL,
+        ColumnType::Bytes => BYTEA,
+        ColumnType::Text => TEXT,
+        ColumnType::Date => DATE,
+        ColumnType::Time => TIME,
+        ColumnType::Timestamp => TIMESTAMP,
+        ColumnType::TimestampTz => TIMESTAMPTZ,
+        ColumnType::Interval => INTERVAL,
+        ColumnType::Decimal { .. } => NUMERIC,
+        ColumnType::Json => JSON,
+    }
+}
+
+/// The type of `oid`, if it is one values here can have.
+fn oid_type(oid: u32) -> Option<ColumnType> {
+    Some(match oid {
+        BOOL => ColumnType::Bool,
+        BYTEA => ColumnType::Bytes,
+        INT2 | INT4 | INT8 => ColumnType::Int,
+        FLOAT4 | FLOAT8 => ColumnType::Float,
+        TEXT | BPCHAR | VARCHAR => ColumnType::Text,
+        JSON => ColumnType::Json,
+        DATE => ColumnType::Date,
+        TIME => ColumnType::Time,
+        TIMESTAMP => ColumnType::Timestamp,
+        TIMESTAMPTZ => ColumnType::TimestampTz,
+        INTERVAL => ColumnType::Interval,
+        NUMERIC => ColumnType::NUMERIC,
+        _ => return None,
+    })
+}
+
+/// A parameter's value from the bytes a Bind message gives for it in `format`, where the client was told the
+/// parameter has the type `oid` and the plan needs it to be `expected`, if anything in particular.
+fn decode(raw: Option<&[u8]>, format: Format, oid: u32, expected: Option<ColumnType>) -> Result<Value, SessionError> {
+    let Some(raw) = raw else { return Ok(Value::Null) };
+    let invalid = || SessionError::Statement("22P03", format!("a parameter of type {oid} cannot be {raw:?}"));
+    if format == Format::Text || matches!(oid, TEXT | BPCHAR | VARCHAR | JSON | UNKNOWN) {
+        let text = str::from_utf8(raw).map_err(|_| invalid())?;
+        return match expected.or(oid_type(oid)) {
+            None | Some(ColumnType::Text) => Ok(Value::Text(text.to_string())),
+            Some(ColumnType::Bytes) => match text.strip_prefix("\\x") {
+                Some(hex) if hex.len() % 2 == 0 => (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2).ok_or_else(invalid)?, 16).map_err(|_| invalid()))
+                    .collect::<Result<_, _>>()
+                    .map(Value::Bytes),
+                Some(_) => Err(invalid()),
+                None => Ok(Value::Bytes(raw.to_vec())),
+            },
+            Some(column_type) => Ok(cast(Value::Text(text.to_string()), column_type).map_err(DatabaseError::from)?),
+        }
+    }
+    // Big-endian and signed, whatever its width.
+    let int = |bytes: &[u8]| bytes.iter().fold(-((bytes[0] >> 7) as i64), |n, &b| n << 8 | b as i64);
+    Ok(match (oid, raw.len()) {
+        (BOOL, 1) => Value::Bool(raw[0] != 0),
+        (BYTEA, _) => Value::Bytes(raw.to_vec()),
+        (INT2, 2) | (INT4, 4) | (INT8, 8) => Value::Int(int(raw)),
+        (FLOAT4, 4) => Value::Float(f32::from_be_bytes(raw.try_into().unwrap()) as f64),
+        (FLOAT8, 8) => Value::Float(f64::from_be_bytes(raw.try_into().unwrap())),
+        (DATE, 4) => Value::Date((int(raw) as i32).checked_add(EPOCH_DAYS).ok_or_else(invalid)?),
+        (TIME, 8) => Value::Time(int(raw)),
+        (TIMESTAMP, 8) => Value::Timestamp(int(raw).checked_add(EPOCH_MICROS).ok_or_else(invalid)?),
+        (TIMESTAMPTZ, 8) => Value::TimestampTz(int(raw).checked_add(EPOCH_MICROS).ok_or_else(invalid)?),
+        (INTERVAL, 16) => {
+            let micros = int(&raw[8..12]).checked_mul(MICROS_PER_DAY).and_then(|days| days.checked_add(int(&raw[..8])));
+            Value::Interval(Interval { months: int(&raw[12..]) as i32, micros: micros.ok_or_else(invalid)? })
+        }
+        _ => return Err(invalid()),
+    })
+}
+
+/// `value` in `format`, or `None` for null.
+fn encode(value: &Value, format: Format) -> Result<Option<Vec<u8>>, SessionError> {
+    Ok(Some(match (value, format) {
+        (Value::Null, _) => return Ok(None),
+        (Value::Bool(v), Format::Text) => if *v { "t" } else { "f" }.into(),
+        (Value::Bytes(bytes), Format::Text) => {
+            bytes.iter().fold("\\x".to_string(), |hex, b| hex + &format!("{b:02x}")).into_bytes()
+        }
+        (Value::Float(v), Format::Text) if v.is_nan() => b"NaN".to_vec(),
+        (Value::Float(v), Format::Text) if v.is_infinite() => {
+            if *v > 0.0 { "Infinity" } else { "-Infinity" }.into()
+        }
+        (value, Format::Text) => match cast(value.clone(), ColumnType::Text) {
+            Ok(Value::Text(text)) => text.into_bytes(),
+            _ => format!("{value:?}").into_bytes(),
+        },
+        (Value::Int(v), Format::Binary) => v.to_be_bytes().to_vec(),
+        (Value::Float(v), Format::Binary) => v.to_be_bytes().to_vec(),
+        (Value::Bool(v), Format::Binary) => vec![*v as u8],
+        (Value::Bytes(bytes), Format::Binary) => bytes.clone(),
+        (Value::Text(text), Format::Binary) => text.as_bytes().to_vec(),
+        (Value::Json(json), Format::Binary) => json.to_string().into_bytes(),
+        (Value::Date(days), Format::Binary) => (*days - EPOCH_DAYS).to_be_bytes().to_vec(),
+        (Value::Time(micros), Format::Binary) => micros.to_be_bytes().to_vec(),
+        (Value::Timestamp(micros) | Value::TimestampTz(micros), Format::Binary) => {
+            (*micros - EPOCH_MICROS).to_be_bytes().to_vec()
+        }
+        (Value::Interval(interval), Format::Binary) => {
+            let mut bytes = interval.micros.to_be_bytes().to_vec();
+            bytes.extend_from_slice(&0i32.to_be_bytes());
+            bytes.extend_from_slice(&interval.months.to_be_bytes());
+            bytes
+        }
+        (Value::Decimal(decimal), Format::Binary) => numeric(&decimal.to_string()),
+    }))
+}
+
+/// A decimal written as `text` in the binary form of a numeric: the count of its base 10000 digits, the
+/// power of 10000 the first is a multiple of, its sign and the digits after its point, then the digits,
+/// leading and trailing zeros left out.
+fn numeric(text: &str) -> Vec<u8> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    // Zeros before the whole part and after the fraction, to make each a whole number of digits.
+    let pad = |digits: &str| "0".repeat((4 - digits.len() % 4) % 4);
+    let padded = pad(whole) + whole + fraction + &pad(fraction);
+    let digit = |d: &[u8]| d.iter().fold(0, |n, &b| n * 10 + (b - b'0') as i16);
+    let mut digits: Vec<i16> = padded.as_bytes().chunks(4).map(digit).collect();
+    let mut weight = (whole.len() as i16 + 3) / 4 - 1;
+    let leading = digits.iter().take_while(|&&d| d == 0).count();
+    digits.drain(..leading);
+    weight -= leading as i16;
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    if digits.is_empty() {
+        weight = 0;
+    }
+    let sign: u16 = if negative && !digits.is_empty() { 0x4000 } else { 0 };
+    let mut bytes = (digits.len() as i16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(&weight.to_be_bytes());
+    bytes.extend_from_slice(&sign.to_be_bytes());
+    bytes.extend_from_slice(&(fraction.len() as u16).to_be_bytes());
+    digits.iter().for_each(|d| bytes.extend_from_slice(&d.to_be_bytes()));
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use crate::connection::Connection;
+    use crate::database::DatabaseError;
+    use crate::decimal::Decimal;
+    use crate::tuple::Value;
+
+    use super::{encode, frame, serve_clients, Format};
+
+    /// A client of the test server, reading its replies.
+    struct Client {
+        stream: TcpStream,
+        input: BufReader<TcpStream>,
+    }
+    impl Client {
+        fn connect(address: std::net::SocketAddr) -> Client {
+            let stream = TcpStream::connect(address).unwrap();
+            let input = BufReader::new(stream.try_clone().unwrap());
+            let mut client = Client { stream, input };
+            // Ask for SSL, which is refused, then start up.
+            client.stream.write_all(&[0, 0, 0, 8, 4, 210, 22, 47]).unwrap();
+            let mut refused = [0];
+            client.input.read_exact(&mut refused).unwrap();
+            assert_eq!(&refused, b"N");
+            let mut startup = 196608i32.to_be_bytes().to_vec();
+            startup.extend_from_slice(b"user\0test\0\0");
+            let mut message = (startup.len() as i32 + 4).to_be_bytes().to_vec();
+            message.extend_from_slice(&startup);
+            client.stream.write_all(&message).unwrap();
+            let replies = client.until_ready();
+            assert_eq!(replies.first(), Some(&(b'R', 0i32.to_be_bytes().to_vec())));
+            client
+        }
+
+        fn send(&mut self, tag: u8, body: &[u8]) {
+            self.stream.write_all(&frame(tag, body)).unwrap();
+        }
+
+        fn query(&mut self, sql: &str) -> Vec<String> {
+            self.send(b'Q', format!("{sql}\0").as_bytes());
+            self.until_ready().iter().map(|(tag, body)| describe(*tag, body)).collect()
+        }
+
+        /// The messages up to and including the next ReadyForQuery.
+        fn until_ready(&mut self) -> Vec<(u8, Vec<u8>)> {
+            let mut messages = Vec::new();
+            loop {
+                let mut header = [0; 5];
+                self.input.read_exact(&mut header).unwrap();
+                let length = i32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+                let mut body = vec![0; length - 4];
+                self.input.read_exact(&mut body).unwrap();
+                messages.push((header[0], body));
+                if header[0] == b'Z' {
+                    return messages
+                }
+            }
+        }
+    }
+
+    /// A message, as the tests compare them: data rows as their text values, command tags and error codes.
+    fn describe(tag: u8, body: &[u8]) -> String {
+        match tag {
+            b'D' => {
+                let (mut at, mut values) = (2, Vec::new());
+                for _ in 0..i16::from_be_bytes([body[0], body[1]]) {
+                    let length = i32::from_be_bytes(body[at..at + 4].try_into().unwrap());
+                    at += 4;
+                    if length < 0 {
+                        values.push("NULL".to_string());
+                    } else {
+                        values.push(String::from_utf8_lossy(&body[at..at + length as usize]).into_owned());
+                        at += length as usize;
+                    }
+                }
+                format!("D {}", values.join(" "))
+            }
+            b'C' => format!("C {}", String::from_utf8_lossy(&body[..body.len() - 1])),
+            b'E' => {
+                let code = body.split(|&b| b == 0).find(|field| field.first() == Some(&b'C')).unwrap();
+                format!("E {}", String::from_utf8_lossy(&code[1..]))
+            }
+            tag => (tag as char).to_string(),
+        }
+    }
+
+    fn server() -> (TcpListener, std::net::SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        (listener, address)
+    }
+
+    #[test]
+    fn test_simple_queries() -> Result<(), DatabaseError> {
+        let mut connection = Connection::open_in_memory()?;
+        let (listener, address) = server();
+        let client = thread::spawn(move || {
+            let mut client = Client::connect(address);
+            let created = client.query("SET extra_float_digits = 3");
+            assert_eq!(created, ["C SET", "Z"]);
+            let written = client.query("CREATE TABLE t (a INT, b TEXT); INSERT INTO t VALUES (1, 'x'), (2, NULL);");
+            assert_eq!(written, ["C CREATE TABLE", "C INSERT 0 2", "Z"]);
+            let read = client.query("SELECT a, b, a > 1 FROM t ORDER BY a");
+            assert_eq!(read, ["T", "D 1 x f", "D 2 NULL t", "C SELECT 2", "Z"]);
+            // The statements after one that fails are not run.
+            let failed = client.query("UPDATE t SET b = 'y'; SELECT nope FROM t; DELETE FROM t;");
+            assert_eq!(failed, ["C UPDATE 2", "E 42703", "Z"]);
+            assert_eq!(client.query(""), ["I", "Z"]);
+            // Constraints fail with the codes of their kinds.
+            client.query("CREATE TABLE u (id INT PRIMARY KEY, t_a INT NOT NULL)");
+            assert_eq!(client.query("INSERT INTO u VALUES (1, 1), (1, 2)"), ["E 23505", "Z"]);
+            assert_eq!(client.query("INSERT INTO u VALUES (1, NULL)"), ["E 23502", "Z"]);
+            assert_eq!(client.query("DROP TABLE u"), ["C DROP TABLE", "Z"]);
+            client.send(b'X', &[]);
+
+            // Another client sees what the first committed.
+            let mut other = Client::connect(address);
+            assert_eq!(other.query("SELECT count(*) FROM t WHERE b = 'y'"), ["T", "D 2", "C SELECT 1", "Z"]);
+            assert_eq!(other.query("ROLLBACK"), ["E 0A000", "Z"]);
+        });
+        serve_clients(&mut connection, listener, 2).unwrap();
+        client.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_queries() -> Result<(), DatabaseError> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.execute("CREATE TABLE t (a INT, b TEXT)")?;
+        connection.execute("INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, 'z')")?;
+        let (listener, address) = server();
+        let client = thread::spawn(move || {
+            let mut client = Client::connect(address);
+            client.send(b'P', b"q\0SELECT a, b FROM t WHERE a >= $1 ORDER BY a\0\0\0");
+            client.send(b'D', b"Sq\0");
+            // Bind the text "2", and ask for the first column in binary and the second as text.
+            client.send(b'B', b"\0q\0\0\0\0\x01\0\0\0\x012\0\x02\0\x01\0\0");
+            client.send(b'E', b"\0\0\0\0\x01");
+            client.send(b'E', b"\0\0\0\0\0");
+            client.send(b'S', &[]);
+            let replies = client.until_ready();
+            let tags: Vec<u8> = replies.iter().map(|(tag, _)| *tag).collect();
+            assert_eq!(tags, b"1tT2DsDCZ");
+            // The parameter is typed by the plan, as an 8 byte integer.
+            assert_eq!(replies[1].1, [0, 1, 0, 0, 0, 20]);
+            assert_eq!(replies[4].1, [0, 2, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, b'y']);
+            assert_eq!(describe(replies[7].0, &replies[7].1), "C SELECT 1");
+
+            // An error skips the messages after it until the Sync.
+            client.send(b'P', b"\0SELECT nope FROM t\0\0\0");
+            client.send(b'B', b"\0\0\0\0\0\0\0\0");
+            client.send(b'E', b"\0\0\0\0\0");
+            client.send(b'S', &[]);
+            let replies: Vec<String> = client.until_ready().iter().map(|(t, b)| describe(*t, b)).collect();
+            assert_eq!(replies, ["E 42703", "Z"]);
+
+            client.send(b'P', b"\0INSERT INTO t VALUES (4, 'w')\0\0\0");
+            client.send(b'B', b"\0\0\0\0\0\0\0\0");
+            client.send(b'D', b"P\0");
+            client.send(b'E', b"\0\0\0\0\0");
+            client.send(b'S', &[]);
+            let replies: Vec<String> = client.until_ready().iter().map(|(t, b)| describe(*t, b)).collect();
+            assert_eq!(replies, ["1", "2", "n", "C INSERT 0 1", "Z"]);
+
+            // Writes take parameters too, each typed by the column it is written to or compared with.
+            client.send(b'P', b"w\0INSERT INTO t VALUES ($1, $2)\0\0\0");
+            client.send(b'D', b"Sw\0");
+            client.send(b'B', b"\0w\0\0\0\0\x02\0\0\0\x015\0\0\0\x01v\0\0");
+            client.send(b'E', b"\0\0\0\0\0");
+            client.send(b'P', b"\0UPDATE t SET b = $2 WHERE a = $1\0\0\0");
+            client.send(b'B', b"\0\0\0\0\0\x02\0\0\0\x015\0\0\0\x01u\0\0");
+            client.send(b'E', b"\0\0\0\0\0");
+            client.send(b'S', &[]);
+            let replies = client.until_ready();
+            assert_eq!(replies[1].1, [0, 2, 0, 0, 0, 20, 0, 0, 0, 25]);
+            let replies: Vec<String> = replies.iter().map(|(t, b)| describe(*t, b)).collect();
+            assert_eq!(replies, ["1", "t", "n", "2", "C INSERT 0 1", "1", "2", "C UPDATE 1", "Z"]);
+
+            // A portal keeps the rows its first Execute read, and lasts past completing until the Sync. A
+            // statement returning no rows runs once however often its portal is executed.
+            client.send(b'B', b"p\0q\0\0\0\0\x01\0\0\0\x014\0\0");
+            client.send(b'E', b"p\0\0\0\0\x01");
+            client.send(b'Q', b"INSERT INTO t VALUES (6, 'new')\0");
+            let replies: Vec<String> = client.until_ready().iter().map(|(t, b)| describe(*t, b)).collect();
+            assert_eq!(replies, ["2", "D 4 w", "s", "C INSERT 0 1", "Z"]);
+            client.send(b'E', b"p\0\0\0\0\0");
+            client.send(b'E', b"p\0\0\0\0\0");
+            client.send(b'B', b"\0w\0\0\0\0\x02\0\0\0\x017\0\0\0\x01s\0\0");
+            client.send(b'E', b"\0\0\0\0\0");
+            client.send(b'E', b"\0\0\0\0\0");
+            client.send(b'S', &[]);
+            let replies: Vec<String> = client.until_ready().iter().map(|(t, b)| describe(*t, b)).collect();
+            assert_eq!(replies, ["D 5 u", "C SELECT 1", "C SELECT 0", "2", "C INSERT 0 1", "C INSERT 0 1", "Z"]);
+            client.send(b'E', b"p\0\0\0\0\0");
+            client.send(b'S', &[]);
+            let replies: Vec<String> = client.until_ready().iter().map(|(t, b)| describe(*t, b)).collect();
+            assert_eq!(replies, ["E 34000", "Z"]);
+        });
+        serve_clients(&mut connection, listener, 1).unwrap();
+        client.join().unwrap();
+        let rows = connection.query("SELECT a, b FROM t WHERE a = 5", &[])?.rows;
+        assert_eq!(rows, vec![vec![Value::Int(5), Value::Text("u".to_string())]]);
+        assert_eq!(connection.query("SELECT count(*) FROM t", &[])?.rows, vec![vec![Value::Int(7)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_numeric() {
+        let decimal = |text| encode(&Value::Decimal(Decimal::parse(text).unwrap()), Format::Binary).unwrap().unwrap();
+        let words = |words: &[i16]| words.iter().flat_map(|w| w.to_be_bytes()).collect::<Vec<_>>();
+        assert_eq!(decimal("-12345.678"), words(&[3, 1, 0x4000, 3, 1, 2345, 6780]));
+        assert_eq!(decimal("0.0001"), words(&[1, -1, 0, 4, 1]));
+        assert_eq!(decimal("20000"), words(&[1, 1, 0, 0, 2]));
+        assert_eq!(decimal("0.00"), words(&[0, 0, 0, 2]));
+    }
+}
@@ -0,0 +1,176 @@
+//! The cost model: how many rows each part of a plan is estimated to produce, and what producing them
+//! costs, in units of reading one row sequentially from a heap.
+//!
+//! Row counts come from the statistics `Database::analyze` stores in the catalog. A table that has never
+//! been analyzed is assumed to hold `DEFAULT_ROWS` rows, and a predicate the statistics cannot estimate
+//! gets a fixed selectivity by its shape, as most planners do.
+use std::ops::Bound;
+
+use crate::exec::Expr;
+use crate::sql::ast::{BinaryOp, UnaryOp};
+use crate::table::stats::ColumnStats;
+use crate::table::TableStats;
+use crate::tuple::Value;
+
+/// Rows assumed for a table without statistics.
+pub const DEFAULT_ROWS: f64 = 1000.0;
+/// Selectivity of `=` when the statistics cannot say.
+const DEFAULT_EQ: f64 = 0.005;
+/// Selectivity of `<`, `<=`, `>` or `>=` when the statistics cannot say.
+const DEFAULT_RANGE: f64 = 1.0 / 3.0;
+/// Selectivity of `IS NULL` on a column without statistics.
+const DEFAULT_NULL: f64 = 0.01;
+/// Selectivity of any other predicate.
+const DEFAULT_OTHER: f64 = 0.5;
+/// Rounds a recursive query is assumed to run its recursive term for.
+pub const RECURSIVE_ROUNDS: f64 = 10.0;
+
+/// Reading one row in a sequential scan of a heap.
+pub const SEQ_ROW: f64 = 1.0;
+/// Descending an index to the first entry for a key.
+pub const INDEX_PROBE: f64 = 20.0;
+/// Reading the bucket of a hash index a key hashes to.
+pub const BUCKET_PROBE: f64 = 4.0;
+/// Fetching one row found through an index, from wherever in the heap it is.
+pub const INDEX_ROW: f64 = 4.0;
+/// Evaluating an expression over one row.
+pub const CPU_ROW: f64 = 0.1;
+/// Adding one row to a hash table.
+pub const HASH_BUILD: f64 = 1.0;
+/// Looking one row's key up in a hash table.
+pub const HASH_PROBE: f64 = 0.5;
+/// Writing one row to temporary space and reading it back.
+pub const SPILL_ROW: f64 = 2.0;
+/// Bytes assumed for one value of a row held in memory, for estimating what fits in a memory budget.
+pub const COLUMN_BYTES: f64 = 16.0;
+/// The least a part of a plan must cost for running it on parallel workers to be worth starting them.
+pub const PARALLEL_COST: f64 = 10_000.0;
+
+/// The statistics behind each column a predicate may read, by the column's position.
+pub struct ColumnEstimates<'a> {
+    pub columns: Vec<Option<(&'a ColumnStats, u64)>>,
+}
+impl<'a> ColumnEstimates<'a> {
+    /// Add the columns of a table with `columns` columns and, if it has been analyzed, `stats`.
+    pub fn push_table(&mut self, columns: usize, stats: Option<&'a TableStats>) {
+        match stats {
+            Some(stats) => self.columns.extend(stats.columns.iter().map(|c| Some((c, stats.row_count)))),
+            None => self.columns.extend((0..columns).map(|_| None)),
+        }
+    }
+
+    /// Estimated fraction of rows that `predicate` is true for.
+    pub fn selectivity(&self, predicate: &Expr) -> f64 {
+        let selectivity = match predicate {
+            Expr::Literal(Value::Bool(true)) => 1.0,
+            Expr::Literal(_) => 0.0,
+            Expr::Binary { op: BinaryOp::And, left, right } => self.selectivity(left) * self.selectivity(right),
+            Expr::Binary { op: BinaryOp::Or, left, right } => {
+                let (left, right) = (self.selectivity(left), self.selectivity(right));
+                left + right - left * right
+            }
+            Expr::Unary { op: UnaryOp::Not, expr } => 1.0 - self.selectivity(expr),
+            Expr::IsNull { expr, negated } => {
+                let null = match **expr {
+                    Expr::Column(c) => self.stats(c).map_or(DEFAULT_NULL, |(s, rows)| fraction(s.null_count, rows)),
+                    _ => DEFAULT_NULL,
+                };
+                if *negated { 1.0 - null } else { null }
+            }
+            Expr::Binary { op, left, right } => self.comparison(*op, left, right),
+            _ => DEFAULT_OTHER,
+        };
+        selectivity.clamp(0.0, 1.0)
+    }
+
+    fn comparison(&self, op: BinaryOp, left: &Expr, right: &Expr) -> f64 {
+        match (left, right) {
+            (Expr::Column(a), Expr::Column(b)) if op == BinaryOp::Eq => {
+                // Each value on the side with fewer distinct values is assumed to match one on the other.
+                let distinct = [self.stats(*a), self.stats(*b)].into_iter().flatten().map(|(s, _)| s.distinct).max();
+                distinct.map_or(DEFAULT_EQ, |d| 1.0 / d.max(1) as f64)
+            }
+            (Expr::Column(c), constant) if is_constant(constant) => self.restriction(op, *c, constant),
+            (constant, Expr::Column(c)) if is_constant(constant) => match flip(op) {
+                Some(op) => self.restriction(op, *c, constant),
+                None => DEFAULT_OTHER,
+            },
+            _ => match op {
+                BinaryOp::Eq => DEFAULT_EQ,
+                BinaryOp::NotEq => 1.0 - DEFAULT_EQ,
+                BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => DEFAULT_RANGE,
+                _ => DEFAULT_OTHER,
+            },
+        }
+    }
+
+    /// Selectivity of `column op constant`. A parameter is a constant whose value is not known yet.
+    fn restriction(&self, op: BinaryOp, column: usize, constant: &Expr) -> f64 {
+        let stats = self.stats(column);
+        let value = match constant {
+            Expr::Literal(v) => Some(v),
+            _ => None,
+        };
+        let eq = match (stats, value) {
+            (Some((s, rows)), Some(v)) => s.eq_selectivity(v, rows),
+            (Some((s, rows)), None) => (1.0 - fraction(s.null_count, rows)) / s.distinct.max(1) as f64,
+            (None, _) => DEFAULT_EQ,
+        };
+        let range = |low, high| match (stats, value) {
+            (Some((s, rows)), Some(_)) => s.range_selectivity(low, high, rows),
+            _ => DEFAULT_RANGE,
+        };
+        match (op, value) {
+            (BinaryOp::Eq, _) => eq,
+            (BinaryOp::NotEq, _) => stats.map_or(1.0 - DEFAULT_EQ, |(s, rows)| 1.0 - fraction(s.null_count, rows) - eq),
+            (BinaryOp::Lt, Some(v)) => range(Bound::Unbounded, Bound::Excluded(v)),
+            (BinaryOp::LtEq, Some(v)) => range(Bound::Unbounded, Bound::Included(v)) + eq,
+            (BinaryOp::Gt, Some(v)) => range(Bound::Excluded(v), Bound::Unbounded) - eq,
+            (BinaryOp::GtEq, Some(v)) => range(Bound::Included(v), Bound::Unbounded),
+            (BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq, None) => DEFAULT_RANGE,
+            _ => DEFAULT_OTHER,
+        }
+    }
+
+    fn stats(&self, column: usize) -> Option<(&'a ColumnStats, u64)> {
+        self.columns.get(column).copied().flatten()
+    }
+}
+
+/// Sorting `rows` rows of `width` columns, which are written out and read back if they do not fit in
+/// `memory` bytes.
+pub fn sort(rows: f64, width: usize, memory: f64) -> f64 {
+    let compare = rows * rows.max(2.0).log2() * CPU_ROW;
+    let spill = if rows * width as f64 * COLUMN_BYTES > memory { rows * SPILL_ROW } else { 0.0 };
+    compare + spill
+}
+
+/// Hashing `rows` rows into groups whose keys and aggregates are `width` columns, as if every row were a
+/// group of its own, with the rows written out and read back if the groups do not fit in `memory` bytes.
+pub fn hash_aggregate(rows: f64, width: usize, memory: f64) -> f64 {
+    let spill = if rows * width as f64 * COLUMN_BYTES > memory { rows * SPILL_ROW } else { 0.0 };
+    rows * (HASH_PROBE + HASH_BUILD) + spill
+}
+
+/// Whether `expr` reads no columns, so has the same value for every row.
+pub fn is_constant(expr: &Expr) -> bool {
+    let mut constant = true;
+    expr.for_each_column(&mut |_| constant = false);
+    constant
+}
+
+fn fraction(count: u64, rows: u64) -> f64 {
+    if rows == 0 { 0.0 } else { count as f64 / rows as f64 }
+}
+
+/// The operator that compares its operands the other way round, so that `a op b` is `b flip(op) a`.
+pub(super) fn flip(op: BinaryOp) -> Option<BinaryOp> {
+    Some(match op {
+        BinaryOp::Eq | BinaryOp::NotEq => op,
+        BinaryOp::Lt => BinaryOp::Gt,
+        BinaryOp::LtEq => BinaryOp::GtEq,
+        BinaryOp::Gt => BinaryOp::Lt,
+        BinaryOp::GtEq => BinaryOp::LtEq,
+        _ => return None,
+    })
+}
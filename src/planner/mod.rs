@@ -0,0 +1,2113 @@
+//! Planning: turning a parsed `SELECT` into an executable `Plan`. The planner resolves table names against
+//! the catalog and column names against the tables in `FROM`, then searches for the cheapest way to read
+//! and join those tables by the cost model in `cost`.
+//!
+//! A column with a table qualifier, `u.name`, is looked up in the table with that alias, or that name if
+//! it has no alias. An unqualified name must belong to exactly one table in scope.
+//!
+//! The `WHERE` clause and every `ON` condition are split into their `AND`ed conjuncts, and each conjunct
+//! is applied as low in the plan as the tables it reads allow: at the scan of its one table, or at the
+//! first join that brings its tables together. Each table is read by a sequential scan or by an index
+//! whose leading columns the conjuncts fix with `=`, and whose next column they may bound with `<`, `<=`,
+//! `>`, `>=` or `BETWEEN`, whichever is cheaper; the bounds still filter the rows the index gives. Join
+//! orders are searched by dynamic programming over subsets of the tables, System R style but allowing
+//! bushy trees, and each join is a nested loop or, when a conjunct equates the two sides, a hash join.
+//! Tables no conjunct connects are only joined by a cross product once nothing else is left.
+//!
+//! A `LEFT JOIN` keeps its sides in order, so each side is planned on its own and the left join is one
+//! table to the joins around it. `WHERE` conjuncts reading only its left side still filter that side
+//! before the join, and `ON` conjuncts reading only its right side filter the right side, but the rest
+//! apply at or above the join itself.
+//!
+//! A query with `GROUP BY`, `HAVING` or an aggregate function aggregates the rows the joins produce.
+//! Its select list and `HAVING` may read the grouping expressions and aggregates, but no other columns.
+//! Aggregation either streams over rows grouped by the grouping columns, which are sorted by them unless
+//! an index scan gives them in order for less, or hashes the rows by their keys, whichever costs less.
+//! Grouping by anything but plain columns always hashes.
+//!
+//! `SELECT DISTINCT` is aggregation too, grouping by the whole select list with no aggregates, and
+//! `DISTINCT ON` groups by its expressions and keeps the first row of each in `ORDER BY` order. Rows are
+//! grouped by streaming over them already in order, or after sorting them by `ORDER BY` and then the rest
+//! of the keys, or by hashing them and sorting the distinct rows for `ORDER BY` after, whichever costs
+//! less; `DISTINCT ON` with `ORDER BY` never hashes. `ORDER BY` may only sort by output columns under
+//! `DISTINCT`, and must start with the `ON` expressions under `DISTINCT ON`.
+//!
+//! `ORDER BY` may name an output column by its alias or its position from 1, or give any expression the
+//! select list could. Null sorts before every other value unless `NULLS LAST` says otherwise, so that an
+//! ascending key sorts as an index does; sorting by plain columns that way may also come from an index.
+//!
+//! Text compares and sorts under a collation: the one an operand names with `COLLATE`, or else that of the
+//! first column compared with one other than binary. Under any other, a comparison's operands and an
+//! `ORDER BY` key are bound to their sort keys, which only an index keyed by the same collation answers.
+//! Grouping, `DISTINCT` and window partitions always tell values apart by their bytes.
+//!
+//! `LIMIT` and `OFFSET` take constants or parameters, and apply last. A sort under a limit keeps only the
+//! rows it could output.
+//!
+//! A view named in `FROM` is planned from its query, which may name views of its own, and the plan read
+//! as one relation under the view's column names. Conjuncts over the view filter the rows its plan gives
+//! rather than being pushed into its query. A query in parentheses in `FROM` is planned and read the
+//! same way.
+//!
+//! A `WHERE` conjunct that is `EXISTS`, `NOT EXISTS` or `IN` of a subquery becomes a semi or anti join,
+//! with the subquery read as one relation, when the subquery neither aggregates nor limits its rows and
+//! each of its conjuncts reading the outer query's columns equates an expression over them with one over
+//! its own: those equalities are what the join matches on. Any other subquery in `WHERE` or the select
+//! list runs under an `Apply` for each row, reading the row's columns as outer columns, or just once if it
+//! reads none. Subqueries elsewhere, in an aggregate query's select list, or reading the columns of a
+//! query two levels out are not supported.
+//!
+//! Window functions in the select list and `ORDER BY` are computed after `WHERE`, over the rows of each
+//! partition: the rows are sorted by the partition keys and then the window's `ORDER BY`, and a `Window`
+//! over the sort outputs each row with the function's value after it. Functions over the same partitions
+//! in the same order share one sort. An aggregate without a frame reads the rows up to the row's last peer,
+//! the whole partition if the window has no `ORDER BY`. Window functions over groups, and `RANGE` frames
+//! with offsets, are not supported.
+//!
+//! A common table expression of a `WITH` is planned before the query, reading those before it. One the
+//! rest of the query reads only once is planned in place of that reference, as a query in `FROM` is; the
+//! rows of one read more often are materialized once, by a `With` over the query's plan, for each
+//! reference to scan. A recursive one runs its base term and then its term after `UNION`, planned with its
+//! own name reading the rows of the round before, until a round adds none; a `WITH` not `RECURSIVE` may
+//! not have a `UNION`.
+//!
+//! `plan_write` plans reading the rows of one table that an `UPDATE` or `DELETE` writes, as a `SELECT` of
+//! them would be planned, with the table's scan giving each row's rid after its columns.
+//!
+//! Before the search, rules in `rewrite` rewrite the conjuncts: folding constants, dropping casts that
+//! change nothing, taking the conditions common to every side of an `OR` out of it, propagating
+//! comparisons with constants across equalities of columns, and pushing filters of views and queries in
+//! `FROM` into their plans. A `Planner` made with `debug_rewrites` prints the conjuncts after each rule.
+//!
+//! A `Planner` made `parallel` runs the parts of a plan that are worth it on parallel workers, as
+//! `parallel` describes.
+//!
+//! A `Planner` made with `traced` keeps every alternative it costed, for seeing why it chose what it did.
+use std::fmt;
+
+use crate::catalog::{Catalog, TableDef, TableKind};
+use crate::collation::Collation;
+use crate::exec::{self, Aggregate, AggregateFunction, ApplyKind, Function, NodeStats, Plan, SortKey, WindowFunction};
+use crate::sql::ast::{
+    self, BinaryOp, Distinct, Expr, Frame, FrameBound, FromItem, JoinKind, Select, SelectItem, UnaryOp, With,
+};
+use crate::sql::{self, Statement};
+use crate::storage::Storage;
+use crate::tuple::{ColumnType, Value};
+
+pub mod cost;
+mod parallel;
+mod params;
+mod rewrite;
+mod search;
+
+use search::{Order, Ordered, Relation, Source, Tree};
+
+/// The name of the rid column `plan_write` reads.
+const RID_COLUMN: &str = "";
+
+#[derive(Debug, PartialEq)]
+pub enum PlanError {
+    NoSuchTable(String),
+    NoSuchColumn(String),
+    /// An unqualified column name that more than one table in scope has.
+    AmbiguousColumn(String),
+    /// Two tables in `FROM` go by the same name.
+    DuplicateAlias(String),
+    /// A column an aggregate query reads outside its aggregates without grouping by it.
+    UngroupedColumn(String),
+    /// A call to this function with the wrong number of arguments.
+    Arguments(String),
+    /// A view whose query no longer parses, or outputs another number of columns than the view names.
+    InvalidView(String),
+    /// A subquery in an expression, other than under `EXISTS`, that outputs more than one column.
+    SubqueryColumns,
+    /// `ORDER BY` under `SELECT DISTINCT` sorting by something other than an output column, or under
+    /// `DISTINCT ON` not starting with the `ON` expressions.
+    DistinctOrder,
+    /// A window function outside the select list and `ORDER BY`, or in another's arguments or window.
+    MisplacedWindow,
+    /// A common table expression whose column list, or whose term after `UNION`, gives another number of
+    /// columns than its query outputs.
+    CteColumns(String),
+    /// Valid SQL that the planner cannot plan yet.
+    Unsupported(&'static str),
+}
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::NoSuchTable(name) => write!(f, "no table or view called {name}"),
+            PlanError::NoSuchColumn(name) => write!(f, "no column called {name}"),
+            PlanError::AmbiguousColumn(name) => write!(f, "column {name} is ambiguous"),
+            PlanError::DuplicateAlias(name) => write!(f, "table name {name} is given more than once"),
+            PlanError::UngroupedColumn(name) => {
+                write!(f, "column {name} must be grouped by or used in an aggregate")
+            }
+            PlanError::Arguments(name) => write!(f, "wrong number of arguments to {name}"),
+            PlanError::InvalidView(name) => write!(f, "view {name} no longer fits its definition"),
+            PlanError::SubqueryColumns => write!(f, "subquery must return only one column"),
+            PlanError::DistinctOrder => write!(f, "ORDER BY under DISTINCT must sort by what DISTINCT keeps"),
+            PlanError::MisplacedWindow => {
+                write!(f, "window functions are only allowed in the select list and ORDER BY")
+            }
+            PlanError::CteColumns(name) => write!(f, "query {name} gives another number of columns than it names"),
+            PlanError::Unsupported(what) => write!(f, "{what} is not supported yet"),
+        }
+    }
+}
+
+/// A planned query: the plan, the names of the columns it outputs, and the types of those columns and of
+/// its bind parameters where they could be inferred.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub plan: Plan,
+    pub columns: Vec<String>,
+    pub types: Vec<Option<ColumnType>>,
+    /// The collation of each column, which a query reading it compares its text under.
+    pub collations: Vec<Collation>,
+    pub params: Vec<Option<ColumnType>>,
+    /// The views the query reads, those under other views included.
+    pub views: Vec<String>,
+    /// The estimates of each node of `plan`, in the order `Plan::nodes` lists them.
+    pub estimates: Vec<Estimate>,
+}
+impl Query {
+    /// Estimated rows the plan outputs.
+    pub fn rows(&self) -> f64 {
+        self.estimates[0].rows
+    }
+
+    /// Estimated cost of running the plan.
+    pub fn cost(&self) -> f64 {
+        self.estimates[0].cost
+    }
+
+    /// The plan as lines of text, one for each node under its parent and indented further, with its
+    /// estimates and, given the `actual` stats of each node from running the plan, what it did.
+    pub fn explain(&self, actual: Option<&[NodeStats]>) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut stack = vec![(0, &self.plan)];
+        while let Some((depth, plan)) = stack.pop() {
+            let node = lines.len();
+            let Estimate { rows, cost } = self.estimates[node];
+            let arrow = if depth == 0 { String::new() } else { format!("{}-> ", "   ".repeat(depth - 1)) };
+            let mut line = format!("{arrow}{}  (rows={rows:.0} cost={cost:.2})", plan.label());
+            if let Some(stats) = actual.map(|actual| actual[node]) {
+                let time = stats.time.as_secs_f64() * 1000.0;
+                line += &format!(" (actual rows={} loops={} time={time:.3}ms)", stats.rows, stats.loops);
+            }
+            lines.push(line);
+            stack.extend(plan.inputs().into_iter().rev().map(|input| (depth + 1, input)));
+        }
+        lines
+    }
+}
+
+/// The rows a node of a plan is estimated to output, and the cost of running it with its inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub rows: f64,
+    pub cost: f64,
+}
+
+/// A way of reading some of a query's tables that the planner costed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+    /// The tables it reads, by the names the query gives them.
+    pub tables: Vec<String>,
+    pub plan: Plan,
+    /// Estimated rows it produces.
+    pub rows: f64,
+    pub cost: f64,
+    /// Whether it was the cheapest way found to read `tables`, whether or not the final plan needed them
+    /// read on their own.
+    pub chosen: bool,
+}
+
+/// The conjuncts of a query's `WHERE` and inner joins after one rewrite rule, as `traced` keeps them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    pub rule: &'static str,
+    pub conjuncts: Vec<exec::Expr>,
+}
+
+pub struct Planner<'a, 'store, S: Storage> {
+    catalog: &'a Catalog<'store, S>,
+    trace: Option<Vec<Alternative>>,
+    rewrites: Option<Vec<Rewrite>>,
+    /// Whether to print the conjuncts after each rewrite rule.
+    debug: bool,
+    /// Whether the next `FROM` table is read with its rids, for `plan_write`.
+    rid: bool,
+    /// The common table expressions in scope, innermost last.
+    ctes: Vec<Cte>,
+    /// Ids given to materialized rows so far.
+    ids: usize,
+    /// Workers a `SELECT` may run parts of its plan on at once.
+    workers: usize,
+}
+impl<'a, 'store, S: Storage> Planner<'a, 'store, S> {
+    pub fn new(catalog: &'a Catalog<'store, S>) -> Planner<'a, 'store, S> {
+        let (rewrites, debug) = (None, false);
+        Planner { catalog, trace: None, rewrites, debug, rid: false, ctes: Vec::new(), ids: 0, workers: 1 }
+    }
+
+    /// A planner that records the alternatives it considers, for `alternatives` to return, and the
+    /// conjuncts after each rewrite rule, for `rewrites` to.
+    pub fn traced(catalog: &'a Catalog<'store, S>) -> Planner<'a, 'store, S> {
+        let (trace, rewrites, debug) = (Some(Vec::new()), Some(Vec::new()), false);
+        Planner { catalog, trace, rewrites, debug, rid: false, ctes: Vec::new(), ids: 0, workers: 1 }
+    }
+
+    /// Every alternative costed so far, in the order the planner considered them. Empty unless `traced`.
+    pub fn alternatives(&self) -> &[Alternative] {
+        self.trace.as_deref().unwrap_or(&[])
+    }
+
+    /// The conjuncts of every query planned so far after each rewrite rule, in the order the rules ran.
+    /// Empty unless `traced`.
+    pub fn rewrites(&self) -> &[Rewrite] {
+        self.rewrites.as_deref().unwrap_or(&[])
+    }
+
+    /// Print the conjuncts of each query to standard error after each rewrite rule, for debugging them.
+    pub fn debug_rewrites(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    /// Plan `SELECT`s to run the parts of their plans worth it on up to `workers` workers at once.
+    pub fn parallel(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Plan `select` over the tables in the catalog.
+    pub fn plan_select(&mut self, select: &Select) -> Result<Query, PlanError> {
+        let mut query = self.plan_query(select, None)?;
+        if self.workers > 1 {
+            parallel::parallelize(&mut query, self.workers);
+        }
+        Ok(query)
+    }
+
+    /// Plan reading the rows of the table called `table` that `filter` holds for, for an `UPDATE` or
+    /// `DELETE` to write. Each row out is the row's rid, as bytes, followed by the value of each of `exprs`
+    /// over it. `filter` and `exprs` may have subqueries as a `SELECT` could.
+    pub fn plan_write(&mut self, table: &str, filter: Option<&Expr>, exprs: &[Expr]) -> Result<Query, PlanError> {
+        let rid = Expr::Column { table: Some(table.to_string()), name: RID_COLUMN.to_string() };
+        let items = std::iter::once(rid).chain(exprs.iter().cloned());
+        let items = items.map(|expr| SelectItem::Expr { expr, alias: None });
+        let select = Select {
+            with: None,
+            distinct: None,
+            items: items.collect(),
+            from: Some(FromItem::Table { name: table.to_string(), alias: None }),
+            filter: filter.cloned(),
+            group_by: Vec::new(),
+            having: None,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+        };
+        self.table(table)?;
+        self.rid = true;
+        let query = self.plan_query(&select, None);
+        self.rid = false;
+        query
+    }
+
+    /// Plan `select`, a subquery of the query whose columns are `outer` if it has one.
+    fn plan_query(&mut self, select: &Select, outer: Option<&Scope>) -> Result<Query, PlanError> {
+        let Some(with) = &select.with else { return self.plan_body(select, outer) };
+        let depth = self.ctes.len();
+        let query = self.plan_with(with, select, outer);
+        self.ctes.truncate(depth);
+        query
+    }
+
+    /// Plan `select` after the common table expressions of its `WITH`, which stay in scope after.
+    fn plan_with(&mut self, with: &With, select: &Select, outer: Option<&Scope>) -> Result<Query, PlanError> {
+        let (mut views, mut nested, mut materialized) = (Vec::new(), Vec::new(), Vec::new());
+        for (i, cte) in with.ctes.iter().enumerate() {
+            // It is read by the ones after it, up to one of the same name, and by the query unless that hides it.
+            let later = &with.ctes[i + 1..];
+            let hidden = later.iter().position(|c| c.name == cte.name);
+            let readers = &later[..hidden.map_or(later.len(), |h| h + 1)];
+            let mut reads: usize = readers.iter().map(|c| cte_reads(c, &cte.name)).sum();
+            if hidden.is_none() {
+                reads += body_reads(select, &cte.name);
+            }
+            let query = self.plan_cte(cte, with.recursive)?;
+            views.extend(query.views.iter().cloned());
+            merge(&mut nested, &query.params);
+            let (id, rows) = (self.id(), query.rows());
+            let (columns, types, collations) = (query.columns.clone(), query.types.clone(), query.collations.clone());
+            let inline = match reads {
+                0 | 1 => Some(query),
+                _ => {
+                    materialized.push((id, query));
+                    None
+                }
+            };
+            self.ctes.push(Cte { name: cte.name.clone(), columns, types, collations, id, rows, inline });
+        }
+        let mut query = self.plan_body(select, outer)?;
+        if !materialized.is_empty() {
+            let (rows, mut cost) = (query.rows(), query.cost());
+            let (mut ctes, mut estimates) = (Vec::new(), Vec::new());
+            for (id, cte) in materialized {
+                cost += cte.cost();
+                estimates.extend(cte.estimates);
+                ctes.push((id, cte.plan));
+            }
+            let input = std::mem::take(&mut query.estimates);
+            query.estimates = std::iter::once(Estimate { rows, cost }).chain(input).chain(estimates).collect();
+            query.plan = Plan::With { input: Box::new(query.plan), ctes };
+        }
+        query.views.extend(views);
+        merge(&mut query.params, &nested);
+        Ok(query)
+    }
+
+    /// Plan the query of `cte`, under the names its column list gives. Under `RECURSIVE`, its term after
+    /// `UNION` is planned with `cte` in scope as the rows of the round before.
+    fn plan_cte(&mut self, cte: &ast::Cte, recursive: bool) -> Result<Query, PlanError> {
+        let mut query = self.plan_query(&cte.query, None)?;
+        if !cte.columns.is_empty() {
+            if cte.columns.len() != query.columns.len() {
+                return Err(PlanError::CteColumns(cte.name.clone()))
+            }
+            query.columns = cte.columns.clone();
+        }
+        let Some(union) = &cte.union else { return Ok(query) };
+        if !recursive {
+            return Err(PlanError::Unsupported("UNION outside WITH RECURSIVE"))
+        }
+        let id = self.id();
+        let (columns, types, collations) = (query.columns.clone(), query.types.clone(), query.collations.clone());
+        let rows = query.rows();
+        self.ctes.push(Cte { name: cte.name.clone(), columns, types, collations, id, rows, inline: None });
+        let step = self.plan_query(&union.query, None);
+        self.ctes.pop();
+        let step = step?;
+        if step.columns.len() != query.columns.len() {
+            return Err(PlanError::CteColumns(cte.name.clone()))
+        }
+        let rows = query.rows() + cost::RECURSIVE_ROUNDS * step.rows();
+        let cost = query.cost() + cost::RECURSIVE_ROUNDS * step.cost();
+        let base = std::mem::take(&mut query.estimates);
+        query.estimates = std::iter::once(Estimate { rows, cost }).chain(base).chain(step.estimates).collect();
+        let base = Box::new(std::mem::replace(&mut query.plan, Plan::Values { rows: Vec::new() }));
+        query.plan = Plan::Recursive { id, base, step: Box::new(step.plan), distinct: !union.all };
+        query.views.extend(step.views);
+        merge(&mut query.params, &step.params);
+        Ok(query)
+    }
+
+    /// A new id for materialized rows.
+    fn id(&mut self) -> usize {
+        self.ids += 1;
+        self.ids - 1
+    }
+
+    /// Plan `select` without its `WITH`, whose common table expressions are in scope.
+    fn plan_body(&mut self, select: &Select, outer: Option<&Scope>) -> Result<Query, PlanError> {
+        let mut views = Vec::new();
+        // The types the subqueries give parameters, which the query's own come before.
+        let mut nested = Vec::new();
+        let (mut relations, mut scope) = self.relations(select.from.as_ref(), outer, &mut views, &mut nested)?;
+        let mut trees = Vec::new();
+        let mut predicates = Vec::new();
+        if let Some(from) = &select.from {
+            flatten(from, &scope, &mut 0, &mut trees, &mut predicates)?;
+        }
+        let aggregated = !select.group_by.is_empty()
+            || select.having.is_some()
+            || select.items.iter().any(|item| matches!(item, SelectItem::Expr { expr, .. } if aggregates(expr)));
+
+        // Subqueries get global columns after those of `FROM`: each semi or anti join's relation, then the
+        // value of each subquery an `Apply` runs.
+        let mut next = scope.columns.len();
+        let mut applied = Vec::new();
+        let mut deferred = Vec::new();
+        for conjunct in select.filter.as_ref().map_or(Vec::new(), conjuncts) {
+            let mut found = Vec::new();
+            subqueries(conjunct, &mut found);
+            if found.is_empty() {
+                predicates.push(scope.bind(conjunct)?);
+                continue
+            }
+            let Some((kind, query, keys)) = self.decorrelate(conjunct, &scope)? else {
+                applied.extend(found);
+                deferred.push(conjunct);
+                continue
+            };
+            views.extend(query.views.iter().cloned());
+            merge(&mut nested, &query.params);
+            let on = keys.into_iter().enumerate().map(|(i, key)| {
+                let column = exec::Expr::Column(next + i);
+                exec::Expr::Binary { op: BinaryOp::Eq, left: Box::new(key), right: Box::new(column) }
+            });
+            let on = on.collect();
+            let (name, offset) = ("subquery".to_string(), next);
+            next += query.columns.len();
+            let source = Source::Derived(Box::new(query));
+            relations.push(Relation { name: name.clone(), alias: name, source, offset, rid: false });
+            let right = vec![Tree::Table(relations.len() - 1)];
+            trees = vec![Tree::Join { kind, left: trees, right, on }];
+        }
+        if !aggregated {
+            for item in &select.items {
+                if let SelectItem::Expr { expr, .. } = item {
+                    subqueries(expr, &mut applied);
+                }
+            }
+        }
+        let mut applies = Vec::new();
+        for expr in applied {
+            let (Expr::Subquery(query) | Expr::Exists(query) | Expr::InSubquery { query, .. }) = expr else {
+                unreachable!("not a subquery")
+            };
+            let query = self.plan_query(query, Some(&scope))?;
+            let kind = match expr {
+                Expr::Exists(_) => ApplyKind::Exists,
+                _ if query.columns.len() != 1 => return Err(PlanError::SubqueryColumns),
+                Expr::InSubquery { expr, .. } => ApplyKind::In(scope.bind(expr)?),
+                _ => ApplyKind::Scalar,
+            };
+            views.extend(query.views.iter().cloned());
+            merge(&mut nested, &query.params);
+            scope.subqueries.push((expr, next));
+            applies.push((query, kind, next));
+            next += 1;
+        }
+        let deferred = deferred.into_iter().map(|c| scope.bind(c)).collect::<Result<Vec<_>, _>>()?;
+        // Window functions get global columns after the subqueries, which their arguments may read.
+        let mut found = Vec::new();
+        if !aggregated {
+            let items = select.items.iter().filter_map(|item| match item {
+                SelectItem::Expr { expr, .. } => Some(expr),
+                SelectItem::Wildcard(_) => None,
+            });
+            items.chain(select.order_by.iter().map(|o| &o.expr)).for_each(|e| windows(e, &mut found));
+        }
+        let mut windowings: Vec<Windowing> = Vec::new();
+        for expr in found {
+            let (partition_by, order_by, function) = bind_window(&scope, expr)?;
+            scope.windows.push((expr, next));
+            match windowings.iter_mut().find(|w| w.partition_by == partition_by && w.order_by == order_by) {
+                Some(windowing) => windowing.functions.push((function, next)),
+                None => windowings.push(Windowing { partition_by, order_by, functions: vec![(function, next)] }),
+            }
+            next += 1;
+        }
+
+        let mut grouping = Grouping { keys: Vec::new(), aggregates: Vec::new() };
+        let mut grouped_columns = Vec::new();
+        for expr in &select.group_by {
+            let key = scope.bind(expr)?;
+            if !grouping.keys.contains(&key) {
+                if let exec::Expr::Column(column) = key {
+                    grouped_columns.push(column);
+                }
+                grouping.keys.push(key);
+            }
+        }
+        // Streaming needs rows ordered by the keys, which only plain columns can be.
+        let streamable = grouped_columns.len() == grouping.keys.len();
+
+        let mut exprs = Vec::new();
+        let mut columns = Vec::new();
+        let mut types = Vec::new();
+        let mut collations = Vec::new();
+        // The name `AS` gives each output column, which `ORDER BY` may sort by.
+        let mut aliases = Vec::new();
+        let mut bind = |expr: &Expr| match aggregated {
+            true => grouping.bind(&scope, expr),
+            false => scope.bind(expr),
+        };
+        for item in &select.items {
+            match item {
+                SelectItem::Wildcard(table) => {
+                    if let Some(table) = table {
+                        if !relations.iter().any(|r| r.alias == *table) {
+                            return Err(PlanError::NoSuchTable(table.clone()))
+                        }
+                    }
+                    let described = scope.columns.iter().zip(&scope.types).zip(&scope.collations);
+                    for (((t, name), column_type), collation) in described {
+                        if table.as_ref().is_none_or(|table| table == t) {
+                            exprs.push(bind(&Expr::Column { table: Some(t.clone()), name: name.clone() })?);
+                            columns.push(name.clone());
+                            types.push(*column_type);
+                            collations.push(*collation);
+                            aliases.push(None);
+                        }
+                    }
+                }
+                SelectItem::Expr { expr, alias } => {
+                    exprs.push(bind(expr)?);
+                    types.push(params::type_of(expr, &scope));
+                    collations.push(scope.collation(expr).unwrap_or(Collation::Binary));
+                    aliases.push(alias.as_ref());
+                    columns.push(match (alias, expr) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, Expr::Column { name, .. }) => name.clone(),
+                        (None, Expr::Function { name, .. } | Expr::Window { name, .. }) => name.to_ascii_lowercase(),
+                        (None, _) => "?column?".to_string(),
+                    });
+                }
+            }
+        }
+        let having = select.having.as_ref().map(&mut bind).transpose()?;
+        // What `DISTINCT` groups rows by, over the same rows as the select list: all of it, or what `ON` gives.
+        let firsts = matches!(select.distinct, Some(Distinct::On(_)));
+        let mut distinct = match &select.distinct {
+            None => None,
+            Some(Distinct::Rows) => Some(exprs.clone()),
+            Some(Distinct::On(on)) => {
+                let mut keys = Vec::new();
+                for expr in on {
+                    let key = bind(expr)?;
+                    if !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                }
+                Some(keys)
+            }
+        };
+        let mut keys = Vec::new();
+        for order in &select.order_by {
+            // Text sorts under its collation, so a key with any but binary sorts by its sort key.
+            let (expr, collation) = match &order.expr {
+                Expr::Literal(Value::Int(n)) => {
+                    let position = usize::try_from(*n).ok().and_then(|n| n.checked_sub(1)).filter(|&p| p < exprs.len());
+                    let position = position.ok_or_else(|| PlanError::NoSuchColumn(n.to_string()))?;
+                    (exprs[position].clone(), collations[position])
+                }
+                Expr::Column { table: None, name } if aliases.contains(&Some(name)) => {
+                    let position = aliases.iter().position(|a| *a == Some(name)).unwrap();
+                    (exprs[position].clone(), collations[position])
+                }
+                expr => (bind(expr)?, scope.collation(expr).unwrap_or(Collation::Binary)),
+            };
+            let expr = collate(expr, collation);
+            let nulls_first = order.nulls_first.unwrap_or(!order.descending);
+            keys.push(SortKey { expr, descending: order.descending, nulls_first });
+        }
+        if let Some(on) = &distinct {
+            let leading = &keys[..if firsts { on.len().min(keys.len()) } else { keys.len() }];
+            let sorted_on = match firsts {
+                true => keys.is_empty() || on.iter().all(|e| leading.iter().any(|k| uncollated(&k.expr) == e)),
+                false => leading.iter().all(|k| on.contains(uncollated(&k.expr))),
+            };
+            if !sorted_on {
+                return Err(PlanError::DistinctOrder)
+            }
+        }
+
+        // Sorting by plain columns the way an index orders them may come for free from an index scan.
+        let indexed = keys.iter().map(|k| match k.expr {
+            exec::Expr::Column(c) if !k.descending && k.nulls_first => Some(c),
+            _ => None,
+        });
+        // Window functions sort the rows their own way, after any order the search gives them.
+        let windowed = !windowings.is_empty();
+        let mut sorted: Option<Vec<usize>> = if aggregated || windowed { None } else { indexed.collect() };
+        // `DISTINCT` over rows `ORDER BY` sorts can stream if they are sorted by all its keys, and without
+        // `ORDER BY` over rows grouped by its keys, if they are plain columns.
+        let mut distinct_columns = None;
+        if let Some(on) = distinct.as_ref().filter(|_| !aggregated) {
+            if !on.iter().all(|e| keys.iter().any(|k| k.expr == *e)) {
+                sorted = None;
+            }
+            let plain = on.iter().map(|e| if let exec::Expr::Column(c) = e { Some(*c) } else { None });
+            distinct_columns = plain.collect::<Option<Vec<_>>>().filter(|_| keys.is_empty() && !windowed);
+        }
+        let order = match (&sorted, &distinct_columns) {
+            _ if aggregated && streamable => Order::Grouped(&grouped_columns),
+            _ if aggregated => Order::Any,
+            (Some(columns), _) => Order::Sorted(columns),
+            (None, Some(columns)) => Order::Grouped(columns),
+            (None, None) => Order::Any,
+        };
+        let (debug, rewrites) = (self.debug, &mut self.rewrites);
+        let mut after = |rule, conjuncts: &[exec::Expr]| {
+            if debug {
+                eprintln!("after {rule}: {conjuncts:?}");
+            }
+            if let Some(rewrites) = rewrites {
+                rewrites.push(Rewrite { rule, conjuncts: conjuncts.to_vec() });
+            }
+        };
+        let predicates = rewrite::rewrite(&mut relations, &mut trees, predicates, &scope.types, &mut after);
+        let (any, ordered) = search::search(&relations, &trees, predicates, order, self.trace.as_mut())?;
+        let memory = exec::DEFAULT_MEMORY_BYTES as f64;
+        let width = grouping.keys.len() + grouping.aggregates.len();
+        let hashing = |searched: &search::Searched| {
+            let Estimate { rows, cost } = searched.estimates[0];
+            cost + cost::hash_aggregate(rows, width, memory)
+        };
+        let hashed = !select.group_by.is_empty() && (!streamable || hashing(&any) < ordered.estimates[0].cost);
+        // `DISTINCT` over rows in no useful order either sorts them, by `ORDER BY` first, and streams over
+        // them, or hashes them and then sorts the distinct rows for `ORDER BY`, though that would pick
+        // other rows for `DISTINCT ON`: whether it hashes, and the cost of either.
+        let (ordering, outputs) = (!keys.is_empty(), exprs.len());
+        let distinct_width = distinct.as_ref().map_or(0, |on| on.len() + if firsts { outputs } else { 0 });
+        let dedup = move |rows: f64, width: usize| {
+            let sort = cost::sort(rows, width, memory);
+            let hash = cost::hash_aggregate(rows, distinct_width, memory)
+                + if ordering { cost::sort(rows, outputs, memory) } else { 0.0 };
+            if (!firsts || !ordering) && hash < sort { (true, hash) } else { (false, sort) }
+        };
+        let grouped = distinct.is_some() && !aggregated && (sorted.is_some() || distinct_columns.is_some());
+        let distinct_streamed = grouped && {
+            let Estimate { rows, cost } = any.estimates[0];
+            ordered.estimates[0].cost <= cost + dedup(rows, any.layout.len()).1
+        };
+        let searched = match hashed || distinct.is_some() && !aggregated && !distinct_streamed {
+            true => any,
+            false => ordered,
+        };
+        let (mut plan, mut layout, mut estimates) = (searched.plan, searched.layout, searched.estimates);
+        let Estimate { mut rows, mut cost } = estimates[0];
+        for (query, mut kind, column) in applies {
+            let (mut subquery, subquery_estimates) = (query.plan, query.estimates);
+            subquery.map_outer(&|c| layout.iter().position(|&l| l == c).expect("outer column outside the layout"));
+            if let ApplyKind::In(expr) = &mut kind {
+                search::remap(expr, &layout);
+            }
+            let runs = if subquery.reads_outer() { rows } else { 1.0 };
+            cost += runs * subquery_estimates[0].cost;
+            plan = Plan::Apply { input: Box::new(plan), subquery: Box::new(subquery), kind };
+            estimates.insert(0, Estimate { rows, cost });
+            estimates.extend(subquery_estimates);
+            layout.push(column);
+        }
+        if let Some(mut predicate) = deferred.into_iter().reduce(|left, right| {
+            exec::Expr::Binary { op: BinaryOp::And, left: Box::new(left), right: Box::new(right) }
+        }) {
+            search::remap(&mut predicate, &layout);
+            cost += rows * cost::CPU_ROW;
+            rows = (rows * cost::ColumnEstimates { columns: Vec::new() }.selectivity(&predicate)).max(1.0);
+            plan = Plan::Filter { input: Box::new(plan), predicate };
+            estimates.insert(0, Estimate { rows, cost });
+        }
+        for Windowing { mut partition_by, mut order_by, functions } in windowings {
+            let (mut functions, columns): (Vec<_>, Vec<_>) = functions.into_iter().unzip();
+            let args = functions.iter_mut().filter_map(|f| match f {
+                WindowFunction::Aggregate { aggregate, .. } => aggregate.arg.as_mut(),
+                _ => None,
+            });
+            let sort_exprs = order_by.iter_mut().map(|k| &mut k.expr);
+            partition_by.iter_mut().chain(sort_exprs).chain(args).for_each(|e| search::remap(e, &layout));
+            let ascending = |e: &exec::Expr| SortKey { expr: e.clone(), descending: false, nulls_first: true };
+            let keys: Vec<_> = partition_by.iter().map(ascending).chain(order_by.iter().cloned()).collect();
+            if !keys.is_empty() {
+                cost += cost::sort(rows, layout.len(), memory);
+                plan = Plan::OrderBy { input: Box::new(plan), keys };
+                estimates.insert(0, Estimate { rows, cost });
+            }
+            cost += rows * cost::CPU_ROW;
+            plan = Plan::Window { input: Box::new(plan), partition_by, order_by, functions };
+            estimates.insert(0, Estimate { rows, cost });
+            layout.extend(columns);
+        }
+        // Each operator placed over the plan so far is the first node of the new plan, with the old one its input.
+        let mut over = |rows: f64, cost: f64| estimates.insert(0, Estimate { rows, cost });
+        if aggregated {
+            cost += rows * cost::CPU_ROW;
+            if hashed {
+                cost += cost::hash_aggregate(rows, width, memory);
+            }
+            if select.group_by.is_empty() {
+                rows = 1.0;
+            }
+            let Grouping { keys: mut group_by, mut aggregates } = grouping;
+            let args = aggregates.iter_mut().filter_map(|a| a.arg.as_mut());
+            group_by.iter_mut().chain(args).for_each(|e| search::remap(e, &layout));
+            let input = Box::new(plan);
+            plan = match hashed {
+                true => Plan::HashAggregate { input, group_by, aggregates },
+                false => Plan::StreamAggregate { input, group_by, aggregates },
+            };
+            over(rows, cost);
+            if let Some(predicate) = having {
+                cost += rows * cost::CPU_ROW;
+                plan = Plan::Filter { input: Box::new(plan), predicate };
+                over(rows, cost);
+            }
+        } else {
+            let sort_exprs = keys.iter_mut().map(|k| &mut k.expr);
+            let distinct_exprs = distinct.iter_mut().flatten();
+            exprs.iter_mut().chain(sort_exprs).chain(distinct_exprs).for_each(|e| search::remap(e, &layout));
+        }
+        if let Some(group_by) = distinct {
+            // Each distinct row is a group of its own, as with aggregation; `DISTINCT ON` keeps the output
+            // columns of each group's first row as aggregates after the `ON` keys.
+            let mut hash = false;
+            if !distinct_streamed {
+                let (hashes, extra) = dedup(rows, if aggregated { width } else { layout.len() });
+                hash = hashes;
+                if !hash {
+                    cost += extra;
+                    let mut sort = keys.clone();
+                    for expr in &group_by {
+                        if !sort.iter().any(|k| k.expr == *expr) {
+                            sort.push(SortKey { expr: expr.clone(), descending: false, nulls_first: true });
+                        }
+                    }
+                    plan = Plan::OrderBy { input: Box::new(plan), keys: sort };
+                    over(rows, cost);
+                }
+            }
+            cost += rows * cost::CPU_ROW;
+            if hash {
+                cost += cost::hash_aggregate(rows, distinct_width, memory);
+            }
+            let shift = if firsts { group_by.len() } else { 0 };
+            let first = |e: &exec::Expr| Aggregate { function: AggregateFunction::First, arg: Some(e.clone()) };
+            let aggregates = if firsts { exprs.iter().map(first).collect() } else { Vec::new() };
+            let input = Box::new(plan);
+            plan = match hash {
+                true => Plan::HashAggregate { input, group_by, aggregates },
+                false => Plan::StreamAggregate { input, group_by, aggregates },
+            };
+            over(rows, cost);
+            if hash && !keys.is_empty() {
+                for key in &mut keys {
+                    let position = exprs.iter().position(|e| e == uncollated(&key.expr)).expect("an output column");
+                    let collation = match key.expr {
+                        exec::Expr::Collate { collation, .. } => collation,
+                        _ => Collation::Binary,
+                    };
+                    key.expr = collate(exec::Expr::Column(position), collation);
+                }
+                cost += cost::sort(rows, outputs, memory);
+                plan = Plan::OrderBy { input: Box::new(plan), keys };
+                over(rows, cost);
+            }
+            if firsts {
+                cost += rows * cost::CPU_ROW;
+                let exprs = (shift..shift + outputs).map(exec::Expr::Column).collect();
+                plan = Plan::Project { input: Box::new(plan), exprs };
+                over(rows, cost);
+            }
+        } else {
+            if sorted.is_none() && !keys.is_empty() {
+                cost += cost::sort(rows, layout.len(), memory);
+                plan = Plan::OrderBy { input: Box::new(plan), keys };
+                over(rows, cost);
+            }
+            cost += rows * cost::CPU_ROW;
+            plan = Plan::Project { input: Box::new(plan), exprs };
+            over(rows, cost);
+        }
+        if select.limit.is_some() || select.offset.is_some() {
+            // A limit is counted before any row is read, so it can read no columns.
+            let none = Scope::empty();
+            let limit = select.limit.as_ref().map(|e| none.bind(e)).transpose()?;
+            let offset = select.offset.as_ref().map(|e| none.bind(e)).transpose()?;
+            if let Some(exec::Expr::Literal(Value::Int(n))) = limit {
+                rows = rows.min(n.max(0) as f64);
+            }
+            plan = Plan::Limit { input: Box::new(plan), limit, offset };
+            over(rows, cost);
+        }
+        let mut params = params::infer(select, &scope);
+        merge(&mut params, &nested);
+        Ok(Query { plan, columns, types, collations, params, views, estimates })
+    }
+
+    /// The relations `from` reads and the scope of their columns, within `outer` for a subquery. Views
+    /// and queries in `FROM` are planned, and the views they read and the types they give parameters are
+    /// added to `views` and `params`.
+    fn relations<'s>(
+        &mut self,
+        from: Option<&FromItem>,
+        outer: Option<&'s Scope<'s>>,
+        views: &mut Vec<String>,
+        params: &mut Vec<Option<ColumnType>>,
+    ) -> Result<(Vec<Relation<'a>>, Scope<'s>), PlanError> {
+        let mut items = Vec::new();
+        if let Some(from) = from {
+            from_tables(from, &mut items);
+        }
+        let mut relations: Vec<Relation> = Vec::new();
+        let mut scope = Scope { outer, ..Scope::empty() };
+        let mut rid = std::mem::take(&mut self.rid);
+        for item in items {
+            let (name, alias) = match item {
+                FromItem::Table { name, alias } => (name, alias.as_ref().unwrap_or(name)),
+                FromItem::Derived { alias, .. } => (alias, alias),
+                FromItem::Join { .. } => unreachable!("a join is no table"),
+            };
+            if relations.iter().any(|r| r.alias == *alias) {
+                return Err(PlanError::DuplicateAlias(alias.clone()))
+            }
+            let offset = scope.columns.len();
+            let table = matches!(item, FromItem::Table { .. });
+            let cte = self.ctes.iter().rev().find(|c| c.name == *name).filter(|_| table).map(Cte::read);
+            let view = self.catalog.view(name).filter(|_| table && cte.is_none());
+            let source = match (item, view) {
+                _ if cte.is_some() => {
+                    let query = cte.unwrap();
+                    scope.columns.extend(query.columns.iter().map(|c| (alias.clone(), c.clone())));
+                    scope.types.extend(&query.types);
+                    scope.collations.extend(&query.collations);
+                    views.extend(query.views.iter().cloned());
+                    Source::Derived(Box::new(query))
+                }
+                (FromItem::Derived { query, .. }, _) => {
+                    let query = self.plan_query(query, None)?;
+                    scope.columns.extend(query.columns.iter().map(|c| (alias.clone(), c.clone())));
+                    scope.types.extend(&query.types);
+                    scope.collations.extend(&query.collations);
+                    views.extend(query.views.iter().cloned());
+                    merge(params, &query.params);
+                    Source::Derived(Box::new(query))
+                }
+                (_, Some(view)) => {
+                    let query = self.plan_view(name)?;
+                    scope.columns.extend(view.columns.iter().map(|c| (alias.clone(), c.clone())));
+                    scope.types.extend(&query.types);
+                    scope.collations.extend(&query.collations);
+                    views.push(name.clone());
+                    views.extend(query.views.iter().cloned());
+                    Source::Derived(Box::new(query))
+                }
+                (_, None) => {
+                    let def = self.table(name)?;
+                    scope.columns.extend(def.columns.iter().map(|c| (alias.clone(), c.name.clone())));
+                    scope.types.extend(def.columns.iter().map(|c| Some(c.column.column_type)));
+                    scope.collations.extend(def.columns.iter().map(|c| c.collation));
+                    Source::Table(def)
+                }
+            };
+            // The rid follows the table's columns under a name no SQL identifier has.
+            if rid {
+                scope.columns.push((alias.clone(), RID_COLUMN.to_string()));
+                scope.types.push(Some(ColumnType::Bytes));
+                scope.collations.push(Collation::Binary);
+            }
+            relations.push(Relation { name: name.clone(), alias: alias.clone(), source, offset, rid });
+            rid = false;
+        }
+        Ok((relations, scope))
+    }
+
+    /// The semi or anti join the `WHERE` conjunct `conjunct` comes to, if it is `[NOT] EXISTS` or `IN` of a
+    /// subquery that can be planned apart from the query, from `scope`: the kind of join, the subquery
+    /// outputting its side of each equality it is joined on, and the other side of each, over `scope`.
+    fn decorrelate(
+        &mut self,
+        conjunct: &Expr,
+        scope: &Scope,
+    ) -> Result<Option<(Ordered, Query, Vec<exec::Expr>)>, PlanError> {
+        let (kind, select, test) = match conjunct {
+            Expr::Exists(query) => (Ordered::Semi, query, None),
+            Expr::Unary { op: UnaryOp::Not, expr } => match &**expr {
+                Expr::Exists(query) => (Ordered::Anti, query, None),
+                _ => return Ok(None),
+            },
+            Expr::InSubquery { expr, query, negated: false } => (Ordered::Semi, query, Some(expr)),
+            _ => return Ok(None),
+        };
+        let aggregated = !select.group_by.is_empty()
+            || select.having.is_some()
+            || select.items.iter().any(|item| matches!(item, SelectItem::Expr { expr, .. } if aggregates(expr)));
+        // Which rows hold a value does not depend on `DISTINCT`, but which values `DISTINCT ON` keeps does.
+        let first_rows = matches!(select.distinct, Some(Distinct::On(_)));
+        if aggregated || first_rows || select.limit.is_some() || select.offset.is_some() || select.with.is_some() {
+            return Ok(None)
+        }
+        let (_, inner) = self.relations(select.from.as_ref(), Some(scope), &mut Vec::new(), &mut Vec::new())?;
+        let (mut keys, mut items, mut kept) = (Vec::new(), Vec::new(), Vec::new());
+        if let Some(test) = test {
+            let [SelectItem::Expr { expr: item, .. }] = select.items.as_slice() else { return Ok(None) };
+            match (scope.bind(test), inner.bind(item)) {
+                (Ok(key), Ok(bound)) if !bound.reads_outer() => {
+                    keys.push(key);
+                    items.push(item.clone());
+                }
+                _ => return Ok(None),
+            }
+        }
+        for conjunct in select.filter.as_ref().map_or(Vec::new(), conjuncts) {
+            let Ok(bound) = inner.bind(conjunct) else { return Ok(None) };
+            if !bound.reads_outer() {
+                kept.push(conjunct.clone());
+                continue
+            }
+            let Expr::Binary { op: BinaryOp::Eq, left, right } = conjunct else { return Ok(None) };
+            let (Ok(l), Ok(r)) = (inner.bind(left), inner.bind(right)) else { return Ok(None) };
+            let outer_only = |e: &exec::Expr| e.reads_outer() && cost::is_constant(e);
+            let (item, key) = match (outer_only(&l), outer_only(&r)) {
+                (true, false) if !r.reads_outer() => (right, left),
+                (false, true) if !l.reads_outer() => (left, right),
+                _ => return Ok(None),
+            };
+            keys.push(scope.bind(key)?);
+            items.push((**item).clone());
+        }
+        if items.is_empty() {
+            items.push(Expr::Literal(Value::Int(1)));
+        }
+        let and = |left, right| Expr::Binary { op: BinaryOp::And, left: Box::new(left), right: Box::new(right) };
+        let select = Select {
+            items: items.into_iter().map(|expr| SelectItem::Expr { expr, alias: None }).collect(),
+            filter: kept.into_iter().reduce(and),
+            distinct: None,
+            order_by: Vec::new(),
+            ..(**select).clone()
+        };
+        // A subquery whose `FROM` still reads the outer query's columns runs for each row after all.
+        let Ok(query) = self.plan_query(&select, None) else { return Ok(None) };
+        Ok(Some((kind, query, keys)))
+    }
+
+    /// Plan the query of the view called `name`.
+    fn plan_view(&mut self, name: &str) -> Result<Query, PlanError> {
+        let view = self.catalog.view(name).expect("a view of this name");
+        let invalid = || PlanError::InvalidView(name.to_string());
+        let Ok(Statement::Select(select)) = sql::parse_statement(&view.query) else { return Err(invalid()) };
+        // The view's names mean what they did when it was created, not the common table expressions around it.
+        let ctes = std::mem::take(&mut self.ctes);
+        let query = self.plan_query(&select, None);
+        self.ctes = ctes;
+        let query = query?;
+        if query.columns.len() != view.columns.len() {
+            return Err(invalid())
+        }
+        Ok(query)
+    }
+
+    fn table(&self, name: &str) -> Result<&'a TableDef, PlanError> {
+        let def = self.catalog.table(name).ok_or_else(|| PlanError::NoSuchTable(name.to_string()))?;
+        match def.kind {
+            TableKind::Heap | TableKind::Clustered { .. } | TableKind::Partitioned { .. } => Ok(def),
+            _ => Err(PlanError::Unsupported("tables other than heap, clustered and partitioned tables")),
+        }
+    }
+}
+
+/// Plan `select` over the tables in `catalog`.
+pub fn plan_select<S: Storage>(catalog: &Catalog<S>, select: &Select) -> Result<Query, PlanError> {
+    Planner::new(catalog).plan_select(select)
+}
+
+/// Bind `expr`, which may not read any columns, to be evaluated over an empty row.
+pub fn bind_constant(expr: &Expr) -> Result<exec::Expr, PlanError> {
+    Scope::empty().bind(expr)
+}
+
+/// Bind `expr` to be evaluated over a row of the table `table`, whose columns are `columns`, reading the
+/// columns of the row `outer` names, a table and its columns, as outer columns. A name both have is the
+/// table's unless qualified.
+pub fn bind_row(
+    table: &str,
+    columns: &[String],
+    (outer, outer_columns): (&str, &[String]),
+    expr: &Expr,
+) -> Result<exec::Expr, PlanError> {
+    let scope = |table: &str, columns: &[String]| Scope {
+        columns: columns.iter().map(|c| (table.to_string(), c.clone())).collect(),
+        types: vec![None; columns.len()],
+        collations: vec![Collation::Binary; columns.len()],
+        ..Scope::empty()
+    };
+    let outer = scope(outer, outer_columns);
+    Scope { outer: Some(&outer), ..scope(table, columns) }.bind(expr)
+}
+
+/// Add the types `other` gives parameters to `params`, keeping those `params` already has.
+fn merge(params: &mut Vec<Option<ColumnType>>, other: &[Option<ColumnType>]) {
+    if params.len() < other.len() {
+        params.resize(other.len(), None);
+    }
+    params.iter_mut().zip(other).for_each(|(param, other)| *param = param.or(*other));
+}
+
+/// Collect the tables and queries of a `FROM` clause in order.
+fn from_tables<'s>(item: &'s FromItem, tables: &mut Vec<&'s FromItem>) {
+    match item {
+        FromItem::Table { .. } | FromItem::Derived { .. } => tables.push(item),
+        FromItem::Join { left, right, .. } => {
+            from_tables(left, tables);
+            from_tables(right, tables);
+        }
+    }
+}
+
+/// Collect the leaves of a `FROM` clause that inner and cross joins bring together, and the conjuncts
+/// of their `ON` conditions. `next` is the number of the next table, in the order of `from_tables`.
+///
+/// An inner join's `ON` conjuncts inside the left side of a left join are collected with the left join's
+/// own level, which the left side's rows pass through unchanged; inside its right side they join the left
+/// join's `ON`, which already drops the right rows they would have.
+fn flatten(
+    item: &FromItem,
+    scope: &Scope,
+    next: &mut usize,
+    trees: &mut Vec<Tree>,
+    predicates: &mut Vec<exec::Expr>,
+) -> Result<(), PlanError> {
+    match item {
+        FromItem::Table { .. } | FromItem::Derived { .. } => {
+            trees.push(Tree::Table(*next));
+            *next += 1;
+        }
+        FromItem::Join { left, right, kind: JoinKind::Left, on } => {
+            let (mut l, mut r, mut conditions) = (Vec::new(), Vec::new(), Vec::new());
+            flatten(left, scope, next, &mut l, predicates)?;
+            flatten(right, scope, next, &mut r, &mut conditions)?;
+            if let Some(on) = on {
+                bind_conjuncts(on, scope, &mut conditions)?;
+            }
+            trees.push(Tree::Join { kind: Ordered::Left, left: l, right: r, on: conditions });
+        }
+        FromItem::Join { left, right, on, .. } => {
+            flatten(left, scope, next, trees, predicates)?;
+            flatten(right, scope, next, trees, predicates)?;
+            if let Some(on) = on {
+                bind_conjuncts(on, scope, predicates)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bind the `AND`ed conjuncts `expr` is made of and add them to `out`.
+fn bind_conjuncts(expr: &Expr, scope: &Scope, out: &mut Vec<exec::Expr>) -> Result<(), PlanError> {
+    conjuncts(expr).into_iter().try_for_each(|conjunct| {
+        out.push(scope.bind(conjunct)?);
+        Ok(())
+    })
+}
+
+/// The `AND`ed conjuncts `expr` is made of, left to right.
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Binary { op: BinaryOp::And, left, right } => [conjuncts(left), conjuncts(right)].concat(),
+        expr => vec![expr],
+    }
+}
+
+/// Collect the subqueries in `expr`, not counting those inside them, each after those in the expression
+/// `IN` compares with it.
+fn subqueries<'e>(expr: &'e Expr, out: &mut Vec<&'e Expr>) {
+    match expr {
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) => {}
+        Expr::Subquery(_) | Expr::Exists(_) => out.push(expr),
+        Expr::InSubquery { expr: test, .. } => {
+            subqueries(test, out);
+            out.push(expr);
+        }
+        Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => subqueries(expr, out),
+        Expr::Collate { expr, .. } => subqueries(expr, out),
+        Expr::Binary { left, right, .. } => [left, right].into_iter().for_each(|e| subqueries(e, out)),
+        Expr::Like { expr, pattern, .. } => [expr, pattern].into_iter().for_each(|e| subqueries(e, out)),
+        Expr::InList { expr, list, .. } => {
+            subqueries(expr, out);
+            list.iter().for_each(|e| subqueries(e, out));
+        }
+        Expr::Between { expr, low, high, .. } => [expr, low, high].into_iter().for_each(|e| subqueries(e, out)),
+        Expr::Function { args, .. } => args.iter().for_each(|e| subqueries(e, out)),
+        Expr::Window { args, over, .. } => {
+            let order_by = over.order_by.iter().map(|o| &o.expr);
+            args.iter().chain(&over.partition_by).chain(order_by).for_each(|e| subqueries(e, out))
+        }
+        Expr::Case { operand, branches, otherwise } => {
+            let branches = branches.iter().flat_map(|(when, then)| [when, then]);
+            operand.iter().chain(otherwise).map(|e| &**e).chain(branches).for_each(|e| subqueries(e, out));
+        }
+    }
+}
+
+/// Whether `expr` calls an aggregate function.
+fn aggregates(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) | Expr::Subquery(_) | Expr::Exists(_) => false,
+        Expr::InSubquery { expr, .. } => aggregates(expr),
+        Expr::Function { name, args, .. } => AggregateFunction::named(name).is_some() || args.iter().any(aggregates),
+        // An aggregate over a window leaves the rows as they are, but one read in it aggregates them.
+        Expr::Window { args, over, .. } => {
+            let order_by = over.order_by.iter().map(|o| &o.expr);
+            args.iter().chain(&over.partition_by).chain(order_by).any(aggregates)
+        }
+        Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => aggregates(expr),
+        Expr::Collate { expr, .. } => aggregates(expr),
+        Expr::Binary { left, right, .. } => aggregates(left) || aggregates(right),
+        Expr::Like { expr, pattern, .. } => aggregates(expr) || aggregates(pattern),
+        Expr::InList { expr, list, .. } => aggregates(expr) || list.iter().any(aggregates),
+        Expr::Between { expr, low, high, .. } => aggregates(expr) || aggregates(low) || aggregates(high),
+        Expr::Case { operand, branches, otherwise } => {
+            let branches = branches.iter().flat_map(|(when, then)| [when, then]);
+            operand.iter().chain(otherwise).map(|e| &**e).chain(branches).any(aggregates)
+        }
+    }
+}
+
+/// Collect the window function calls in `expr`, not counting those inside them or inside subqueries.
+fn windows<'e>(expr: &'e Expr, out: &mut Vec<&'e Expr>) {
+    match expr {
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) | Expr::Subquery(_) | Expr::Exists(_) => {}
+        Expr::Window { .. } => out.push(expr),
+        Expr::InSubquery { expr, .. }
+        | Expr::Unary { expr, .. }
+        | Expr::IsNull { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::Collate { expr, .. } => windows(expr, out),
+        Expr::Binary { left, right, .. } => [left, right].into_iter().for_each(|e| windows(e, out)),
+        Expr::Like { expr, pattern, .. } => [expr, pattern].into_iter().for_each(|e| windows(e, out)),
+        Expr::InList { expr, list, .. } => {
+            windows(expr, out);
+            list.iter().for_each(|e| windows(e, out));
+        }
+        Expr::Between { expr, low, high, .. } => [expr, low, high].into_iter().for_each(|e| windows(e, out)),
+        Expr::Function { args, .. } => args.iter().for_each(|e| windows(e, out)),
+        Expr::Case { operand, branches, otherwise } => {
+            let branches = branches.iter().flat_map(|(when, then)| [when, then]);
+            operand.iter().chain(otherwise).map(|e| &**e).chain(branches).for_each(|e| windows(e, out));
+        }
+    }
+}
+
+/// Window functions over the same partitions in the same order, which one `Window` over one sort computes,
+/// each with the global column its value is in.
+struct Windowing {
+    partition_by: Vec<exec::Expr>,
+    order_by: Vec<SortKey>,
+    functions: Vec<(WindowFunction, usize)>,
+}
+
+/// Bind a window function call: the keys of its partitions, the order within them, and what it computes.
+fn bind_window(scope: &Scope, expr: &Expr) -> Result<(Vec<exec::Expr>, Vec<SortKey>, WindowFunction), PlanError> {
+    let Expr::Window { name, args, over } = expr else { unreachable!("not a window function") };
+    let partition_by = over.partition_by.iter().map(|e| scope.bind(e)).collect::<Result<Vec<_>, _>>()?;
+    let mut order_by = Vec::new();
+    for order in &over.order_by {
+        let nulls_first = order.nulls_first.unwrap_or(!order.descending);
+        order_by.push(SortKey { expr: scope.bind(&order.expr)?, descending: order.descending, nulls_first });
+    }
+    let function = match (WindowFunction::named(name), AggregateFunction::named(name)) {
+        (Some(function), _) if args.is_empty() => function,
+        (Some(_), _) => return Err(PlanError::Arguments(name.clone())),
+        (None, Some(function)) => {
+            let arg = match (function, args.as_slice()) {
+                (AggregateFunction::Count, []) => None,
+                (_, [arg]) => Some(scope.bind(arg)?),
+                _ => return Err(PlanError::Arguments(name.clone())),
+            };
+            let (start, end) = (FrameBound::UnboundedPreceding, FrameBound::CurrentRow);
+            let frame = over.frame.unwrap_or(Frame { range: true, start, end });
+            let offset = |b| matches!(b, FrameBound::Preceding(_) | FrameBound::Following(_));
+            if frame.range && (offset(frame.start) || offset(frame.end)) {
+                return Err(PlanError::Unsupported("RANGE frames with offsets"))
+            }
+            WindowFunction::Aggregate { aggregate: Aggregate { function, arg }, frame }
+        }
+        (None, None) => return Err(PlanError::Unsupported("window functions other than ranks and aggregates")),
+    };
+    Ok((partition_by, order_by, function))
+}
+
+/// A common table expression in scope.
+struct Cte {
+    name: String,
+    columns: Vec<String>,
+    types: Vec<Option<ColumnType>>,
+    collations: Vec<Collation>,
+    /// The id its rows are materialized under, and how many it is estimated to have.
+    id: usize,
+    rows: f64,
+    /// Its plan, for its one reference to read in place, unless its rows are materialized.
+    inline: Option<Query>,
+}
+impl Cte {
+    /// What a reference to it reads: its plan, or its materialized rows.
+    fn read(&self) -> Query {
+        if let Some(query) = &self.inline {
+            return query.clone()
+        }
+        Query {
+            plan: Plan::CteScan { name: self.name.clone(), id: self.id },
+            columns: self.columns.clone(),
+            types: self.types.clone(),
+            collations: self.collations.clone(),
+            params: Vec::new(),
+            views: Vec::new(),
+            estimates: vec![Estimate { rows: self.rows, cost: self.rows * cost::CPU_ROW }],
+        }
+    }
+}
+
+/// How many times the queries of `cte` name the table `name`.
+fn cte_reads(cte: &ast::Cte, name: &str) -> usize {
+    reads(&cte.query, name) + cte.union.as_ref().map_or(0, |union| reads(&union.query, name))
+}
+
+/// How many times `select` names the table `name`, in its own `WITH` up to a common table expression of
+/// that name, and if there is none, in the rest of it.
+fn reads(select: &Select, name: &str) -> usize {
+    let mut reads = 0;
+    for cte in select.with.iter().flat_map(|with| &with.ctes) {
+        reads += cte_reads(cte, name);
+        if cte.name == name {
+            return reads
+        }
+    }
+    reads + body_reads(select, name)
+}
+
+/// How many times `select` names the table `name` in its `FROM` and its subqueries, leaving out its `WITH`.
+fn body_reads(select: &Select, name: &str) -> usize {
+    let mut reads = 0;
+    let mut exprs = Vec::new();
+    let mut items = Vec::new();
+    if let Some(from) = &select.from {
+        from_tables(from, &mut items);
+        join_conditions(from, &mut exprs);
+    }
+    for item in items {
+        match item {
+            FromItem::Table { name: table, .. } => reads += (table == name) as usize,
+            FromItem::Derived { query, .. } => reads += self::reads(query, name),
+            FromItem::Join { .. } => unreachable!("a join is no table"),
+        }
+    }
+    let items = select.items.iter().filter_map(|item| match item {
+        SelectItem::Expr { expr, .. } => Some(expr),
+        SelectItem::Wildcard(_) => None,
+    });
+    exprs.extend(items.chain(&select.filter).chain(&select.group_by).chain(&select.having));
+    exprs.extend(select.order_by.iter().map(|o| &o.expr));
+    let mut found = Vec::new();
+    exprs.into_iter().for_each(|e| subqueries(e, &mut found));
+    for expr in found {
+        let (Expr::Subquery(query) | Expr::Exists(query) | Expr::InSubquery { query, .. }) = expr else {
+            unreachable!("not a subquery")
+        };
+        reads += self::reads(query, name);
+    }
+    reads
+}
+
+/// Collect the `ON` conditions of the joins in `item`.
+fn join_conditions<'e>(item: &'e FromItem, out: &mut Vec<&'e Expr>) {
+    if let FromItem::Join { left, right, on, .. } = item {
+        join_conditions(left, out);
+        join_conditions(right, out);
+        out.extend(on);
+    }
+}
+
+/// The columns of every table in `FROM`, in order, each with the name its table goes by. Expressions are
+/// bound to positions in this list, which the search renumbers to positions in the rows of each plan.
+struct Scope<'s> {
+    columns: Vec<(String, String)>,
+    /// The type of each column, unless it is a column of a view whose query gives no telling.
+    types: Vec<Option<ColumnType>>,
+    /// The collation of each column.
+    collations: Vec<Collation>,
+    /// The scope of the query this is a subquery of, whose columns a name may resolve to as outer columns.
+    outer: Option<&'s Scope<'s>>,
+    /// The subqueries an `Apply` runs, each with the global column its value is in.
+    subqueries: Vec<(&'s Expr, usize)>,
+    /// The window function calls a `Window` computes, each with the global column its value is in.
+    windows: Vec<(&'s Expr, usize)>,
+}
+impl Scope<'_> {
+    fn empty() -> Scope<'static> {
+        let (columns, types, collations) = (Vec::new(), Vec::new(), Vec::new());
+        Scope { columns, types, collations, outer: None, subqueries: Vec::new(), windows: Vec::new() }
+    }
+
+    fn resolve(&self, table: Option<&str>, name: &str) -> Result<usize, PlanError> {
+        let mut found = self.columns.iter().enumerate().filter(|(_, (t, n))| n == name && table.is_none_or(|q| q == t));
+        let Some((position, _)) = found.next() else {
+            return Err(PlanError::NoSuchColumn(qualified(table, name)))
+        };
+        match found.next() {
+            Some(_) => Err(PlanError::AmbiguousColumn(name.to_string())),
+            None => Ok(position),
+        }
+    }
+
+    fn bind(&self, expr: &Expr) -> Result<exec::Expr, PlanError> {
+        Ok(match expr {
+            Expr::Literal(v) => exec::Expr::Literal(v.clone()),
+            Expr::Column { table, name } => self.column(table.as_deref(), name)?,
+            Expr::Parameter(n) => exec::Expr::Parameter(*n),
+            Expr::Subquery(_) | Expr::Exists(_) | Expr::InSubquery { .. } => {
+                let Some(&(_, column)) = self.subqueries.iter().find(|(e, _)| std::ptr::eq(*e, expr)) else {
+                    return Err(PlanError::Unsupported("subqueries outside WHERE and the select list"))
+                };
+                match expr {
+                    Expr::InSubquery { negated: true, .. } => {
+                        exec::Expr::Unary { op: UnaryOp::Not, expr: Box::new(exec::Expr::Column(column)) }
+                    }
+                    _ => exec::Expr::Column(column),
+                }
+            }
+            Expr::Window { .. } => {
+                let Some(&(_, column)) = self.windows.iter().find(|(e, _)| std::ptr::eq(*e, expr)) else {
+                    return Err(PlanError::MisplacedWindow)
+                };
+                exec::Expr::Column(column)
+            }
+            expr => match self.comparison_collation(expr) {
+                Some(collation) => compound(expr, &mut |e| Ok(collate(self.bind(e)?, collation)))?,
+                None => compound(expr, &mut |e| self.bind(e))?,
+            },
+        })
+    }
+
+    /// The collation `expr` is given with `COLLATE`, or else that of the column it is, if either.
+    fn collation(&self, expr: &Expr) -> Option<Collation> {
+        match expr {
+            Expr::Collate { collation, .. } => Some(*collation),
+            Expr::Column { table, name } => match self.resolve(table.as_deref(), name) {
+                Ok(column) => self.collations.get(column).copied(),
+                Err(_) => self.outer.and_then(|outer| outer.collation(expr)),
+            },
+            _ => None,
+        }
+    }
+
+    /// The collation a comparison compares text under, unless it is binary: the first one an operand is
+    /// given with `COLLATE`, or else the first one other than binary of a column compared.
+    fn comparison_collation(&self, expr: &Expr) -> Option<Collation> {
+        let operands: Vec<&Expr> = match expr {
+            Expr::Binary { op, left, right } if comparison(*op) => vec![left, right],
+            Expr::InList { expr, list, .. } => std::iter::once(&**expr).chain(list).collect(),
+            Expr::Between { expr, low, high, .. } => vec![expr, low, high],
+            _ => return None,
+        };
+        let explicit = operands.iter().find_map(|e| match e {
+            Expr::Collate { collation, .. } => Some(*collation),
+            _ => None,
+        });
+        let implicit = || operands.iter().filter_map(|e| self.collation(e)).find(|c| *c != Collation::Binary);
+        explicit.or_else(implicit).filter(|c| *c != Collation::Binary)
+    }
+
+    /// The column a name resolves to: one of this scope's, or else an outer column of the enclosing one.
+    fn column(&self, table: Option<&str>, name: &str) -> Result<exec::Expr, PlanError> {
+        let missing = match self.resolve(table, name) {
+            Ok(column) => return Ok(exec::Expr::Column(column)),
+            Err(PlanError::NoSuchColumn(missing)) => missing,
+            Err(e) => return Err(e),
+        };
+        let Some(outer) = self.outer else { return Err(PlanError::NoSuchColumn(missing)) };
+        match outer.column(table, name) {
+            Ok(exec::Expr::Column(column)) => Ok(exec::Expr::Outer(column)),
+            Ok(_) => Err(PlanError::Unsupported("subqueries reading columns two queries out")),
+            Err(PlanError::NoSuchColumn(_)) => Err(PlanError::NoSuchColumn(missing)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Bind an expression made of others, binding those with `bind`. `IN` becomes an `OR` of `=`s and
+/// `BETWEEN` an `AND` of `>=` and `<=`, which give the same nulls.
+fn compound(
+    expr: &Expr,
+    bind: &mut impl FnMut(&Expr) -> Result<exec::Expr, PlanError>,
+) -> Result<exec::Expr, PlanError> {
+    let binary = |op, left, right| exec::Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+    let not = |expr, negated| match negated {
+        true => exec::Expr::Unary { op: UnaryOp::Not, expr: Box::new(expr) },
+        false => expr,
+    };
+    Ok(match expr {
+        // A collation only changes how text compares, which the comparison it is an operand of binds.
+        Expr::Collate { expr, .. } => bind(expr)?,
+        Expr::Unary { op, expr } => exec::Expr::Unary { op: *op, expr: Box::new(bind(expr)?) },
+        Expr::Binary { op, left, right } => binary(*op, bind(left)?, bind(right)?),
+        Expr::IsNull { expr, negated } => exec::Expr::IsNull { expr: Box::new(bind(expr)?), negated: *negated },
+        Expr::Like { expr, pattern, negated } => {
+            exec::Expr::Like { expr: Box::new(bind(expr)?), pattern: Box::new(bind(pattern)?), negated: *negated }
+        }
+        Expr::InList { expr, list, negated } => {
+            let expr = bind(expr)?;
+            let mut equals = Vec::with_capacity(list.len());
+            for item in list {
+                equals.push(binary(BinaryOp::Eq, expr.clone(), bind(item)?));
+            }
+            let any = equals.into_iter().reduce(|a, b| binary(BinaryOp::Or, a, b));
+            not(any.unwrap_or(exec::Expr::Literal(Value::Bool(false))), *negated)
+        }
+        Expr::Between { expr, low, high, negated } => {
+            let expr = bind(expr)?;
+            let low = binary(BinaryOp::GtEq, expr.clone(), bind(low)?);
+            let high = binary(BinaryOp::LtEq, expr, bind(high)?);
+            not(binary(BinaryOp::And, low, high), *negated)
+        }
+        Expr::Case { operand, branches, otherwise } => {
+            let operand = operand.as_ref().map(|e| bind(e).map(Box::new)).transpose()?;
+            let mut bound = Vec::with_capacity(branches.len());
+            for (when, then) in branches {
+                bound.push((bind(when)?, bind(then)?));
+            }
+            let otherwise = otherwise.as_ref().map(|e| bind(e).map(Box::new)).transpose()?;
+            exec::Expr::Case { operand, branches: bound, otherwise }
+        }
+        Expr::Cast { expr, to } => exec::Expr::Cast { expr: Box::new(bind(expr)?), to: *to },
+        Expr::Function { name, args, distinct } => {
+            let function = Function::named(name).filter(|_| !distinct);
+            let function = function.ok_or(PlanError::Unsupported("function calls"))?;
+            if !function.takes(args.len()) {
+                return Err(PlanError::Arguments(name.clone()))
+            }
+            exec::Expr::Call { function, args: args.iter().map(bind).collect::<Result<_, _>>()? }
+        }
+        Expr::Literal(_)
+        | Expr::Column { .. }
+        | Expr::Parameter(_)
+        | Expr::Window { .. }
+        | Expr::Subquery(_)
+        | Expr::Exists(_)
+        | Expr::InSubquery { .. } => unreachable!("bound by the caller"),
+    })
+}
+
+/// What an aggregate query's select list and `HAVING` read: its grouping keys, bound over the rows it
+/// groups, and the aggregates those expressions call so far. Expressions over the groups are bound to
+/// positions in the rows of the aggregation, the keys followed by the aggregates.
+struct Grouping {
+    keys: Vec<exec::Expr>,
+    aggregates: Vec<Aggregate>,
+}
+impl Grouping {
+    fn bind(&mut self, scope: &Scope, expr: &Expr) -> Result<exec::Expr, PlanError> {
+        if let Expr::Function { name, args, distinct } = expr {
+            if let Some(function) = AggregateFunction::named(name) {
+                if *distinct {
+                    return Err(PlanError::Unsupported("DISTINCT aggregates"))
+                }
+                let arg = match (function, args.as_slice()) {
+                    (AggregateFunction::Count, []) => None,
+                    (_, [arg]) => Some(scope.bind(arg)?),
+                    _ => return Err(PlanError::Arguments(name.clone())),
+                };
+                let aggregate = Aggregate { function, arg };
+                let position = match self.aggregates.iter().position(|a| *a == aggregate) {
+                    Some(position) => position,
+                    None => {
+                        self.aggregates.push(aggregate);
+                        self.aggregates.len() - 1
+                    }
+                };
+                return Ok(exec::Expr::Column(self.keys.len() + position))
+            }
+        }
+        if let Ok(bound) = scope.bind(expr) {
+            if let Some(position) = self.keys.iter().position(|k| *k == bound) {
+                return Ok(exec::Expr::Column(position))
+            }
+        }
+        match expr {
+            Expr::Column { table, name } => {
+                scope.resolve(table.as_deref(), name)?;
+                Err(PlanError::UngroupedColumn(qualified(table.as_deref(), name)))
+            }
+            Expr::Literal(_) | Expr::Parameter(_) => scope.bind(expr),
+            Expr::Subquery(_) | Expr::Exists(_) | Expr::InSubquery { .. } => {
+                Err(PlanError::Unsupported("subqueries over groups"))
+            }
+            Expr::Window { .. } => Err(PlanError::Unsupported("window functions over groups")),
+            expr => match scope.comparison_collation(expr) {
+                Some(collation) => compound(expr, &mut |e| Ok(collate(self.bind(scope, e)?, collation))),
+                None => compound(expr, &mut |e| self.bind(scope, e)),
+            },
+        }
+    }
+}
+
+fn comparison(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq)
+}
+
+/// `expr` compared under `collation`: by its sort key, unless the collation is binary.
+fn collate(expr: exec::Expr, collation: Collation) -> exec::Expr {
+    match collation {
+        Collation::Binary => expr,
+        collation => exec::Expr::Collate { expr: Box::new(expr), collation },
+    }
+}
+
+/// `expr` without the sort key `collate` may have put it under.
+fn uncollated(expr: &exec::Expr) -> &exec::Expr {
+    match expr {
+        exec::Expr::Collate { expr, .. } => expr,
+        expr => expr,
+    }
+}
+
+fn qualified(table: Option<&str>, name: &str) -> String {
+    match table {
+        Some(table) => format!("{table}.{name}"),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::allocator::PageAllocator;
+    use crate::catalog::{Catalog, CatalogError, ColumnDef, TableDef, TableKind};
+    use crate::database::{Database, DatabaseError};
+    use crate::exec::{self, ExecError, Expr, Plan};
+    use crate::page_store::{PageId, PageStore};
+    use crate::sql::{parse_expr, parse_statement, BinaryOp, Statement};
+    use crate::storage::TestStorage;
+    use crate::table::TableError;
+    use crate::tuple::{Column, ColumnType, Value};
+
+    use super::{plan_select, PlanError, Planner, Query};
+
+    fn plan(catalog: &Catalog<TestStorage>, sql: &str) -> Result<Query, PlanError> {
+        let Ok(Statement::Select(select)) = parse_statement(sql) else { panic!("not a query: {sql}") };
+        plan_select(catalog, &select)
+    }
+
+    #[test]
+    fn test_names_resolve_to_positions() -> Result<(), CatalogError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut catalog = Catalog::create(&store, allocator)?;
+        let columns = vec![
+            ColumnDef::new("id", Column::new(ColumnType::Int)),
+            ColumnDef::new("name", Column::new(ColumnType::Text)),
+        ];
+        catalog.create_table(TableDef::new("users", columns, TableKind::Heap, PageId::new(7)))?;
+
+        let query = plan(&catalog, "SELECT name AS who, u.*, 1 FROM users u WHERE u.id IS NOT NULL").unwrap();
+        assert_eq!(query.columns, vec!["who", "id", "name", "?column?"]);
+        let scan = Box::new(Plan::SeqScan { table: "users".to_string(), rid: false });
+        let predicate = Expr::IsNull { expr: Box::new(Expr::Column(0)), negated: true };
+        let exprs = vec![Expr::Column(1), Expr::Column(0), Expr::Column(1), Expr::Literal(Value::Int(1))];
+        let filter = Box::new(Plan::Filter { input: scan, predicate });
+        assert_eq!(query.plan, Plan::Project { input: filter, exprs });
+
+        let error = |sql| plan(&catalog, sql).err();
+        assert_eq!(error("SELECT * FROM orders"), Some(PlanError::NoSuchTable("orders".to_string())));
+        assert_eq!(error("SELECT users.id FROM users u"), Some(PlanError::NoSuchColumn("users.id".to_string())));
+        assert_eq!(error("SELECT x.* FROM users u"), Some(PlanError::NoSuchTable("x".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_costs_choose_access_paths_and_joins() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::new(ColumnType::Int));
+        let name = ColumnDef::new("name", Column::new(ColumnType::Text));
+        let mut users = db.create_table("users", vec![int("id"), name])?;
+        for i in 0..1000 {
+            users.insert(&[Value::Int(i), Value::Text(format!("user {i}"))])?;
+        }
+        let mut orders = db.create_table("orders", vec![int("id"), int("user_id"), int("status")])?;
+        for i in 0..5000 {
+            orders.insert(&[Value::Int(i), Value::Int(i % 1000), Value::Int(i % 2)])?;
+        }
+        db.create_index("users", "by_id", vec![0], vec![])?;
+        db.create_index("orders", "by_status", vec![2], vec![])?;
+        db.analyze("users")?;
+        db.analyze("orders")?;
+
+        let describe = |sql| {
+            let Ok(Statement::Select(select)) = parse_statement(sql) else { panic!("not a query: {sql}") };
+            let mut planner = Planner::traced(db.catalog());
+            let plan = planner.plan_select(&select).unwrap().plan.describe();
+            (plan, planner.alternatives().to_vec())
+        };
+        let (plan, alternatives) = describe("SELECT * FROM orders o JOIN users u ON o.user_id = u.id WHERE u.id = 7");
+        // Orders are filtered by the user id the join implies they have.
+        assert_eq!(plan, "Project(NestedLoopJoin(Filter(SeqScan(orders)), IndexScan(users.by_id)))");
+        let users_alone: Vec<_> = alternatives.iter().filter(|a| a.tables == ["u"]).collect();
+        assert_eq!(users_alone.len(), 2);
+        assert!(users_alone[1].chosen && users_alone[1].cost < users_alone[0].cost);
+        assert!(alternatives.iter().filter(|a| a.tables.len() == 2).count() >= 4);
+
+        // Orders is bigger, so the hash table is built from users.
+        let (plan, _) = describe("SELECT u.name FROM users u, orders o WHERE o.user_id = u.id AND o.status = 1");
+        assert_eq!(plan, "Project(HashJoin(Filter(SeqScan(orders)), SeqScan(users)))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges_scan_indexes() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::nullable(ColumnType::Int));
+        let mut orders = db.create_table("orders", vec![int("id"), int("user_id"), int("total")])?;
+        for i in 0..5000 {
+            let total = if i % 10 == 0 { Value::Null } else { Value::Int(i % 1000) };
+            orders.insert(&[Value::Int(i), Value::Int(i % 100), total])?;
+        }
+        db.create_index("orders", "by_id", vec![0], vec![])?;
+        db.create_index("orders", "by_user_total", vec![1, 2], vec![])?;
+        db.analyze("orders")?;
+
+        let describe = |sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        let ids = |sql, params: &[Value]| -> Result<Vec<i64>, DatabaseError> {
+            let rows = db.query(sql, params)?.rows;
+            Ok(rows.iter().map(|row| if let Value::Int(id) = row[0] { id } else { panic!("{row:?}") }).collect())
+        };
+        let sql = "SELECT id FROM orders WHERE id BETWEEN 10 AND 14";
+        assert_eq!(describe(sql), "Project(Filter(IndexScan(orders.by_id)))");
+        assert_eq!(ids(sql, &[])?, vec![10, 11, 12, 13, 14]);
+        assert_eq!(ids("SELECT id FROM orders WHERE 4990 < id", &[])?, (4991..5000).collect::<Vec<_>>());
+        // A bound the column's type cannot hold is left open, and the filter over the scan applies it.
+        assert_eq!(ids("SELECT id FROM orders WHERE id > 10.5 AND id <= 12.5", &[])?, vec![11, 12]);
+        assert_eq!(ids("SELECT id FROM orders WHERE id < ? AND id >= 2", &[Value::Int(5)])?, vec![2, 3, 4]);
+        assert_eq!(ids("SELECT id FROM orders WHERE id < ?", &[Value::Null])?, Vec::<i64>::new());
+        // Most of the table is cheaper to read in order than by fetching each row.
+        assert_eq!(describe("SELECT id FROM orders WHERE id > 100"), "Project(Filter(SeqScan(orders)))");
+
+        // The range follows the leading column fixed with `=`, and takes no nulls.
+        let sql = "SELECT id FROM orders WHERE user_id = 7 AND total < 300";
+        assert_eq!(describe(sql), "Project(Filter(IndexScan(orders.by_user_total)))");
+        let in_index_order = (0..3).flat_map(|total| (0..5).map(move |thousands| thousands * 1000 + total * 100 + 7));
+        assert_eq!(ids(sql, &[])?, in_index_order.collect::<Vec<_>>());
+        let query = plan(db.catalog(), sql).unwrap();
+        let Plan::Project { input, .. } = &query.plan else { panic!("{:?}", query.plan) };
+        let Plan::Filter { input, .. } = &**input else { panic!("{input:?}") };
+        let Plan::IndexScan { key, low, high, .. } = &**input else { panic!("{input:?}") };
+        assert_eq!((key, low), (&vec![Expr::Literal(Value::Int(7))], &Bound::Unbounded));
+        assert_eq!(high, &Bound::Excluded(Expr::Literal(Value::Int(300))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_indexes_serve_equality() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::nullable(ColumnType::Int));
+        let mut orders = db.create_table("orders", vec![int("id"), int("user_id"), int("total")])?;
+        for i in 0..5000 {
+            orders.insert(&[Value::Int(i), Value::Int(i % 100), Value::Int(i % 1000)])?;
+        }
+        db.execute("CREATE UNIQUE INDEX by_id ON orders USING HASH (id)")?;
+        db.execute("CREATE INDEX by_user_total ON orders USING hash (user_id, total)")?;
+        db.analyze("orders")?;
+        let duplicate = db.execute("INSERT INTO orders VALUES (42, 0, 0)");
+        assert!(matches!(duplicate, Err(DatabaseError::Table(TableError::UniqueViolation { .. }))));
+        db.execute("UPDATE orders SET total = 1 WHERE id = 1307")?;
+
+        let describe = |sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        let ids = |sql| -> Result<Vec<i64>, DatabaseError> {
+            let rows = db.query(sql, &[])?.rows;
+            Ok(rows.iter().map(|row| if let Value::Int(id) = row[0] { id } else { panic!("{row:?}") }).collect())
+        };
+        let sql = "SELECT id FROM orders WHERE id = 42";
+        assert_eq!(describe(sql), "Project(IndexScan(orders.by_id))");
+        assert_eq!(ids(sql)?, vec![42]);
+        let sql = "SELECT id FROM orders WHERE total = 307 AND user_id = 7";
+        assert_eq!(describe(sql), "Project(IndexScan(orders.by_user_total))");
+        // The rid 1307 left behind is filled by the last one added.
+        assert_eq!(ids(sql)?, vec![307, 4307, 2307, 3307]);
+        // Neither a range nor part of the key can be looked up in a hash index.
+        assert_eq!(describe("SELECT id FROM orders WHERE id BETWEEN 10 AND 12"), "Project(Filter(SeqScan(orders)))");
+        assert_eq!(describe("SELECT id FROM orders WHERE user_id = 7"), "Project(Filter(SeqScan(orders)))");
+        assert_eq!(describe("SELECT id FROM orders ORDER BY id LIMIT 1"), "Limit(Project(OrderBy(SeqScan(orders))))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregates_stream_over_index_order() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::nullable(ColumnType::Int));
+        let mut orders = db.create_table("orders", vec![int("id"), int("status"), int("amount")])?;
+        for i in 0..100 {
+            let amount = if i % 10 == 0 { Value::Null } else { Value::Int(i) };
+            orders.insert(&[Value::Int(i), Value::Int(i % 3), amount])?;
+        }
+        db.create_index("orders", "by_status", vec![1], vec![])?;
+
+        let sql = "SELECT status, COUNT(*), count(amount), sum(amount) AS total, max(amount) FROM orders \
+            GROUP BY status HAVING min(id) > 0";
+        let Ok(Statement::Select(select)) = parse_statement(sql) else { panic!("not a query: {sql}") };
+        let query = plan_select(db.catalog(), &select).unwrap();
+        assert_eq!(query.plan.describe(), "Project(Filter(StreamAggregate(OrderBy(SeqScan(orders)))))");
+        assert_eq!(query.columns, vec!["status", "count", "count", "total", "max"]);
+        let expected = |status: i64| {
+            let amounts = (0..100).filter(|i| i % 3 == status && i % 10 != 0);
+            let (count, sum, max) = (amounts.clone().count() as i64, amounts.clone().sum::<i64>(), amounts.max());
+            let all = (0..100).filter(|i| i % 3 == status).count() as i64;
+            vec![Value::Int(status), Value::Int(all), Value::Int(count), Value::Int(sum), Value::Int(max.unwrap())]
+        };
+        assert_eq!(db.query(sql, &[])?.rows, vec![expected(1), expected(2)]);
+        let total = db.query("SELECT count(*), avg(amount) FROM orders WHERE id < 0", &[])?.rows;
+        assert_eq!(total, vec![vec![Value::Int(0), Value::Null]]);
+
+        let error = |sql| plan(db.catalog(), sql).err();
+        let ungrouped = Some(PlanError::UngroupedColumn("id".to_string()));
+        assert_eq!(error("SELECT id, count(*) FROM orders GROUP BY status"), ungrouped);
+        assert_eq!(error("SELECT sum(id, status) FROM orders"), Some(PlanError::Arguments("sum".to_string())));
+        // Fetching the few rows of one status through the index beats sorting them.
+        let describe = |sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        let one = describe("SELECT status, count(*) FROM orders WHERE status = 1 GROUP BY status");
+        assert_eq!(one, "Project(StreamAggregate(IndexScan(orders.by_status)))");
+        // Only plain columns can be streamed over in order, so grouping by anything else hashes.
+        let sql = "SELECT id % 4 AS k, count(*) FROM orders WHERE id < 10 GROUP BY id % 4";
+        assert_eq!(describe(sql), "Project(HashAggregate(Filter(SeqScan(orders))))");
+        let groups: Vec<_> = [(0, 3), (1, 3), (2, 2), (3, 2)].map(|(k, n)| vec![Value::Int(k), Value::Int(n)]).into();
+        assert_eq!(db.query(sql, &[])?.rows, groups);
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_by() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::nullable(ColumnType::Int));
+        let mut t = db.create_table("t", vec![int("a"), int("b")])?;
+        for (a, b) in [(2, 1), (1, 2), (3, 2), (0, 0)] {
+            let b = if b == 0 { Value::Null } else { Value::Int(b) };
+            t.insert(&[Value::Int(a), b])?;
+        }
+        db.create_index("t", "by_a", vec![0], vec![])?;
+
+        let describe = |sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        assert_eq!(describe("SELECT * FROM t ORDER BY a DESC"), "Project(OrderBy(SeqScan(t)))");
+        assert_eq!(describe("SELECT * FROM t WHERE a = 1 ORDER BY a"), "Project(IndexScan(t.by_a))");
+        let rows = |sql| -> Result<Vec<Vec<Value>>, DatabaseError> { Ok(db.query(sql, &[])?.rows) };
+        let ints = |values: &[i64]| values.iter().map(|&a| vec![Value::Int(a)]).collect::<Vec<_>>();
+        assert_eq!(rows("SELECT a FROM t ORDER BY a")?, ints(&[0, 1, 2, 3]));
+        assert_eq!(rows("SELECT a AS x FROM t ORDER BY b DESC, x")?, ints(&[1, 3, 2, 0]));
+        assert_eq!(rows("SELECT a FROM t ORDER BY b NULLS LAST, 1 DESC")?, ints(&[2, 3, 1, 0]));
+        let error = plan(db.catalog(), "SELECT a FROM t ORDER BY 2").err();
+        assert_eq!(error, Some(PlanError::NoSuchColumn("2".to_string())));
+
+        assert_eq!(describe("SELECT * FROM t ORDER BY b LIMIT 2"), "Limit(Project(OrderBy(SeqScan(t))))");
+        assert_eq!(rows("SELECT a FROM t ORDER BY a DESC LIMIT 2")?, ints(&[3, 2]));
+        assert_eq!(rows("SELECT a FROM t ORDER BY a LIMIT 2 OFFSET 1")?, ints(&[1, 2]));
+        assert_eq!(rows("SELECT a FROM t ORDER BY a OFFSET 3")?, ints(&[3]));
+        let error = plan(db.catalog(), "SELECT a FROM t LIMIT a").err();
+        assert_eq!(error, Some(PlanError::NoSuchColumn("a".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_distinct() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::nullable(ColumnType::Int));
+        let mut t = db.create_table("t", vec![int("id"), int("a"), int("b")])?;
+        let b = |i: i64| if i % 5 == 0 { Value::Null } else { Value::Int(i % 3) };
+        for i in 0..40 {
+            t.insert(&[Value::Int(i), Value::Int(i % 4), b(i)])?;
+        }
+
+        let describe = |sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        let rows = |sql| -> Result<Vec<Vec<Value>>, DatabaseError> { Ok(db.query(sql, &[])?.rows) };
+        let ints = |rows: &[&[Option<i64>]]| -> Vec<Vec<Value>> {
+            rows.iter().map(|row| row.iter().map(|v| v.map_or(Value::Null, Value::Int)).collect()).collect()
+        };
+        // Few rows sort for less than they hash.
+        assert_eq!(describe("SELECT DISTINCT a FROM t"), "StreamAggregate(OrderBy(SeqScan(t)))");
+        assert_eq!(rows("SELECT DISTINCT a FROM t")?, ints(&[&[Some(0)], &[Some(1)], &[Some(2)], &[Some(3)]]));
+        let sql = "SELECT DISTINCT b, a % 2 FROM t WHERE a < 2 ORDER BY 2 DESC";
+        let expected = ints(&[&[None, Some(1)], &[Some(0), Some(1)], &[Some(1), Some(1)], &[Some(2), Some(1)]]);
+        assert_eq!(rows(sql)?[..4], expected);
+        assert_eq!(rows(sql)?.len(), 8);
+        assert_eq!(rows("SELECT DISTINCT count(*) FROM t GROUP BY a")?, ints(&[&[Some(10)]]));
+
+        // Each `ON` group keeps its first row in `ORDER BY` order, or any row without one.
+        let sql = "SELECT DISTINCT ON (a) a, id FROM t ORDER BY a, id DESC";
+        assert_eq!(describe(sql), "Project(StreamAggregate(OrderBy(SeqScan(t))))");
+        let expected = ints(&[&[Some(0), Some(36)], &[Some(1), Some(37)], &[Some(2), Some(38)], &[Some(3), Some(39)]]);
+        assert_eq!(rows(sql)?, expected);
+        assert_eq!(rows("SELECT DISTINCT ON (b) b FROM t")?, ints(&[&[None], &[Some(0)], &[Some(1)], &[Some(2)]]));
+
+        // Many rows hash for less than they sort.
+        let sql = "SELECT DISTINCT x.a, y.b FROM t x, t y, t z";
+        assert!(describe(sql).starts_with("HashAggregate("), "{}", describe(sql));
+        assert_eq!(rows(sql)?.len(), 16);
+        // Unless they would be sorted for `ORDER BY` all the same.
+        let sql = "SELECT DISTINCT x.a FROM t x, t y, t z ORDER BY x.a DESC";
+        assert!(describe(sql).starts_with("StreamAggregate(OrderBy("), "{}", describe(sql));
+        assert_eq!(rows(sql)?, ints(&[&[Some(3)], &[Some(2)], &[Some(1)], &[Some(0)]]));
+
+        let error = |sql| plan(db.catalog(), sql).err();
+        assert_eq!(error("SELECT DISTINCT a FROM t ORDER BY id"), Some(PlanError::DistinctOrder));
+        assert_eq!(error("SELECT DISTINCT ON (a) id FROM t ORDER BY id, a"), Some(PlanError::DistinctOrder));
+        Ok(())
+    }
+
+    #[test]
+    fn test_subqueries() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::nullable(ColumnType::Int));
+        let mut users = db.create_table("users", vec![int("id")])?;
+        for i in 0..10 {
+            users.insert(&[Value::Int(i)])?;
+        }
+        let mut orders = db.create_table("orders", vec![int("user_id"), int("amount")])?;
+        for i in 0..20 {
+            let amount = if i == 3 { Value::Null } else { Value::Int(i) };
+            orders.insert(&[Value::Int(i % 5), amount])?;
+        }
+
+        let describe = |sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        let rows = |sql: &str, params: &[Value]| -> Result<_, DatabaseError> { Ok(db.query(sql, params)?.rows) };
+        let ints = |values: &[i64]| values.iter().map(|&a| vec![Value::Int(a)]).collect::<Vec<_>>();
+        // Subqueries that match on equalities with the outer query's columns become semi and anti joins.
+        let exists = "SELECT id FROM users u WHERE EXISTS (SELECT 1 FROM orders o WHERE o.user_id = u.id)";
+        assert_eq!(describe(exists), "Project(SemiHashJoin(SeqScan(users), Project(SeqScan(orders))))");
+        assert_eq!(rows(exists, &[])?, ints(&[0, 1, 2, 3, 4]));
+        let missing = "SELECT id FROM users WHERE id > 2 AND NOT EXISTS (SELECT * FROM orders WHERE user_id = id + 1)";
+        assert_eq!(describe(missing), "Project(AntiHashJoin(Filter(SeqScan(users)), Project(SeqScan(orders))))");
+        assert_eq!(rows(missing, &[])?, ints(&[4, 5, 6, 7, 8, 9]));
+        let within = "SELECT id FROM users WHERE id IN (SELECT user_id FROM orders WHERE amount > $1)";
+        assert_eq!(rows(within, &[Value::Int(16)])?, ints(&[2, 3, 4]));
+        assert_eq!(plan(db.catalog(), within).unwrap().params, vec![Some(ColumnType::Int)]);
+
+        // Others run for each row, or once if they read no outer column. NOT IN of a null is never true.
+        let not_in = "SELECT id FROM users WHERE id NOT IN (SELECT amount FROM orders) OR id = 0";
+        assert_eq!(describe(not_in), "Project(Filter(InApply(SeqScan(users), Project(SeqScan(orders)))))");
+        assert_eq!(rows(not_in, &[])?, ints(&[0]));
+        let present = "SELECT id FROM users WHERE id NOT IN (SELECT amount FROM orders WHERE amount <> 3)";
+        assert_eq!(rows(present, &[])?, ints(&[3]));
+        let later = "SELECT id FROM users u WHERE EXISTS (SELECT 1 FROM orders o WHERE o.user_id > u.id)";
+        let applied = "Project(Filter(ExistsApply(SeqScan(users), Project(Filter(SeqScan(orders))))))";
+        assert_eq!(describe(later), applied);
+        assert_eq!(rows(later, &[])?, ints(&[0, 1, 2, 3]));
+        let counts = "SELECT id, (SELECT count(*) FROM orders o WHERE o.user_id = u.id) FROM users u \
+            WHERE id > 3 LIMIT 2";
+        let expected = vec![vec![Value::Int(4), Value::Int(4)], vec![Value::Int(5), Value::Int(0)]];
+        assert_eq!(rows(counts, &[])?, expected);
+        // The subquery's nodes count every run, of a copy bound to each row.
+        let explained = rows(&format!("EXPLAIN ANALYZE {counts}"), &[])?;
+        let aggregate = explained.iter().find(|row| matches!(&row[0], Value::Text(l) if l.contains("StreamAggregate")));
+        assert!(matches!(aggregate, Some(row) if matches!(&row[0], Value::Text(l) if l.contains(" loops=2 "))));
+        assert_eq!(rows("SELECT id FROM users WHERE id = (SELECT max(user_id) FROM orders)", &[])?, ints(&[4]));
+        let many = rows("SELECT (SELECT id FROM users) FROM users", &[]);
+        assert_eq!(many, Err(DatabaseError::Exec(crate::exec::ExecError::SubqueryRows)));
+
+        let derived = "SELECT d.total FROM (SELECT user_id, sum(amount) AS total FROM orders GROUP BY user_id) d \
+            WHERE d.user_id = 1";
+        assert_eq!(rows(derived, &[])?, ints(&[34]));
+        let error = |sql| plan(db.catalog(), sql).err();
+        assert_eq!(error("SELECT (SELECT id, id FROM users)"), Some(PlanError::SubqueryColumns));
+        let ordered = Some(PlanError::Unsupported("subqueries outside WHERE and the select list"));
+        assert_eq!(error("SELECT id FROM users ORDER BY (SELECT 1)"), ordered);
+        let grouped = Some(PlanError::Unsupported("subqueries over groups"));
+        assert_eq!(error("SELECT count(*), (SELECT 1) FROM users"), grouped);
+        Ok(())
+    }
+
+    #[test]
+    fn test_writes_read_rids() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::new(ColumnType::Int));
+        let mut users = db.create_table("users", vec![int("id"), int("score")])?;
+        for i in 0..1000 {
+            users.insert(&[Value::Int(i), Value::Int(i % 10)])?;
+        }
+        db.create_index("users", "by_id", vec![0], vec![])?;
+        db.analyze("users")?;
+
+        // The rid follows the table's columns, and a filter on an index's columns scans the index.
+        let (filter, score) = (parse_expr("id = 7")?, parse_expr("score + 1")?);
+        let query = Planner::new(db.catalog()).plan_write("users", Some(&filter), &[score])?;
+        let key = vec![Expr::Literal(Value::Int(7))];
+        let (table, index, low, high) = ("users".to_string(), "by_id".to_string(), Bound::Unbounded, Bound::Unbounded);
+        let scan = Plan::IndexScan { table, index, key, low, high, rid: true };
+        let one = Box::new(Expr::Literal(Value::Int(1)));
+        let plus = Expr::Binary { op: BinaryOp::Add, left: Box::new(Expr::Column(1)), right: one };
+        assert_eq!(query.plan, Plan::Project { input: Box::new(scan), exprs: vec![Expr::Column(2), plus] });
+        assert_eq!(query.types, vec![Some(ColumnType::Bytes), Some(ColumnType::Int)]);
+
+        let mut planner = Planner::new(db.catalog());
+        assert_eq!(planner.plan_write("orders", None, &[]).err(), Some(PlanError::NoSuchTable("orders".to_string())));
+        assert_eq!(parse_statement("SELECT \"\" FROM users").map(|_| ()).err().map(|e| e.offset), Some(7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_windows() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::new(ColumnType::Int));
+        let mut t = db.create_table("t", vec![int("id"), int("g"), int("x")])?;
+        for i in 0..10 {
+            t.insert(&[Value::Int(i), Value::Int(i % 2), Value::Int(i / 3)])?;
+        }
+
+        let describe = |sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        let rows = |sql| -> Result<Vec<Vec<Value>>, DatabaseError> { Ok(db.query(sql, &[])?.rows) };
+        let ints = |rows: &[&[i64]]| -> Vec<Vec<Value>> {
+            rows.iter().map(|row| row.iter().map(|v| Value::Int(*v)).collect()).collect()
+        };
+        // Each window sorts the rows its own way, and `ORDER BY` sorts them again after.
+        let sql = "SELECT id, row_number() OVER (PARTITION BY g ORDER BY id DESC), sum(x) OVER (PARTITION BY g) \
+            FROM t ORDER BY id";
+        assert_eq!(describe(sql), "Project(OrderBy(Window(OrderBy(Window(OrderBy(SeqScan(t)))))))");
+        let expected: Vec<[i64; 3]> = (0..10).map(|i| [i, 5 - i / 2, if i % 2 == 0 { 5 } else { 7 }]).collect();
+        assert_eq!(rows(sql)?, ints(&expected.iter().map(|r| &r[..]).collect::<Vec<_>>()));
+
+        // Functions over the same window share its sort. Peers rank the same, and the default frame ends at
+        // the current row's last peer.
+        let sql = "SELECT x, rank() OVER (ORDER BY x), dense_rank() OVER (ORDER BY x), sum(x) OVER (ORDER BY x), \
+            count(*) OVER (ORDER BY x ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) FROM t WHERE id < 6";
+        assert!(describe(sql).starts_with("Project(Window(OrderBy("), "{}", describe(sql));
+        let (first, rest) = ([0, 1, 1, 0, 1], [0, 1, 1, 0, 2]);
+        let expected = [first, rest, rest, [1, 4, 2, 3, 2], [1, 4, 2, 3, 2], [1, 4, 2, 3, 2]];
+        assert_eq!(rows(sql)?, ints(&expected.iter().map(|r| &r[..]).collect::<Vec<_>>()));
+        let sql = "SELECT id, rank() OVER (ORDER BY x DESC) AS r FROM t ORDER BY r, id LIMIT 3";
+        assert_eq!(rows(sql)?, ints(&[&[9, 1], &[6, 2], &[7, 2]]));
+
+        let error = |sql| plan(db.catalog(), sql).err();
+        assert_eq!(error("SELECT id FROM t WHERE rank() OVER () > 1"), Some(PlanError::MisplacedWindow));
+        assert_eq!(error("SELECT sum(rank() OVER ()) OVER () FROM t"), Some(PlanError::MisplacedWindow));
+        assert_eq!(error("SELECT rank(x) OVER () FROM t"), Some(PlanError::Arguments("rank".to_string())));
+        let over_groups = Some(PlanError::Unsupported("window functions over groups"));
+        assert_eq!(error("SELECT g, rank() OVER (ORDER BY g) FROM t GROUP BY g"), over_groups);
+        let sql = "SELECT sum(x) OVER (ORDER BY x RANGE BETWEEN 1 PRECEDING AND CURRENT ROW) FROM t";
+        assert_eq!(error(sql), Some(PlanError::Unsupported("RANGE frames with offsets")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ctes() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::new(ColumnType::Int));
+        let mut edges = db.create_table("edges", vec![int("a"), int("b")])?;
+        for (a, b) in [(1, 2), (2, 3), (3, 1), (3, 4)] {
+            edges.insert(&[Value::Int(a), Value::Int(b)])?;
+        }
+
+        let describe = |db: &Database<_>, sql| plan(db.catalog(), sql).unwrap().plan.describe();
+        let rows = |db: &Database<_>, sql| -> Result<Vec<Vec<Value>>, DatabaseError> { Ok(db.query(sql, &[])?.rows) };
+        let ints = |values: &[i64]| -> Vec<Vec<Value>> { values.iter().map(|v| vec![Value::Int(*v)]).collect() };
+        // Read once, a common table expression is planned in place; read twice, its rows are materialized.
+        let sql = "WITH big AS (SELECT a FROM edges WHERE a > 1) SELECT * FROM big ORDER BY a";
+        assert_eq!(describe(&db, sql), "Project(OrderBy(Project(Filter(SeqScan(edges)))))");
+        assert_eq!(rows(&db, sql)?, ints(&[2, 3, 3]));
+        let sql = "WITH big (n) AS (SELECT a FROM edges WHERE a > 2) SELECT x.n FROM big x, big y ORDER BY x.n";
+        assert!(describe(&db, sql).starts_with("With("), "{}", describe(&db, sql));
+        assert!(describe(&db, sql).contains("CteScan(big)"), "{}", describe(&db, sql));
+        assert_eq!(rows(&db, sql)?, ints(&[3, 3, 3, 3]));
+        let query = plan(db.catalog(), sql).unwrap();
+        assert_eq!(query.estimates.len(), query.plan.nodes().len());
+        // A later common table expression reads an earlier one, and a name in scope hides a table.
+        let sql = "WITH edges AS (SELECT 7 AS a), twice AS (SELECT a * 2 AS b FROM edges) SELECT b FROM twice";
+        assert_eq!(rows(&db, sql)?, ints(&[14]));
+
+        let sql = "WITH RECURSIVE n (i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5) SELECT i FROM n";
+        assert!(describe(&db, sql).contains("Recursive("), "{}", describe(&db, sql));
+        assert_eq!(rows(&db, sql)?, ints(&[1, 2, 3, 4, 5]));
+        let query = plan(db.catalog(), sql).unwrap();
+        assert_eq!(query.estimates.len(), query.plan.nodes().len());
+        assert_eq!(db.query(&format!("EXPLAIN {sql}"), &[])?.rows.len(), query.estimates.len());
+        // `UNION` drops rows already reached, so a walk around the cycle ends.
+        let sql = "WITH RECURSIVE reach (n) AS (SELECT 1 UNION SELECT b FROM reach, edges WHERE a = n) \
+            SELECT n FROM reach ORDER BY n";
+        assert_eq!(rows(&db, sql)?, ints(&[1, 2, 3, 4]));
+        let sql = "WITH RECURSIVE reach (n) AS (SELECT 1 UNION ALL SELECT b FROM reach, edges WHERE a = n) \
+            SELECT n FROM reach";
+        let limit = Err(DatabaseError::Exec(ExecError::RecursionLimit(exec::DEFAULT_RECURSION_LIMIT)));
+        assert_eq!(rows(&db, sql), limit);
+        db.set_recursion_limit(5);
+        assert_eq!(rows(&db, sql), Err(DatabaseError::Exec(ExecError::RecursionLimit(5))));
+
+        let error = |sql| plan(db.catalog(), sql).err();
+        let columns = Some(PlanError::CteColumns("c".to_string()));
+        assert_eq!(error("WITH c (x, y) AS (SELECT 1) SELECT * FROM c"), columns);
+        assert_eq!(error("WITH RECURSIVE c AS (SELECT 1 UNION SELECT 1, 2 FROM c) SELECT * FROM c"), columns);
+        let union = Some(PlanError::Unsupported("UNION outside WITH RECURSIVE"));
+        assert_eq!(error("WITH c AS (SELECT 1 UNION SELECT 2) SELECT * FROM c"), union);
+        // A common table expression is in scope only in its own query.
+        let no_table = Some(PlanError::NoSuchTable("c".to_string()));
+        assert_eq!(error("SELECT * FROM (WITH c AS (SELECT 1) SELECT * FROM c) d, c"), no_table);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_plans_gather_what_workers_can_share() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::new(ColumnType::Int));
+        let mut big = db.create_table("big", vec![int("a"), int("b")])?;
+        for i in 0..12000 {
+            big.insert(&[Value::Int(i), Value::Int(i % 100)])?;
+        }
+        let mut small = db.create_table("small", vec![int("a")])?;
+        for i in 0..100 {
+            small.insert(&[Value::Int(i)])?;
+        }
+        db.analyze("big")?;
+        db.analyze("small")?;
+
+        let parallel = |sql| {
+            let Ok(Statement::Select(select)) = parse_statement(sql) else { panic!("not a query: {sql}") };
+            let query = Planner::new(db.catalog()).parallel(4).plan_select(&select).unwrap();
+            assert_eq!(query.estimates.len(), query.plan.nodes().len());
+            assert!(query.estimates[0].cost <= plan(db.catalog(), sql).unwrap().estimates[0].cost);
+            query.plan.describe()
+        };
+        let sql = "SELECT a FROM big WHERE b = 7";
+        assert_eq!(parallel(sql), "Gather(Project(Filter(SeqScan(big))))");
+        let sql = "SELECT b + 1, count(*) FROM big GROUP BY b + 1";
+        assert_eq!(parallel(sql), "Gather(Project(HashAggregate(Repartition(SeqScan(big)))))");
+        // Only scanning, or reading too few rows, is not worth the workers.
+        assert_eq!(parallel("SELECT a FROM big"), "Project(SeqScan(big))");
+        assert_eq!(parallel("SELECT a FROM small WHERE a > 3"), "Project(Filter(SeqScan(small)))");
+        // A subquery that runs for each row stays on one thread, though what it runs for may not.
+        let sql = "SELECT (SELECT count(*) FROM small s WHERE s.a = big.b) FROM big WHERE a % 2 = 0";
+        let plan = parallel(sql);
+        assert!(plan.starts_with("Project(ScalarApply(Gather(Filter(SeqScan(big))), "), "{plan}");
+        assert_eq!(plan.matches("Gather").count(), 1, "{plan}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrites() -> Result<(), DatabaseError> {
+        let store = PageStore::new(TestStorage::new());
+        let mut db = Database::create(&store)?;
+        let int = |name| ColumnDef::new(name, Column::new(ColumnType::Int));
+        let mut a = db.create_table("a", vec![int("x"), int("z")])?;
+        let mut b = db.create_table("b", vec![int("y"), int("w")])?;
+        for i in 0..200 {
+            a.insert(&[Value::Int(i), Value::Int(i % 3)])?;
+            b.insert(&[Value::Int(i * 2), Value::Int(i % 5)])?;
+        }
+
+        let traced = |sql| {
+            let Ok(Statement::Select(select)) = parse_statement(sql) else { panic!("not a query: {sql}") };
+            let mut planner = Planner::traced(db.catalog());
+            let plan = planner.plan_select(&select).unwrap().plan.describe();
+            (plan, planner.rewrites().to_vec())
+        };
+        let rows = |sql| -> Result<Vec<Vec<Value>>, DatabaseError> { Ok(db.query(sql, &[])?.rows) };
+        // The condition both sides of the `OR` have joins the tables by hashing.
+        let sql = "SELECT x FROM a, b WHERE (x = y AND z = 1) OR (y = x AND w = 2 - 1 + 1) ORDER BY x";
+        let (plan, rewrites) = traced(sql);
+        assert_eq!(plan, "Project(OrderBy(HashJoin(SeqScan(b), SeqScan(a))))");
+        let rules: Vec<_> = rewrites.iter().map(|r| r.rule).collect();
+        assert_eq!(rules, ["fold", "uncast", "extract", "propagate", "push"]);
+        assert_eq!(rewrites[2].conjuncts.len(), 2);
+        let expected: Vec<_> = (0..200).filter(|x| x % 2 == 0 && (x % 3 == 1 || x / 2 % 5 == 2)).collect();
+        assert_eq!(rows(sql)?, expected.into_iter().map(|x| vec![Value::Int(x)]).collect::<Vec<_>>());
+
+        // A filter over a query in `FROM` filters its rows before they are sorted.
+        let sql = "SELECT * FROM (SELECT CAST(x AS INT) AS x, z FROM a ORDER BY z) d WHERE d.x < 10 AND 1 = 1";
+        let (plan, rewrites) = traced(sql);
+        assert_eq!(plan, "Project(Project(OrderBy(Filter(SeqScan(a)))))");
+        assert!(rewrites.last().unwrap().conjuncts.is_empty());
+        let sorted = rows(sql)?;
+        assert_eq!(sorted.len(), 10);
+        assert!(sorted.windows(2).all(|w| w[0][1] <= w[1][1]));
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+//! Running the parts of a plan that are worth it on parallel workers. A part every worker can run over its
+//! share of the pages of the tables it reads runs under a `Gather`: a sequential scan, a filter or
+//! projection over such a part, a hash join of two, or a hash aggregate with grouping keys over one. Each
+//! join's inputs, and each aggregate's input, are repartitioned by their keys, so that every worker joins
+//! or groups the rows of its own keys. The topmost such part that costs at least `PARALLEL_COST` and does
+//! more than scan is gathered, unless it reads the outer query's columns; parts of a plan that run more
+//! than once, the inner side of a nested loop join, a subquery and a recursive term, are never gathered.
+use crate::exec::{Expr, Plan};
+
+use super::cost::{self, PARALLEL_COST};
+use super::{Estimate, Query};
+
+/// Gather the parts of `query`'s plan worth running on `workers` workers, adding the estimates of the
+/// nodes that takes.
+pub(super) fn parallelize(query: &mut Query, workers: usize) {
+    gather(&mut query.plan, 0, &mut query.estimates, workers);
+}
+
+/// Gather the parts of `plan`, whose estimate is at `node` of `estimates`, worth running in parallel, and
+/// return how many nodes it has after and how much less it is estimated to cost.
+fn gather(plan: &mut Plan, node: usize, estimates: &mut Vec<Estimate>, workers: usize) -> (usize, f64) {
+    if estimates[node].cost >= PARALLEL_COST && partitionable(plan) && !plan.reads_outer() && worth(plan) {
+        let nodes = repartition(plan, node, estimates);
+        let Estimate { rows, cost } = estimates[node];
+        let input = std::mem::replace(plan, Plan::Values { rows: Vec::new() });
+        *plan = Plan::Gather { input: Box::new(input), workers };
+        let gathered = cost / workers as f64 + rows * cost::CPU_ROW;
+        estimates.insert(node, Estimate { rows, cost: gathered });
+        return (nodes + 1, cost - gathered)
+    }
+    // The second input of these runs once for each row or round of the first.
+    let rerun = matches!(plan, Plan::NestedLoopJoin { .. } | Plan::Apply { .. } | Plan::Recursive { .. });
+    let (mut next, mut saved) = (node + 1, 0.0);
+    for (i, input) in plan.inputs_mut().into_iter().enumerate() {
+        let (nodes, less) = match rerun && i == 1 {
+            true => (input.nodes().len(), 0.0),
+            false => gather(input, next, estimates, workers),
+        };
+        next += nodes;
+        saved += less;
+    }
+    estimates[node].cost -= saved;
+    (next - node, saved)
+}
+
+/// Whether every worker can run `plan` over its share of the pages of the tables it scans.
+fn partitionable(plan: &Plan) -> bool {
+    match plan {
+        Plan::SeqScan { .. } => true,
+        Plan::Filter { input, .. } | Plan::Project { input, .. } => partitionable(input),
+        Plan::HashJoin { left, right, .. } => partitionable(left) && partitionable(right),
+        Plan::HashAggregate { input, group_by, .. } => !group_by.is_empty() && partitionable(input),
+        _ => false,
+    }
+}
+
+/// Whether `plan` does more with its rows than scan and project them, for the workers to share.
+fn worth(plan: &Plan) -> bool {
+    let works = |node: &&Plan| matches!(node, Plan::Filter { .. } | Plan::HashJoin { .. } | Plan::HashAggregate { .. });
+    plan.nodes().iter().any(works)
+}
+
+/// Repartition the inputs of the hash joins and aggregates in `plan`, whose estimate is at `node` of
+/// `estimates`, by their keys, and return how many nodes it has after.
+fn repartition(plan: &mut Plan, node: usize, estimates: &mut Vec<Estimate>) -> usize {
+    let keys: Vec<Vec<Expr>> = match plan {
+        Plan::HashJoin { left_keys, right_keys, .. } => vec![left_keys.clone(), right_keys.clone()],
+        Plan::HashAggregate { group_by, .. } => vec![group_by.clone()],
+        _ => Vec::new(),
+    };
+    let mut next = node + 1;
+    for (i, input) in plan.inputs_mut().into_iter().enumerate() {
+        let nodes = repartition(input, next, estimates);
+        if let Some(keys) = keys.get(i) {
+            let Estimate { rows, cost } = estimates[next];
+            estimates.insert(next, Estimate { rows, cost: cost + rows * cost::CPU_ROW });
+            let inner = std::mem::replace(input, Plan::Values { rows: Vec::new() });
+            *input = Plan::Repartition { input: Box::new(inner), keys: keys.clone() };
+            next += 1;
+        }
+        next += nodes;
+    }
+    next - node
+}
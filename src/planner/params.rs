@@ -0,0 +1,194 @@
+//! Inferring the types of a query's bind parameters from where they appear: a parameter compared with a
+//! column, or added to one, takes the column's type, one under `AND` or `NOT` is a boolean, one matched by
+//! `LIKE` or concatenated is text, one a JSON operator reads from is a document, and a `LIMIT` or `OFFSET`
+//! is an integer. A parameter nothing gives a type to may hold any value; where two places disagree, the
+//! first one wins.
+use crate::sql::ast::{BinaryOp, Expr, FromItem, Select, SelectItem, UnaryOp};
+use crate::tuple::ColumnType;
+
+use super::Scope;
+
+/// The type of each parameter of `select`, numbered from 0, where it can be told.
+pub(super) fn infer(select: &Select, scope: &Scope) -> Vec<Option<ColumnType>> {
+    let mut params = Vec::new();
+    let mut infer = |expr: &Expr, expected| infer_expr(expr, expected, scope, &mut params);
+    if let Some(from) = &select.from {
+        join_conditions(from, &mut |on| infer(on, Some(ColumnType::Bool)));
+    }
+    for item in &select.items {
+        if let SelectItem::Expr { expr, .. } = item {
+            infer(expr, None);
+        }
+    }
+    select.filter.iter().chain(&select.having).for_each(|e| infer(e, Some(ColumnType::Bool)));
+    select.group_by.iter().chain(select.order_by.iter().map(|o| &o.expr)).for_each(|e| infer(e, None));
+    select.limit.iter().chain(&select.offset).for_each(|e| infer(e, Some(ColumnType::Int)));
+    params
+}
+
+fn join_conditions(item: &FromItem, f: &mut impl FnMut(&Expr)) {
+    if let FromItem::Join { left, right, on, .. } = item {
+        join_conditions(left, f);
+        join_conditions(right, f);
+        on.iter().for_each(f);
+    }
+}
+
+/// The type `expr` evaluates to, where it can be told without evaluating it.
+pub(super) fn type_of(expr: &Expr, scope: &Scope) -> Option<ColumnType> {
+    match expr {
+        Expr::Literal(v) => v.column_type(),
+        Expr::Column { table, name } => scope.resolve(table.as_deref(), name).ok().and_then(|c| scope.types[c]),
+        Expr::Parameter(_) | Expr::Function { .. } | Expr::Window { .. } | Expr::Case { .. } => None,
+        Expr::Subquery(_) => None,
+        Expr::Unary { op: UnaryOp::Neg, expr } | Expr::Collate { expr, .. } => type_of(expr, scope),
+        Expr::Binary { op: BinaryOp::Concat | BinaryOp::ExtractText | BinaryOp::ExtractPathText, .. } => {
+            Some(ColumnType::Text)
+        }
+        Expr::Binary { op: BinaryOp::Extract | BinaryOp::ExtractPath, .. } => Some(ColumnType::Json),
+        Expr::Binary { op, left, right } if arithmetic(*op) => {
+            arithmetic_type(*op, type_of(left, scope), type_of(right, scope))
+        }
+        Expr::Cast { to, .. } => Some(*to),
+        Expr::Unary { .. }
+        | Expr::Binary { .. }
+        | Expr::IsNull { .. }
+        | Expr::Like { .. }
+        | Expr::InList { .. }
+        | Expr::Between { .. }
+        | Expr::Exists(_)
+        | Expr::InSubquery { .. } => Some(ColumnType::Bool),
+    }
+}
+
+/// The type of arithmetic on operands of types `left` and `right`: a date moved by an interval is a
+/// timestamp, the difference of two dates a number of days and of two timestamps an interval, a scaled
+/// interval an interval, a decimal with an integer a decimal and with a float a float, and otherwise the
+/// type of either side.
+fn arithmetic_type(op: BinaryOp, left: Option<ColumnType>, right: Option<ColumnType>) -> Option<ColumnType> {
+    use ColumnType::{Date, Decimal, Float, Int, Interval, Timestamp, TimestampTz};
+    match (left, right) {
+        (Some(Decimal { .. }), Some(Float)) | (Some(Float), Some(Decimal { .. })) => Some(Float),
+        (Some(Decimal { .. }), Some(Int | Decimal { .. })) | (Some(Int), Some(Decimal { .. })) => {
+            Some(ColumnType::NUMERIC)
+        }
+        (Some(Date), Some(Interval)) | (Some(Interval), Some(Date)) => Some(Timestamp),
+        (Some(Date), Some(Date)) if op == BinaryOp::Sub => Some(Int),
+        (Some(Timestamp | TimestampTz), Some(Timestamp | TimestampTz)) if op == BinaryOp::Sub => Some(Interval),
+        (Some(Int | Float | Decimal { .. }), Some(Interval)) => Some(Interval),
+        _ => left.or(right),
+    }
+}
+
+fn arithmetic(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem)
+}
+
+fn extraction(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Extract | BinaryOp::ExtractText | BinaryOp::ExtractPath | BinaryOp::ExtractPathText)
+}
+
+/// Record the types of the parameters in `expr` in `params`, where `expected` is the type the place
+/// `expr` appears in wants.
+fn infer_expr(expr: &Expr, expected: Option<ColumnType>, scope: &Scope, params: &mut Vec<Option<ColumnType>>) {
+    let mut infer = |expr: &Expr, expected| infer_expr(expr, expected, scope, params);
+    let type_of = |expr: &Expr| type_of(expr, scope);
+    let (boolean, text) = (Some(ColumnType::Bool), Some(ColumnType::Text));
+    match expr {
+        Expr::Parameter(n) => {
+            if params.len() <= *n {
+                params.resize(n + 1, None);
+            }
+            params[*n] = params[*n].or(expected);
+        }
+        // A subquery's parameters are inferred when it is planned, over its own columns.
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Subquery(_) | Expr::Exists(_) => {}
+        Expr::InSubquery { expr, .. } => infer(expr, None),
+        Expr::Unary { op: UnaryOp::Not, expr } => infer(expr, boolean),
+        Expr::Unary { op: UnaryOp::Neg, expr } | Expr::Collate { expr, .. } => infer(expr, expected),
+        Expr::Binary { op: BinaryOp::And | BinaryOp::Or, left, right } => {
+            infer(left, boolean);
+            infer(right, boolean);
+        }
+        Expr::Binary { op: BinaryOp::Concat, left, right } => {
+            infer(left, text);
+            infer(right, text);
+        }
+        Expr::Binary { op, left, right } if extraction(*op) => {
+            infer(left, Some(ColumnType::Json));
+            // A key may be text or a position, but a path is always text.
+            infer(right, if matches!(op, BinaryOp::ExtractPath | BinaryOp::ExtractPathText) { text } else { None });
+        }
+        Expr::Binary { op, left, right } => {
+            // Each side of a comparison takes the other's type; arithmetic may take its result's.
+            let context = if arithmetic(*op) { expected } else { None };
+            infer(left, type_of(right).or(context));
+            infer(right, type_of(left).or(context));
+        }
+        Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => infer(expr, None),
+        Expr::Like { expr, pattern, .. } => {
+            infer(expr, text);
+            infer(pattern, text);
+        }
+        Expr::InList { expr, list, .. } => {
+            let common = type_of(expr).or_else(|| list.iter().find_map(type_of));
+            infer(expr, common);
+            list.iter().for_each(|e| infer(e, common));
+        }
+        Expr::Between { expr, low, high, .. } => {
+            let common = [expr, low, high].into_iter().find_map(|e| type_of(e));
+            [expr, low, high].into_iter().for_each(|e| infer(e, common));
+        }
+        Expr::Function { args, .. } => args.iter().for_each(|e| infer(e, None)),
+        Expr::Window { args, over, .. } => {
+            let order_by = over.order_by.iter().map(|o| &o.expr);
+            args.iter().chain(&over.partition_by).chain(order_by).for_each(|e| infer(e, None))
+        }
+        Expr::Case { operand, branches, otherwise } => {
+            let when = match operand {
+                Some(operand) => {
+                    let common = type_of(operand).or_else(|| branches.iter().find_map(|(when, _)| type_of(when)));
+                    infer(operand, common);
+                    common
+                }
+                None => boolean,
+            };
+            for (condition, then) in branches {
+                infer(condition, when);
+                infer(then, expected);
+            }
+            otherwise.iter().for_each(|e| infer(e, expected));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sql::{self, ParseError, Statement};
+    use crate::tuple::ColumnType;
+
+    use super::super::Scope;
+    use super::infer;
+
+    #[test]
+    fn test_parameter_types() -> Result<(), ParseError> {
+        let columns = [("id", ColumnType::Int), ("name", ColumnType::Text), ("score", ColumnType::Float)];
+        let scope = Scope {
+            columns: columns.iter().map(|(name, _)| ("t".to_string(), name.to_string())).collect(),
+            types: columns.iter().map(|(_, c)| Some(*c)).collect(),
+            ..Scope::empty()
+        };
+        let types = |sql: &str| -> Result<_, ParseError> {
+            let Statement::Select(select) = sql::parse_statement(sql)? else { unreachable!() };
+            Ok(infer(&select, &scope))
+        };
+        let (int, float, text, boolean) =
+            (Some(ColumnType::Int), Some(ColumnType::Float), Some(ColumnType::Text), Some(ColumnType::Bool));
+
+        let sql = "SELECT ? + 1, name FROM t WHERE id = ? AND ? < score - 1 AND name LIKE ? AND ? LIMIT ? OFFSET $7";
+        assert_eq!(types(sql)?, vec![int, int, float, text, boolean, int, int]);
+        assert_eq!(types("SELECT ? FROM t WHERE id IN (?, $2) OR ? IS NULL")?, vec![None, int, None]);
+        assert_eq!(types("SELECT * FROM t WHERE CASE ? WHEN name THEN ? ELSE ? END")?, vec![text, boolean, boolean]);
+        Ok(())
+    }
+}
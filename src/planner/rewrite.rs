@@ -0,0 +1,320 @@
+//! Rewriting a query's conjuncts by rules before the search costs plans for them. The rules run in turn
+//! over the conjuncts of `WHERE` and of the inner joins' `ON`s, which the search may place anywhere their
+//! tables are; those that keep each conjunct where it is also run over the conjuncts of left joins' `ON`s.
+//!
+//! - `fold` evaluates the parts of each conjunct that read no columns or parameters, and drops the
+//!   constant side of an `AND` or `OR` that does not decide it. A conjunct that folds to true is dropped.
+//!   A part that fails to evaluate, such as a division by zero, is left for the query to fail on if a row
+//!   reaches it, and so are `now()`, whose value is that of when the query runs, and a collation's sort
+//!   key, which an index keyed by the collation is matched on.
+//! - `uncast` drops casts of values to the type they already have.
+//! - `extract` takes the conjuncts every side of an `OR` has in common out of it, so that a join's
+//!   condition written into each side joins the tables rather than filtering their product.
+//! - `propagate` adds, for a column compared with a constant and equated with another column, the same
+//!   comparison of the other column, so that it filters the other table's rows before they are joined.
+//! - `push` moves each conjunct reading only a view or query in `FROM` into its plan, under the projection
+//!   at its top and any projections and sorts under that, so that it filters the rows before they are
+//!   projected and sorted. It goes no further than a filter, which takes it, or anything else.
+use crate::exec::{Expr, Function, Plan};
+use crate::sql::ast::BinaryOp;
+use crate::tuple::{ColumnType, Value};
+
+use super::cost::{self, ColumnEstimates};
+use super::search::{conjoin, tables_of, Relation, Source, Tree};
+use super::{Estimate, Query};
+
+/// A rule rewriting conjuncts where they are.
+type Rule<'a> = dyn Fn(Vec<Expr>) -> Vec<Expr> + 'a;
+
+/// Rewrite `predicates`, and the conjuncts of the left joins in `trees`, by each rule in turn, calling
+/// `after` with the name of each rule and the predicates it leaves. `types` are those of the global columns.
+pub(super) fn rewrite(
+    relations: &mut [Relation],
+    trees: &mut [Tree],
+    mut predicates: Vec<Expr>,
+    types: &[Option<ColumnType>],
+    after: &mut dyn FnMut(&'static str, &[Expr]),
+) -> Vec<Expr> {
+    let folded = |conjuncts: Vec<Expr>| {
+        let folded = conjuncts.into_iter().map(fold);
+        folded.filter(|c| *c != Expr::Literal(Value::Bool(true))).collect()
+    };
+    let uncast = |conjuncts: Vec<Expr>| conjuncts.into_iter().map(|c| uncast(c, types)).collect();
+    let extracted = |conjuncts: Vec<Expr>| conjuncts.into_iter().flat_map(extract).collect();
+    let local: [(&'static str, &Rule); 3] = [("fold", &folded), ("uncast", &uncast), ("extract", &extracted)];
+    for (rule, rewrite) in local {
+        predicates = rewrite(predicates);
+        each_join(trees, &mut |on| *on = rewrite(std::mem::take(on)));
+        after(rule, &predicates);
+    }
+    propagate(&mut predicates);
+    after("propagate", &predicates);
+    predicates.retain(|predicate| !push(relations, predicate));
+    after("push", &predicates);
+    predicates
+}
+
+/// Call `f` with the conjuncts of each join in `trees`.
+fn each_join(trees: &mut [Tree], f: &mut dyn FnMut(&mut Vec<Expr>)) {
+    for tree in trees {
+        if let Tree::Join { left, right, on, .. } = tree {
+            each_join(left, f);
+            each_join(right, f);
+            f(on);
+        }
+    }
+}
+
+/// `expr` with its constant parts evaluated.
+fn fold(mut expr: Expr) -> Expr {
+    for child in expr.children_mut() {
+        *child = fold(std::mem::replace(child, Expr::Literal(Value::Null)));
+    }
+    let (t, f) = (Expr::Literal(Value::Bool(true)), Expr::Literal(Value::Bool(false)));
+    match expr {
+        Expr::Binary { op: BinaryOp::And, left, right } => match (*left, *right) {
+            (side, other) | (other, side) if side == t => other,
+            (side, _) | (_, side) if side == f => side,
+            (left, right) => Expr::Binary { op: BinaryOp::And, left: Box::new(left), right: Box::new(right) },
+        },
+        Expr::Binary { op: BinaryOp::Or, left, right } => match (*left, *right) {
+            (side, other) | (other, side) if side == f => other,
+            (side, _) | (_, side) if side == t => side,
+            (left, right) => Expr::Binary { op: BinaryOp::Or, left: Box::new(left), right: Box::new(right) },
+        },
+        Expr::Column(_) | Expr::Literal(_) | Expr::Parameter(_) | Expr::Outer(_) | Expr::Collate { .. } => expr,
+        Expr::Call { function: Function::Now, .. } => expr,
+        expr if expr.children().iter().all(|c| matches!(c, Expr::Literal(_))) => match expr.eval(&[]) {
+            Ok(value) => Expr::Literal(value),
+            Err(_) => expr,
+        },
+        expr => expr,
+    }
+}
+
+/// `expr` without the casts of values to their own types.
+fn uncast(mut expr: Expr, types: &[Option<ColumnType>]) -> Expr {
+    for child in expr.children_mut() {
+        *child = uncast(std::mem::replace(child, Expr::Literal(Value::Null)), types);
+    }
+    match expr {
+        Expr::Cast { expr, to } => {
+            let typed = match &*expr {
+                Expr::Column(c) => types.get(*c) == Some(&Some(to)),
+                Expr::Literal(value) => value.column_type() == Some(to),
+                Expr::Cast { to: inner, .. } => *inner == to,
+                _ => false,
+            };
+            if typed { *expr } else { Expr::Cast { expr, to } }
+        }
+        expr => expr,
+    }
+}
+
+/// `conjunct` as the conjuncts every side of it has in common, if it is an `OR`, and the `OR` of what
+/// is left of its sides, unless a side has nothing left and the common conjuncts imply it.
+fn extract(conjunct: Expr) -> Vec<Expr> {
+    let Expr::Binary { op: BinaryOp::Or, .. } = conjunct else { return vec![conjunct] };
+    let mut sides = Vec::new();
+    disjuncts(conjunct.clone(), &mut sides);
+    let sides: Vec<Vec<Expr>> = sides.into_iter().map(|side| conjuncts(side, Vec::new())).collect();
+    let within = |side: &[Expr], c: &Expr| side.iter().any(|d| same(c, d));
+    let mut common: Vec<Expr> = Vec::new();
+    for c in &sides[0] {
+        if sides[1..].iter().all(|side| within(side, c)) && !within(&common, c) {
+            common.push(c.clone());
+        }
+    }
+    if common.is_empty() {
+        return vec![conjunct]
+    }
+    let rest = sides.into_iter().map(|side| conjoin(side.into_iter().filter(|c| !within(&common, c))));
+    let or = |left, right| Expr::Binary { op: BinaryOp::Or, left: Box::new(left), right: Box::new(right) };
+    if let Some(rest) = rest.collect::<Option<Vec<_>>>() {
+        common.extend(rest.into_iter().reduce(or));
+    }
+    common
+}
+
+/// Whether `a` and `b` are the same conjunct, taking `=` to be the same either way round.
+fn same(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Binary { op: BinaryOp::Eq, left, right }, Expr::Binary { op: BinaryOp::Eq, left: l, right: r }) => {
+            (left == l && right == r) || (left == r && right == l)
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// The sides of the `OR`s `expr` is made of, left to right.
+fn disjuncts(expr: Expr, out: &mut Vec<Expr>) {
+    match expr {
+        Expr::Binary { op: BinaryOp::Or, left, right } => {
+            disjuncts(*left, out);
+            disjuncts(*right, out);
+        }
+        expr => out.push(expr),
+    }
+}
+
+/// The `AND`ed conjuncts `expr` is made of, left to right, after `out`.
+fn conjuncts(expr: Expr, mut out: Vec<Expr>) -> Vec<Expr> {
+    match expr {
+        Expr::Binary { op: BinaryOp::And, left, right } => conjuncts(*right, conjuncts(*left, out)),
+        expr => {
+            out.push(expr);
+            out
+        }
+    }
+}
+
+/// Add the comparisons with constants that the equalities of columns in `predicates` imply.
+fn propagate(predicates: &mut Vec<Expr>) {
+    let equal = predicates.iter().filter_map(|p| match p {
+        Expr::Binary { op: BinaryOp::Eq, left, right } => match (&**left, &**right) {
+            (Expr::Column(a), Expr::Column(b)) if a != b => Some((*a, *b)),
+            _ => None,
+        },
+        _ => None,
+    });
+    let equal: Vec<(usize, usize)> = equal.collect();
+    let mut implied = Vec::new();
+    for predicate in predicates.iter() {
+        let Expr::Binary { op, left, right } = predicate else { continue };
+        let comparison = cost::flip(*op).is_some();
+        let column = match (&**left, &**right) {
+            (Expr::Column(c), value) | (value, Expr::Column(c)) if comparison && cost::is_constant(value) => *c,
+            _ => continue,
+        };
+        for &(a, b) in &equal {
+            let other = match column {
+                c if c == a => b,
+                c if c == b => a,
+                _ => continue,
+            };
+            let mut copy = predicate.clone();
+            copy.map_columns(&|_| other);
+            if !predicates.contains(&copy) && !implied.contains(&copy) {
+                implied.push(copy);
+            }
+        }
+    }
+    predicates.extend(implied);
+}
+
+/// Push `predicate` into the plan of the derived relation it reads, if it reads only one whose plan is a
+/// projection, and return whether it did.
+fn push(relations: &mut [Relation], predicate: &Expr) -> bool {
+    let tables = tables_of(relations, predicate);
+    if tables.count_ones() != 1 {
+        return false
+    }
+    let relation = &mut relations[tables.trailing_zeros() as usize];
+    let Source::Derived(query) = &mut relation.source else { return false };
+    if !matches!(query.plan, Plan::Project { .. }) {
+        return false
+    }
+    let mut predicate = predicate.clone();
+    let offset = relation.offset;
+    predicate.map_columns(&|column| column - offset);
+    let selectivity = ColumnEstimates { columns: Vec::new() }.selectivity(&predicate);
+    let Query { plan, estimates, .. } = &mut **query;
+    push_under(plan, 0, estimates, predicate, selectivity);
+    true
+}
+
+/// Filter the rows of `plan`, whose estimate is at `node` of `estimates`, by `predicate`, as far down it as
+/// the filter keeps its meaning.
+fn push_under(plan: &mut Plan, node: usize, estimates: &mut Vec<Estimate>, predicate: Expr, selectivity: f64) {
+    let Estimate { rows, cost } = estimates[node];
+    let filtered = (rows * selectivity).max(1.0);
+    match plan {
+        Plan::Project { input, exprs } => {
+            estimates[node].rows = filtered;
+            push_under(input, node + 1, estimates, substitute(predicate, exprs), selectivity);
+        }
+        Plan::OrderBy { input, .. } => {
+            estimates[node].rows = filtered;
+            push_under(input, node + 1, estimates, predicate, selectivity);
+        }
+        Plan::Filter { predicate: existing, .. } => {
+            estimates[node].rows = filtered;
+            let left = Box::new(std::mem::replace(existing, Expr::Literal(Value::Null)));
+            *existing = Expr::Binary { op: BinaryOp::And, left, right: Box::new(predicate) };
+        }
+        _ => {
+            let input = std::mem::replace(plan, Plan::Values { rows: Vec::new() });
+            *plan = Plan::Filter { input: Box::new(input), predicate };
+            estimates.insert(node, Estimate { rows: filtered, cost: cost + rows * cost::CPU_ROW });
+        }
+    }
+}
+
+/// `expr`, over the rows of a projection by `exprs`, over the rows the projection reads.
+fn substitute(expr: Expr, exprs: &[Expr]) -> Expr {
+    match expr {
+        Expr::Column(c) => exprs[c].clone(),
+        mut expr => {
+            for child in expr.children_mut() {
+                *child = substitute(std::mem::replace(child, Expr::Literal(Value::Null)), exprs);
+            }
+            expr
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exec::{Expr, Function};
+    use crate::sql::ast::BinaryOp;
+    use crate::tuple::{ColumnType, Value};
+
+    use super::{extract, fold, propagate, uncast};
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+    }
+
+    #[test]
+    fn test_rules() {
+        let (int, column) = (|v| Expr::Literal(Value::Int(v)), Expr::Column);
+        let bool = |v| Expr::Literal(Value::Bool(v));
+        let is = |left, right| binary(BinaryOp::Eq, left, right);
+
+        // Constants fold, but a failing part, `now()` and a sort key are left.
+        let sum = binary(BinaryOp::Add, int(1), binary(BinaryOp::Mul, int(2), int(3)));
+        assert_eq!(fold(is(column(0), sum)), is(column(0), int(7)));
+        assert_eq!(fold(binary(BinaryOp::And, is(int(1), int(1)), is(column(0), int(2)))), is(column(0), int(2)));
+        assert_eq!(fold(binary(BinaryOp::Or, is(column(0), int(2)), is(int(1), int(1)))), bool(true));
+        assert_eq!(fold(binary(BinaryOp::And, is(column(0), int(2)), bool(false))), bool(false));
+        let failing = binary(BinaryOp::Div, int(1), int(0));
+        assert_eq!(fold(failing.clone()), failing);
+        let now = Expr::Call { function: Function::Now, args: Vec::new() };
+        assert_eq!(fold(now.clone()), now);
+
+        let types = [Some(ColumnType::Int), None];
+        let cast = |expr, to| Expr::Cast { expr: Box::new(expr), to };
+        assert_eq!(uncast(cast(cast(column(0), ColumnType::Int), ColumnType::Int), &types), column(0));
+        assert_eq!(uncast(cast(column(1), ColumnType::Int), &types), cast(column(1), ColumnType::Int));
+        assert_eq!(uncast(cast(int(1), ColumnType::Float), &types), cast(int(1), ColumnType::Float));
+
+        // `(a = b AND a = 1) OR (a = b AND b = 2)` joins on `a = b`; `a = b OR (a = b AND b = 2)` is `a = b`.
+        let join = is(column(0), column(1));
+        let (first, second) = (is(column(0), int(1)), is(column(1), int(2)));
+        let sides = binary(
+            BinaryOp::Or,
+            binary(BinaryOp::And, join.clone(), first.clone()),
+            binary(BinaryOp::And, join.clone(), second.clone()),
+        );
+        assert_eq!(extract(sides), vec![join.clone(), binary(BinaryOp::Or, first.clone(), second.clone())]);
+        let implied = binary(BinaryOp::Or, join.clone(), binary(BinaryOp::And, join.clone(), second.clone()));
+        assert_eq!(extract(implied), vec![join.clone()]);
+        let unrelated = binary(BinaryOp::Or, first.clone(), second.clone());
+        assert_eq!(extract(unrelated.clone()), vec![unrelated]);
+
+        let mut predicates = vec![join.clone(), binary(BinaryOp::Lt, int(5), column(1)), first.clone()];
+        propagate(&mut predicates);
+        let implied = [binary(BinaryOp::Lt, int(5), column(0)), is(column(1), int(1))];
+        assert_eq!(predicates[3..], implied);
+    }
+}
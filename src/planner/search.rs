@@ -0,0 +1,687 @@
+//! The search for the cheapest plan: access paths for each table, then join orders and algorithms over
+//! every subset of the tables, smallest subsets first.
+//!
+//! Subsets are bitmasks over the leaves of one level of the search: the tables joined with inner and
+//! cross joins, among which any order is allowed. A left join is one leaf of the level it appears in.
+//! Its two sides are searched as levels of their own, since its right side cannot be joined before or
+//! apart from its left, and the cheapest plans for them are joined in that order. The semi and anti
+//! joins a subquery in `WHERE` becomes are leaves the same way, with the subquery their right side.
+//!
+//! A query that groups its rows needs them ordered by its grouping columns, and one with `ORDER BY` may
+//! want them sorted by its columns. An index scan gives rows in order, so each level also keeps the
+//! cheapest candidate for each subset whose rows come out in the order wanted, and in the end that wins
+//! over sorting the cheapest plan when it costs less.
+//!
+//! Expressions arrive bound to the query's global column numbering, in which table `i`'s columns start
+//! at its `offset`; each candidate plan carries the global number of every column in its rows, its
+//! layout, and expressions are renumbered against that layout as they are placed in the plan.
+use std::ops::Bound;
+
+use crate::catalog::{TableDef, TableKind};
+use crate::collation::Collation;
+use crate::exec::{self, Expr, JoinType, Plan, SortKey};
+use crate::json::JsonPath;
+use crate::sql::ast::BinaryOp;
+use crate::table::{IndexKind, PartitionBy};
+
+use super::cost::{self, ColumnEstimates};
+use super::{Alternative, Estimate, PlanError, Query};
+
+/// Most tables one query may join. The search considers every way of splitting every subset of the
+/// tables in two, which is 3^n splits over n tables.
+const MAX_TABLES: usize = 10;
+
+pub(super) struct Relation<'a> {
+    /// The table's name in the catalog.
+    pub name: String,
+    /// The name the query gives it: its alias, or else its name.
+    pub alias: String,
+    pub source: Source<'a>,
+    /// The global number of the table's first column.
+    pub offset: usize,
+    /// Whether each of the table's rows is read with its rid after its columns, for a write to find it by.
+    pub rid: bool,
+}
+impl Relation<'_> {
+    /// The number of columns read from the relation, the rid included.
+    fn width(&self) -> usize {
+        self.source.width() + self.rid as usize
+    }
+}
+
+/// What a relation reads.
+pub(super) enum Source<'a> {
+    Table(&'a TableDef),
+    /// The rows of a planned query, as a view or a subquery gives.
+    Derived(Box<Query>),
+}
+impl Source<'_> {
+    fn width(&self) -> usize {
+        match self {
+            Source::Table(def) => def.columns.len(),
+            Source::Derived(query) => query.columns.len(),
+        }
+    }
+}
+
+/// A join that keeps its sides in order, and what it outputs for each left row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Ordered {
+    /// The row with each right row it matches, or with nulls if it matches none.
+    Left,
+    /// The row alone, if it matches any right row.
+    Semi,
+    /// The row alone, if it matches no right row.
+    Anti,
+}
+
+/// A leaf of a search level.
+pub(super) enum Tree {
+    /// The relation at this index.
+    Table(usize),
+    /// `left` joined to `right` on the conjuncts `on`, each side the leaves of a level of its own.
+    Join { kind: Ordered, left: Vec<Tree>, right: Vec<Tree>, on: Vec<Expr> },
+}
+impl Tree {
+    /// The relations under the leaf.
+    fn tables(&self) -> u32 {
+        match self {
+            Tree::Table(relation) => 1 << relation,
+            Tree::Join { left, right, .. } => left.iter().chain(right).fold(0, |tables, t| tables | t.tables()),
+        }
+    }
+}
+
+/// An order the query wants its rows in.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Order<'a> {
+    Any,
+    /// Rows with equal values of these global columns together, as when sorted by them in any order.
+    Grouped(&'a [usize]),
+    /// Sorted by these global columns, ascending with nulls first as an index orders them.
+    Sorted(&'a [usize]),
+}
+impl Order<'_> {
+    fn columns(&self) -> &[usize] {
+        match self {
+            Order::Any => &[],
+            Order::Grouped(columns) | Order::Sorted(columns) => columns,
+        }
+    }
+
+    /// Whether rows sorted by the columns `order` are in this order.
+    fn met_by(&self, order: &[usize]) -> bool {
+        let columns = self.columns();
+        let Some(leading) = order.get(..columns.len()) else { return false };
+        match self {
+            Order::Any => false,
+            Order::Grouped(columns) => leading.iter().all(|c| columns.contains(c)),
+            Order::Sorted(columns) => leading == *columns,
+        }
+    }
+}
+
+struct Conjunct {
+    expr: Expr,
+    /// The relations it reads.
+    tables: u32,
+    /// The leaves of its level that it reads.
+    leaves: u32,
+    selectivity: f64,
+}
+
+#[derive(Clone)]
+struct Candidate {
+    plan: Plan,
+    /// The relations it reads.
+    tables: u32,
+    layout: Vec<usize>,
+    /// The global columns its rows are sorted by, the leading one first.
+    order: Vec<usize>,
+    rows: f64,
+    cost: f64,
+    /// The estimates of each node of `plan`, in the order `Plan::nodes` lists them.
+    estimates: Vec<Estimate>,
+}
+impl Candidate {
+    /// A candidate whose plan `plan` produces `rows` rows at `cost`, over the plans of `inputs`.
+    fn new(plan: Plan, tables: u32, layout: Vec<usize>, rows: f64, cost: f64, inputs: &[&Candidate]) -> Candidate {
+        let mut estimates = vec![Estimate { rows, cost }];
+        inputs.iter().for_each(|input| estimates.extend(&input.estimates));
+        Candidate { plan, tables, layout, order: Vec::new(), rows, cost, estimates }
+    }
+
+    /// The candidate's rows filtered by `predicate`, if there is one, estimated at `rows` rows and `cost`.
+    fn filtered(mut self, predicate: Option<Expr>, rows: f64, cost: f64) -> Candidate {
+        if let Some(predicate) = predicate {
+            self.plan = Plan::Filter { input: Box::new(self.plan), predicate };
+            self.estimates.insert(0, Estimate { rows, cost });
+        }
+        Candidate { rows, cost, ..self }
+    }
+}
+
+struct Search<'r, 't> {
+    relations: &'r [Relation<'r>],
+    estimates: ColumnEstimates<'r>,
+    order: Order<'r>,
+    trace: Option<&'t mut Vec<Alternative>>,
+}
+
+/// One level of the search, over leaves joined in any order.
+struct Level {
+    conjuncts: Vec<Conjunct>,
+    best: Vec<Option<Candidate>>,
+    /// The cheapest candidate whose rows come out in the order the query wants, for each subset.
+    ordered: Vec<Option<Candidate>>,
+    /// Where the alternative in `best` is in the trace, for each subset.
+    traced: Vec<Option<usize>>,
+}
+
+/// The plan a search settled on.
+pub(super) struct Searched {
+    pub plan: Plan,
+    pub layout: Vec<usize>,
+    pub estimates: Vec<Estimate>,
+}
+impl From<Candidate> for Searched {
+    fn from(c: Candidate) -> Self {
+        Searched { plan: c.plan, layout: c.layout, estimates: c.estimates }
+    }
+}
+
+/// The cheapest plans reading the leaves `trees` and filtering them by every one of `predicates`: the
+/// cheapest in any order, and the cheapest whose rows come out in `order`.
+pub(super) fn search(
+    relations: &[Relation],
+    trees: &[Tree],
+    predicates: Vec<Expr>,
+    order: Order,
+    trace: Option<&mut Vec<Alternative>>,
+) -> Result<(Searched, Searched), PlanError> {
+    if relations.len() > MAX_TABLES {
+        return Err(PlanError::Unsupported("joins of more than 10 tables"))
+    }
+    let mut estimates = ColumnEstimates { columns: Vec::new() };
+    for relation in relations {
+        let stats = match &relation.source {
+            Source::Table(def) => def.stats.as_ref(),
+            Source::Derived(_) => None,
+        };
+        estimates.push_table(relation.source.width(), stats);
+        if relation.rid {
+            estimates.columns.push(None);
+        }
+    }
+    let (best, ordered) = Search { relations, estimates, order, trace }.level(trees, predicates);
+    let columns = order.columns();
+    if columns.is_empty() {
+        return Ok((best.clone().into(), best.into()))
+    }
+    let keys = columns.iter().map(|&c| {
+        let mut expr = Expr::Column(c);
+        remap(&mut expr, &best.layout);
+        SortKey { expr, descending: false, nulls_first: true }
+    });
+    let cost = best.cost + cost::sort(best.rows, best.layout.len(), exec::DEFAULT_MEMORY_BYTES as f64);
+    let ordered = match ordered {
+        Some(ordered) if ordered.cost <= cost => ordered,
+        _ => {
+            let plan = Plan::OrderBy { input: Box::new(best.plan.clone()), keys: keys.collect() };
+            Candidate::new(plan, best.tables, best.layout.clone(), best.rows, cost, &[&best])
+        }
+    };
+    Ok((best.into(), ordered.into()))
+}
+
+impl Search<'_, '_> {
+    fn conjunct(&self, expr: Expr, leaves: &[u32]) -> Conjunct {
+        let tables = tables_of(self.relations, &expr);
+        let leaves = leaves.iter().enumerate().filter(|(_, &t)| t & tables != 0).fold(0, |m, (i, _)| m | 1 << i);
+        let selectivity = self.estimates.selectivity(&expr);
+        Conjunct { expr, tables, leaves, selectivity }
+    }
+
+    /// The cheapest plan joining `trees` and filtering them by `predicates`, and the cheapest whose rows come
+    /// out in the wanted order, if there is one.
+    fn level(&mut self, trees: &[Tree], predicates: Vec<Expr>) -> (Candidate, Option<Candidate>) {
+        let leaves: Vec<u32> = trees.iter().map(Tree::tables).collect();
+        let conjuncts = predicates.into_iter().map(|expr| self.conjunct(expr, &leaves)).collect();
+        let subsets = 1usize << trees.len();
+        let none = || (0..subsets).map(|_| None).collect();
+        let mut level = Level { conjuncts, best: none(), ordered: none(), traced: vec![None; subsets] };
+        for (leaf, tree) in trees.iter().enumerate() {
+            match tree {
+                Tree::Table(relation) => self.access_paths(&mut level, leaf, *relation),
+                Tree::Join { kind, left, right, on } => self.ordered_join(&mut level, leaf, *kind, left, right, on),
+            }
+        }
+        for mask in 1..subsets as u32 {
+            if !mask.is_power_of_two() {
+                self.joins(&mut level, mask);
+            }
+        }
+        if let Some(trace) = self.trace.as_deref_mut() {
+            level.traced.iter().flatten().for_each(|&i| trace[i].chosen = true);
+        }
+        let best = match level.best.pop().flatten() {
+            Some(best) if !trees.is_empty() => best,
+            _ => {
+                Candidate::new(Plan::Values { rows: vec![vec![]] }, 0, Vec::new(), 1.0, 0.0, &[])
+            }
+        };
+        let ordered = level.ordered.pop().flatten();
+        // Conjuncts that read no table at all, such as a comparison of parameters, filter the final rows.
+        let constant = conjoin(level.conjuncts.into_iter().filter(|c| c.tables == 0).map(|c| c.expr));
+        let filter = |c: Candidate| {
+            let (rows, cost) = (c.rows, c.cost);
+            c.filtered(constant.clone(), rows, cost)
+        };
+        (filter(best), ordered.map(filter))
+    }
+
+    /// Cost reading the relation `relation`, the leaf `leaf`, with the conjuncts that read only it: for a
+    /// table by a sequential scan, by each B+tree index whose leading columns those conjuncts fix with `=` or
+    /// whose next column they bound with `<`, `<=`, `>` or `>=`, and by each hash index whose every column
+    /// they fix; for a clustered table by scanning its tree for the leading primary key columns they fix; for
+    /// a partitioned table by scanning the partitions that may hold the rows they fix or bound the partition
+    /// column to; for a derived relation by running its plan.
+    fn access_paths(&mut self, level: &mut Level, leaf: usize, relation: usize) {
+        let table = &self.relations[relation];
+        let tables = 1 << relation;
+        let local: Vec<&Conjunct> = level.conjuncts.iter().filter(|c| c.leaves == 1 << leaf).collect();
+        let layout: Vec<usize> = (table.offset..table.offset + table.width()).collect();
+        let def = match &table.source {
+            Source::Table(def) => *def,
+            Source::Derived(query) => {
+                let predicate = conjoin(local.iter().map(|c| c.expr.clone())).map(|mut e| {
+                    remap(&mut e, &layout);
+                    e
+                });
+                let check = if predicate.is_some() { query.rows() * cost::CPU_ROW } else { 0.0 };
+                let rows = (query.rows() * local.iter().map(|c| c.selectivity).product::<f64>()).max(1.0);
+                let (plan, estimates) = (query.plan.clone(), query.estimates.clone());
+                let (input_rows, cost) = (query.rows(), query.cost());
+                let order = Vec::new();
+                let candidate = Candidate { plan, tables, layout, order, rows: input_rows, cost, estimates };
+                return self.consider(level, 1 << leaf, candidate.filtered(predicate, rows, cost + check))
+            }
+        };
+        let rows = def.stats.as_ref().map_or(cost::DEFAULT_ROWS, |s| s.row_count as f64);
+        let output = (rows * local.iter().map(|c| c.selectivity).product::<f64>()).max(1.0);
+        let residual = |used: &[usize]| {
+            let unused = local.iter().enumerate().filter(|(i, _)| !used.contains(i));
+            conjoin(unused.map(|(_, c)| c.expr.clone())).map(|mut e| {
+                remap(&mut e, &layout);
+                e
+            })
+        };
+
+        if let TableKind::Clustered { primary_key } = &def.kind {
+            // The tree is keyed by the binary encoding of the primary key, so it finds and orders rows by the
+            // key's columns only up to the first with another collation.
+            let binary = primary_key.iter().take_while(|c| def.columns[**c].collation == Collation::Binary);
+            let order: Vec<usize> = binary.map(|c| table.offset + c).collect();
+            let (mut key, mut used) = (Vec::new(), Vec::new());
+            for &column in &order {
+                let Some((i, value)) = local.iter().enumerate().find_map(|(i, c)| {
+                    let value = fixed_value(&c.expr, column, None, Collation::Binary)?;
+                    (!used.contains(&i)).then_some((i, value))
+                }) else {
+                    break
+                };
+                key.push(value.clone());
+                used.push(i);
+            }
+            let selectivity: f64 = used.iter().map(|&i| local[i].selectivity).product();
+            let fetched = (rows * selectivity).max(1.0);
+            let scan_cost = match key.is_empty() {
+                true => rows * cost::SEQ_ROW,
+                false => cost::INDEX_PROBE + fetched * cost::INDEX_ROW,
+            };
+            let predicate = residual(&used);
+            let check = if predicate.is_some() { fetched * cost::CPU_ROW } else { 0.0 };
+            let plan = Plan::ClusteredScan { table: table.name.clone(), key, rid: table.rid };
+            let scan = Candidate { order, ..Candidate::new(plan, tables, layout.clone(), fetched, scan_cost, &[]) };
+            return self.consider(level, 1 << leaf, scan.filtered(predicate, output, scan_cost + check))
+        }
+
+        if let TableKind::Partitioned { by } = &def.kind {
+            // Partitions split rows by the binary encoding of the column, an order a column with another
+            // collation does not compare in, and hash partitions by no order at all. The conjuncts pruning
+            // partitions still filter the rows, since a partition holds rows they do not match.
+            let column = table.offset + by.column();
+            let binary = def.columns[by.column()].collation == Collation::Binary;
+            let mut fixed = local.iter().enumerate().filter(|_| binary);
+            let fixed = fixed.find_map(|(i, c)| Some((i, fixed_value(&c.expr, column, None, Collation::Binary)?)));
+            let (low, high, used) = match fixed {
+                Some((i, value)) => (Bound::Included(value.clone()), Bound::Included(value.clone()), vec![i]),
+                None if binary && matches!(by, PartitionBy::Range(_)) => {
+                    range(&local, &[], column, None, Collation::Binary)
+                }
+                None => (Bound::Unbounded, Bound::Unbounded, Vec::new()),
+            };
+            let selectivity: f64 = used.iter().map(|&i| local[i].selectivity).product();
+            let scanned = (rows * selectivity).max(1.0);
+            let predicate = residual(&[]);
+            let scan_cost = scanned * cost::SEQ_ROW;
+            let check = if predicate.is_some() { scanned * cost::CPU_ROW } else { 0.0 };
+            let (column, rid) = (by.column(), table.rid);
+            let plan = Plan::PartitionScan { table: table.name.clone(), column, low, high, rid };
+            let scan = Candidate::new(plan, tables, layout.clone(), scanned, scan_cost, &[]);
+            return self.consider(level, 1 << leaf, scan.filtered(predicate, output, scan_cost + check))
+        }
+
+        let mut candidates = Vec::new();
+        let predicate = residual(&[]);
+        let plan = Plan::SeqScan { table: table.name.clone(), rid: table.rid };
+        let scan = Candidate::new(plan, tables, layout.clone(), rows, rows * cost::SEQ_ROW, &[]);
+        let cost = rows * cost::SEQ_ROW + if predicate.is_some() { rows * cost::CPU_ROW } else { 0.0 };
+        candidates.push(scan.filtered(predicate, output, cost));
+
+        for index in &def.indexes {
+            let mut key = Vec::new();
+            let mut used = Vec::new();
+            for ((column, path), collation) in index.columns.iter().zip(&index.paths).zip(&index.collations) {
+                let Some((i, value)) = local.iter().enumerate().find_map(|(i, c)| {
+                    let value = fixed_value(&c.expr, table.offset + column, path.as_ref(), *collation)?;
+                    (!used.contains(&i)).then_some((i, value))
+                }) else {
+                    break
+                };
+                key.push(value.clone());
+                used.push(i);
+            }
+            // A hash index finds rows by its whole key alone, in no order.
+            let hash = index.kind == IndexKind::Hash;
+            if hash && key.len() < index.columns.len() {
+                continue
+            }
+            // The conjuncts bounding the next column still filter the rows scanned, since the scan leaves
+            // open a bound no value of the column's type equals.
+            let (low, high, ranged) = match index.columns.get(key.len()) {
+                Some(&column) => {
+                    let (path, collation) = (index.paths[key.len()].as_ref(), index.collations[key.len()]);
+                    range(&local, &used, table.offset + column, path, collation)
+                }
+                None => (Bound::Unbounded, Bound::Unbounded, Vec::new()),
+            };
+            // An index no conjunct fixes or bounds is still worth scanning whole if it gives the order wanted.
+            // Its key orders rows by columns only up to its first path or collation other than binary.
+            let parts = index.columns.iter().zip(&index.paths).zip(&index.collations);
+            let plain = parts.take_while(|((_, p), collation)| p.is_none() && **collation == Collation::Binary);
+            let order: Vec<usize> = plain.filter(|_| !hash).map(|((c, _), _)| table.offset + c).collect();
+            if key.is_empty() && ranged.is_empty() && !self.order.met_by(&order) {
+                continue
+            }
+            let selectivity: f64 = used.iter().chain(&ranged).map(|&i| local[i].selectivity).product();
+            let fetched = (rows * selectivity).max(1.0);
+            let predicate = residual(&used);
+            let check = if predicate.is_some() { fetched * cost::CPU_ROW } else { 0.0 };
+            let probe = if hash { cost::BUCKET_PROBE } else { cost::INDEX_PROBE };
+            let scan_cost = probe + fetched * cost::INDEX_ROW;
+            let (index, rid) = (index.name.clone(), table.rid);
+            let plan = Plan::IndexScan { table: table.name.clone(), index, key, low, high, rid };
+            let scan = Candidate { order, ..Candidate::new(plan, tables, layout.clone(), fetched, scan_cost, &[]) };
+            candidates.push(scan.filtered(predicate, output, scan_cost + check));
+        }
+        candidates.into_iter().for_each(|c| self.consider(level, 1 << leaf, c));
+    }
+
+    /// Cost the join that is the leaf `leaf`. The level's conjuncts that read only the left side filter
+    /// the left input, which keeps the same left rows; those reading the right side filter the join's
+    /// output, since they see the nulls of a left join's unmatched rows. Conjuncts of `on` that read only
+    /// the right side filter the right input, and the rest decide which pairs match.
+    fn ordered_join(
+        &mut self,
+        level: &mut Level,
+        leaf: usize,
+        kind: Ordered,
+        left: &[Tree],
+        right: &[Tree],
+        on: &[Expr],
+    ) {
+        let right_tables: u32 = right.iter().fold(0, |tables, t| tables | t.tables());
+        let (mut pushed, mut above) = (Vec::new(), Vec::new());
+        for c in level.conjuncts.iter().filter(|c| c.leaves == 1 << leaf) {
+            match c.tables & right_tables {
+                0 => pushed.push(c.expr.clone()),
+                _ => above.push(c),
+            }
+        }
+        let (inside, matching): (Vec<Expr>, Vec<Expr>) = on.iter().cloned().partition(|e| {
+            tables_of(self.relations, e) & !right_tables == 0
+        });
+        let (l, _) = self.level(left, pushed);
+        let (r, _) = self.level(right, inside);
+        let matching: Vec<Conjunct> = matching.into_iter().map(|e| self.conjunct(e, &[])).collect();
+        let selectivity: f64 = above.iter().map(|c| c.selectivity).product();
+        let predicate = conjoin(above.iter().map(|c| c.expr.clone()));
+        let matching: Vec<&Conjunct> = matching.iter().collect();
+        for mut candidate in join_candidates(self.relations, &l, &r, &matching, Some(kind)) {
+            if let Some(mut predicate) = predicate.clone() {
+                remap(&mut predicate, &candidate.layout);
+                let cost = candidate.cost + candidate.rows * cost::CPU_ROW;
+                let rows = (candidate.rows * selectivity).max(1.0);
+                candidate = candidate.filtered(Some(predicate), rows, cost);
+            }
+            self.consider(level, 1 << leaf, candidate);
+        }
+    }
+
+    /// Cost every way of joining two disjoint subsets of leaves that make up `mask`. Splits that no
+    /// conjunct joins are left out unless every split is like that.
+    fn joins(&mut self, level: &mut Level, mask: u32) {
+        let within = |c: &Conjunct| c.leaves & !mask == 0;
+        let connected = level.conjuncts.iter().any(|c| within(c) && c.leaves.count_ones() >= 2);
+        let mut left = (mask - 1) & mask;
+        while left != 0 {
+            let right = mask ^ left;
+            let spanning: Vec<&Conjunct> =
+                level.conjuncts.iter().filter(|c| within(c) && c.leaves & left != 0 && c.leaves & right != 0).collect();
+            let candidates = match (&level.best[left as usize], &level.best[right as usize]) {
+                (Some(l), Some(r)) if !connected || !spanning.is_empty() => {
+                    join_candidates(self.relations, l, r, &spanning, None)
+                }
+                _ => Vec::new(),
+            };
+            candidates.into_iter().for_each(|c| self.consider(level, mask, c));
+            left = (left - 1) & mask;
+        }
+    }
+
+    /// Record `candidate` as a way to read the leaves in `mask`, and keep it if it is the cheapest yet.
+    fn consider(&mut self, level: &mut Level, mask: u32, candidate: Candidate) {
+        let mask = mask as usize;
+        let better = level.best[mask].as_ref().is_none_or(|best| candidate.cost < best.cost);
+        if let Some(trace) = self.trace.as_deref_mut() {
+            let tables = self.relations.iter().enumerate().filter(|(i, _)| candidate.tables & 1 << i != 0);
+            trace.push(Alternative {
+                tables: tables.map(|(_, r)| r.alias.clone()).collect(),
+                plan: candidate.plan.clone(),
+                rows: candidate.rows,
+                cost: candidate.cost,
+                chosen: false,
+            });
+            if better {
+                level.traced[mask] = Some(trace.len() - 1);
+            }
+        }
+        if self.order.met_by(&candidate.order) && level.ordered[mask].as_ref().is_none_or(|o| candidate.cost < o.cost) {
+            level.ordered[mask] = Some(candidate.clone());
+        }
+        if better {
+            level.best[mask] = Some(candidate);
+        }
+    }
+}
+
+/// The joins of `l` and `r` on the conjuncts `on`, inner unless `kind` says otherwise: a block nested
+/// loop, and a hash join if some conjunct equates the two sides. A nested loop runs its right input once
+/// per block of left rows, and a hash join whose right input outgrows the memory budget writes both inputs
+/// out and reads them back. Semi and anti joins output only the left row's columns, but their conjuncts
+/// read both rows'.
+fn join_candidates(
+    relations: &[Relation],
+    l: &Candidate,
+    r: &Candidate,
+    on: &[&Conjunct],
+    kind: Option<Ordered>,
+) -> Vec<Candidate> {
+    let tables = l.tables | r.tables;
+    let layout = [l.layout.as_slice(), &r.layout].concat();
+    let selectivity: f64 = on.iter().map(|c| c.selectivity).product();
+    let pairs = (l.rows * r.rows * selectivity).max(1.0);
+    let (join, rows, output) = match kind {
+        None => (JoinType::Inner, pairs, layout.clone()),
+        Some(Ordered::Left) => (JoinType::Left { right_columns: r.layout.len() }, pairs.max(l.rows), layout.clone()),
+        Some(Ordered::Semi) => (JoinType::Semi, pairs.min(l.rows), l.layout.clone()),
+        Some(Ordered::Anti) => (JoinType::Anti, (l.rows - pairs).max(1.0), l.layout.clone()),
+    };
+    let memory = exec::DEFAULT_MEMORY_BYTES as f64;
+    let bytes = |c: &Candidate| c.rows * c.layout.len() as f64 * cost::COLUMN_BYTES;
+
+    let mut candidates = Vec::new();
+    let mut predicate = conjoin(on.iter().map(|c| c.expr.clone()));
+    predicate.iter_mut().for_each(|e| remap(e, &layout));
+    let (left_plan, right_plan) = (Box::new(l.plan.clone()), Box::new(r.plan.clone()));
+    let blocks = (bytes(l) / memory).ceil().max(1.0);
+    let cost = l.cost + blocks * (r.cost + r.rows * cost::CPU_ROW) + l.rows * r.rows * cost::CPU_ROW;
+    let plan = Plan::NestedLoopJoin { left: left_plan.clone(), right: right_plan.clone(), predicate, join };
+    candidates.push(Candidate::new(plan, tables, output.clone(), rows, cost, &[l, r]));
+
+    let (mut left_keys, mut right_keys, mut residual) = (Vec::new(), Vec::new(), Vec::new());
+    for conjunct in on {
+        let sides = match &conjunct.expr {
+            Expr::Binary { op: BinaryOp::Eq, left: a, right: b } => {
+                let (ta, tb) = (tables_of(relations, a), tables_of(relations, b));
+                let within = |t: u32, side: u32| t != 0 && t & !side == 0;
+                if within(ta, l.tables) && within(tb, r.tables) {
+                    Some((a, b))
+                } else if within(tb, l.tables) && within(ta, r.tables) {
+                    Some((b, a))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        match sides {
+            Some((a, b)) => {
+                let (mut a, mut b) = ((**a).clone(), (**b).clone());
+                remap(&mut a, &l.layout);
+                remap(&mut b, &r.layout);
+                left_keys.push(a);
+                right_keys.push(b);
+            }
+            None => residual.push(conjunct.expr.clone()),
+        }
+    }
+    if !left_keys.is_empty() {
+        let mut residual = conjoin(residual.into_iter());
+        residual.iter_mut().for_each(|e| remap(e, &layout));
+        let spill = if bytes(r) > memory { (l.rows + r.rows) * cost::SPILL_ROW } else { 0.0 };
+        let hashing = r.rows * cost::HASH_BUILD + l.rows * cost::HASH_PROBE + spill;
+        let cost = l.cost + r.cost + hashing + rows * cost::CPU_ROW;
+        let plan = Plan::HashJoin { left: left_plan, right: right_plan, left_keys, right_keys, residual, join };
+        candidates.push(Candidate::new(plan, tables, output, rows, cost, &[l, r]));
+    }
+    candidates
+}
+
+/// The tables whose columns `expr` reads.
+pub(super) fn tables_of(relations: &[Relation], expr: &Expr) -> u32 {
+    let mut tables = 0;
+    expr.for_each_column(&mut |column| {
+        let relation = relations.iter().rposition(|r| r.offset <= column).expect("column of no table");
+        tables |= 1 << relation;
+    });
+    tables
+}
+
+/// The expression `expr` says the key part equals, if it is `key = constant` or `constant = key`.
+fn fixed_value<'a>(expr: &'a Expr, column: usize, path: Option<&JsonPath>, collation: Collation) -> Option<&'a Expr> {
+    compared(expr, column, path, collation).filter(|(op, _)| *op == BinaryOp::Eq).map(|(_, value)| value)
+}
+
+/// The first bound on each side that the conjuncts of `local` other than those at `used` put on the key part,
+/// as `bounds` finds them, and the positions of the conjuncts giving them.
+fn range(
+    local: &[&Conjunct],
+    used: &[usize],
+    column: usize,
+    path: Option<&JsonPath>,
+    collation: Collation,
+) -> (Bound<Expr>, Bound<Expr>, Vec<usize>) {
+    let (mut low, mut high, mut ranged) = (Bound::Unbounded, Bound::Unbounded, Vec::new());
+    for (i, c) in local.iter().enumerate().filter(|(i, _)| !used.contains(i)) {
+        let Some(bounds) = bounds(&c.expr, column, path, collation) else { continue };
+        let mut narrows = false;
+        for (op, value) in bounds {
+            let side = if matches!(op, BinaryOp::Lt | BinaryOp::LtEq) { &mut high } else { &mut low };
+            if let Bound::Unbounded = side {
+                let value = value.clone();
+                let inclusive = matches!(op, BinaryOp::LtEq | BinaryOp::GtEq);
+                *side = if inclusive { Bound::Included(value) } else { Bound::Excluded(value) };
+                narrows = true;
+            }
+        }
+        if narrows {
+            ranged.push(i);
+        }
+    }
+    (low, high, ranged)
+}
+
+/// The bounds `expr` puts on the key part, each as the comparison of the key with a constant, if it is
+/// such a comparison by `<`, `<=`, `>` or `>=` or the `AND` of them, as `BETWEEN` is.
+fn bounds<'a>(
+    expr: &'a Expr,
+    column: usize,
+    path: Option<&JsonPath>,
+    collation: Collation,
+) -> Option<Vec<(BinaryOp, &'a Expr)>> {
+    if let Expr::Binary { op: BinaryOp::And, left, right } = expr {
+        let left = bounds(left, column, path, collation)?;
+        return Some([left, bounds(right, column, path, collation)?].concat())
+    }
+    let (op, value) = compared(expr, column, path, collation)?;
+    matches!(op, BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq).then(|| vec![(op, value)])
+}
+
+/// The comparison `expr` makes of the key part with a constant, as the operator between the key and the
+/// constant, if it is `key op constant` or `constant op key`. The key is `column` or, with a `path`, that
+/// path into it. Under a `collation` other than binary both sides must compare by their sort keys under
+/// it, which the index keeps.
+fn compared<'a>(
+    expr: &'a Expr,
+    column: usize,
+    path: Option<&JsonPath>,
+    collation: Collation,
+) -> Option<(BinaryOp, &'a Expr)> {
+    let Expr::Binary { op, left, right } = expr else { return None };
+    let uncollated = |e: &'a Expr| match (collation, e) {
+        (Collation::Binary, e) => Some(e),
+        (_, Expr::Collate { expr, collation: c }) if *c == collation => Some(&**expr),
+        _ => None,
+    };
+    let (left, right) = (uncollated(left)?, uncollated(right)?);
+    let is_key = |e: &Expr| match path {
+        None => matches!(e, Expr::Column(c) if *c == column),
+        Some(path) => e.json_path().is_some_and(|(c, p)| c == column && p == *path),
+    };
+    match (left, right) {
+        (key, value) if is_key(key) && cost::is_constant(value) => Some((*op, value)),
+        (value, key) if is_key(key) && cost::is_constant(value) => Some((cost::flip(*op)?, value)),
+        _ => None,
+    }
+}
+
+/// Renumber the global columns `expr` reads to their positions in `layout`.
+pub(super) fn remap(expr: &mut Expr, layout: &[usize]) {
+    expr.map_columns(&|column| layout.iter().position(|&c| c == column).expect("column outside the layout"))
+}
+
+pub(super) fn conjoin(exprs: impl Iterator<Item = Expr>) -> Option<Expr> {
+    exprs.reduce(|left, right| Expr::Binary { op: BinaryOp::And, left: Box::new(left), right: Box::new(right) })
+}
@@ -0,0 +1,645 @@
+//! A server speaking RESP2, the Redis protocol, so that Redis clients can keep keys and values in a
+//! keyspace of a connection.
+//!
+//! It answers `GET`, `SET` with its `EX`, `PX`, `NX`, `XX` and `KEEPTTL` options, `DEL`, `EXPIRE`, `TTL`,
+//! `SCAN` with `MATCH` and `COUNT`, `PING` and `QUIT`, each sent as an array of bulk strings or inline as
+//! words on a line. Clients are read the way `pgwire` reads them, by a thread each, while the thread
+//! serving the connection runs their commands one at a time, committing after each command that writes, so
+//! a reply to a write means it is durable.
+//!
+//! Every value is stored behind when it expires, as 8 big-endian bytes counting milliseconds since the Unix
+//! epoch, or 0 for never. A key past its expiry reads as missing, and is deleted when a command comes
+//! across it. `SCAN` goes through the keys in order, and its cursor, other than 0, names where a scan the
+//! client's session started left off, so a scan returns every key that was there throughout it; a cursor
+//! lasts as long as the session, or until the scan is finished.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::ops::Bound;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::connection::{Connection, ConnectionStorage};
+use crate::database::DatabaseError;
+use crate::kv::{Keyspace, KvError};
+
+/// The longest bulk string a client may send, as in Redis.
+const MAX_BULK: usize = 512 << 20;
+/// The most arguments a command may have.
+const MAX_ARGUMENTS: usize = 1 << 20;
+/// The keys `SCAN` looks at when not given a `COUNT`.
+const SCAN_COUNT: usize = 10;
+/// The most scans a session may have unfinished; starting another forgets the oldest.
+const MAX_CURSORS: usize = 1024;
+
+/// The commands the server answers.
+const COMMANDS: [&str; 8] = ["get", "set", "del", "expire", "ttl", "scan", "ping", "quit"];
+
+#[derive(Debug)]
+pub enum RespError {
+    /// Accepting a client failed.
+    Io(io::Error),
+    Database(DatabaseError),
+}
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RespError::Io(e) => write!(f, "{e}"),
+            RespError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+impl From<io::Error> for RespError {
+    fn from(e: io::Error) -> Self {
+        RespError::Io(e)
+    }
+}
+impl From<DatabaseError> for RespError {
+    fn from(e: DatabaseError) -> Self {
+        RespError::Database(e)
+    }
+}
+
+/// Serve the clients `listener` accepts from the keyspace called `keyspace`, making it if there is none,
+/// until accepting one fails.
+pub fn serve(connection: &mut Connection, keyspace: &str, listener: TcpListener) -> Result<(), RespError> {
+    serve_clients(connection, keyspace, listener, usize::MAX)
+}
+
+/// Serve the first `limit` clients `listener` accepts, returning once they have all gone.
+fn serve_clients(
+    connection: &mut Connection,
+    keyspace: &str,
+    listener: TcpListener,
+    limit: usize,
+) -> Result<(), RespError> {
+    if connection.database().catalog().keyspace(keyspace).is_none() {
+        connection.create_keyspace(keyspace)?;
+    }
+    let (events, received) = mpsc::channel();
+    thread::spawn(move || {
+        for (id, stream) in listener.incoming().take(limit).enumerate() {
+            match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                Ok((output, stream)) => {
+                    if events.send(Event::Opened(id, output)).is_err() {
+                        return
+                    }
+                    let events = events.clone();
+                    thread::spawn(move || read_client(id, stream, events));
+                }
+                Err(e) => {
+                    let _ = events.send(Event::AcceptFailed(e));
+                    return
+                }
+            }
+        }
+    });
+    let mut sessions = HashMap::new();
+    for event in received {
+        match event {
+            Event::Opened(id, stream) => drop(sessions.insert(id, Session::new(stream))),
+            Event::Command(id, args) => {
+                let Some(session) = sessions.get_mut(&id) else { continue };
+                let mut kv = connection.keyspace(keyspace)?;
+                if !session.handle(connection, &mut kv, &args) {
+                    sessions.remove(&id);
+                }
+            }
+            Event::Closed(id, error) => {
+                if let (Some(mut session), Some(message)) = (sessions.remove(&id), error) {
+                    let _ = session.reply(&Reply::Error(format!("ERR Protocol error: {message}")));
+                }
+            }
+            Event::AcceptFailed(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// What a client's thread passes on to the thread serving the connection.
+enum Event {
+    /// A client connected, and is answered through this stream.
+    Opened(usize, TcpStream),
+    Command(usize, Vec<Vec<u8>>),
+    /// A client's stream ended, or failed, or the client broke the protocol in the way given.
+    Closed(usize, Option<String>),
+    AcceptFailed(io::Error),
+}
+
+fn read_client(id: usize, stream: TcpStream, events: Sender<Event>) {
+    let mut input = BufReader::new(stream);
+    let error = loop {
+        match read_command(&mut input) {
+            Ok(Some(args)) if args.is_empty() => {}
+            Ok(Some(args)) => {
+                if events.send(Event::Command(id, args)).is_err() {
+                    return
+                }
+            }
+            Ok(None) | Err(ReadError::Io) => break None,
+            Err(ReadError::Protocol(message)) => break Some(message),
+        }
+    };
+    let _ = events.send(Event::Closed(id, error));
+}
+
+enum ReadError {
+    Io,
+    Protocol(String),
+}
+impl From<io::Error> for ReadError {
+    fn from(_: io::Error) -> Self {
+        ReadError::Io
+    }
+}
+
+/// The arguments of the next command, or `None` if the stream ends before it.
+fn read_command(input: &mut impl BufRead) -> Result<Option<Vec<Vec<u8>>>, ReadError> {
+    let Some(line) = read_line(input)? else { return Ok(None) };
+    let Some(count) = line.strip_prefix(b"*") else {
+        let words = line.split(|b| b.is_ascii_whitespace()).filter(|word| !word.is_empty());
+        return Ok(Some(words.map(<[u8]>::to_vec).collect()))
+    };
+    let count = length(count, MAX_ARGUMENTS)?;
+    let mut args = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let line = read_line(input)?.ok_or(ReadError::Io)?;
+        let Some(len) = line.strip_prefix(b"$") else {
+            return Err(ReadError::Protocol(format!("expected '$', got '{}'", String::from_utf8_lossy(&line))))
+        };
+        let mut arg = vec![0; length(len, MAX_BULK)? + 2];
+        input.read_exact(&mut arg)?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(ReadError::Protocol("a bulk string longer than its length".to_string()))
+        }
+        arg.truncate(arg.len() - 2);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// A line, without its line break.
+fn read_line(input: &mut impl BufRead) -> Result<Option<Vec<u8>>, ReadError> {
+    let mut line = Vec::new();
+    if input.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None)
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(ReadError::Io)
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+/// A count from a header, which must be at most `max`; a negative one is none.
+fn length(digits: &[u8], max: usize) -> Result<usize, ReadError> {
+    let n: i64 = integer(digits).ok_or_else(|| ReadError::Protocol("invalid length".to_string()))?;
+    match usize::try_from(n) {
+        Ok(n) if n <= max => Ok(n),
+        Ok(_) => Err(ReadError::Protocol("too long".to_string())),
+        Err(_) => Ok(0),
+    }
+}
+
+fn integer(digits: &[u8]) -> Option<i64> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+impl Reply {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Reply::Status(status) => write!(out, "+{status}\r\n"),
+            Reply::Error(message) => write!(out, "-{}\r\n", message.replace(['\r', '\n'], " ")),
+            Reply::Integer(n) => write!(out, ":{n}\r\n"),
+            Reply::Bulk(None) => write!(out, "$-1\r\n"),
+            Reply::Bulk(Some(bytes)) => {
+                write!(out, "${}\r\n", bytes.len())?;
+                out.write_all(bytes)?;
+                out.write_all(b"\r\n")
+            }
+            Reply::Array(replies) => {
+                write!(out, "*{}\r\n", replies.len())?;
+                replies.iter().try_for_each(|reply| reply.write(out))
+            }
+        }
+    }
+}
+
+/// Why a command failed, as the client is told.
+enum CommandError {
+    Database(DatabaseError),
+    /// The command of this name was given too many arguments or too few.
+    Arguments(String),
+    Syntax,
+    NotAnInteger,
+    InvalidCursor,
+}
+impl From<DatabaseError> for CommandError {
+    fn from(e: DatabaseError) -> Self {
+        CommandError::Database(e)
+    }
+}
+impl From<KvError> for CommandError {
+    fn from(e: KvError) -> Self {
+        CommandError::Database(e.into())
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// A value, and when it expires, in milliseconds since the epoch, if ever.
+type Entry = (Option<u64>, Vec<u8>);
+
+fn unpack(stored: Vec<u8>) -> Result<Entry, KvError> {
+    let Some(expiry) = stored.get(..8) else { return Err(KvError::Corrupt) };
+    let expiry = u64::from_be_bytes(expiry.try_into().unwrap());
+    Ok(((expiry != 0).then_some(expiry), stored[8..].to_vec()))
+}
+
+fn pack(expiry: Option<u64>, value: &[u8]) -> Vec<u8> {
+    let mut stored = expiry.unwrap_or(0).to_be_bytes().to_vec();
+    stored.extend_from_slice(value);
+    stored
+}
+
+struct Session {
+    output: BufWriter<TcpStream>,
+    /// Where each unfinished scan left off: the last key it looked at.
+    cursors: HashMap<u64, Vec<u8>>,
+    next_cursor: u64,
+}
+impl Session {
+    fn new(stream: TcpStream) -> Session {
+        Session { output: BufWriter::new(stream), cursors: HashMap::new(), next_cursor: 1 }
+    }
+
+    /// Run the command `args` and reply to it, returning whether the session goes on after it.
+    fn handle(&mut self, connection: &Connection, kv: &mut Keyspace<ConnectionStorage>, args: &[Vec<u8>]) -> bool {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+        let reply = match self.command(connection, kv, &name, &args[1..]) {
+            Ok(reply) => reply,
+            Err(CommandError::Database(DatabaseError::Kv(KvError::KeyTooLong))) => {
+                Reply::Error("ERR key too long".to_string())
+            }
+            Err(CommandError::Database(e)) => Reply::Error(format!("ERR {e}")),
+            Err(CommandError::Arguments(name)) => {
+                Reply::Error(format!("ERR wrong number of arguments for '{name}' command"))
+            }
+            Err(CommandError::Syntax) => Reply::Error("ERR syntax error".to_string()),
+            Err(CommandError::NotAnInteger) => Reply::Error("ERR value is not an integer or out of range".to_string()),
+            Err(CommandError::InvalidCursor) => Reply::Error("ERR invalid cursor".to_string()),
+        };
+        self.reply(&reply).is_ok() && name != "quit"
+    }
+
+    fn command(
+        &mut self,
+        connection: &Connection,
+        kv: &mut Keyspace<ConnectionStorage>,
+        name: &str,
+        args: &[Vec<u8>],
+    ) -> Result<Reply, CommandError> {
+        match (name, args) {
+            ("ping", []) => Ok(Reply::Status("PONG")),
+            ("ping", [message]) => Ok(Reply::Bulk(Some(message.clone()))),
+            ("quit", []) => Ok(Reply::Status("OK")),
+            ("get", [key]) => Ok(Reply::Bulk(live(connection, kv, key)?.map(|(_, value)| value))),
+            ("set", [key, value, options @ ..]) => {
+                let written = set(kv, key, value, options)?;
+                connection.commit()?;
+                Ok(if written { Reply::Status("OK") } else { Reply::Bulk(None) })
+            }
+            ("del", [_, ..]) => {
+                let mut deleted = 0;
+                for key in args {
+                    let value = kv.delete(key)?.map(unpack).transpose()?;
+                    deleted += value.is_some_and(|(expiry, _)| !expired(expiry)) as i64;
+                }
+                connection.commit()?;
+                Ok(Reply::Integer(deleted))
+            }
+            ("expire", [key, seconds]) => {
+                let seconds = integer(seconds).ok_or(CommandError::NotAnInteger)?;
+                let Some((_, value)) = live(connection, kv, key)? else { return Ok(Reply::Integer(0)) };
+                if seconds <= 0 {
+                    kv.delete(key)?;
+                } else {
+                    let millis = seconds.checked_mul(1000).ok_or(CommandError::NotAnInteger)?;
+                    kv.put(key, &pack(Some(now_millis().saturating_add(millis as u64)), &value))?;
+                }
+                connection.commit()?;
+                Ok(Reply::Integer(1))
+            }
+            ("ttl", [key]) => Ok(Reply::Integer(match live(connection, kv, key)? {
+                None => -2,
+                Some((None, _)) => -1,
+                Some((Some(expiry), _)) => expiry.saturating_sub(now_millis()).div_ceil(1000) as i64,
+            })),
+            ("scan", [cursor, options @ ..]) => self.scan(connection, kv, cursor, options),
+            (name, _) if COMMANDS.contains(&name) => Err(CommandError::Arguments(name.to_string())),
+            (name, _) => Ok(Reply::Error(format!("ERR unknown command '{name}'"))),
+        }
+    }
+
+    fn scan(
+        &mut self,
+        connection: &Connection,
+        kv: &mut Keyspace<ConnectionStorage>,
+        cursor: &[u8],
+        mut options: &[Vec<u8>],
+    ) -> Result<Reply, CommandError> {
+        let (mut pattern, mut count) = (None, SCAN_COUNT);
+        while let [option, value, rest @ ..] = options {
+            match option.to_ascii_lowercase().as_slice() {
+                b"match" => pattern = Some(value.as_slice()),
+                b"count" => match integer(value) {
+                    Some(n) if n > 0 => count = n as usize,
+                    _ => return Err(CommandError::NotAnInteger),
+                },
+                _ => return Err(CommandError::Syntax),
+            }
+            options = rest;
+        }
+        if !options.is_empty() {
+            return Err(CommandError::Syntax)
+        }
+        let start = match integer(cursor).ok_or(CommandError::InvalidCursor)? {
+            0 => Bound::Unbounded,
+            cursor => Bound::Excluded(self.cursors.remove(&(cursor as u64)).ok_or(CommandError::InvalidCursor)?),
+        };
+        let (mut keys, mut stale, mut last) = (Vec::new(), Vec::new(), None);
+        for entry in kv.range((start, Bound::Unbounded)).take(count) {
+            let (key, stored) = entry?;
+            match unpack(stored)? {
+                (expiry, _) if expired(expiry) => stale.push(key.clone()),
+                _ if pattern.is_some_and(|pattern| !glob(pattern, &key)) => {}
+                _ => keys.push(Reply::Bulk(Some(key.clone()))),
+            }
+            last = Some(key);
+        }
+        let more = match &last {
+            Some(last) => kv.range((Bound::Excluded(last.clone()), Bound::Unbounded)).next().is_some(),
+            None => false,
+        };
+        if !stale.is_empty() {
+            stale.iter().try_for_each(|key| kv.delete(key).map(drop))?;
+            connection.commit()?;
+        }
+        let next = match last {
+            Some(last) if more => {
+                if self.cursors.len() >= MAX_CURSORS {
+                    let oldest = *self.cursors.keys().min().unwrap();
+                    self.cursors.remove(&oldest);
+                }
+                let cursor = self.next_cursor;
+                self.next_cursor += 1;
+                self.cursors.insert(cursor, last);
+                cursor
+            }
+            _ => 0,
+        };
+        Ok(Reply::Array(vec![Reply::Bulk(Some(next.to_string().into_bytes())), Reply::Array(keys)]))
+    }
+
+    fn reply(&mut self, reply: &Reply) -> io::Result<()> {
+        reply.write(&mut self.output)?;
+        self.output.flush()
+    }
+}
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Ends the client's thread too, if the client has not gone already.
+        let _ = self.output.flush();
+        let _ = self.output.get_ref().shutdown(Shutdown::Both);
+    }
+}
+
+fn expired(expiry: Option<u64>) -> bool {
+    expiry.is_some_and(|expiry| expiry <= now_millis())
+}
+
+/// When `key` expires, if ever, and its value, if it has one that has not expired; one that has is deleted.
+fn live(
+    connection: &Connection,
+    kv: &mut Keyspace<ConnectionStorage>,
+    key: &[u8],
+) -> Result<Option<Entry>, CommandError> {
+    match kv.get(key)?.map(unpack).transpose()? {
+        Some((expiry, _)) if expired(expiry) => {
+            kv.delete(key)?;
+            connection.commit()?;
+            Ok(None)
+        }
+        entry => Ok(entry),
+    }
+}
+
+/// Run `SET key value options`, returning whether it wrote the value: one with `NX` or `XX` does not when
+/// the key is there, or is not.
+fn set(
+    kv: &mut Keyspace<ConnectionStorage>,
+    key: &[u8],
+    value: &[u8],
+    options: &[Vec<u8>],
+) -> Result<bool, CommandError> {
+    let (mut expiry, mut keep_ttl, mut only) = (None, false, None);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let option = option.to_ascii_lowercase();
+        match option.as_slice() {
+            b"ex" | b"px" if expiry.is_none() && !keep_ttl => {
+                let n = integer(options.next().ok_or(CommandError::Syntax)?).ok_or(CommandError::NotAnInteger)?;
+                let millis = if option == b"ex" { n.checked_mul(1000) } else { Some(n) };
+                match millis {
+                    Some(millis) if millis > 0 => expiry = Some(now_millis().saturating_add(millis as u64)),
+                    _ => return Err(CommandError::Syntax),
+                }
+            }
+            b"keepttl" if expiry.is_none() => keep_ttl = true,
+            b"nx" | b"xx" if only.is_none() => only = Some(option == b"nx"),
+            _ => return Err(CommandError::Syntax),
+        }
+    }
+    let old = kv.get(key)?.map(unpack).transpose()?.filter(|(expiry, _)| !expired(*expiry));
+    match only {
+        Some(true) if old.is_some() => return Ok(false),
+        Some(false) if old.is_none() => return Ok(false),
+        _ => {}
+    }
+    if keep_ttl {
+        expiry = old.and_then(|(expiry, _)| expiry);
+    }
+    kv.put(key, &pack(expiry, value))?;
+    Ok(true)
+}
+
+/// Whether `key` matches the glob `pattern`, as `SCAN`'s `MATCH` takes it: `*` for any run of bytes, `?` for
+/// any one, `[...]` for one of a set, with ranges like `a-z` and `^` first for the bytes not in it, and `\` to
+/// take the byte after it as it is.
+fn glob(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern {
+        [] => key.is_empty(),
+        [b'*', rest @ ..] => (0..=key.len()).any(|i| glob(rest, &key[i..])),
+        [b'?', rest @ ..] => !key.is_empty() && glob(rest, &key[1..]),
+        [b'[', rest @ ..] => {
+            let Some((&first, key_rest)) = key.split_first() else { return false };
+            let (negated, mut set) = match rest {
+                [b'^', set @ ..] => (true, set),
+                set => (false, set),
+            };
+            let mut found = false;
+            loop {
+                match set {
+                    [] => return false,
+                    [b']', after @ ..] => {
+                        set = after;
+                        break
+                    }
+                    [b'\\', c, after @ ..] => {
+                        found |= *c == first;
+                        set = after;
+                    }
+                    [low, b'-', high, after @ ..] if *high != b']' => {
+                        found |= (*low.min(high)..=*low.max(high)).contains(&first);
+                        set = after;
+                    }
+                    [c, after @ ..] => {
+                        found |= *c == first;
+                        set = after;
+                    }
+                }
+            }
+            found != negated && glob(set, key_rest)
+        }
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => key.first() == Some(c) && glob(rest, &key[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::connection::Connection;
+
+    use super::{glob, serve_clients, RespError};
+
+    /// A client sending commands as arrays and reading replies as the lines they are written on.
+    struct Client {
+        stream: TcpStream,
+        input: BufReader<TcpStream>,
+    }
+    impl Client {
+        fn connect(address: std::net::SocketAddr) -> Client {
+            let stream = TcpStream::connect(address).unwrap();
+            Client { input: BufReader::new(stream.try_clone().unwrap()), stream }
+        }
+
+        fn send(&mut self, command: &[&str]) -> String {
+            let mut request = format!("*{}\r\n", command.len());
+            command.iter().for_each(|arg| request += &format!("${}\r\n{arg}\r\n", arg.len()));
+            self.stream.write_all(request.as_bytes()).unwrap();
+            self.reply()
+        }
+
+        /// The next reply, its lines joined by spaces.
+        fn reply(&mut self) -> String {
+            let mut line = String::new();
+            self.input.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_string();
+            match line.as_bytes()[0] {
+                b'*' => {
+                    let count: usize = line[1..].parse().unwrap();
+                    let items: Vec<String> = (0..count).map(|_| self.reply()).collect();
+                    format!("[{}]", items.join(" "))
+                }
+                b'$' if line != "$-1" => {
+                    let mut bulk = vec![0; line[1..].parse::<usize>().unwrap() + 2];
+                    self.input.read_exact(&mut bulk).unwrap();
+                    String::from_utf8_lossy(&bulk[..bulk.len() - 2]).into_owned()
+                }
+                _ => line,
+            }
+        }
+    }
+
+    #[test]
+    fn test_commands() -> Result<(), RespError> {
+        let mut connection = Connection::open_in_memory()?;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut client = Client::connect(address);
+            assert_eq!(client.send(&["PING"]), "+PONG");
+            assert_eq!(client.send(&["GET", "a"]), "$-1");
+            assert_eq!(client.send(&["SET", "a", "1"]), "+OK");
+            assert_eq!(client.send(&["set", "a", "2", "NX"]), "$-1");
+            assert_eq!(client.send(&["SET", "b", "2", "XX"]), "$-1");
+            assert_eq!(client.send(&["SET", "b", "two words", "EX", "100"]), "+OK");
+            assert_eq!(client.send(&["GET", "b"]), "two words");
+            assert_eq!(client.send(&["TTL", "b"]), ":100");
+            assert_eq!(client.send(&["TTL", "a"]), ":-1");
+            assert_eq!(client.send(&["SET", "a", "3", "EX", "x"]), "-ERR value is not an integer or out of range");
+            assert_eq!(client.send(&["SET", "a", "3", "EX"]), "-ERR syntax error");
+            assert_eq!(client.send(&["GET"]), "-ERR wrong number of arguments for 'get' command");
+            assert_eq!(client.send(&["NOPE"]), "-ERR unknown command 'nope'");
+
+            assert_eq!(client.send(&["SET", "c", "3", "PX", "1"]), "+OK");
+            thread::sleep(Duration::from_millis(5));
+            assert_eq!(client.send(&["GET", "c"]), "$-1");
+            assert_eq!(client.send(&["TTL", "c"]), ":-2");
+            assert_eq!(client.send(&["EXPIRE", "a", "0"]), ":1");
+            assert_eq!(client.send(&["EXPIRE", "a", "10"]), ":0");
+            assert_eq!(client.send(&["DEL", "a", "b", "c"]), ":1");
+
+            // Inline commands are words on a line.
+            client.stream.write_all(b"SET key:1 x\r\n").unwrap();
+            assert_eq!(client.reply(), "+OK");
+            for key in ["key:2", "key:3", "other", "key:4"] {
+                assert_eq!(client.send(&["SET", key, "x"]), "+OK");
+            }
+            assert_eq!(client.send(&["SCAN", "0", "MATCH", "key:[2-4]", "COUNT", "3"]), "[1 [key:2 key:3]]");
+            assert_eq!(client.send(&["SCAN", "1", "MATCH", "key:[2-4]", "COUNT", "3"]), "[0 [key:4]]");
+            assert_eq!(client.send(&["SCAN", "1"]), "-ERR invalid cursor");
+            assert_eq!(client.send(&["QUIT"]), "+OK");
+
+            // What the first client wrote is there for the next.
+            let mut other = Client::connect(address);
+            assert_eq!(other.send(&["GET", "other"]), "x");
+            other.stream.write_all(b"*1\r\n#\r\n").unwrap();
+            assert_eq!(other.reply(), "-ERR Protocol error: expected '$', got '#'");
+        });
+        serve_clients(&mut connection, "redis", listener, 2)?;
+        client.join().unwrap();
+        let kv = connection.keyspace("redis")?;
+        assert_eq!(kv.iter().count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob(b"*", b""));
+        assert!(glob(b"h?llo*", b"hallo world"));
+        assert!(!glob(b"h?llo", b"hllo"));
+        assert!(glob(b"h[^e]llo", b"hallo"));
+        assert!(!glob(b"h[^e]llo", b"hello"));
+        assert!(glob(b"h[a-b\\]]llo", b"h]llo"));
+        assert!(glob(b"\\*x", b"*x"));
+        assert!(!glob(b"\\*x", b"ax"));
+    }
+}
@@ -0,0 +1,485 @@
+//! R-tree over the page store, for two-dimensional bounding boxes.
+//!
+//! Every node is one page holding a fixed-size array of (rectangle, u64) entries. In a leaf the u64 is the
+//! caller's id for the object, typically an encoded record id; in an inner node it is a child page and the
+//! rectangle bounds everything beneath it. Inserts descend by least enlargement and split overfull nodes
+//! with Guttman's quadratic split. Deletes remove nodes that become empty and shrink the bounding boxes
+//! above them, but do not merge underfull nodes. A meta page records the root.
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+
+const META_MAGIC: u32 = 0x5254_4d41;
+const ROOT: usize = 8;
+
+const NODE_MAGIC: u32 = 0x5254_4e44;
+const LEVEL: usize = 4;
+const COUNT: usize = 6;
+const ENTRIES: usize = 16;
+const ENTRY_LEN: usize = 40;
+const CAPACITY: usize = (PAGE_SIZE - ENTRIES) / ENTRY_LEN;
+const MIN_FILL: usize = CAPACITY * 2 / 5;
+
+/// An axis-aligned rectangle. Points are rectangles with no extent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+impl Rect {
+    pub fn new(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Rect {
+        Rect { min_x, min_y, max_x, max_y }
+    }
+
+    pub fn point(x: f64, y: f64) -> Rect {
+        Rect::new(x, y, x, y)
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.min_x <= other.max_x && other.min_x <= self.max_x && self.min_y <= other.max_y && other.min_y <= self.max_y
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        self.min_x <= other.min_x && other.max_x <= self.max_x && self.min_y <= other.min_y && other.max_y <= self.max_y
+    }
+
+    fn area(&self) -> f64 {
+        (self.max_x - self.min_x) * (self.max_y - self.min_y)
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        Rect::new(self.min_x.min(other.min_x), self.min_y.min(other.min_y), self.max_x.max(other.max_x), self.max_y.max(other.max_y))
+    }
+
+    fn enlargement(&self, other: &Rect) -> f64 {
+        self.union(other).area() - self.area()
+    }
+
+    /// Squared distance from `(x, y)` to the nearest point of the rectangle.
+    fn distance2(&self, x: f64, y: f64) -> f64 {
+        let dx = (self.min_x - x).max(0.0).max(x - self.max_x);
+        let dy = (self.min_y - y).max(0.0).max(y - self.max_y);
+        dx * dx + dy * dy
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RTreeError {
+    Page(PageError),
+}
+impl From<PageError> for RTreeError {
+    fn from(e: PageError) -> Self {
+        RTreeError::Page(e)
+    }
+}
+
+type Entry = (Rect, u64);
+
+struct Node {
+    level: u16,
+    entries: Vec<Entry>,
+}
+impl Node {
+    fn bounds(&self) -> Rect {
+        let first = self.entries[0].0;
+        self.entries[1..].iter().fold(first, |acc, (r, _)| acc.union(r))
+    }
+}
+
+pub struct RTree<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    meta: PageId,
+}
+impl<'store, S: Storage> RTree<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator) -> Result<RTree<'store, S>, RTreeError> {
+        let meta = allocator.allocate(store)?.id();
+        let root = allocator.allocate(store)?.id();
+        let tree = RTree { store, allocator, meta };
+        tree.write_node(root, &Node { level: 0, entries: Vec::new() })?;
+        let page = store.pin_page(&meta)?;
+        let mut buf = page.try_write()?;
+        write_u32(&mut *buf, 0, META_MAGIC);
+        write_u64(&mut *buf, ROOT, root.offset() as u64);
+        drop(buf);
+        Ok(tree)
+    }
+
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, meta: PageId) -> Result<RTree<'store, S>, RTreeError> {
+        let page = store.pin_page(&meta)?;
+        if read_u32(&*page.try_read()?, 0) != META_MAGIC {
+            return Err(RTreeError::Page(PageError::WrongPageType))
+        }
+        Ok(RTree { store, allocator, meta })
+    }
+
+    /// The page to pass to `open` to find this tree again.
+    pub fn meta_page(&self) -> PageId {
+        self.meta
+    }
+
+    pub fn insert(&self, rect: Rect, id: u64) -> Result<(), RTreeError> {
+        let mut page = self.root()?;
+        let mut node = self.read_node(page)?;
+        let mut path = Vec::new();
+        while node.level > 0 {
+            let slot = choose_subtree(&node, &rect);
+            let child = PageId::new(node.entries[slot].1 as usize);
+            path.push((page, node, slot));
+            page = child;
+            node = self.read_node(page)?;
+        }
+        node.entries.push((rect, id));
+
+        // Walk back up, splitting overfull nodes and widening bounding boxes.
+        loop {
+            let split = if node.entries.len() > CAPACITY {
+                let (left, right) = quadratic_split(std::mem::take(&mut node.entries));
+                node.entries = left;
+                let sibling = Node { level: node.level, entries: right };
+                let sibling_page = self.allocator.allocate(self.store)?.id();
+                self.write_node(sibling_page, &sibling)?;
+                Some((sibling.bounds(), sibling_page.offset() as u64))
+            } else {
+                None
+            };
+            self.write_node(page, &node)?;
+            let Some((parent_page, mut parent, slot)) = path.pop() else {
+                if let Some(sibling) = split {
+                    let new_root = self.allocator.allocate(self.store)?.id();
+                    let entries = vec![(node.bounds(), page.offset() as u64), sibling];
+                    self.write_node(new_root, &Node { level: node.level + 1, entries })?;
+                    self.set_root(new_root)?;
+                }
+                return Ok(())
+            };
+            parent.entries[slot].0 = node.bounds();
+            parent.entries.extend(split);
+            page = parent_page;
+            node = parent;
+        }
+    }
+
+    /// Remove the entry with exactly this rectangle and id, returning whether it was present.
+    pub fn delete(&self, rect: Rect, id: u64) -> Result<bool, RTreeError> {
+        let root = self.root()?;
+        let mut path = Vec::new();
+        if !self.find_leaf(root, &rect, id, &mut path)? {
+            return Ok(false)
+        }
+        let (mut page, mut node, slot) = path.pop().expect("find_leaf records the leaf");
+        node.entries.remove(slot);
+
+        while let Some((parent_page, mut parent, slot)) = path.pop() {
+            if node.entries.is_empty() {
+                parent.entries.remove(slot);
+                self.allocator.free(self.store, page)?;
+            } else {
+                parent.entries[slot].0 = node.bounds();
+                self.write_node(page, &node)?;
+            }
+            page = parent_page;
+            node = parent;
+        }
+
+        // `node` is now the root. Drop levels that have a single child, or no children at all.
+        loop {
+            match node.entries.len() {
+                0 if node.level > 0 => node.level = 0,
+                1 if node.level > 0 => {
+                    let child = PageId::new(node.entries[0].1 as usize);
+                    self.allocator.free(self.store, page)?;
+                    self.set_root(child)?;
+                    page = child;
+                    node = self.read_node(child)?;
+                    continue
+                }
+                _ => {}
+            }
+            self.write_node(page, &node)?;
+            return Ok(true)
+        }
+    }
+
+    /// Every entry whose rectangle intersects `window`.
+    pub fn search(&self, window: &Rect) -> Result<Vec<Entry>, RTreeError> {
+        let mut found = Vec::new();
+        let mut stack = vec![self.root()?];
+        while let Some(page) = stack.pop() {
+            let node = self.read_node(page)?;
+            for (rect, value) in node.entries {
+                if rect.intersects(window) {
+                    match node.level {
+                        0 => found.push((rect, value)),
+                        _ => stack.push(PageId::new(value as usize)),
+                    }
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// The `k` entries closest to `(x, y)`, nearest first. Distance is measured to the nearest point of each
+    /// entry's rectangle.
+    pub fn nearest(&self, x: f64, y: f64, k: usize) -> Result<Vec<Entry>, RTreeError> {
+        let mut found = Vec::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate { distance2: 0.0, rect: Rect::point(x, y), value: self.root()?.offset() as u64, is_node: true });
+        while let Some(candidate) = queue.pop() {
+            if found.len() == k {
+                break
+            }
+            if !candidate.is_node {
+                found.push((candidate.rect, candidate.value));
+                continue
+            }
+            let node = self.read_node(PageId::new(candidate.value as usize))?;
+            for (rect, value) in node.entries {
+                queue.push(Candidate { distance2: rect.distance2(x, y), rect, value, is_node: node.level > 0 });
+            }
+        }
+        Ok(found)
+    }
+
+    fn root(&self) -> Result<PageId, RTreeError> {
+        let page = self.store.pin_page(&self.meta)?;
+        let root = read_u64(&*page.try_read()?, ROOT);
+        Ok(PageId::new(root as usize))
+    }
+
+    fn set_root(&self, root: PageId) -> Result<(), RTreeError> {
+        let page = self.store.pin_page(&self.meta)?;
+        write_u64(&mut *page.try_write()?, ROOT, root.offset() as u64);
+        Ok(())
+    }
+
+    /// Depth-first search for the leaf holding `(rect, id)`, recording the nodes and slots passed through.
+    fn find_leaf(&self, page: PageId, rect: &Rect, id: u64, path: &mut Vec<(PageId, Node, usize)>) -> Result<bool, RTreeError> {
+        let node = self.read_node(page)?;
+        if node.level == 0 {
+            if let Some(slot) = node.entries.iter().position(|(r, v)| r == rect && *v == id) {
+                path.push((page, node, slot));
+                return Ok(true)
+            }
+            return Ok(false)
+        }
+        let children: Vec<(usize, PageId)> = node.entries.iter().enumerate()
+            .filter(|(_, (r, _))| r.contains(rect))
+            .map(|(slot, (_, child))| (slot, PageId::new(*child as usize)))
+            .collect();
+        path.push((page, node, 0));
+        for (slot, child) in children {
+            path.last_mut().unwrap().2 = slot;
+            if self.find_leaf(child, rect, id, path)? {
+                return Ok(true)
+            }
+        }
+        path.pop();
+        Ok(false)
+    }
+
+    fn read_node(&self, page: PageId) -> Result<Node, RTreeError> {
+        let pinned = self.store.pin_page(&page)?;
+        let buf = pinned.try_read()?;
+        if read_u32(&*buf, 0) != NODE_MAGIC {
+            return Err(RTreeError::Page(PageError::WrongPageType))
+        }
+        let entries = (0..read_u16(&*buf, COUNT) as usize)
+            .map(|i| {
+                let at = ENTRIES + i * ENTRY_LEN;
+                let f = |n: usize| f64::from_bits(read_u64(&*buf, at + n * 8));
+                (Rect::new(f(0), f(1), f(2), f(3)), read_u64(&*buf, at + 32))
+            })
+            .collect();
+        Ok(Node { level: read_u16(&*buf, LEVEL), entries })
+    }
+
+    fn write_node(&self, page: PageId, node: &Node) -> Result<(), RTreeError> {
+        let pinned = self.store.pin_page(&page)?;
+        let mut buf = pinned.try_write()?;
+        write_u32(&mut *buf, 0, NODE_MAGIC);
+        write_u16(&mut *buf, LEVEL, node.level);
+        write_u16(&mut *buf, COUNT, node.entries.len() as u16);
+        for (i, (rect, value)) in node.entries.iter().enumerate() {
+            let at = ENTRIES + i * ENTRY_LEN;
+            for (n, f) in [rect.min_x, rect.min_y, rect.max_x, rect.max_y].into_iter().enumerate() {
+                write_u64(&mut *buf, at + n * 8, f.to_bits());
+            }
+            write_u64(&mut *buf, at + 32, *value);
+        }
+        Ok(())
+    }
+}
+
+/// The entry of an inner node needing the least enlargement to cover `rect`, breaking ties by area.
+fn choose_subtree(node: &Node, rect: &Rect) -> usize {
+    let cost = |r: &Rect| (r.enlargement(rect), r.area());
+    (0..node.entries.len())
+        .min_by(|a, b| {
+            let (ea, aa) = cost(&node.entries[*a].0);
+            let (eb, ab) = cost(&node.entries[*b].0);
+            ea.total_cmp(&eb).then(aa.total_cmp(&ab))
+        })
+        .expect("inner nodes are never empty")
+}
+
+/// Guttman's quadratic split: seed two groups with the pair that would waste the most area together,
+/// then hand out the rest one at a time, most decisive entry first.
+fn quadratic_split(mut entries: Vec<Entry>) -> (Vec<Entry>, Vec<Entry>) {
+    let mut seeds = (0, 1);
+    let mut worst = f64::NEG_INFINITY;
+    for i in 0..entries.len() {
+        for j in i + 1..entries.len() {
+            let (a, b) = (entries[i].0, entries[j].0);
+            let waste = a.union(&b).area() - a.area() - b.area();
+            if waste > worst {
+                worst = waste;
+                seeds = (i, j);
+            }
+        }
+    }
+    let second = entries.swap_remove(seeds.1);
+    let first = entries.swap_remove(seeds.0);
+    let (mut left, mut right) = (vec![first], vec![second]);
+    let (mut left_box, mut right_box) = (first.0, second.0);
+
+    while !entries.is_empty() {
+        if left.len() + entries.len() == MIN_FILL {
+            left.append(&mut entries);
+            break
+        }
+        if right.len() + entries.len() == MIN_FILL {
+            right.append(&mut entries);
+            break
+        }
+        let (pick, _) = entries.iter().enumerate()
+            .map(|(i, (r, _))| (i, (left_box.enlargement(r) - right_box.enlargement(r)).abs()))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        let entry = entries.swap_remove(pick);
+        let (grow_left, grow_right) = (left_box.enlargement(&entry.0), right_box.enlargement(&entry.0));
+        let to_left = match grow_left.total_cmp(&grow_right) {
+            Ordering::Less => true,
+            Ordering::Greater => false,
+            Ordering::Equal => (left_box.area(), left.len()) <= (right_box.area(), right.len()),
+        };
+        if to_left {
+            left_box = left_box.union(&entry.0);
+            left.push(entry);
+        } else {
+            right_box = right_box.union(&entry.0);
+            right.push(entry);
+        }
+    }
+    (left, right)
+}
+
+/// A node or entry waiting in the nearest-neighbour queue, ordered so the closest pops first.
+struct Candidate {
+    distance2: f64,
+    rect: Rect,
+    value: u64,
+    is_node: bool,
+}
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed for the max-heap; at equal distance, entries come out before nodes.
+        other.distance2.total_cmp(&self.distance2).then(other.is_node.cmp(&self.is_node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+
+    use super::{RTree, RTreeError, Rect};
+
+    fn boxes() -> Vec<(Rect, u64)> {
+        let mut x: u64 = 42;
+        let mut next = || {
+            x = x.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            (x >> 33) as f64 / (1u64 << 31) as f64 * 1000.0
+        };
+        (0..3000).map(|i| {
+            let (cx, cy, w, h) = (next(), next(), next() / 100.0, next() / 100.0);
+            (Rect::new(cx, cy, cx + w, cy + h), i)
+        }).collect()
+    }
+
+    fn sorted(entries: Vec<(Rect, u64)>) -> Vec<u64> {
+        let mut ids: Vec<u64> = entries.into_iter().map(|(_, id)| id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_window_and_nearest_match_brute_force() -> Result<(), RTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = RTree::create(&store, allocator)?;
+        let all = boxes();
+        for (rect, id) in &all {
+            tree.insert(*rect, *id)?;
+        }
+
+        let window = Rect::new(200.0, 300.0, 450.0, 380.0);
+        let expected: Vec<_> = all.iter().filter(|(r, _)| r.intersects(&window)).copied().collect();
+        assert!(!expected.is_empty());
+        assert_eq!(sorted(tree.search(&window)?), sorted(expected));
+
+        let nearest = tree.nearest(500.0, 500.0, 10)?;
+        let mut by_distance: Vec<_> = all.iter().map(|(r, id)| (r.distance2(500.0, 500.0), *id)).collect();
+        by_distance.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let distances: Vec<f64> = nearest.iter().map(|(r, _)| r.distance2(500.0, 500.0)).collect();
+        assert_eq!(distances, by_distance[..10].iter().map(|(d, _)| *d).collect::<Vec<_>>());
+
+        let tree = RTree::open(&store, allocator, tree.meta_page())?;
+        assert_eq!(tree.search(&Rect::new(-1.0, -1.0, 2000.0, 2000.0))?.len(), all.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete() -> Result<(), RTreeError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let tree = RTree::create(&store, allocator)?;
+        let all = boxes();
+        for (rect, id) in &all {
+            tree.insert(*rect, *id)?;
+        }
+        for (rect, id) in all.iter().filter(|(_, id)| id % 2 == 0) {
+            assert!(tree.delete(*rect, *id)?);
+        }
+        assert!(!tree.delete(all[0].0, all[0].1)?);
+
+        let everything = Rect::new(-1.0, -1.0, 2000.0, 2000.0);
+        let odd: Vec<_> = all.iter().filter(|(_, id)| id % 2 == 1).copied().collect();
+        assert_eq!(sorted(tree.search(&everything)?), sorted(odd.clone()));
+        for (rect, id) in &odd {
+            assert!(tree.delete(*rect, *id)?);
+        }
+        assert!(tree.search(&everything)?.is_empty());
+        tree.insert(Rect::point(1.0, 1.0), 7)?;
+        assert_eq!(tree.nearest(0.0, 0.0, 5)?, vec![(Rect::point(1.0, 1.0), 7)]);
+        Ok(())
+    }
+}
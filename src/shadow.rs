@@ -0,0 +1,417 @@
+//! Shadow paging: a copy-on-write durability strategy that needs no log.
+//!
+//! `ShadowStorage` wraps another `Storage` and maps logical pages onto physical ones. A page is never
+//! overwritten in place once it is part of a committed generation; the first write after a commit goes to a
+//! fresh physical page instead. `sync` writes the new page table and then switches the root by writing one
+//! of two alternating meta pages, so a crash at any point leaves the previous generation intact and opening
+//! the store needs no recovery beyond picking the newest valid meta page.
+//!
+//! Until a generation commits, `rollback` can discard it: the page table goes back to the committed one,
+//! and the pages the generation wrote are free again.
+//!
+//! Choosing it is a runtime decision: hand a `ShadowStorage` to `PageStore::new` in place of the raw storage.
+use std::collections::{HashMap, HashSet};
+
+use crate::bytes::{crc32, read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{Data, PageId, PAGE_SIZE};
+use crate::storage::{Storage, StorageError};
+
+const META_PAGES: [usize; 2] = [0, 1];
+const FIRST_DATA_PAGE: usize = 2;
+const MAGIC: u32 = 0x5053_4844;
+const NO_PAGE: u64 = u64::MAX;
+
+const META_LEN: usize = 28;
+const TABLE_HEADER_LEN: usize = 10;
+const TABLE_ENTRY_LEN: usize = 16;
+const TABLE_ENTRIES_PER_PAGE: usize = (PAGE_SIZE - TABLE_HEADER_LEN) / TABLE_ENTRY_LEN;
+
+pub struct ShadowStorage<S: Storage> {
+    inner: S,
+    generation: u64,
+    next_physical: usize,
+    /// Logical to physical mapping as seen by readers, including uncommitted writes.
+    table: HashMap<PageId, PageId>,
+    /// Physical pages holding the committed page table.
+    table_pages: Vec<PageId>,
+    /// Physical pages first written in the current generation; these may be overwritten in place.
+    fresh: HashSet<PageId>,
+    /// Physical pages superseded in the current generation, reusable once it commits.
+    pending_free: Vec<PageId>,
+    /// The physical page each logical page the current generation wrote had in the committed one, or
+    /// `None` for a page it created, for `rollback` to put back.
+    undo: HashMap<PageId, Option<PageId>>,
+    free: Vec<PageId>,
+}
+impl<S: Storage> ShadowStorage<S> {
+    /// Open the newest committed generation found in `inner`, initializing empty storage if needed.
+    pub fn open(inner: S) -> Result<ShadowStorage<S>, StorageError> {
+        let mut metas = Vec::with_capacity(2);
+        let mut initialized = true;
+        for offset in META_PAGES {
+            let mut buf = [0u8; PAGE_SIZE];
+            match inner.load_page(&mut buf, &PageId::new(offset)) {
+                Ok(()) => metas.push(Meta::decode(&buf)),
+                Err(StorageError::NotFound) => initialized = false,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut store = ShadowStorage {
+            inner,
+            generation: 0,
+            next_physical: FIRST_DATA_PAGE,
+            table: HashMap::new(),
+            table_pages: Vec::new(),
+            fresh: HashSet::new(),
+            pending_free: Vec::new(),
+            undo: HashMap::new(),
+            free: Vec::new(),
+        };
+
+        if !initialized {
+            for offset in META_PAGES {
+                match store.inner.create_page(&PageId::new(offset)) {
+                    Ok(()) | Err(StorageError::PageAlreadyExists) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            return Ok(store);
+        }
+
+        let newest = metas.iter().flatten().max_by_key(|m| m.generation).cloned();
+        match newest {
+            Some(meta) => store.load_generation(&meta)?,
+            None if metas.iter().all(|m| m.is_none()) && store.meta_pages_blank()? => {}
+            None => return Err(StorageError::Corrupt),
+        }
+        Ok(store)
+    }
+
+    /// The number of committed generations; zero until the first `sync`.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Discard every write since the last commit, going back to the committed generation. The physical
+    /// pages the discarded writes went to are reused.
+    pub fn rollback(&mut self) {
+        for (logical, physical) in self.undo.drain() {
+            match physical {
+                Some(physical) => self.table.insert(logical, physical),
+                None => self.table.remove(&logical),
+            };
+        }
+        self.free.extend(self.fresh.drain());
+        self.pending_free.clear();
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn meta_pages_blank(&self) -> Result<bool, StorageError> {
+        let mut buf = [0u8; PAGE_SIZE];
+        for offset in META_PAGES {
+            self.inner.load_page(&mut buf, &PageId::new(offset))?;
+            if buf.iter().any(|b| *b != 0) {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    fn load_generation(&mut self, meta: &Meta) -> Result<(), StorageError> {
+        self.generation = meta.generation;
+        self.next_physical = meta.next_physical as usize;
+
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut next = meta.table_root;
+        while next != NO_PAGE {
+            let page = PageId::new(next as usize);
+            self.inner.load_page(&mut buf, &page)?;
+            self.table_pages.push(page);
+            next = read_u64(&buf, 0);
+            let count = read_u16(&buf, 8) as usize;
+            if count > TABLE_ENTRIES_PER_PAGE {
+                return Err(StorageError::Corrupt)
+            }
+            for i in 0..count {
+                let at = TABLE_HEADER_LEN + i * TABLE_ENTRY_LEN;
+                let logical = PageId::new(read_u64(&buf, at) as usize);
+                let physical = PageId::new(read_u64(&buf, at + 8) as usize);
+                self.table.insert(logical, physical);
+            }
+        }
+
+        let used: HashSet<PageId> = self.table.values().chain(self.table_pages.iter()).copied().collect();
+        self.free = (FIRST_DATA_PAGE..self.next_physical)
+            .map(PageId::new)
+            .filter(|p| !used.contains(p))
+            .collect();
+        Ok(())
+    }
+
+    fn allocate_physical(&mut self) -> Result<PageId, StorageError> {
+        if let Some(page) = self.free.pop() {
+            return Ok(page)
+        }
+        let page = PageId::new(self.next_physical);
+        // A generation that never committed may have written pages past the committed one's, which nothing
+        // references, so they are overwritten like new ones.
+        match self.inner.create_page(&page) {
+            Ok(()) | Err(StorageError::PageAlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+        self.next_physical += 1;
+        Ok(page)
+    }
+
+    fn write_table(&mut self) -> Result<Option<PageId>, StorageError> {
+        let mut entries: Vec<(PageId, PageId)> = self.table.iter().map(|(l, p)| (*l, *p)).collect();
+        entries.sort_by_key(|(l, _)| l.offset());
+
+        let chunks: Vec<&[(PageId, PageId)]> = entries.chunks(TABLE_ENTRIES_PER_PAGE).collect();
+        let mut pages = Vec::with_capacity(chunks.len());
+        for _ in 0..chunks.len() {
+            pages.push(self.allocate_physical()?);
+        }
+
+        let mut buf = [0u8; PAGE_SIZE];
+        for (i, chunk) in chunks.iter().enumerate() {
+            buf.fill(0);
+            let next = pages.get(i + 1).map(|p| p.offset() as u64).unwrap_or(NO_PAGE);
+            write_u64(&mut buf, 0, next);
+            write_u16(&mut buf, 8, chunk.len() as u16);
+            for (j, (logical, physical)) in chunk.iter().enumerate() {
+                let at = TABLE_HEADER_LEN + j * TABLE_ENTRY_LEN;
+                write_u64(&mut buf, at, logical.offset() as u64);
+                write_u64(&mut buf, at + 8, physical.offset() as u64);
+            }
+            self.inner.write_page(&buf, &pages[i])?;
+        }
+
+        let root = pages.first().copied();
+        let old = std::mem::replace(&mut self.table_pages, pages);
+        self.pending_free.extend(old);
+        Ok(root)
+    }
+}
+impl<S: Storage> Storage for ShadowStorage<S> {
+    fn load_page(&self, buf: &mut Data, page: &PageId) -> Result<(), StorageError> {
+        let physical = self.table.get(page).ok_or(StorageError::NotFound)?;
+        self.inner.load_page(buf, physical)
+    }
+
+    fn create_page(&mut self, page: &PageId) -> Result<(), StorageError> {
+        if self.table.contains_key(page) {
+            return Err(StorageError::PageAlreadyExists)
+        }
+        let physical = self.allocate_physical()?;
+        self.inner.write_page(&[0u8; PAGE_SIZE], &physical)?;
+        self.table.insert(*page, physical);
+        self.fresh.insert(physical);
+        self.undo.entry(*page).or_insert(None);
+        Ok(())
+    }
+
+    fn write_page(&mut self, buf: &Data, page: &PageId) -> Result<(), StorageError> {
+        let current = *self.table.get(page).ok_or(StorageError::NotFound)?;
+        if self.fresh.contains(&current) {
+            return self.inner.write_page(buf, &current)
+        }
+        let physical = self.allocate_physical()?;
+        self.inner.write_page(buf, &physical)?;
+        self.table.insert(*page, physical);
+        self.fresh.insert(physical);
+        self.pending_free.push(current);
+        self.undo.entry(*page).or_insert(Some(current));
+        Ok(())
+    }
+
+    /// Commit the current generation by writing its page table and atomically switching the root.
+    fn sync(&mut self) -> Result<(), StorageError> {
+        if self.fresh.is_empty() && self.pending_free.is_empty() {
+            return self.inner.sync()
+        }
+
+        let table_root = self.write_table()?;
+        self.inner.sync()?;
+
+        let meta = Meta {
+            generation: self.generation + 1,
+            table_root: table_root.map(|p| p.offset() as u64).unwrap_or(NO_PAGE),
+            next_physical: self.next_physical as u64,
+        };
+        let slot = META_PAGES[(meta.generation % 2) as usize];
+        self.inner.write_page(&meta.encode(), &PageId::new(slot))?;
+        self.inner.sync()?;
+
+        self.generation = meta.generation;
+        self.free.append(&mut self.pending_free);
+        self.fresh.clear();
+        self.undo.clear();
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Meta {
+    generation: u64,
+    table_root: u64,
+    next_physical: u64,
+}
+impl Meta {
+    fn encode(&self) -> Data {
+        let mut buf = [0u8; PAGE_SIZE];
+        write_u32(&mut buf, 0, MAGIC);
+        write_u64(&mut buf, 4, self.generation);
+        write_u64(&mut buf, 12, self.table_root);
+        write_u64(&mut buf, 20, self.next_physical);
+        let crc = crc32(&buf[..META_LEN]);
+        write_u32(&mut buf, META_LEN, crc);
+        buf
+    }
+
+    fn decode(buf: &Data) -> Option<Meta> {
+        if read_u32(buf, 0) != MAGIC || read_u32(buf, META_LEN) != crc32(&buf[..META_LEN]) {
+            return None
+        }
+        Some(Meta {
+            generation: read_u64(buf, 4),
+            table_root: read_u64(buf, 12),
+            next_physical: read_u64(buf, 20),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page_store::{PageId, PAGE_SIZE, PageStore, PageError};
+    use crate::storage::{Storage, StorageError, TestStorage};
+
+    use super::ShadowStorage;
+
+    fn page_of(byte: u8) -> [u8; PAGE_SIZE] {
+        [byte; PAGE_SIZE]
+    }
+
+    #[test]
+    fn test_committed_pages_survive_reopen() -> Result<(), StorageError> {
+        let mut shadow = ShadowStorage::open(TestStorage::new())?;
+        shadow.create_page(&PageId::new(7))?;
+        shadow.write_page(&page_of(1), &PageId::new(7))?;
+        shadow.sync()?;
+
+        let shadow = ShadowStorage::open(shadow.into_inner())?;
+        let mut buf = [0u8; PAGE_SIZE];
+        shadow.load_page(&mut buf, &PageId::new(7))?;
+        assert_eq!(buf, page_of(1));
+        assert_eq!(shadow.generation(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_uncommitted_writes_are_discarded() -> Result<(), StorageError> {
+        let mut shadow = ShadowStorage::open(TestStorage::new())?;
+        shadow.create_page(&PageId::new(0))?;
+        shadow.write_page(&page_of(1), &PageId::new(0))?;
+        shadow.sync()?;
+        shadow.write_page(&page_of(2), &PageId::new(0))?;
+        shadow.create_page(&PageId::new(1))?;
+
+        let shadow = ShadowStorage::open(shadow.into_inner())?;
+        let mut buf = [0u8; PAGE_SIZE];
+        shadow.load_page(&mut buf, &PageId::new(0))?;
+        assert_eq!(buf, page_of(1));
+        assert_eq!(shadow.load_page(&mut buf, &PageId::new(1)), Err(StorageError::NotFound));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_after_uncommitted_writes() -> Result<(), StorageError> {
+        let mut shadow = ShadowStorage::open(TestStorage::new())?;
+        for i in 0..3 {
+            shadow.create_page(&PageId::new(i))?;
+            shadow.write_page(&page_of(i as u8), &PageId::new(i))?;
+        }
+
+        // Nothing was ever committed, then a generation commits over the pages the first one left.
+        let mut shadow = ShadowStorage::open(shadow.into_inner())?;
+        shadow.create_page(&PageId::new(0))?;
+        shadow.write_page(&page_of(7), &PageId::new(0))?;
+        shadow.sync()?;
+        shadow.write_page(&page_of(8), &PageId::new(0))?;
+        shadow.create_page(&PageId::new(1))?;
+
+        let mut shadow = ShadowStorage::open(shadow.into_inner())?;
+        shadow.create_page(&PageId::new(1))?;
+        shadow.write_page(&page_of(9), &PageId::new(1))?;
+        shadow.sync()?;
+
+        let shadow = ShadowStorage::open(shadow.into_inner())?;
+        let mut buf = [0u8; PAGE_SIZE];
+        shadow.load_page(&mut buf, &PageId::new(0))?;
+        assert_eq!(buf, page_of(7));
+        shadow.load_page(&mut buf, &PageId::new(1))?;
+        assert_eq!(buf, page_of(9));
+        assert_eq!(shadow.generation(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback() -> Result<(), StorageError> {
+        let mut shadow = ShadowStorage::open(TestStorage::new())?;
+        shadow.create_page(&PageId::new(0))?;
+        shadow.write_page(&page_of(1), &PageId::new(0))?;
+        shadow.sync()?;
+        let used = shadow.next_physical;
+        shadow.write_page(&page_of(2), &PageId::new(0))?;
+        shadow.write_page(&page_of(3), &PageId::new(0))?;
+        shadow.create_page(&PageId::new(1))?;
+        shadow.rollback();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        shadow.load_page(&mut buf, &PageId::new(0))?;
+        assert_eq!(buf, page_of(1));
+        assert_eq!(shadow.load_page(&mut buf, &PageId::new(1)), Err(StorageError::NotFound));
+        shadow.write_page(&page_of(4), &PageId::new(0))?;
+        shadow.create_page(&PageId::new(1))?;
+        shadow.sync()?;
+        // The two pages the discarded writes took hold the new ones, and only the page table needs another.
+        assert_eq!(shadow.next_physical, used + 3);
+
+        let shadow = ShadowStorage::open(shadow.into_inner())?;
+        shadow.load_page(&mut buf, &PageId::new(0))?;
+        assert_eq!(buf, page_of(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_superseded_pages_are_reused() -> Result<(), StorageError> {
+        let mut shadow = ShadowStorage::open(TestStorage::new())?;
+        shadow.create_page(&PageId::new(0))?;
+        for i in 0..20 {
+            shadow.write_page(&page_of(i), &PageId::new(0))?;
+            shadow.sync()?;
+        }
+        assert!(shadow.next_physical < 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_store_flush_commits() -> Result<(), PageError> {
+        let page_store = PageStore::new(ShadowStorage::open(TestStorage::new()).map_err(PageError::Storage)?);
+        {
+            let page = page_store.allocate_page(&PageId::new(3))?;
+            (*page.try_write()?)[0] = 9u8;
+        }
+        page_store.flush()?;
+
+        let shadow = ShadowStorage::open(page_store.into_storage().into_inner()).map_err(PageError::Storage)?;
+        let page_store = PageStore::new(shadow);
+        let page = page_store.pin_page(&PageId::new(3))?;
+        assert_eq!((*page.try_read()?)[0], 9u8);
+        Ok(())
+    }
+}
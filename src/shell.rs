@@ -0,0 +1,608 @@
+//! The interactive shell the `purpledb` binary runs over a connection.
+//!
+//! The shell reads lines and runs the SQL in them once a line ends a statement: with a semicolon outside
+//! any string, and outside any trigger body still open. A line starting with a dot while no statement is
+//! waiting for more is a command to the shell itself, such as `.tables` or `.mode csv`; `.help` lists them.
+//! A statement's error is written out and the shell goes on to the next one.
+//!
+//! An interactive session keeps every line entered, and appends each to the history file given to
+//! `open_history`, so that `.history` lists those of earlier sessions too. The shell reads whole lines and
+//! does no line editing of its own: moving along a line and recalling earlier ones with the arrow keys is
+//! left to the terminal, or to a wrapper such as `rlwrap`.
+//!
+//! Queries run through a prepared statement's cursor, so as CSV or JSON their rows are written as they are
+//! pulled from the plan; as a table, the default, they are all read first to size its columns.
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::catalog::{Constraint, IndexDef, TableDef, TableKind, ViewDef};
+use crate::collation::Collation;
+use crate::connection::Connection;
+use crate::csv::{self, CsvOptions};
+use crate::database::DatabaseError;
+use crate::exec::expr::cast;
+use crate::json::{self, Step as PathStep};
+use crate::sql::{self, ast, ParseError, ParseErrorKind};
+use crate::table::{IndexKind, PartitionBy};
+use crate::tuple::{ColumnType, Value};
+
+const HELP: &str = "\
+.exit                Leave the shell
+.export TABLE FILE   Write the rows of TABLE to FILE as CSV, under a header
+.help                Show this message
+.history             List the lines entered, oldest first
+.import FILE TABLE   Copy the rows of the CSV in FILE, under a header naming their columns, into TABLE
+.mode [MODE]         Write query results as MODE: table, csv or json
+.quit                Leave the shell
+.read FILE           Run the statements and commands in FILE
+.schema [NAME]       Show the statements that would make each table and view, or the one called NAME
+.tables              List the tables and views
+.timer on|off        Show how long each statement takes";
+
+/// How a query's rows are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Aligned columns under a header, in a box.
+    Table,
+    /// A header line of column names, then a line of comma separated values for each row.
+    Csv,
+    /// An array holding an object for each row, keyed by column name.
+    Json,
+}
+impl Mode {
+    fn named(name: &str) -> Option<Mode> {
+        match name {
+            "table" => Some(Mode::Table),
+            "csv" => Some(Mode::Csv),
+            "json" => Some(Mode::Json),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Table => "table",
+            Mode::Csv => "csv",
+            Mode::Json => "json",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ShellError {
+    Io(io::Error),
+    Database(DatabaseError),
+    /// A command the shell does not know, or given arguments it does not take.
+    Usage(String),
+}
+impl From<io::Error> for ShellError {
+    fn from(e: io::Error) -> Self {
+        ShellError::Io(e)
+    }
+}
+impl From<DatabaseError> for ShellError {
+    fn from(e: DatabaseError) -> Self {
+        ShellError::Database(e)
+    }
+}
+impl From<ParseError> for ShellError {
+    fn from(e: ParseError) -> Self {
+        ShellError::Database(DatabaseError::Parse(e))
+    }
+}
+
+pub struct Shell<W: Write> {
+    connection: Connection,
+    out: W,
+    mode: Mode,
+    timer: bool,
+    /// The lines of a statement read so far, waiting for the rest of it.
+    pending: String,
+    /// Whether a statement or command has failed.
+    failed: bool,
+    /// The lines entered in interactive sessions, oldest first.
+    history: Vec<String>,
+    /// Where each line entered is appended, if anywhere.
+    history_file: Option<File>,
+}
+impl<W: Write> Shell<W> {
+    pub fn new(connection: Connection, out: W) -> Shell<W> {
+        let (pending, history) = (String::new(), Vec::new());
+        Shell { connection, out, mode: Mode::Table, timer: false, pending, failed: false, history, history_file: None }
+    }
+
+    /// Read the lines of earlier sessions from the history file at `path`, creating it if there is none, and
+    /// append each line entered from now on to it.
+    pub fn open_history(&mut self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let earlier = BufReader::new(&mut file).lines().collect::<io::Result<Vec<_>>>()?;
+        self.history.splice(0..0, earlier);
+        self.history_file = Some(file);
+        Ok(())
+    }
+
+    /// Whether a statement or command has failed so far.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    pub fn into_connection(self) -> Connection {
+        self.connection
+    }
+
+    /// Run the lines of `input` until it ends or one of them is `.quit`, prompting for each if `interactive`,
+    /// and return whether it was `.quit`. A statement still waiting for more when the input ends is run as
+    /// it is.
+    pub fn run(&mut self, input: impl BufRead, interactive: bool) -> io::Result<bool> {
+        let mut lines = input.lines();
+        loop {
+            if interactive {
+                write!(self.out, "{}", if self.pending.is_empty() { "purpledb> " } else { "     ...> " })?;
+                self.out.flush()?;
+            }
+            let Some(line) = lines.next() else { break };
+            let line = line?;
+            if interactive && !line.trim().is_empty() {
+                self.remember(&line);
+            }
+            if self.line(&line)? {
+                return Ok(true)
+            }
+        }
+        if interactive {
+            writeln!(self.out)?;
+        }
+        let rest = mem::take(&mut self.pending);
+        if !rest.trim().is_empty() {
+            self.script(&rest)?;
+        }
+        Ok(false)
+    }
+
+    /// Keep `line` in the history, and in the history file unless writing to it fails, which stops the file
+    /// being written to rather than failing the line.
+    fn remember(&mut self, line: &str) {
+        self.history.push(line.to_string());
+        if let Some(file) = &mut self.history_file {
+            if writeln!(file, "{line}").is_err() {
+                self.history_file = None;
+            }
+        }
+    }
+
+    /// Take in one line, running it if it is a command or ends a statement, and return whether it was
+    /// `.quit`.
+    fn line(&mut self, line: &str) -> io::Result<bool> {
+        // A blank line starts no statement, so a command may still follow it.
+        if self.pending.is_empty() && line.trim().is_empty() {
+            return Ok(false)
+        }
+        if self.pending.is_empty() && line.trim_start().starts_with('.') {
+            let result = self.command(line.trim());
+            return self.report(result).map(|quit| quit.unwrap_or(false))
+        }
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        if !self.pending.trim_end().ends_with(';') {
+            return Ok(false)
+        }
+        match sql::split(&self.pending) {
+            Err(e) if e.kind == ParseErrorKind::UnterminatedString || e.offset >= self.pending.trim_end().len() => {
+                Ok(false)
+            }
+            _ => {
+                let script = mem::take(&mut self.pending);
+                self.script(&script)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Run each statement of `sql`.
+    fn script(&mut self, sql: &str) -> io::Result<()> {
+        let statements = match sql::split(sql) {
+            Ok(statements) => statements,
+            Err(e) => return self.report::<()>(Err(e.into())).map(drop),
+        };
+        for statement in statements {
+            let start = Instant::now();
+            let result = self.statement(statement);
+            self.report(result)?;
+            if self.timer {
+                writeln!(self.out, "Time: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, text: &str) -> Result<(), ShellError> {
+        match sql::parse_statement(text)? {
+            sql::Statement::Select(_) => {
+                let mut statement = self.connection.prepare(text)?;
+                let rows = statement.query(self.connection.database(), &[])?;
+                let columns = rows.columns().to_vec();
+                write_rows(&mut self.out, self.mode, &columns, rows)
+            }
+            sql::Statement::Explain { .. } => {
+                let result = self.connection.query(text, &[])?;
+                write_rows(&mut self.out, self.mode, &result.columns, result.rows.into_iter().map(Ok))
+            }
+            sql::Statement::Insert(_) | sql::Statement::Update(_) | sql::Statement::Delete(_) => {
+                let count = self.connection.execute(text)?;
+                Ok(writeln!(self.out, "{count} {}", if count == 1 { "row" } else { "rows" })?)
+            }
+            _ => Ok(self.connection.execute(text).map(drop)?),
+        }
+    }
+
+    /// Run the command on `line`, returning whether it was `.quit`.
+    fn command(&mut self, line: &str) -> Result<bool, ShellError> {
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        match (name, args.as_slice()) {
+            (".quit" | ".exit", []) => return Ok(true),
+            (".help", []) => writeln!(self.out, "{HELP}")?,
+            (".history", []) => {
+                let width = self.history.len().to_string().len();
+                for (i, line) in self.history.iter().enumerate() {
+                    writeln!(self.out, "{:>width$}  {line}", i + 1)?;
+                }
+            }
+            (".tables", []) => {
+                let catalog = self.connection.database().catalog();
+                let mut names: Vec<&str> = catalog.tables().map(|t| t.name.as_str()).collect();
+                names.extend(catalog.views().map(|v| v.name.as_str()));
+                names.sort_unstable();
+                names.iter().try_for_each(|name| writeln!(self.out, "{name}"))?;
+            }
+            (".schema", [] | [_]) => {
+                let catalog = self.connection.database().catalog();
+                let wanted = |name: &str| args.first().is_none_or(|wanted| *wanted == name);
+                for table in catalog.tables().filter(|t| wanted(&t.name)) {
+                    writeln!(self.out, "{}", create_table(table))?;
+                }
+                for view in catalog.views().filter(|v| wanted(&v.name)) {
+                    writeln!(self.out, "{}", create_view(view))?;
+                }
+            }
+            (".mode", []) => writeln!(self.out, "{}", self.mode.name())?,
+            (".mode", [mode]) => {
+                self.mode = Mode::named(mode).ok_or_else(|| ShellError::Usage(format!("no mode called {mode}")))?
+            }
+            (".timer", ["on"]) => self.timer = true,
+            (".timer", ["off"]) => self.timer = false,
+            (".read", [_, ..]) => {
+                let file = File::open(rest.trim())?;
+                return Ok(self.run(BufReader::new(file), false)?)
+            }
+            (".import", [file, table]) => {
+                let count = self.connection.copy_from_csv(table, File::open(file)?, &CsvOptions::default())?;
+                writeln!(self.out, "{count} {}", if count == 1 { "row" } else { "rows" })?
+            }
+            (".export", [table, file]) => {
+                let out = BufWriter::new(File::create(file)?);
+                let count = self.connection.copy_to_csv(table, out, &CsvOptions::default())?;
+                writeln!(self.out, "{count} {}", if count == 1 { "row" } else { "rows" })?
+            }
+            _ => return Err(ShellError::Usage(format!("cannot run {line}, see .help"))),
+        }
+        Ok(false)
+    }
+
+    /// Write out the error `result` failed with, if any, and give back what it succeeded with. Only a
+    /// failure to write output is passed on.
+    fn report<T>(&mut self, result: Result<T, ShellError>) -> io::Result<Option<T>> {
+        let message = match result {
+            Ok(value) => return Ok(Some(value)),
+            Err(ShellError::Database(e)) => e.to_string(),
+            Err(ShellError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => return Err(e),
+            Err(ShellError::Io(e)) => e.to_string(),
+            Err(ShellError::Usage(message)) => message,
+        };
+        self.failed = true;
+        writeln!(self.out, "Error: {message}")?;
+        Ok(None)
+    }
+}
+
+/// Write `rows` out as `mode` says, under `columns`.
+fn write_rows<W: Write>(
+    out: &mut W,
+    mode: Mode,
+    columns: &[String],
+    mut rows: impl Iterator<Item = Result<Vec<Value>, DatabaseError>>,
+) -> Result<(), ShellError> {
+    match mode {
+        Mode::Table => {
+            let rows = rows.collect::<Result<Vec<_>, _>>()?;
+            let cell = |v: &Value| text(v).unwrap_or_else(|| "NULL".to_string());
+            let cells: Vec<Vec<String>> = rows.iter().map(|row| row.iter().map(cell).collect()).collect();
+            let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+            for row in &cells {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+            let rule: String = widths.iter().map(|w| format!("+{}", "-".repeat(w + 2))).collect::<String>() + "+";
+            writeln!(out, "{rule}")?;
+            let header = columns.iter().zip(&widths).map(|(c, &w)| format!("| {c:<w$} ")).collect::<String>();
+            writeln!(out, "{header}|\n{rule}")?;
+            for (row, values) in cells.iter().zip(&rows) {
+                for ((cell, &width), value) in row.iter().zip(&widths).zip(values) {
+                    let numeric = matches!(value, Value::Int(_) | Value::Float(_) | Value::Decimal(_));
+                    match numeric {
+                        true => write!(out, "| {cell:>width$} ")?,
+                        false => write!(out, "| {cell:<width$} ")?,
+                    }
+                }
+                writeln!(out, "|")?;
+            }
+            if !rows.is_empty() {
+                writeln!(out, "{rule}")?;
+            }
+            writeln!(out, "({} {})", rows.len(), if rows.len() == 1 { "row" } else { "rows" })?;
+        }
+        Mode::Csv => {
+            let options = CsvOptions::default();
+            csv::write_record(out, columns.iter().map(|c| Some(c.as_str())), &options)?;
+            for row in rows {
+                let fields: Vec<Option<String>> = row?.iter().map(text).collect();
+                csv::write_record(out, fields.iter().map(Option::as_deref), &options)?;
+            }
+        }
+        Mode::Json => {
+            write!(out, "[")?;
+            let mut first = true;
+            for row in rows.by_ref() {
+                let members: Vec<String> =
+                    columns.iter().zip(row?).map(|(c, v)| format!("{}: {}", json::quote(c), json_value(&v))).collect();
+                write!(out, "{}\n  {{{}}}", if first { "" } else { "," }, members.join(", "))?;
+                first = false;
+            }
+            writeln!(out, "{}]", if first { "" } else { "\n" })?;
+        }
+    }
+    Ok(())
+}
+
+/// `value` as text, or `None` for null. Bytes are written in hex after `\x`.
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Bytes(bytes) => Some(bytes.iter().fold("\\x".to_string(), |hex, b| hex + &format!("{b:02x}"))),
+        value => match cast(value.clone(), ColumnType::Text) {
+            Ok(Value::Text(text)) => Some(text),
+            _ => Some(format!("{value:?}")),
+        },
+    }
+}
+
+/// `value` as JSON: numbers, booleans, null and documents as themselves, everything else as its text.
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Int(v) => v.to_string(),
+        Value::Float(v) if v.is_finite() => v.to_string(),
+        Value::Decimal(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::Json(v) => v.to_string(),
+        value => json::quote(&text(value).unwrap_or_default()),
+    }
+}
+
+/// The `CREATE TABLE` making a table like `def`, with its indexes after it.
+fn create_table(def: &TableDef) -> String {
+    let mut parts: Vec<String> = def
+        .columns
+        .iter()
+        .map(|column| {
+            let mut part = format!("{} {}", column.name, ast::type_name(column.column.column_type));
+            if !column.column.nullable {
+                part += " NOT NULL";
+            }
+            if let Some(default) = &column.default {
+                part += &format!(" DEFAULT {default}");
+            }
+            if column.collation != Collation::Binary {
+                part += &format!(" COLLATE {}", column.collation.name());
+            }
+            part
+        })
+        .collect();
+    let keys = |index: &IndexDef| {
+        let key = index.columns.iter().zip(&index.paths).map(|(&c, path)| {
+            let name = &def.columns[c].name;
+            let Some(path) = path else { return name.clone() };
+            let last = path.steps.len().saturating_sub(1);
+            let steps = path.steps.iter().enumerate().map(|(i, step)| {
+                let arrow = if i == last && path.text { "->>" } else { "->" };
+                match step {
+                    PathStep::Key(key) => format!("{arrow}'{}'", key.replace('\'', "''")),
+                    PathStep::Index(i) => format!("{arrow}{i}"),
+                }
+            });
+            format!("({name}{})", steps.collect::<String>())
+        });
+        key.collect::<Vec<_>>().join(", ")
+    };
+    let mut indexes = Vec::new();
+    for index in &def.indexes {
+        let using = if index.kind == IndexKind::Hash { " USING HASH" } else { "" };
+        match index.constraint {
+            Some(Constraint::PrimaryKey) => {
+                parts.push(format!("CONSTRAINT {} PRIMARY KEY ({})", index.name, keys(index)))
+            }
+            Some(Constraint::Unique) if index.kind == IndexKind::Hash => {
+                indexes.push(format!("CREATE UNIQUE INDEX {} ON {}{using} ({});", index.name, def.name, keys(index)))
+            }
+            Some(Constraint::Unique) => parts.push(format!("CONSTRAINT {} UNIQUE ({})", index.name, keys(index))),
+            None if def.foreign_keys.iter().any(|k| k.name == index.name) => {}
+            None => indexes.push(format!("CREATE INDEX {} ON {}{using} ({});", index.name, def.name, keys(index))),
+        }
+    }
+    for key in &def.foreign_keys {
+        let index = def.index(&key.name).expect("a foreign key has an index of its own");
+        parts.push(format!("CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {}", key.name, keys(index), key.table));
+    }
+    let clustered = match &def.kind {
+        TableKind::Clustered { primary_key } => Some(primary_key),
+        _ => None,
+    };
+    if let Some(primary_key) = clustered {
+        let names: Vec<&str> = primary_key.iter().map(|&c| def.columns[c].name.as_str()).collect();
+        parts.push(format!("PRIMARY KEY ({})", names.join(", ")));
+    }
+    let mut sql = format!("CREATE TABLE {} ({})", def.name, parts.join(", "));
+    if clustered.is_some() {
+        sql += " WITHOUT ROWID";
+    }
+    match def.kind {
+        TableKind::Partitioned { by: PartitionBy::Range(column) } => {
+            sql += &format!(" PARTITION BY RANGE ({})", def.columns[column].name)
+        }
+        TableKind::Partitioned { by: PartitionBy::Hash { column, partitions } } => {
+            sql += &format!(" PARTITION BY HASH ({}) PARTITIONS {partitions}", def.columns[column].name)
+        }
+        _ => {}
+    }
+    if let Some(ttl) = &def.ttl {
+        sql += &format!(" TTL {} + {}", def.columns[ttl.column].name, ast::Expr::Literal(ttl.lifetime.clone()));
+    }
+    sql.push(';');
+    indexes.iter().for_each(|index| sql += &format!("\n{index}"));
+    sql
+}
+
+fn create_view(def: &ViewDef) -> String {
+    format!("CREATE VIEW {} ({}) AS {};", def.name, def.columns.join(", "), def.query)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::connection::Connection;
+    use crate::database::DatabaseError;
+
+    use super::Shell;
+
+    fn run(script: &str) -> Result<(String, bool), DatabaseError> {
+        let mut shell = Shell::new(Connection::open_in_memory()?, Vec::new());
+        shell.run(Cursor::new(script), false).unwrap();
+        let failed = shell.failed();
+        Ok((String::from_utf8(shell.out).unwrap(), failed))
+    }
+
+    #[test]
+    fn test_statements_and_modes() -> Result<(), DatabaseError> {
+        let script = "CREATE TABLE t (a INT, b TEXT);\n\
+            INSERT INTO t\n  VALUES (1, 'x'), (22, NULL);\n\
+            SELECT a, b FROM t ORDER BY a;\n\
+            .mode csv\n\
+            SELECT b, 'say \"hi\", then' AS c, '' AS e FROM t ORDER BY a; SELECT 1 AS one;\n\
+            .mode json\n\
+            SELECT a, b FROM t WHERE a > 100; SELECT a, b FROM t ORDER BY a;\n";
+        let expected = "2 rows\n\
+            +----+------+\n\
+            | a  | b    |\n\
+            +----+------+\n\
+            |  1 | x    |\n\
+            | 22 | NULL |\n\
+            +----+------+\n\
+            (2 rows)\n\
+            b,c,e\n\
+            x,\"say \"\"hi\"\", then\",\"\"\n\
+            ,\"say \"\"hi\"\", then\",\"\"\n\
+            one\n\
+            1\n\
+            []\n\
+            [\n  {\"a\": 1, \"b\": \"x\"},\n  {\"a\": 22, \"b\": null}\n]\n";
+        assert_eq!(run(script)?, (expected.to_string(), false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands_and_errors() -> Result<(), DatabaseError> {
+        let script = "CREATE TABLE t (a INT PRIMARY KEY, b TEXT NOT NULL DEFAULT 'none');\n\
+            CREATE INDEX by_b ON t (b);\n\
+            CREATE VIEW v AS SELECT a FROM t;\n\
+            CREATE TRIGGER stamp AFTER INSERT ON t BEGIN\n\
+              UPDATE t SET b = 'seen' WHERE a = new.a;\n\
+            END;\n\
+            CREATE TABLE visits (at TIMESTAMP) TTL at + INTERVAL '1 month 2 days';\n\
+            CREATE TABLE pairs (a INT, b TEXT, PRIMARY KEY (b, a)) WITHOUT ROWID;\n\
+            CREATE TABLE buckets (k INT) PARTITION BY HASH (k) PARTITIONS 4;\n\
+            .tables\n\
+            .schema t\n\
+            .schema visits\n\
+            .schema pairs\n\
+            .schema buckets\n\
+            SELECT nope FROM t;\n\
+            .mode yaml\n\
+            SELECT 'unfinished\n;' AS s;\n\
+            .quit\n\
+            SELECT 1;\n";
+        let expected = "buckets\npairs\nt\nv\nvisits\n\
+            CREATE TABLE t (a BIGINT NOT NULL, b TEXT NOT NULL DEFAULT 'none', CONSTRAINT t_pkey PRIMARY KEY (a));\n\
+            CREATE INDEX by_b ON t (b);\n\
+            CREATE TABLE visits (at TIMESTAMP) TTL at + INTERVAL '1 mon 2 days';\n\
+            CREATE TABLE pairs (a BIGINT NOT NULL, b TEXT NOT NULL, PRIMARY KEY (b, a)) WITHOUT ROWID;\n\
+            CREATE TABLE buckets (k BIGINT) PARTITION BY HASH (k) PARTITIONS 4;\n\
+            Error: no column called nope\n\
+            Error: no mode called yaml\n\
+            +--------------+\n\
+            | s            |\n\
+            +--------------+\n\
+            | unfinished\n; |\n\
+            +--------------+\n\
+            (1 row)\n";
+        assert_eq!(run(script)?, (expected.to_string(), true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_and_export() -> Result<(), DatabaseError> {
+        let dir = std::env::temp_dir().join(format!("purpledb-shell-csv-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.csv"), dir.join("out.csv"));
+        let bad = dir.join("bad.csv");
+        std::fs::write(&input, "b,a\n\"x, y\",1\n,2\n").unwrap();
+        std::fs::write(&bad, "a\n3\nthree\n").unwrap();
+        let script = format!(
+            "CREATE TABLE t (a INT, b TEXT);\n.import {} t\n.export t {}\n.import {} nope\n.import {} t\n",
+            input.display(),
+            output.display(),
+            input.display(),
+            bad.display()
+        );
+        let (out, failed) = run(&script)?;
+        let exported = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let expected = "2 rows\n2 rows\nError: no table or view called nope\n\
+            Error: at line 3 of the CSV: \"three\" is not a value for column a\n";
+        assert_eq!((out.as_str(), failed), (expected, true));
+        assert_eq!(exported, "a,b\n1,\"x, y\"\n2,\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> Result<(), DatabaseError> {
+        let path = std::env::temp_dir().join(format!("purpledb-shell-history-{}", std::process::id()));
+        std::fs::write(&path, "SELECT 0;\n").unwrap();
+        let mut shell = Shell::new(Connection::open_in_memory()?, Vec::new());
+        shell.open_history(&path).unwrap();
+        shell.run(Cursor::new("CREATE TABLE t (a INT);\n\n.history\n"), true).unwrap();
+        // Lines read from a script are not kept.
+        shell.run(Cursor::new("SELECT 1;\n"), false).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let out = String::from_utf8(shell.out).unwrap();
+        assert_eq!(out, "purpledb> purpledb> purpledb> 1  SELECT 0;\n2  CREATE TABLE t (a INT);\n3  .history\n\
+            purpledb> \n+----------+\n| ?column? |\n+----------+\n|        1 |\n+----------+\n(1 row)\n");
+        assert_eq!(written, "SELECT 0;\nCREATE TABLE t (a INT);\n.history\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+//! An ordered in-memory map built as a skip list.
+//!
+//! Nodes live in an arena and link to each other by index, so the list needs no unsafe code and can be
+//! dropped in one go. The list is `Sync`: lookups and iterators share a read lock and may run on many
+//! threads at once, while inserts take the write lock briefly. It serves as the LSM memtable and as a
+//! scratch sorted index for temporary data; `into_sorted` hands the contents over in key order for
+//! writing out to pages.
+use std::borrow::Borrow;
+use std::ops::Bound;
+use std::sync::{RwLock, RwLockReadGuard};
+
+const MAX_HEIGHT: usize = 12;
+const NIL: usize = usize::MAX;
+
+pub struct SkipList<K, V> {
+    inner: RwLock<Inner<K, V>>,
+}
+struct Inner<K, V> {
+    nodes: Vec<Node<K, V>>,
+    /// Links out of the head sentinel, one per level.
+    head: [usize; MAX_HEIGHT],
+    height: usize,
+    rng: u64,
+}
+struct Node<K, V> {
+    key: K,
+    value: V,
+    next: Vec<usize>,
+}
+impl<K, V> Inner<K, V> {
+    fn next(&self, node: Option<usize>, level: usize) -> usize {
+        match node {
+            None => self.head[level],
+            Some(i) => self.nodes[i].next[level],
+        }
+    }
+
+    fn set_next(&mut self, node: Option<usize>, level: usize, to: usize) {
+        match node {
+            None => self.head[level] = to,
+            Some(i) => self.nodes[i].next[level] = to,
+        }
+    }
+
+    /// The last node at each level whose key is less than `key`, or `None` for the head.
+    fn predecessors<Q: Ord + ?Sized>(&self, key: &Q, inclusive: bool) -> [Option<usize>; MAX_HEIGHT]
+    where K: Borrow<Q> {
+        let mut preds = [None; MAX_HEIGHT];
+        let mut node = None;
+        for level in (0..self.height).rev() {
+            loop {
+                let next = self.next(node, level);
+                let before = next != NIL && match self.nodes[next].key.borrow().cmp(key) {
+                    std::cmp::Ordering::Less => true,
+                    std::cmp::Ordering::Equal => inclusive,
+                    std::cmp::Ordering::Greater => false,
+                };
+                if !before {
+                    break
+                }
+                node = Some(next);
+            }
+            preds[level] = node;
+        }
+        preds
+    }
+
+    /// Tower height for a new node: each extra level is kept with probability 1/4.
+    fn random_height(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let height = 1 + (self.rng.trailing_zeros() / 2) as usize;
+        height.min(MAX_HEIGHT)
+    }
+}
+impl<K: Ord, V> SkipList<K, V> {
+    pub fn new() -> SkipList<K, V> {
+        SkipList {
+            inner: RwLock::new(Inner { nodes: Vec::new(), head: [NIL; MAX_HEIGHT], height: 1, rng: 0x9e37_79b9_7f4a_7c15 }),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Inner<K, V>> {
+        self.inner.read().expect("skip list lock poisoned")
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert or replace the value for `key`, returning the value it replaced.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let mut inner = self.inner.write().expect("skip list lock poisoned");
+        let preds = inner.predecessors(&key, false);
+        let found = inner.next(preds[0], 0);
+        if found != NIL && inner.nodes[found].key == key {
+            return Some(std::mem::replace(&mut inner.nodes[found].value, value))
+        }
+
+        let height = inner.random_height();
+        let index = inner.nodes.len();
+        // Levels above the current height have the head as their predecessor.
+        let next = (0..height).map(|level| inner.next(preds[level], level)).collect();
+        inner.nodes.push(Node { key, value, next });
+        for (level, pred) in preds.iter().enumerate().take(height) {
+            inner.set_next(*pred, level, index);
+        }
+        inner.height = inner.height.max(height);
+        None
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<V>
+    where K: Borrow<Q>, V: Clone {
+        let inner = self.read();
+        let preds = inner.predecessors(key, false);
+        let found = inner.next(preds[0], 0);
+        (found != NIL && inner.nodes[found].key.borrow() == key).then(|| inner.nodes[found].value.clone())
+    }
+
+    /// Entries in key order. The iterator holds the read lock, so inserts wait until it is dropped.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.range_from::<K>(Bound::Unbounded)
+    }
+
+    /// Entries from `start` onward, in key order.
+    pub fn range_from<Q: Ord + ?Sized>(&self, start: Bound<&Q>) -> Iter<'_, K, V>
+    where K: Borrow<Q> {
+        let inner = self.read();
+        let next = match start {
+            Bound::Unbounded => inner.head[0],
+            Bound::Included(key) => inner.next(inner.predecessors(key, false)[0], 0),
+            Bound::Excluded(key) => inner.next(inner.predecessors(key, true)[0], 0),
+        };
+        Iter { inner, next }
+    }
+
+    /// Freeze the list and take its entries in key order, for flushing to sorted pages.
+    pub fn into_sorted(self) -> Vec<(K, V)> {
+        let inner = self.inner.into_inner().expect("skip list lock poisoned");
+        let mut order = Vec::with_capacity(inner.nodes.len());
+        let mut node = inner.head[0];
+        while node != NIL {
+            order.push(node);
+            node = inner.nodes[node].next[0];
+        }
+        let mut slots: Vec<Option<(K, V)>> = inner.nodes.into_iter().map(|n| Some((n.key, n.value))).collect();
+        order.into_iter().map(|i| slots[i].take().expect("each node is linked once")).collect()
+    }
+}
+impl<K: Ord, V> Default for SkipList<K, V> {
+    fn default() -> Self {
+        SkipList::new()
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    inner: RwLockReadGuard<'a, Inner<K, V>>,
+    next: usize,
+}
+impl<K: Clone, V: Clone> Iterator for Iter<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == NIL {
+            return None
+        }
+        let node = &self.inner.nodes[self.next];
+        self.next = node.next[0];
+        Some((node.key.clone(), node.value.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    use super::SkipList;
+
+    #[test]
+    fn test_matches_btreemap() {
+        let list = SkipList::new();
+        let mut model = BTreeMap::new();
+        let mut x: u32 = 7;
+        for _ in 0..5000 {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let key = (x >> 8) % 1500;
+            assert_eq!(list.insert(key, x), model.insert(key, x));
+        }
+        assert_eq!(list.len(), model.len());
+        assert_eq!(list.get(&700), model.get(&700).copied());
+        assert_eq!(list.get(&5000), None);
+        assert!(list.iter().eq(model.iter().map(|(k, v)| (*k, *v))));
+        assert!(list.range_from(Bound::Excluded(&100)).eq(model.range(101..).map(|(k, v)| (*k, *v))));
+        assert!(list.range_from(Bound::Included(&100)).eq(model.range(100..).map(|(k, v)| (*k, *v))));
+        assert_eq!(list.into_sorted(), model.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_concurrent_inserts_and_reads() {
+        let list = SkipList::new();
+        std::thread::scope(|s| {
+            for t in 0..4u32 {
+                let list = &list;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        list.insert(i * 4 + t, t);
+                        assert_eq!(list.get(&(i * 4 + t)), Some(t));
+                    }
+                });
+            }
+        });
+        assert_eq!(list.len(), 4000);
+        assert!(list.iter().map(|(k, _)| k).eq(0..4000));
+    }
+}
@@ -0,0 +1,374 @@
+//! Slotted page layout over a single `Data` buffer.
+//!
+//! ```text
+//! | header | reserved | slot directory -> |   free   | <- records |
+//! ```
+//!
+//! The header holds the slot count, the start of the record area, the number of bytes lost to
+//! fragmentation and the length of the reserved area. The reserved area belongs to whichever access
+//! method owns the page. Each slot is an (offset, length) pair; an offset of zero marks an empty slot so
+//! that slot ids of the remaining records stay stable across deletes.
+use std::ops::{Deref, DerefMut};
+
+use crate::page_store::{Data, PAGE_SIZE};
+
+pub type SlotId = u16;
+
+const SLOT_COUNT: usize = 0;
+const FREE_END: usize = 2;
+const FRAGMENTED: usize = 4;
+const RESERVED_LEN: usize = 6;
+pub const HEADER_LEN: usize = 8;
+pub const SLOT_LEN: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum SlottedPageError {
+    PageFull,
+    InvalidSlot,
+    RecordTooLarge,
+}
+
+pub struct SlottedPage<T> {
+    data: T
+}
+impl<T: Deref<Target = Data>> SlottedPage<T> {
+    /// View a page that was previously set up with `init`.
+    pub fn new(data: T) -> SlottedPage<T> {
+        SlottedPage { data }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.data
+    }
+
+    /// Number of slots in the directory, including empty ones.
+    pub fn slot_count(&self) -> u16 {
+        self.read_u16(SLOT_COUNT)
+    }
+
+    pub fn reserved(&self) -> &[u8] {
+        &self.data[HEADER_LEN..self.directory_start()]
+    }
+
+    pub fn get(&self, slot: SlotId) -> Option<&[u8]> {
+        let (offset, len) = self.slot(slot)?;
+        Some(&self.data[offset..offset + len])
+    }
+
+    /// Bytes available to a new record, assuming it needs a new slot and the page is compacted first.
+    pub fn free_space(&self) -> usize {
+        (self.contiguous_free() + self.fragmented()).saturating_sub(SLOT_LEN)
+    }
+
+    /// The largest record an empty page with this reserved area can hold.
+    pub fn max_record_len(&self) -> usize {
+        PAGE_SIZE - self.directory_start() - SLOT_LEN
+    }
+
+    /// Whether the header and slot directory describe a layout that fits in the page, so that the other
+    /// accessors cannot index out of bounds. Pages from `init` always are; a corrupt page may not be.
+    pub fn is_well_formed(&self) -> bool {
+        let directory_end = self.directory_start() + self.slot_count() as usize * SLOT_LEN;
+        if directory_end > self.free_end() || self.free_end() > PAGE_SIZE {
+            return false
+        }
+        (0..self.slot_count()).all(|slot| {
+            let at = self.slot_position(slot);
+            let (offset, len) = (self.read_u16(at) as usize, self.read_u16(at + 2) as usize);
+            offset == 0 || (offset >= self.free_end() && offset + len <= PAGE_SIZE)
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SlotId, &[u8])> + '_ {
+        (0..self.slot_count()).filter_map(move |slot| self.get(slot).map(|r| (slot, r)))
+    }
+
+    fn slot(&self, slot: SlotId) -> Option<(usize, usize)> {
+        if slot >= self.slot_count() {
+            return None
+        }
+        let at = self.slot_position(slot);
+        let offset = self.read_u16(at) as usize;
+        if offset == 0 {
+            return None
+        }
+        Some((offset, self.read_u16(at + 2) as usize))
+    }
+
+    fn directory_start(&self) -> usize {
+        HEADER_LEN + self.read_u16(RESERVED_LEN) as usize
+    }
+
+    fn slot_position(&self, slot: SlotId) -> usize {
+        self.directory_start() + slot as usize * SLOT_LEN
+    }
+
+    fn free_end(&self) -> usize {
+        self.read_u16(FREE_END) as usize
+    }
+
+    fn fragmented(&self) -> usize {
+        self.read_u16(FRAGMENTED) as usize
+    }
+
+    fn contiguous_free(&self) -> usize {
+        self.free_end() - self.slot_position(self.slot_count())
+    }
+
+    fn read_u16(&self, at: usize) -> u16 {
+        u16::from_le_bytes([self.data[at], self.data[at + 1]])
+    }
+}
+impl<T: DerefMut<Target = Data>> SlottedPage<T> {
+    /// Format `data` as an empty slotted page with `reserved` bytes set aside after the header.
+    pub fn init(data: T, reserved: usize) -> SlottedPage<T> {
+        assert!(HEADER_LEN + reserved + SLOT_LEN < PAGE_SIZE);
+        let mut page = SlottedPage { data };
+        page.data.fill(0);
+        page.write_u16(FREE_END, PAGE_SIZE as u16);
+        page.write_u16(RESERVED_LEN, reserved as u16);
+        page
+    }
+
+    /// Drop every record, leaving the reserved area untouched.
+    pub fn clear(&mut self) {
+        self.write_u16(SLOT_COUNT, 0);
+        self.write_u16(FREE_END, PAGE_SIZE as u16);
+        self.write_u16(FRAGMENTED, 0);
+    }
+
+    /// Drop every record and resize the reserved area to `reserved` bytes. The reserved area keeps its
+    /// contents up to the smaller of the old and new sizes; any bytes added to it are zeroed.
+    pub fn reset(&mut self, reserved: usize) {
+        assert!(HEADER_LEN + reserved + SLOT_LEN < PAGE_SIZE);
+        let old = self.directory_start();
+        self.clear();
+        self.write_u16(RESERVED_LEN, reserved as u16);
+        let end = self.directory_start();
+        if end > old {
+            self.data[old..end].fill(0);
+        }
+    }
+
+    pub fn reserved_mut(&mut self) -> &mut [u8] {
+        let end = self.directory_start();
+        &mut self.data[HEADER_LEN..end]
+    }
+
+    /// Store `record`, reusing an empty slot when there is one.
+    pub fn insert(&mut self, record: &[u8]) -> Result<SlotId, SlottedPageError> {
+        if record.len() > self.max_record_len() {
+            return Err(SlottedPageError::RecordTooLarge)
+        }
+        let reuse = (0..self.slot_count()).find(|s| self.slot(*s).is_none());
+        let directory_growth = if reuse.is_some() { 0 } else { SLOT_LEN };
+        self.make_room(record.len() + directory_growth)?;
+
+        let slot = match reuse {
+            Some(slot) => slot,
+            None => {
+                let slot = self.slot_count();
+                self.write_u16(SLOT_COUNT, slot + 1);
+                slot
+            }
+        };
+        let offset = self.place(record);
+        self.set_slot(slot, offset, record.len());
+        Ok(slot)
+    }
+
+    pub fn delete(&mut self, slot: SlotId) -> Result<(), SlottedPageError> {
+        let (_, len) = self.slot(slot).ok_or(SlottedPageError::InvalidSlot)?;
+        self.set_slot(slot, 0, 0);
+        self.write_u16(FRAGMENTED, (self.fragmented() + len) as u16);
+
+        let mut count = self.slot_count();
+        while count > 0 && self.slot(count - 1).is_none() {
+            count -= 1;
+            self.write_u16(SLOT_COUNT, count);
+        }
+        Ok(())
+    }
+
+    /// Replace the record in `slot`, moving it within the page if it grew.
+    pub fn update(&mut self, slot: SlotId, record: &[u8]) -> Result<(), SlottedPageError> {
+        let (offset, len) = self.slot(slot).ok_or(SlottedPageError::InvalidSlot)?;
+        if record.len() <= len {
+            self.data[offset..offset + record.len()].copy_from_slice(record);
+            self.set_slot(slot, offset, record.len());
+            self.write_u16(FRAGMENTED, (self.fragmented() + len - record.len()) as u16);
+            return Ok(())
+        }
+        if record.len() > self.max_record_len() {
+            return Err(SlottedPageError::RecordTooLarge)
+        }
+        if record.len() > self.contiguous_free() + self.fragmented() + len {
+            return Err(SlottedPageError::PageFull)
+        }
+
+        // Release the old copy first so compaction can reclaim it if needed.
+        self.set_slot(slot, 0, 0);
+        self.write_u16(FRAGMENTED, (self.fragmented() + len) as u16);
+        self.make_room(record.len())?;
+        let offset = self.place(record);
+        self.set_slot(slot, offset, record.len());
+        Ok(())
+    }
+
+    /// Insert `record` at directory position `pos`, shifting later slots up by one. Meant for pages that keep
+    /// records in a caller-defined order and never leave empty slots.
+    pub fn insert_at(&mut self, pos: SlotId, record: &[u8]) -> Result<(), SlottedPageError> {
+        let count = self.slot_count();
+        if pos > count {
+            return Err(SlottedPageError::InvalidSlot)
+        }
+        if record.len() > self.max_record_len() {
+            return Err(SlottedPageError::RecordTooLarge)
+        }
+        self.make_room(record.len() + SLOT_LEN)?;
+        let start = self.slot_position(pos);
+        let end = self.slot_position(count);
+        self.data.copy_within(start..end, start + SLOT_LEN);
+        self.write_u16(SLOT_COUNT, count + 1);
+        let offset = self.place(record);
+        self.set_slot(pos, offset, record.len());
+        Ok(())
+    }
+
+    /// Remove the record at directory position `pos`, shifting later slots down by one.
+    pub fn remove_at(&mut self, pos: SlotId) -> Result<(), SlottedPageError> {
+        let (_, len) = self.slot(pos).ok_or(SlottedPageError::InvalidSlot)?;
+        let count = self.slot_count();
+        let start = self.slot_position(pos + 1);
+        let end = self.slot_position(count);
+        self.data.copy_within(start..end, start - SLOT_LEN);
+        self.write_u16(SLOT_COUNT, count - 1);
+        self.write_u16(FRAGMENTED, (self.fragmented() + len) as u16);
+        Ok(())
+    }
+
+    /// Move all records to the end of the page, reclaiming fragmented space. Slot ids are preserved.
+    pub fn compact(&mut self) {
+        let copy: Data = *self.data;
+        let view = SlottedPage::new(&copy);
+        let mut free_end = PAGE_SIZE;
+        for (slot, record) in view.iter() {
+            free_end -= record.len();
+            self.data[free_end..free_end + record.len()].copy_from_slice(record);
+            self.set_slot(slot, free_end, record.len());
+        }
+        self.write_u16(FREE_END, free_end as u16);
+        self.write_u16(FRAGMENTED, 0);
+    }
+
+    fn make_room(&mut self, needed: usize) -> Result<(), SlottedPageError> {
+        if self.contiguous_free() >= needed {
+            return Ok(())
+        }
+        if self.contiguous_free() + self.fragmented() < needed {
+            return Err(SlottedPageError::PageFull)
+        }
+        self.compact();
+        Ok(())
+    }
+
+    fn place(&mut self, record: &[u8]) -> usize {
+        let offset = self.free_end() - record.len();
+        self.data[offset..offset + record.len()].copy_from_slice(record);
+        self.write_u16(FREE_END, offset as u16);
+        offset
+    }
+
+    fn set_slot(&mut self, slot: SlotId, offset: usize, len: usize) {
+        let at = self.slot_position(slot);
+        self.write_u16(at, offset as u16);
+        self.write_u16(at + 2, len as u16);
+    }
+
+    fn write_u16(&mut self, at: usize, v: u16) {
+        self.data[at..at + 2].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page_store::PAGE_SIZE;
+
+    use super::{SlottedPage, SlottedPageError};
+
+    #[test]
+    fn test_insert_get_delete() -> Result<(), SlottedPageError> {
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut page = SlottedPage::init(&mut buf, 0);
+
+        let a = page.insert(b"hello")?;
+        let b = page.insert(b"world!")?;
+        assert_eq!(page.get(a), Some(&b"hello"[..]));
+        assert_eq!(page.get(b), Some(&b"world!"[..]));
+
+        page.delete(a)?;
+        assert_eq!(page.get(a), None);
+        assert_eq!(page.delete(a), Err(SlottedPageError::InvalidSlot));
+        assert_eq!(page.insert(b"again")?, a);
+        assert_eq!(page.iter().map(|(s, _)| s).collect::<Vec<_>>(), vec![a, b]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_reclaims_deleted_space() -> Result<(), SlottedPageError> {
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut page = SlottedPage::init(&mut buf, 0);
+        let record = [7u8; 1000];
+
+        let slots: Vec<_> = (0..4).map(|_| page.insert(&record)).collect::<Result<_, _>>()?;
+        assert_eq!(page.insert(&record), Err(SlottedPageError::PageFull));
+
+        page.delete(slots[0])?;
+        page.delete(slots[2])?;
+        let big = [9u8; 1900];
+        let slot = page.insert(&big)?;
+        assert_eq!(page.get(slot), Some(&big[..]));
+        assert_eq!(page.get(slots[1]), Some(&record[..]));
+        assert_eq!(page.get(slots[3]), Some(&record[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_positional_insert_and_remove() -> Result<(), SlottedPageError> {
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut page = SlottedPage::init(&mut buf, 0);
+        page.insert_at(0, b"c")?;
+        page.insert_at(0, b"a")?;
+        page.insert_at(1, b"b")?;
+        page.insert_at(3, b"d")?;
+        assert_eq!(page.iter().map(|(_, r)| r).collect::<Vec<_>>(), vec![b"a", b"b", b"c", b"d"]);
+
+        page.remove_at(1)?;
+        assert_eq!(page.iter().map(|(_, r)| r).collect::<Vec<_>>(), vec![b"a", b"c", b"d"]);
+        assert_eq!(page.insert_at(5, b"x"), Err(SlottedPageError::InvalidSlot));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_grows_and_shrinks() -> Result<(), SlottedPageError> {
+        let mut buf = [0u8; PAGE_SIZE];
+        let mut page = SlottedPage::init(&mut buf, 16);
+        page.reserved_mut().copy_from_slice(&[1u8; 16]);
+
+        let a = page.insert(b"short")?;
+        let b = page.insert(&[3u8; 2000])?;
+        page.update(a, &[5u8; 1500])?;
+        assert_eq!(page.get(a), Some(&[5u8; 1500][..]));
+        page.update(b, b"tiny")?;
+        page.update(a, &[6u8; 3000])?;
+        assert_eq!(page.get(a), Some(&[6u8; 3000][..]));
+        assert_eq!(page.get(b), Some(&b"tiny"[..]));
+        assert_eq!(page.update(b, &[0u8; 4096]), Err(SlottedPageError::RecordTooLarge));
+        assert_eq!(page.reserved(), &[1u8; 16]);
+        Ok(())
+    }
+}
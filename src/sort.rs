@@ -0,0 +1,383 @@
+//! External merge sort: sorting more items than fit in memory.
+//!
+//! An `ExternalSorter` buffers pushed items until their encoded size passes the memory budget, then
+//! sorts the buffer and writes it out as a run, a sequence of pages holding the items one after another
+//! as length-prefixed records that may straddle pages. When all items are in, runs are merged
+//! `fan_in` at a time into longer runs until at most `fan_in` are left, and those are merged as the
+//! sorted items are read, one page of each run in memory at a time. Nothing is written at all when the
+//! items fit in the budget. Runs are written to a `TempSpace`, in a scope of the sorter's own: pages go
+//! back as soon as their items have been read, and the rest when the sorted iterator is dropped.
+//!
+//! The sort is stable: items that compare equal come out in the order they were pushed. The items
+//! written to runs must implement `Spill`, which is provided for byte strings, integers and pairs of
+//! them, so index builds can sort `(key, value)` entries and ORDER BY can sort encoded sort keys paired
+//! with their rows.
+//!
+//! A sorter given a `MemoryBudget` counts its buffer against the budget as well, and writes a run whenever
+//! the budget has no room for the next item, however little the buffer holds; the items a sort keeps in
+//! memory to the end stay counted until its sorted iterator is dropped.
+//!
+//! Runs are not only for sorting: hash joins write the partitions they spill as runs too, through the
+//! crate-private `RunWriter` and `RunReader`.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::marker::PhantomData;
+
+use crate::bytes::{read_u16, write_u16};
+use crate::memory::{MemoryBudget, Reservation};
+use crate::page_store::{PageError, PageId, PAGE_SIZE};
+use crate::storage::Storage;
+use crate::temp::{TempScope, TempSpace};
+use crate::varint;
+
+const LEN: usize = 0;
+const DATA: usize = 2;
+const CHUNK_LEN: usize = PAGE_SIZE - DATA;
+
+#[derive(Debug, PartialEq)]
+pub enum SortError {
+    Page(PageError),
+    /// A run holds bytes that do not decode as an item.
+    Corrupt,
+    /// The fan-in is below two, so runs could never be merged down.
+    InvalidOptions,
+}
+impl From<PageError> for SortError {
+    fn from(e: PageError) -> Self {
+        SortError::Page(e)
+    }
+}
+
+/// Items that can be written to a run and read back.
+pub trait Spill: Sized {
+    /// Append the encoding of the item to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+    /// Decode an item from the whole of `bytes`, `None` if they are not one.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+impl Spill for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+impl Spill for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(u64::from_be_bytes(bytes.try_into().ok()?))
+    }
+}
+impl<A: Spill, B: Spill> Spill for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut first = Vec::new();
+        self.0.encode(&mut first);
+        varint::write_prefixed(out, &first);
+        self.1.encode(out);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let (first, len) = varint::read_prefixed(bytes)?;
+        Some((A::decode(first)?, B::decode(&bytes[len..])?))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SortOptions {
+    /// Write a run once the buffered items take this many bytes encoded.
+    pub memory_bytes: usize,
+    /// Most runs merged at once, each with a page of it in memory.
+    pub fan_in: usize,
+}
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions { memory_bytes: 4 * 1024 * 1024, fan_in: 16 }
+    }
+}
+
+pub struct ExternalSorter<'space, S: Storage, T: Ord + Spill> {
+    scope: TempScope<'space, S>,
+    options: SortOptions,
+    buffer: Vec<T>,
+    buffered_bytes: usize,
+    /// Runs written so far, in push order.
+    runs: Vec<Vec<PageId>>,
+    scratch: Vec<u8>,
+    /// What the buffer holds of the budget the sorter was given, if any.
+    reservation: Option<Reservation<'space>>,
+}
+impl<'space, S: Storage, T: Ord + Spill> ExternalSorter<'space, S, T> {
+    /// A sorter that writes its runs to pages of `space`.
+    pub fn new(space: &'space TempSpace<S>, options: SortOptions) -> Result<ExternalSorter<'space, S, T>, SortError> {
+        if options.fan_in < 2 {
+            return Err(SortError::InvalidOptions)
+        }
+        let (buffer, runs, scratch) = (Vec::new(), Vec::new(), Vec::new());
+        let (scope, reservation) = (space.scope(), None);
+        Ok(ExternalSorter { scope, options, buffer, buffered_bytes: 0, runs, scratch, reservation })
+    }
+
+    /// A sorter that writes its runs to pages of `space`, and counts its buffer against `budget` too.
+    pub fn with_budget(
+        space: &'space TempSpace<S>,
+        options: SortOptions,
+        budget: &'space MemoryBudget,
+    ) -> Result<ExternalSorter<'space, S, T>, SortError> {
+        let mut sorter = ExternalSorter::new(space, options)?;
+        sorter.reservation = Some(budget.reservation());
+        Ok(sorter)
+    }
+
+    /// Number of runs written so far.
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn push(&mut self, item: T) -> Result<(), SortError> {
+        self.scratch.clear();
+        item.encode(&mut self.scratch);
+        self.buffered_bytes += self.scratch.len();
+        self.buffer.push(item);
+        let fits = self.reservation.as_mut().is_none_or(|r| r.try_grow(self.scratch.len()));
+        if !fits || self.buffered_bytes > self.options.memory_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Every item pushed, in order.
+    pub fn finish(mut self) -> Result<Sorted<'space, S, T>, SortError> {
+        if self.runs.is_empty() {
+            self.buffer.sort();
+            let items = Items::Memory(self.buffer.into_iter());
+            return Ok(Sorted { scope: self.scope, items, _reservation: self.reservation })
+        }
+        self.spill()?;
+        while self.runs.len() > self.options.fan_in {
+            // Merging the oldest runs into one that takes their place keeps equal items in push order.
+            let runs: Vec<_> = self.runs.drain(..self.options.fan_in).collect();
+            let mut merge: Merge<T> = Merge::new(&mut self.scope, runs)?;
+            let mut writer = RunWriter::new();
+            while let Some(item) = merge.next_item(&mut self.scope)? {
+                writer.push(&mut self.scope, &item)?;
+            }
+            self.runs.insert(0, writer.finish(&mut self.scope)?);
+        }
+        let merge = Merge::new(&mut self.scope, self.runs)?;
+        Ok(Sorted { scope: self.scope, items: Items::Merge(merge), _reservation: self.reservation })
+    }
+
+    /// Sort the buffer and write it out as a run.
+    fn spill(&mut self) -> Result<(), SortError> {
+        if self.buffer.is_empty() {
+            return Ok(())
+        }
+        self.buffer.sort();
+        let mut writer = RunWriter::new();
+        for item in self.buffer.drain(..) {
+            writer.push(&mut self.scope, &item)?;
+        }
+        self.runs.push(writer.finish(&mut self.scope)?);
+        self.buffered_bytes = 0;
+        self.reservation.iter_mut().for_each(Reservation::clear);
+        Ok(())
+    }
+}
+
+/// Writes items to new pages as a stream of length-prefixed records.
+pub(crate) struct RunWriter {
+    pages: Vec<PageId>,
+    pending: Vec<u8>,
+    scratch: Vec<u8>,
+}
+impl RunWriter {
+    pub(crate) fn new() -> RunWriter {
+        RunWriter { pages: Vec::new(), pending: Vec::new(), scratch: Vec::new() }
+    }
+
+    pub(crate) fn push<S: Storage>(&mut self, scope: &mut TempScope<S>, item: &impl Spill) -> Result<(), PageError> {
+        self.scratch.clear();
+        item.encode(&mut self.scratch);
+        varint::write_prefixed(&mut self.pending, &self.scratch);
+        while self.pending.len() >= CHUNK_LEN {
+            self.write_page(scope, CHUNK_LEN)?;
+        }
+        Ok(())
+    }
+
+    /// The pages of the run, in order.
+    pub(crate) fn finish<S: Storage>(mut self, scope: &mut TempScope<S>) -> Result<Vec<PageId>, PageError> {
+        if !self.pending.is_empty() {
+            self.write_page(scope, self.pending.len())?;
+        }
+        Ok(self.pages)
+    }
+
+    fn write_page<S: Storage>(&mut self, scope: &mut TempScope<S>, len: usize) -> Result<(), PageError> {
+        let page = scope.allocate()?;
+        let mut buf = page.try_write()?;
+        write_u16(&mut *buf, LEN, len as u16);
+        buf[DATA..DATA + len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        self.pages.push(page.id());
+        Ok(())
+    }
+}
+
+/// Reads a run's items back, freeing each page once it has been read.
+pub(crate) struct RunReader<T> {
+    pages: std::vec::IntoIter<PageId>,
+    bytes: Vec<u8>,
+    at: usize,
+    item: PhantomData<T>,
+}
+impl<T: Spill> RunReader<T> {
+    pub(crate) fn new(pages: Vec<PageId>) -> RunReader<T> {
+        RunReader { pages: pages.into_iter(), bytes: Vec::new(), at: 0, item: PhantomData }
+    }
+
+    pub(crate) fn next_item<S: Storage>(&mut self, scope: &mut TempScope<S>) -> Result<Option<T>, SortError> {
+        loop {
+            if let Some((record, len)) = varint::read_prefixed(&self.bytes[self.at..]) {
+                self.at += len;
+                return T::decode(record).map(Some).ok_or(SortError::Corrupt)
+            }
+            let Some(id) = self.pages.next() else {
+                return if self.at == self.bytes.len() { Ok(None) } else { Err(SortError::Corrupt) }
+            };
+            self.bytes.drain(..self.at);
+            self.at = 0;
+            {
+                let page = scope.store().pin_page(&id)?;
+                let buf = page.try_read()?;
+                let len = (read_u16(&*buf, LEN) as usize).min(CHUNK_LEN);
+                self.bytes.extend_from_slice(&buf[DATA..DATA + len]);
+            }
+            scope.free(id);
+        }
+    }
+}
+
+/// Merges runs, taking the smallest head item each time and the earliest run's among equal ones.
+struct Merge<T> {
+    readers: Vec<RunReader<T>>,
+    heads: BinaryHeap<Reverse<(T, usize)>>,
+}
+impl<T: Ord + Spill> Merge<T> {
+    fn new<S: Storage>(scope: &mut TempScope<S>, runs: Vec<Vec<PageId>>) -> Result<Merge<T>, SortError> {
+        let readers = runs.into_iter().map(RunReader::new).collect();
+        let mut merge = Merge { readers, heads: BinaryHeap::new() };
+        for run in 0..merge.readers.len() {
+            merge.advance(scope, run)?;
+        }
+        Ok(merge)
+    }
+
+    fn next_item<S: Storage>(&mut self, scope: &mut TempScope<S>) -> Result<Option<T>, SortError> {
+        let Some(Reverse((item, run))) = self.heads.pop() else { return Ok(None) };
+        self.advance(scope, run)?;
+        Ok(Some(item))
+    }
+
+    /// Put the next item of `run`, if it has one, among the heads.
+    fn advance<S: Storage>(&mut self, scope: &mut TempScope<S>, run: usize) -> Result<(), SortError> {
+        if let Some(item) = self.readers[run].next_item(scope)? {
+            self.heads.push(Reverse((item, run)));
+        }
+        Ok(())
+    }
+}
+
+enum Items<T> {
+    Memory(std::vec::IntoIter<T>),
+    Merge(Merge<T>),
+    Failed,
+}
+
+/// The sorted items of an `ExternalSorter`. Stops after the first error.
+pub struct Sorted<'space, S: Storage, T> {
+    /// Holds the pages of the runs not yet read, which are given back when this is dropped.
+    scope: TempScope<'space, S>,
+    items: Items<T>,
+    /// Holds the budget for the items kept in memory until this is dropped.
+    _reservation: Option<Reservation<'space>>,
+}
+impl<S: Storage, T> Sorted<'_, S, T> {
+    /// Whether the items were sorted without writing any runs.
+    pub fn in_memory(&self) -> bool {
+        matches!(self.items, Items::Memory(_))
+    }
+}
+impl<S: Storage, T: Ord + Spill> Iterator for Sorted<'_, S, T> {
+    type Item = Result<T, SortError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match &mut self.items {
+            Items::Memory(items) => return items.next().map(Ok),
+            Items::Merge(merge) => merge.next_item(&mut self.scope),
+            Items::Failed => return None,
+        };
+        if result.is_err() {
+            self.items = Items::Failed;
+        }
+        result.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::MemoryBudget;
+    use crate::storage::TestStorage;
+    use crate::temp::TempSpace;
+
+    use super::{ExternalSorter, SortError, SortOptions};
+
+    #[test]
+    fn test_sorts_beyond_memory_budget() -> Result<(), SortError> {
+        let space = TempSpace::new(TestStorage::new());
+        let options = SortOptions { memory_bytes: 16 * 1024, fan_in: 3 };
+        let mut sorter = ExternalSorter::new(&space, options)?;
+        let mut items = Vec::new();
+        for i in 0..20_000u64 {
+            let key = (i * 7919 % 5003).to_be_bytes().to_vec();
+            items.push((key.clone(), i));
+            sorter.push((key, i))?;
+        }
+        assert!(sorter.run_count() > 9);
+        let sorted = sorter.finish()?;
+        assert!(!sorted.in_memory());
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(sorted.collect::<Result<Vec<_>, _>>()?, items);
+        assert_eq!(space.pages_in_use(), 0);
+        // Abandoning a sort part way through gives back the pages of the runs left unread.
+        let mut abandoned = ExternalSorter::new(&space, options)?;
+        items.iter().try_for_each(|item| abandoned.push(item.clone()))?;
+        let mut sorted = abandoned.finish()?;
+        assert_eq!(sorted.next(), Some(Ok(items[0].clone())));
+        assert!(space.pages_in_use() > 0);
+        drop(sorted);
+        assert_eq!(space.pages_in_use(), 0);
+        // A budget with no room for the next item makes a run of a buffer well inside the sorter's own limit.
+        let budget = MemoryBudget::new(16 * 1024);
+        let mut budgeted = ExternalSorter::with_budget(&space, SortOptions::default(), &budget)?;
+        items.iter().try_for_each(|item| budgeted.push(item.clone()))?;
+        assert!(budgeted.run_count() > 9);
+        assert_eq!(budgeted.finish()?.collect::<Result<Vec<_>, _>>()?, items);
+        assert_eq!((budget.used(), budget.peak() <= 16 * 1024), (0, true));
+
+        let mut small = ExternalSorter::new(&space, SortOptions::default())?;
+        for i in (0..100u64).rev() {
+            small.push(i)?;
+        }
+        let sorted = small.finish()?;
+        assert!(sorted.in_memory());
+        assert_eq!(sorted.collect::<Result<Vec<_>, _>>()?, (0..100).collect::<Vec<_>>());
+        assert!(ExternalSorter::<_, u64>::new(&space, SortOptions { memory_bytes: 1, fan_in: 1 }).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,771 @@
+//! The syntax tree the parser builds: statements and the expressions inside them, with names still
+//! unresolved. Identifiers are stored as written, unquoted ones folded to lower case.
+//!
+//! An `Expr` displays as SQL that parses back to an expression with the same value: every operation is
+//! parenthesized, and a name is quoted unless it reads as itself unquoted. A `Select` displays the same
+//! way, for a view to keep its query as SQL.
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::catalog::{TriggerEvent, TriggerTiming};
+use crate::collation::Collation;
+use crate::datetime;
+use crate::table::IndexKind;
+use crate::tuple::{ColumnType, Value};
+
+use super::parser::RESERVED;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Box<Select>),
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    DropTable { name: String, if_exists: bool },
+    CreateView(CreateView),
+    DropView { name: String, if_exists: bool },
+    CreateSequence(CreateSequence),
+    DropSequence { name: String, if_exists: bool },
+    /// Index names are only unique within their table, so dropping one names both.
+    DropIndex { name: String, table: String },
+    CreateTrigger(CreateTrigger),
+    /// Trigger names are only unique within their table too.
+    DropTrigger { name: String, table: String },
+    AlterTable { table: String, change: AlterColumn },
+    /// `EXPLAIN [ANALYZE]` of a query: its plan, and with `analyze` what the plan did when run.
+    Explain { analyze: bool, select: Box<Select> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub with: Option<With>,
+    pub distinct: Option<Distinct>,
+    pub items: Vec<SelectItem>,
+    pub from: Option<FromItem>,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+/// `WITH [RECURSIVE] name [(a, b)] AS (query), ...`: queries named for the rest of the query to read as
+/// tables, each reading those before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct With {
+    /// `RECURSIVE`: each query may also read itself, in the term after its `UNION`.
+    pub recursive: bool,
+    pub ctes: Vec<Cte>,
+}
+
+/// One common table expression of a `WITH`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cte {
+    pub name: String,
+    /// The names its columns go by, or empty for those its query gives them.
+    pub columns: Vec<String>,
+    pub query: Box<Select>,
+    pub union: Option<Union>,
+}
+
+/// `UNION [ALL] query` after a common table expression's query: under `RECURSIVE`, the term that reads the
+/// rows the round before it added, until a round adds none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Union {
+    /// `ALL` keeps rows equal to ones already there, which `UNION` alone drops.
+    pub all: bool,
+    pub query: Box<Select>,
+}
+
+/// Which rows `SELECT DISTINCT` keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distinct {
+    /// `DISTINCT`: one of each set of equal output rows.
+    Rows,
+    /// `DISTINCT ON (a, b)`: the first row, in the query's order, of each set of rows equal on these.
+    On(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+    /// `*`, or `t.*` for the columns of one table.
+    Wildcard(Option<String>),
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FromItem {
+    Table { name: String, alias: Option<String> },
+    /// A query in parentheses, read as a table under `alias`.
+    Derived { query: Box<Select>, alias: String },
+    Join { left: Box<FromItem>, right: Box<FromItem>, kind: JoinKind, on: Option<Expr> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    /// Every pair of rows, written `CROSS JOIN` or as a comma; never has an `on` condition.
+    Cross,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+    /// `NULLS FIRST` or `NULLS LAST`, if given.
+    pub nulls_first: Option<bool>,
+}
+
+/// `OVER (PARTITION BY .. ORDER BY .. [frame])`: the rows a window function reads for each row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderBy>,
+    pub frame: Option<Frame>,
+}
+
+/// `ROWS` or `RANGE BETWEEN start AND end`: which rows of its partition around a row an aggregate over a
+/// window reads. `RANGE` counts peers, rows equal in `ORDER BY`, as one, so its `CURRENT ROW` reaches the
+/// row's first or last peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub range: bool,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(u64),
+    CurrentRow,
+    Following(u64),
+    UnboundedFollowing,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub table: String,
+    /// The columns the values are for, or empty for all of them in order.
+    pub columns: Vec<String>,
+    pub source: InsertSource,
+    pub on_conflict: Option<OnConflict>,
+}
+
+/// Where an `INSERT` takes its rows from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertSource {
+    /// `VALUES (1, 'a'), (2, 'b')`.
+    Values(Vec<Vec<Expr>>),
+    /// `SELECT ...`, whose output rows are inserted.
+    Query(Box<Select>),
+}
+
+/// `ON CONFLICT [(a, b)] DO ...`: what an `INSERT` does with a row whose key a unique index already holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnConflict {
+    /// The columns of the unique index whose conflicts it handles, or empty for every unique index.
+    pub target: Vec<String>,
+    pub action: ConflictAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictAction {
+    /// `DO NOTHING`: skip the row.
+    Nothing,
+    /// `DO UPDATE SET a = ... [WHERE ...]`: update the row already holding the key, if the filter holds for
+    /// it. Both read the row that was to be inserted as the table `excluded`.
+    Update { assignments: Vec<(String, Expr)>, filter: Option<Expr> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub table: String,
+    pub assignments: Vec<(String, Expr)>,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub table: String,
+    pub filter: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<ColumnSpec>,
+    /// Constraints declared on the table, and those declared on a single column after its type.
+    pub constraints: Vec<TableConstraint>,
+    pub if_not_exists: bool,
+    /// `TTL column + lifetime`: rows expire once `lifetime` has passed since the time in `column`.
+    pub ttl: Option<(String, Expr)>,
+    /// `WITHOUT ROWID`: the rows are kept in a tree keyed by the table's primary key instead of in a heap.
+    pub without_rowid: bool,
+    /// `PARTITION BY`: how the rows are split over partitions.
+    pub partition_by: Option<Partitioning>,
+}
+
+/// `PARTITION BY` in `CREATE TABLE`: how the table's rows are split over partitions by one column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Partitioning {
+    /// `RANGE (column)`: each partition holds a range of the column, and is added with `ADD PARTITION FROM`.
+    Range(String),
+    /// `HASH (column) PARTITIONS n`: `n` partitions, made with the table, each taking the rows the column's
+    /// hash sends it.
+    Hash { column: String, partitions: usize },
+}
+
+/// A constraint in `CREATE TABLE`, named by `CONSTRAINT name` if it has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableConstraint {
+    pub name: Option<String>,
+    pub kind: ConstraintKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstraintKind {
+    PrimaryKey(Vec<String>),
+    Unique(Vec<String>),
+    /// `FOREIGN KEY (columns) REFERENCES table (references)`. With no `references` listed, the columns
+    /// referenced are those of the other table's primary key.
+    ForeignKey { columns: Vec<String>, table: String, references: Vec<String>, on_delete: ReferentialAction },
+}
+
+/// What `ON DELETE` does to the rows referencing a deleted row. `NO ACTION` is `Restrict`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferentialAction {
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+    /// The `DEFAULT` expression, if it has one.
+    pub default: Option<Expr>,
+    /// `AUTOINCREMENT` or `GENERATED BY DEFAULT AS IDENTITY`: the column takes the next value of a sequence
+    /// made for it when a row gives it none.
+    pub identity: bool,
+    /// `COLLATE name`, or binary.
+    pub collation: Collation,
+}
+
+/// `CREATE SEQUENCE name [START [WITH] n] [INCREMENT [BY] n] [CACHE n]`, with each option left out `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateSequence {
+    pub name: String,
+    pub start: Option<i64>,
+    pub increment: Option<i64>,
+    pub cache: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateView {
+    pub name: String,
+    /// Names for the query's output columns, or empty to keep the query's own.
+    pub columns: Vec<String>,
+    pub query: Box<Select>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    /// `CREATE UNIQUE INDEX`.
+    pub unique: bool,
+    pub table: String,
+    /// `USING HASH` or `USING BTREE`, the latter when left out.
+    pub kind: IndexKind,
+    /// The index's key: each a column or, in parentheses, a path into a JSON column.
+    pub columns: Vec<Expr>,
+    /// Columns named by `INCLUDE (..)`, stored in the index without being part of its key.
+    pub include: Vec<String>,
+}
+
+/// `CREATE TRIGGER name {BEFORE | AFTER} {INSERT | UPDATE | DELETE} ON table [FOR EACH ROW] BEGIN ... END`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTrigger {
+    pub name: String,
+    pub timing: TriggerTiming,
+    pub event: TriggerEvent,
+    pub table: String,
+    /// The statements between `BEGIN` and `END`, as written.
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlterColumn {
+    Add(ColumnSpec),
+    Drop(String),
+    SetNullable { column: String, nullable: bool },
+    /// `SET DEFAULT`, or `DROP DEFAULT` with no default.
+    SetDefault { column: String, default: Option<Expr> },
+    AddConstraint(TableConstraint),
+    DropConstraint(String),
+    /// `ADD PARTITION FROM lower`: a range partition for the rows from `lower` up to the next one's bound.
+    AddPartition(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Column { table: Option<String>, name: String },
+    /// A bind parameter, numbered from 0: `$n` is parameter `n - 1`, and each `?` takes the number after the
+    /// highest one before it.
+    Parameter(usize),
+    Unary { op: UnaryOp, expr: Box<Expr> },
+    Binary { op: BinaryOp, left: Box<Expr>, right: Box<Expr> },
+    IsNull { expr: Box<Expr>, negated: bool },
+    Like { expr: Box<Expr>, pattern: Box<Expr>, negated: bool },
+    InList { expr: Box<Expr>, list: Vec<Expr>, negated: bool },
+    Between { expr: Box<Expr>, low: Box<Expr>, high: Box<Expr>, negated: bool },
+    /// A function call such as `lower(name)` or an aggregate such as `count(DISTINCT id)`. `count(*)` has
+    /// no arguments.
+    Function { name: String, args: Vec<Expr>, distinct: bool },
+    /// A call of a window function such as `rank()`, or of an aggregate, `OVER` a window of the rows around
+    /// each row rather than over a group.
+    Window { name: String, args: Vec<Expr>, over: Window },
+    /// `CASE [operand] WHEN .. THEN .. [ELSE ..] END`.
+    Case { operand: Option<Box<Expr>>, branches: Vec<(Expr, Expr)>, otherwise: Option<Box<Expr>> },
+    Cast { expr: Box<Expr>, to: ColumnType },
+    /// `expr COLLATE name`: text to compare and sort by `collation` rather than its column's.
+    Collate { expr: Box<Expr>, collation: Collation },
+    /// A query in parentheses standing for the one value of its one column: null if it returns no rows.
+    Subquery(Box<Select>),
+    /// `EXISTS (query)`: whether the query returns any row.
+    Exists(Box<Select>),
+    /// `expr [NOT] IN (query)`, over the values of the query's one column.
+    InSubquery { expr: Box<Expr>, query: Box<Select>, negated: bool },
+}
+
+impl Statement {
+    /// Call `f` on each expression of a query or of a write, and on those inside them as `Expr::visit`
+    /// does. The expressions of other statements are left alone.
+    pub fn visit_exprs(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        match self {
+            Statement::Select(select) | Statement::Explain { select, .. } => select.visit_exprs(f),
+            Statement::Insert(insert) => {
+                match &mut insert.source {
+                    InsertSource::Values(rows) => rows.iter_mut().flatten().for_each(|e| e.visit(f)),
+                    InsertSource::Query(select) => select.visit_exprs(f),
+                }
+                if let Some(OnConflict { action: ConflictAction::Update { assignments, filter }, .. }) =
+                    &mut insert.on_conflict
+                {
+                    assignments.iter_mut().map(|(_, e)| e).chain(filter).for_each(|e| e.visit(f));
+                }
+            }
+            Statement::Update(update) => {
+                update.assignments.iter_mut().map(|(_, e)| e).chain(&mut update.filter).for_each(|e| e.visit(f))
+            }
+            Statement::Delete(delete) => delete.filter.iter_mut().for_each(|e| e.visit(f)),
+            _ => {}
+        }
+    }
+}
+
+impl Select {
+    /// Call `f` on each expression of the query, its common table expressions and the queries in its
+    /// `FROM` included, as `Expr::visit` does.
+    pub fn visit_exprs(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        if let Some(with) = &mut self.with {
+            for cte in &mut with.ctes {
+                cte.query.visit_exprs(f);
+                cte.union.iter_mut().for_each(|u| u.query.visit_exprs(f));
+            }
+        }
+        if let Some(Distinct::On(on)) = &mut self.distinct {
+            on.iter_mut().for_each(|e| e.visit(f));
+        }
+        for item in &mut self.items {
+            if let SelectItem::Expr { expr, .. } = item {
+                expr.visit(f);
+            }
+        }
+        if let Some(from) = &mut self.from {
+            visit_from(from, f);
+        }
+        let order_by = self.order_by.iter_mut().map(|o| &mut o.expr);
+        let clauses = self.filter.iter_mut().chain(&mut self.group_by).chain(&mut self.having).chain(order_by);
+        clauses.chain(&mut self.limit).chain(&mut self.offset).for_each(|e| e.visit(f));
+    }
+}
+
+fn visit_from(item: &mut FromItem, f: &mut impl FnMut(&mut Expr)) {
+    match item {
+        FromItem::Table { .. } => {}
+        FromItem::Derived { query, .. } => query.visit_exprs(f),
+        FromItem::Join { left, right, on, .. } => {
+            visit_from(left, f);
+            visit_from(right, f);
+            on.iter_mut().for_each(|e| e.visit(f));
+        }
+    }
+}
+
+impl Expr {
+    /// Call `f` on this expression and then on each one inside it, those of its subqueries included. What
+    /// `f` leaves in place of an expression is what is visited inside it.
+    pub fn visit(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        f(self);
+        match self {
+            Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) => {}
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => expr.visit(f),
+            Expr::Collate { expr, .. } => expr.visit(f),
+            Expr::Binary { left, right, .. } | Expr::Like { expr: left, pattern: right, .. } => {
+                left.visit(f);
+                right.visit(f);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.visit(f);
+                list.iter_mut().for_each(|e| e.visit(f));
+            }
+            Expr::Between { expr, low, high, .. } => [expr, low, high].into_iter().for_each(|e| e.visit(f)),
+            Expr::Function { args, .. } => args.iter_mut().for_each(|e| e.visit(f)),
+            Expr::Window { args, over, .. } => {
+                let order_by = over.order_by.iter_mut().map(|o| &mut o.expr);
+                args.iter_mut().chain(&mut over.partition_by).chain(order_by).for_each(|e| e.visit(f))
+            }
+            Expr::Case { operand, branches, otherwise } => {
+                operand.iter_mut().for_each(|e| e.visit(f));
+                for (when, then) in branches {
+                    when.visit(f);
+                    then.visit(f);
+                }
+                otherwise.iter_mut().for_each(|e| e.visit(f));
+            }
+            Expr::Subquery(query) | Expr::Exists(query) => query.visit_exprs(f),
+            Expr::InSubquery { expr, query, .. } => {
+                expr.visit(f);
+                query.visit_exprs(f);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let not = |negated: &bool| if *negated { "NOT " } else { "" };
+        match self {
+            Expr::Literal(v) => literal(f, v),
+            Expr::Column { table: Some(table), name } => write!(f, "{}.{}", Ident(table), Ident(name)),
+            Expr::Column { table: None, name } => write!(f, "{}", Ident(name)),
+            Expr::Parameter(n) => write!(f, "${}", n + 1),
+            Expr::Unary { op: UnaryOp::Neg, expr } => write!(f, "(-{expr})"),
+            Expr::Unary { op: UnaryOp::Not, expr } => write!(f, "(NOT {expr})"),
+            Expr::Binary { op, left, right } => write!(f, "({left} {} {right})", op.symbol()),
+            Expr::IsNull { expr, negated } => write!(f, "({expr} IS {}NULL)", not(negated)),
+            Expr::Like { expr, pattern, negated } => write!(f, "({expr} {}LIKE {pattern})", not(negated)),
+            Expr::InList { expr, list, negated } => {
+                write!(f, "({expr} {}IN (", not(negated))?;
+                comma_separated(f, list)?;
+                write!(f, "))")
+            }
+            Expr::Between { expr, low, high, negated } => {
+                write!(f, "({expr} {}BETWEEN {low} AND {high})", not(negated))
+            }
+            Expr::Function { name, args, distinct } => {
+                write!(f, "{}({}", Ident(name), if *distinct { "DISTINCT " } else { "" })?;
+                if args.is_empty() && name.eq_ignore_ascii_case("count") {
+                    write!(f, "*")?;
+                }
+                comma_separated(f, args)?;
+                write!(f, ")")
+            }
+            Expr::Window { name, args, over } => {
+                write!(f, "{}(", Ident(name))?;
+                if args.is_empty() && name.eq_ignore_ascii_case("count") {
+                    write!(f, "*")?;
+                }
+                comma_separated(f, args)?;
+                write!(f, ") OVER ({over})")
+            }
+            Expr::Case { operand, branches, otherwise } => {
+                write!(f, "CASE")?;
+                if let Some(operand) = operand {
+                    write!(f, " {operand}")?;
+                }
+                for (when, then) in branches {
+                    write!(f, " WHEN {when} THEN {then}")?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " ELSE {otherwise}")?;
+                }
+                write!(f, " END")
+            }
+            Expr::Cast { expr, to } => write!(f, "CAST({expr} AS {})", type_name(*to)),
+            Expr::Collate { expr, collation } => write!(f, "({expr} COLLATE {})", collation.name()),
+            Expr::Subquery(query) => write!(f, "({query})"),
+            Expr::Exists(query) => write!(f, "EXISTS ({query})"),
+            Expr::InSubquery { expr, query, negated } => write!(f, "({expr} {}IN ({query}))", not(negated)),
+        }
+    }
+}
+
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(With { recursive, ctes }) = &self.with {
+            write!(f, "WITH {}", if *recursive { "RECURSIVE " } else { "" })?;
+            for (i, cte) in ctes.iter().enumerate() {
+                write!(f, "{}{}", if i == 0 { "" } else { ", " }, Ident(&cte.name))?;
+                if !cte.columns.is_empty() {
+                    let columns: Vec<_> = cte.columns.iter().map(|c| Ident(c)).collect();
+                    write!(f, " (")?;
+                    comma_separated(f, &columns)?;
+                    write!(f, ")")?;
+                }
+                write!(f, " AS ({}", cte.query)?;
+                if let Some(Union { all, query }) = &cte.union {
+                    write!(f, " UNION {}{query}", if *all { "ALL " } else { "" })?;
+                }
+                write!(f, ")")?;
+            }
+            write!(f, " ")?;
+        }
+        write!(f, "SELECT ")?;
+        match &self.distinct {
+            None => {}
+            Some(Distinct::Rows) => write!(f, "DISTINCT ")?,
+            Some(Distinct::On(exprs)) => {
+                write!(f, "DISTINCT ON (")?;
+                comma_separated(f, exprs)?;
+                write!(f, ") ")?;
+            }
+        }
+        for (i, item) in self.items.iter().enumerate() {
+            write!(f, "{}", if i == 0 { "" } else { ", " })?;
+            match item {
+                SelectItem::Wildcard(None) => write!(f, "*")?,
+                SelectItem::Wildcard(Some(table)) => write!(f, "{}.*", Ident(table))?,
+                SelectItem::Expr { expr, alias: None } => write!(f, "{expr}")?,
+                SelectItem::Expr { expr, alias: Some(alias) } => write!(f, "{expr} AS {}", Ident(alias))?,
+            }
+        }
+        if let Some(from) = &self.from {
+            write!(f, " FROM {from}")?;
+        }
+        if let Some(filter) = &self.filter {
+            write!(f, " WHERE {filter}")?;
+        }
+        if !self.group_by.is_empty() {
+            write!(f, " GROUP BY ")?;
+            comma_separated(f, &self.group_by)?;
+        }
+        if let Some(having) = &self.having {
+            write!(f, " HAVING {having}")?;
+        }
+        if !self.order_by.is_empty() {
+            write!(f, " ORDER BY ")?;
+            comma_separated(f, &self.order_by)?;
+        }
+        if let Some(limit) = &self.limit {
+            write!(f, " LIMIT {limit}")?;
+        }
+        if let Some(offset) = &self.offset {
+            write!(f, " OFFSET {offset}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.expr, if self.descending { " DESC" } else { "" })?;
+        match self.nulls_first {
+            Some(true) => write!(f, " NULLS FIRST"),
+            Some(false) => write!(f, " NULLS LAST"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut space = "";
+        if !self.partition_by.is_empty() {
+            write!(f, "PARTITION BY ")?;
+            comma_separated(f, &self.partition_by)?;
+            space = " ";
+        }
+        if !self.order_by.is_empty() {
+            write!(f, "{space}ORDER BY ")?;
+            comma_separated(f, &self.order_by)?;
+            space = " ";
+        }
+        match self.frame {
+            Some(Frame { range, start, end }) => {
+                write!(f, "{space}{} BETWEEN {start} AND {end}", if range { "RANGE" } else { "ROWS" })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameBound::UnboundedPreceding => write!(f, "UNBOUNDED PRECEDING"),
+            FrameBound::Preceding(n) => write!(f, "{n} PRECEDING"),
+            FrameBound::CurrentRow => write!(f, "CURRENT ROW"),
+            FrameBound::Following(n) => write!(f, "{n} FOLLOWING"),
+            FrameBound::UnboundedFollowing => write!(f, "UNBOUNDED FOLLOWING"),
+        }
+    }
+}
+
+impl fmt::Display for FromItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FromItem::Table { name, alias: None } => write!(f, "{}", Ident(name)),
+            FromItem::Table { name, alias: Some(alias) } => write!(f, "{} AS {}", Ident(name), Ident(alias)),
+            FromItem::Derived { query, alias } => write!(f, "({query}) AS {}", Ident(alias)),
+            FromItem::Join { left, right, kind, on } => {
+                let kind = match kind {
+                    JoinKind::Inner => "JOIN",
+                    JoinKind::Left => "LEFT JOIN",
+                    JoinKind::Cross => "CROSS JOIN",
+                };
+                write!(f, "{left} {kind} {right}")?;
+                match on {
+                    Some(on) => write!(f, " ON {on}"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// A name, quoted if it would not read as itself unquoted.
+struct Ident<'a>(&'a str);
+impl fmt::Display for Ident<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut chars = self.0.chars();
+        let bare = chars.next().is_some_and(|c| c.is_lowercase() || c == '_')
+            && chars.all(|c| c.is_lowercase() || c.is_numeric() || c == '_')
+            && !RESERVED.contains(&self.0);
+        match bare {
+            true => write!(f, "{}", self.0),
+            false => write!(f, "\"{}\"", self.0.replace('"', "\"\"")),
+        }
+    }
+}
+
+fn literal(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match value {
+        Value::Null => write!(f, "NULL"),
+        Value::Int(v) if *v < 0 => write!(f, "({v})"),
+        Value::Int(v) => write!(f, "{v}"),
+        Value::Float(v) if !v.is_finite() => write!(f, "CAST('{v}' AS DOUBLE)"),
+        // With an exponent, so that it reads back as a float rather than a decimal.
+        Value::Float(v) if *v < 0.0 => write!(f, "({v:e})"),
+        Value::Float(v) => write!(f, "{v:e}"),
+        Value::Bool(v) => write!(f, "{}", if *v { "TRUE" } else { "FALSE" }),
+        Value::Bytes(v) => {
+            write!(f, "x'")?;
+            v.iter().try_for_each(|b| write!(f, "{b:02x}"))?;
+            write!(f, "'")
+        }
+        Value::Text(v) => write!(f, "'{}'", v.replace('\'', "''")),
+        Value::Date(v) => write!(f, "DATE '{}'", datetime::format_date(*v)),
+        Value::Time(v) => write!(f, "TIME '{}'", datetime::format_time(*v)),
+        Value::Timestamp(v) => write!(f, "TIMESTAMP '{}'", datetime::format_timestamp(*v)),
+        Value::TimestampTz(v) => write!(f, "TIMESTAMPTZ '{}'", datetime::format_timestamp_tz(*v)),
+        Value::Interval(v) => write!(f, "INTERVAL '{}'", datetime::format_interval(*v)),
+        // One with no point would read back as an integer.
+        Value::Decimal(v) if v.scale() == 0 => write!(f, "DECIMAL '{v}'"),
+        Value::Decimal(v) if v.is_negative() => write!(f, "({v})"),
+        Value::Decimal(v) => write!(f, "{v}"),
+        Value::Json(v) => write!(f, "JSON '{}'", v.to_string().replace('\'', "''")),
+    }
+}
+
+fn comma_separated(f: &mut fmt::Formatter, exprs: &[impl fmt::Display]) -> fmt::Result {
+    for (i, expr) in exprs.iter().enumerate() {
+        write!(f, "{}{expr}", if i == 0 { "" } else { ", " })?;
+    }
+    Ok(())
+}
+
+/// The name `CREATE TABLE` gives the type by.
+pub(crate) fn type_name(column_type: ColumnType) -> Cow<'static, str> {
+    Cow::Borrowed(match column_type {
+        ColumnType::Int => "BIGINT",
+        ColumnType::Float => "DOUBLE",
+        ColumnType::Bool => "BOOLEAN",
+        ColumnType::Bytes => "BYTES",
+        ColumnType::Text => "TEXT",
+        ColumnType::Date => "DATE",
+        ColumnType::Time => "TIME",
+        ColumnType::Timestamp => "TIMESTAMP",
+        ColumnType::TimestampTz => "TIMESTAMP WITH TIME ZONE",
+        ColumnType::Interval => "INTERVAL",
+        ColumnType::Decimal { precision: 0, .. } => "NUMERIC",
+        ColumnType::Json => "JSON",
+        ColumnType::Decimal { precision, scale } => return Cow::Owned(format!("DECIMAL({precision}, {scale})")),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    /// `||`, string concatenation.
+    Concat,
+    /// `->`, the value of a key of a JSON object or of a position in an array.
+    Extract,
+    /// `->>`, what `->` gives, as text.
+    ExtractText,
+    /// `#>`, the value at the end of a path of keys and positions, written as a text array like `'{a,0}'`.
+    ExtractPath,
+    /// `#>>`, what `#>` gives, as text.
+    ExtractPathText,
+}
+impl BinaryOp {
+    /// The operator as written in SQL.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "OR",
+            BinaryOp::And => "AND",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Concat => "||",
+            BinaryOp::Extract => "->",
+            BinaryOp::ExtractText => "->>",
+            BinaryOp::ExtractPath => "#>",
+            BinaryOp::ExtractPathText => "#>>",
+        }
+    }
+}
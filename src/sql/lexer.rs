@@ -0,0 +1,203 @@
+//! Splits SQL text into tokens, each remembering the byte offset it starts at.
+//!
+//! Unquoted identifiers and keywords are one kind of token; the parser tells them apart. They are folded
+//! to lower case, while double-quoted identifiers keep their case and may contain anything but an
+//! unescaped quote. Strings are single-quoted with `''` for a quote inside, and `x'..'` is a bytes
+//! literal in hex. `--` starts a comment that runs to the end of the line. A number with a point in it is a
+//! decimal, exactly as written, and one with an exponent a float; so is an integer too large for a
+//! BIGINT, as in PostgreSQL.
+use crate::decimal::Decimal;
+
+use super::{ParseError, ParseErrorKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    /// An unquoted word, folded to lower case.
+    Word(String),
+    QuotedIdent(String),
+    Int(i64),
+    /// A number with a fraction and no exponent, as in `1.50`.
+    Decimal(Decimal),
+    /// A number with an exponent, as in `1.5e3`.
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// `?`, or `$n` with its number.
+    Placeholder(Option<usize>),
+    Symbol(&'static str),
+    End,
+}
+
+/// Longest first, so that `<=` is not read as `<` followed by `=`.
+const SYMBOLS: [&str; 22] = [
+    "->>", "#>>", "->", "#>", "<=", ">=", "<>", "!=", "||", "=", "<", ">", "+", "-", "*", "/", "%", "(", ")", ",", ".",
+    ";",
+];
+
+pub(crate) fn tokenize(sql: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut at = 0;
+    loop {
+        while at < bytes.len() && bytes[at].is_ascii_whitespace() {
+            at += 1;
+        }
+        if sql[at..].starts_with("--") {
+            at = sql[at..].find('\n').map_or(sql.len(), |end| at + end);
+            continue
+        }
+        let start = at;
+        let Some(c) = sql[at..].chars().next() else {
+            tokens.push((Token::End, at));
+            return Ok(tokens)
+        };
+        let error = |kind| ParseError::at(sql, start, kind);
+        let token = if (c == 'x' || c == 'X') && bytes.get(at + 1) == Some(&b'\'') {
+            let (text, end) = quoted(sql, at + 1, '\'').ok_or_else(|| error(ParseErrorKind::UnterminatedString))?;
+            at = end;
+            Token::Bytes(unhex(&text).ok_or_else(|| error(ParseErrorKind::InvalidLiteral))?)
+        } else if c.is_alphabetic() || c == '_' {
+            let end = sql[at..].find(|c: char| !(c.is_alphanumeric() || c == '_')).map_or(sql.len(), |end| at + end);
+            let word = sql[at..end].to_lowercase();
+            at = end;
+            Token::Word(word)
+        } else if c.is_ascii_digit() || (c == '.' && bytes.get(at + 1).is_some_and(u8::is_ascii_digit)) {
+            let (token, end) = number(sql, at).ok_or_else(|| error(ParseErrorKind::InvalidLiteral))?;
+            at = end;
+            token
+        } else if c == '\'' {
+            let (text, end) = quoted(sql, at, '\'').ok_or_else(|| error(ParseErrorKind::UnterminatedString))?;
+            at = end;
+            Token::String(text)
+        } else if c == '"' {
+            let (text, end) = quoted(sql, at, '"').ok_or_else(|| error(ParseErrorKind::UnterminatedString))?;
+            at = end;
+            Token::QuotedIdent(text)
+        } else if c == '?' {
+            at += 1;
+            Token::Placeholder(None)
+        } else if c == '$' {
+            let end = sql[at + 1..].find(|c: char| !c.is_ascii_digit()).map_or(sql.len(), |end| at + 1 + end);
+            let n: usize = sql[at + 1..end].parse().map_err(|_| error(ParseErrorKind::InvalidLiteral))?;
+            if n == 0 {
+                return Err(error(ParseErrorKind::InvalidLiteral))
+            }
+            at = end;
+            Token::Placeholder(Some(n))
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| sql[at..].starts_with(**s)) {
+            at += symbol.len();
+            Token::Symbol(symbol)
+        } else {
+            return Err(error(ParseErrorKind::UnexpectedCharacter(c)))
+        };
+        tokens.push((token, start));
+    }
+}
+
+/// The text between the `quote` at `start` and its closing quote, with doubled quotes inside read as one,
+/// and the offset just past the closing quote.
+fn quoted(sql: &str, start: usize, quote: char) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut chars = sql[start + 1..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != quote {
+            text.push(c);
+        } else if chars.peek().is_some_and(|(_, next)| *next == quote) {
+            text.push(quote);
+            chars.next();
+        } else {
+            return Some((text, start + 1 + i + 1))
+        }
+    }
+    None
+}
+
+fn number(sql: &str, start: usize) -> Option<(Token, usize)> {
+    let bytes = sql.as_bytes();
+    let mut end = start;
+    let (mut fraction, mut float) = (false, false);
+    while end < bytes.len() {
+        match bytes[end] {
+            b'0'..=b'9' => {}
+            b'.' if !fraction && !float => fraction = true,
+            b'e' | b'E' if !float => {
+                float = true;
+                if matches!(bytes.get(end + 1), Some(b'+' | b'-')) {
+                    end += 1;
+                }
+            }
+            _ => break,
+        }
+        end += 1;
+    }
+    if bytes.get(end).is_some_and(|b| b.is_ascii_alphabetic() || *b == b'_') {
+        return None
+    }
+    let text = &sql[start..end];
+    let token = match (fraction, float) {
+        (_, true) => Token::Float(text.parse().ok()?),
+        (true, false) => Token::Decimal(Decimal::parse(text)?),
+        (false, false) => match text.parse() {
+            Ok(v) => Token::Int(v),
+            Err(_) => Token::Decimal(Decimal::parse(text)?),
+        },
+    };
+    Some((token, end))
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+    use crate::sql::{ParseError, ParseErrorKind};
+
+    use super::{tokenize, Token};
+
+    #[test]
+    fn test_tokens() -> Result<(), ParseError> {
+        let sql = "SELECT \"Mixed\"\"Case\", 'it''s', x'00ff', 1.5e3, .5, 42 -- comment\n FROM t WHERE a <= $2 AND b <> ?";
+        let tokens: Vec<Token> = tokenize(sql)?.into_iter().map(|(t, _)| t).collect();
+        let word = |w: &str| Token::Word(w.to_string());
+        assert_eq!(tokens, vec![
+            word("select"),
+            Token::QuotedIdent("Mixed\"Case".to_string()),
+            Token::Symbol(","),
+            Token::String("it's".to_string()),
+            Token::Symbol(","),
+            Token::Bytes(vec![0, 255]),
+            Token::Symbol(","),
+            Token::Float(1500.0),
+            Token::Symbol(","),
+            Token::Decimal(Decimal::new(5, 1)),
+            Token::Symbol(","),
+            Token::Int(42),
+            word("from"),
+            word("t"),
+            word("where"),
+            word("a"),
+            Token::Symbol("<="),
+            Token::Placeholder(Some(2)),
+            word("and"),
+            word("b"),
+            Token::Symbol("<>"),
+            Token::Placeholder(None),
+            Token::End,
+        ]);
+
+        let error = tokenize("SELECT 'open\n  FROM t").unwrap_err();
+        assert_eq!((error.kind, error.line, error.column), (ParseErrorKind::UnterminatedString, 1, 8));
+        let error = tokenize("SELECT 1\n  # 2").unwrap_err();
+        assert_eq!((error.kind, error.line, error.column), (ParseErrorKind::UnexpectedCharacter('#'), 2, 3));
+        assert_eq!(tokenize("SELECT 12abc").unwrap_err().kind, ParseErrorKind::InvalidLiteral);
+        let tokens = tokenize("9223372036854775807 9223372036854775808")?;
+        assert_eq!(tokens[0].0, Token::Int(i64::MAX));
+        assert_eq!(tokens[1].0, Token::Decimal(Decimal::new(1 << 63, 0)));
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+//! SQL front end: text to syntax tree.
+//!
+//! `parse` reads a script of statements separated by semicolons, `split` cuts one into the text of each
+//! of its statements, and `parse_statement` reads exactly one. The parser is hand-written recursive
+//! descent over the tokens of `lexer`, with one function per level of operator precedence, loosest first:
+//! `OR`, `AND`, `NOT`, comparisons and the `IS`, `LIKE`, `IN` and `BETWEEN` tests, `+ - ||`, `* / %`, then
+//! unary minus. Keywords are not case sensitive and may not be used as bare identifiers; quote them to use
+//! them as names.
+//!
+//! Errors say where they are by byte offset and by line and column, both counted from 1, so that a
+//! front end can point at the offending text.
+pub mod ast;
+mod lexer;
+mod parser;
+
+use std::fmt;
+
+pub use ast::{
+    AlterColumn, BinaryOp, ColumnSpec, ConflictAction, ConstraintKind, CreateIndex, CreateSequence, CreateTable,
+    CreateTrigger, CreateView, Cte, Delete, Distinct, Expr, Frame, FrameBound, FromItem, Insert, InsertSource, JoinKind,
+    OnConflict, OrderBy, Partitioning, ReferentialAction, Select, SelectItem, Statement, TableConstraint, UnaryOp,
+    Union, Update, Window, With,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    /// A number, bytes literal or placeholder that cannot be read.
+    InvalidLiteral,
+    /// A token the grammar does not allow here, and a description of what it does allow.
+    Unexpected { found: String, expected: &'static str },
+}
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {c:?}"),
+            ParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            ParseErrorKind::InvalidLiteral => write!(f, "invalid literal"),
+            ParseErrorKind::Unexpected { found, expected } => write!(f, "found {found} where {expected} was expected"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset into the text.
+    pub offset: usize,
+    pub line: usize,
+    /// Counted in characters, not bytes.
+    pub column: usize,
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at line {}, column {}: {}", self.line, self.column, self.kind)
+    }
+}
+impl ParseError {
+    fn at(sql: &str, offset: usize, kind: ParseErrorKind) -> ParseError {
+        let before = &sql[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        ParseError { kind, offset, line, column }
+    }
+}
+
+/// Parse a script of zero or more statements separated by semicolons.
+pub fn parse(sql: &str) -> Result<Vec<Statement>, ParseError> {
+    parser::Parser::new(sql)?.statements()
+}
+
+/// Split a script as `parse` reads it, into the text of each of its statements.
+pub fn split(sql: &str) -> Result<Vec<&str>, ParseError> {
+    parser::Parser::new(sql)?.split()
+}
+
+/// Parse exactly one statement, optionally followed by a semicolon.
+pub fn parse_statement(sql: &str) -> Result<Statement, ParseError> {
+    parser::Parser::new(sql)?.single()
+}
+
+/// Parse exactly one expression, such as a column default the catalog keeps as text.
+pub fn parse_expr(sql: &str) -> Result<Expr, ParseError> {
+    parser::Parser::new(sql)?.single_expr()
+}
@@ -0,0 +1,1706 @@
+//! Recursive descent parser from tokens to `ast` nodes.
+use crate::catalog::{TriggerEvent, TriggerTiming};
+use crate::collation::Collation;
+use crate::decimal::{Decimal, MAX_DIGITS};
+use crate::table::IndexKind;
+use crate::tuple::{ColumnType, Value};
+
+use super::ast::{
+    AlterColumn, BinaryOp, ColumnSpec, ConflictAction, ConstraintKind, CreateIndex, CreateSequence, CreateTable,
+    CreateTrigger, CreateView, Cte, Delete, Distinct, Expr, Frame, FrameBound, FromItem, Insert, InsertSource, JoinKind,
+    OnConflict, OrderBy, Partitioning, ReferentialAction, Select, SelectItem, Statement, TableConstraint, UnaryOp,
+    Union, Update, Window, With,
+};
+use super::lexer::{tokenize, Token};
+use super::{ParseError, ParseErrorKind};
+
+/// Words that always mean their keyword and so cannot name tables, columns or aliases unquoted.
+pub(super) const RESERVED: &[&str] = &[
+    "all", "alter", "and", "as", "asc", "between", "by", "case", "cast", "collate", "create", "cross", "delete", "desc",
+    "distinct", "drop", "else", "end", "exists", "false", "from", "group", "having", "in", "index", "inner", "insert",
+    "into", "is", "join", "left", "like", "limit", "not", "null", "nulls", "offset", "on", "or", "order", "outer",
+    "select", "set", "table", "then", "true", "union", "update", "values", "when", "where", "with",
+];
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+pub(crate) struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<(Token, usize)>,
+    at: usize,
+    /// Parameters numbered so far in the current statement.
+    parameters: usize,
+}
+impl<'a> Parser<'a> {
+    pub(crate) fn new(sql: &'a str) -> Result<Parser<'a>> {
+        Ok(Parser { sql, tokens: tokenize(sql)?, at: 0, parameters: 0 })
+    }
+
+    pub(crate) fn statements(mut self) -> Result<Vec<Statement>> {
+        let mut statements = Vec::new();
+        loop {
+            while self.symbol(";") {}
+            if *self.peek() == Token::End {
+                return Ok(statements)
+            }
+            statements.push(self.statement()?);
+            if !self.symbol(";") && *self.peek() != Token::End {
+                return self.unexpected("`;` or the end of the script")
+            }
+        }
+    }
+
+    /// The text of each statement of the script, without the semicolons between them.
+    pub(crate) fn split(mut self) -> Result<Vec<&'a str>> {
+        let mut texts = Vec::new();
+        loop {
+            while self.symbol(";") {}
+            if *self.peek() == Token::End {
+                return Ok(texts)
+            }
+            let start = self.tokens[self.at].1;
+            self.statement()?;
+            texts.push(self.sql[start..self.tokens[self.at].1].trim_end());
+            if !self.symbol(";") && *self.peek() != Token::End {
+                return self.unexpected("`;` or the end of the script")
+            }
+        }
+    }
+
+    pub(crate) fn single(mut self) -> Result<Statement> {
+        let statement = self.statement()?;
+        self.symbol(";");
+        if *self.peek() != Token::End {
+            return self.unexpected("the end of the statement")
+        }
+        Ok(statement)
+    }
+
+    pub(crate) fn single_expr(mut self) -> Result<Expr> {
+        let expr = self.expr()?;
+        if *self.peek() != Token::End {
+            return self.unexpected("the end of the expression")
+        }
+        Ok(expr)
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        self.parameters = 0;
+        match self.peek() {
+            _ if self.at_query() => Ok(Statement::Select(Box::new(self.select()?))),
+            Token::Word(w) if w == "insert" => self.insert(),
+            Token::Word(w) if w == "update" => self.update(),
+            Token::Word(w) if w == "delete" => self.delete(),
+            Token::Word(w) if w == "create" => self.create(),
+            Token::Word(w) if w == "drop" => self.drop(),
+            Token::Word(w) if w == "alter" => self.alter(),
+            Token::Word(w) if w == "explain" => {
+                self.advance();
+                let analyze = self.keyword("analyze");
+                Ok(Statement::Explain { analyze, select: Box::new(self.select()?) })
+            }
+            _ => self.unexpected("a statement"),
+        }
+    }
+
+    /// Whether a query starts at the next token.
+    fn at_query(&self) -> bool {
+        matches!(self.peek(), Token::Word(w) if w == "select" || w == "with")
+    }
+
+    fn select(&mut self) -> Result<Select> {
+        let with = if self.keyword("with") { Some(self.with()?) } else { None };
+        self.expect_keyword("select")?;
+        let distinct = match self.keyword("distinct") {
+            true if self.keyword("on") => {
+                self.expect_symbol("(")?;
+                Some(Distinct::On(self.parenthesized_rest(Parser::expr)?))
+            }
+            true => Some(Distinct::Rows),
+            false => {
+                self.keyword("all");
+                None
+            }
+        };
+        let items = self.comma_separated(Parser::select_item)?;
+        let from = if self.keyword("from") { Some(self.tables()?) } else { None };
+        let filter = self.where_clause()?;
+        let group_by = if self.keyword("group") {
+            self.expect_keyword("by")?;
+            self.comma_separated(Parser::expr)?
+        } else {
+            Vec::new()
+        };
+        let having = if self.keyword("having") { Some(self.expr()?) } else { None };
+        let order_by = if self.keyword("order") {
+            self.expect_keyword("by")?;
+            self.comma_separated(Parser::order_by)?
+        } else {
+            Vec::new()
+        };
+        let limit = if self.keyword("limit") { Some(self.expr()?) } else { None };
+        let offset = if self.keyword("offset") { Some(self.expr()?) } else { None };
+        Ok(Select { with, distinct, items, from, filter, group_by, having, order_by, limit, offset })
+    }
+
+    /// The rest of a `WITH` after the keyword.
+    fn with(&mut self) -> Result<With> {
+        let recursive = self.keyword("recursive");
+        let ctes = self.comma_separated(|p| {
+            let name = p.ident()?;
+            let columns = if p.symbol("(") { p.parenthesized_rest(Parser::ident)? } else { Vec::new() };
+            p.expect_keyword("as")?;
+            p.expect_symbol("(")?;
+            let query = Box::new(p.select()?);
+            let union = match p.keyword("union") {
+                true => Some(Union { all: p.keyword("all"), query: Box::new(p.select()?) }),
+                false => None,
+            };
+            p.expect_symbol(")")?;
+            Ok(Cte { name, columns, query, union })
+        })?;
+        Ok(With { recursive, ctes })
+    }
+
+    fn select_item(&mut self) -> Result<SelectItem> {
+        if self.symbol("*") {
+            return Ok(SelectItem::Wildcard(None))
+        }
+        let qualified = matches!(self.peek_at(1), Token::Symbol(".")) && matches!(self.peek_at(2), Token::Symbol("*"));
+        if qualified && self.is_ident() {
+            let table = self.ident()?;
+            self.at += 2;
+            return Ok(SelectItem::Wildcard(Some(table)))
+        }
+        let expr = self.expr()?;
+        Ok(SelectItem::Expr { expr, alias: self.alias()? })
+    }
+
+    /// An optional `[AS] name`.
+    fn alias(&mut self) -> Result<Option<String>> {
+        if self.keyword("as") {
+            return Ok(Some(self.ident()?))
+        }
+        self.is_ident().then(|| self.ident()).transpose()
+    }
+
+    fn tables(&mut self) -> Result<FromItem> {
+        let mut left = self.table_ref()?;
+        loop {
+            let kind = if self.symbol(",") {
+                JoinKind::Cross
+            } else if self.keyword("cross") {
+                self.expect_keyword("join")?;
+                JoinKind::Cross
+            } else if self.keyword("left") {
+                self.keyword("outer");
+                self.expect_keyword("join")?;
+                JoinKind::Left
+            } else if self.keyword("inner") {
+                self.expect_keyword("join")?;
+                JoinKind::Inner
+            } else if self.keyword("join") {
+                JoinKind::Inner
+            } else {
+                return Ok(left)
+            };
+            let right = self.table_ref()?;
+            let on = if kind == JoinKind::Cross {
+                None
+            } else {
+                self.expect_keyword("on")?;
+                Some(self.expr()?)
+            };
+            left = FromItem::Join { left: Box::new(left), right: Box::new(right), kind, on };
+        }
+    }
+
+    fn table_ref(&mut self) -> Result<FromItem> {
+        if self.symbol("(") {
+            let query = Box::new(self.select()?);
+            self.expect_symbol(")")?;
+            let Some(alias) = self.alias()? else { return self.unexpected("an alias for the subquery") };
+            return Ok(FromItem::Derived { query, alias })
+        }
+        let name = self.ident()?;
+        Ok(FromItem::Table { name, alias: self.alias()? })
+    }
+
+    fn order_by(&mut self) -> Result<OrderBy> {
+        let expr = self.expr()?;
+        let descending = if self.keyword("desc") {
+            true
+        } else {
+            self.keyword("asc");
+            false
+        };
+        let nulls_first = if self.keyword("nulls") {
+            if self.keyword("first") {
+                Some(true)
+            } else if self.keyword("last") {
+                Some(false)
+            } else {
+                return self.unexpected("`FIRST` or `LAST`")
+            }
+        } else {
+            None
+        };
+        Ok(OrderBy { expr, descending, nulls_first })
+    }
+
+    fn where_clause(&mut self) -> Result<Option<Expr>> {
+        if self.keyword("where") {
+            Ok(Some(self.expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn insert(&mut self) -> Result<Statement> {
+        self.expect_keyword("insert")?;
+        self.expect_keyword("into")?;
+        let table = self.ident()?;
+        let columns = if self.symbol("(") { self.parenthesized_rest(Parser::ident)? } else { Vec::new() };
+        let source = match self.peek() {
+            _ if self.at_query() => InsertSource::Query(Box::new(self.select()?)),
+            _ => {
+                self.expect_keyword("values")?;
+                InsertSource::Values(self.comma_separated(|p| {
+                    p.expect_symbol("(")?;
+                    p.parenthesized_rest(Parser::expr)
+                })?)
+            }
+        };
+        let on_conflict = if self.keyword("on") { Some(self.on_conflict()?) } else { None };
+        Ok(Statement::Insert(Insert { table, columns, source, on_conflict }))
+    }
+
+    /// The rest of `ON CONFLICT`, after the `ON`.
+    fn on_conflict(&mut self) -> Result<OnConflict> {
+        self.expect_keyword("conflict")?;
+        let target = if self.symbol("(") { self.parenthesized_rest(Parser::ident)? } else { Vec::new() };
+        self.expect_keyword("do")?;
+        let action = match self.keyword("nothing") {
+            true => ConflictAction::Nothing,
+            false => {
+                self.expect_keyword("update")?;
+                self.expect_keyword("set")?;
+                let assignments = self.assignments()?;
+                ConflictAction::Update { assignments, filter: self.where_clause()? }
+            }
+        };
+        Ok(OnConflict { target, action })
+    }
+
+    fn update(&mut self) -> Result<Statement> {
+        self.expect_keyword("update")?;
+        let table = self.ident()?;
+        self.expect_keyword("set")?;
+        let assignments = self.assignments()?;
+        let filter = self.where_clause()?;
+        Ok(Statement::Update(Update { table, assignments, filter }))
+    }
+
+    /// `a = 1, b = a + 1`, after `SET`.
+    fn assignments(&mut self) -> Result<Vec<(String, Expr)>> {
+        self.comma_separated(|p| {
+            let column = p.ident()?;
+            p.expect_symbol("=")?;
+            Ok((column, p.expr()?))
+        })
+    }
+
+    fn delete(&mut self) -> Result<Statement> {
+        self.expect_keyword("delete")?;
+        self.expect_keyword("from")?;
+        let table = self.ident()?;
+        let filter = self.where_clause()?;
+        Ok(Statement::Delete(Delete { table, filter }))
+    }
+
+    fn create(&mut self) -> Result<Statement> {
+        self.expect_keyword("create")?;
+        let unique = self.keyword("unique");
+        if unique || self.keyword("index") {
+            if unique {
+                self.expect_keyword("index")?;
+            }
+            let name = self.ident()?;
+            self.expect_keyword("on")?;
+            let table = self.ident()?;
+            let kind = if self.keyword("using") { self.index_kind()? } else { IndexKind::BTree };
+            self.expect_symbol("(")?;
+            let columns = self.parenthesized_rest(Parser::index_key)?;
+            let include = if self.keyword("include") {
+                self.expect_symbol("(")?;
+                self.parenthesized_rest(Parser::ident)?
+            } else {
+                Vec::new()
+            };
+            return Ok(Statement::CreateIndex(CreateIndex { name, unique, table, kind, columns, include }))
+        }
+        if self.keyword("view") {
+            let name = self.ident()?;
+            let columns = if self.symbol("(") { self.parenthesized_rest(Parser::ident)? } else { Vec::new() };
+            self.expect_keyword("as")?;
+            let query = Box::new(self.select()?);
+            return Ok(Statement::CreateView(CreateView { name, columns, query }))
+        }
+        if self.keyword("sequence") {
+            return self.create_sequence()
+        }
+        if self.keyword("trigger") {
+            return self.create_trigger()
+        }
+        self.expect_keyword("table")?;
+        let if_not_exists = self.keyword("if");
+        if if_not_exists {
+            self.expect_keyword("not")?;
+            self.expect_keyword("exists")?;
+        }
+        let name = self.ident()?;
+        self.expect_symbol("(")?;
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        self.parenthesized_rest(|p| {
+            match p.constraint(None)? {
+                Some(constraint) => constraints.push(constraint),
+                None => columns.push(p.column_spec(Some(&mut constraints))?),
+            }
+            Ok(())
+        })?;
+        // The table's options follow its columns, in any order.
+        let (mut ttl, mut without_rowid, mut partition_by) = (None, false, None);
+        loop {
+            if ttl.is_none() && self.keyword("ttl") {
+                ttl = Some(self.ttl()?);
+            } else if !without_rowid && self.keyword("without") {
+                self.expect_keyword("rowid")?;
+                without_rowid = true;
+            } else if partition_by.is_none() && self.keyword("partition") {
+                self.expect_keyword("by")?;
+                partition_by = Some(self.partitioning()?);
+            } else {
+                break
+            }
+        }
+        let create = CreateTable { name, columns, constraints, if_not_exists, ttl, without_rowid, partition_by };
+        Ok(Statement::CreateTable(create))
+    }
+
+    /// `RANGE (column)` or `HASH (column) PARTITIONS n`, after `PARTITION BY`.
+    fn partitioning(&mut self) -> Result<Partitioning> {
+        let range = if self.keyword("range") {
+            true
+        } else if self.keyword("hash") {
+            false
+        } else {
+            return self.unexpected("`RANGE` or `HASH`")
+        };
+        self.expect_symbol("(")?;
+        let column = self.ident()?;
+        self.expect_symbol(")")?;
+        if range {
+            return Ok(Partitioning::Range(column))
+        }
+        self.expect_keyword("partitions")?;
+        let Ok(partitions) = usize::try_from(self.integer()?) else {
+            return self.unexpected_previous("a number of partitions")
+        };
+        Ok(Partitioning::Hash { column, partitions })
+    }
+
+    /// `column + lifetime`, after `TTL`.
+    fn ttl(&mut self) -> Result<(String, Expr)> {
+        let column = self.ident()?;
+        self.expect_symbol("+")?;
+        Ok((column, self.multiplicative()?))
+    }
+
+    /// A key of `CREATE INDEX`: a column, which may name a collation, or an expression in parentheses.
+    fn index_key(&mut self) -> Result<Expr> {
+        if self.symbol("(") {
+            let expr = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr)
+        }
+        let column = Expr::Column { table: None, name: self.ident()? };
+        match self.keyword("collate") {
+            true => Ok(Expr::Collate { expr: Box::new(column), collation: self.collation()? }),
+            false => Ok(column),
+        }
+    }
+
+    /// The rest of `CREATE SEQUENCE`, after `SEQUENCE`. Its options may come in any order.
+    fn create_sequence(&mut self) -> Result<Statement> {
+        let mut create = CreateSequence { name: self.ident()?, start: None, increment: None, cache: None };
+        loop {
+            if self.keyword("start") {
+                self.keyword("with");
+                create.start = Some(self.integer()?);
+            } else if self.keyword("increment") {
+                self.keyword("by");
+                create.increment = Some(self.integer()?);
+            } else if self.keyword("cache") {
+                let Ok(cache) = u64::try_from(self.integer()?) else { return self.unexpected_previous("a cache size") };
+                create.cache = Some(cache);
+            } else {
+                return Ok(Statement::CreateSequence(create))
+            }
+        }
+    }
+
+    /// The rest of `CREATE TRIGGER`, after `TRIGGER`. Its body is kept as the text of the statements up to
+    /// `END`, each of which must parse.
+    fn create_trigger(&mut self) -> Result<Statement> {
+        let name = self.ident()?;
+        let timing = if self.keyword("before") {
+            TriggerTiming::Before
+        } else if self.keyword("after") {
+            TriggerTiming::After
+        } else {
+            return self.unexpected("`BEFORE` or `AFTER`")
+        };
+        let event = if self.keyword("insert") {
+            TriggerEvent::Insert
+        } else if self.keyword("update") {
+            TriggerEvent::Update
+        } else if self.keyword("delete") {
+            TriggerEvent::Delete
+        } else {
+            return self.unexpected("`INSERT`, `UPDATE` or `DELETE`")
+        };
+        self.expect_keyword("on")?;
+        let table = self.ident()?;
+        if self.keyword("for") {
+            self.expect_keyword("each")?;
+            self.expect_keyword("row")?;
+        }
+        self.expect_keyword("begin")?;
+        let start = self.tokens[self.at].1;
+        loop {
+            while self.symbol(";") {}
+            if self.keyword("end") {
+                break
+            }
+            self.statement()?;
+            if !self.symbol(";") && !matches!(self.peek(), Token::Word(w) if w == "end") {
+                return self.unexpected("`;` or `END`")
+            }
+        }
+        let body = self.sql[start..self.tokens[self.at - 1].1].trim().to_string();
+        Ok(Statement::CreateTrigger(CreateTrigger { name, timing, event, table, body }))
+    }
+
+    /// An integer, negative after a minus sign.
+    fn integer(&mut self) -> Result<i64> {
+        let negative = self.symbol("-");
+        match self.advance() {
+            Token::Int(v) if negative => Ok(-v),
+            Token::Int(v) => Ok(v),
+            _ => self.unexpected_previous("an integer"),
+        }
+    }
+
+    /// A column and its type. With `constraints`, constraints may be declared on it too, and are added there.
+    fn column_spec(&mut self, mut constraints: Option<&mut Vec<TableConstraint>>) -> Result<ColumnSpec> {
+        let name = self.ident()?;
+        let column_type = self.column_type()?;
+        let (mut nullable, mut default, mut identity, mut collation) = (true, None, false, Collation::Binary);
+        loop {
+            if self.keyword("not") {
+                self.expect_keyword("null")?;
+                nullable = false;
+            } else if self.keyword("null") {
+                nullable = true;
+            } else if self.keyword("default") {
+                default = Some(self.default()?);
+            } else if self.keyword("autoincrement") {
+                identity = true;
+            } else if self.keyword("generated") {
+                for keyword in ["by", "default", "as", "identity"] {
+                    self.expect_keyword(keyword)?;
+                }
+                identity = true;
+            } else if self.keyword("collate") {
+                collation = self.collation()?;
+            } else if let Some(constraints) = constraints.as_deref_mut() {
+                let Some(constraint) = self.constraint(Some(&name))? else {
+                    return Ok(ColumnSpec { name, column_type, nullable, default, identity, collation })
+                };
+                constraints.push(constraint);
+            } else {
+                return Ok(ColumnSpec { name, column_type, nullable, default, identity, collation })
+            }
+        }
+    }
+
+    /// The expression after `DEFAULT`. Comparisons and logic need parentheses, so that `NOT NULL` after it
+    /// is not read as part of it.
+    fn default(&mut self) -> Result<Expr> {
+        self.additive()
+    }
+
+    /// A constraint, if one comes next: on the table, listing its columns, or after the type of `column`.
+    fn constraint(&mut self, column: Option<&str>) -> Result<Option<TableConstraint>> {
+        let name = if self.keyword("constraint") { Some(self.ident()?) } else { None };
+        let columns = |p: &mut Parser| match column {
+            Some(column) => Ok(vec![column.to_string()]),
+            None => {
+                p.expect_symbol("(")?;
+                p.parenthesized_rest(Parser::ident)
+            }
+        };
+        let kind = if self.keyword("primary") {
+            self.expect_keyword("key")?;
+            ConstraintKind::PrimaryKey(columns(self)?)
+        } else if self.keyword("unique") {
+            ConstraintKind::Unique(columns(self)?)
+        } else if column.is_none() && self.keyword("foreign") {
+            self.expect_keyword("key")?;
+            let columns = columns(self)?;
+            self.expect_keyword("references")?;
+            self.references(columns)?
+        } else if column.is_some() && self.keyword("references") {
+            let columns = columns(self)?;
+            self.references(columns)?
+        } else {
+            return match name {
+                Some(_) => self.unexpected("`PRIMARY KEY`, `UNIQUE` or a foreign key"),
+                None => Ok(None),
+            }
+        };
+        Ok(Some(TableConstraint { name, kind }))
+    }
+
+    /// The rest of a foreign key on `columns`, after `REFERENCES`.
+    fn references(&mut self, columns: Vec<String>) -> Result<ConstraintKind> {
+        let table = self.ident()?;
+        let references = if self.symbol("(") { self.parenthesized_rest(Parser::ident)? } else { Vec::new() };
+        let mut on_delete = ReferentialAction::Restrict;
+        if self.keyword("on") {
+            self.expect_keyword("delete")?;
+            on_delete = if self.keyword("restrict") {
+                ReferentialAction::Restrict
+            } else if self.keyword("cascade") {
+                ReferentialAction::Cascade
+            } else if self.keyword("set") {
+                self.expect_keyword("null")?;
+                ReferentialAction::SetNull
+            } else if self.keyword("no") {
+                self.expect_keyword("action")?;
+                ReferentialAction::Restrict
+            } else {
+                return self.unexpected("`RESTRICT`, `CASCADE`, `SET NULL` or `NO ACTION`")
+            };
+        }
+        Ok(ConstraintKind::ForeignKey { columns, table, references, on_delete })
+    }
+
+    fn column_type(&mut self) -> Result<ColumnType> {
+        let column_type = match self.peek() {
+            Token::Word(w) => match w.as_str() {
+                "int" | "integer" | "bigint" | "smallint" => ColumnType::Int,
+                "float" | "real" | "double" => ColumnType::Float,
+                "bool" | "boolean" => ColumnType::Bool,
+                "text" | "varchar" | "char" | "string" => ColumnType::Text,
+                "bytes" | "blob" | "bytea" => ColumnType::Bytes,
+                "date" => ColumnType::Date,
+                "time" => ColumnType::Time,
+                "timestamp" | "datetime" => ColumnType::Timestamp,
+                "timestamptz" => ColumnType::TimestampTz,
+                "interval" => ColumnType::Interval,
+                "decimal" | "numeric" | "dec" => ColumnType::NUMERIC,
+                "json" | "jsonb" => ColumnType::Json,
+                _ => return self.unexpected("a type"),
+            },
+            _ => return self.unexpected("a type"),
+        };
+        let word = self.advance();
+        if word == Token::Word("double".to_string()) {
+            self.keyword("precision");
+        }
+        if column_type == ColumnType::NUMERIC && self.symbol("(") {
+            let precision = match self.advance() {
+                Token::Int(v) if (1..=MAX_DIGITS as i64).contains(&v) => v as u32,
+                _ => return self.unexpected_previous("a precision from 1 to 1000"),
+            };
+            let scale = match self.symbol(",") {
+                true => match self.advance() {
+                    Token::Int(v) if (0..=precision as i64).contains(&v) => v as u32,
+                    _ => return self.unexpected_previous("a scale no greater than the precision"),
+                },
+                false => 0,
+            };
+            self.expect_symbol(")")?;
+            return Ok(ColumnType::Decimal { precision, scale })
+        }
+        // A length, as in `VARCHAR(20)`, is accepted and ignored.
+        if self.symbol("(") {
+            match self.advance() {
+                Token::Int(_) => {}
+                _ => return self.unexpected_previous("a length"),
+            }
+            self.expect_symbol(")")?;
+        }
+        let zoned = matches!(column_type, ColumnType::Time | ColumnType::Timestamp);
+        if zoned && self.keyword("with") {
+            self.expect_keyword("time")?;
+            self.expect_keyword("zone")?;
+            if column_type == ColumnType::Time {
+                return self.unexpected_previous("a time without a time zone")
+            }
+            return Ok(ColumnType::TimestampTz)
+        }
+        if zoned && self.keyword("without") {
+            self.expect_keyword("time")?;
+            self.expect_keyword("zone")?;
+        }
+        Ok(column_type)
+    }
+
+    fn drop(&mut self) -> Result<Statement> {
+        self.expect_keyword("drop")?;
+        if self.keyword("index") {
+            let name = self.ident()?;
+            self.expect_keyword("on")?;
+            return Ok(Statement::DropIndex { name, table: self.ident()? })
+        }
+        if self.keyword("trigger") {
+            let name = self.ident()?;
+            self.expect_keyword("on")?;
+            return Ok(Statement::DropTrigger { name, table: self.ident()? })
+        }
+        let (view, sequence) = (self.keyword("view"), self.keyword("sequence"));
+        if !view && !sequence {
+            self.expect_keyword("table")?;
+        }
+        let if_exists = self.keyword("if");
+        if if_exists {
+            self.expect_keyword("exists")?;
+        }
+        let name = self.ident()?;
+        Ok(match (view, sequence) {
+            (true, _) => Statement::DropView { name, if_exists },
+            (_, true) => Statement::DropSequence { name, if_exists },
+            _ => Statement::DropTable { name, if_exists },
+        })
+    }
+
+    fn alter(&mut self) -> Result<Statement> {
+        self.expect_keyword("alter")?;
+        self.expect_keyword("table")?;
+        let table = self.ident()?;
+        let change = if self.keyword("add") {
+            // A column being added may be called `partition` too.
+            if matches!(self.peek_at(1), Token::Word(w) if w == "from") && self.keyword("partition") {
+                self.advance();
+                return Ok(Statement::AlterTable { table, change: AlterColumn::AddPartition(self.expr()?) })
+            }
+            match self.constraint(None)? {
+                Some(constraint) => AlterColumn::AddConstraint(constraint),
+                None => {
+                    self.keyword("column");
+                    AlterColumn::Add(self.column_spec(None)?)
+                }
+            }
+        } else if self.keyword("drop") {
+            if self.keyword("constraint") {
+                AlterColumn::DropConstraint(self.ident()?)
+            } else {
+                self.keyword("column");
+                AlterColumn::Drop(self.ident()?)
+            }
+        } else if self.keyword("alter") {
+            self.keyword("column");
+            let column = self.ident()?;
+            let set = if self.keyword("set") {
+                true
+            } else if self.keyword("drop") {
+                false
+            } else {
+                return self.unexpected("`SET` or `DROP`")
+            };
+            if self.keyword("default") {
+                let default = if set { Some(self.default()?) } else { None };
+                AlterColumn::SetDefault { column, default }
+            } else {
+                self.expect_keyword("not")?;
+                self.expect_keyword("null")?;
+                AlterColumn::SetNullable { column, nullable: !set }
+            }
+        } else {
+            return self.unexpected("`ADD`, `DROP` or `ALTER`")
+        };
+        Ok(Statement::AlterTable { table, change })
+    }
+
+    pub(crate) fn expr(&mut self) -> Result<Expr> {
+        let mut left = self.and()?;
+        while self.keyword("or") {
+            left = binary(BinaryOp::Or, left, self.and()?);
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut left = self.not()?;
+        while self.keyword("and") {
+            left = binary(BinaryOp::And, left, self.not()?);
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.keyword("not") {
+            return Ok(Expr::Unary { op: UnaryOp::Not, expr: Box::new(self.not()?) })
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let mut left = self.extraction()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("=") => BinaryOp::Eq,
+                Token::Symbol("<>" | "!=") => BinaryOp::NotEq,
+                Token::Symbol("<") => BinaryOp::Lt,
+                Token::Symbol("<=") => BinaryOp::LtEq,
+                Token::Symbol(">") => BinaryOp::Gt,
+                Token::Symbol(">=") => BinaryOp::GtEq,
+                _ => {
+                    if self.keyword("is") {
+                        let negated = self.keyword("not");
+                        self.expect_keyword("null")?;
+                        left = Expr::IsNull { expr: Box::new(left), negated };
+                        continue
+                    }
+                    // `NOT` here can only start `NOT LIKE`, `NOT IN` or `NOT BETWEEN`.
+                    let negated = matches!(self.peek(), Token::Word(w) if w == "not")
+                        && matches!(self.peek_at(1), Token::Word(w) if w == "like" || w == "in" || w == "between");
+                    if negated {
+                        self.at += 1;
+                    }
+                    let expr = Box::new(left);
+                    left = if self.keyword("like") {
+                        Expr::Like { expr, pattern: Box::new(self.extraction()?), negated }
+                    } else if self.keyword("in") {
+                        self.expect_symbol("(")?;
+                        match self.peek() {
+                            _ if self.at_query() => {
+                                let query = Box::new(self.select()?);
+                                self.expect_symbol(")")?;
+                                Expr::InSubquery { expr, query, negated }
+                            }
+                            _ => Expr::InList { expr, list: self.parenthesized_rest(Parser::expr)?, negated },
+                        }
+                    } else if self.keyword("between") {
+                        let low = Box::new(self.extraction()?);
+                        self.expect_keyword("and")?;
+                        Expr::Between { expr, low, high: Box::new(self.extraction()?), negated }
+                    } else {
+                        return Ok(*expr)
+                    };
+                    continue
+                }
+            };
+            self.at += 1;
+            left = binary(op, left, self.extraction()?);
+        }
+    }
+
+    /// `doc -> 'a'` and the other JSON operators, which bind less tightly than arithmetic.
+    fn extraction(&mut self) -> Result<Expr> {
+        let mut left = self.additive()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("->") => BinaryOp::Extract,
+                Token::Symbol("->>") => BinaryOp::ExtractText,
+                Token::Symbol("#>") => BinaryOp::ExtractPath,
+                Token::Symbol("#>>") => BinaryOp::ExtractPathText,
+                _ => return Ok(left),
+            };
+            self.at += 1;
+            left = binary(op, left, self.additive()?);
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expr> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("+") => BinaryOp::Add,
+                Token::Symbol("-") => BinaryOp::Sub,
+                Token::Symbol("||") => BinaryOp::Concat,
+                _ => return Ok(left),
+            };
+            self.at += 1;
+            left = binary(op, left, self.multiplicative()?);
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr> {
+        let mut left = self.collate()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("*") => BinaryOp::Mul,
+                Token::Symbol("/") => BinaryOp::Div,
+                Token::Symbol("%") => BinaryOp::Rem,
+                _ => return Ok(left),
+            };
+            self.at += 1;
+            left = binary(op, left, self.collate()?);
+        }
+    }
+
+    /// As in PostgreSQL, `COLLATE` binds looser than a sign and tighter than arithmetic.
+    fn collate(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.keyword("collate") {
+            expr = Expr::Collate { expr: Box::new(expr), collation: self.collation()? };
+        }
+        Ok(expr)
+    }
+
+    fn index_kind(&mut self) -> Result<IndexKind> {
+        match self.ident()?.as_str() {
+            "btree" => Ok(IndexKind::BTree),
+            "hash" => Ok(IndexKind::Hash),
+            _ => self.unexpected_previous("an index method"),
+        }
+    }
+
+    fn collation(&mut self) -> Result<Collation> {
+        let name = self.ident()?;
+        Collation::named(&name).map_or_else(|| self.unexpected_previous("a collation"), Ok)
+    }
+
+    /// A minus sign before an integer literal makes a negative literal, so that the smallest BIGINT can be
+    /// written although its magnitude, read first, is too large for one.
+    fn unary(&mut self) -> Result<Expr> {
+        if self.symbol("-") {
+            return Ok(match self.unary()? {
+                Expr::Literal(Value::Int(v)) if v != i64::MIN => Expr::Literal(Value::Int(-v)),
+                Expr::Literal(Value::Decimal(v)) if v.scale() == 0 && v == Decimal::new(1 << 63, 0) => {
+                    Expr::Literal(Value::Int(i64::MIN))
+                }
+                expr => Expr::Unary { op: UnaryOp::Neg, expr: Box::new(expr) },
+            })
+        }
+        if self.symbol("+") {
+            return self.unary()
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let expr = match self.peek().clone() {
+            Token::Int(v) => Expr::Literal(Value::Int(v)),
+            Token::Decimal(v) => Expr::Literal(Value::Decimal(v)),
+            Token::Float(v) => Expr::Literal(Value::Float(v)),
+            Token::String(v) => Expr::Literal(Value::Text(v)),
+            Token::Bytes(v) => Expr::Literal(Value::Bytes(v)),
+            Token::Placeholder(n) => {
+                let n = n.unwrap_or(self.parameters + 1);
+                self.parameters = self.parameters.max(n);
+                Expr::Parameter(n - 1)
+            }
+            Token::Symbol("(") => {
+                self.at += 1;
+                let expr = match self.peek() {
+                    _ if self.at_query() => Expr::Subquery(Box::new(self.select()?)),
+                    _ => self.expr()?,
+                };
+                self.expect_symbol(")")?;
+                return Ok(expr)
+            }
+            Token::Word(w) if w == "exists" => {
+                self.at += 1;
+                self.expect_symbol("(")?;
+                let query = Box::new(self.select()?);
+                self.expect_symbol(")")?;
+                return Ok(Expr::Exists(query))
+            }
+            Token::Word(w) if w == "true" || w == "false" => Expr::Literal(Value::Bool(w == "true")),
+            Token::Word(w) if w == "null" => Expr::Literal(Value::Null),
+            Token::Word(w) if w == "case" => return self.case(),
+            // A typed literal, as in `DATE '2024-01-31'`, is a cast of its text.
+            Token::Word(w)
+                if matches!(
+                    w.as_str(),
+                    "date" | "time" | "timestamp" | "timestamptz" | "interval" | "decimal" | "numeric" | "json"
+                        | "jsonb"
+                ) && matches!(self.peek_at(1), Token::String(_)) =>
+            {
+                let to = self.column_type()?;
+                let Token::String(text) = self.advance() else { unreachable!() };
+                return Ok(Expr::Cast { expr: Box::new(Expr::Literal(Value::Text(text))), to })
+            }
+            Token::Word(w) if w == "cast" => {
+                self.at += 1;
+                self.expect_symbol("(")?;
+                let expr = Box::new(self.expr()?);
+                self.expect_keyword("as")?;
+                let to = self.column_type()?;
+                self.expect_symbol(")")?;
+                return Ok(Expr::Cast { expr, to })
+            }
+            _ if self.is_ident() => {
+                let name = self.ident()?;
+                if self.symbol("(") {
+                    return self.function(name)
+                }
+                if self.symbol(".") {
+                    return Ok(Expr::Column { table: Some(name), name: self.ident()? })
+                }
+                return Ok(Expr::Column { table: None, name })
+            }
+            _ => return self.unexpected("an expression"),
+        };
+        self.at += 1;
+        Ok(expr)
+    }
+
+    /// The rest of a call to `name` after its opening parenthesis, with the window it is over if any.
+    fn function(&mut self, name: String) -> Result<Expr> {
+        let (args, distinct) = if self.symbol("*") {
+            self.expect_symbol(")")?;
+            (Vec::new(), false)
+        } else {
+            let distinct = self.keyword("distinct");
+            let args = if !distinct && self.symbol(")") { Vec::new() } else { self.parenthesized_rest(Parser::expr)? };
+            (args, distinct)
+        };
+        // `over` is not reserved, so only `over (` starts a window rather than being an alias.
+        let over = matches!(self.peek(), Token::Word(w) if w == "over");
+        let over = over && matches!(self.peek_at(1), Token::Symbol("("));
+        if !over {
+            return Ok(Expr::Function { name, args, distinct })
+        }
+        if distinct {
+            return self.unexpected("a call without `DISTINCT` before `OVER`")
+        }
+        self.at += 2;
+        Ok(Expr::Window { name, args, over: self.window()? })
+    }
+
+    /// The rest of a window after `OVER (`.
+    fn window(&mut self) -> Result<Window> {
+        let partition_by = if self.keyword("partition") {
+            self.expect_keyword("by")?;
+            self.comma_separated(Parser::expr)?
+        } else {
+            Vec::new()
+        };
+        let order_by = if self.keyword("order") {
+            self.expect_keyword("by")?;
+            self.comma_separated(Parser::order_by)?
+        } else {
+            Vec::new()
+        };
+        let range = self.keyword("range");
+        let frame = if range || self.keyword("rows") {
+            // A start alone frames the rows from it to the current row.
+            let between = self.keyword("between");
+            let start = self.frame_bound()?;
+            let end = if between {
+                self.expect_keyword("and")?;
+                self.frame_bound()?
+            } else {
+                FrameBound::CurrentRow
+            };
+            if start == FrameBound::UnboundedFollowing || end == FrameBound::UnboundedPreceding {
+                return self.unexpected_previous("a frame starting before it ends")
+            }
+            Some(Frame { range, start, end })
+        } else {
+            None
+        };
+        self.expect_symbol(")")?;
+        Ok(Window { partition_by, order_by, frame })
+    }
+
+    fn frame_bound(&mut self) -> Result<FrameBound> {
+        if self.keyword("unbounded") {
+            if self.keyword("preceding") {
+                return Ok(FrameBound::UnboundedPreceding)
+            }
+            self.expect_keyword("following")?;
+            return Ok(FrameBound::UnboundedFollowing)
+        }
+        if self.keyword("current") {
+            self.expect_keyword("row")?;
+            return Ok(FrameBound::CurrentRow)
+        }
+        let Token::Int(n) = *self.peek() else { return self.unexpected("a frame bound") };
+        let Ok(n) = u64::try_from(n) else { return self.unexpected("a frame bound") };
+        self.at += 1;
+        if self.keyword("preceding") {
+            return Ok(FrameBound::Preceding(n))
+        }
+        self.expect_keyword("following")?;
+        Ok(FrameBound::Following(n))
+    }
+
+    fn case(&mut self) -> Result<Expr> {
+        self.expect_keyword("case")?;
+        let operand = match self.peek() {
+            Token::Word(w) if w == "when" => None,
+            _ => Some(Box::new(self.expr()?)),
+        };
+        let mut branches = Vec::new();
+        while self.keyword("when") {
+            let condition = self.expr()?;
+            self.expect_keyword("then")?;
+            branches.push((condition, self.expr()?));
+        }
+        if branches.is_empty() {
+            return self.unexpected("`WHEN`")
+        }
+        let otherwise = if self.keyword("else") { Some(Box::new(self.expr()?)) } else { None };
+        self.expect_keyword("end")?;
+        Ok(Expr::Case { operand, branches, otherwise })
+    }
+
+    /// One or more of `item` separated by commas.
+    fn comma_separated<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let mut items = vec![item(self)?];
+        while self.symbol(",") {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    /// A comma separated list after its opening parenthesis, and the closing one.
+    fn parenthesized_rest<T>(&mut self, item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let items = self.comma_separated(item)?;
+        self.expect_symbol(")")?;
+        Ok(items)
+    }
+
+    fn is_ident(&self) -> bool {
+        match self.peek() {
+            Token::Word(w) => !RESERVED.contains(&w.as_str()),
+            // As in PostgreSQL, `""` names nothing.
+            Token::QuotedIdent(name) => !name.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        if !self.is_ident() {
+            return self.unexpected("a name")
+        }
+        match self.advance() {
+            Token::Word(name) | Token::QuotedIdent(name) => Ok(name),
+            _ => unreachable!("checked by is_ident"),
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, ahead: usize) -> &Token {
+        // The last token is always `End`, which nothing consumes.
+        &self.tokens[(self.at + ahead).min(self.tokens.len() - 1)].0
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token != Token::End {
+            self.at += 1;
+        }
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Token::Word(w) if w == keyword);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &'static str) -> Result<()> {
+        if self.keyword(keyword) {
+            return Ok(())
+        }
+        self.unexpected(keyword)
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Token::Symbol(s) if *s == symbol);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<()> {
+        if self.symbol(symbol) {
+            return Ok(())
+        }
+        self.unexpected(symbol)
+    }
+
+    /// An error at the next token.
+    fn unexpected<T>(&self, expected: &'static str) -> Result<T> {
+        self.error_at(self.at, expected)
+    }
+
+    /// An error at the token just consumed.
+    fn unexpected_previous<T>(&self, expected: &'static str) -> Result<T> {
+        self.error_at(self.at - 1, expected)
+    }
+
+    fn error_at<T>(&self, at: usize, expected: &'static str) -> Result<T> {
+        let (token, offset) = &self.tokens[at.min(self.tokens.len() - 1)];
+        let found = match token {
+            Token::Word(w) => w.clone(),
+            Token::QuotedIdent(name) => format!("\"{name}\""),
+            Token::Int(v) => v.to_string(),
+            Token::Decimal(v) => v.to_string(),
+            Token::Float(v) => v.to_string(),
+            Token::String(_) => "a string".to_string(),
+            Token::Bytes(_) => "a bytes literal".to_string(),
+            Token::Placeholder(_) => "a placeholder".to_string(),
+            Token::Symbol(s) => s.to_string(),
+            Token::End => "the end of the input".to_string(),
+        };
+        Err(ParseError::at(self.sql, *offset, ParseErrorKind::Unexpected { found, expected }))
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::catalog::{TriggerEvent, TriggerTiming};
+    use crate::sql::ast::{
+        AlterColumn, BinaryOp, ColumnSpec, ConflictAction, ConstraintKind, CreateIndex, CreateSequence, CreateTable,
+        CreateTrigger, Distinct, Expr, Frame, FrameBound, FromItem, Insert, InsertSource, JoinKind, OnConflict, OrderBy,
+        Partitioning, ReferentialAction, Select, SelectItem, Statement, TableConstraint, UnaryOp,
+    };
+    use crate::collation::Collation;
+    use crate::sql::{parse, parse_expr, parse_statement, split, ParseError, ParseErrorKind};
+    use crate::table::IndexKind;
+    use crate::tuple::{ColumnType, Value};
+
+    fn column(name: &str) -> Expr {
+        Expr::Column { table: None, name: name.to_string() }
+    }
+
+    fn qualified(table: &str, name: &str) -> Expr {
+        Expr::Column { table: Some(table.to_string()), name: name.to_string() }
+    }
+
+    fn int(v: i64) -> Expr {
+        Expr::Literal(Value::Int(v))
+    }
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+    }
+
+    #[test]
+    fn test_select_and_precedence() -> Result<(), ParseError> {
+        let sql = "SELECT DISTINCT o.*, count(*) AS n, -a * 2 + b total FROM orders o LEFT JOIN users AS u ON o.user = u.id, \
+                   tags WHERE NOT a = 1 OR b IS NOT NULL AND c NOT BETWEEN ? AND $3 + 1 \
+                   GROUP BY o.id ORDER BY n DESC NULLS LAST, 2 LIMIT 10 OFFSET ?";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let users = FromItem::Table { name: "users".to_string(), alias: Some("u".to_string()) };
+        let orders = FromItem::Table { name: "orders".to_string(), alias: Some("o".to_string()) };
+        let joined = FromItem::Join {
+            left: Box::new(orders),
+            right: Box::new(users),
+            kind: JoinKind::Left,
+            on: Some(binary(BinaryOp::Eq, qualified("o", "user"), qualified("u", "id"))),
+        };
+        let tags = FromItem::Table { name: "tags".to_string(), alias: None };
+        let negated = Expr::Unary { op: UnaryOp::Neg, expr: Box::new(column("a")) };
+        let not_between = Expr::Between {
+            expr: Box::new(column("c")),
+            low: Box::new(Expr::Parameter(0)),
+            high: Box::new(binary(BinaryOp::Add, Expr::Parameter(2), int(1))),
+            negated: true,
+        };
+        let not_null = Expr::IsNull { expr: Box::new(column("b")), negated: true };
+        let not_eq = Expr::Unary { op: UnaryOp::Not, expr: Box::new(binary(BinaryOp::Eq, column("a"), int(1))) };
+        assert_eq!(*select, Select {
+            with: None,
+            distinct: Some(Distinct::Rows),
+            items: vec![
+                SelectItem::Wildcard(Some("o".to_string())),
+                SelectItem::Expr {
+                    expr: Expr::Function { name: "count".to_string(), args: vec![], distinct: false },
+                    alias: Some("n".to_string()),
+                },
+                SelectItem::Expr {
+                    expr: binary(BinaryOp::Add, binary(BinaryOp::Mul, negated, int(2)), column("b")),
+                    alias: Some("total".to_string()),
+                },
+            ],
+            from: Some(FromItem::Join { left: Box::new(joined), right: Box::new(tags), kind: JoinKind::Cross, on: None }),
+            filter: Some(binary(BinaryOp::Or, not_eq, binary(BinaryOp::And, not_null, not_between))),
+            group_by: vec![qualified("o", "id")],
+            having: None,
+            order_by: vec![
+                OrderBy { expr: column("n"), descending: true, nulls_first: Some(false) },
+                OrderBy { expr: int(2), descending: false, nulls_first: None },
+            ],
+            limit: Some(int(10)),
+            offset: Some(Expr::Parameter(3)),
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_statements_and_errors() -> Result<(), ParseError> {
+        let script = "create table if not exists \"Users\" (id BIGINT NOT NULL, name varchar(20), score double precision);
+                      INSERT INTO t (a, b) VALUES (1, 'x'), (?, CASE WHEN ? THEN x'01' ELSE NULL END);
+                      UPDATE t SET a = a + 1, b = lower(b) WHERE a IN (1, 2) AND b LIKE 'x%';
+                      DELETE FROM t; CREATE INDEX by_b ON t (b) INCLUDE (a); DROP INDEX by_b ON t; DROP TABLE IF EXISTS t;
+                      ALTER TABLE t ALTER COLUMN a DROP NOT NULL; SELECT CAST(a AS text), \"select\" FROM t;";
+        let statements = parse(script)?;
+        assert_eq!(statements.len(), 9);
+        let spec = |name: &str, column_type, nullable| {
+            let collation = Collation::Binary;
+            ColumnSpec { name: name.to_string(), column_type, nullable, default: None, identity: false, collation }
+        };
+        assert_eq!(statements[0], Statement::CreateTable(CreateTable {
+            name: "Users".to_string(),
+            columns: vec![
+                spec("id", ColumnType::Int, false),
+                spec("name", ColumnType::Text, true),
+                spec("score", ColumnType::Float, true),
+            ],
+            constraints: vec![],
+            if_not_exists: true,
+            ttl: None,
+            without_rowid: false,
+            partition_by: None,
+        }));
+        let Statement::Insert(Insert { source: InsertSource::Values(rows), .. }) = &statements[1] else {
+            panic!("not an insert of values")
+        };
+        assert_eq!(rows[1][1], Expr::Case {
+            operand: None,
+            branches: vec![(Expr::Parameter(1), Expr::Literal(Value::Bytes(vec![1])))],
+            otherwise: Some(Box::new(Expr::Literal(Value::Null))),
+        });
+        let Statement::Select(select) = &statements[8] else { panic!("not a select") };
+        assert_eq!(select.items[1], SelectItem::Expr { expr: column("select"), alias: None });
+        let Statement::Insert(insert) = parse_statement("INSERT INTO t (a) SELECT x FROM u WHERE x > 1")? else {
+            panic!("not an insert")
+        };
+        assert!(matches!(&insert.source, InsertSource::Query(select) if select.filter.is_some()));
+        assert!(parse_statement("INSERT INTO t (a) (1)").is_err());
+        let sql = "INSERT INTO t VALUES (1, 2) ON CONFLICT (a) DO UPDATE SET b = excluded.b + b WHERE b < 5";
+        let Statement::Insert(Insert { on_conflict: Some(on_conflict), .. }) = parse_statement(sql)? else {
+            panic!("not an upsert")
+        };
+        assert_eq!(on_conflict.target, vec!["a".to_string()]);
+        let ConflictAction::Update { assignments, filter } = on_conflict.action else { panic!("not DO UPDATE") };
+        assert_eq!(assignments[0].1.to_string(), "(excluded.b + b)");
+        assert!(filter.is_some());
+        let sql = "INSERT INTO t SELECT * FROM u ON CONFLICT DO NOTHING";
+        let Statement::Insert(insert) = parse_statement(sql)? else { panic!("not an insert") };
+        assert_eq!(insert.on_conflict, Some(OnConflict { target: Vec::new(), action: ConflictAction::Nothing }));
+
+        let sql = "CREATE TABLE t (id INT PRIMARY KEY, a TEXT CONSTRAINT t_a UNIQUE, UNIQUE (a, id))";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        assert_eq!(create.constraints, vec![
+            TableConstraint { name: None, kind: ConstraintKind::PrimaryKey(names(&["id"])) },
+            TableConstraint { name: Some("t_a".to_string()), kind: ConstraintKind::Unique(names(&["a"])) },
+            TableConstraint { name: None, kind: ConstraintKind::Unique(names(&["a", "id"])) },
+        ]);
+        let sql = "CREATE TABLE u (t INT REFERENCES t ON DELETE SET NULL, FOREIGN KEY (t) REFERENCES t (id), \
+            CONSTRAINT u_t FOREIGN KEY (t) REFERENCES t (id) ON DELETE CASCADE)";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        let foreign_key = |references: &[&str], on_delete| ConstraintKind::ForeignKey {
+            columns: names(&["t"]),
+            table: "t".to_string(),
+            references: names(references),
+            on_delete,
+        };
+        let kinds: Vec<_> = create.constraints.iter().map(|c| c.kind.clone()).collect();
+        assert_eq!(kinds, vec![
+            foreign_key(&[], ReferentialAction::SetNull),
+            foreign_key(&["id"], ReferentialAction::Restrict),
+            foreign_key(&["id"], ReferentialAction::Cascade),
+        ]);
+        let dropped = parse_statement("ALTER TABLE u DROP CONSTRAINT u_t")?;
+        let change = AlterColumn::DropConstraint("u_t".to_string());
+        assert_eq!(dropped, Statement::AlterTable { table: "u".to_string(), change });
+        let Statement::CreateIndex(index) = parse_statement("CREATE UNIQUE INDEX by_a ON t (a)")? else { unreachable!() };
+        assert!(index.unique);
+        let Statement::CreateIndex(index) = parse_statement("CREATE INDEX by_a ON t USING HASH (a, b)")? else {
+            unreachable!()
+        };
+        assert_eq!((index.kind, index.columns.len()), (IndexKind::Hash, 2));
+        let error = parse_statement("CREATE INDEX by_a ON t USING gist (a)").unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::Unexpected { found: "gist".to_string(), expected: "an index method" });
+
+        let error = parse("SELECT a\nFROM t WHERE a = = 1").unwrap_err();
+        let found = ParseErrorKind::Unexpected { found: "=".to_string(), expected: "an expression" };
+        assert_eq!((error.kind, error.offset, error.line, error.column), (found, 26, 2, 18));
+        let error = parse_statement("SELECT a FROM select").unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::Unexpected { found: "select".to_string(), expected: "a name" });
+        let error = parse_statement("SELECT 1; SELECT 2").unwrap_err();
+        assert_eq!((error.kind, error.column), (ParseErrorKind::Unexpected {
+            found: "select".to_string(),
+            expected: "the end of the statement",
+        }, 11));
+        assert!(parse("CREATE TABLE t (a widget)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_defaults_and_printing() -> Result<(), ParseError> {
+        let sql = "CREATE TABLE t (a INT DEFAULT -1 NOT NULL, b TEXT DEFAULT 'x' || 'y' UNIQUE, c INT DEFAULT now())";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        let defaults: Vec<_> = create.columns.iter().map(|c| c.default.clone()).collect();
+        let now = Expr::Function { name: "now".to_string(), args: vec![], distinct: false };
+        let text = |s: &str| Expr::Literal(Value::Text(s.to_string()));
+        assert_eq!(defaults, vec![
+            Some(int(-1)),
+            Some(binary(BinaryOp::Concat, text("x"), text("y"))),
+            Some(now),
+        ]);
+        assert!(!create.columns[0].nullable);
+        assert_eq!(create.constraints.len(), 1);
+        let Statement::AlterTable { change, .. } = parse_statement("ALTER TABLE t ALTER a SET DEFAULT 2")? else {
+            panic!("not an alter table")
+        };
+        assert_eq!(change, AlterColumn::SetDefault { column: "a".to_string(), default: Some(int(2)) });
+        let Statement::AlterTable { change, .. } = parse_statement("ALTER TABLE t ALTER a DROP DEFAULT")? else {
+            panic!("not an alter table")
+        };
+        assert_eq!(change, AlterColumn::SetDefault { column: "a".to_string(), default: None });
+
+        // Printed expressions parse back to themselves.
+        let sql = "SELECT -a * (b + 2) - c, \"Mixed\".\"select\" || 'it''s', x NOT LIKE 'a%' OR y IS NOT NULL, \
+            z NOT IN (1, 2.5, x'0aff'), w BETWEEN $1 AND $2, count(*), count(DISTINCT a), \
+            CASE a WHEN 1 THEN TRUE ELSE NULL END, CAST(a AS text), -9223372036854775807 - 1, -9223372036854775808, \
+            - -1, -(-9223372036854775808)";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        for item in &select.items {
+            let SelectItem::Expr { expr, .. } = item else { panic!("not an expression") };
+            let Statement::Select(printed) = parse_statement(&format!("SELECT {expr}"))? else { unreachable!() };
+            assert_eq!(printed.items[0], SelectItem::Expr { expr: expr.clone(), alias: None }, "{expr}");
+        }
+        // A negated integer is folded into a literal, the smallest one too, unless that would overflow.
+        let negated = |expr| Expr::Unary { op: UnaryOp::Neg, expr: Box::new(expr) };
+        let folded = |i: usize| match &select.items[i] {
+            SelectItem::Expr { expr, .. } => expr.clone(),
+            item => panic!("{item:?}"),
+        };
+        assert_eq!((folded(10), folded(11), folded(12)), (int(i64::MIN), int(1), negated(int(i64::MIN))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_views_and_explain() -> Result<(), ParseError> {
+        let sql = "CREATE VIEW v (a, b) AS SELECT DISTINCT t.x AS a, count(*) FROM t AS t LEFT JOIN u ON t.x = u.x, w \
+            WHERE t.y > 1 GROUP BY t.x HAVING count(*) > 2 ORDER BY a DESC NULLS LAST LIMIT 5 OFFSET 1";
+        let Statement::CreateView(create) = parse_statement(sql)? else { panic!("not a create view") };
+        assert_eq!((create.name.as_str(), create.columns.clone()), ("v", vec!["a".to_string(), "b".to_string()]));
+        // A printed query parses back to itself.
+        let Statement::Select(printed) = parse_statement(&create.query.to_string())? else { panic!("not a select") };
+        assert_eq!(printed, create.query);
+        let Statement::CreateView(create) = parse_statement("CREATE VIEW v AS SELECT * FROM t")? else { panic!() };
+        assert!(create.columns.is_empty());
+
+        let Statement::Explain { analyze, select } = parse_statement("EXPLAIN ANALYZE SELECT * FROM v")? else {
+            panic!("not an explain")
+        };
+        assert!(analyze && select.from.is_some());
+        assert!(matches!(parse_statement("explain select 1")?, Statement::Explain { analyze: false, .. }));
+        assert!(parse_statement("EXPLAIN DROP VIEW v").is_err());
+
+        let drop = |name: &str, if_exists| Statement::DropView { name: name.to_string(), if_exists };
+        assert_eq!(parse_statement("DROP VIEW v")?, drop("v", false));
+        assert_eq!(parse_statement("DROP VIEW IF EXISTS v")?, drop("v", true));
+        assert!(parse_statement("CREATE VIEW v SELECT 1").is_err());
+
+        let sql = "SELECT DISTINCT ON (a, b + 1) a, c FROM t ORDER BY a, b + 1, c DESC";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let on = vec![column("a"), binary(BinaryOp::Add, column("b"), int(1))];
+        assert_eq!(select.distinct, Some(Distinct::On(on)));
+        assert_eq!(parse_statement(&select.to_string())?, Statement::Select(select));
+        assert!(parse_statement("SELECT DISTINCT ON a FROM t").is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_subqueries() -> Result<(), ParseError> {
+        let sql = "SELECT (SELECT max(b) FROM u WHERE u.a = t.a), d.n FROM t, (SELECT a AS n FROM u) d \
+            WHERE EXISTS (SELECT 1 FROM u) AND NOT EXISTS (SELECT * FROM w) AND t.a NOT IN (SELECT a FROM w)";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let Some(FromItem::Join { right, .. }) = &select.from else { panic!("not a join") };
+        assert!(matches!(&**right, FromItem::Derived { alias, .. } if alias == "d"));
+        let Some(Expr::Binary { right, .. }) = &select.filter else { panic!("not a conjunction") };
+        assert!(matches!(&**right, Expr::InSubquery { negated: true, .. }));
+        let Statement::Select(printed) = parse_statement(&select.to_string())? else { panic!("not a select") };
+        assert_eq!(printed, select);
+
+        assert!(matches!(parse_expr("x IN (SELECT 1)")?, Expr::InSubquery { negated: false, .. }));
+        assert!(matches!(parse_expr("(SELECT 1) + 1")?, Expr::Binary { .. }));
+        assert!(matches!(parse_expr("(1) + 1")?, Expr::Binary { .. }));
+        assert!(parse_statement("SELECT * FROM (SELECT 1)").is_err());
+        assert!(parse_expr("EXISTS SELECT 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_windows() -> Result<(), ParseError> {
+        let sql = "SELECT row_number() OVER (), rank() OVER (PARTITION BY a, b ORDER BY c DESC), \
+            sum(x) OVER (ORDER BY c ROWS BETWEEN 2 PRECEDING AND UNBOUNDED FOLLOWING), \
+            count(*) OVER (PARTITION BY a RANGE UNBOUNDED PRECEDING), max(x) over FROM t";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let SelectItem::Expr { expr: Expr::Window { name, over, .. }, .. } = &select.items[1] else {
+            panic!("not a window")
+        };
+        assert_eq!((name.as_str(), over.frame), ("rank", None));
+        assert_eq!(over.partition_by, vec![column("a"), column("b")]);
+        let SelectItem::Expr { expr: Expr::Window { over, .. }, .. } = &select.items[2] else { panic!("not a window") };
+        let frame = Frame { range: false, start: FrameBound::Preceding(2), end: FrameBound::UnboundedFollowing };
+        assert_eq!(over.frame, Some(frame));
+        let SelectItem::Expr { expr: Expr::Window { args, over, .. }, .. } = &select.items[3] else {
+            panic!("not a window")
+        };
+        let frame = Frame { range: true, start: FrameBound::UnboundedPreceding, end: FrameBound::CurrentRow };
+        assert!(args.is_empty() && over.frame == Some(frame));
+        // `over` without a window after it is an alias.
+        let SelectItem::Expr { expr: Expr::Function { .. }, alias: Some(alias) } = &select.items[4] else {
+            panic!("not an aliased call")
+        };
+        assert_eq!(alias, "over");
+        assert_eq!(parse_statement(&select.to_string())?, Statement::Select(select));
+
+        assert!(parse_expr("count(DISTINCT a) OVER ()").is_err());
+        assert!(parse_expr("sum(a) OVER (ROWS UNBOUNDED FOLLOWING)").is_err());
+        assert!(parse_expr("sum(a) OVER (ROWS BETWEEN CURRENT ROW AND UNBOUNDED PRECEDING)").is_err());
+        assert!(parse_expr("sum(a) OVER (ORDER BY a ROWS 1)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequences() -> Result<(), ParseError> {
+        let create = CreateSequence { name: "s".to_string(), start: Some(-5), increment: None, cache: Some(10) };
+        assert_eq!(parse_statement("CREATE SEQUENCE s CACHE 10 START WITH -5")?, Statement::CreateSequence(create));
+        let drop = Statement::DropSequence { name: "s".to_string(), if_exists: true };
+        assert_eq!(parse_statement("DROP SEQUENCE IF EXISTS s")?, drop);
+        let Statement::CreateTable(create) = parse_statement("CREATE TABLE t (a INT AUTOINCREMENT PRIMARY KEY, \
+            b INT NOT NULL GENERATED BY DEFAULT AS IDENTITY, c INT)")?
+        else {
+            panic!("not a create table")
+        };
+        let identities: Vec<_> = create.columns.iter().map(|c| c.identity).collect();
+        assert_eq!((identities, create.constraints.len()), (vec![true, true, false], 1));
+        assert!(parse_statement("CREATE SEQUENCE s CACHE -1").is_err());
+        assert!(parse_statement("CREATE TABLE t (a INT GENERATED ALWAYS AS IDENTITY)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_with() -> Result<(), ParseError> {
+        let sql = "WITH a AS (SELECT 1), b (x, y) AS (SELECT * FROM a) SELECT * FROM b WHERE x IN (WITH c AS \
+            (SELECT 2) SELECT * FROM c)";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let with = select.with.as_ref().unwrap();
+        assert!(!with.recursive);
+        let names: Vec<_> = with.ctes.iter().map(|c| (c.name.as_str(), c.columns.len())).collect();
+        assert_eq!(names, vec![("a", 0), ("b", 2)]);
+        assert_eq!(parse_statement(&select.to_string())?, Statement::Select(select));
+
+        let sql = "WITH RECURSIVE n AS (SELECT 1 UNION ALL SELECT n + 1 FROM n WHERE n < 5) SELECT * FROM n";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let with = select.with.as_ref().unwrap();
+        assert!(with.recursive && with.ctes[0].union.as_ref().is_some_and(|u| u.all));
+        assert_eq!(parse_statement(&select.to_string())?, Statement::Select(select));
+        assert!(matches!(parse_statement("INSERT INTO t WITH a AS (SELECT 1) SELECT * FROM a")?, Statement::Insert(_)));
+        assert!(parse_statement("WITH a AS SELECT 1 SELECT 1").is_err());
+        assert!(parse_statement("WITH a AS (SELECT 1)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_and_time_types() -> Result<(), ParseError> {
+        let sql = "CREATE TABLE t (a DATE, b TIME WITHOUT TIME ZONE, c TIMESTAMP(3) WITH TIME ZONE, d TIMESTAMPTZ, \
+            e timestamp, f INTERVAL)";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        let types: Vec<_> = create.columns.iter().map(|c| c.column_type).collect();
+        assert_eq!(types, vec![
+            ColumnType::Date,
+            ColumnType::Time,
+            ColumnType::TimestampTz,
+            ColumnType::TimestampTz,
+            ColumnType::Timestamp,
+            ColumnType::Interval,
+        ]);
+        assert!(parse_statement("CREATE TABLE t (a TIME WITH TIME ZONE)").is_err());
+
+        let sql = "SELECT DATE '2024-01-31' + INTERVAL '1 month', CAST(a AS TIMESTAMP WITH TIME ZONE), date";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let cast = |text: &str, to| Expr::Cast { expr: Box::new(Expr::Literal(Value::Text(text.to_string()))), to };
+        let sum = binary(BinaryOp::Add, cast("2024-01-31", ColumnType::Date), cast("1 month", ColumnType::Interval));
+        assert_eq!(select.items[0], SelectItem::Expr { expr: sum, alias: None });
+        // Without a string after it, `date` is a name.
+        let date = Expr::Column { table: None, name: "date".to_string() };
+        assert_eq!(select.items[2], SelectItem::Expr { expr: date, alias: None });
+        assert_eq!(parse_statement(&select.to_string())?, Statement::Select(select));
+
+        let sql = "CREATE TABLE t (a TIMESTAMP) TTL a + INTERVAL '30 days'";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        assert_eq!(create.ttl, Some(("a".to_string(), cast("30 days", ColumnType::Interval))));
+        let Statement::CreateTable(create) = parse_statement("CREATE TABLE t (a INT) TTL a + 60 * 60")? else {
+            panic!("not a create table")
+        };
+        assert_eq!(create.ttl, Some(("a".to_string(), binary(BinaryOp::Mul, int(60), int(60)))));
+        let error = parse_statement("CREATE TABLE t (a INT) TTL a - 60").unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::Unexpected { found: "-".to_string(), expected: "+" });
+        let sql = "CREATE TABLE t (a INT PRIMARY KEY, b TIMESTAMP) WITHOUT ROWID TTL b + INTERVAL '1 day'";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        assert!(create.without_rowid && create.ttl.is_some());
+        assert!(parse_statement("CREATE TABLE t (a INT PRIMARY KEY) WITHOUT ROWID WITHOUT ROWID").is_err());
+        let sql = "CREATE TABLE t (a INT NOT NULL, b TEXT) PARTITION BY HASH (b) PARTITIONS 4";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        assert_eq!(create.partition_by, Some(Partitioning::Hash { column: "b".to_string(), partitions: 4 }));
+        let sql = "CREATE TABLE t (a INT NOT NULL) PARTITION BY RANGE (a) TTL a + 60";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        assert_eq!(create.partition_by, Some(Partitioning::Range("a".to_string())));
+        let error = parse_statement("CREATE TABLE t (a INT) PARTITION BY LIST (a)").unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::Unexpected { found: "list".to_string(), expected: "`RANGE` or `HASH`" });
+        let Statement::AlterTable { change, .. } = parse_statement("ALTER TABLE t ADD PARTITION FROM 7 * 4")? else {
+            panic!("not an alter table")
+        };
+        assert_eq!(change, AlterColumn::AddPartition(binary(BinaryOp::Mul, int(7), int(4))));
+        let Statement::AlterTable { change, .. } = parse_statement("ALTER TABLE t ADD partition INT")? else {
+            panic!("not an alter table")
+        };
+        assert!(matches!(change, AlterColumn::Add(ColumnSpec { name, .. }) if name == "partition"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimal_types() -> Result<(), ParseError> {
+        let sql = "CREATE TABLE t (a DECIMAL(10, 2), b NUMERIC(5), c numeric, d DEC(1000, 1000))";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        let types: Vec<_> = create.columns.iter().map(|c| c.column_type).collect();
+        assert_eq!(types, vec![
+            ColumnType::Decimal { precision: 10, scale: 2 },
+            ColumnType::Decimal { precision: 5, scale: 0 },
+            ColumnType::NUMERIC,
+            ColumnType::Decimal { precision: 1000, scale: 1000 },
+        ]);
+        for bad in ["DECIMAL(0)", "DECIMAL(1001)", "DECIMAL(4, 5)", "DECIMAL(4, -1)", "DECIMAL()"] {
+            assert!(parse_statement(&format!("CREATE TABLE t (a {bad})")).is_err(), "{bad}");
+        }
+
+        let sql = "SELECT CAST(a AS DECIMAL(10, 2)), NUMERIC '1.50', CAST(b AS numeric)";
+        let Statement::Select(select) = parse_statement(sql)? else { panic!("not a select") };
+        let text = Box::new(Expr::Literal(Value::Text("1.50".to_string())));
+        let literal = Expr::Cast { expr: text, to: ColumnType::NUMERIC };
+        assert_eq!(select.items[1], SelectItem::Expr { expr: literal, alias: None });
+        assert_eq!(parse_statement(&select.to_string())?, Statement::Select(select));
+        Ok(())
+    }
+    #[test]
+    fn test_json() -> Result<(), ParseError> {
+        let text = |s: &str| Expr::Literal(Value::Text(s.to_string()));
+        // The JSON operators bind tighter than comparisons and looser than arithmetic, from the left.
+        let extracted = binary(BinaryOp::Extract, column("doc"), text("a"));
+        let expected = binary(BinaryOp::ExtractText, extracted, binary(BinaryOp::Add, int(1), int(2)));
+        assert_eq!(parse_expr("doc->'a'->>1 + 2 = 'x'")?, binary(BinaryOp::Eq, expected, text("x")));
+        let path = binary(BinaryOp::ExtractPathText, column("doc"), text("{a,0}"));
+        assert_eq!(parse_expr("doc #>> '{a,0}' LIKE 'x%'")?, Expr::Like {
+            expr: Box::new(path),
+            pattern: Box::new(text("x%")),
+            negated: false,
+        });
+
+        let sql = "CREATE TABLE t (a JSON, b jsonb)";
+        let Statement::CreateTable(create) = parse_statement(sql)? else { panic!("not a create table") };
+        assert!(create.columns.iter().all(|c| c.column_type == ColumnType::Json));
+        let literal = Expr::Cast { expr: Box::new(text("[1]")), to: ColumnType::Json };
+        assert_eq!(parse_expr("JSONB '[1]'")?, literal);
+
+        let index = parse_statement("CREATE INDEX by_name ON t ((a->>'name'), b) INCLUDE (c)")?;
+        assert_eq!(index, Statement::CreateIndex(CreateIndex {
+            name: "by_name".to_string(),
+            unique: false,
+            table: "t".to_string(),
+            kind: IndexKind::BTree,
+            columns: vec![binary(BinaryOp::ExtractText, column("a"), text("name")), column("b")],
+            include: vec!["c".to_string()],
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_collations() -> Result<(), ParseError> {
+        let collate = |expr, collation| Expr::Collate { expr: Box::new(expr), collation };
+        // `COLLATE` binds tighter than arithmetic and comparisons, and looser than a sign.
+        let negated = Expr::Unary { op: UnaryOp::Neg, expr: Box::new(column("a")) };
+        let concatenated = binary(BinaryOp::Concat, column("b"), collate(negated, Collation::CaseInsensitive));
+        let expected = binary(BinaryOp::Eq, concatenated, collate(column("c"), Collation::Binary));
+        let expr = parse_expr("b || -a COLLATE NOCASE = c collate binary")?;
+        assert_eq!(expr, expected);
+        assert_eq!(parse_expr(&expr.to_string())?, expr);
+        let error = parse_expr("a COLLATE french").unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::Unexpected { found: "french".to_string(), expected: "a collation" });
+
+        let Statement::CreateTable(create) = parse_statement("CREATE TABLE t (a TEXT COLLATE nocase NOT NULL, b TEXT)")?
+        else {
+            panic!("not a create table")
+        };
+        let collations: Vec<_> = create.columns.iter().map(|c| (c.collation, c.nullable)).collect();
+        assert_eq!(collations, vec![(Collation::CaseInsensitive, false), (Collation::Binary, true)]);
+        let Statement::CreateIndex(index) = parse_statement("CREATE INDEX by_a ON t (a COLLATE binary, b)")? else {
+            panic!("not a create index")
+        };
+        assert_eq!(index.columns, vec![collate(column("a"), Collation::Binary), column("b")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_triggers() -> Result<(), ParseError> {
+        let sql = "CREATE TRIGGER audit AFTER UPDATE ON t FOR EACH ROW BEGIN INSERT INTO log VALUES (old.a, new.a); \
+            DELETE FROM u WHERE b = old.b; END";
+        let body = "INSERT INTO log VALUES (old.a, new.a); \
+            DELETE FROM u WHERE b = old.b;";
+        let (name, table) = ("audit".to_string(), "t".to_string());
+        let (timing, event) = (TriggerTiming::After, TriggerEvent::Update);
+        let create = CreateTrigger { name, timing, event, table, body: body.to_string() };
+        assert_eq!(parse_statement(sql)?, Statement::CreateTrigger(create));
+        let Statement::CreateTrigger(create) = parse_statement("create trigger t before delete on t begin end")? else {
+            panic!("not a create trigger")
+        };
+        let created = (create.timing, create.event, create.body.as_str());
+        assert_eq!(created, (TriggerTiming::Before, TriggerEvent::Delete, ""));
+        let drop = Statement::DropTrigger { name: "audit".to_string(), table: "t".to_string() };
+        assert_eq!(parse_statement("DROP TRIGGER audit ON t")?, drop);
+
+        let error = parse_statement("CREATE TRIGGER a INSTEAD OF INSERT ON t BEGIN END").unwrap_err();
+        let expected = "`BEFORE` or `AFTER`";
+        assert_eq!(error.kind, ParseErrorKind::Unexpected { found: "instead".to_string(), expected });
+        assert!(parse_statement("CREATE TRIGGER a AFTER INSERT ON t BEGIN DELETE FROM t END x").is_err());
+        assert!(parse_statement("CREATE TRIGGER a AFTER INSERT ON t BEGIN DELETE FROM t").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split() -> Result<(), ParseError> {
+        let trigger = "CREATE TRIGGER a AFTER INSERT ON t BEGIN DELETE FROM u; END";
+        let script = format!("SELECT ';' FROM t;; {trigger} ;\n-- done\n");
+        assert_eq!(split(&script)?, vec!["SELECT ';' FROM t", trigger]);
+        // A trigger with no `END` yet fails at the end of the script, where more text would go.
+        let unfinished = "SELECT 1; CREATE TRIGGER a AFTER INSERT ON t BEGIN DELETE FROM u; ";
+        assert_eq!(split(unfinished).unwrap_err().offset, unfinished.len());
+        Ok(())
+    }
+}
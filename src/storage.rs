@@ -1,31 +1,65 @@
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
-use crate::page_store::{Data, PageId};
+use crate::page_store::{Data, PageId, PAGE_SIZE};
 
-pub trait Storage {
+/// Where a page store keeps its pages. Storage is `Send`, so that the page store over it can be shared by the
+/// workers of a parallel plan.
+pub trait Storage: Send {
     fn load_page(&self, buf: &mut Data, page: &PageId) -> Result<(), StorageError>;
     fn create_page(&mut self, page: &PageId) -> Result<(), StorageError>;
     fn write_page(&mut self, buf: &Data, page: &PageId) -> Result<(), StorageError>;
+
+    /// Make every page written so far durable. Storage that has nothing to do can rely on the default.
+    fn sync(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+impl<S: Storage + ?Sized> Storage for Box<S> {
+    fn load_page(&self, buf: &mut Data, page: &PageId) -> Result<(), StorageError> {
+        (**self).load_page(buf, page)
+    }
+
+    fn create_page(&mut self, page: &PageId) -> Result<(), StorageError> {
+        (**self).create_page(page)
+    }
+
+    fn write_page(&mut self, buf: &Data, page: &PageId) -> Result<(), StorageError> {
+        (**self).write_page(buf, page)
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        (**self).sync()
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum StorageError {
     NotFound,
     PageAlreadyExists,
+    Corrupt,
+    /// Reading or writing the file under the storage failed.
+    Io(io::ErrorKind),
+}
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        StorageError::Io(e.kind())
+    }
 }
 
-#[cfg(test)]
-pub struct TestStorage {
+/// Storage that keeps every page in memory and loses them all when dropped, for scratch space and tests.
+#[derive(Default)]
+pub struct MemoryStorage {
     map: HashMap<PageId, Data>
 }
-#[cfg(test)]
-impl TestStorage {
-    pub(crate) fn new() -> TestStorage {
-        TestStorage { map: HashMap::new() }
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage { map: HashMap::new() }
     }
 }
-#[cfg(test)]
-impl Storage for TestStorage {
+impl Storage for MemoryStorage {
     fn load_page(&self, buf: &mut Data, page: &PageId) -> Result<(), StorageError> {
         let data = self.map.get(page).ok_or(StorageError::NotFound)?;
         buf.copy_from_slice(data);
@@ -33,8 +67,8 @@ impl Storage for TestStorage {
     }
 
     fn write_page(&mut self, buf: &Data, page: &PageId) -> Result<(), StorageError> {
-        let dst = self.map.entry(page.clone()).or_insert_with(|| {
-            [0u8; 4096]
+        let dst = self.map.entry(*page).or_insert_with(|| {
+            [0u8; PAGE_SIZE]
         });
         dst.copy_from_slice(buf);
         Ok(())
@@ -44,7 +78,68 @@ impl Storage for TestStorage {
         if self.map.contains_key(page) {
             return Err(StorageError::PageAlreadyExists)
         }
-        self.map.insert(page.clone(), [0u8; 4096]);
+        self.map.insert(*page, [0u8; PAGE_SIZE]);
         Ok(()) 
     }
-}
\ No newline at end of file
+}
+
+/// Storage in a file, page `n` at byte `n * PAGE_SIZE`. A page is there once the file reaches past its
+/// end, so writing a page past the end makes the pages before it too, as zeroes.
+pub struct FileStorage {
+    file: File,
+    /// The number of pages the file holds.
+    pages: usize,
+}
+impl FileStorage {
+    /// Open the file at `path`, making it, empty, if `create` is set and there is none.
+    pub fn open(path: &Path, create: bool) -> Result<FileStorage, StorageError> {
+        let file = OpenOptions::new().read(true).write(true).create(create).truncate(false).open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if !len.is_multiple_of(PAGE_SIZE) {
+            return Err(StorageError::Corrupt)
+        }
+        Ok(FileStorage { file, pages: len / PAGE_SIZE })
+    }
+
+    /// Make a new, empty file at `path`, failing if there is one already.
+    pub fn create_new(path: &Path) -> Result<FileStorage, StorageError> {
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+        Ok(FileStorage { file, pages: 0 })
+    }
+
+    fn seek(&self, page: &PageId) -> Result<(), StorageError> {
+        (&self.file).seek(SeekFrom::Start((page.offset() * PAGE_SIZE) as u64))?;
+        Ok(())
+    }
+}
+impl Storage for FileStorage {
+    fn load_page(&self, buf: &mut Data, page: &PageId) -> Result<(), StorageError> {
+        if page.offset() >= self.pages {
+            return Err(StorageError::NotFound)
+        }
+        self.seek(page)?;
+        (&self.file).read_exact(buf)?;
+        Ok(())
+    }
+
+    fn create_page(&mut self, page: &PageId) -> Result<(), StorageError> {
+        if page.offset() < self.pages {
+            return Err(StorageError::PageAlreadyExists)
+        }
+        self.write_page(&[0u8; PAGE_SIZE], page)
+    }
+
+    fn write_page(&mut self, buf: &Data, page: &PageId) -> Result<(), StorageError> {
+        self.seek(page)?;
+        self.file.write_all(buf)?;
+        self.pages = self.pages.max(page.offset() + 1);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        Ok(self.file.sync_all()?)
+    }
+}
+
+#[cfg(test)]
+pub(crate) type TestStorage = MemoryStorage;
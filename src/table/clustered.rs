@@ -0,0 +1,283 @@
+//! Index-organized tables: rows stored in a B+tree keyed by their primary key instead of in a heap.
+//!
+//! The tree's keys are the order-preserving encoding of the primary key columns and its values are the
+//! encoded rows, so a lookup or range scan by primary key reads the rows straight from the leaves with
+//! no second hop to a heap. Each value starts with a kind byte: inline rows follow it directly, and rows
+//! too long for a B+tree cell are written to an overflow chain whose first page follows it instead.
+use std::ops::Bound;
+
+use crate::allocator::PageAllocator;
+use crate::btree::{BTree, BTreeError, BTreeRange};
+use crate::bytes::{read_u64, write_u64};
+use crate::overflow;
+use crate::page_store::{PageId, PageStore};
+use crate::storage::Storage;
+use crate::tuple::{Schema, TupleError, Value};
+
+use super::{key, stats, TableError, TableStats};
+
+const INLINE: u8 = 0;
+const OVERFLOW: u8 = 1;
+
+pub struct ClusteredTable<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    schema: Schema,
+    primary_key: Vec<usize>,
+    tree: BTree<'store, S>,
+}
+impl<'store, S: Storage> ClusteredTable<'store, S> {
+    /// Create a table whose rows are ordered and identified by the `primary_key` columns, which must not
+    /// be nullable.
+    pub fn create(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        schema: Schema,
+        primary_key: Vec<usize>,
+    ) -> Result<ClusteredTable<'store, S>, TableError> {
+        check_key(&schema, &primary_key)?;
+        let tree = BTree::create(store, allocator)?;
+        Ok(ClusteredTable { store, allocator, schema, primary_key, tree })
+    }
+
+    /// Open the table whose tree starts at `root`.
+    pub fn open(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        schema: Schema,
+        primary_key: Vec<usize>,
+        root: PageId,
+    ) -> Result<ClusteredTable<'store, S>, TableError> {
+        check_key(&schema, &primary_key)?;
+        let tree = BTree::open(store, allocator, root)?;
+        Ok(ClusteredTable { store, allocator, schema, primary_key, tree })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Primary key columns, most significant first.
+    pub fn primary_key(&self) -> &[usize] {
+        &self.primary_key
+    }
+
+    /// The page to pass to `open` to find this table again.
+    pub fn root(&self) -> PageId {
+        self.tree.meta_page()
+    }
+
+    /// Insert a row whose primary key is not in the table yet.
+    pub fn insert(&mut self, row: &[Value]) -> Result<(), TableError> {
+        let record = self.schema.encode(row)?;
+        let key = self.key(row);
+        if self.tree.get(&key)?.is_some() {
+            return Err(TableError::DuplicateKey)
+        }
+        self.put(&key, &record)
+    }
+
+    /// The row with primary key `key`, if there is one.
+    pub fn get(&self, key: &[Value]) -> Result<Option<Vec<Value>>, TableError> {
+        self.check_key_values(key)?;
+        match self.tree.get(&key::encode(key))? {
+            Some(stored) => Ok(Some(self.schema.decode(&self.load(&stored)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the row with the same primary key as `row`.
+    pub fn update(&mut self, row: &[Value]) -> Result<(), TableError> {
+        let record = self.schema.encode(row)?;
+        let key = self.key(row);
+        let Some(old) = self.tree.get(&key)? else { return Err(TableError::NoSuchRow) };
+        self.put(&key, &record)?;
+        self.release(&old)
+    }
+
+    /// Remove the row with primary key `key`, returning whether there was one.
+    pub fn delete(&mut self, key: &[Value]) -> Result<bool, TableError> {
+        self.check_key_values(key)?;
+        match self.tree.delete(&key::encode(key))? {
+            Some(old) => self.release(&old).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Return the pages of the tree and of every overflow chain to the allocator.
+    pub fn free(self) -> Result<(), TableError> {
+        for entry in self.tree.range::<&[u8], _>(..) {
+            self.release(&entry?.1)?;
+        }
+        Ok(self.tree.free()?)
+    }
+
+    /// Gather statistics over every row, as `Table::analyze` does.
+    pub fn analyze(&self, sample_size: usize) -> Result<TableStats, TableError> {
+        stats::analyze(&self.schema, self.scan(), sample_size)
+    }
+
+    /// Every row, in primary key order.
+    pub fn scan(&self) -> ClusteredScan<'_, 'store, S> {
+        self.scan_prefix(&[]).expect("an empty prefix is always valid")
+    }
+
+    /// The rows whose leading primary key columns equal `values`, in primary key order.
+    pub fn scan_prefix(&self, values: &[Value]) -> Result<ClusteredScan<'_, 'store, S>, TableError> {
+        if values.len() > self.primary_key.len() {
+            return Err(TableError::Tuple(TupleError::WrongColumnCount))
+        }
+        let prefix = key::encode(values);
+        let range = self.tree.range::<&[u8], _>((Bound::Included(&prefix[..]), Bound::Unbounded));
+        Ok(ClusteredScan { table: self, prefix, range })
+    }
+
+    /// The values of `row`'s primary key columns, most significant first.
+    pub fn key_values(&self, row: &[Value]) -> Vec<Value> {
+        self.primary_key.iter().map(|c| row[*c].clone()).collect()
+    }
+
+    /// The primary key values whose encoding `key` is, as a scan with rids gives it after a row.
+    pub(crate) fn decode_key(&self, key: &[u8]) -> Result<Vec<Value>, TableError> {
+        let types: Vec<_> = self.primary_key.iter().map(|c| self.schema.column_type(*c)).collect();
+        match key::decode(key, &types) {
+            Some((values, read)) if read == key.len() => Ok(values),
+            _ => Err(TableError::Tuple(TupleError::Corrupt)),
+        }
+    }
+
+    /// The encoding of `row`'s primary key, which the tree keeps it under.
+    pub(crate) fn key(&self, row: &[Value]) -> Vec<u8> {
+        key::encode(&self.key_values(row))
+    }
+
+    fn check_key_values(&self, key: &[Value]) -> Result<(), TableError> {
+        if key.len() != self.primary_key.len() {
+            return Err(TableError::Tuple(TupleError::WrongColumnCount))
+        }
+        Ok(())
+    }
+
+    /// Store `record` under `key`, inline if it fits in a cell and in an overflow chain otherwise.
+    fn put(&self, key: &[u8], record: &[u8]) -> Result<(), TableError> {
+        let mut inline = vec![INLINE];
+        inline.extend_from_slice(record);
+        match self.tree.insert(key, &inline) {
+            Err(BTreeError::EntryTooLarge) => {}
+            result => return result.map(|_| ()).map_err(TableError::from),
+        }
+        let head = overflow::write(self.store, &self.allocator, record)?;
+        let mut stub = vec![OVERFLOW; 9];
+        write_u64(&mut stub, 1, head.offset() as u64);
+        if let Err(e) = self.tree.insert(key, &stub) {
+            overflow::free(self.store, &self.allocator, head)?;
+            return Err(e.into())
+        }
+        Ok(())
+    }
+
+    fn load(&self, stored: &[u8]) -> Result<Vec<u8>, TableError> {
+        match stored.first() {
+            Some(&INLINE) => Ok(stored[1..].to_vec()),
+            Some(&OVERFLOW) if stored.len() == 9 => Ok(overflow::read(self.store, PageId::new(read_u64(stored, 1) as usize))?),
+            _ => Err(TableError::Tuple(TupleError::Corrupt)),
+        }
+    }
+
+    /// Release the overflow chain of a stored row that has been replaced or deleted, if it has one.
+    fn release(&self, stored: &[u8]) -> Result<(), TableError> {
+        if stored.first() == Some(&OVERFLOW) {
+            overflow::free(self.store, &self.allocator, PageId::new(read_u64(stored, 1) as usize))?;
+        }
+        Ok(())
+    }
+}
+
+fn check_key(schema: &Schema, primary_key: &[usize]) -> Result<(), TableError> {
+    if primary_key.is_empty() {
+        return Err(TableError::Tuple(TupleError::WrongColumnCount))
+    }
+    for &c in primary_key {
+        if c >= schema.len() {
+            return Err(TableError::NoSuchColumn(c))
+        }
+        if schema.column(c).nullable {
+            return Err(TableError::NullableKey(c))
+        }
+    }
+    Ok(())
+}
+
+pub struct ClusteredScan<'table, 'store, S: Storage> {
+    table: &'table ClusteredTable<'store, S>,
+    prefix: Vec<u8>,
+    range: BTreeRange<'table, 'store, S>,
+}
+impl<S: Storage> Iterator for ClusteredScan<'_, '_, S> {
+    type Item = Result<Vec<Value>, TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, stored) = match self.range.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e.into())),
+        };
+        if !key.starts_with(&self.prefix) {
+            return None
+        }
+        Some(self.table.load(&stored).and_then(|record| Ok(self.table.schema.decode(&record)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+    use crate::table::TableError;
+    use crate::tuple::{Column, ColumnType, Schema, Value};
+
+    use super::ClusteredTable;
+
+    fn schema() -> Schema {
+        Schema::new(vec![Column::new(ColumnType::Text), Column::new(ColumnType::Int), Column::nullable(ColumnType::Bytes)])
+    }
+
+    fn row(region: &str, id: i64, payload: usize) -> Vec<Value> {
+        vec![Value::Text(region.to_string()), Value::Int(id), Value::Bytes(vec![id as u8; payload])]
+    }
+
+    #[test]
+    fn test_rows_live_in_primary_key_order() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = ClusteredTable::create(&store, allocator, schema(), vec![0, 1])?;
+        for i in (0..400).rev() {
+            table.insert(&row(["east", "west"][i as usize % 2], i, if i % 50 == 0 { 9000 } else { 20 }))?;
+        }
+        assert_eq!(table.insert(&row("east", 0, 1)), Err(TableError::DuplicateKey));
+
+        let key = |region: &str, id: i64| [Value::Text(region.to_string()), Value::Int(id)];
+        assert_eq!(table.get(&key("east", 100))?, Some(row("east", 100, 9000)));
+        assert_eq!(table.get(&key("west", 100))?, None);
+        table.update(&row("east", 100, 3))?;
+        table.update(&row("west", 7, 9000))?;
+        assert_eq!(table.update(&row("west", 8, 0)), Err(TableError::NoSuchRow));
+        assert!(table.delete(&key("east", 2))? && !table.delete(&key("east", 2))?);
+
+        let mut reopened = ClusteredTable::open(&store, allocator, schema(), vec![0, 1], table.root())?;
+        let west: Vec<_> = reopened.scan_prefix(&[Value::Text("west".into())])?.collect::<Result<_, _>>()?;
+        assert_eq!(west.len(), 200);
+        assert!(west.iter().enumerate().all(|(i, r)| r[1] == Value::Int(2 * i as i64 + 1)));
+        assert_eq!(west[3], row("west", 7, 9000));
+        assert_eq!(reopened.get(&key("east", 100))?, Some(row("east", 100, 3)));
+        assert_eq!(reopened.scan().count(), 399);
+        assert!(reopened.delete(&key("west", 7))?);
+        // Pages are handed out in order, so with every one freed the free list has no gaps.
+        reopened.free()?;
+        let mut freed: Vec<usize> = allocator.free_pages(&store)?.iter().map(PageId::offset).collect();
+        freed.sort_unstable();
+        assert!(freed.len() > 20 && freed.iter().enumerate().all(|(i, &page)| page == i + 1), "{freed:?}");
+        assert_eq!(ClusteredTable::create(&store, allocator, schema(), vec![2]).err(), Some(TableError::NullableKey(2)));
+        Ok(())
+    }
+}
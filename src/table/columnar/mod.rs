@@ -0,0 +1,344 @@
+//! Append-only tables stored column by column within each page, for scans that read a few columns of
+//! many rows.
+//!
+//! Every data page holds a group of whole rows, laid out as one contiguous chunk per column (see
+//! `page`), so a scan decodes only the chunks of the columns it asks for rather than every byte of every
+//! row. Rows are appended to the last page, which is held decoded in memory and rewritten as it fills;
+//! `extend` writes it once per batch rather than once per row, which is how bulk loads should arrive.
+//! A chain of directory pages lists the data pages and their row counts. Rows cannot be updated or
+//! deleted: the layout is meant for analytical data loaded in bulk and read many times.
+//!
+//! `scan_where` tests a `Predicate` on one column before decoding the others, and skips them entirely on
+//! pages where no row matches. The predicate is tested once per distinct value of a dictionary-encoded
+//! column and once per run of a run-length encoded one, rather than once per row.
+mod page;
+
+use std::ops::{Bound, RangeBounds};
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+use crate::tuple::{Schema, TupleError, Value};
+
+use super::{stats, TableError, TableStats};
+
+use page::{ColumnPage, PageBuilder};
+
+const MAGIC: u32 = 0x5041_5844;
+const NEXT: usize = 8;
+const COUNT: usize = 16;
+const ENTRIES: usize = 24;
+/// Data page and row count.
+const ENTRY_LEN: usize = 16;
+const ENTRIES_PER_PAGE: usize = (PAGE_SIZE - ENTRIES) / ENTRY_LEN;
+const NO_PAGE: u64 = u64::MAX;
+
+/// A condition on the value of one column. Null matches no predicate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Eq(Value),
+    In(Vec<Value>),
+    /// Between the bounds, in the order index keys sort in.
+    Range(Bound<Value>, Bound<Value>),
+}
+impl Predicate {
+    pub fn matches(&self, value: &Value) -> bool {
+        if value.is_null() {
+            return false
+        }
+        match self {
+            Predicate::Eq(v) => value == v,
+            Predicate::In(values) => values.contains(value),
+            Predicate::Range(low, high) => (low.as_ref(), high.as_ref()).contains(value),
+        }
+    }
+}
+
+pub struct ColumnarTable<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    schema: Schema,
+    directory: Vec<PageId>,
+    /// Every data page and its row count, the last one being the page `tail` is written to.
+    pages: Vec<(PageId, u64)>,
+    tail: PageBuilder,
+    /// Whether `tail` has rows not yet written to a page.
+    dirty: bool,
+}
+impl<'store, S: Storage> ColumnarTable<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator, schema: Schema) -> Result<ColumnarTable<'store, S>, TableError> {
+        let root = new_directory_page(store, &allocator)?;
+        let tail = PageBuilder::new(&schema);
+        Ok(ColumnarTable { store, allocator, schema, directory: vec![root], pages: Vec::new(), tail, dirty: false })
+    }
+
+    /// Open the table whose directory starts at `root`.
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, schema: Schema, root: PageId) -> Result<ColumnarTable<'store, S>, TableError> {
+        let tail = PageBuilder::new(&schema);
+        let mut table = ColumnarTable { store, allocator, schema, directory: Vec::new(), pages: Vec::new(), tail, dirty: false };
+        let mut next = root.offset() as u64;
+        while next != NO_PAGE {
+            let id = PageId::new(next as usize);
+            let page = store.pin_page(&id)?;
+            let buf = page.try_read()?;
+            if read_u32(&*buf, 0) != MAGIC {
+                return Err(TableError::Page(PageError::WrongPageType))
+            }
+            for i in 0..read_u16(&*buf, COUNT) as usize {
+                let at = ENTRIES + i * ENTRY_LEN;
+                table.pages.push((PageId::new(read_u64(&*buf, at) as usize), read_u64(&*buf, at + 8)));
+            }
+            table.directory.push(id);
+            next = read_u64(&*buf, NEXT);
+        }
+        if let Some((last, _)) = table.pages.last() {
+            let page = store.pin_page(last)?;
+            let buf = page.try_read()?;
+            let data = ColumnPage::new(&*buf, &table.schema)?;
+            let columns = (0..table.schema.len()).map(|c| data.column(c)).collect::<Result<Vec<_>, _>>()?;
+            for row in 0..data.rows() {
+                let row: Vec<Value> = columns.iter().map(|column| column[row].clone()).collect();
+                table.tail.push(&row);
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// The page to pass to `open` to find this table again.
+    pub fn root(&self) -> PageId {
+        self.directory[0]
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Number of rows in the table.
+    pub fn len(&self) -> u64 {
+        self.pages.iter().map(|(_, rows)| rows).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn append(&mut self, row: &[Value]) -> Result<(), TableError> {
+        self.extend([row.to_vec()])
+    }
+
+    /// Append every row of `rows`, writing each page once it is full. If a row is rejected, the rows
+    /// before it are kept.
+    pub fn extend(&mut self, rows: impl IntoIterator<Item = Vec<Value>>) -> Result<(), TableError> {
+        let mut result = Ok(());
+        for row in rows {
+            result = self.push(&row);
+            if result.is_err() {
+                break
+            }
+        }
+        self.flush()?;
+        result
+    }
+
+    /// Every row with just the values of `columns`, in that order, reading only those columns.
+    pub fn scan(&self, columns: &[usize]) -> Result<ColumnarScan<'_, 'store, S>, TableError> {
+        self.new_scan(columns, None)
+    }
+
+    /// The rows whose value of `column` matches `predicate`, with just the values of `columns`.
+    pub fn scan_where(&self, columns: &[usize], column: usize, predicate: Predicate) -> Result<ColumnarScan<'_, 'store, S>, TableError> {
+        if column >= self.schema.len() {
+            return Err(TableError::NoSuchColumn(column))
+        }
+        self.new_scan(columns, Some((column, predicate)))
+    }
+
+    /// Gather statistics over every row, as `Table::analyze` does.
+    pub fn analyze(&self, sample_size: usize) -> Result<TableStats, TableError> {
+        let columns: Vec<usize> = (0..self.schema.len()).collect();
+        stats::analyze(&self.schema, self.scan(&columns)?, sample_size)
+    }
+
+    fn new_scan(&self, columns: &[usize], filter: Option<(usize, Predicate)>) -> Result<ColumnarScan<'_, 'store, S>, TableError> {
+        if let Some(c) = columns.iter().find(|c| **c >= self.schema.len()) {
+            return Err(TableError::NoSuchColumn(*c))
+        }
+        let columns = columns.to_vec();
+        Ok(ColumnarScan { table: self, columns, filter, pages: 0..self.pages.len(), batch: Vec::new(), remaining: 0 })
+    }
+
+    /// Add `row` to the tail, writing the tail out and starting a new one if it is full.
+    fn push(&mut self, row: &[Value]) -> Result<(), TableError> {
+        self.schema.check(row)?;
+        if self.pages.is_empty() || !self.tail.push(row) {
+            let mut tail = PageBuilder::new(&self.schema);
+            if !tail.push(row) {
+                return Err(TableError::Tuple(TupleError::RowTooLarge))
+            }
+            self.flush()?;
+            self.tail = tail;
+            self.add_page()?;
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Start a new, empty data page for the tail, listing it in the directory.
+    fn add_page(&mut self) -> Result<(), TableError> {
+        let page = self.allocator.allocate(self.store)?.id();
+        if self.pages.len() == self.directory.len() * ENTRIES_PER_PAGE {
+            let directory = new_directory_page(self.store, &self.allocator)?;
+            let last = self.store.pin_page(self.directory.last().unwrap())?;
+            write_u64(&mut *last.try_write()?, NEXT, directory.offset() as u64);
+            self.directory.push(directory);
+        }
+        self.pages.push((page, 0));
+        Ok(())
+    }
+
+    /// Write the tail to the last data page and its row count to the directory.
+    fn flush(&mut self) -> Result<(), TableError> {
+        if !self.dirty {
+            return Ok(())
+        }
+        let index = self.pages.len() - 1;
+        let (id, rows) = &mut self.pages[index];
+        *rows = self.tail.rows() as u64;
+        let page = self.store.pin_page(id)?;
+        self.tail.write(&mut *page.try_write()?);
+
+        let directory = self.store.pin_page(&self.directory[index / ENTRIES_PER_PAGE])?;
+        let mut buf = directory.try_write()?;
+        let slot = index % ENTRIES_PER_PAGE;
+        write_u64(&mut *buf, ENTRIES + slot * ENTRY_LEN, id.offset() as u64);
+        write_u64(&mut *buf, ENTRIES + slot * ENTRY_LEN + 8, *rows);
+        if slot >= read_u16(&*buf, COUNT) as usize {
+            write_u16(&mut *buf, COUNT, slot as u16 + 1);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn new_directory_page<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator) -> Result<PageId, PageError> {
+    let page = allocator.allocate(store)?;
+    let mut buf = page.try_write()?;
+    write_u32(&mut *buf, 0, MAGIC);
+    write_u64(&mut *buf, NEXT, NO_PAGE);
+    Ok(page.id())
+}
+
+/// Decodes the requested columns of one data page at a time, holding no latch between pages.
+pub struct ColumnarScan<'table, 'store, S: Storage> {
+    table: &'table ColumnarTable<'store, S>,
+    columns: Vec<usize>,
+    /// The column tested and the predicate rows must match to be returned.
+    filter: Option<(usize, Predicate)>,
+    pages: std::ops::Range<usize>,
+    /// The rest of each requested column on the current page.
+    batch: Vec<std::vec::IntoIter<Value>>,
+    /// Rows left on the current page.
+    remaining: usize,
+}
+impl<S: Storage> ColumnarScan<'_, '_, S> {
+    fn read_page(&mut self, index: usize) -> Result<(), TableError> {
+        let page = self.table.store.pin_page(&self.table.pages[index].0)?;
+        let buf = page.try_read()?;
+        let data = ColumnPage::new(&*buf, &self.table.schema)?;
+        let selected = match &self.filter {
+            Some((column, predicate)) => Some(data.select(*column, predicate)?),
+            None => None,
+        };
+        self.remaining = selected.as_ref().map_or(data.rows(), |s| s.iter().filter(|s| **s).count());
+        if self.remaining == 0 {
+            return Ok(())
+        }
+        let project = |values: Vec<Value>| match &selected {
+            Some(selected) => values.into_iter().zip(selected).filter(|(_, s)| **s).map(|(v, _)| v).collect(),
+            None => values,
+        };
+        self.batch = self.columns.iter().map(|c| data.column(*c).map(|v| project(v).into_iter())).collect::<Result<_, _>>()?;
+        Ok(())
+    }
+}
+impl<S: Storage> Iterator for ColumnarScan<'_, '_, S> {
+    type Item = Result<Vec<Value>, TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining == 0 {
+            let index = self.pages.next()?;
+            if let Err(e) = self.read_page(index) {
+                return Some(Err(e))
+            }
+        }
+        self.remaining -= 1;
+        Some(Ok(self.batch.iter_mut().map(|column| column.next().unwrap()).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+    use crate::table::TableError;
+    use crate::tuple::{Column, ColumnType, Schema, TupleError, Value};
+
+    use std::ops::Bound;
+
+    use super::{ColumnarTable, Predicate};
+
+    fn schema() -> Schema {
+        let mut columns = vec![Column::new(ColumnType::Int), Column::nullable(ColumnType::Text)];
+        columns.extend((0..10).map(|_| Column::new(ColumnType::Float)));
+        Schema::new(columns)
+    }
+
+    fn row(i: i64) -> Vec<Value> {
+        let mut row = vec![Value::Int(i), if i % 10 == 0 { Value::Null } else { Value::Text(format!("sku-{}", i % 37)) }];
+        row.extend((0..10).map(|c| Value::Float((i * c) as f64)));
+        row
+    }
+
+    #[test]
+    fn test_projected_scans() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = ColumnarTable::create(&store, allocator, schema())?;
+        table.extend((0..2000).map(row))?;
+        for i in 2000..2050 {
+            table.append(&row(i))?;
+        }
+
+        let mut table = ColumnarTable::open(&store, allocator, schema(), table.root())?;
+        assert!(table.page_count() > 30);
+        table.extend((2050..2100).map(row))?;
+        let too_long = vec![Value::Text("x".repeat(5000))];
+        let mut bad = row(2100);
+        bad.splice(1..2, too_long);
+        assert_eq!(table.extend([row(2100), bad]), Err(TableError::Tuple(TupleError::RowTooLarge)));
+        assert_eq!(table.append(&[Value::Int(1)]), Err(TableError::Tuple(TupleError::WrongColumnCount)));
+        assert_eq!(table.len(), 2101);
+
+        let projected = table.scan(&[7, 1])?.collect::<Result<Vec<_>, _>>()?;
+        let expected: Vec<_> = (0..2101).map(|i| vec![row(i)[7].clone(), row(i)[1].clone()]).collect();
+        assert_eq!(projected, expected);
+        assert_eq!(table.scan(&[])?.count(), 2101);
+        assert_eq!(table.scan(&[12]).err(), Some(TableError::NoSuchColumn(12)));
+        let sku = table.scan_where(&[0], 1, Predicate::Eq(Value::Text("sku-5".into())))?.collect::<Result<Vec<_>, _>>()?;
+        let expected: Vec<_> = (0..2101).filter(|i| i % 37 == 5 && i % 10 != 0).map(|i| vec![Value::Int(i)]).collect();
+        assert_eq!(sku, expected);
+        let range = Predicate::Range(Bound::Included(Value::Int(100)), Bound::Excluded(Value::Int(110)));
+        assert_eq!(table.scan_where(&[1], 0, range)?.count(), 10);
+        let none = Predicate::In(vec![Value::Text("sku-99".into()), Value::Null]);
+        assert_eq!(table.scan_where(&[0, 1], 1, none)?.count(), 0);
+        let stats = table.analyze(100)?;
+        assert_eq!(stats.row_count, 2101);
+        Ok(())
+    }
+}
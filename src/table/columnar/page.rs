@@ -0,0 +1,674 @@
+//! Column-group pages: the rows on a page stored column by column.
+//!
+//! ```text
+//! | magic u32 | row count u16 | column count u16 | end offset u16 per column | column chunks ... |
+//! ```
+//!
+//! Each column's chunk starts with an encoding tag, then a null bitmap with one bit per row, then the
+//! values. Plain chunks store integers, floats, times and timestamps eight bytes wide, dates in four,
+//! intervals in twelve and bools in one, so the value of row `i` sits at a known position; text, bytes,
+//! decimals and JSON documents, the last two as the row format keeps them, have an end offset u16 per row
+//! followed by the data. Nulls keep their place with a zero or empty value. The offset table gives the
+//! end of every chunk, so one column can be read without touching the others.
+//!
+//! Text, bytes, decimal and JSON columns with few distinct values are dictionary encoded instead:
+//!
+//! ```text
+//! | tag | null bitmap | entry count u16 | end offset u16 per entry | entry data | code per row |
+//! ```
+//!
+//! Codes are one byte wide when there are at most 256 entries and two otherwise. A predicate on a
+//! dictionary column is tested once per entry, and rows are then selected by comparing their codes,
+//! without decoding a single string.
+//!
+//! Columns of any type whose values come in runs, as sorted and clustered columns do, are run-length
+//! encoded:
+//!
+//! ```text
+//! | tag | null bitmap | run count u16 | end row u16 per run | one value per run, laid out as in plain |
+//! ```
+//!
+//! A predicate on a run-length encoded column is tested once per run. The builder keeps each column's
+//! dictionary and runs as rows arrive and writes whichever encoding is smallest, so pages of repetitive
+//! columns hold more rows. Run-length encoding is only considered while a sample of the rows, every
+//! `SAMPLE_EVERY`th one, mostly repeats the row before it: with short runs it saves little and makes
+//! every read expand runs.
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::bytes::{read_u16, read_u32, write_u16, write_u32};
+use crate::datetime::Interval;
+use crate::decimal::Decimal;
+use crate::json::Json;
+use crate::page_store::{PageError, PAGE_SIZE};
+use crate::tuple::{ColumnType, Schema, TupleError, Value};
+
+use super::super::TableError;
+use super::Predicate;
+
+const MAGIC: u32 = 0x5041_5847;
+const ROWS: usize = 4;
+const COLUMNS: usize = 6;
+const HEADER_LEN: usize = 8;
+/// Values stored one after another as they are.
+const PLAIN: u8 = 0;
+/// Distinct values stored once, with a code per row.
+const DICTIONARY: u8 = 1;
+/// One value per run of equal values.
+const RUN_LENGTH: u8 = 2;
+/// Which rows are compared with the row before them to judge whether a column comes in runs.
+const SAMPLE_EVERY: usize = 8;
+/// Sampled rows needed before run-length encoding is considered.
+const MIN_SAMPLES: usize = 4;
+
+/// The width of every value of a fixed-width type, `None` for text and bytes.
+fn width(column_type: ColumnType) -> Option<usize> {
+    match column_type {
+        ColumnType::Int | ColumnType::Float | ColumnType::Time | ColumnType::Timestamp | ColumnType::TimestampTz => {
+            Some(8)
+        }
+        ColumnType::Date => Some(4),
+        ColumnType::Interval => Some(12),
+        ColumnType::Bool => Some(1),
+        ColumnType::Bytes | ColumnType::Text | ColumnType::Decimal { .. } | ColumnType::Json => None,
+    }
+}
+
+/// The variable-length data of `value`, empty for other types and for null. A decimal's is as
+/// `Decimal::write` writes it.
+fn data_of(value: &Value) -> Cow<'_, [u8]> {
+    match value {
+        Value::Bytes(v) => Cow::Borrowed(v),
+        Value::Text(v) => Cow::Borrowed(v.as_bytes()),
+        Value::Json(v) => Cow::Borrowed(v.as_bytes()),
+        Value::Decimal(v) => {
+            let mut data = Vec::new();
+            v.write(&mut data);
+            Cow::Owned(data)
+        }
+        _ => Cow::Borrowed(&[]),
+    }
+}
+
+fn code_width(entries: usize) -> usize {
+    if entries <= 256 { 1 } else { 2 }
+}
+
+/// Bytes taken by `count` values laid out as in a plain chunk, holding `data` bytes of text or bytes.
+fn values_len(width: Option<usize>, count: usize, data: usize) -> usize {
+    match width {
+        Some(width) => width * count,
+        None => 2 * count + data,
+    }
+}
+
+/// What decides the size of a column's chunk under each encoding.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    rows: usize,
+    /// Text and bytes data over all rows.
+    data: usize,
+    /// Distinct non-null values and their data, for text and bytes columns.
+    entries: usize,
+    entry_data: usize,
+    /// Runs of equal values and the data of one value from each.
+    runs: usize,
+    run_data: usize,
+    /// Sampled rows, and how many of them equal the row before.
+    sampled: usize,
+    repeats: usize,
+}
+
+/// One column's values for a page, with the counts behind its choice of encoding.
+struct ColumnBuilder {
+    column_type: ColumnType,
+    values: Vec<Value>,
+    /// Code of every distinct value of a text or bytes column, in order of first appearance.
+    dictionary: HashMap<Vec<u8>, u16>,
+    counts: Counts,
+}
+impl ColumnBuilder {
+    fn new(column_type: ColumnType) -> ColumnBuilder {
+        ColumnBuilder { column_type, values: Vec::new(), dictionary: HashMap::new(), counts: Counts::default() }
+    }
+
+    /// Whether `value` would be a new entry in the dictionary.
+    fn new_entry(&self, value: &Value) -> bool {
+        width(self.column_type).is_none() && !value.is_null() && !self.dictionary.contains_key(&*data_of(value))
+    }
+
+    /// The counts once `value` is added.
+    fn counts_with(&self, value: &Value) -> Counts {
+        let mut counts = self.counts;
+        let data = data_of(value);
+        counts.rows += 1;
+        counts.data += data.len();
+        if self.new_entry(value) {
+            counts.entries += 1;
+            counts.entry_data += data.len();
+        }
+        let repeat = self.values.last() == Some(value);
+        if !repeat {
+            counts.runs += 1;
+            counts.run_data += data.len();
+        }
+        if counts.rows.is_multiple_of(SAMPLE_EVERY) {
+            counts.sampled += 1;
+            counts.repeats += repeat as usize;
+        }
+        counts
+    }
+
+    fn push(&mut self, value: &Value) {
+        self.counts = self.counts_with(value);
+        if self.new_entry(value) {
+            let code = self.dictionary.len() as u16;
+            self.dictionary.insert(data_of(value).to_vec(), code);
+        }
+        self.values.push(value.clone());
+    }
+
+    /// The smallest encoding allowed with `counts`, and the length of the chunk it makes.
+    fn encoding(&self, counts: &Counts) -> (u8, usize) {
+        let header = 1 + counts.rows.div_ceil(8);
+        let width = width(self.column_type);
+        let mut best = (PLAIN, header + values_len(width, counts.rows, counts.data));
+        if width.is_none() {
+            let len = header + 2 + values_len(None, counts.entries, counts.entry_data) + code_width(counts.entries) * counts.rows;
+            if len < best.1 {
+                best = (DICTIONARY, len);
+            }
+        }
+        if counts.sampled >= MIN_SAMPLES && 4 * counts.repeats >= 3 * counts.sampled {
+            let len = header + 2 + 2 * counts.runs + values_len(width, counts.runs, counts.run_data);
+            if len < best.1 {
+                best = (RUN_LENGTH, len);
+            }
+        }
+        best
+    }
+
+    /// Write the chunk, which is exactly as long as `encoding` says.
+    fn write(&self, chunk: &mut [u8]) {
+        let (encoding, _) = self.encoding(&self.counts);
+        chunk[0] = encoding;
+        for (row, value) in self.values.iter().enumerate() {
+            if value.is_null() {
+                chunk[1 + row / 8] |= 1 << (row % 8);
+            }
+        }
+        let at = 1 + self.values.len().div_ceil(8);
+        match encoding {
+            DICTIONARY => self.write_dictionary(chunk, at),
+            RUN_LENGTH => self.write_runs(chunk, at),
+            _ => write_values(chunk, at, self.column_type, &self.values),
+        }
+    }
+
+    fn write_dictionary(&self, chunk: &mut [u8], at: usize) {
+        let mut entries = vec![&[][..]; self.dictionary.len()];
+        for (data, code) in &self.dictionary {
+            entries[*code as usize] = data;
+        }
+        write_u16(chunk, at, entries.len() as u16);
+        let mut data_at = at + 2 + 2 * entries.len();
+        for (i, data) in entries.iter().enumerate() {
+            chunk[data_at..data_at + data.len()].copy_from_slice(data);
+            data_at += data.len();
+            write_u16(chunk, at + 2 + 2 * i, data_at as u16);
+        }
+        let code_width = code_width(entries.len());
+        for (row, value) in self.values.iter().enumerate() {
+            let code = if value.is_null() { 0 } else { self.dictionary[&*data_of(value)] };
+            let code_at = data_at + code_width * row;
+            match code_width {
+                1 => chunk[code_at] = code as u8,
+                _ => write_u16(chunk, code_at, code),
+            }
+        }
+    }
+
+    fn write_runs(&self, chunk: &mut [u8], at: usize) {
+        let mut runs = Vec::new();
+        for (row, value) in self.values.iter().enumerate() {
+            if row == 0 || self.values[row - 1] != *value {
+                runs.push(value.clone());
+            }
+            if self.values.get(row + 1) != Some(value) {
+                write_u16(chunk, at + 2 * runs.len(), row as u16 + 1);
+            }
+        }
+        write_u16(chunk, at, runs.len() as u16);
+        write_values(chunk, at + 2 + 2 * runs.len(), self.column_type, &runs);
+    }
+}
+
+/// Lay `values` out from `at` as a plain chunk does, with offsets into `chunk`.
+fn write_values(chunk: &mut [u8], at: usize, column_type: ColumnType, values: &[Value]) {
+    let mut value_at = at;
+    let mut data_at = at + 2 * values.len();
+    for value in values {
+        match value {
+            Value::Int(v) => chunk[value_at..value_at + 8].copy_from_slice(&v.to_le_bytes()),
+            Value::Float(v) => chunk[value_at..value_at + 8].copy_from_slice(&v.to_le_bytes()),
+            Value::Bool(v) => chunk[value_at] = *v as u8,
+            Value::Date(v) => chunk[value_at..value_at + 4].copy_from_slice(&v.to_le_bytes()),
+            Value::Time(v) | Value::Timestamp(v) | Value::TimestampTz(v) => {
+                chunk[value_at..value_at + 8].copy_from_slice(&v.to_le_bytes())
+            }
+            Value::Interval(v) => {
+                chunk[value_at..value_at + 4].copy_from_slice(&v.months.to_le_bytes());
+                chunk[value_at + 4..value_at + 12].copy_from_slice(&v.micros.to_le_bytes());
+            }
+            Value::Null | Value::Bytes(_) | Value::Text(_) | Value::Decimal(_) | Value::Json(_) => {}
+        }
+        match width(column_type) {
+            Some(width) => value_at += width,
+            None => {
+                let data = data_of(value);
+                chunk[data_at..data_at + data.len()].copy_from_slice(&data);
+                data_at += data.len();
+                write_u16(chunk, value_at, data_at as u16);
+                value_at += 2;
+            }
+        }
+    }
+}
+
+/// Rows gathered for one page, held column by column until the page is written.
+pub(crate) struct PageBuilder {
+    columns: Vec<ColumnBuilder>,
+    rows: usize,
+}
+impl PageBuilder {
+    pub(crate) fn new(schema: &Schema) -> PageBuilder {
+        PageBuilder { columns: (0..schema.len()).map(|c| ColumnBuilder::new(schema.column_type(c))).collect(), rows: 0 }
+    }
+
+    pub(crate) fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Add `row`, which must already be checked against the schema, if the page has room for it.
+    pub(crate) fn push(&mut self, row: &[Value]) -> bool {
+        let chunks: usize = self.columns.iter().zip(row).map(|(c, value)| c.encoding(&c.counts_with(value)).1).sum();
+        if HEADER_LEN + 2 * self.columns.len() + chunks > PAGE_SIZE || self.rows + 1 > u16::MAX as usize {
+            return false
+        }
+        for (column, value) in self.columns.iter_mut().zip(row) {
+            column.push(value);
+        }
+        self.rows += 1;
+        true
+    }
+
+    /// Lay the rows out on `buf`, a whole page.
+    pub(crate) fn write(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        write_u32(buf, 0, MAGIC);
+        write_u16(buf, ROWS, self.rows as u16);
+        write_u16(buf, COLUMNS, self.columns.len() as u16);
+        let mut at = HEADER_LEN + 2 * self.columns.len();
+        for (c, column) in self.columns.iter().enumerate() {
+            let (_, len) = column.encoding(&column.counts);
+            column.write(&mut buf[at..at + len]);
+            at += len;
+            write_u16(buf, HEADER_LEN + 2 * c, at as u16);
+        }
+    }
+}
+
+/// The dictionary of a dictionary-encoded chunk.
+struct Dictionary<'a> {
+    entries: Vec<&'a [u8]>,
+    codes: &'a [u8],
+    code_width: usize,
+}
+impl Dictionary<'_> {
+    fn code(&self, row: usize) -> usize {
+        match self.code_width {
+            1 => self.codes[row] as usize,
+            _ => read_u16(self.codes, 2 * row) as usize,
+        }
+    }
+}
+
+/// A column-group page read through the schema it was written with.
+pub(crate) struct ColumnPage<'a> {
+    buf: &'a [u8],
+    schema: &'a Schema,
+}
+impl<'a> ColumnPage<'a> {
+    /// Check that `buf` is a column-group page with `schema`'s columns.
+    pub(crate) fn new(buf: &'a [u8], schema: &'a Schema) -> Result<ColumnPage<'a>, TableError> {
+        if read_u32(buf, 0) != MAGIC {
+            return Err(TableError::Page(PageError::WrongPageType))
+        }
+        if read_u16(buf, COLUMNS) as usize != schema.len() {
+            return Err(TableError::Tuple(TupleError::Corrupt))
+        }
+        Ok(ColumnPage { buf, schema })
+    }
+
+    pub(crate) fn rows(&self) -> usize {
+        read_u16(self.buf, ROWS) as usize
+    }
+
+    /// Every value of `column`, decoding no other column.
+    pub(crate) fn column(&self, column: usize) -> Result<Vec<Value>, TupleError> {
+        let chunk = self.chunk(column)?;
+        let rows = self.rows();
+        let column_type = self.schema.column_type(column);
+        let values = match chunk[0] {
+            PLAIN => read_values(chunk, 1 + rows.div_ceil(8), column_type, rows)?,
+            DICTIONARY => {
+                let dictionary = self.dictionary(chunk)?;
+                let entries: Vec<Value> = dictionary.entries.iter().map(|e| to_value(column_type, e)).collect::<Result<_, _>>()?;
+                let value = |row| match is_null(chunk, row) {
+                    true => Some(Value::Null),
+                    false => entries.get(dictionary.code(row)).cloned(),
+                };
+                (0..rows).map(|row| value(row).ok_or(TupleError::Corrupt)).collect::<Result<Vec<_>, _>>()?
+            }
+            RUN_LENGTH => {
+                let (ends, runs) = self.runs(chunk, column_type)?;
+                let mut values = Vec::with_capacity(rows);
+                for (end, value) in ends.into_iter().zip(runs) {
+                    values.resize(end, value);
+                }
+                values
+            }
+            _ => return Err(TupleError::Corrupt),
+        };
+        let nullable = self.schema.column(column).nullable;
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(row, value)| match is_null(chunk, row) {
+                false => Ok(value),
+                true if nullable => Ok(Value::Null),
+                true => Err(TupleError::Corrupt),
+            })
+            .collect()
+    }
+
+    /// Which rows have a value of `column` matching `predicate`. Null matches nothing.
+    pub(crate) fn select(&self, column: usize, predicate: &Predicate) -> Result<Vec<bool>, TupleError> {
+        let chunk = self.chunk(column)?;
+        let column_type = self.schema.column_type(column);
+        match chunk[0] {
+            DICTIONARY => {
+                let dictionary = self.dictionary(chunk)?;
+                let matching: Vec<bool> = dictionary
+                    .entries
+                    .iter()
+                    .map(|entry| to_value(column_type, entry).map(|value| predicate.matches(&value)))
+                    .collect::<Result<_, _>>()?;
+                let select = |row| Ok(!is_null(chunk, row) && *matching.get(dictionary.code(row)).ok_or(TupleError::Corrupt)?);
+                (0..self.rows()).map(select).collect()
+            }
+            RUN_LENGTH => {
+                let (ends, runs) = self.runs(chunk, column_type)?;
+                let mut selected = Vec::with_capacity(self.rows());
+                for (end, value) in ends.into_iter().zip(runs) {
+                    selected.resize(end, predicate.matches(&value));
+                }
+                Ok(selected.into_iter().enumerate().map(|(row, s)| s && !is_null(chunk, row)).collect())
+            }
+            _ => Ok(self.column(column)?.iter().map(|value| predicate.matches(value)).collect()),
+        }
+    }
+
+    /// The chunk of `column`, checked to be long enough for its null bitmap.
+    fn chunk(&self, column: usize) -> Result<&'a [u8], TupleError> {
+        let first = HEADER_LEN + 2 * self.schema.len();
+        let start = if column == 0 { first } else { read_u16(self.buf, HEADER_LEN + 2 * (column - 1)) as usize };
+        let end = read_u16(self.buf, HEADER_LEN + 2 * column) as usize;
+        if start < first || end < start + 1 + self.rows().div_ceil(8) || end > PAGE_SIZE {
+            return Err(TupleError::Corrupt)
+        }
+        Ok(&self.buf[start..end])
+    }
+
+    fn dictionary(&self, chunk: &'a [u8]) -> Result<Dictionary<'a>, TupleError> {
+        let rows = self.rows();
+        let count_at = 1 + rows.div_ceil(8);
+        let count = chunk.get(count_at..count_at + 2).map(|c| read_u16(c, 0) as usize).ok_or(TupleError::Corrupt)?;
+        let mut data_at = count_at + 2 + 2 * count;
+        if chunk.len() < data_at {
+            return Err(TupleError::Corrupt)
+        }
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let end = read_u16(chunk, count_at + 2 + 2 * i) as usize;
+            entries.push(chunk.get(data_at..end).ok_or(TupleError::Corrupt)?);
+            data_at = end;
+        }
+        let code_width = code_width(count);
+        let codes = chunk.get(data_at..data_at + code_width * rows).ok_or(TupleError::Corrupt)?;
+        Ok(Dictionary { entries, codes, code_width })
+    }
+
+    /// The end row and value of every run of a run-length encoded chunk, null runs decoded as zero or
+    /// empty values.
+    fn runs(&self, chunk: &[u8], column_type: ColumnType) -> Result<(Vec<usize>, Vec<Value>), TupleError> {
+        let count_at = 1 + self.rows().div_ceil(8);
+        let count = chunk.get(count_at..count_at + 2).map(|c| read_u16(c, 0) as usize).ok_or(TupleError::Corrupt)?;
+        let values_at = count_at + 2 + 2 * count;
+        if chunk.len() < values_at {
+            return Err(TupleError::Corrupt)
+        }
+        let ends: Vec<usize> = (0..count).map(|i| read_u16(chunk, count_at + 2 + 2 * i) as usize).collect();
+        if ends.windows(2).any(|w| w[0] >= w[1]) || ends.last().copied().unwrap_or(0) != self.rows() {
+            return Err(TupleError::Corrupt)
+        }
+        Ok((ends, read_values(chunk, values_at, column_type, count)?))
+    }
+}
+
+/// `count` values laid out from `at` as in a plain chunk, nulls decoded as zero or empty values.
+fn read_values(chunk: &[u8], at: usize, column_type: ColumnType, count: usize) -> Result<Vec<Value>, TupleError> {
+    let fixed = width(column_type).unwrap_or(2);
+    if chunk.len() < at + fixed * count {
+        return Err(TupleError::Corrupt)
+    }
+    let mut data_at = at + 2 * count;
+    let mut values = Vec::with_capacity(count);
+    for i in 0..count {
+        let value_at = at + fixed * i;
+        let int = |at: usize| i64::from_le_bytes(chunk[at..at + 8].try_into().unwrap());
+        let short = |at: usize| i32::from_le_bytes(chunk[at..at + 4].try_into().unwrap());
+        values.push(match column_type {
+            ColumnType::Int => Value::Int(int(value_at)),
+            ColumnType::Float => Value::Float(f64::from_le_bytes(chunk[value_at..value_at + 8].try_into().unwrap())),
+            ColumnType::Bool => Value::Bool(chunk[value_at] == 1),
+            ColumnType::Date => Value::Date(short(value_at)),
+            ColumnType::Time => Value::Time(int(value_at)),
+            ColumnType::Timestamp => Value::Timestamp(int(value_at)),
+            ColumnType::TimestampTz => Value::TimestampTz(int(value_at)),
+            ColumnType::Interval => Value::Interval(Interval { months: short(value_at), micros: int(value_at + 4) }),
+            ColumnType::Bytes | ColumnType::Text | ColumnType::Decimal { .. } | ColumnType::Json => {
+                let end = read_u16(chunk, value_at) as usize;
+                let data = chunk.get(data_at..end).ok_or(TupleError::Corrupt)?;
+                data_at = end;
+                to_value(column_type, data)?
+            }
+        });
+    }
+    Ok(values)
+}
+
+fn is_null(chunk: &[u8], row: usize) -> bool {
+    chunk[1 + row / 8] >> (row % 8) & 1 == 1
+}
+
+fn to_value(column_type: ColumnType, data: &[u8]) -> Result<Value, TupleError> {
+    match column_type {
+        ColumnType::Text => Ok(Value::Text(String::from_utf8(data.to_vec()).map_err(|_| TupleError::Corrupt)?)),
+        ColumnType::Decimal { .. } => match Decimal::read(data) {
+            Some((v, len)) if len == data.len() => Ok(Value::Decimal(v)),
+            _ => Err(TupleError::Corrupt),
+        },
+        ColumnType::Json => Ok(Value::Json(Json::from_bytes(data).ok_or(TupleError::Corrupt)?)),
+        _ => Ok(Value::Bytes(data.to_vec())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::page_store::PAGE_SIZE;
+    use crate::tuple::{Column, ColumnType, Schema, TupleError, Value};
+
+    use super::{ColumnPage, PageBuilder, Predicate, DICTIONARY, PLAIN, RUN_LENGTH};
+
+    /// A page holding as many rows made by `row` as fit, and those rows.
+    fn fill(schema: &Schema, row: impl Fn(usize) -> Vec<Value>) -> (Vec<u8>, Vec<Vec<Value>>) {
+        let mut builder = PageBuilder::new(schema);
+        let mut rows = Vec::new();
+        while builder.push(&row(rows.len())) {
+            rows.push(row(rows.len()));
+        }
+        let mut buf = vec![0u8; PAGE_SIZE];
+        builder.write(&mut buf);
+        (buf, rows)
+    }
+
+    /// Check that every column of `page` reads back as `rows` has it, and that `predicate` selects the rows
+    /// it matches in `column`.
+    fn check(page: &ColumnPage, rows: &[Vec<Value>], column: usize, predicate: &Predicate) -> Result<(), TupleError> {
+        for c in 0..rows[0].len() {
+            assert_eq!(page.column(c)?, rows.iter().map(|r| r[c].clone()).collect::<Vec<_>>());
+        }
+        let expected: Vec<bool> = rows.iter().map(|r| predicate.matches(&r[column])).collect();
+        assert_eq!(page.select(column, predicate)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_columns_round_trip() -> Result<(), TupleError> {
+        let schema = Schema::new(vec![
+            Column::new(ColumnType::Int),
+            Column::nullable(ColumnType::Text),
+            Column::nullable(ColumnType::Float),
+            Column::new(ColumnType::Bool),
+            Column::new(ColumnType::Bytes),
+            Column::nullable(ColumnType::Text),
+            Column::new(ColumnType::Int),
+        ]);
+        let mut builder = PageBuilder::new(&schema);
+        let mut rows = Vec::new();
+        loop {
+            let i = rows.len() as i64;
+            let text = if i % 3 == 0 { Value::Null } else { Value::Text("ab".repeat(i as usize % 5)) };
+            let float = if i % 4 == 0 { Value::Null } else { Value::Float(i as f64) };
+            let bytes = Value::Bytes(i.to_le_bytes()[..2].to_vec());
+            let region = if i < 40 { Value::Null } else { Value::Text(format!("region-{}", i / 30)) };
+            let row = vec![Value::Int(i - 50), text, float, Value::Bool(i % 2 == 0), bytes, region, Value::Int(i / 10)];
+            if !builder.push(&row) {
+                break
+            }
+            rows.push(row);
+        }
+        assert!(rows.len() > 100);
+
+        let mut buf = vec![0u8; PAGE_SIZE];
+        builder.write(&mut buf);
+        let page = ColumnPage::new(&buf, &schema).unwrap();
+        assert_eq!(page.rows(), rows.len());
+        for column in 0..schema.len() {
+            let expected: Vec<_> = rows.iter().map(|r| r[column].clone()).collect();
+            assert_eq!(page.column(column)?, expected);
+        }
+        let encodings: Vec<u8> = (0..schema.len()).map(|c| page.chunk(c).map(|chunk| chunk[0])).collect::<Result<_, _>>()?;
+        assert_eq!(encodings, [PLAIN, DICTIONARY, PLAIN, PLAIN, PLAIN, RUN_LENGTH, RUN_LENGTH]);
+
+        let abab = Predicate::Eq(Value::Text("abab".into()));
+        let expected: Vec<bool> = rows.iter().map(|r| r[1] == Value::Text("abab".into())).collect();
+        assert_eq!(page.select(1, &abab)?, expected);
+        assert_eq!(page.select(1, &Predicate::Range(Bound::Unbounded, Bound::Unbounded))?, rows.iter().map(|r| !r[1].is_null()).collect::<Vec<_>>());
+        let region = Predicate::Range(Bound::Included(Value::Text("region-2".into())), Bound::Unbounded);
+        let expected: Vec<bool> = rows.iter().map(|r| region.matches(&r[5])).collect();
+        assert_eq!(page.select(5, &region)?, expected);
+        assert_eq!(expected.iter().filter(|s| **s).count(), rows.len() - 60);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dictionary_codes() -> Result<(), TupleError> {
+        let schema = Schema::new(vec![Column::nullable(ColumnType::Text)]);
+        let entry = |i: usize, entries: usize| match i % 7 {
+            3 => Value::Null,
+            _ => Value::Text(format!("c{}", i % entries)),
+        };
+        // A byte per code holds 256 entries, and the 257th widens every code to two.
+        for (entries, code_width) in [(256, 1), (257, 2)] {
+            let (buf, rows) = fill(&schema, |i| vec![entry(i, entries)]);
+            let page = ColumnPage::new(&buf, &schema).unwrap();
+            let chunk = page.chunk(0)?;
+            assert_eq!(chunk[0], DICTIONARY);
+            let dictionary = page.dictionary(chunk)?;
+            assert_eq!((dictionary.entries.len(), dictionary.code_width), (entries, code_width));
+            assert_eq!(dictionary.entries[..3], [b"c0", b"c1", b"c2"]);
+
+            check(&page, &rows, 0, &Predicate::Eq(Value::Text("c255".into())))?;
+            let some = Predicate::In(vec![Value::Text("c3".into()), Value::Text("c4".into()), Value::Null]);
+            check(&page, &rows, 0, &some)?;
+            let range = Predicate::Range(Bound::Excluded(Value::Text("c250".into())), Bound::Unbounded);
+            check(&page, &rows, 0, &range)?;
+            let everything = Predicate::Range(Bound::Unbounded, Bound::Unbounded);
+            assert_eq!(page.select(0, &everything)?, rows.iter().map(|r| !r[0].is_null()).collect::<Vec<_>>());
+        }
+
+        // Every value distinct: the dictionary would take more room than plain values.
+        let (buf, rows) = fill(&schema, |i| vec![Value::Text(format!("unique-{i}"))]);
+        let page = ColumnPage::new(&buf, &schema).unwrap();
+        assert_eq!(page.chunk(0)?[0], PLAIN);
+        check(&page, &rows, 0, &Predicate::Eq(Value::Text("unique-9".into())))
+    }
+
+    #[test]
+    fn test_runs() -> Result<(), TupleError> {
+        let schema = Schema::new(vec![
+            Column::nullable(ColumnType::Int),
+            Column::new(ColumnType::Date),
+            Column::nullable(ColumnType::Text),
+            Column::new(ColumnType::Int),
+            Column::nullable(ColumnType::Bool),
+        ]);
+        let row = |i: usize| {
+            let run = if (i / 50) % 3 == 2 { Value::Null } else { Value::Int(i as i64 / 50) };
+            let text = Value::Text(format!("batch-{}", i / 20));
+            vec![run, Value::Date(19000), text, Value::Int(i as i64 / 3), Value::Null]
+        };
+        let (buf, rows) = fill(&schema, row);
+        let page = ColumnPage::new(&buf, &schema).unwrap();
+        assert!(rows.len() > 300);
+        let encodings = (0..schema.len()).map(|c| page.chunk(c).map(|chunk| chunk[0])).collect::<Result<Vec<_>, _>>()?;
+        // Runs of three are too short to be worth expanding on every read.
+        assert_eq!(encodings, [RUN_LENGTH, RUN_LENGTH, RUN_LENGTH, PLAIN, RUN_LENGTH]);
+        let (ends, runs) = page.runs(page.chunk(0)?, ColumnType::Int)?;
+        assert_eq!(ends.len(), rows.len().div_ceil(50));
+        assert_eq!((ends[0], ends[2], &runs[..3]), (50, 150, &[Value::Int(0), Value::Int(1), Value::Int(0)][..]));
+        assert_eq!(page.runs(page.chunk(1)?, ColumnType::Date)?, (vec![rows.len()], vec![Value::Date(19000)]));
+        assert_eq!(page.runs(page.chunk(4)?, ColumnType::Bool)?, (vec![rows.len()], vec![Value::Bool(false)]));
+
+        check(&page, &rows, 0, &Predicate::Range(Bound::Included(Value::Int(3)), Bound::Excluded(Value::Int(7))))?;
+        check(&page, &rows, 0, &Predicate::Range(Bound::Unbounded, Bound::Unbounded))?;
+        check(&page, &rows, 1, &Predicate::Eq(Value::Date(19000)))?;
+        check(&page, &rows, 2, &Predicate::In(vec![Value::Text("batch-3".into()), Value::Text("batch-9".into())]))?;
+        check(&page, &rows, 3, &Predicate::Eq(Value::Int(7)))?;
+        // Null runs decode as false, which nothing may select.
+        check(&page, &rows, 4, &Predicate::Eq(Value::Bool(false)))?;
+        assert!(page.select(4, &Predicate::Range(Bound::Unbounded, Bound::Unbounded))?.iter().all(|s| !s));
+
+        // One run over the whole page: only the null bitmap grows with the rows.
+        let schema = Schema::new(vec![Column::new(ColumnType::Bool)]);
+        let (buf, rows) = fill(&schema, |_| vec![Value::Bool(true)]);
+        let page = ColumnPage::new(&buf, &schema).unwrap();
+        assert!(rows.len() > 30_000);
+        assert_eq!(page.runs(page.chunk(0)?, ColumnType::Bool)?, (vec![rows.len()], vec![Value::Bool(true)]));
+        check(&page, &rows, 0, &Predicate::Eq(Value::Bool(true)))
+    }
+}
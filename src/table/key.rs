@@ -0,0 +1,253 @@
+//! Order-preserving key encoding: encoded values compare bytewise in the same order as the values.
+//!
+//! Each column starts with a marker byte, 0 for null and 1 otherwise, so nulls sort first. Integers are
+//! big-endian with the sign bit flipped, and floats are big-endian with the sign bit flipped for positive
+//! numbers and every bit flipped for negative ones. Dates, times and timestamps are big-endian with the
+//! sign bit flipped like integers, dates in four bytes, and intervals are their length, with a month as
+//! 30 days, in sixteen bytes and then their months in four. Text, bytes and JSON documents, the last by
+//! their encoding, escape each zero byte as `00 ff` and end with `00 00`, which keeps every column
+//! self-delimiting: the encoding of a leading subset of a key's columns is a prefix of the encoding of
+//! the whole key.
+//!
+//! A decimal is a byte for its sign, 0 if negative, 1 for zero and 2 if positive, and for other than
+//! zero then the power of ten its significant digits start below in four bytes like a date, and then
+//! those digits a byte each, one more than the digit, ending with a 0. For a negative decimal the power
+//! is every bit flipped and each digit byte is ten less the digit, ending with an 11, so that greater
+//! magnitudes sort first. Only the value is kept, not the scale, so decimals equal but for trailing zeros
+//! have the same key; a decimal decoded for a column of a declared scale has that scale, and otherwise
+//! just its significant digits.
+use crate::datetime::Interval;
+use crate::decimal::Decimal;
+use crate::json::Json;
+use crate::tuple::{ColumnType, Value};
+
+const NULL: u8 = 0;
+pub(crate) const PRESENT: u8 = 1;
+
+pub(crate) fn encode(values: &[Value]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for value in values {
+        key.push(if value.is_null() { NULL } else { PRESENT });
+        match value {
+            Value::Null => {}
+            Value::Int(v) => key.extend_from_slice(&(*v as u64 ^ 1 << 63).to_be_bytes()),
+            Value::Float(v) => {
+                let bits = v.to_bits();
+                let bits = if bits >> 63 == 1 { !bits } else { bits ^ 1 << 63 };
+                key.extend_from_slice(&bits.to_be_bytes());
+            }
+            Value::Bool(v) => key.push(*v as u8),
+            Value::Bytes(v) => escape(&mut key, v),
+            Value::Text(v) => escape(&mut key, v.as_bytes()),
+            Value::Date(v) => key.extend_from_slice(&(*v as u32 ^ 1 << 31).to_be_bytes()),
+            Value::Time(v) | Value::Timestamp(v) | Value::TimestampTz(v) => {
+                key.extend_from_slice(&(*v as u64 ^ 1 << 63).to_be_bytes())
+            }
+            Value::Interval(v) => {
+                key.extend_from_slice(&(v.length() as u128 ^ 1 << 127).to_be_bytes());
+                key.extend_from_slice(&(v.months as u32 ^ 1 << 31).to_be_bytes());
+            }
+            Value::Decimal(v) => encode_decimal(&mut key, v),
+            Value::Json(v) => escape(&mut key, v.as_bytes()),
+        }
+    }
+    key
+}
+
+/// Decode values of `types` from the start of `key`, returning them and the number of bytes they took.
+pub(crate) fn decode(key: &[u8], types: &[ColumnType]) -> Option<(Vec<Value>, usize)> {
+    let mut values = Vec::with_capacity(types.len());
+    let mut at = 0;
+    for ty in types {
+        let marker = *key.get(at)?;
+        at += 1;
+        if marker == NULL {
+            values.push(Value::Null);
+            continue
+        }
+        let fixed = || Some(u64::from_be_bytes(key.get(at..at + 8)?.try_into().ok()?));
+        let short = |at: usize| Some(u32::from_be_bytes(key.get(at..at + 4)?.try_into().ok()?) ^ 1 << 31);
+        let (value, len) = match ty {
+            ColumnType::Int => (Value::Int((fixed()? ^ 1 << 63) as i64), 8),
+            ColumnType::Float => {
+                let bits = fixed()?;
+                (Value::Float(f64::from_bits(if bits >> 63 == 1 { bits ^ 1 << 63 } else { !bits })), 8)
+            }
+            ColumnType::Bool => (Value::Bool(*key.get(at)? == 1), 1),
+            ColumnType::Bytes => {
+                let (bytes, len) = unescape(&key[at..])?;
+                (Value::Bytes(bytes), len)
+            }
+            ColumnType::Text => {
+                let (bytes, len) = unescape(&key[at..])?;
+                (Value::Text(String::from_utf8(bytes).ok()?), len)
+            }
+            ColumnType::Date => (Value::Date(short(at)? as i32), 4),
+            ColumnType::Time => (Value::Time((fixed()? ^ 1 << 63) as i64), 8),
+            ColumnType::Timestamp => (Value::Timestamp((fixed()? ^ 1 << 63) as i64), 8),
+            ColumnType::TimestampTz => (Value::TimestampTz((fixed()? ^ 1 << 63) as i64), 8),
+            ColumnType::Interval => {
+                let length = (u128::from_be_bytes(key.get(at..at + 16)?.try_into().ok()?) ^ 1 << 127) as i128;
+                let months = short(at + 16)? as i32;
+                let micros = i64::try_from(length - Interval { months, micros: 0 }.length()).ok()?;
+                (Value::Interval(Interval { months, micros }), 20)
+            }
+            ColumnType::Decimal { precision, scale } => {
+                decode_decimal(&key[at..], (*precision > 0).then_some(*scale))?
+            }
+            ColumnType::Json => {
+                let (bytes, len) = unescape(&key[at..])?;
+                (Value::Json(Json::from_bytes(&bytes)?), len)
+            }
+        };
+        values.push(value);
+        at += len;
+    }
+    Some((values, at))
+}
+
+fn encode_decimal(key: &mut Vec<u8>, value: &Decimal) {
+    if value.is_zero() {
+        key.push(1);
+        return
+    }
+    let negative = value.is_negative();
+    let digits = value.digits();
+    let significant = digits.trim_end_matches('0');
+    let power = digits.len() as i32 - value.scale() as i32;
+    let power = power as u32 ^ 1 << 31;
+    key.push(if negative { 0 } else { 2 });
+    key.extend_from_slice(&if negative { !power } else { power }.to_be_bytes());
+    for digit in significant.bytes().map(|d| d - b'0') {
+        key.push(if negative { 10 - digit } else { digit + 1 });
+    }
+    key.push(if negative { 11 } else { 0 });
+}
+
+/// Undo `encode_decimal`, giving the decimal `scale` digits after the point if there is one.
+fn decode_decimal(key: &[u8], scale: Option<u32>) -> Option<(Value, usize)> {
+    let negative = match *key.first()? {
+        0 => true,
+        1 => return Some((Value::Decimal(Decimal::new(0, scale.unwrap_or(0))), 1)),
+        2 => false,
+        _ => return None,
+    };
+    let power = u32::from_be_bytes(key.get(1..5)?.try_into().ok()?);
+    let power = (if negative { !power } else { power } ^ 1 << 31) as i32 as i64;
+    let end = 5 + key[5..].iter().position(|b| *b == if negative { 11 } else { 0 })?;
+    let mut digits = String::with_capacity(end - 5);
+    for byte in &key[5..end] {
+        let digit = if negative { 10u8.checked_sub(*byte)? } else { byte.checked_sub(1)? };
+        digits.push(char::from_digit(digit as u32, 10)?);
+    }
+    let natural = digits.len() as i64 - power;
+    if natural < 0 {
+        digits.extend(std::iter::repeat_n('0', usize::try_from(-natural).ok()?));
+    }
+    let decimal = Decimal::from_digits(negative, &digits, u32::try_from(natural.max(0)).ok()?);
+    let decimal = match scale {
+        Some(scale) => decimal.round(scale)?,
+        None => decimal,
+    };
+    Some((Value::Decimal(decimal), end + 1))
+}
+
+fn escape(key: &mut Vec<u8>, bytes: &[u8]) {
+    for byte in bytes {
+        key.push(*byte);
+        if *byte == 0 {
+            key.push(0xff);
+        }
+    }
+    key.extend_from_slice(&[0, 0]);
+}
+
+/// Undo `escape`, returning the bytes and the length of their escaped form including the terminator.
+fn unescape(key: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut bytes = Vec::new();
+    let mut at = 0;
+    loop {
+        match (*key.get(at)?, key.get(at + 1)) {
+            (0, Some(0)) => return Some((bytes, at + 2)),
+            (0, Some(0xff)) => bytes.push(0),
+            (0, _) => return None,
+            (byte, _) => {
+                bytes.push(byte);
+                at += 1;
+                continue
+            }
+        }
+        at += 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+    use crate::datetime::Interval;
+    use crate::decimal::Decimal;
+    use crate::tuple::{ColumnType, Value};
+
+    #[test]
+    fn test_encoding_preserves_order() {
+        let ordered = [
+            vec![Value::Null],
+            vec![Value::Int(i64::MIN)],
+            vec![Value::Int(-1)],
+            vec![Value::Int(0)],
+            vec![Value::Int(i64::MAX)],
+        ];
+        assert!(ordered.windows(2).all(|w| encode(&w[0]) < encode(&w[1])));
+        let floats = [f64::NEG_INFINITY, -2.5, -0.0, 0.0, 1e-300, 3.0, f64::INFINITY];
+        assert!(floats.windows(2).all(|w| encode(&[Value::Float(w[0])]) <= encode(&[Value::Float(w[1])])));
+
+        let text = |s: &[u8]| vec![Value::Bytes(s.to_vec()), Value::Int(0)];
+        let strings: [&[u8]; 5] = [b"", b"\0", b"\0\0", b"a", b"a\0b"];
+        assert!(strings.windows(2).all(|w| encode(&text(w[0])) < encode(&text(w[1]))));
+        assert!(encode(&[Value::Text("ab".into()), Value::Int(1)]).starts_with(&encode(&[Value::Text("ab".into())])));
+
+        let dates = [i32::MIN, -1, 0, 19_782].map(Value::Date);
+        assert!(dates.windows(2).all(|w| encode(&w[..1]) < encode(&w[1..])));
+        let interval = |months, micros| Value::Interval(Interval { months, micros });
+        let intervals = [interval(-1, 0), interval(0, -1), interval(0, 30 * 86_400_000_000), interval(1, 0)];
+        assert!(intervals.windows(2).all(|w| w[0] < w[1] && encode(&w[..1]) < encode(&w[1..])));
+
+        let decimals = ["-1000", "-99.99", "-99.9", "-1", "-0.5", "0", "0.0099", "0.01", "0.5", "1", "1.01", "10"];
+        let decimals = decimals.map(|d| [Value::Decimal(Decimal::parse(d).unwrap()), Value::Int(0)]);
+        assert!(decimals.windows(2).all(|w| w[0] < w[1] && encode(&w[0]) < encode(&w[1])));
+        let decimal = |d| [Value::Decimal(Decimal::parse(d).unwrap())];
+        assert_eq!(encode(&decimal("-1.50")), encode(&decimal("-1.5")));
+    }
+
+    #[test]
+    fn test_decode_round_trips() {
+        let values = vec![
+            Value::Int(-7),
+            Value::Null,
+            Value::Float(-0.25),
+            Value::Bool(true),
+            Value::Text("a\0b".into()),
+            Value::Bytes(vec![0, 0xff, 0]),
+            Value::Date(-3),
+            Value::TimestampTz(-5),
+            Value::Interval(Interval { months: i32::MIN, micros: i64::MAX }),
+        ];
+        let mut types = vec![ColumnType::Int, ColumnType::Text, ColumnType::Float, ColumnType::Bool, ColumnType::Text];
+        types.extend([ColumnType::Bytes, ColumnType::Date, ColumnType::TimestampTz, ColumnType::Interval]);
+        let mut key = encode(&values);
+        let len = key.len();
+        key.extend_from_slice(b"rid");
+        assert_eq!(decode(&key, &types), Some((values, len)));
+        assert_eq!(decode(&key[..len - 1], &types), None);
+
+        let decimal = |d| Value::Decimal(Decimal::parse(d).unwrap());
+        let show = |values: Vec<Value>| values.iter().map(|v| format!("{v:?}")).collect::<Vec<_>>();
+        let values = [decimal("-12.50"), decimal("0.00"), decimal("300.00"), decimal("0.000120")];
+        let key = encode(&values);
+        let scaled = [2, 2, 2, 6].map(|scale| ColumnType::Decimal { precision: 10, scale });
+        let (decoded, len) = decode(&key, &scaled).unwrap();
+        assert_eq!((show(decoded), len), (show(values.to_vec()), key.len()));
+        let (decoded, _) = decode(&key, &[ColumnType::NUMERIC; 4]).unwrap();
+        assert_eq!(show(decoded), show(vec![decimal("-12.5"), decimal("0"), decimal("300"), decimal("0.00012")]));
+    }
+}
@@ -0,0 +1,1320 @@
+//! Tables: rows of a schema stored in a heap file, with secondary indexes kept in step.
+//!
+//! Every insert, update and delete goes through the table, which writes the heap and then every index
+//! registered on it, so callers never maintain indexes by hand. A write builds each index's entry, and
+//! checks it fits, before touching the heap, so a row whose key is too large for an index leaves nothing
+//! behind. An index is a B+tree whose keys are the
+//! order-preserving encoding of the indexed columns followed by the row's `Rid`, which makes every entry
+//! unique even when many rows share a value. Updates that leave an index's columns unchanged skip that
+//! index; ones that change them delete the old entry and insert the new one. Rids are stable across
+//! updates, so nothing else needs rewriting.
+//!
+//! `insert_many` writes many rows at once for bulk loads: the heap fills a page at a time, and then each
+//! index takes the new entries sorted by key, so the leaves they land in are written in turn rather
+//! than at random.
+//!
+//! A unique index refuses a write that would give two rows the same key, leaving the table unchanged,
+//! unless the key has a null: as in SQL, nulls are never equal to one another. The entry format is the
+//! same as for any other index, so a write looks up the new key's prefix before making any change.
+//!
+//! An index can also carry `include` columns, stored as a row in each entry's value rather than in its
+//! key. They cannot be searched on, but together with the key columns they let `index_scan` answer a
+//! query from the index alone, without reading the heap. Every committed write updates the heap and
+//! its indexes together, so an index entry is visible exactly when its row is.
+//!
+//! A key column of JSON documents can be given a `JsonPath`, and is then keyed on the part of each
+//! document the path leads to rather than the whole document, so that rows are found by a value inside
+//! their documents. Such a key column does not count towards covering a scan.
+//!
+//! An index of kind `IndexKind::Hash` keeps its entries in an extendible hash table instead, keyed by the
+//! encoded key columns alone, each mapping to the rids of the rows with that key in chunks of as many as
+//! fit in an entry. It finds rows only by their whole key, never by a prefix or a range, and holds no
+//! `include` columns; in exchange a lookup reads a bucket or two rather than descending a tree.
+//!
+//! Each key column also has a `Collation`, which for text is keyed on the collation's sort key, so that
+//! the index orders text the way the collation does and a unique index refuses texts the collation finds
+//! equal. Only a binary key column, whose key is the text itself, counts towards covering a scan.
+//!
+//! `ClusteredTable` is the alternative organization: rows live in a B+tree keyed by their primary key,
+//! with no heap at all. `TimeSeriesTable` is an append-only layout for rows arriving in timestamp
+//! order, which skips the free space map and prunes time-range scans by page. `ColumnarTable` stores
+//! each page's rows column by column, for analytical scans that read only a few columns.
+//! `PartitionedTable` splits rows by range or hash of one column over tables of their own.
+//!
+//! A table's schema can change after it is created: `add_column`, `drop_column` and `set_nullable` make
+//! a new version of its `SchemaHistory` without rewriting any rows, and rows are read through the version
+//! they were written under.
+//!
+//! A `Ttl` names a time column and how long rows live after it. `Table::expired` finds a bounded number
+//! of the rows that have expired, through an index led by the time column where there is one, for the
+//! database to delete as it deletes any other rows; `TimeSeriesTable::expire` drops whole pages.
+pub mod clustered;
+pub mod columnar;
+pub(crate) mod key;
+pub mod partitioned;
+pub mod stats;
+pub mod timeseries;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use crate::allocator::PageAllocator;
+use crate::btree::{self, BTree, BTreeError};
+use crate::cancel::CancelHandle;
+use crate::collation::Collation;
+use crate::datetime;
+use crate::hash::{self, ExtendibleHash, HashIndex, HashIndexError};
+use crate::heap::{HeapError, HeapFile, HeapScan, Rid};
+use crate::json::JsonPath;
+use crate::page_store::{PageError, PageId, PageStore};
+use crate::storage::Storage;
+use crate::tuple::{Column, ColumnType, Schema, SchemaHistory, TupleError, Value};
+
+pub use clustered::{ClusteredScan, ClusteredTable};
+pub use columnar::{ColumnarScan, ColumnarTable, Predicate};
+pub use partitioned::{PartitionBy, PartitionRid, PartitionedScan, PartitionedTable};
+pub use stats::TableStats;
+pub use timeseries::{TimeSeriesScan, TimeSeriesTable};
+
+/// How full `create_index` packs the nodes of a new index, leaving room for the rows written after it.
+const INDEX_FILL_FACTOR: f64 = 0.9;
+
+#[derive(Debug, PartialEq)]
+pub enum TableError {
+    Page(PageError),
+    Heap(HeapError),
+    Tuple(TupleError),
+    NoSuchColumn(usize),
+    NoSuchIndex(String),
+    DuplicateIndex(String),
+    /// A row with the same primary key is already in the table.
+    DuplicateKey,
+    /// No row has the given primary key.
+    NoSuchRow,
+    /// Primary key and timestamp columns must not be nullable.
+    NullableKey(usize),
+    /// An index was given a path into a column that does not hold JSON documents, or not one path, or
+    /// none, for each of its key columns.
+    InvalidPath,
+    /// An index was given a collation other than binary for a key column that is not text, or not one
+    /// collation for each of its key columns.
+    InvalidCollation,
+    /// An index key is too long to store in the index's B+tree.
+    KeyTooLarge,
+    /// No partition takes the row's partition column value, or there is no partition with the given id.
+    NoPartition,
+    /// The partition operation does not fit how the table is partitioned, such as a range partition
+    /// bound that is not above the existing ones.
+    InvalidPartition,
+    /// The partition list no longer fits on the table's root page.
+    TooManyPartitions,
+    /// The column is used by an index, so cannot be dropped.
+    IndexedColumn(usize),
+    /// A row would share the key of the unique index named `index` with another row.
+    UniqueViolation { index: String, key: Vec<Value> },
+    /// A hash index was given `include` columns, or asked for rows by a range or by part of its key.
+    UnsupportedByHash,
+}
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableError::Page(e) => write!(f, "page error: {e:?}"),
+            TableError::Heap(e) => write!(f, "heap error: {e:?}"),
+            TableError::Tuple(e) => write!(f, "{e}"),
+            TableError::NoSuchColumn(c) => write!(f, "no column {}", c + 1),
+            TableError::NoSuchIndex(name) => write!(f, "no index called {name}"),
+            TableError::DuplicateIndex(name) => write!(f, "an index called {name} already exists"),
+            TableError::DuplicateKey => write!(f, "a row with the same primary key already exists"),
+            TableError::NoSuchRow => write!(f, "no row has the primary key"),
+            TableError::NullableKey(c) => write!(f, "key column {} must not be nullable", c + 1),
+            TableError::InvalidPath => write!(f, "index paths must lead into JSON columns, one for each key column"),
+            TableError::InvalidCollation => write!(f, "only text key columns take a collation other than binary"),
+            TableError::KeyTooLarge => write!(f, "index key too large"),
+            TableError::NoPartition => write!(f, "no partition for the row"),
+            TableError::InvalidPartition => write!(f, "partition does not fit how the table is partitioned"),
+            TableError::TooManyPartitions => write!(f, "too many partitions"),
+            TableError::IndexedColumn(c) => write!(f, "column {} is used by an index", c + 1),
+            TableError::UniqueViolation { index, .. } => write!(f, "duplicate key violates unique index {index}"),
+            TableError::UnsupportedByHash => write!(f, "hash indexes only look up whole keys"),
+        }
+    }
+}
+impl From<PageError> for TableError {
+    fn from(e: PageError) -> Self {
+        TableError::Page(e)
+    }
+}
+impl From<HeapError> for TableError {
+    fn from(e: HeapError) -> Self {
+        match e {
+            HeapError::Page(e) => TableError::Page(e),
+            e => TableError::Heap(e),
+        }
+    }
+}
+impl From<TupleError> for TableError {
+    fn from(e: TupleError) -> Self {
+        TableError::Tuple(e)
+    }
+}
+impl From<HashIndexError> for TableError {
+    fn from(e: HashIndexError) -> Self {
+        match e {
+            HashIndexError::Page(e) => TableError::Page(e),
+            HashIndexError::EntryTooLarge => TableError::KeyTooLarge,
+        }
+    }
+}
+impl From<BTreeError> for TableError {
+    fn from(e: BTreeError) -> Self {
+        match e {
+            BTreeError::Page(e) => TableError::Page(e),
+            BTreeError::EntryTooLarge => TableError::KeyTooLarge,
+            BTreeError::Unsorted => unreachable!("index entries are sorted before bulk loading"),
+        }
+    }
+}
+
+/// Rows expire once `lifetime` has passed since the time in `column`: an `Interval` after a `Timestamp` or
+/// `TimestampTz`, or an `Int` after an `Int` in the same unit as the `now` passed to `expired`. Rows whose
+/// time is null never expire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ttl {
+    pub column: usize,
+    pub lifetime: Value,
+}
+impl Ttl {
+    /// The latest time, as a value of `column_type`, at which rows have expired by `now`: microseconds
+    /// since the epoch for a timestamp column. `None` if the lifetime does not suit the column.
+    fn cutoff(&self, column_type: ColumnType, now: i64) -> Option<Value> {
+        match (column_type, &self.lifetime) {
+            (ColumnType::Int, Value::Int(lifetime)) => Some(Value::Int(now.saturating_sub(*lifetime))),
+            (ColumnType::Timestamp | ColumnType::TimestampTz, Value::Interval(lifetime)) => {
+                let cutoff = datetime::add_interval(now, lifetime.checked_neg()?).unwrap_or(i64::MIN);
+                Some(time_value(column_type, cutoff))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the type of `column` can have a TTL, and `lifetime`'s type suits it.
+    pub fn suits(&self, column_type: ColumnType) -> bool {
+        self.cutoff(column_type, 0).is_some()
+    }
+}
+
+/// `time` as a value of `column_type`, an `Int` or a timestamp type.
+fn time_value(column_type: ColumnType, time: i64) -> Value {
+    match column_type {
+        ColumnType::Timestamp => Value::Timestamp(time),
+        ColumnType::TimestampTz => Value::TimestampTz(time),
+        _ => Value::Int(time),
+    }
+}
+
+/// How an index stores its entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexKind {
+    /// A B+tree in key order, which finds rows by a prefix of the key and a range of the column after it.
+    #[default]
+    BTree,
+    /// An extendible hash table, which finds rows only by their whole key.
+    Hash,
+}
+
+enum Entries<'store, S: Storage> {
+    BTree(BTree<'store, S>),
+    Hash(ExtendibleHash<'store, S>),
+}
+
+/// A secondary index over some of a table's columns.
+pub struct Index<'store, S: Storage> {
+    name: String,
+    columns: Vec<usize>,
+    /// For each key column, the path into its documents the index keys on instead of the whole value.
+    paths: Vec<Option<JsonPath>>,
+    /// For each key column, the collation its text is keyed and ordered by.
+    collations: Vec<Collation>,
+    include: Vec<usize>,
+    /// Schema of the included columns, which are stored as a row in each entry's value.
+    included: Schema,
+    unique: bool,
+    entries: Entries<'store, S>,
+}
+impl<S: Storage> Index<'_, S> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Indexed columns, most significant first.
+    pub fn columns(&self) -> &[usize] {
+        &self.columns
+    }
+
+    /// The path each of `columns` is keyed on, if any.
+    pub fn paths(&self) -> &[Option<JsonPath>] {
+        &self.paths
+    }
+
+    /// The collation each of `columns` is keyed by.
+    pub fn collations(&self) -> &[Collation] {
+        &self.collations
+    }
+
+    /// The type of each part of the key, given the table's schema: its column's, or for one with a path,
+    /// that of what the path extracts.
+    pub fn key_types(&self, schema: &Schema) -> Vec<ColumnType> {
+        key_types(schema, &self.columns, &self.paths)
+    }
+
+    /// Columns stored alongside each entry without being part of its key.
+    pub fn include(&self) -> &[usize] {
+        &self.include
+    }
+
+    /// Whether no two rows may have the same key, unless it has a null.
+    pub fn unique(&self) -> bool {
+        self.unique
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        match self.entries {
+            Entries::BTree(_) => IndexKind::BTree,
+            Entries::Hash(_) => IndexKind::Hash,
+        }
+    }
+
+    /// Whether every one of `columns` can be read from this index without visiting the heap.
+    pub fn covers(&self, columns: &[usize]) -> bool {
+        let stored = |c: &usize| self.stored(*c).is_some() || self.include.contains(c);
+        self.kind() == IndexKind::BTree && columns.iter().all(stored)
+    }
+
+    /// The part of the key that holds `column`'s value as it is, if any.
+    fn stored(&self, column: usize) -> Option<usize> {
+        let mut parts = self.columns.iter().zip(&self.paths).zip(&self.collations);
+        parts.position(|((c, path), collation)| {
+            *c == column && path.is_none() && *collation == Collation::Binary
+        })
+    }
+
+    /// The page to pass to `Table::open_index` to attach this index again.
+    pub fn meta_page(&self) -> PageId {
+        match &self.entries {
+            Entries::BTree(tree) => tree.meta_page(),
+            Entries::Hash(hash) => hash.header_page(),
+        }
+    }
+
+    /// The key and value of the entry for `row`.
+    fn entry(&self, row: &[Value], rid: Rid) -> Result<(Vec<u8>, Vec<u8>), TableError> {
+        entry(&self.columns, &self.paths, &self.collations, &self.include, &self.included, row, rid)
+    }
+
+    /// The values `row` has for the key.
+    fn key_values(&self, row: &[Value]) -> Vec<Value> {
+        key_values(&self.columns, &self.paths, row)
+    }
+
+    /// Whether the entry `entry` made for a row fits in the index.
+    fn fits(&self, (key, value): &(Vec<u8>, Vec<u8>)) -> bool {
+        match &self.entries {
+            Entries::BTree(_) => btree::entry_fits(key, value),
+            Entries::Hash(_) => {
+                let (key, rid) = key.split_at(key.len() - Rid::ENCODED_LEN);
+                hash::entry_fits(&chunk_key(key, 0), rid)
+            }
+        }
+    }
+
+    /// Add the entry `entry` made for a row.
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), TableError> {
+        match &self.entries {
+            Entries::BTree(tree) => {
+                tree.insert(key, value)?;
+            }
+            Entries::Hash(hash) => hash_insert(hash, &key[..key.len() - Rid::ENCODED_LEN], rid_of(key))?,
+        }
+        Ok(())
+    }
+
+    /// Remove the entry with the key `key` made for a row.
+    fn delete(&self, key: &[u8]) -> Result<(), TableError> {
+        match &self.entries {
+            Entries::BTree(tree) => {
+                tree.delete(key)?;
+            }
+            Entries::Hash(hash) => hash_delete(hash, &key[..key.len() - Rid::ENCODED_LEN], rid_of(key))?,
+        }
+        Ok(())
+    }
+}
+
+/// The key of the `chunk`th entry of the rids of the rows whose encoded key is `key`. Every chunk but the
+/// last holds as many rids as fit in an entry, and the last at least one.
+fn chunk_key(key: &[u8], chunk: u32) -> Vec<u8> {
+    [key, &chunk.to_be_bytes()].concat()
+}
+
+/// The chunks of rids of the rows whose encoded key is `key`, in order.
+fn hash_chunks<S: Storage>(hash: &ExtendibleHash<S>, key: &[u8]) -> Result<Vec<Vec<u8>>, HashIndexError> {
+    let mut chunks = Vec::new();
+    while let Some(rids) = hash.get(&chunk_key(key, chunks.len() as u32))? {
+        chunks.push(rids);
+    }
+    Ok(chunks)
+}
+
+/// The rids of the rows whose encoded key is `key`, in the order they were added.
+fn hash_rids<S: Storage>(hash: &ExtendibleHash<S>, key: &[u8]) -> Result<Vec<Rid>, HashIndexError> {
+    let chunks = hash_chunks(hash, key)?;
+    let rids = chunks.iter().flat_map(|rids| rids.chunks(Rid::ENCODED_LEN));
+    Ok(rids.map(|rid| Rid::from_bytes(rid.try_into().unwrap())).collect())
+}
+
+/// Add `rid` to the last chunk of rids of `key`, or to a new one once that is full.
+fn hash_insert<S: Storage>(hash: &ExtendibleHash<S>, key: &[u8], rid: Rid) -> Result<(), HashIndexError> {
+    let mut chunks = hash_chunks(hash, key)?;
+    if let Some(mut last) = chunks.pop() {
+        let chunk = chunk_key(key, chunks.len() as u32);
+        last.extend_from_slice(&rid.to_bytes());
+        if hash::entry_fits(&chunk, &last) {
+            hash.insert(&chunk, &last)?;
+            return Ok(())
+        }
+        chunks.push(last);
+    }
+    hash.insert(&chunk_key(key, chunks.len() as u32), &rid.to_bytes())?;
+    Ok(())
+}
+
+/// Remove `rid` from the chunks of rids of `key`, filling its place from the last chunk so that every
+/// other one stays full.
+fn hash_delete<S: Storage>(hash: &ExtendibleHash<S>, key: &[u8], rid: Rid) -> Result<(), HashIndexError> {
+    let mut chunks = hash_chunks(hash, key)?;
+    let rid = rid.to_bytes();
+    let found = chunks.iter().enumerate().find_map(|(i, rids)| {
+        rids.chunks(Rid::ENCODED_LEN).position(|r| r == rid).map(|at| (i, at * Rid::ENCODED_LEN))
+    });
+    let (Some((i, at)), Some(mut last)) = (found, chunks.pop()) else { return Ok(()) };
+    let moved = last.split_off(last.len() - Rid::ENCODED_LEN);
+    if i < chunks.len() {
+        chunks[i][at..at + Rid::ENCODED_LEN].copy_from_slice(&moved);
+        hash.insert(&chunk_key(key, i as u32), &chunks[i])?;
+    } else if at < last.len() {
+        last[at..at + Rid::ENCODED_LEN].copy_from_slice(&moved);
+    }
+    let chunk = chunk_key(key, chunks.len() as u32);
+    if last.is_empty() {
+        hash.delete(&chunk)?;
+    } else {
+        hash.insert(&chunk, &last)?;
+    }
+    Ok(())
+}
+
+fn key_values(columns: &[usize], paths: &[Option<JsonPath>], row: &[Value]) -> Vec<Value> {
+    let value = |(c, path): (&usize, &Option<JsonPath>)| match path {
+        Some(path) => path.extract(&row[*c]),
+        None => row[*c].clone(),
+    };
+    columns.iter().zip(paths).map(value).collect()
+}
+
+fn key_types(schema: &Schema, columns: &[usize], paths: &[Option<JsonPath>]) -> Vec<ColumnType> {
+    let column_type = |(c, path): (&usize, &Option<JsonPath>)| match path {
+        Some(path) => path.column_type(),
+        None => schema.column_type(*c),
+    };
+    columns.iter().zip(paths).map(column_type).collect()
+}
+
+/// The encoded key of `values`, with text keyed on its sort key under its part's collation.
+fn encode_key(values: &[Value], collations: &[Collation]) -> Vec<u8> {
+    let collate = |(value, collation): (&Value, &Collation)| match value {
+        Value::Text(text) if *collation != Collation::Binary => Value::Bytes(collation.sort_key(text)),
+        value => value.clone(),
+    };
+    key::encode(&values.iter().zip(collations).map(collate).collect::<Vec<_>>())
+}
+
+fn entry(
+    columns: &[usize],
+    paths: &[Option<JsonPath>],
+    collations: &[Collation],
+    include: &[usize],
+    included: &Schema,
+    row: &[Value],
+    rid: Rid,
+) -> Result<(Vec<u8>, Vec<u8>), TableError> {
+    let values = key_values(columns, paths, row);
+    let mut key = encode_key(&values, collations);
+    key.extend_from_slice(&rid.to_bytes());
+    let values: Vec<Value> = include.iter().map(|c| row[*c].clone()).collect();
+    Ok((key, included.encode(&values)?))
+}
+
+pub struct Table<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    history: SchemaHistory,
+    heap: HeapFile<'store, S>,
+    indexes: Vec<Index<'store, S>>,
+}
+impl<'store, S: Storage> Table<'store, S> {
+    pub fn create(store: &'store PageStore<S>, allocator: PageAllocator, schema: Schema) -> Result<Table<'store, S>, TableError> {
+        let heap = HeapFile::create(store, allocator)?;
+        Ok(Table { store, allocator, history: SchemaHistory::new(schema), heap, indexes: Vec::new() })
+    }
+
+    /// Open the table whose heap starts at `root`, which has never been altered. Its indexes are attached
+    /// with `open_index`.
+    pub fn open(store: &'store PageStore<S>, allocator: PageAllocator, schema: Schema, root: PageId) -> Result<Table<'store, S>, TableError> {
+        Table::open_with_history(store, allocator, SchemaHistory::new(schema), root)
+    }
+
+    /// Open the table whose heap starts at `root` and whose schema has been through `history`.
+    pub fn open_with_history(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        history: SchemaHistory,
+        root: PageId,
+    ) -> Result<Table<'store, S>, TableError> {
+        let heap = HeapFile::open(store, allocator, root)?;
+        Ok(Table { store, allocator, history, heap, indexes: Vec::new() })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        self.history.schema()
+    }
+
+    /// Every version of the schema, to pass to `open_with_history` once the table has been altered.
+    pub fn history(&self) -> &SchemaHistory {
+        &self.history
+    }
+
+    /// Add `column` after the others. Rows already in the table read `default` for it; none are rewritten.
+    pub fn add_column(&mut self, column: Column, default: Value) -> Result<(), TableError> {
+        Ok(self.history.add_column(column, default)?)
+    }
+
+    /// Drop `column`, which no index may use. Later columns move down one, in the indexes too; rows keep
+    /// the dropped value until they are next written.
+    pub fn drop_column(&mut self, column: usize) -> Result<(), TableError> {
+        if column >= self.schema().len() {
+            return Err(TableError::NoSuchColumn(column))
+        }
+        if self.indexes.iter().any(|i| i.columns.contains(&column) || i.include.contains(&column)) {
+            return Err(TableError::IndexedColumn(column))
+        }
+        self.history.drop_column(column);
+        for index in &mut self.indexes {
+            for c in index.columns.iter_mut().chain(&mut index.include) {
+                if *c > column {
+                    *c -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Allow or forbid nulls in `column`. Forbidding them reads through the table to check it has none.
+    pub fn set_nullable(&mut self, column: usize, nullable: bool) -> Result<(), TableError> {
+        if column >= self.schema().len() {
+            return Err(TableError::NoSuchColumn(column))
+        }
+        if !nullable {
+            for row in self.scan() {
+                if row?.1[column].is_null() {
+                    return Err(TableError::Tuple(TupleError::NullNotAllowed { column }))
+                }
+            }
+        }
+        self.history.set_nullable(column, nullable);
+        let schema = self.history.schema();
+        for index in &mut self.indexes {
+            index.included = Schema::new(index.include.iter().map(|c| schema.column(*c)).collect());
+        }
+        Ok(())
+    }
+
+    /// The page to pass to `open` to find this table again.
+    pub fn root(&self) -> PageId {
+        self.heap.root()
+    }
+
+    pub fn indexes(&self) -> &[Index<'store, S>] {
+        &self.indexes
+    }
+
+    /// Build an index on `columns`, also storing the `include` columns in its entries, over the rows
+    /// already in the table and keep it up to date from now on.
+    pub fn create_index(&mut self, name: &str, columns: Vec<usize>, include: Vec<usize>) -> Result<&Index<'store, S>, TableError> {
+        let (paths, collations) = (vec![None; columns.len()], vec![Collation::Binary; columns.len()]);
+        self.create_keyed_index(name, columns, paths, collations, include, false, IndexKind::BTree)
+    }
+
+    /// Build an index like `create_index` does, which also refuses writes that would give two rows the
+    /// same key. Fails, building nothing, if two rows already do.
+    pub fn create_unique_index(&mut self, name: &str, columns: Vec<usize>, include: Vec<usize>) -> Result<&Index<'store, S>, TableError> {
+        let (paths, collations) = (vec![None; columns.len()], vec![Collation::Binary; columns.len()]);
+        self.create_keyed_index(name, columns, paths, collations, include, true, IndexKind::BTree)
+    }
+
+    /// Build an index like `create_index`, or `create_unique_index` if `unique`, with each key column that
+    /// has a path in `paths` keyed on what the path extracts from its documents, and each keyed by its
+    /// collation in `collations`, storing its entries as `kind` says.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_keyed_index(
+        &mut self,
+        name: &str,
+        columns: Vec<usize>,
+        paths: Vec<Option<JsonPath>>,
+        collations: Vec<Collation>,
+        include: Vec<usize>,
+        unique: bool,
+        kind: IndexKind,
+    ) -> Result<&Index<'store, S>, TableError> {
+        let included = self.check_index(name, &columns, &paths, &collations, &include, kind)?;
+        let mut entries = Vec::new();
+        for row in self.scan() {
+            let (rid, row) = row?;
+            entries.push(entry(&columns, &paths, &collations, &include, &included, &row, rid)?);
+        }
+        entries.sort_unstable();
+        if unique {
+            for pair in entries.windows(2) {
+                let (a, b) = (&pair[0].0, &pair[1].0);
+                if a[..a.len() - Rid::ENCODED_LEN] == b[..b.len() - Rid::ENCODED_LEN] {
+                    // The key may hold sort keys, so the values come from the row itself.
+                    let values = key_values(&columns, &paths, &self.get(&rid_of(a))?);
+                    if !values.iter().any(Value::is_null) {
+                        return Err(TableError::UniqueViolation { index: name.to_string(), key: values })
+                    }
+                }
+            }
+        }
+        let entries = match kind {
+            IndexKind::BTree => {
+                Entries::BTree(BTree::bulk_load(self.store, self.allocator, entries, INDEX_FILL_FACTOR)?)
+            }
+            IndexKind::Hash => {
+                let hash = ExtendibleHash::create(self.store, self.allocator)?;
+                for (key, _) in entries {
+                    hash_insert(&hash, &key[..key.len() - Rid::ENCODED_LEN], rid_of(&key))?;
+                }
+                Entries::Hash(hash)
+            }
+        };
+        let index = Index { name: name.to_string(), columns, paths, collations, include, included, unique, entries };
+        self.indexes.push(index);
+        Ok(self.indexes.last().unwrap())
+    }
+
+    /// Attach an index made earlier by `create_index`, which must have been kept up to date since.
+    pub fn open_index(&mut self, name: &str, columns: Vec<usize>, include: Vec<usize>, meta: PageId) -> Result<&Index<'store, S>, TableError> {
+        let (paths, collations) = (vec![None; columns.len()], vec![Collation::Binary; columns.len()]);
+        self.open_keyed_index(name, columns, paths, collations, include, meta, false, IndexKind::BTree)
+    }
+
+    /// Attach an index made earlier by `create_unique_index`, which must have been kept up to date since.
+    pub fn open_unique_index(&mut self, name: &str, columns: Vec<usize>, include: Vec<usize>, meta: PageId) -> Result<&Index<'store, S>, TableError> {
+        let (paths, collations) = (vec![None; columns.len()], vec![Collation::Binary; columns.len()]);
+        self.open_keyed_index(name, columns, paths, collations, include, meta, true, IndexKind::BTree)
+    }
+
+    /// Attach an index made earlier by `create_keyed_index`, which must have been kept up to date since.
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_keyed_index(
+        &mut self,
+        name: &str,
+        columns: Vec<usize>,
+        paths: Vec<Option<JsonPath>>,
+        collations: Vec<Collation>,
+        include: Vec<usize>,
+        meta: PageId,
+        unique: bool,
+        kind: IndexKind,
+    ) -> Result<&Index<'store, S>, TableError> {
+        let included = self.check_index(name, &columns, &paths, &collations, &include, kind)?;
+        let entries = match kind {
+            IndexKind::BTree => Entries::BTree(BTree::open(self.store, self.allocator, meta)?),
+            IndexKind::Hash => Entries::Hash(ExtendibleHash::open(self.store, self.allocator, meta)?),
+        };
+        let index = Index { name: name.to_string(), columns, paths, collations, include, included, unique, entries };
+        self.indexes.push(index);
+        Ok(self.indexes.last().unwrap())
+    }
+
+    /// Fail if `row` would have the key of another row than `rid` in a unique index. With `old`, the
+    /// row's values before an update, indexes whose key columns it leaves alone are skipped.
+    fn check_unique(&self, row: &[Value], rid: Option<Rid>, old: Option<&[Value]>) -> Result<(), TableError> {
+        for index in self.indexes.iter().filter(|i| i.unique) {
+            let values = index.key_values(row);
+            let unchanged = old.is_some_and(|old| index.columns.iter().all(|c| old[*c] == row[*c]));
+            if unchanged || values.iter().any(Value::is_null) {
+                continue
+            }
+            let mut taken = false;
+            self.for_each_entry(index, &values, (Bound::Unbounded, Bound::Unbounded), |key, _| {
+                taken = Some(rid_of(key)) != rid;
+                Ok(!taken)
+            })?;
+            if taken {
+                return Err(TableError::UniqueViolation { index: index.name.clone(), key: values })
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail with `KeyTooLarge` if the entry of `row` in some index is too large for it.
+    fn check_entries(&self, row: &[Value]) -> Result<(), TableError> {
+        for index in &self.indexes {
+            // Every rid encodes to the same length, so any one stands in for the row's.
+            if !index.fits(&index.entry(row, Rid::new(PageId::new(0), 0))?) {
+                return Err(TableError::KeyTooLarge)
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, row: &[Value]) -> Result<Rid, TableError> {
+        let record = self.history.encode(row)?;
+        self.check_entries(row)?;
+        self.check_unique(row, None, None)?;
+        let rid = self.heap.insert(&record)?;
+        for index in &self.indexes {
+            let (key, value) = index.entry(row, rid)?;
+            index.insert(&key, &value)?;
+        }
+        Ok(rid)
+    }
+
+    /// Insert every one of `rows`, returning their rids in the same order. Fails, inserting none of them,
+    /// if a row does not fit the schema or has a key too large for an index, or two rows, in the table or
+    /// among `rows`, would share a unique key.
+    pub fn insert_many(&mut self, rows: &[Vec<Value>]) -> Result<Vec<Rid>, TableError> {
+        let records = rows.iter().map(|row| self.history.encode(row)).collect::<Result<Vec<_>, _>>()?;
+        for row in rows {
+            self.check_entries(row)?;
+            self.check_unique(row, None, None)?;
+        }
+        for index in self.indexes.iter().filter(|i| i.unique) {
+            let mut keys = HashSet::new();
+            for row in rows {
+                let values = index.key_values(row);
+                if !values.iter().any(Value::is_null) && !keys.insert(values.clone()) {
+                    return Err(TableError::UniqueViolation { index: index.name.clone(), key: values })
+                }
+            }
+        }
+        let rids = self.heap.insert_many(&records)?;
+        for index in &self.indexes {
+            let entries = rows.iter().zip(&rids).map(|(row, rid)| index.entry(row, *rid));
+            let mut entries = entries.collect::<Result<Vec<_>, _>>()?;
+            entries.sort_unstable();
+            for (key, value) in entries {
+                index.insert(&key, &value)?;
+            }
+        }
+        Ok(rids)
+    }
+
+    pub fn get(&self, rid: &Rid) -> Result<Vec<Value>, TableError> {
+        Ok(self.history.decode(&self.heap.get(rid)?)?)
+    }
+
+    pub fn update(&mut self, rid: &Rid, row: &[Value]) -> Result<(), TableError> {
+        let old = self.get(rid)?;
+        let record = self.history.encode(row)?;
+        self.check_entries(row)?;
+        self.check_unique(row, Some(*rid), Some(&old))?;
+        self.heap.update(rid, &record)?;
+        for index in &self.indexes {
+            let (old_key, old_value) = index.entry(&old, *rid)?;
+            let (new_key, new_value) = index.entry(row, *rid)?;
+            if old_key != new_key {
+                index.delete(&old_key)?;
+            }
+            if old_key != new_key || old_value != new_value {
+                index.insert(&new_key, &new_value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn delete(&mut self, rid: &Rid) -> Result<(), TableError> {
+        let old = self.get(rid)?;
+        self.heap.delete(rid)?;
+        for index in &self.indexes {
+            index.delete(&index.entry(&old, *rid)?.0)?;
+        }
+        Ok(())
+    }
+
+    /// The rids of at most `limit` rows that have expired under `ttl` by `now`, microseconds since the
+    /// epoch for a timestamp column; fewer than `limit` means there are no more. Expired rows are found
+    /// through an index led by the TTL column if there is one, and by scanning the heap otherwise.
+    pub fn expired(&self, ttl: &Ttl, now: i64, limit: usize) -> Result<Vec<Rid>, TableError> {
+        if ttl.column >= self.schema().len() {
+            return Err(TableError::NoSuchColumn(ttl.column))
+        }
+        let column_type = self.schema().column_type(ttl.column);
+        let Some(cutoff) = ttl.cutoff(column_type, now) else {
+            return Err(TableError::Tuple(TupleError::TypeMismatch { column: ttl.column }))
+        };
+        let mut expired = Vec::new();
+        let led = |i: &&Index<'store, S>| {
+            i.kind() == IndexKind::BTree && i.columns.first() == Some(&ttl.column) && i.paths[0].is_none()
+        };
+        if let Some(Entries::BTree(tree)) = self.indexes.iter().find(led).map(|i| &i.entries) {
+            // Null times sort before every other, so starting at the smallest one skips them.
+            let start = key::encode(&[time_value(column_type, i64::MIN)]);
+            for entry in tree.range::<&[u8], _>((Bound::Included(&start[..]), Bound::Unbounded)) {
+                let (key, _) = entry?;
+                let Some((time, _)) = key::decode(&key, &[column_type]) else {
+                    return Err(TableError::Tuple(TupleError::Corrupt))
+                };
+                if expired.len() == limit || time[0] > cutoff {
+                    break
+                }
+                expired.push(rid_of(&key));
+            }
+        } else {
+            for row in self.scan() {
+                let (rid, row) = row?;
+                if expired.len() == limit {
+                    break
+                }
+                if !row[ttl.column].is_null() && row[ttl.column] <= cutoff {
+                    expired.push(rid);
+                }
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Return the pages of the heap and of every index to the allocator.
+    pub fn free(self) -> Result<(), TableError> {
+        self.heap.free()?;
+        for index in self.indexes {
+            match index.entries {
+                Entries::BTree(tree) => tree.free()?,
+                Entries::Hash(hash) => hash.free()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Gather statistics over every row, building histograms and distinct counts from a sample of at most
+    /// `sample_size` rows.
+    pub fn analyze(&self, sample_size: usize) -> Result<TableStats, TableError> {
+        stats::analyze(self.schema(), self.scan().map(|row| row.map(|(_, row)| row)), sample_size)
+    }
+
+    /// Every row, in heap order.
+    pub fn scan(&self) -> TableScan<'_, 'store, S> {
+        TableScan { history: &self.history, heap: self.heap.scan() }
+    }
+
+    /// Rids of the rows whose leading indexed columns equal `values`, in index order. `values` may
+    /// cover fewer columns than the index, unless it is a hash index, whose rids come in the order their
+    /// rows were added.
+    pub fn lookup(&self, index: &str, values: &[Value]) -> Result<Vec<Rid>, TableError> {
+        self.lookup_first(index, values, usize::MAX)
+    }
+
+    /// The first `limit` rids `lookup` would return, reading no further into the index.
+    pub fn lookup_first(&self, index: &str, values: &[Value], limit: usize) -> Result<Vec<Rid>, TableError> {
+        self.lookup_range(index, values, (Bound::Unbounded, Bound::Unbounded), limit)
+    }
+
+    /// The first `limit` rids of the rows whose leading indexed columns equal `values` and whose next
+    /// indexed column is within `range`, in index order. A range bounded on either side holds no nulls.
+    pub fn lookup_range(
+        &self,
+        index: &str,
+        values: &[Value],
+        range: (Bound<&Value>, Bound<&Value>),
+        limit: usize,
+    ) -> Result<Vec<Rid>, TableError> {
+        let index = self.index(index)?;
+        let mut rids = Vec::new();
+        if limit == 0 {
+            return Ok(rids)
+        }
+        self.for_each_entry(index, values, range, |key, _| {
+            rids.push(rid_of(key));
+            Ok(rids.len() < limit)
+        })?;
+        Ok(rids)
+    }
+
+    /// The given `columns` of the rows whose leading indexed columns equal `values`, in index order.
+    /// When the index covers `columns` the rows are read from the index alone; otherwise each one is
+    /// fetched from the heap.
+    pub fn index_scan(&self, index: &str, values: &[Value], columns: &[usize]) -> Result<Vec<(Rid, Vec<Value>)>, TableError> {
+        let index = self.index(index)?;
+        if let Some(c) = columns.iter().find(|c| **c >= self.schema().len()) {
+            return Err(TableError::NoSuchColumn(*c))
+        }
+        let mut rows = Vec::new();
+        if !index.covers(columns) {
+            for rid in self.lookup(&index.name, values)? {
+                let row = self.get(&rid)?;
+                rows.push((rid, columns.iter().map(|c| row[*c].clone()).collect()));
+            }
+            return Ok(rows)
+        }
+        let mut types = index.key_types(self.schema());
+        for (t, collation) in types.iter_mut().zip(&index.collations) {
+            if *collation != Collation::Binary {
+                *t = ColumnType::Bytes;
+            }
+        }
+        self.for_each_entry(index, values, (Bound::Unbounded, Bound::Unbounded), |key, value| {
+            let (keyed, _) = key::decode(key, &types).ok_or(TableError::Tuple(TupleError::Corrupt))?;
+            let included = index.included.decode(value)?;
+            let project = |c: &usize| match index.stored(*c) {
+                Some(i) => keyed[i].clone(),
+                None => included[index.include.iter().position(|k| k == c).unwrap()].clone(),
+            };
+            rows.push((rid_of(key), columns.iter().map(project).collect()));
+            Ok(true)
+        })?;
+        Ok(rows)
+    }
+
+    /// Call `f` with the key and value of each entry of `index` whose leading columns equal `values`, and
+    /// whose next column is within `range`, until it returns false. A hash index takes its whole key and no
+    /// range, and gives each entry's key as a B+tree's would be, with an empty value.
+    fn for_each_entry(
+        &self,
+        index: &Index<'store, S>,
+        values: &[Value],
+        range: (Bound<&Value>, Bound<&Value>),
+        mut f: impl FnMut(&[u8], &[u8]) -> Result<bool, TableError>,
+    ) -> Result<(), TableError> {
+        let ranged = range != (Bound::Unbounded, Bound::Unbounded);
+        if values.len() + ranged as usize > index.columns.len() {
+            return Err(TableError::Tuple(TupleError::WrongColumnCount))
+        }
+        let prefix = encode_key(values, &index.collations);
+        let tree = match &index.entries {
+            Entries::BTree(tree) => tree,
+            Entries::Hash(_) if ranged || values.len() < index.columns.len() => {
+                return Err(TableError::UnsupportedByHash)
+            }
+            Entries::Hash(hash) => {
+                for rid in hash_rids(hash, &prefix)? {
+                    if !f(&[&prefix[..], &rid.to_bytes()].concat(), &[])? {
+                        break
+                    }
+                }
+                return Ok(())
+            }
+        };
+        let bound = |value: &Value| encode_key(&[values, std::slice::from_ref(value)].concat(), &index.collations);
+        let (low, high) = (range.0.map(bound), range.1.map(bound));
+        // The entries whose next column equals a bound are those whose keys start with the bound's key, since
+        // each column's encoding delimits itself; the non-null ones start after every null.
+        let start = match &low {
+            Bound::Included(low) | Bound::Excluded(low) => low.clone(),
+            Bound::Unbounded if ranged => [&prefix[..], &[key::PRESENT]].concat(),
+            Bound::Unbounded => prefix.clone(),
+        };
+        for entry in tree.range::<&[u8], _>((Bound::Included(&start[..]), Bound::Unbounded)) {
+            let (key, value) = entry?;
+            let past = match &high {
+                Bound::Included(high) => key > *high && !key.starts_with(high),
+                Bound::Excluded(high) => key >= *high,
+                Bound::Unbounded => false,
+            };
+            if !key.starts_with(&prefix) || past {
+                break
+            }
+            if matches!(&low, Bound::Excluded(low) if key.starts_with(low)) {
+                continue
+            }
+            if !f(&key, &value)? {
+                break
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&self, name: &str) -> Result<&Index<'store, S>, TableError> {
+        self.indexes.iter().find(|i| i.name == name).ok_or_else(|| TableError::NoSuchIndex(name.to_string()))
+    }
+
+    /// Check that an index can be attached under `name`, returning the schema of its included columns.
+    fn check_index(
+        &self,
+        name: &str,
+        columns: &[usize],
+        paths: &[Option<JsonPath>],
+        collations: &[Collation],
+        include: &[usize],
+        kind: IndexKind,
+    ) -> Result<Schema, TableError> {
+        if self.indexes.iter().any(|i| i.name == name) {
+            return Err(TableError::DuplicateIndex(name.to_string()))
+        }
+        if kind == IndexKind::Hash && !include.is_empty() {
+            return Err(TableError::UnsupportedByHash)
+        }
+        if let Some(c) = columns.iter().chain(include).find(|c| **c >= self.schema().len()) {
+            return Err(TableError::NoSuchColumn(*c))
+        }
+        let documents = |(c, path): (&usize, &Option<JsonPath>)| {
+            path.is_none() || self.schema().column_type(*c) == ColumnType::Json
+        };
+        if paths.len() != columns.len() || !columns.iter().zip(paths).all(documents) {
+            return Err(TableError::InvalidPath)
+        }
+        let types = key_types(self.schema(), columns, paths);
+        let text = |(t, collation): (&ColumnType, &Collation)| {
+            *collation == Collation::Binary || *t == ColumnType::Text
+        };
+        if collations.len() != columns.len() || !types.iter().zip(collations).all(text) {
+            return Err(TableError::InvalidCollation)
+        }
+        Ok(Schema::new(include.iter().map(|c| self.schema().column(*c)).collect()))
+    }
+}
+
+fn rid_of(key: &[u8]) -> Rid {
+    Rid::from_bytes(key[key.len() - Rid::ENCODED_LEN..].try_into().unwrap())
+}
+
+pub struct TableScan<'table, 'store, S: Storage> {
+    history: &'table SchemaHistory,
+    heap: HeapScan<'table, 'store, S>,
+}
+impl<S: Storage> TableScan<'_, '_, S> {
+    /// Fail with `HeapError::Cancelled` once `handle` is cancelled, before loading the next page.
+    pub fn cancel_with(self, handle: CancelHandle) -> Self {
+        TableScan { heap: self.heap.cancel_with(handle), ..self }
+    }
+
+    /// Read only the pages no other scan sharing `next` has read, as `HeapScan::shared` does.
+    pub fn shared(self, next: Arc<AtomicUsize>) -> Self {
+        TableScan { heap: self.heap.shared(next), ..self }
+    }
+}
+impl<S: Storage> Iterator for TableScan<'_, '_, S> {
+    type Item = Result<(Rid, Vec<Value>), TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.heap.next()?;
+        Some(entry.map_err(TableError::from).and_then(|(rid, record)| Ok((rid, self.history.decode(&record)?))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::allocator::PageAllocator;
+    use crate::collation::Collation;
+    use crate::datetime::Interval;
+    use crate::heap::Rid;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+    use crate::tuple::{Column, ColumnType, Schema, TupleError, Value};
+
+    use super::{IndexKind, Table, TableError, Ttl};
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Column::new(ColumnType::Int),
+            Column::new(ColumnType::Text),
+            Column::nullable(ColumnType::Int),
+        ])
+    }
+
+    fn row(id: i64, city: &str, age: Option<i64>) -> Vec<Value> {
+        vec![Value::Int(id), Value::Text(city.to_string()), age.map_or(Value::Null, Value::Int)]
+    }
+
+    #[test]
+    fn test_indexes_follow_writes() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = Table::create(&store, allocator, schema())?;
+        let cities = ["oslo", "lima", "pune"];
+        let rids: Vec<_> = (0..300).map(|i| table.insert(&row(i, cities[i as usize % 3], Some(i % 50)))).collect::<Result<_, _>>()?;
+        table.create_index("by_city", vec![1, 2], vec![])?;
+
+        assert_eq!(table.lookup("by_city", &[Value::Text("lima".into())])?.len(), 100);
+        table.update(&rids[1], &row(1, "oslo", Some(1)))?;
+        table.update(&rids[4], &row(4, "lima", None))?;
+        table.delete(&rids[7])?;
+        table.insert(&row(300, "lima", Some(4)))?;
+        assert_eq!(table.lookup("by_city", &[Value::Text("lima".into())])?.len(), 99);
+        assert_eq!(table.lookup("by_city", &[Value::Text("oslo".into())])?.len(), 101);
+        assert_eq!(table.lookup("by_city", &[Value::Text("lima".into()), Value::Null])?, vec![rids[4]]);
+
+        let hits = table.lookup("by_city", &[Value::Text("lima".into()), Value::Int(4)])?;
+        assert!(hits.windows(2).all(|w| w[0] < w[1]));
+        let ids: Vec<_> = hits.iter().map(|rid| table.get(rid).map(|r| r[0].clone())).collect::<Result<_, _>>()?;
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&Value::Int(154)) && ids.contains(&Value::Int(300)));
+
+        let meta = table.indexes()[0].meta_page();
+        let mut reopened = Table::open(&store, allocator, schema(), table.root())?;
+        reopened.open_index("by_city", vec![1, 2], vec![], meta)?;
+        assert_eq!(reopened.lookup("by_city", &[Value::Text("pune".into())])?.len(), 100);
+        assert_eq!(reopened.create_index("by_city", vec![0], vec![]).err(), Some(TableError::DuplicateIndex("by_city".into())));
+        assert_eq!(reopened.create_index("bad", vec![3], vec![]).err(), Some(TableError::NoSuchColumn(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_lookups() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = Table::create(&store, allocator, schema())?;
+        let cities = ["oslo", "lima", "pune"];
+        for i in 0..300 {
+            table.insert(&row(i, cities[i as usize % 3], (i % 7 != 0).then_some(i % 50)))?;
+        }
+        table.create_index("by_city", vec![1, 2], vec![])?;
+
+        let lima = [Value::Text("lima".into())];
+        let ids = |rids: Vec<Rid>| -> Result<Vec<i64>, TableError> {
+            let rows = rids.iter().map(|rid| table.get(rid)).collect::<Result<Vec<_>, _>>()?;
+            Ok(rows.iter().map(|row| match row[0] { Value::Int(id) => id, _ => unreachable!() }).collect())
+        };
+        let expected = |within: &dyn Fn(i64) -> bool| -> Vec<i64> {
+            let mut rows: Vec<_> = (0..300).filter(|i| i % 3 == 1 && i % 7 != 0 && within(i % 50)).collect();
+            rows.sort_by_key(|i| (i % 50, *i));
+            rows
+        };
+        let (ten, twenty) = (Value::Int(10), Value::Int(20));
+        let range = (Bound::Excluded(&ten), Bound::Included(&twenty));
+        let mut found = ids(table.lookup_range("by_city", &lima, range, usize::MAX)?)?;
+        assert_eq!(found, expected(&|age| age > 10 && age <= 20));
+        found = ids(table.lookup_range("by_city", &lima, (Bound::Unbounded, Bound::Excluded(&ten)), usize::MAX)?)?;
+        assert_eq!(found, expected(&|age| age < 10));
+        found = ids(table.lookup_range("by_city", &lima, (Bound::Included(&twenty), Bound::Unbounded), 5)?)?;
+        assert_eq!(found, expected(&|age| age >= 20)[..5]);
+        // On the leading column alone, and past every indexed column.
+        let only = (Bound::Included(&lima[0]), Bound::Included(&lima[0]));
+        let all = table.lookup_range("by_city", &[], only, usize::MAX)?;
+        assert_eq!(all.len(), 100);
+        let range = (Bound::Unbounded, Bound::Included(&ten));
+        let error = table.lookup_range("by_city", &[lima[0].clone(), Value::Int(1)], range, usize::MAX).err();
+        assert_eq!(error, Some(TableError::Tuple(TupleError::WrongColumnCount)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_many() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = Table::create(&store, allocator, schema())?;
+        table.create_unique_index("by_id", vec![0], vec![])?;
+        table.create_index("by_city", vec![1], vec![2])?;
+        let cities = ["oslo", "lima", "pune"];
+        let rows: Vec<_> = (0..1000).map(|i| row(999 - i, cities[i as usize % 3], Some(i % 7))).collect();
+        let rids = table.insert_many(&rows)?;
+        assert_eq!(table.get(&rids[10])?, rows[10]);
+        assert_eq!(table.lookup("by_id", &[Value::Int(989)])?, vec![rids[10]]);
+        assert_eq!(table.lookup("by_city", &[Value::Text("pune".into())])?.len(), 333);
+
+        // A key taken in the table or twice in the batch fails it all.
+        let duplicate = |key| Some(TableError::UniqueViolation { index: "by_id".into(), key: vec![Value::Int(key)] });
+        assert_eq!(table.insert_many(&[row(1000, "oslo", None), row(5, "lima", None)]).err(), duplicate(5));
+        assert_eq!(table.insert_many(&[row(1001, "oslo", None), row(1001, "lima", None)]).err(), duplicate(1001));
+        let bad = vec![row(1002, "oslo", None), vec![Value::Int(1003)]];
+        assert_eq!(table.insert_many(&bad).err(), Some(TableError::Tuple(TupleError::WrongColumnCount)));
+        assert_eq!(table.scan().count(), 1000);
+        assert!(table.lookup("by_id", &[Value::Int(1000)])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_too_large_writes_nothing() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = Table::create(&store, allocator, schema())?;
+        table.create_unique_index("by_city", vec![1], vec![])?;
+        let long = "x".repeat(3000);
+        assert_eq!(table.insert(&row(1, &long, None)).err(), Some(TableError::KeyTooLarge));
+        assert_eq!(table.insert_many(&[row(2, "oslo", None), row(3, &long, None)]).err(), Some(TableError::KeyTooLarge));
+        assert_eq!(table.scan().count(), 0);
+
+        let rid = table.insert(&row(4, "lima", None))?;
+        assert_eq!(table.update(&rid, &row(4, &long, None)).err(), Some(TableError::KeyTooLarge));
+        assert_eq!(table.get(&rid)?, row(4, "lima", None));
+        assert_eq!(table.lookup("by_city", &[Value::Text("lima".into())])?, vec![rid]);
+        assert_eq!(table.scan().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_indexes_refuse_duplicates() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = Table::create(&store, allocator, schema())?;
+        let first = table.insert(&row(1, "oslo", None))?;
+        table.insert(&row(1, "lima", None))?;
+        let duplicate = |index: &str, key: Vec<Value>| TableError::UniqueViolation { index: index.to_string(), key };
+        assert_eq!(table.create_unique_index("by_id", vec![0], vec![]).err(), Some(duplicate("by_id", vec![Value::Int(1)])));
+        table.create_unique_index("by_city_age", vec![1, 2], vec![])?;
+        assert!(table.indexes()[0].unique());
+
+        let oslo = |age| row(2, "oslo", age);
+        let taken = Some(duplicate("by_city_age", vec![Value::Text("oslo".into()), Value::Int(5)]));
+        // Nulls are never equal, so any number of rows may share a key with one.
+        table.insert(&oslo(None))?;
+        let second = table.insert(&oslo(Some(5)))?;
+        assert_eq!(table.insert(&oslo(Some(5))).err(), taken);
+        assert_eq!(table.update(&first, &oslo(Some(5))).err(), taken);
+        assert_eq!(table.get(&first)?, row(1, "oslo", None));
+        // Rewriting a row in place keeps its own key.
+        table.update(&second, &row(3, "oslo", Some(5)))?;
+        table.update(&first, &oslo(Some(6)))?;
+        assert_eq!(table.scan().count(), 4);
+        assert_eq!(table.lookup("by_city_age", &[Value::Text("oslo".into())])?.len(), 3);
+
+        let meta = table.indexes()[0].meta_page();
+        let mut reopened = Table::open(&store, allocator, schema(), table.root())?;
+        reopened.open_unique_index("by_city_age", vec![1, 2], vec![], meta)?;
+        assert_eq!(reopened.insert(&oslo(Some(6))).err(), Some(duplicate("by_city_age", vec![Value::Text("oslo".into()), Value::Int(6)])));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_indexes() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = Table::create(&store, allocator, schema())?;
+        let cities = ["oslo", "lima", "pune"];
+        let rows = (0..900).map(|i| table.insert(&row(i, cities[i as usize % 3], None)));
+        let mut rids = rows.collect::<Result<Vec<_>, _>>()?;
+        let (paths, collations) = (vec![None], vec![Collation::Binary]);
+        let hash = |table: &mut Table<_>, name: &str, column, unique| {
+            let (paths, collations) = (paths.clone(), collations.clone());
+            table.create_keyed_index(name, vec![column], paths, collations, vec![], unique, IndexKind::Hash).map(drop)
+        };
+        hash(&mut table, "by_city", 1, false)?;
+        hash(&mut table, "by_id", 0, true)?;
+        assert_eq!(table.indexes()[0].kind(), IndexKind::Hash);
+
+        // Each city's rids span several chunks, and come back in the order they were added.
+        let lima = [Value::Text("lima".into())];
+        let expected: Vec<Rid> = rids.iter().skip(1).step_by(3).copied().collect();
+        assert_eq!(table.lookup("by_city", &lima)?, expected);
+        for i in [1, 4, 7, 301, 898] {
+            table.delete(&rids[i])?;
+        }
+        table.update(&rids[0], &row(0, "lima", None))?;
+        rids.push(table.insert(&row(900, "lima", Some(1)))?);
+        let mut found = table.lookup("by_city", &lima)?;
+        found.sort_unstable();
+        let lima_rows = table.scan().filter(|r| r.as_ref().map_or(true, |(_, row)| row[1] == lima[0]));
+        let mut expected = lima_rows.map(|r| r.map(|(rid, _)| rid)).collect::<Result<Vec<_>, _>>()?;
+        expected.sort_unstable();
+        assert_eq!((found.len(), &found), (297, &expected));
+        assert_eq!(table.lookup_first("by_city", &[Value::Text("oslo".into())], 3)?.len(), 3);
+        assert_eq!(table.lookup("by_city", &[Value::Text("rome".into())])?, Vec::<Rid>::new());
+
+        let taken = TableError::UniqueViolation { index: "by_id".to_string(), key: vec![Value::Int(5)] };
+        assert_eq!(table.insert(&row(5, "rome", None)).err(), Some(taken));
+        assert_eq!(table.lookup("by_id", &[Value::Int(5)])?, vec![rids[5]]);
+        // A hash index finds rows by its whole key and nothing else.
+        let range = (Bound::Included(&lima[0]), Bound::Unbounded);
+        assert_eq!(table.lookup_range("by_city", &[], range, 10).err(), Some(TableError::UnsupportedByHash));
+        let (keyed, collated) = (paths.clone(), collations.clone());
+        let error = table.create_keyed_index("with", vec![1], keyed, collated, vec![0], false, IndexKind::Hash);
+        assert_eq!(error.err(), Some(TableError::UnsupportedByHash));
+
+        let meta = table.indexes()[0].meta_page();
+        let mut reopened = Table::open(&store, allocator, schema(), table.root())?;
+        reopened.open_keyed_index("by_city", vec![1], paths, collations, vec![], meta, false, IndexKind::Hash)?;
+        assert_eq!(reopened.lookup("by_city", &lima)?.len(), 297);
+        Ok(())
+    }
+
+    #[test]
+    fn test_covering_index_scans() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = Table::create(&store, allocator, schema())?;
+        let rids: Vec<_> = (0..100).map(|i| table.insert(&row(i, if i % 2 == 0 { "oslo" } else { "lima" }, Some(i)))).collect::<Result<_, _>>()?;
+        let index = table.create_index("by_city", vec![1], vec![2])?;
+        assert!(index.covers(&[2, 1]) && !index.covers(&[0]));
+
+        table.update(&rids[3], &row(3, "lima", None))?;
+        let oslo = table.index_scan("by_city", &[Value::Text("oslo".into())], &[2, 1])?;
+        assert_eq!(oslo.len(), 50);
+        assert_eq!(oslo[1], (rids[2], vec![Value::Int(2), Value::Text("oslo".into())]));
+        let lima = table.index_scan("by_city", &[Value::Text("lima".into())], &[2])?;
+        assert_eq!(lima[1], (rids[3], vec![Value::Null]));
+
+        // Columns outside the index are fetched from the heap.
+        let ids = table.index_scan("by_city", &[Value::Text("lima".into())], &[0, 2])?;
+        assert_eq!(ids[0].1, vec![Value::Int(1), Value::Int(1)]);
+
+        // Reopened indexes keep their included columns.
+        let meta = table.indexes()[0].meta_page();
+        let mut reopened = Table::open(&store, allocator, schema(), table.root())?;
+        reopened.open_index("by_city", vec![1], vec![2], meta)?;
+        assert_eq!(reopened.index_scan("by_city", &[], &[2])?.len(), 100);
+        assert_eq!(reopened.index_scan("by_city", &[], &[5]).err(), Some(TableError::NoSuchColumn(5)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_in_batches() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let ttl = Ttl { column: 2, lifetime: Value::Int(100) };
+        for indexed in [false, true] {
+            let mut table = Table::create(&store, allocator, schema())?;
+            for i in 0..500 {
+                table.insert(&row(i, "oslo", (i % 10 != 0).then_some(i)))?;
+            }
+            if indexed {
+                table.create_index("by_time", vec![2], vec![])?;
+            }
+            // Rows up to time 250 have expired at 350, except the ones with no time.
+            let first = table.expired(&ttl, 350, 100)?;
+            assert_eq!(first.len(), 100);
+            first.iter().try_for_each(|rid| table.delete(rid))?;
+            let second = table.expired(&ttl, 350, 200)?;
+            assert_eq!(second.len(), 125);
+            second.iter().try_for_each(|rid| table.delete(rid))?;
+            assert_eq!(table.expired(&ttl, 350, 200)?, vec![]);
+            let left: Vec<_> = table.scan().map(|r| r.map(|(_, row)| row[0].clone())).collect::<Result<_, _>>()?;
+            let expected: Vec<_> = (0..500).filter(|i| i % 10 == 0 || *i > 250).map(Value::Int).collect();
+            assert_eq!(left.len(), expected.len());
+            assert!(expected.iter().all(|id| left.contains(id)));
+            if indexed {
+                assert_eq!(table.lookup("by_time", &[])?.len(), expected.len());
+            }
+        }
+
+        // A timestamp column lives for an interval, months and all.
+        let schema = Schema::new(vec![Column::new(ColumnType::Int), Column::nullable(ColumnType::TimestampTz)]);
+        let mut table = Table::create(&store, allocator, schema)?;
+        let day = 86_400_000_000;
+        for i in 0..60 {
+            table.insert(&[Value::Int(i), Value::TimestampTz(i * day)])?;
+        }
+        table.create_index("by_time", vec![1], vec![])?;
+        let month = Ttl { column: 1, lifetime: Value::Interval(Interval { months: 1, micros: 0 }) };
+        // 1 January plus 59 days is 1 March, and a month before it is 1 February, day 31.
+        assert_eq!(table.expired(&month, 59 * day, 100)?.len(), 32);
+        let err = table.expired(&Ttl { column: 1, lifetime: Value::Int(100) }, 0, 10).err();
+        assert_eq!(err, Some(TableError::Tuple(TupleError::TypeMismatch { column: 1 })));
+        let err = table.expired(&Ttl { column: 0, lifetime: month.lifetime.clone() }, 0, 10).err();
+        assert_eq!(err, Some(TableError::Tuple(TupleError::TypeMismatch { column: 0 })));
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+//! Partitioned tables: rows split by the value of one column across partitions, each a `Table` of its own.
+//!
+//! Range partitioning gives each partition a lower bound, and a partition holds the rows whose partition
+//! column is at least its bound and below the next one's, compared in index key order. Partitions are added
+//! over time with `add_partition`, typically one per day or month. Hash partitioning fixes the number of
+//! partitions when the table is created and spreads rows over them by the hash of the column.
+//!
+//! `prune` names the partitions a predicate on the partition column could match, so a scan can skip the
+//! others; `scan_where` does exactly that. Whole range partitions can be detached to live on as tables of
+//! their own, or dropped, which frees their pages without reading a row, for cheap retention.
+//!
+//! The table's root page lists its partitions, each with a stable id, its lower bound and its heap.
+use std::ops::Bound;
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::hash::hash_key;
+use crate::heap::Rid;
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::storage::Storage;
+use crate::tuple::{Schema, Value};
+
+use super::{key, stats, Predicate, Table, TableError, TableScan, TableStats};
+
+const MAGIC: u32 = 0x5054_4e44;
+const KIND: usize = 4;
+const COLUMN: usize = 6;
+const COUNT: usize = 8;
+const NEXT_ID: usize = 12;
+const ENTRIES: usize = 16;
+const RANGE: u8 = 0;
+const HASH: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartitionBy {
+    /// By ranges of `column`, which must not be nullable. Partitions are added with `add_partition`.
+    Range(usize),
+    /// By the hash of `column`, over a fixed number of partitions.
+    Hash { column: usize, partitions: usize },
+}
+impl PartitionBy {
+    /// The column rows are partitioned by.
+    pub fn column(&self) -> usize {
+        match self {
+            PartitionBy::Range(column) | PartitionBy::Hash { column, .. } => *column,
+        }
+    }
+}
+
+/// Where a row of a partitioned table lives: the id of its partition and its rid there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PartitionRid {
+    pub partition: u32,
+    pub rid: Rid,
+}
+impl PartitionRid {
+    pub const ENCODED_LEN: usize = 4 + Rid::ENCODED_LEN;
+
+    /// A big-endian encoding, the partition's id followed by the rid's.
+    pub fn to_bytes(&self) -> [u8; PartitionRid::ENCODED_LEN] {
+        let mut bytes = [0u8; PartitionRid::ENCODED_LEN];
+        bytes[..4].copy_from_slice(&self.partition.to_be_bytes());
+        bytes[4..].copy_from_slice(&self.rid.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; PartitionRid::ENCODED_LEN]) -> PartitionRid {
+        let partition = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+        PartitionRid { partition, rid: Rid::from_bytes(bytes[4..].try_into().unwrap()) }
+    }
+}
+
+struct Partition<'store, S: Storage> {
+    id: u32,
+    /// Key encoding of the smallest value the partition holds, for range partitions.
+    lower: Vec<u8>,
+    table: Table<'store, S>,
+}
+
+pub struct PartitionedTable<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    schema: Schema,
+    by: PartitionBy,
+    root: PageId,
+    next_id: u32,
+    /// Range partitions are kept in order of their lower bounds; hash partitions in hash order.
+    partitions: Vec<Partition<'store, S>>,
+}
+impl<'store, S: Storage> PartitionedTable<'store, S> {
+    /// Create a table partitioned as `by` says. A range partitioned table starts with no partitions.
+    pub fn create(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        schema: Schema,
+        by: PartitionBy,
+    ) -> Result<PartitionedTable<'store, S>, TableError> {
+        let column = by.column();
+        if column >= schema.len() {
+            return Err(TableError::NoSuchColumn(column))
+        }
+        match by {
+            PartitionBy::Range(_) if schema.column(column).nullable => return Err(TableError::NullableKey(column)),
+            PartitionBy::Hash { partitions: 0, .. } => return Err(TableError::InvalidPartition),
+            _ => {}
+        }
+        let root = allocator.allocate(store)?.id();
+        let mut table = PartitionedTable { store, allocator, schema, by, root, next_id: 0, partitions: Vec::new() };
+        if let PartitionBy::Hash { partitions, .. } = by {
+            for _ in 0..partitions {
+                let partition = Table::create(store, allocator, table.schema.clone())?;
+                table.partitions.push(Partition { id: table.next_id, lower: Vec::new(), table: partition });
+                table.next_id += 1;
+            }
+        }
+        table.write_root()?;
+        Ok(table)
+    }
+
+    /// Open the table whose root page is `root`.
+    pub fn open(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        schema: Schema,
+        root: PageId,
+    ) -> Result<PartitionedTable<'store, S>, TableError> {
+        let page = store.pin_page(&root)?;
+        let buf = page.try_read()?;
+        if read_u32(&*buf, 0) != MAGIC {
+            return Err(TableError::Page(PageError::WrongPageType))
+        }
+        let column = read_u16(&*buf, COLUMN) as usize;
+        let count = read_u16(&*buf, COUNT) as usize;
+        let by = match buf[KIND] {
+            RANGE => PartitionBy::Range(column),
+            HASH => PartitionBy::Hash { column, partitions: count },
+            _ => return Err(TableError::Page(PageError::WrongPageType)),
+        };
+        let mut partitions = Vec::with_capacity(count);
+        let mut at = ENTRIES;
+        for _ in 0..count {
+            let id = read_u32(&*buf, at);
+            let heap = PageId::new(read_u64(&*buf, at + 4) as usize);
+            let len = read_u16(&*buf, at + 12) as usize;
+            let lower = buf[at + 14..at + 14 + len].to_vec();
+            at += 14 + len;
+            partitions.push(Partition { id, lower, table: Table::open(store, allocator, schema.clone(), heap)? });
+        }
+        let next_id = read_u32(&*buf, NEXT_ID);
+        drop(buf);
+        Ok(PartitionedTable { store, allocator, schema, by, root, next_id, partitions })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn partition_by(&self) -> PartitionBy {
+        self.by
+    }
+
+    /// The page to pass to `open` to find this table again.
+    pub fn root(&self) -> PageId {
+        self.root
+    }
+
+    /// Ids of the partitions, range partitions in order of their bounds.
+    pub fn partitions(&self) -> Vec<u32> {
+        self.partitions.iter().map(|p| p.id).collect()
+    }
+
+    /// The table holding partition `id`.
+    pub fn partition(&self, id: u32) -> Option<&Table<'store, S>> {
+        self.partitions.iter().find(|p| p.id == id).map(|p| &p.table)
+    }
+
+    /// Add a range partition for the rows from `lower` up to the next partition's bound. The bound must be
+    /// above every existing one, so no rows ever move between partitions.
+    pub fn add_partition(&mut self, lower: Value) -> Result<u32, TableError> {
+        let PartitionBy::Range(column) = self.by else { return Err(TableError::InvalidPartition) };
+        Schema::new(vec![self.schema.column(column)]).check(std::slice::from_ref(&lower))?;
+        let lower = key::encode(&[lower]);
+        if self.partitions.last().is_some_and(|last| last.lower >= lower) {
+            return Err(TableError::InvalidPartition)
+        }
+        let table = Table::create(self.store, self.allocator, self.schema.clone())?;
+        let id = self.next_id;
+        self.partitions.push(Partition { id, lower, table });
+        self.next_id += 1;
+        if let Err(e) = self.write_root() {
+            self.next_id -= 1;
+            self.partitions.pop().unwrap().table.free()?;
+            return Err(e)
+        }
+        Ok(id)
+    }
+
+    /// Take partition `id` out of the table, handing it back as a table of its own. Its rows stay where
+    /// they are, under its root page.
+    pub fn detach_partition(&mut self, id: u32) -> Result<Table<'store, S>, TableError> {
+        if matches!(self.by, PartitionBy::Hash { .. }) {
+            return Err(TableError::InvalidPartition)
+        }
+        let index = self.position(id)?;
+        let partition = self.partitions.remove(index);
+        self.write_root()?;
+        Ok(partition.table)
+    }
+
+    /// Remove partition `id` and free its pages along with every row in it.
+    pub fn drop_partition(&mut self, id: u32) -> Result<(), TableError> {
+        self.detach_partition(id)?.free()
+    }
+
+    pub fn insert(&mut self, row: &[Value]) -> Result<PartitionRid, TableError> {
+        self.schema.check(row)?;
+        let index = self.partition_for(&row[self.by.column()])?;
+        let partition = &mut self.partitions[index];
+        Ok(PartitionRid { partition: partition.id, rid: partition.table.insert(row)? })
+    }
+
+    pub fn get(&self, rid: &PartitionRid) -> Result<Vec<Value>, TableError> {
+        self.partitions[self.position(rid.partition)?].table.get(&rid.rid)
+    }
+
+    /// Replace the row at `rid`, returning where it lives now: a row whose partition column changes
+    /// moves to the partition that takes the new value.
+    pub fn update(&mut self, rid: &PartitionRid, row: &[Value]) -> Result<PartitionRid, TableError> {
+        self.schema.check(row)?;
+        let from = self.position(rid.partition)?;
+        let to = self.partition_for(&row[self.by.column()])?;
+        if from == to {
+            self.partitions[from].table.update(&rid.rid, row)?;
+            return Ok(*rid)
+        }
+        self.partitions[from].table.delete(&rid.rid)?;
+        let partition = &mut self.partitions[to];
+        Ok(PartitionRid { partition: partition.id, rid: partition.table.insert(row)? })
+    }
+
+    pub fn delete(&mut self, rid: &PartitionRid) -> Result<(), TableError> {
+        let index = self.position(rid.partition)?;
+        self.partitions[index].table.delete(&rid.rid)
+    }
+
+    /// Free the pages of every partition and the root page.
+    pub fn free(self) -> Result<(), TableError> {
+        for partition in self.partitions {
+            partition.table.free()?;
+        }
+        Ok(self.allocator.free(self.store, self.root)?)
+    }
+
+    /// Gather statistics over every row, as `Table::analyze` does.
+    pub fn analyze(&self, sample_size: usize) -> Result<TableStats, TableError> {
+        stats::analyze(&self.schema, self.scan().map(|row| row.map(|(_, row)| row)), sample_size)
+    }
+
+    /// Every row, partition by partition.
+    pub fn scan(&self) -> PartitionedScan<'_, 'store, S> {
+        self.scan_partitions((0..self.partitions.len()).collect(), None)
+    }
+
+    /// The rows whose `column` matches `predicate`, reading only the partitions that may hold them.
+    pub fn scan_where(&self, column: usize, predicate: Predicate) -> Result<PartitionedScan<'_, 'store, S>, TableError> {
+        if column >= self.schema.len() {
+            return Err(TableError::NoSuchColumn(column))
+        }
+        let ids = self.prune(column, &predicate);
+        let indexes = ids.iter().map(|id| self.position(*id)).collect::<Result<_, _>>()?;
+        Ok(self.scan_partitions(indexes, Some((column, predicate))))
+    }
+
+    /// Ids of the partitions that may hold rows whose `column` matches `predicate`. Predicates on other
+    /// columns rule nothing out.
+    pub fn prune(&self, column: usize, predicate: &Predicate) -> Vec<u32> {
+        let all = || self.partitions.iter().map(|p| p.id).collect();
+        if column != self.by.column() {
+            return all()
+        }
+        let mut indexes: Vec<usize> = match (self.by, predicate) {
+            (_, Predicate::Eq(value)) => self.partition_for(value).into_iter().collect(),
+            (_, Predicate::In(values)) => values.iter().filter_map(|v| self.partition_for(v).ok()).collect(),
+            (PartitionBy::Hash { .. }, Predicate::Range(..)) => return all(),
+            (PartitionBy::Range(_), Predicate::Range(low, high)) => {
+                let bound = |b: &Bound<Value>| b.as_ref().map(|v| key::encode(std::slice::from_ref(v)));
+                let (low, high) = (bound(low), bound(high));
+                (0..self.partitions.len())
+                    .filter(|i| {
+                        let lower = &self.partitions[*i].lower;
+                        let upper = self.partitions.get(i + 1).map(|p| &p.lower);
+                        let below_high = match &high {
+                            Bound::Included(high) => lower <= high,
+                            Bound::Excluded(high) => lower < high,
+                            Bound::Unbounded => true,
+                        };
+                        let above_low = match (&low, upper) {
+                            (Bound::Included(low) | Bound::Excluded(low), Some(upper)) => upper > low,
+                            _ => true,
+                        };
+                        below_high && above_low
+                    })
+                    .collect()
+            }
+        };
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes.into_iter().map(|i| self.partitions[i].id).collect()
+    }
+
+    fn scan_partitions(&self, indexes: Vec<usize>, filter: Option<(usize, Predicate)>) -> PartitionedScan<'_, 'store, S> {
+        PartitionedScan { table: self, indexes: indexes.into_iter(), current: None, filter }
+    }
+
+    fn position(&self, id: u32) -> Result<usize, TableError> {
+        self.partitions.iter().position(|p| p.id == id).ok_or(TableError::NoPartition)
+    }
+
+    /// Index into `partitions` of the partition that takes rows whose partition column is `value`.
+    fn partition_for(&self, value: &Value) -> Result<usize, TableError> {
+        let key = key::encode(std::slice::from_ref(value));
+        match self.by {
+            PartitionBy::Hash { .. } => Ok((hash_key(&key) % self.partitions.len() as u64) as usize),
+            PartitionBy::Range(_) if value.is_null() => Err(TableError::NoPartition),
+            PartitionBy::Range(_) => {
+                let after = self.partitions.partition_point(|p| p.lower <= key);
+                after.checked_sub(1).ok_or(TableError::NoPartition)
+            }
+        }
+    }
+
+    fn write_root(&self) -> Result<(), TableError> {
+        let len = ENTRIES + self.partitions.iter().map(|p| 14 + p.lower.len()).sum::<usize>();
+        if len > PAGE_SIZE {
+            return Err(TableError::TooManyPartitions)
+        }
+        let page = self.store.pin_page(&self.root)?;
+        let mut buf = page.try_write()?;
+        write_u32(&mut *buf, 0, MAGIC);
+        buf[KIND] = match self.by {
+            PartitionBy::Range(_) => RANGE,
+            PartitionBy::Hash { .. } => HASH,
+        };
+        write_u16(&mut *buf, COLUMN, self.by.column() as u16);
+        write_u16(&mut *buf, COUNT, self.partitions.len() as u16);
+        write_u32(&mut *buf, NEXT_ID, self.next_id);
+        let mut at = ENTRIES;
+        for partition in &self.partitions {
+            write_u32(&mut *buf, at, partition.id);
+            write_u64(&mut *buf, at + 4, partition.table.root().offset() as u64);
+            write_u16(&mut *buf, at + 12, partition.lower.len() as u16);
+            buf[at + 14..at + 14 + partition.lower.len()].copy_from_slice(&partition.lower);
+            at += 14 + partition.lower.len();
+        }
+        Ok(())
+    }
+}
+
+/// Scans the chosen partitions one after another, keeping the rows that pass the filter if there is one.
+pub struct PartitionedScan<'table, 'store, S: Storage> {
+    table: &'table PartitionedTable<'store, S>,
+    indexes: std::vec::IntoIter<usize>,
+    current: Option<(u32, TableScan<'table, 'store, S>)>,
+    filter: Option<(usize, Predicate)>,
+}
+impl<S: Storage> Iterator for PartitionedScan<'_, '_, S> {
+    type Item = Result<(PartitionRid, Vec<Value>), TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((partition, scan)) = &mut self.current {
+                match scan.next() {
+                    Some(Ok((rid, row))) => {
+                        if self.filter.as_ref().is_none_or(|(column, predicate)| predicate.matches(&row[*column])) {
+                            return Some(Ok((PartitionRid { partition: *partition, rid }, row)))
+                        }
+                        continue
+                    }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => {}
+                }
+            }
+            let partition = &self.table.partitions[self.indexes.next()?];
+            self.current = Some((partition.id, partition.table.scan()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+    use crate::table::{Predicate, TableError};
+    use crate::tuple::{Column, ColumnType, Schema, Value};
+
+    use super::{PartitionBy, PartitionRid, PartitionedTable};
+
+    fn schema() -> Schema {
+        Schema::new(vec![Column::new(ColumnType::Int), Column::new(ColumnType::Text)])
+    }
+
+    fn row(day: i64) -> Vec<Value> {
+        vec![Value::Int(day), Value::Text(format!("event on day {day}"))]
+    }
+
+    fn days(rows: Vec<Result<(PartitionRid, Vec<Value>), TableError>>) -> Vec<Value> {
+        rows.into_iter().map(|r| r.unwrap().1[0].clone()).collect()
+    }
+
+    #[test]
+    fn test_range_partitions_prune_and_drop() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = PartitionedTable::create(&store, allocator, schema(), PartitionBy::Range(0))?;
+        assert_eq!(table.insert(&row(5)).err(), Some(TableError::NoPartition));
+        let weeks: Vec<u32> = (0..4).map(|w| table.add_partition(Value::Int(w * 7))).collect::<Result<_, _>>()?;
+        assert_eq!(table.add_partition(Value::Int(14)).err(), Some(TableError::InvalidPartition));
+        let mut rids = Vec::new();
+        for day in 0..28 {
+            for _ in 0..50 {
+                rids.push(table.insert(&row(day))?);
+            }
+        }
+
+        let predicate = Predicate::Range(Bound::Included(Value::Int(6)), Bound::Excluded(Value::Int(8)));
+        assert_eq!(table.prune(0, &predicate), vec![weeks[0], weeks[1]]);
+        assert_eq!(table.prune(0, &Predicate::In(vec![Value::Int(22), Value::Int(100)])), vec![weeks[3]]);
+        assert_eq!(table.prune(1, &predicate), weeks);
+        let hits = days(table.scan_where(0, predicate)?.collect());
+        assert_eq!(hits.len(), 100);
+        assert!(hits.iter().all(|d| *d == Value::Int(6) || *d == Value::Int(7)));
+
+        // Moving a row's day moves it to the matching partition.
+        let moved = table.update(&rids[0], &row(20))?;
+        assert_eq!(moved.partition, weeks[2]);
+        assert_eq!(table.get(&moved)?, row(20));
+
+        let mut table = PartitionedTable::open(&store, allocator, schema(), table.root())?;
+        let free_before = allocator.free_pages(&store)?.len();
+        table.drop_partition(weeks[0])?;
+        assert!(allocator.free_pages(&store)?.len() > free_before + 1);
+        assert_eq!(table.get(&rids[1]).err(), Some(TableError::NoPartition));
+        assert_eq!(table.insert(&row(3)).err(), Some(TableError::NoPartition));
+        assert_eq!(table.scan().count(), 21 * 50 + 1);
+
+        let detached = table.detach_partition(weeks[3])?;
+        assert_eq!(detached.scan().count(), 7 * 50);
+        let table = PartitionedTable::open(&store, allocator, schema(), table.root())?;
+        assert_eq!(table.partitions(), vec![weeks[1], weeks[2]]);
+        assert_eq!(table.scan().count(), 14 * 50 + 1);
+        assert_eq!(table.analyze(100)?.row_count, 14 * 50 + 1);
+        let free_before = allocator.free_pages(&store)?.len();
+        table.free()?;
+        assert!(allocator.free_pages(&store)?.len() > free_before + 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_partitions() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let by = PartitionBy::Hash { column: 1, partitions: 4 };
+        let mut table = PartitionedTable::create(&store, allocator, schema(), by)?;
+        for day in 0..400 {
+            table.insert(&row(day))?;
+        }
+        let table = PartitionedTable::open(&store, allocator, schema(), table.root())?;
+        assert_eq!(table.partition_by(), by);
+        let sizes: Vec<usize> = table.partitions().iter().map(|id| table.partition(*id).unwrap().scan().count()).collect();
+        assert_eq!(sizes.iter().sum::<usize>(), 400);
+        assert!(sizes.iter().all(|size| *size > 50), "{sizes:?}");
+
+        let wanted = Predicate::Eq(Value::Text("event on day 77".into()));
+        assert_eq!(table.prune(1, &wanted).len(), 1);
+        assert_eq!(days(table.scan_where(1, wanted)?.collect()), vec![Value::Int(77)]);
+        assert_eq!(table.prune(0, &Predicate::Eq(Value::Int(77))).len(), 4);
+        Ok(())
+    }
+}
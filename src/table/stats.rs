@@ -0,0 +1,290 @@
+//! Table statistics for cardinality estimation: row counts, distinct value estimates and equi-depth
+//! histograms.
+//!
+//! `analyze` reads every row once, counting rows and nulls exactly and keeping a uniform reservoir sample
+//! of the rest. Histograms and distinct counts come from the sample, so their cost is bounded by its size
+//! rather than the table's. Distinct counts are scaled up from the sample with Haas and Stokes'
+//! first-order jackknife estimator, which tells a column of unique values from one of a few repeated
+//! ones by how many values were seen exactly once.
+use std::ops::Bound;
+
+use crate::tuple::{ColumnType, Schema, Value};
+use crate::varint;
+
+use super::{key, TableError};
+
+/// Buckets per histogram, at most.
+const HISTOGRAM_BUCKETS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    pub row_count: u64,
+    /// Statistics for each column, in schema order.
+    pub columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub null_count: u64,
+    /// Estimated number of distinct non-null values.
+    pub distinct: u64,
+    pub histogram: Histogram,
+}
+
+/// An equi-depth histogram over a column's non-null values: each bucket between consecutive bounds holds
+/// about the same number of values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: Vec<Value>,
+}
+
+impl TableStats {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        varint::write_u64(&mut bytes, self.row_count);
+        varint::write_u64(&mut bytes, self.columns.len() as u64);
+        for column in &self.columns {
+            varint::write_u64(&mut bytes, column.null_count);
+            varint::write_u64(&mut bytes, column.distinct);
+            varint::write_u64(&mut bytes, column.histogram.bounds.len() as u64);
+            for bound in &column.histogram.bounds {
+                varint::write_prefixed(&mut bytes, &key::encode(std::slice::from_ref(bound)));
+            }
+        }
+        bytes
+    }
+
+    /// Decode the output of `to_bytes` for a table of `schema`. Returns `None` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8], schema: &Schema) -> Option<TableStats> {
+        let mut at = 0;
+        let next = |at: &mut usize| -> Option<u64> {
+            let (v, len) = varint::read_u64(&bytes[*at..])?;
+            *at += len;
+            Some(v)
+        };
+        let row_count = next(&mut at)?;
+        if next(&mut at)? != schema.len() as u64 {
+            return None
+        }
+        let mut columns = Vec::with_capacity(schema.len());
+        for column in 0..schema.len() {
+            let (null_count, distinct, bound_count) = (next(&mut at)?, next(&mut at)?, next(&mut at)?);
+            let mut bounds = Vec::new();
+            for _ in 0..bound_count {
+                let (encoded, len) = varint::read_prefixed(&bytes[at..])?;
+                let (mut value, used) = key::decode(encoded, &[schema.column_type(column)])?;
+                if used != encoded.len() {
+                    return None
+                }
+                bounds.push(value.pop()?);
+                at += len;
+            }
+            columns.push(ColumnStats { null_count, distinct, histogram: Histogram { bounds } });
+        }
+        (at == bytes.len()).then_some(TableStats { row_count, columns })
+    }
+}
+
+impl ColumnStats {
+    /// Estimated fraction of a table of `row_count` rows whose value equals `value`.
+    pub fn eq_selectivity(&self, value: &Value, row_count: u64) -> f64 {
+        if row_count == 0 {
+            return 0.0
+        }
+        let non_null = 1.0 - self.null_count as f64 / row_count as f64;
+        if value.is_null() || self.distinct == 0 || !self.histogram.may_contain(value) {
+            return 0.0
+        }
+        non_null / self.distinct as f64
+    }
+
+    /// Estimated fraction of a table of `row_count` rows whose value lies between `low` and `high`.
+    pub fn range_selectivity(&self, low: Bound<&Value>, high: Bound<&Value>, row_count: u64) -> f64 {
+        if row_count == 0 {
+            return 0.0
+        }
+        let non_null = 1.0 - self.null_count as f64 / row_count as f64;
+        let low = match low {
+            Bound::Included(v) | Bound::Excluded(v) => self.histogram.fraction_below(v),
+            Bound::Unbounded => 0.0,
+        };
+        let high = match high {
+            Bound::Included(v) | Bound::Excluded(v) => self.histogram.fraction_below(v),
+            Bound::Unbounded => 1.0,
+        };
+        (high - low).max(0.0) * non_null
+    }
+}
+
+impl Histogram {
+    /// Bucket boundaries in ascending order: bucket `i` runs from `bounds()[i]` to `bounds()[i + 1]`.
+    /// Empty if the column has no non-null values.
+    pub fn bounds(&self) -> &[Value] {
+        &self.bounds
+    }
+
+    /// Whether `value` is within the range of values the histogram was built from.
+    fn may_contain(&self, value: &Value) -> bool {
+        let (Some(first), Some(last)) = (self.bounds.first(), self.bounds.last()) else { return false };
+        let encoded = key::encode(std::slice::from_ref(value));
+        key::encode(std::slice::from_ref(first)) <= encoded && encoded <= key::encode(std::slice::from_ref(last))
+    }
+
+    /// Estimated fraction of the non-null values that sort before `value`. Numbers, dates, times and
+    /// timestamps are interpolated within their bucket; other values are assumed to sit halfway through it.
+    pub fn fraction_below(&self, value: &Value) -> f64 {
+        let buckets = self.bounds.len().saturating_sub(1);
+        let encoded = key::encode(std::slice::from_ref(value));
+        let keys: Vec<_> = self.bounds.iter().map(|b| key::encode(std::slice::from_ref(b))).collect();
+        match keys.iter().position(|k| *k >= encoded) {
+            _ if buckets == 0 => match keys.first() {
+                Some(k) if *k < encoded => 1.0,
+                _ => 0.0,
+            },
+            Some(0) => 0.0,
+            None => 1.0,
+            Some(i) => {
+                let within = match (scale(&self.bounds[i - 1]), scale(value), scale(&self.bounds[i])) {
+                    (Some((ty, lo)), Some((v_ty, v)), Some((hi_ty, hi))) if ty == v_ty && ty == hi_ty && hi > lo => {
+                        (v - lo) / (hi - lo)
+                    }
+                    _ => 0.5,
+                };
+                (i - 1) as f64 / buckets as f64 + within.clamp(0.0, 1.0) / buckets as f64
+            }
+        }
+    }
+}
+
+/// The type of a value that can be interpolated between others of its type, and where it sits on their
+/// scale.
+fn scale(value: &Value) -> Option<(ColumnType, f64)> {
+    let position = match value {
+        Value::Int(v) | Value::Time(v) | Value::Timestamp(v) | Value::TimestampTz(v) => *v as f64,
+        Value::Float(v) => *v,
+        Value::Date(v) => *v as f64,
+        Value::Decimal(v) => v.to_f64(),
+        _ => return None,
+    };
+    Some((value.column_type()?, position))
+}
+
+/// Build statistics for `schema` from every row of a table, keeping a sample of at most `sample_size`
+/// rows for histograms and distinct counts.
+pub(crate) fn analyze(
+    schema: &Schema,
+    rows: impl Iterator<Item = Result<Vec<Value>, TableError>>,
+    sample_size: usize,
+) -> Result<TableStats, TableError> {
+    let mut row_count = 0u64;
+    let mut null_counts = vec![0u64; schema.len()];
+    let mut sample: Vec<Vec<Value>> = Vec::new();
+    let mut random = 0x2545_f491_4f6c_dd1du64;
+    for row in rows {
+        let row = row?;
+        for (count, value) in null_counts.iter_mut().zip(&row) {
+            *count += value.is_null() as u64;
+        }
+        row_count += 1;
+        // Reservoir sampling: row `n` replaces a random sample entry with probability `sample_size / n`.
+        if sample.len() < sample_size {
+            sample.push(row);
+        } else {
+            random ^= random << 13;
+            random ^= random >> 7;
+            random ^= random << 17;
+            if let Some(slot) = sample.get_mut((random % row_count) as usize) {
+                *slot = row;
+            }
+        }
+    }
+
+    let columns = (0..schema.len()).map(|column| {
+        let mut values: Vec<(Vec<u8>, &Value)> = sample.iter()
+            .map(|row| &row[column])
+            .filter(|v| !v.is_null())
+            .map(|v| (key::encode(std::slice::from_ref(v)), v))
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        let non_null = row_count - null_counts[column];
+        ColumnStats {
+            null_count: null_counts[column],
+            distinct: estimate_distinct(&values, non_null),
+            histogram: histogram(&values),
+        }
+    });
+    Ok(TableStats { row_count, columns: columns.collect() })
+}
+
+/// Scale the distinct values among the sorted `sample` up to a column of `total` non-null values.
+fn estimate_distinct(sample: &[(Vec<u8>, &Value)], total: u64) -> u64 {
+    let mut distinct = 0u64;
+    let mut singletons = 0u64;
+    let mut i = 0;
+    while i < sample.len() {
+        let run = sample[i..].iter().take_while(|(k, _)| *k == sample[i].0).count();
+        distinct += 1;
+        singletons += (run == 1) as u64;
+        i += run;
+    }
+    if sample.len() as u64 >= total {
+        return distinct
+    }
+    // n d / (n - f1 + f1 n / N), for a sample of n values with d distinct, f1 of them seen once.
+    let (n, d, f1) = (sample.len() as f64, distinct as f64, singletons as f64);
+    ((n * d / (n - f1 + f1 * n / total as f64)).round() as u64).clamp(distinct, total)
+}
+
+fn histogram(sorted: &[(Vec<u8>, &Value)]) -> Histogram {
+    if sorted.is_empty() {
+        return Histogram { bounds: Vec::new() }
+    }
+    let buckets = HISTOGRAM_BUCKETS.min(sorted.len());
+    let bounds = (0..=buckets).map(|i| sorted[i * (sorted.len() - 1) / buckets].1.clone()).collect();
+    Histogram { bounds }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+    use crate::table::{Table, TableError};
+    use crate::tuple::{Column, ColumnType, Schema, Value};
+
+    use super::TableStats;
+
+    #[test]
+    fn test_analyze_estimates() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let schema = Schema::new(vec![Column::new(ColumnType::Int), Column::nullable(ColumnType::Text)]);
+        let mut table = Table::create(&store, allocator, schema.clone())?;
+        for i in 0..20_000 {
+            let city = if i % 4 == 0 { Value::Null } else { Value::Text(format!("city-{}", i % 30)) };
+            table.insert(&[Value::Int(i), city])?;
+        }
+
+        let stats = table.analyze(2000)?;
+        assert_eq!(stats.row_count, 20_000);
+        let (ids, cities) = (&stats.columns[0], &stats.columns[1]);
+        assert_eq!((ids.null_count, cities.null_count), (0, 5000));
+        assert!((10_000..=20_000).contains(&ids.distinct), "{}", ids.distinct);
+        assert_eq!(cities.distinct, 30);
+
+        let below = ids.range_selectivity(Bound::Unbounded, Bound::Excluded(&Value::Int(5000)), stats.row_count);
+        assert!((below - 0.25).abs() < 0.05, "{below}");
+        let between = ids.range_selectivity(Bound::Included(&Value::Int(-50)), Bound::Included(&Value::Int(30_000)), stats.row_count);
+        assert!((between - 1.0).abs() < 1e-9);
+        let eq = cities.eq_selectivity(&Value::Text("city-7".into()), stats.row_count);
+        assert!((eq - 0.75 / 30.0).abs() < 1e-9);
+        assert_eq!(cities.eq_selectivity(&Value::Text("zzz".into()), stats.row_count), 0.0);
+
+        let bytes = stats.to_bytes();
+        assert_eq!(TableStats::from_bytes(&bytes, &schema), Some(stats));
+        assert_eq!(TableStats::from_bytes(&bytes[..bytes.len() - 1], &schema), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+//! Append-only tables for rows arriving in timestamp order, such as metrics and events.
+//!
+//! Rows are appended to the last data page until it is full and then to a new one, so there is no free
+//! space map to consult or keep up to date and pages fill completely. A chain of directory pages lists
+//! every data page with the smallest and largest timestamp on it and its row count, and is read into
+//! memory when the table is opened. A time-range scan reads only the pages whose timestamps overlap the
+//! range. Rows appended out of order are fine: they only widen their page's range, making pruning less
+//! effective. Scans return rows in append order, which is timestamp order when rows arrive in order.
+//!
+//! Expiry works a page at a time too: a page is freed once its newest row has expired, without reading
+//! it, and rows on pages that still hold live ones are kept until the rest catch up.
+use std::ops::{Bound, RangeBounds};
+
+use crate::allocator::PageAllocator;
+use crate::bytes::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64};
+use crate::heap::HeapError;
+use crate::page_store::{PageError, PageId, PageStore, PAGE_SIZE};
+use crate::slotted_page::{SlotId, SlottedPage};
+use crate::storage::Storage;
+use crate::tuple::{ColumnType, Row, Schema, TupleError, Value};
+
+use super::{stats, TableError, TableStats};
+
+const MAGIC: u32 = 0x5453_4449;
+const NEXT: usize = 8;
+const COUNT: usize = 16;
+const ENTRIES: usize = 24;
+/// Data page, smallest and largest timestamp, row count.
+const ENTRY_LEN: usize = 32;
+const ENTRIES_PER_PAGE: usize = (PAGE_SIZE - ENTRIES) / ENTRY_LEN;
+const NO_PAGE: u64 = u64::MAX;
+
+/// What the directory records about one data page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PageSummary {
+    page: PageId,
+    min: i64,
+    max: i64,
+    rows: u64,
+}
+
+pub struct TimeSeriesTable<'store, S: Storage> {
+    store: &'store PageStore<S>,
+    allocator: PageAllocator,
+    schema: Schema,
+    timestamp: usize,
+    directory: Vec<PageId>,
+    pages: Vec<PageSummary>,
+}
+impl<'store, S: Storage> TimeSeriesTable<'store, S> {
+    /// Create a table whose rows are ordered by the `timestamp` column, which must be a non-nullable
+    /// integer, such as microseconds since the epoch.
+    pub fn create(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        schema: Schema,
+        timestamp: usize,
+    ) -> Result<TimeSeriesTable<'store, S>, TableError> {
+        check_timestamp(&schema, timestamp)?;
+        let root = new_directory_page(store, &allocator)?;
+        Ok(TimeSeriesTable { store, allocator, schema, timestamp, directory: vec![root], pages: Vec::new() })
+    }
+
+    /// Open the table whose directory starts at `root`.
+    pub fn open(
+        store: &'store PageStore<S>,
+        allocator: PageAllocator,
+        schema: Schema,
+        timestamp: usize,
+        root: PageId,
+    ) -> Result<TimeSeriesTable<'store, S>, TableError> {
+        check_timestamp(&schema, timestamp)?;
+        let mut table =
+            TimeSeriesTable { store, allocator, schema, timestamp, directory: Vec::new(), pages: Vec::new() };
+        let mut next = root.offset() as u64;
+        while next != NO_PAGE {
+            let id = PageId::new(next as usize);
+            let page = store.pin_page(&id)?;
+            let buf = page.try_read()?;
+            if read_u32(&*buf, 0) != MAGIC {
+                return Err(TableError::Page(PageError::WrongPageType))
+            }
+            for i in 0..read_u16(&*buf, COUNT) as usize {
+                let at = ENTRIES + i * ENTRY_LEN;
+                table.pages.push(PageSummary {
+                    page: PageId::new(read_u64(&*buf, at) as usize),
+                    min: read_u64(&*buf, at + 8) as i64,
+                    max: read_u64(&*buf, at + 16) as i64,
+                    rows: read_u64(&*buf, at + 24),
+                });
+            }
+            table.directory.push(id);
+            next = read_u64(&*buf, NEXT);
+        }
+        Ok(table)
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// The timestamp column.
+    pub fn timestamp(&self) -> usize {
+        self.timestamp
+    }
+
+    /// The page to pass to `open` to find this table again.
+    pub fn root(&self) -> PageId {
+        self.directory[0]
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Number of rows in the table.
+    pub fn len(&self) -> u64 {
+        self.pages.iter().map(|p| p.rows).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// The smallest and largest timestamps in the table, if it has any rows.
+    pub fn time_range(&self) -> Option<(i64, i64)> {
+        let min = self.pages.iter().map(|p| p.min).min()?;
+        Some((min, self.pages.iter().map(|p| p.max).max()?))
+    }
+
+    pub fn append(&mut self, row: &[Value]) -> Result<(), TableError> {
+        let record = self.schema.encode(row)?;
+        let Value::Int(timestamp) = row[self.timestamp] else { unreachable!("encode checked the timestamp's type") };
+        let appended = match self.pages.last() {
+            Some(last) => {
+                let page = self.store.pin_page(&last.page)?;
+                let mut data = SlottedPage::new(page.try_write()?);
+                data.insert(&record).is_ok()
+            }
+            None => false,
+        };
+        if !appended {
+            self.add_page(&record)?;
+        }
+        let last = self.pages.len() - 1;
+        let summary = &mut self.pages[last];
+        if summary.rows == 0 {
+            (summary.min, summary.max) = (timestamp, timestamp);
+        }
+        summary.min = summary.min.min(timestamp);
+        summary.max = summary.max.max(timestamp);
+        summary.rows += 1;
+        self.write_summary(last)
+    }
+
+    /// Every row, in append order.
+    pub fn scan(&self) -> TimeSeriesScan<'_, 'store, S> {
+        self.scan_range(..)
+    }
+
+    /// The rows whose timestamp is in `range`, in append order, reading only the pages that may hold them.
+    pub fn scan_range(&self, range: impl RangeBounds<i64>) -> TimeSeriesScan<'_, 'store, S> {
+        let (start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+        TimeSeriesScan { table: self, pages: self.overlapping(start, end), start, end, rows: Vec::new().into_iter() }
+    }
+
+    /// Drop the data pages whose every row has lived longer than `lifetime` by `now`, in the timestamp
+    /// column's unit, returning the number of rows dropped.
+    pub fn expire(&mut self, lifetime: i64, now: i64) -> Result<u64, TableError> {
+        let cutoff = now.saturating_sub(lifetime);
+        let (expired, live): (Vec<_>, Vec<_>) = self.pages.iter().partition(|p| p.max <= cutoff);
+        if expired.is_empty() {
+            return Ok(0)
+        }
+        self.pages = live;
+        self.rewrite_directory()?;
+        for summary in &expired {
+            self.allocator.free(self.store, summary.page)?;
+        }
+        Ok(expired.iter().map(|p| p.rows).sum())
+    }
+
+    /// Gather statistics over every row, as `Table::analyze` does.
+    pub fn analyze(&self, sample_size: usize) -> Result<TableStats, TableError> {
+        stats::analyze(&self.schema, self.scan(), sample_size)
+    }
+
+    /// Indexes into `pages` of the pages with a timestamp between `start` and `end`, in order.
+    fn overlapping(&self, start: Bound<i64>, end: Bound<i64>) -> std::vec::IntoIter<usize> {
+        let after_start = |max: i64| match start {
+            Bound::Included(s) => max >= s,
+            Bound::Excluded(s) => max > s,
+            Bound::Unbounded => true,
+        };
+        let before_end = |min: i64| match end {
+            Bound::Included(e) => min <= e,
+            Bound::Excluded(e) => min < e,
+            Bound::Unbounded => true,
+        };
+        let pages: Vec<usize> = (0..self.pages.len())
+            .filter(|i| after_start(self.pages[*i].max) && before_end(self.pages[*i].min))
+            .collect();
+        pages.into_iter()
+    }
+
+    /// Start a new data page holding `record`, listing it in the directory.
+    fn add_page(&mut self, record: &[u8]) -> Result<(), TableError> {
+        let page = self.allocator.allocate(self.store)?;
+        let mut data = SlottedPage::init(page.try_write()?, 0);
+        data.insert(record).map_err(HeapError::from)?;
+        drop(data);
+        if self.pages.len() == self.directory.len() * ENTRIES_PER_PAGE {
+            let directory = new_directory_page(self.store, &self.allocator)?;
+            let last = self.store.pin_page(self.directory.last().unwrap())?;
+            write_u64(&mut *last.try_write()?, NEXT, directory.offset() as u64);
+            self.directory.push(directory);
+        }
+        self.pages.push(PageSummary { page: page.id(), min: 0, max: 0, rows: 0 });
+        Ok(())
+    }
+
+    /// Write out the whole directory after pages were removed from it, freeing directory pages no
+    /// longer needed.
+    fn rewrite_directory(&mut self) -> Result<(), TableError> {
+        let needed = self.pages.len().div_ceil(ENTRIES_PER_PAGE).max(1);
+        let surplus = self.directory.split_off(needed);
+        for (i, id) in self.directory.iter().enumerate() {
+            let page = self.store.pin_page(id)?;
+            let mut buf = page.try_write()?;
+            let next = self.directory.get(i + 1).map_or(NO_PAGE, |p| p.offset() as u64);
+            write_u64(&mut *buf, NEXT, next);
+            write_u16(&mut *buf, COUNT, 0);
+        }
+        for index in 0..self.pages.len() {
+            self.write_summary(index)?;
+        }
+        for id in surplus {
+            self.allocator.free(self.store, id)?;
+        }
+        Ok(())
+    }
+
+    /// Write the directory entry for `pages[index]`.
+    fn write_summary(&self, index: usize) -> Result<(), TableError> {
+        let summary = &self.pages[index];
+        let page = self.store.pin_page(&self.directory[index / ENTRIES_PER_PAGE])?;
+        let mut buf = page.try_write()?;
+        let slot = index % ENTRIES_PER_PAGE;
+        let at = ENTRIES + slot * ENTRY_LEN;
+        write_u64(&mut *buf, at, summary.page.offset() as u64);
+        write_u64(&mut *buf, at + 8, summary.min as u64);
+        write_u64(&mut *buf, at + 16, summary.max as u64);
+        write_u64(&mut *buf, at + 24, summary.rows);
+        if slot >= read_u16(&*buf, COUNT) as usize {
+            write_u16(&mut *buf, COUNT, slot as u16 + 1);
+        }
+        Ok(())
+    }
+}
+
+fn check_timestamp(schema: &Schema, timestamp: usize) -> Result<(), TableError> {
+    if timestamp >= schema.len() {
+        return Err(TableError::NoSuchColumn(timestamp))
+    }
+    if schema.column_type(timestamp) != ColumnType::Int {
+        return Err(TableError::Tuple(TupleError::TypeMismatch { column: timestamp }))
+    }
+    if schema.column(timestamp).nullable {
+        return Err(TableError::NullableKey(timestamp))
+    }
+    Ok(())
+}
+
+fn new_directory_page<S: Storage>(store: &PageStore<S>, allocator: &PageAllocator) -> Result<PageId, PageError> {
+    let page = allocator.allocate(store)?;
+    let mut buf = page.try_write()?;
+    write_u32(&mut *buf, 0, MAGIC);
+    write_u64(&mut *buf, NEXT, NO_PAGE);
+    Ok(page.id())
+}
+
+/// Reads one data page at a time, holding no latch between pages.
+pub struct TimeSeriesScan<'table, 'store, S: Storage> {
+    table: &'table TimeSeriesTable<'store, S>,
+    pages: std::vec::IntoIter<usize>,
+    start: Bound<i64>,
+    end: Bound<i64>,
+    rows: std::vec::IntoIter<Vec<Value>>,
+}
+impl<S: Storage> TimeSeriesScan<'_, '_, S> {
+    /// The rows of `pages[index]` in the scan's range.
+    fn read_page(&self, index: usize) -> Result<Vec<Vec<Value>>, TableError> {
+        let schema = &self.table.schema;
+        let page = self.table.store.pin_page(&self.table.pages[index].page)?;
+        let data = SlottedPage::new(page.try_read()?);
+        let mut rows = Vec::new();
+        for slot in 0..data.slot_count() as SlotId {
+            let record = data.get(slot).ok_or(TableError::Tuple(TupleError::Corrupt))?;
+            let Value::Int(timestamp) = Row::new(schema, record)?.get(self.table.timestamp)? else {
+                return Err(TableError::Tuple(TupleError::Corrupt))
+            };
+            if (self.start, self.end).contains(&timestamp) {
+                rows.push(schema.decode(record)?);
+            }
+        }
+        Ok(rows)
+    }
+}
+impl<S: Storage> Iterator for TimeSeriesScan<'_, '_, S> {
+    type Item = Result<Vec<Value>, TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(Ok(row))
+            }
+            let index = self.pages.next()?;
+            match self.read_page(index) {
+                Ok(rows) => self.rows = rows.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::PageAllocator;
+    use crate::page_store::{PageId, PageStore};
+    use crate::storage::TestStorage;
+    use crate::table::TableError;
+    use crate::tuple::{Column, ColumnType, Schema, TupleError, Value};
+
+    use super::TimeSeriesTable;
+
+    fn schema() -> Schema {
+        Schema::new(vec![Column::new(ColumnType::Text), Column::new(ColumnType::Int), Column::new(ColumnType::Float)])
+    }
+
+    fn row(ts: i64) -> Vec<Value> {
+        vec![Value::Text(format!("host-{}", ts % 7)), Value::Int(ts), Value::Float(ts as f64 / 10.0)]
+    }
+
+    fn timestamps(rows: Vec<Result<Vec<Value>, TableError>>) -> Vec<i64> {
+        rows.into_iter().map(|r| match r.unwrap()[1] {
+            Value::Int(ts) => ts,
+            _ => unreachable!(),
+        }).collect()
+    }
+
+    #[test]
+    fn test_appends_and_pruned_range_scans() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = TimeSeriesTable::create(&store, allocator, schema(), 1)?;
+        // Timestamps rise by 10 with every fifth row a little late.
+        let order: Vec<i64> = (0..30_000).map(|i| if i % 5 == 4 { (i - 2) * 10 + 1 } else { i * 10 }).collect();
+        for ts in &order {
+            table.append(&row(*ts))?;
+        }
+        assert_eq!(table.len(), 30_000);
+        assert_eq!(table.time_range(), Some((0, 299_980)));
+
+        let table = TimeSeriesTable::open(&store, allocator, schema(), 1, table.root())?;
+        let pages = table.page_count();
+        assert!(pages > 100);
+        let range = 150_000..150_500;
+        let expected: Vec<i64> = order.iter().copied().filter(|ts| range.contains(ts)).collect();
+        assert_eq!(timestamps(table.scan_range(range.clone()).collect()), expected);
+        assert!(table.overlapping(std::ops::Bound::Included(150_000), std::ops::Bound::Excluded(150_500)).len() <= 2);
+        assert_eq!(timestamps(table.scan_range(..=20).collect()), vec![0, 10, 20]);
+        assert_eq!(table.scan_range(300_000..).count(), 0);
+        assert_eq!(table.scan().count(), 30_000);
+
+        let bad = Schema::new(vec![Column::new(ColumnType::Text)]);
+        let err = TimeSeriesTable::create(&store, allocator, bad, 0).err();
+        assert_eq!(err, Some(TableError::Tuple(TupleError::TypeMismatch { column: 0 })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expire_drops_whole_pages() -> Result<(), TableError> {
+        let store = PageStore::new(TestStorage::new());
+        let allocator = PageAllocator::create(&store, PageId::new(0))?;
+        let mut table = TimeSeriesTable::create(&store, allocator, schema(), 1)?;
+        for ts in 0..60_000 {
+            table.append(&row(ts))?;
+        }
+        let pages = table.page_count();
+        assert_eq!(table.expire(1000, 500)?, 0);
+
+        // Pages wholly before the cutoff go; the page straddling it keeps its expired rows.
+        let dropped = table.expire(1000, 41_000)?;
+        assert!(dropped <= 40_000 && dropped > 39_000, "{dropped}");
+        assert_eq!(table.len(), 60_000 - dropped);
+        assert_eq!(table.time_range(), Some((dropped as i64, 59_999)));
+        assert!(allocator.free_pages(&store)?.len() > pages - table.page_count());
+        let reopened = TimeSeriesTable::open(&store, allocator, schema(), 1, table.root())?;
+        assert_eq!(reopened.len(), 60_000 - dropped);
+        assert_eq!(timestamps(reopened.scan_range(..dropped as i64 + 2).collect()), vec![dropped as i64, dropped as i64 + 1]);
+
+        assert_eq!(table.expire(0, 60_000)?, 60_000 - dropped);
+        assert!(table.is_empty());
+        table.append(&row(70_000))?;
+        let reopened = TimeSeriesTable::open(&store, allocator, schema(), 1, table.root())?;
+        assert_eq!(timestamps(reopened.scan().collect()), vec![70_000]);
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Temporary space: scratch pages for sorts, hash joins and other operators that spill.
+//!
+//! A `TempSpace` keeps its pages in a page store of its own, over storage separate from the database's,
+//! so scratch pages never go through whatever makes the database durable: hand it raw storage, not a
+//! `ShadowStorage`, and nothing about them is logged, checksummed or synced. Nothing about temporary
+//! pages survives a restart either; the free list is kept in memory and every page is handed out afresh
+//! by a new `TempSpace`.
+//!
+//! Pages are taken through a `TempScope`, which belongs to one operator or transaction. A scope can give
+//! pages back one at a time as it finishes with them, and whatever it still holds is given back wholesale
+//! when it is dropped, so an operator that fails or is abandoned part way leaks nothing. Scopes of one
+//! space can be used from several threads at once.
+//!
+//! A `TempFile` is storage for a space that should not hold its pages in memory: a file of its own,
+//! deleted when it is dropped. Only the page store's frames over it are resident, however much spills.
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::page_store::{Data, PageError, PageId, PageStore, PinnedPage};
+use crate::storage::{FileStorage, Storage, StorageError};
+
+/// Told apart the temporary files one process makes.
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+struct TempState {
+    /// The first page never handed out.
+    next: usize,
+    /// Pages handed out before and given back since.
+    free: Vec<PageId>,
+}
+
+pub struct TempSpace<S: Storage> {
+    store: PageStore<S>,
+    state: Mutex<TempState>,
+}
+impl<S: Storage> TempSpace<S> {
+    /// Use `storage`, which should hold nothing else, for temporary pages.
+    pub fn new(storage: S) -> TempSpace<S> {
+        TempSpace { store: PageStore::new(storage), state: Mutex::new(TempState { next: 0, free: Vec::new() }) }
+    }
+
+    /// The store temporary pages live in, for reading and writing pages a scope has handed out.
+    pub fn store(&self) -> &PageStore<S> {
+        &self.store
+    }
+
+    /// Start a scope whose pages are all given back when it is dropped.
+    pub fn scope(&self) -> TempScope<'_, S> {
+        TempScope { space: self, pages: HashSet::new() }
+    }
+
+    /// Number of pages held by scopes right now.
+    pub fn pages_in_use(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.next - state.free.len()
+    }
+
+    fn allocate(&self) -> Result<PinnedPage<'_, S>, PageError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(id) = state.free.pop() {
+            let page = self.store.pin_page(&id).and_then(|page| {
+                page.try_write()?.fill(0);
+                Ok(page)
+            });
+            if page.is_err() {
+                state.free.push(id);
+            }
+            return page
+        }
+        let page = self.store.allocate_page(&PageId::new(state.next))?;
+        state.next += 1;
+        Ok(page)
+    }
+}
+
+/// The temporary pages of one operator or transaction.
+pub struct TempScope<'space, S: Storage> {
+    space: &'space TempSpace<S>,
+    pages: HashSet<PageId>,
+}
+impl<'space, S: Storage> TempScope<'space, S> {
+    pub fn store(&self) -> &'space PageStore<S> {
+        &self.space.store
+    }
+
+    /// Hand out a zeroed page, held by this scope until it is freed or the scope is dropped.
+    pub fn allocate(&mut self) -> Result<PinnedPage<'space, S>, PageError> {
+        let page = self.space.allocate()?;
+        self.pages.insert(page.id());
+        Ok(page)
+    }
+
+    /// Give `page` back to the space early. Pages this scope does not hold are ignored.
+    pub fn free(&mut self, page: PageId) {
+        if self.pages.remove(&page) {
+            self.space.state.lock().unwrap().free.push(page);
+        }
+    }
+
+    /// Number of pages this scope holds.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+}
+impl<S: Storage> Drop for TempScope<'_, S> {
+    fn drop(&mut self) {
+        self.space.state.lock().unwrap().free.extend(self.pages.drain());
+    }
+}
+
+/// Storage in a new file, deleted when the storage is dropped.
+pub struct TempFile {
+    storage: FileStorage,
+    path: PathBuf,
+}
+impl TempFile {
+    /// Make a file for temporary pages in the directory `dir`, under a name no other file there has.
+    pub fn create(dir: &Path) -> Result<TempFile, StorageError> {
+        loop {
+            let name = format!("purpledb-temp-{}-{}", std::process::id(), NEXT_FILE.fetch_add(1, Ordering::Relaxed));
+            let path = dir.join(name);
+            match FileStorage::create_new(&path) {
+                Ok(storage) => return Ok(TempFile { storage, path }),
+                Err(StorageError::Io(io::ErrorKind::AlreadyExists)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+impl Storage for TempFile {
+    fn load_page(&self, buf: &mut Data, page: &PageId) -> Result<(), StorageError> {
+        self.storage.load_page(buf, page)
+    }
+
+    fn create_page(&mut self, page: &PageId) -> Result<(), StorageError> {
+        self.storage.create_page(page)
+    }
+
+    fn write_page(&mut self, buf: &Data, page: &PageId) -> Result<(), StorageError> {
+        self.storage.write_page(buf, page)
+    }
+}
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page_store::{PageError, PageId, PAGE_SIZE};
+    use crate::storage::TestStorage;
+
+    use super::{TempFile, TempSpace};
+
+    #[test]
+    fn test_scopes_give_pages_back() -> Result<(), PageError> {
+        let space = TempSpace::new(TestStorage::new());
+        let mut sort = space.scope();
+        let mut join = space.scope();
+        let mut pages = Vec::new();
+        for _ in 0..10 {
+            let page = sort.allocate()?;
+            page.try_write()?[0] = 7;
+            pages.push(page.id());
+        }
+        join.allocate()?;
+        sort.free(pages[3]);
+        sort.free(pages[3]);
+        assert_eq!((sort.page_count(), join.page_count(), space.pages_in_use()), (9, 1, 10));
+
+        // The freed page is handed out again, zeroed.
+        let reused = join.allocate()?;
+        assert_eq!(reused.id(), pages[3]);
+        assert_eq!(reused.try_read()?[0], 0);
+        drop(reused);
+        drop(sort);
+        assert_eq!(space.pages_in_use(), 2);
+        let joined: Vec<_> = (0..12).map(|_| join.allocate().map(|p| p.id().offset())).collect::<Result<_, _>>()?;
+        assert_eq!(joined.iter().filter(|p| **p < 11).count(), 9);
+        assert!(joined.iter().all(|p| *p != 10));
+        drop(join);
+        assert_eq!(space.pages_in_use(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_temp_files() -> Result<(), PageError> {
+        let file = TempFile::create(&std::env::temp_dir()).map_err(PageError::Storage)?;
+        let path = file.path().to_path_buf();
+        let other = TempFile::create(&std::env::temp_dir()).map_err(PageError::Storage)?;
+        assert_ne!(other.path(), path);
+        let space = TempSpace::new(file);
+        let mut scope = space.scope();
+        for i in 0..100 {
+            scope.allocate()?.try_write()?[0] = i;
+        }
+        // Evicted frames are written to the file, which the space reads them back from.
+        assert_eq!(space.store().pin_page(&PageId::new(3))?.try_read()?[0], 3);
+        assert!(std::fs::metadata(&path).unwrap().len() >= 50 * PAGE_SIZE as u64);
+        drop(scope);
+        drop(space);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,756 @@
+//! Row format: typed column values encoded against a schema.
+//!
+//! ```text
+//! | null bitmap | end offset u16 per column | column data ... |
+//! ```
+//!
+//! The null bitmap has one bit per column, set for columns that are null; null columns have no data. Column
+//! data is stored in schema order with no type tags, since the schema says what each column holds. The
+//! offset table gives the end of every column's data, so any one column can be decoded straight from the
+//! encoded row without touching the others. Integers are zigzag varints, floats are
+//! little-endian and eight bytes wide, bools one byte, and text and bytes are stored as they are. Dates,
+//! times and timestamps are their numbers of days or microseconds as zigzag varints, and intervals their
+//! months followed by their microseconds. Decimals are as `Decimal::write` writes them, keeping their scale,
+//! and JSON documents are their encoding.
+//!
+//! Values have two orderings. `Ord` is total and the one index keys sort in: null before everything,
+//! then values of different types by type, floats as `f64::total_cmp` has them, so that sorting, hashing
+//! and deduplicating values always agrees with an index. `Value::compare` is the SQL comparison used by
+//! expressions: it has no answer when either side is null or the types cannot be compared, compares
+//! integers with floats by their numeric value, decimals with integers exactly and with floats as floats,
+//! and dates and timestamps with each other as instants. Under both, decimals that differ only in trailing
+//! zeros are equal.
+//!
+//! A `SchemaHistory` lets a table's schema change without rewriting its rows. Rows are stored in the
+//! physical layout of the version current when they were written, prefixed with that version's number.
+//! Columns are only ever appended to the layout: adding one appends it, dropping one leaves it in place
+//! but nullable and null in every later row, and changing nullability changes it in the next layout.
+//! Decoding reads a row in its own version's layout and maps it onto the current columns, filling in
+//! each added column's default for rows written before it existed.
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::bytes::read_u16;
+use crate::datetime::{Interval, MICROS_PER_DAY};
+use crate::decimal::Decimal;
+use crate::json::Json;
+use crate::varint;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Int,
+    Float,
+    Bool,
+    Bytes,
+    Text,
+    Date,
+    Time,
+    Timestamp,
+    /// A timestamp with a time zone, held in UTC.
+    TimestampTz,
+    Interval,
+    /// An exact decimal of at most `precision` digits, `scale` of them after the point, or of any number
+    /// of digits when `precision` is 0.
+    Decimal { precision: u32, scale: u32 },
+    Json,
+}
+impl ColumnType {
+    /// A decimal of any precision and scale, the type of every decimal value.
+    pub const NUMERIC: ColumnType = ColumnType::Decimal { precision: 0, scale: 0 };
+
+    /// Whether values of this type are values of `other`: the types are the same but for the precision and
+    /// scale of decimals.
+    pub fn same_kind(self, other: ColumnType) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+
+    /// The byte that stands for this type in stored schemas.
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            ColumnType::Int => 0,
+            ColumnType::Float => 1,
+            ColumnType::Bool => 2,
+            ColumnType::Bytes => 3,
+            ColumnType::Text => 4,
+            ColumnType::Date => 5,
+            ColumnType::Time => 6,
+            ColumnType::Timestamp => 7,
+            ColumnType::TimestampTz => 8,
+            ColumnType::Interval => 9,
+            ColumnType::Decimal { .. } => 10,
+            ColumnType::Json => 11,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Option<ColumnType> {
+        Some(match tag {
+            0 => ColumnType::Int,
+            1 => ColumnType::Float,
+            2 => ColumnType::Bool,
+            3 => ColumnType::Bytes,
+            4 => ColumnType::Text,
+            5 => ColumnType::Date,
+            6 => ColumnType::Time,
+            7 => ColumnType::Timestamp,
+            8 => ColumnType::TimestampTz,
+            9 => ColumnType::Interval,
+            10 => ColumnType::NUMERIC,
+            11 => ColumnType::Json,
+            _ => return None,
+        })
+    }
+
+    /// Append the type as stored schemas keep it: its tag, then the precision and scale of a decimal as
+    /// varints.
+    pub(crate) fn write(self, buf: &mut Vec<u8>) {
+        buf.push(self.tag());
+        if let ColumnType::Decimal { precision, scale } = self {
+            varint::write_u64(buf, precision as u64);
+            varint::write_u64(buf, scale as u64);
+        }
+    }
+
+    /// Decode a type written by `write` from the start of `buf`, returning it and the number of bytes it
+    /// took.
+    pub(crate) fn read(buf: &[u8]) -> Option<(ColumnType, usize)> {
+        let column_type = ColumnType::from_tag(*buf.first()?)?;
+        if column_type != ColumnType::NUMERIC {
+            return Some((column_type, 1))
+        }
+        let (precision, len) = varint::read_u64(&buf[1..])?;
+        let (scale, end) = varint::read_u64(&buf[1 + len..])?;
+        let (precision, scale) = (u32::try_from(precision).ok()?, u32::try_from(scale).ok()?);
+        if scale > precision || (precision == 0 && scale != 0) {
+            return None
+        }
+        Some((ColumnType::Decimal { precision, scale }, 1 + len + end))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+impl Column {
+    /// A column that must always hold a value.
+    pub fn new(column_type: ColumnType) -> Column {
+        Column { column_type, nullable: false }
+    }
+
+    pub fn nullable(column_type: ColumnType) -> Column {
+        Column { column_type, nullable: true }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// SQL null: no value at all, as opposed to a zero or empty one.
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    Text(String),
+    /// Days since 1970-01-01.
+    Date(i32),
+    /// Microseconds since midnight.
+    Time(i64),
+    /// Microseconds since midnight on 1970-01-01.
+    Timestamp(i64),
+    /// Microseconds since midnight on 1970-01-01 UTC.
+    TimestampTz(i64),
+    Interval(Interval),
+    Decimal(Decimal),
+    Json(Json),
+}
+impl Value {
+    /// The type of the value, or `None` for null, which fits a nullable column of any type.
+    pub fn column_type(&self) -> Option<ColumnType> {
+        match self {
+            Value::Null => None,
+            Value::Int(_) => Some(ColumnType::Int),
+            Value::Float(_) => Some(ColumnType::Float),
+            Value::Bool(_) => Some(ColumnType::Bool),
+            Value::Bytes(_) => Some(ColumnType::Bytes),
+            Value::Text(_) => Some(ColumnType::Text),
+            Value::Date(_) => Some(ColumnType::Date),
+            Value::Time(_) => Some(ColumnType::Time),
+            Value::Timestamp(_) => Some(ColumnType::Timestamp),
+            Value::TimestampTz(_) => Some(ColumnType::TimestampTz),
+            Value::Interval(_) => Some(ColumnType::Interval),
+            Value::Decimal(_) => Some(ColumnType::NUMERIC),
+            Value::Json(_) => Some(ColumnType::Json),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Compare as SQL does: `None` if either value is null or their types cannot be compared.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Decimal(a), Value::Int(b)) => Some(a.cmp(&Decimal::from(*b))),
+            (Value::Int(a), Value::Decimal(b)) => Some(Decimal::from(*a).cmp(b)),
+            (Value::Decimal(a), Value::Float(b)) => a.to_f64().partial_cmp(b),
+            (Value::Float(a), Value::Decimal(b)) => a.partial_cmp(&b.to_f64()),
+            (a, b) if a.column_type() != b.column_type() && a.instant().is_some() && b.instant().is_some() => {
+                Some(a.instant().cmp(&b.instant()))
+            }
+            (a, b) if !a.is_null() && a.column_type() == b.column_type() => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    /// Microseconds since 1970-01-01 of a date or timestamp, for comparing them with each other: a date
+    /// is its midnight, and a timestamp without a time zone is read as UTC.
+    fn instant(&self) -> Option<i64> {
+        match self {
+            Value::Date(v) => Some(*v as i64 * MICROS_PER_DAY),
+            Value::Timestamp(v) | Value::TimestampTz(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Position of the value's type in the order of `Ord`, null first.
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Int(_) => 1,
+            Value::Float(_) => 2,
+            Value::Bool(_) => 3,
+            Value::Bytes(_) => 4,
+            Value::Text(_) => 5,
+            Value::Date(_) => 6,
+            Value::Time(_) => 7,
+            Value::Timestamp(_) => 8,
+            Value::TimestampTz(_) => 9,
+            Value::Interval(_) => 10,
+            Value::Decimal(_) => 11,
+            Value::Json(_) => 12,
+        }
+    }
+}
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            (Value::Text(a), Value::Text(b)) => a.cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.cmp(b),
+            (Value::Time(a), Value::Time(b))
+            | (Value::Timestamp(a), Value::Timestamp(b))
+            | (Value::TimestampTz(a), Value::TimestampTz(b)) => a.cmp(b),
+            (Value::Interval(a), Value::Interval(b)) => a.cmp(b),
+            (Value::Decimal(a), Value::Decimal(b)) => a.cmp(b),
+            (Value::Json(a), Value::Json(b)) => a.cmp(b),
+            (a, b) => a.rank().cmp(&b.rank()),
+        }
+    }
+}
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Value {}
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Value::Null => {}
+            Value::Int(v) => v.hash(state),
+            Value::Float(v) => v.to_bits().hash(state),
+            Value::Bool(v) => v.hash(state),
+            Value::Bytes(v) => v.hash(state),
+            Value::Text(v) => v.hash(state),
+            Value::Date(v) => v.hash(state),
+            Value::Time(v) | Value::Timestamp(v) | Value::TimestampTz(v) => v.hash(state),
+            Value::Interval(v) => v.hash(state),
+            Value::Decimal(v) => v.hash(state),
+            Value::Json(v) => v.hash(state),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TupleError {
+    /// The number of values does not match the number of columns.
+    WrongColumnCount,
+    /// A value's type does not match its column's type.
+    TypeMismatch { column: usize },
+    /// A null was given for a column that is not nullable.
+    NullNotAllowed { column: usize },
+    /// The encoded row is longer than the offset table can address.
+    RowTooLarge,
+    /// The bytes are not a row of this schema.
+    Corrupt,
+}
+impl fmt::Display for TupleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TupleError::WrongColumnCount => write!(f, "wrong number of values for the columns"),
+            TupleError::TypeMismatch { column } => write!(f, "value of the wrong type for column {}", column + 1),
+            TupleError::NullNotAllowed { column } => {
+                write!(f, "null value in column {}, which is not null", column + 1)
+            }
+            TupleError::RowTooLarge => write!(f, "row too large"),
+            TupleError::Corrupt => write!(f, "corrupt row"),
+        }
+    }
+}
+
+/// Columns of a row, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    columns: Vec<Column>,
+}
+impl Schema {
+    pub fn new(columns: Vec<Column>) -> Schema {
+        Schema { columns }
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn column(&self, column: usize) -> Column {
+        self.columns[column]
+    }
+
+    pub fn column_type(&self, column: usize) -> ColumnType {
+        self.columns[column].column_type
+    }
+
+    fn bitmap_len(&self) -> usize {
+        self.columns.len().div_ceil(8)
+    }
+
+    /// Bytes before the column data: the null bitmap and the offset table.
+    fn header_len(&self) -> usize {
+        self.bitmap_len() + 2 * self.columns.len()
+    }
+
+    /// Check that `values` is a row of this schema: one value per column, each of its column's type or
+    /// null where the column allows it.
+    pub fn check(&self, values: &[Value]) -> Result<(), TupleError> {
+        if values.len() != self.columns.len() {
+            return Err(TupleError::WrongColumnCount)
+        }
+        for (column, (value, expected)) in values.iter().zip(&self.columns).enumerate() {
+            match value.column_type() {
+                None if !expected.nullable => return Err(TupleError::NullNotAllowed { column }),
+                Some(ty) if !ty.same_kind(expected.column_type) => return Err(TupleError::TypeMismatch { column }),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self, values: &[Value]) -> Result<Vec<u8>, TupleError> {
+        self.check(values)?;
+        let header_len = self.header_len();
+        let table = self.bitmap_len();
+        let mut row = vec![0u8; header_len];
+        for (column, value) in values.iter().enumerate() {
+            match value {
+                Value::Null => row[column / 8] |= 1 << (column % 8),
+                Value::Int(v) => varint::write_i64(&mut row, *v),
+                Value::Float(v) => row.extend_from_slice(&v.to_le_bytes()),
+                Value::Bool(v) => row.push(*v as u8),
+                Value::Bytes(v) => row.extend_from_slice(v),
+                Value::Text(v) => row.extend_from_slice(v.as_bytes()),
+                Value::Date(v) => varint::write_i64(&mut row, *v as i64),
+                Value::Time(v) | Value::Timestamp(v) | Value::TimestampTz(v) => varint::write_i64(&mut row, *v),
+                Value::Interval(v) => {
+                    varint::write_i64(&mut row, v.months as i64);
+                    varint::write_i64(&mut row, v.micros);
+                }
+                Value::Decimal(v) => v.write(&mut row),
+                Value::Json(v) => row.extend_from_slice(v.as_bytes()),
+            }
+            let end = u16::try_from(row.len() - header_len).map_err(|_| TupleError::RowTooLarge)?;
+            row[table + 2 * column..table + 2 * column + 2].copy_from_slice(&end.to_le_bytes());
+        }
+        Ok(row)
+    }
+
+    /// Every column of `row`.
+    pub fn decode(&self, row: &[u8]) -> Result<Vec<Value>, TupleError> {
+        let row = Row::new(self, row)?;
+        (0..self.len()).map(|column| row.get(column)).collect()
+    }
+}
+
+/// Every version of a schema, for reading rows written under any of them as rows of the latest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaHistory {
+    /// The physical layout of each version, oldest first.
+    versions: Vec<Schema>,
+    /// Position in the layout of each current column.
+    positions: Vec<usize>,
+    /// Value of each current column in rows written before it was added.
+    defaults: Vec<Value>,
+    /// The current columns, kept to check rows against.
+    current: Schema,
+}
+impl SchemaHistory {
+    /// A history whose only version is `schema`.
+    pub fn new(schema: Schema) -> SchemaHistory {
+        let positions = (0..schema.len()).collect();
+        let defaults = vec![Value::Null; schema.len()];
+        SchemaHistory { versions: vec![schema.clone()], positions, defaults, current: schema }
+    }
+
+    /// The current columns.
+    pub fn schema(&self) -> &Schema {
+        &self.current
+    }
+
+    /// Number of the current version, counting from 0.
+    pub fn version(&self) -> usize {
+        self.versions.len() - 1
+    }
+
+    /// Append `column`, which reads as `default` in rows written before now.
+    pub fn add_column(&mut self, column: Column, default: Value) -> Result<(), TupleError> {
+        Schema::new(vec![column]).check(std::slice::from_ref(&default)).map_err(|e| match e {
+            TupleError::TypeMismatch { .. } => TupleError::TypeMismatch { column: self.current.len() },
+            _ => TupleError::NullNotAllowed { column: self.current.len() },
+        })?;
+        let mut layout = self.layout().columns.clone();
+        self.positions.push(layout.len());
+        self.defaults.push(default);
+        layout.push(column);
+        self.push(layout);
+        Ok(())
+    }
+
+    /// Drop the current column `column`; later columns move down one.
+    pub fn drop_column(&mut self, column: usize) {
+        let position = self.positions.remove(column);
+        self.defaults.remove(column);
+        let mut layout = self.layout().columns.clone();
+        layout[position].nullable = true;
+        self.push(layout);
+    }
+
+    /// Change whether the current column `column` may be null. Rows already written are not checked.
+    pub fn set_nullable(&mut self, column: usize, nullable: bool) {
+        let mut layout = self.layout().columns.clone();
+        layout[self.positions[column]].nullable = nullable;
+        self.push(layout);
+    }
+
+    /// Encode `values`, a row of the current schema, in the current layout.
+    pub fn encode(&self, values: &[Value]) -> Result<Vec<u8>, TupleError> {
+        self.current.check(values)?;
+        let mut physical = vec![Value::Null; self.layout().len()];
+        for (value, position) in values.iter().zip(&self.positions) {
+            physical[*position] = value.clone();
+        }
+        let mut row = Vec::new();
+        varint::write_u64(&mut row, self.version() as u64);
+        row.extend_from_slice(&self.layout().encode(&physical)?);
+        Ok(row)
+    }
+
+    /// Decode a row written under any version as a row of the current schema.
+    pub fn decode(&self, row: &[u8]) -> Result<Vec<Value>, TupleError> {
+        let (version, len) = varint::read_u64(row).ok_or(TupleError::Corrupt)?;
+        let layout = usize::try_from(version).ok().and_then(|v| self.versions.get(v)).ok_or(TupleError::Corrupt)?;
+        let mut physical = layout.decode(&row[len..])?;
+        Ok(self
+            .positions
+            .iter()
+            .zip(&self.defaults)
+            .map(|(&position, default)| match physical.get_mut(position) {
+                Some(value) => std::mem::replace(value, Value::Null),
+                None => default.clone(),
+            })
+            .collect())
+    }
+
+    fn layout(&self) -> &Schema {
+        self.versions.last().unwrap()
+    }
+
+    fn push(&mut self, layout: Vec<Column>) {
+        self.current = Schema::new(self.positions.iter().map(|p| layout[*p]).collect());
+        self.versions.push(Schema::new(layout));
+    }
+
+    pub(crate) fn write(&self, buf: &mut Vec<u8>) {
+        varint::write_u64(buf, self.versions.len() as u64);
+        for layout in &self.versions {
+            varint::write_u64(buf, layout.len() as u64);
+            for column in &layout.columns {
+                column.column_type.write(buf);
+                buf.push(column.nullable as u8);
+            }
+        }
+        varint::write_u64(buf, self.positions.len() as u64);
+        for position in &self.positions {
+            varint::write_u64(buf, *position as u64);
+        }
+        // Defaults are a row of the current columns, every one of them allowed to be null.
+        let defaults = Schema::new(self.current.columns.iter().map(|c| Column::nullable(c.column_type)).collect());
+        varint::write_prefixed(buf, &defaults.encode(&self.defaults).expect("defaults are checked when added"));
+    }
+
+    /// Decode a history written by `write` from the start of `buf`, returning it and the number of bytes
+    /// it took.
+    pub(crate) fn read(buf: &[u8]) -> Option<(SchemaHistory, usize)> {
+        fn next(buf: &[u8], at: &mut usize) -> Option<usize> {
+            let (v, len) = varint::read_u64(buf.get(*at..)?)?;
+            *at += len;
+            usize::try_from(v).ok()
+        }
+        let mut at = 0;
+        let mut versions = Vec::new();
+        for _ in 0..next(buf, &mut at)? {
+            let mut columns = Vec::new();
+            for _ in 0..next(buf, &mut at)? {
+                let (column_type, len) = ColumnType::read(buf.get(at..)?)?;
+                let nullable = match *buf.get(at + len)? {
+                    0 => false,
+                    1 => true,
+                    _ => return None,
+                };
+                columns.push(Column { column_type, nullable });
+                at += len + 1;
+            }
+            versions.push(Schema::new(columns));
+        }
+        let layout = versions.last()?;
+        let positions: Vec<usize> = (0..next(buf, &mut at)?).map(|_| next(buf, &mut at)).collect::<Option<_>>()?;
+        if positions.iter().any(|p| *p >= layout.len()) {
+            return None
+        }
+        let current = Schema::new(positions.iter().map(|p| layout.column(*p)).collect());
+        let (defaults, len) = varint::read_prefixed(&buf[at..])?;
+        let nullable = Schema::new(current.columns.iter().map(|c| Column::nullable(c.column_type)).collect());
+        let defaults = nullable.decode(defaults).ok()?;
+        Some((SchemaHistory { versions, positions, defaults, current }, at + len))
+    }
+}
+
+/// An encoded row viewed through its schema, decoding columns on demand.
+#[derive(Debug, Clone, Copy)]
+pub struct Row<'a> {
+    schema: &'a Schema,
+    bytes: &'a [u8],
+}
+impl<'a> Row<'a> {
+    /// Check that `bytes` has a well-formed header for `schema`.
+    pub fn new(schema: &'a Schema, bytes: &'a [u8]) -> Result<Row<'a>, TupleError> {
+        let header_len = schema.header_len();
+        if bytes.len() < header_len {
+            return Err(TupleError::Corrupt)
+        }
+        let row = Row { schema, bytes };
+        let mut start = 0;
+        for column in 0..schema.len() {
+            let end = row.end(column);
+            let null = row.is_null(column);
+            if end < start || header_len + end > bytes.len() || (null && (end != start || !schema.column(column).nullable)) {
+                return Err(TupleError::Corrupt)
+            }
+            start = end;
+        }
+        Ok(row)
+    }
+
+    /// Whether `column` is SQL null.
+    pub fn is_null(&self, column: usize) -> bool {
+        self.bytes[column / 8] >> (column % 8) & 1 == 1
+    }
+
+    /// The raw encoded bytes of `column`, empty if it is null.
+    pub fn raw(&self, column: usize) -> &'a [u8] {
+        let header_len = self.schema.header_len();
+        let start = if column == 0 { 0 } else { self.end(column - 1) };
+        &self.bytes[header_len + start..header_len + self.end(column)]
+    }
+
+    /// The value of `column`, `Value::Null` if it is null.
+    pub fn get(&self, column: usize) -> Result<Value, TupleError> {
+        if self.is_null(column) {
+            return Ok(Value::Null)
+        }
+        let raw = self.raw(column);
+        let int = || match varint::read_i64(raw) {
+            Some((v, len)) if len == raw.len() => Ok(v),
+            _ => Err(TupleError::Corrupt),
+        };
+        let value = match self.schema.column_type(column) {
+            ColumnType::Int => Value::Int(int()?),
+            ColumnType::Float => Value::Float(f64::from_le_bytes(raw.try_into().map_err(|_| TupleError::Corrupt)?)),
+            ColumnType::Bool => match raw {
+                [0] => Value::Bool(false),
+                [1] => Value::Bool(true),
+                _ => return Err(TupleError::Corrupt),
+            },
+            ColumnType::Bytes => Value::Bytes(raw.to_vec()),
+            ColumnType::Text => Value::Text(String::from_utf8(raw.to_vec()).map_err(|_| TupleError::Corrupt)?),
+            ColumnType::Date => Value::Date(i32::try_from(int()?).map_err(|_| TupleError::Corrupt)?),
+            ColumnType::Time => Value::Time(int()?),
+            ColumnType::Timestamp => Value::Timestamp(int()?),
+            ColumnType::TimestampTz => Value::TimestampTz(int()?),
+            ColumnType::Interval => {
+                let (months, len) = varint::read_i64(raw).ok_or(TupleError::Corrupt)?;
+                let months = i32::try_from(months).map_err(|_| TupleError::Corrupt)?;
+                match varint::read_i64(&raw[len..]) {
+                    Some((micros, end)) if len + end == raw.len() => Value::Interval(Interval { months, micros }),
+                    _ => return Err(TupleError::Corrupt),
+                }
+            }
+            ColumnType::Decimal { .. } => match Decimal::read(raw) {
+                Some((v, len)) if len == raw.len() => Value::Decimal(v),
+                _ => return Err(TupleError::Corrupt),
+            },
+            ColumnType::Json => Value::Json(Json::from_bytes(raw).ok_or(TupleError::Corrupt)?),
+        };
+        Ok(value)
+    }
+
+    fn end(&self, column: usize) -> usize {
+        read_u16(self.bytes, self.schema.bitmap_len() + 2 * column) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::collections::HashSet;
+
+    use crate::decimal::Decimal;
+    use crate::table::key;
+
+    use super::{Column, ColumnType, Row, Schema, SchemaHistory, TupleError, Value};
+
+    #[test]
+    fn test_encode_and_decode_columns() -> Result<(), TupleError> {
+        let types = [ColumnType::Int, ColumnType::Text, ColumnType::Bool, ColumnType::Float, ColumnType::Bytes];
+        let schema = Schema::new(types.into_iter().map(Column::new).collect());
+        let values = vec![
+            Value::Int(-42),
+            Value::Text("héllo".to_string()),
+            Value::Bool(true),
+            Value::Float(2.5),
+            Value::Bytes(vec![]),
+        ];
+        let encoded = schema.encode(&values)?;
+        assert_eq!(schema.decode(&encoded)?, values);
+
+        let row = Row::new(&schema, &encoded)?;
+        assert_eq!(row.get(3)?, Value::Float(2.5));
+        assert_eq!(row.raw(1), "héllo".as_bytes());
+        assert_eq!(row.get(4)?, Value::Bytes(vec![]));
+
+        assert_eq!(schema.encode(&values[..2]), Err(TupleError::WrongColumnCount));
+        let mut wrong = values.clone();
+        wrong[2] = Value::Int(1);
+        assert_eq!(schema.encode(&wrong), Err(TupleError::TypeMismatch { column: 2 }));
+        assert_eq!(encoded.len(), 1 + 10 + 1 + 6 + 1 + 8);
+        assert_eq!(Row::new(&schema, &encoded[..13]).err(), Some(TupleError::Corrupt));
+        assert_eq!(schema.decode(&encoded[..encoded.len() - 1]), Err(TupleError::Corrupt));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nulls() -> Result<(), TupleError> {
+        let mut columns: Vec<_> = (0..9).map(|_| Column::nullable(ColumnType::Int)).collect();
+        columns.push(Column::new(ColumnType::Text));
+        let schema = Schema::new(columns);
+        let mut values: Vec<_> = (0..9).map(|i| if i % 4 == 0 { Value::Null } else { Value::Int(0) }).collect();
+        values.push(Value::Text(String::new()));
+
+        let encoded = schema.encode(&values)?;
+        let row = Row::new(&schema, &encoded)?;
+        assert!(row.is_null(8) && !row.is_null(9));
+        assert_eq!(row.get(8)?, Value::Null);
+        assert_eq!(row.get(7)?, Value::Int(0));
+        assert_eq!(row.get(9)?, Value::Text(String::new()));
+        assert_eq!(schema.decode(&encoded)?, values);
+
+        values[9] = Value::Null;
+        assert_eq!(schema.encode(&values), Err(TupleError::NullNotAllowed { column: 9 }));
+        let mut forged = encoded.clone();
+        forged[1] |= 0b10;
+        assert_eq!(Row::new(&schema, &forged).err(), Some(TupleError::Corrupt));
+        Ok(())
+    }
+
+    #[test]
+    fn test_orderings() {
+        let mut values = vec![
+            Value::Float(f64::INFINITY),
+            Value::Float(-0.0),
+            Value::Float(0.0),
+            Value::Float(-2.5),
+            Value::Int(i64::MIN),
+            Value::Int(3),
+            Value::Null,
+            Value::Bool(true),
+            Value::Text("b".to_string()),
+            Value::Text("a\0".to_string()),
+            Value::Bytes(vec![]),
+        ];
+        values.sort();
+        let keys: Vec<_> = values.iter().map(|v| key::encode(std::slice::from_ref(v))).collect();
+        for ty in [ColumnType::Int, ColumnType::Float, ColumnType::Text] {
+            let of_type: Vec<_> = (0..values.len()).filter(|i| values[*i].column_type() == Some(ty)).collect();
+            assert!(of_type.windows(2).all(|w| keys[w[0]] < keys[w[1]]), "{ty:?} sorts as its keys do");
+        }
+        assert_eq!(values[0], Value::Null);
+
+        // Equal under `Ord` is equal as a hash key: -0.0 and 0.0 are distinct, NaN is itself.
+        let set: HashSet<Value> = [-0.0, 0.0, f64::NAN, f64::NAN].map(Value::Float).into();
+        assert_eq!(set.len(), 3);
+
+        assert_eq!(Value::Int(2).compare(&Value::Float(2.5)), Some(Ordering::Less));
+        assert_eq!(Value::Float(-0.0).compare(&Value::Int(0)), Some(Ordering::Equal));
+        assert_eq!(Value::Text("a".to_string()).compare(&Value::Text("b".to_string())), Some(Ordering::Less));
+        assert_eq!(Value::Null.compare(&Value::Null), None);
+        assert_eq!(Value::Int(1).compare(&Value::Text("1".to_string())), None);
+    }
+
+    #[test]
+    fn test_decimal_columns() -> Result<(), TupleError> {
+        let money = ColumnType::Decimal { precision: 10, scale: 2 };
+        let decimal = |s: &str| Value::Decimal(Decimal::parse(s).unwrap());
+        let schema = Schema::new(vec![Column::new(money), Column::nullable(ColumnType::NUMERIC)]);
+        let values = vec![decimal("-12.50"), decimal("123456789012345678901234567890.000")];
+        let decoded = schema.decode(&schema.encode(&values)?)?;
+        assert_eq!(format!("{decoded:?}"), format!("{values:?}"));
+        assert_eq!(schema.check(&[Value::Int(1), Value::Null]), Err(TupleError::TypeMismatch { column: 0 }));
+
+        assert_eq!(decimal("1.50").compare(&Value::Int(2)), Some(Ordering::Less));
+        assert_eq!(decimal("1.50").compare(&Value::Float(1.5)), Some(Ordering::Equal));
+        let set: HashSet<Value> = [decimal("1.5"), decimal("1.50"), Value::Int(1)].into();
+        assert_eq!(set.len(), 2);
+
+        let history = SchemaHistory::new(schema);
+        let mut buf = Vec::new();
+        history.write(&mut buf);
+        assert_eq!(SchemaHistory::read(&buf), Some((history, buf.len())));
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+//! Variable-length integers and length-prefixed byte strings.
+//!
+//! Unsigned integers are LEB128: seven bits per byte, least significant group first, with the high bit
+//! set on every byte but the last. Signed integers are zigzag-mapped first so small negative numbers
+//! stay short. Decoding accepts only the shortest encoding of each value, so every value has exactly one
+//! encoding, and returns `None` rather than panicking on truncated or malformed input.
+
+/// Longest encoding of a `u64`.
+pub(crate) const MAX_LEN: usize = 10;
+
+pub(crate) fn encoded_len(mut v: u64) -> usize {
+    let mut len = 1;
+    while v >= 0x80 {
+        v >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub(crate) fn write_u64(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// Decode a `u64` from the start of `buf`, returning it and the number of bytes it took.
+pub(crate) fn read_u64(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut v = 0u64;
+    for (i, byte) in buf.iter().take(MAX_LEN).enumerate() {
+        let group = (byte & 0x7f) as u64;
+        if i == MAX_LEN - 1 && group > 1 {
+            return None
+        }
+        v |= group << (7 * i);
+        if byte & 0x80 == 0 {
+            // A zero last byte means a shorter encoding existed.
+            return (i == 0 || *byte != 0).then_some((v, i + 1))
+        }
+    }
+    None
+}
+
+pub(crate) fn write_i64(buf: &mut Vec<u8>, v: i64) {
+    write_u64(buf, ((v << 1) ^ (v >> 63)) as u64)
+}
+
+pub(crate) fn read_i64(buf: &[u8]) -> Option<(i64, usize)> {
+    let (v, len) = read_u64(buf)?;
+    Some(((v >> 1) as i64 ^ -((v & 1) as i64), len))
+}
+
+/// Write `bytes` preceded by its length as a varint.
+pub(crate) fn write_prefixed(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_u64(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Decode a length-prefixed byte string from the start of `buf`, returning it and the number of bytes
+/// taken by the prefix and the string together.
+pub(crate) fn read_prefixed(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let (len, prefix) = read_u64(buf)?;
+    let end = prefix.checked_add(usize::try_from(len).ok()?)?;
+    Some((buf.get(prefix..end)?, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encoded_len, read_i64, read_prefixed, read_u64, write_i64, write_prefixed, write_u64};
+
+    /// Deterministic xorshift values spread over every bit width.
+    fn values() -> impl Iterator<Item = u64> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..20_000).map(move |i| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state >> (i % 64)
+        })
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for v in values().chain([0, 127, 128, 16_383, 16_384, u64::MAX]) {
+            let mut buf = Vec::new();
+            write_u64(&mut buf, v);
+            assert_eq!(buf.len(), encoded_len(v));
+            assert_eq!(read_u64(&buf), Some((v, buf.len())));
+            assert_eq!(read_u64(&buf[..buf.len() - 1]), None);
+
+            let signed = v as i64;
+            buf.clear();
+            write_i64(&mut buf, signed);
+            assert_eq!(read_i64(&buf), Some((signed, buf.len())));
+
+            let bytes = v.to_le_bytes();
+            let bytes = &bytes[..(v % 9) as usize];
+            buf.clear();
+            write_prefixed(&mut buf, bytes);
+            buf.push(0xff);
+            assert_eq!(read_prefixed(&buf), Some((bytes, buf.len() - 1)));
+        }
+        let mut buf = Vec::new();
+        write_i64(&mut buf, -1);
+        assert_eq!(buf, [1]);
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        assert_eq!(read_u64(&[]), None);
+        assert_eq!(read_u64(&[0x80, 0x00]), None);
+        assert_eq!(read_u64(&[0xff; 9].iter().copied().chain([0x02]).collect::<Vec<_>>()), None);
+        assert_eq!(read_u64(&[0xff; 11]), None);
+        assert_eq!(read_prefixed(&[5, 1, 2]), None);
+
+        // Arbitrary bytes never panic, and whatever decodes re-encodes to the same bytes.
+        for v in values() {
+            let bytes: Vec<u8> = v.to_le_bytes().iter().map(|b| b.rotate_left((v % 8) as u32)).collect();
+            if let Some((value, len)) = read_u64(&bytes) {
+                let mut buf = Vec::new();
+                write_u64(&mut buf, value);
+                assert_eq!(buf, bytes[..len]);
+            }
+            read_prefixed(&bytes);
+        }
+    }
+}